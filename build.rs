@@ -0,0 +1,39 @@
+//! 构建脚本：写入版本命令使用的构建信息
+//!
+//! - `PLURIBUS_GIT_HASH`: 当前 Git commit（无法获取时为 "unknown"）
+//! - `PLURIBUS_BUILD_TIMESTAMP`: 构建时间 (Unix timestamp)
+//! - `PLURIBUS_RUSTC_VERSION`: 编译使用的 rustc 版本
+
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8(output.stdout).ok()?;
+    Some(text.trim().to_string()).filter(|s| !s.is_empty())
+}
+
+fn main() {
+    let git_hash = command_output("git", &["rev-parse", "--short=12", "HEAD"])
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let build_timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version =
+        command_output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=PLURIBUS_GIT_HASH={git_hash}");
+    println!("cargo:rustc-env=PLURIBUS_BUILD_TIMESTAMP={build_timestamp}");
+    println!("cargo:rustc-env=PLURIBUS_RUSTC_VERSION={rustc_version}");
+
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+    println!("cargo:rerun-if-changed=build.rs");
+}
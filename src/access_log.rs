@@ -0,0 +1,282 @@
+//! 访问日志采样
+//!
+//! `PLURIBUS_ACCESS_LOG_SAMPLE=N` 时，成功的请求按请求 ID 每 N 个记录一个访问日志（`done`）
+//! 及其转发日志（`request` / `response` / `stream completed`）；错误（4xx / 5xx）与超过
+//! `PLURIBUS_SLOW_REQUEST_MS` 的请求始终记录 `done`。是否采样只取决于请求 ID，同一请求的各条
+//! 日志要么都记录要么都不记录。
+//!
+//! 未记录 `done` 的请求按 `PLURIBUS_ACCESS_LOG_ROLLUP_SECS` 汇总为一条 `access log rollup`：
+//! 请求数、延迟分位数，以及未记录转发日志的请求的 token 数，日志中的明细加上汇总即为总量。
+
+use rand::Rng;
+use std::future::Future;
+use std::sync::Mutex;
+
+use crate::providers::Usage;
+use crate::utils::splitmix64;
+
+/// 汇总窗口内保留的延迟样本上限，超过后按蓄水池抽样替换
+const MAX_LATENCY_SAMPLES: usize = 10_000;
+
+tokio::task_local! {
+    static SAMPLED: bool;
+}
+
+/// 在 `fut` 中记录当前请求是否被采样，供 [`current`] 读取
+pub async fn scope<F: Future>(sampled: bool, fut: F) -> F::Output {
+    SAMPLED.scope(sampled, fut).await
+}
+
+/// 当前请求是否被采样，不在 [`scope`] 中调用时为 true
+pub fn current() -> bool {
+    SAMPLED.try_with(|sampled| *sampled).unwrap_or(true)
+}
+
+/// 一个汇总窗口内未记录明细的流量
+#[derive(Debug, Default)]
+struct Rollup {
+    requests: u64,
+    /// 延迟样本（毫秒）
+    latencies: Vec<u64>,
+    max_latency_ms: u64,
+    usage: Usage,
+}
+
+/// 一个汇总窗口的统计，即 `access log rollup` 日志的内容
+#[derive(Debug)]
+struct RollupSummary {
+    requests: u64,
+    latency_p50_ms: u64,
+    latency_p90_ms: u64,
+    latency_p99_ms: u64,
+    latency_max_ms: u64,
+    usage: Usage,
+}
+
+/// 访问日志采样设置与当前汇总窗口
+#[derive(Debug)]
+pub struct AccessLog {
+    /// 每 N 个请求采样一个，1 表示全部记录
+    sample_every: u32,
+    /// 始终记录的慢请求阈值（毫秒），0 表示不按延迟判断
+    slow_ms: u64,
+    rollup: Mutex<Rollup>,
+}
+
+impl Default for AccessLog {
+    fn default() -> Self {
+        Self::new(1, 0)
+    }
+}
+
+impl AccessLog {
+    pub fn new(sample_every: u32, slow_ms: u64) -> Self {
+        Self {
+            sample_every: sample_every.max(1),
+            slow_ms,
+            rollup: Mutex::default(),
+        }
+    }
+
+    /// 是否开启了采样
+    pub fn is_enabled(&self) -> bool {
+        self.sample_every > 1
+    }
+
+    /// 请求是否被采样，只取决于请求 ID
+    pub fn is_sampled(&self, request_id: u64) -> bool {
+        self.sample_every <= 1
+            || splitmix64(request_id).is_multiple_of(u64::from(self.sample_every))
+    }
+
+    /// 请求结束时是否记录 `done`：被采样、出错或超过慢请求阈值
+    pub fn should_log(&self, request_id: u64, status: u16, latency_ms: u64) -> bool {
+        self.is_sampled(request_id)
+            || status >= 400
+            || (self.slow_ms > 0 && latency_ms >= self.slow_ms)
+    }
+
+    /// 记录一个未记录 `done` 的请求
+    pub fn record_request(&self, latency_ms: u64) {
+        let Ok(mut rollup) = self.rollup.lock() else {
+            return;
+        };
+        rollup.requests += 1;
+        rollup.max_latency_ms = rollup.max_latency_ms.max(latency_ms);
+        if rollup.latencies.len() < MAX_LATENCY_SAMPLES {
+            rollup.latencies.push(latency_ms);
+        } else {
+            let index = rand::rng().random_range(0..rollup.requests) as usize;
+            if let Some(slot) = rollup.latencies.get_mut(index) {
+                *slot = latency_ms;
+            }
+        }
+    }
+
+    /// 记录未记录转发日志的请求的 token 用量
+    pub fn record_usage(&self, usage: &Usage) {
+        if let Ok(mut rollup) = self.rollup.lock() {
+            let total = &mut rollup.usage;
+            total.input_tokens += usage.input_tokens;
+            total.output_tokens += usage.output_tokens;
+            total.cache_read_tokens += usage.cache_read_tokens;
+            total.cache_creation_tokens += usage.cache_creation_tokens;
+        }
+    }
+
+    /// 输出并清空当前窗口的汇总，窗口内没有流量时不输出
+    pub fn flush(&self) {
+        let Some(summary) = self.take_summary() else {
+            return;
+        };
+        tracing::info!(
+            requests = summary.requests,
+            latency_p50_ms = summary.latency_p50_ms,
+            latency_p90_ms = summary.latency_p90_ms,
+            latency_p99_ms = summary.latency_p99_ms,
+            latency_max_ms = summary.latency_max_ms,
+            input_tokens = summary.usage.input_tokens,
+            output_tokens = summary.usage.output_tokens,
+            cache_read = summary.usage.cache_read_tokens,
+            cache_write = summary.usage.cache_creation_tokens,
+            "access log rollup"
+        );
+    }
+
+    /// 清空当前窗口并返回其统计，窗口内没有流量时为 `None`
+    fn take_summary(&self) -> Option<RollupSummary> {
+        let rollup = match self.rollup.lock() {
+            Ok(mut rollup) => std::mem::take(&mut *rollup),
+            Err(_) => return None,
+        };
+        let Rollup {
+            requests,
+            mut latencies,
+            max_latency_ms,
+            usage,
+        } = rollup;
+        if requests == 0 && usage.total() == 0 {
+            return None;
+        }
+        latencies.sort_unstable();
+        Some(RollupSummary {
+            requests,
+            latency_p50_ms: percentile(&latencies, 50),
+            latency_p90_ms: percentile(&latencies, 90),
+            latency_p99_ms: percentile(&latencies, 99),
+            latency_max_ms: max_latency_ms,
+            usage,
+        })
+    }
+}
+
+/// 已排序样本的第 `p` 百分位（最近秩法），没有样本时为 0
+fn percentile(sorted: &[u64], p: usize) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (p * sorted.len()).div_ceil(100).max(1);
+    sorted[rank.min(sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::test_router;
+    use crate::test_support::{self, MockProvider, USER_KEY};
+    use std::sync::Arc;
+
+    /// `log` 不采样的第一个请求 ID
+    fn unsampled(log: &AccessLog) -> u64 {
+        (1..).find(|&id| !log.is_sampled(id)).unwrap()
+    }
+
+    #[test]
+    fn samples_one_in_n_by_request_id() {
+        let log = AccessLog::new(10, 0);
+        assert!(log.is_enabled());
+        let sampled = (1..=10_000).filter(|&id| log.is_sampled(id)).count();
+        assert!((800..=1200).contains(&sampled), "{sampled}");
+        // 同一请求 ID 的结果不变
+        assert!((1..=100).all(|id| log.is_sampled(id) == log.is_sampled(id)));
+
+        let all = AccessLog::new(1, 0);
+        assert!(!all.is_enabled());
+        assert!((1..=100).all(|id| all.is_sampled(id)));
+        // 0 视为 1
+        assert!(!AccessLog::new(0, 0).is_enabled());
+    }
+
+    #[test]
+    fn errors_and_slow_requests_are_always_logged() {
+        let log = AccessLog::new(1000, 500);
+        let id = unsampled(&log);
+        assert!(!log.should_log(id, 200, 10));
+        assert!(!log.should_log(id, 399, 499));
+        assert!(log.should_log(id, 400, 10));
+        assert!(log.should_log(id, 503, 10));
+        assert!(log.should_log(id, 200, 500));
+
+        // 未设置慢请求阈值时不按延迟判断
+        let log = AccessLog::new(1000, 0);
+        assert!(!log.should_log(unsampled(&log), 200, u64::MAX));
+    }
+
+    #[test]
+    fn rollup_counts_requests_latency_and_usage() {
+        let log = AccessLog::new(10, 0);
+        assert!(log.take_summary().is_none());
+        for latency in 1..=100 {
+            log.record_request(latency);
+        }
+        let usage = Usage {
+            input_tokens: 10,
+            output_tokens: 5,
+            cache_read_tokens: 3,
+            cache_creation_tokens: 1,
+        };
+        log.record_usage(&usage);
+        log.record_usage(&usage);
+
+        let summary = log.take_summary().unwrap();
+        assert_eq!(summary.requests, 100);
+        assert_eq!(
+            (
+                summary.latency_p50_ms,
+                summary.latency_p90_ms,
+                summary.latency_p99_ms,
+                summary.latency_max_ms
+            ),
+            (50, 90, 99, 100)
+        );
+        assert_eq!(summary.usage.input_tokens, 20);
+        assert_eq!(summary.usage.output_tokens, 10);
+        assert_eq!(summary.usage.cache_read_tokens, 6);
+        assert_eq!(summary.usage.cache_creation_tokens, 2);
+        // 输出后清空窗口
+        assert!(log.take_summary().is_none());
+    }
+
+    #[tokio::test]
+    async fn unsampled_requests_go_to_the_state_rollup() {
+        let (_dir, config) = test_support::config("access_log_sample = 1000000");
+        let providers = [Arc::new(MockProvider::new("first"))];
+        let state = test_support::state(config, &providers);
+        let router = test_router(state.clone());
+
+        let request = test_support::messages_request(
+            USER_KEY,
+            &serde_json::json!({
+                "model": "claude-test",
+                "max_tokens": 16,
+                "messages": [{"role": "user", "content": "hi"}]
+            }),
+        );
+        test_support::send(&router, request).await;
+
+        let summary = state.access_log().take_summary().unwrap();
+        assert_eq!(summary.requests, 1);
+        assert_eq!(summary.usage.input_tokens, 10);
+        assert_eq!(summary.usage.output_tokens, 5);
+    }
+}
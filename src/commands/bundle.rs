@@ -0,0 +1,766 @@
+//! Bundle 命令 - 导出 / 导入 gateway 状态，用于迁移到新主机
+//!
+//! 此模块实现两个命令：
+//! - `export-bundle`: 将 provider 配置、客户端密钥、配置文件（可选用量数据库）打包为
+//!   `tar.zst`，附带记录版本与文件校验和的 `manifest.json`
+//! - `import-bundle`: 校验 manifest 后写入当前主机配置的路径，已有数据时需要 `--force`
+//!
+//! `--encrypt` 时含凭证的文件（provider 配置、keys 文件与可能含 `secret` 的配置文件）用口令加密：PBKDF2-HMAC-SHA256
+//! 派生密钥，ChaCha20-Poly1305 加密，每个文件独立的随机 nonce 置于密文之前。
+
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::output::Output;
+use crate::config::{self, Config};
+use crate::keys::KeyStore;
+use crate::providers::config as provider_config;
+use crate::utils::unix_timestamp_secs;
+
+/// bundle 格式版本，不兼容的修改时递增
+const BUNDLE_FORMAT: u32 = 1;
+
+const MANIFEST_PATH: &str = "manifest.json";
+const PROVIDERS_PREFIX: &str = "providers/";
+const KEYS_PATH: &str = "keys.toml";
+const USAGE_PATH: &str = "usage.db";
+const CONFIG_PATH: &str = "pluribus.toml";
+
+/// 读取 bundle 时解压后的总大小上限
+const MAX_BUNDLE_BYTES: u64 = 64 * 1024 * 1024;
+
+const ZSTD_LEVEL: i32 = 19;
+
+/// 迭代次数写入 manifest，测试中降低以免 debug 构建下派生过慢
+const PBKDF2_ITERATIONS: u32 = if cfg!(test) { 1_000 } else { 600_000 };
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// 口令环境变量，未设置时从标准输入读取
+const PASSPHRASE_ENV: &str = "PLURIBUS_BUNDLE_PASSPHRASE";
+
+/// bundle 中的文件类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum FileKind {
+    Provider,
+    Keys,
+    Usage,
+    Config,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleFile {
+    path: String,
+    kind: FileKind,
+    /// 明文的 SHA-256
+    sha256: String,
+    encrypted: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Encryption {
+    cipher: String,
+    kdf: String,
+    iterations: u32,
+    salt: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    format: u32,
+    pluribus_version: String,
+    created_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encryption: Option<Encryption>,
+    files: Vec<BundleFile>,
+    /// 导出主机不含密钥的运行配置，导入时与当前主机比较以提示需要设置的环境变量
+    settings: serde_json::Map<String, serde_json::Value>,
+}
+
+/// export-bundle 的参数
+pub struct ExportOptions {
+    pub output: PathBuf,
+    pub include_usage: bool,
+    pub encrypt: bool,
+}
+
+/// 导出 gateway 状态
+pub async fn export_bundle_command(
+    config: Config,
+    output: &Output,
+    options: ExportOptions,
+) -> Result<()> {
+    let cipher = if options.encrypt {
+        let passphrase = read_passphrase(output, true)?;
+        Some(BundleCipher::new(&passphrase))
+    } else {
+        None
+    };
+
+    let mut entries: Vec<(String, FileKind, Vec<u8>)> = Vec::new();
+
+    // 先完整加载一遍，确保导出的配置有效且已分配 ID
+    let providers =
+        provider_config::load_all(config.providers_dir(), config.strict_provider_config).await?;
+    for provider in &providers {
+        let path = config
+            .providers_dir()
+            .join(format!("{}.toml", provider.name));
+        let content =
+            std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        entries.push((
+            format!("{PROVIDERS_PREFIX}{}.toml", provider.name),
+            FileKind::Provider,
+            content,
+        ));
+    }
+
+    let optional = [
+        (KEYS_PATH, FileKind::Keys, config.keys_file.as_path()),
+        (CONFIG_PATH, FileKind::Config, config.config_file.as_path()),
+    ];
+    for (name, kind, path) in optional {
+        match std::fs::read(path) {
+            Ok(content) => entries.push((name.to_string(), kind, content)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+    if options.include_usage {
+        if let Some(content) = usage_snapshot(&config).await? {
+            entries.push((USAGE_PATH.to_string(), FileKind::Usage, content));
+        }
+    }
+
+    let mut files = Vec::new();
+    let mut payloads = Vec::new();
+    for (path, kind, content) in entries {
+        let encrypted = cipher.is_some() && kind != FileKind::Usage;
+        files.push(BundleFile {
+            path: path.clone(),
+            kind,
+            sha256: sha256_hex(&content),
+            encrypted,
+        });
+        let payload = match &cipher {
+            Some(cipher) if encrypted => cipher.encrypt(&content)?,
+            _ => content,
+        };
+        payloads.push((path, payload));
+    }
+
+    let manifest = Manifest {
+        format: BUNDLE_FORMAT,
+        pluribus_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: unix_timestamp_secs(),
+        encryption: cipher.as_ref().map(BundleCipher::describe),
+        files,
+        settings: config.settings(),
+    };
+    write_archive(&options.output, &manifest, &payloads)?;
+
+    output.text(format!(
+        "Exported {} provider(s){}{}{} to {}",
+        providers.len(),
+        if manifest.has(FileKind::Keys) {
+            ", keys"
+        } else {
+            ""
+        },
+        if manifest.has(FileKind::Config) {
+            ", config"
+        } else {
+            ""
+        },
+        if manifest.has(FileKind::Usage) {
+            ", usage"
+        } else {
+            ""
+        },
+        options.output.display()
+    ));
+    if cipher.is_none() {
+        output.text(
+            "The bundle contains credentials in plain text; use --encrypt or keep it private.",
+        );
+    }
+    Ok(())
+}
+
+/// 导入 gateway 状态到当前主机配置的路径
+pub async fn import_bundle_command(
+    config: Config,
+    output: &Output,
+    bundle: PathBuf,
+    force: bool,
+) -> Result<()> {
+    let (manifest, mut contents) = read_archive(&bundle)?;
+    if manifest.format != BUNDLE_FORMAT {
+        bail!(
+            "Unsupported bundle format {} (expected {BUNDLE_FORMAT}), exported by pluribus {}",
+            manifest.format,
+            manifest.pluribus_version
+        );
+    }
+
+    let cipher = match &manifest.encryption {
+        Some(encryption) => Some(BundleCipher::from_manifest(
+            encryption,
+            &read_passphrase(output, false)?,
+        )?),
+        None => None,
+    };
+
+    // 解密并校验所有文件，任何一项失败都不写入
+    let mut files = Vec::new();
+    for file in &manifest.files {
+        let payload = contents
+            .remove(&file.path)
+            .with_context(|| format!("Bundle is missing {}", file.path))?;
+        let content = match (&cipher, file.encrypted) {
+            (Some(cipher), true) => cipher.decrypt(&payload)?,
+            (None, true) => bail!(
+                "{} is encrypted but the manifest has no encryption",
+                file.path
+            ),
+            (_, false) => payload,
+        };
+        if sha256_hex(&content) != file.sha256 {
+            bail!(
+                "Checksum mismatch for {}, the bundle is corrupted",
+                file.path
+            );
+        }
+        let destination = destination(&config, file)?;
+        files.push((file, destination, content));
+    }
+
+    let existing: Vec<&PathBuf> = files
+        .iter()
+        .map(|(_, destination, _)| destination)
+        .filter(|destination| destination.exists())
+        .collect();
+    if !existing.is_empty() && !force {
+        let list = existing
+            .iter()
+            .map(|p| format!("  {}", p.display()))
+            .collect::<Vec<_>>()
+            .join("\n");
+        bail!("Refusing to overwrite existing data, use --force to replace:\n{list}");
+    }
+
+    validate(&config, &files).await?;
+
+    for (file, destination, content) in &files {
+        if file.kind == FileKind::Usage {
+            remove_sqlite_sidecars(destination)?;
+        }
+        if let Some(parent) = destination.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        std::fs::write(destination, content)
+            .with_context(|| format!("Failed to write {}", destination.display()))?;
+        output.text(format!("Imported {}", destination.display()));
+    }
+
+    let providers =
+        provider_config::load_all(config.providers_dir(), config.strict_provider_config).await?;
+    output.text(format!(
+        "Loaded {} provider(s) from {}",
+        providers.len(),
+        config.providers_dir().display()
+    ));
+    report_setting_differences(output, &manifest.settings, &config.settings());
+    Ok(())
+}
+
+impl Manifest {
+    fn has(&self, kind: FileKind) -> bool {
+        self.files.iter().any(|f| f.kind == kind)
+    }
+}
+
+/// 文件在当前主机上的写入路径
+fn destination(config: &Config, file: &BundleFile) -> Result<PathBuf> {
+    Ok(match file.kind {
+        FileKind::Provider => {
+            let name = file
+                .path
+                .strip_prefix(PROVIDERS_PREFIX)
+                .filter(|name| name.ends_with(".toml") && is_plain_file_name(name))
+                .with_context(|| format!("Invalid provider path in bundle: {}", file.path))?;
+            config.providers_dir().join(name)
+        }
+        FileKind::Keys => config.keys_file.clone(),
+        FileKind::Usage => config
+            .usage_db
+            .clone()
+            .context("The bundle contains usage history but PLURIBUS_USAGE_DB is not set")?,
+        FileKind::Config => config.config_file.clone(),
+    })
+}
+
+/// 用量数据库的一致快照，数据库还不存在时返回 `None`
+async fn usage_snapshot(config: &Config) -> Result<Option<Vec<u8>>> {
+    let Some(path) = &config.usage_db else {
+        bail!("--include-usage needs usage history, set PLURIBUS_USAGE_DB");
+    };
+    if !path.exists() {
+        return Ok(None);
+    }
+    #[cfg(feature = "usage-sqlite")]
+    {
+        let snapshot =
+            std::env::temp_dir().join(format!("pluribus-usage-{:016x}.db", rand::random::<u64>()));
+        let result = async {
+            crate::usage::UsageStore::open(path, false)
+                .await?
+                .snapshot(&snapshot)
+                .await?;
+            std::fs::read(&snapshot)
+                .with_context(|| format!("Failed to read {}", snapshot.display()))
+        }
+        .await;
+        let _ = std::fs::remove_file(&snapshot);
+        result.map(Some)
+    }
+    // 未启用 usage-sqlite 时配置中不会有 usage_db
+    #[cfg(not(feature = "usage-sqlite"))]
+    unreachable!("usage_db is rejected without the usage-sqlite feature")
+}
+
+/// 删除旧数据库留下的 WAL 文件，否则会被应用到导入的数据库上
+fn remove_sqlite_sidecars(database: &Path) -> Result<()> {
+    for suffix in ["-wal", "-shm"] {
+        let mut sidecar = database.as_os_str().to_owned();
+        sidecar.push(suffix);
+        match std::fs::remove_file(&sidecar) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e)
+                    .with_context(|| format!("Failed to remove {}", Path::new(&sidecar).display()))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn is_plain_file_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\'])
+}
+
+/// 在临时目录中用正常的加载逻辑检查导入的文件
+async fn validate(config: &Config, files: &[(&BundleFile, PathBuf, Vec<u8>)]) -> Result<()> {
+    let staging =
+        std::env::temp_dir().join(format!("pluribus-import-{:016x}", rand::random::<u64>()));
+    let result = validate_in(config, files, &staging).await;
+    let _ = std::fs::remove_dir_all(&staging);
+    result
+}
+
+async fn validate_in(
+    config: &Config,
+    files: &[(&BundleFile, PathBuf, Vec<u8>)],
+    staging: &Path,
+) -> Result<()> {
+    let providers_dir = staging.join("providers");
+    std::fs::create_dir_all(&providers_dir)?;
+
+    let mut provider_count = 0;
+    for (file, destination, content) in files {
+        let path = match file.kind {
+            FileKind::Provider => {
+                provider_count += 1;
+                providers_dir.join(destination.file_name().unwrap_or_default())
+            }
+            _ => staging.join(&file.path),
+        };
+        std::fs::write(&path, content)?;
+        match file.kind {
+            FileKind::Provider => {}
+            FileKind::Keys => {
+                KeyStore::load(&path, &config.secret)
+                    .context("Keys in the bundle are invalid on this host")?;
+            }
+            FileKind::Usage => {
+                // 没有用量历史时 destination 已经失败
+                #[cfg(feature = "usage-sqlite")]
+                crate::usage::UsageStore::open(&path, false)
+                    .await
+                    .context("Usage database in the bundle is invalid")?;
+            }
+            FileKind::Config => config::check_config_file(&path)?,
+        }
+    }
+
+    let providers =
+        provider_config::load_all(&providers_dir, config.strict_provider_config).await?;
+    if providers.len() != provider_count {
+        bail!(
+            "Only {} of {provider_count} provider configs in the bundle are valid",
+            providers.len()
+        );
+    }
+    Ok(())
+}
+
+/// 列出与导出主机不同的运行配置
+fn report_setting_differences(
+    output: &Output,
+    exported: &serde_json::Map<String, serde_json::Value>,
+    current: &serde_json::Map<String, serde_json::Value>,
+) {
+    let differences: Vec<_> = exported
+        .iter()
+        .filter(|(key, value)| current.get(*key) != Some(*value))
+        .collect();
+    if differences.is_empty() {
+        return;
+    }
+    output.text("Settings that differ from the exporting host (set the matching PLURIBUS_* variables if needed):");
+    for (key, value) in differences {
+        let current = current
+            .get(key)
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        output.text(format!("  {key}: {value} (here: {current})"));
+    }
+}
+
+fn write_archive(path: &Path, manifest: &Manifest, payloads: &[(String, Vec<u8>)]) -> Result<()> {
+    let file = create_private(path)?;
+    let encoder = zstd::Encoder::new(file, ZSTD_LEVEL)?;
+    let mut builder = tar::Builder::new(encoder);
+    let mtime = manifest.created_at;
+
+    let manifest = serde_json::to_vec_pretty(manifest)?;
+    append(&mut builder, MANIFEST_PATH, &manifest, mtime)?;
+    for (name, payload) in payloads {
+        append(&mut builder, name, payload, mtime)?;
+    }
+
+    let mut encoder = builder.into_inner()?;
+    encoder.flush()?;
+    encoder.finish()?;
+    Ok(())
+}
+
+fn append<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &str,
+    data: &[u8],
+    mtime: u64,
+) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(mtime);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, path, data)
+        .with_context(|| format!("Failed to add {path} to bundle"))
+}
+
+/// 创建仅所有者可读写的文件，bundle 中可能含有明文凭证
+fn create_private(path: &Path) -> Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))
+}
+
+fn read_archive(path: &Path) -> Result<(Manifest, HashMap<String, Vec<u8>>)> {
+    let file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let decoder = zstd::Decoder::new(file)?;
+    let mut archive = tar::Archive::new(decoder.take(MAX_BUNDLE_BYTES));
+
+    let mut contents = HashMap::new();
+    for entry in archive
+        .entries()
+        .with_context(|| format!("{} is not a pluribus bundle", path.display()))?
+    {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        contents.insert(name, data);
+    }
+
+    let manifest = contents
+        .remove(MANIFEST_PATH)
+        .with_context(|| format!("{} has no {MANIFEST_PATH}", path.display()))?;
+    let manifest: Manifest =
+        serde_json::from_slice(&manifest).context("Failed to parse bundle manifest")?;
+    Ok((manifest, contents))
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// 读取口令：优先使用环境变量，否则从标准输入读取（导出时需要输入两次）
+fn read_passphrase(output: &Output, confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        if passphrase.is_empty() {
+            bail!("{PASSPHRASE_ENV} is empty");
+        }
+        return Ok(passphrase);
+    }
+
+    let prompt = |label: &str| -> Result<String> {
+        output.prompt(label)?;
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)?;
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    };
+    let passphrase = prompt("Bundle passphrase")?;
+    if passphrase.is_empty() {
+        bail!("Passphrase cannot be empty");
+    }
+    if confirm && prompt("Repeat passphrase")? != passphrase {
+        bail!("Passphrases do not match");
+    }
+    Ok(passphrase)
+}
+
+/// 由口令派生的文件加密器
+struct BundleCipher {
+    cipher: ChaCha20Poly1305,
+    salt: [u8; SALT_LEN],
+    iterations: u32,
+}
+
+impl BundleCipher {
+    fn new(passphrase: &str) -> Self {
+        Self::derive(passphrase, rand::random(), PBKDF2_ITERATIONS)
+    }
+
+    fn from_manifest(encryption: &Encryption, passphrase: &str) -> Result<Self> {
+        if encryption.cipher != "chacha20poly1305" || encryption.kdf != "pbkdf2-hmac-sha256" {
+            bail!(
+                "Unsupported bundle encryption {} / {}",
+                encryption.cipher,
+                encryption.kdf
+            );
+        }
+        let salt: [u8; SALT_LEN] = STANDARD
+            .decode(&encryption.salt)
+            .ok()
+            .and_then(|salt| salt.try_into().ok())
+            .context("Invalid salt in bundle manifest")?;
+        Ok(Self::derive(passphrase, salt, encryption.iterations))
+    }
+
+    fn derive(passphrase: &str, salt: [u8; SALT_LEN], iterations: u32) -> Self {
+        let key: [u8; 32] =
+            pbkdf2::pbkdf2_hmac_array::<Sha256, 32>(passphrase.as_bytes(), &salt, iterations);
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            salt,
+            iterations,
+        }
+    }
+
+    fn describe(&self) -> Encryption {
+        Encryption {
+            cipher: "chacha20poly1305".to_string(),
+            kdf: "pbkdf2-hmac-sha256".to_string(),
+            iterations: self.iterations,
+            salt: STANDARD.encode(self.salt),
+        }
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt bundle file"))?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    fn decrypt(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        if payload.len() < NONCE_LEN {
+            bail!("Encrypted bundle file is truncated");
+        }
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow::anyhow!("Wrong passphrase or corrupted bundle"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, SECRET};
+
+    const PROVIDER: &str = "id = \"id-first\"\ntype = \"claude_code\"\n\n[oauth]\n\
+                            access_token = \"sk-ant-oat-secret-access\"\n\
+                            refresh_token = \"sk-ant-ort-secret-refresh\"\n\
+                            expires_at = 3786912000000\n";
+
+    /// 有一个 provider 的导出主机
+    fn source() -> (tempfile::TempDir, Config) {
+        let (dir, config) = test_support::config("");
+        std::fs::write(config.providers_dir().join("first.toml"), PROVIDER).unwrap();
+        (dir, config)
+    }
+
+    /// 还没有任何数据的导入主机
+    fn empty_target() -> (tempfile::TempDir, Config) {
+        let (dir, config) = test_support::config("");
+        std::fs::remove_file(&config.keys_file).unwrap();
+        std::fs::remove_file(&config.config_file).unwrap();
+        (dir, config)
+    }
+
+    async fn export(config: &Config, output: &Path, encrypt: bool) {
+        let options = ExportOptions {
+            output: output.to_path_buf(),
+            include_usage: false,
+            encrypt,
+        };
+        export_bundle_command(config.clone(), &test_support::quiet(), options)
+            .await
+            .unwrap();
+    }
+
+    fn read(path: &Path) -> Vec<u8> {
+        std::fs::read(path).unwrap()
+    }
+
+    fn contains(haystack: &[u8], needle: &str) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle.as_bytes())
+    }
+
+    #[tokio::test]
+    async fn bundle_round_trips_to_a_new_host() {
+        let (dir, source) = source();
+        let bundle = dir.path().join("state.tar.zst");
+        export(&source, &bundle, false).await;
+
+        let (manifest, contents) = read_archive(&bundle).unwrap();
+        let mut paths: Vec<&str> = manifest.files.iter().map(|f| f.path.as_str()).collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            ["keys.toml", "pluribus.toml", "providers/first.toml"]
+        );
+        assert!(manifest.files.iter().all(|f| !f.encrypted));
+        assert_eq!(contents["providers/first.toml"], PROVIDER.as_bytes());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&bundle).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let (_target_dir, target) = empty_target();
+        import_bundle_command(target.clone(), &test_support::quiet(), bundle, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            read(&target.providers_dir().join("first.toml")),
+            PROVIDER.as_bytes()
+        );
+        assert_eq!(read(&target.keys_file), read(&source.keys_file));
+        assert_eq!(read(&target.config_file), read(&source.config_file));
+    }
+
+    #[tokio::test]
+    async fn import_refuses_to_overwrite_without_force() {
+        let (dir, source) = source();
+        let bundle = dir.path().join("state.tar.zst");
+        export(&source, &bundle, false).await;
+
+        let (_target_dir, target) = test_support::config("");
+        std::fs::write(&target.keys_file, "# local keys\n").unwrap();
+        let err = import_bundle_command(
+            target.clone(),
+            &test_support::quiet(),
+            bundle.clone(),
+            false,
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Refusing to overwrite existing data"),
+            "{err}"
+        );
+        assert!(err.to_string().contains("keys.toml"), "{err}");
+        // 拒绝时不写入任何文件
+        assert_eq!(read(&target.keys_file), b"# local keys\n");
+        assert!(!target.providers_dir().join("first.toml").exists());
+
+        import_bundle_command(target.clone(), &test_support::quiet(), bundle, true)
+            .await
+            .unwrap();
+        assert_eq!(read(&target.keys_file), read(&source.keys_file));
+        assert!(target.providers_dir().join("first.toml").exists());
+    }
+
+    #[tokio::test]
+    async fn encrypted_bundles_hide_every_credential() {
+        std::env::set_var(PASSPHRASE_ENV, "correct horse battery staple");
+        let (dir, source) = source();
+        let bundle = dir.path().join("state.tar.zst");
+        export(&source, &bundle, true).await;
+
+        let (manifest, contents) = read_archive(&bundle).unwrap();
+        for file in &manifest.files {
+            assert!(file.encrypted, "{} is stored in plain text", file.path);
+        }
+        for (path, payload) in &contents {
+            for secret in [
+                "sk-ant-oat-secret-access",
+                "sk-ant-ort-secret-refresh",
+                SECRET,
+            ] {
+                assert!(!contains(payload, secret), "{path} leaks {secret}");
+            }
+        }
+        // 运行配置中不含 secret
+        let settings = serde_json::to_string(&manifest.settings).unwrap();
+        assert!(!settings.contains(SECRET), "{settings}");
+
+        let encryption = manifest.encryption.as_ref().unwrap();
+        let wrong = BundleCipher::from_manifest(encryption, "wrong passphrase").unwrap();
+        let err = wrong
+            .decrypt(&contents["providers/first.toml"])
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Wrong passphrase or corrupted bundle");
+
+        let (_target_dir, target) = empty_target();
+        import_bundle_command(target.clone(), &test_support::quiet(), bundle, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            read(&target.providers_dir().join("first.toml")),
+            PROVIDER.as_bytes()
+        );
+        assert_eq!(read(&target.config_file), read(&source.config_file));
+    }
+}
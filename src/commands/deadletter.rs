@@ -0,0 +1,158 @@
+//! Deadletter 命令 - 查看和重新提交失败的请求
+//!
+//! 此模块实现 `deadletter` 命令：
+//! - `deadletter list`: 列出未过期的死信及其尝试链
+//! - `deadletter retry`: 将保存的请求体通过本地服务器重新提交
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+
+use super::output::Output;
+use crate::config::Config;
+use crate::dead_letter::{self, DeadLetter, FailedAttempt, DEAD_LETTER_HEADER};
+use crate::utils::{format_timestamp, unix_timestamp_secs};
+
+fn load(config: &Config) -> Result<Vec<DeadLetter>> {
+    dead_letter::read_dead_letters(
+        &config.dead_letter_file,
+        config.dead_letter_retention_days,
+        unix_timestamp_secs(),
+    )
+}
+
+/// `deadletter list` 的 JSON 输出，不含请求体
+#[derive(Serialize)]
+struct DeadLetterListing<'a> {
+    dead_letters: Vec<DeadLetterEntry<'a>>,
+}
+
+#[derive(Serialize)]
+struct DeadLetterEntry<'a> {
+    id: &'a str,
+    timestamp: u64,
+    client_key: Option<&'a str>,
+    model: &'a str,
+    attempts: &'a [FailedAttempt],
+    error: &'a str,
+    has_body: bool,
+}
+
+/// `deadletter retry` 的 JSON 输出
+#[derive(Serialize)]
+struct RetryReport {
+    id: String,
+    status: u16,
+    response: Value,
+}
+
+/// 列出死信
+pub fn deadletter_list_command(config: Config, output: &Output) -> Result<()> {
+    let entries = load(&config)?;
+    if output.is_json() {
+        let dead_letters = entries
+            .iter()
+            .map(|entry| DeadLetterEntry {
+                id: &entry.id,
+                timestamp: entry.timestamp,
+                client_key: entry.client_key.as_deref(),
+                model: &entry.model,
+                attempts: &entry.attempts,
+                error: &entry.error,
+                has_body: entry.body.is_some(),
+            })
+            .collect();
+        return output.emit(&DeadLetterListing { dead_letters });
+    }
+    if entries.is_empty() {
+        println!("No dead letters in {}", config.dead_letter_file.display());
+        return Ok(());
+    }
+
+    println!(
+        "{:<24} {:<17} {:<16} {:<28} {:<5} ATTEMPTS",
+        "ID", "TIME", "KEY", "MODEL", "BODY"
+    );
+    for entry in &entries {
+        let attempts = entry
+            .attempts
+            .iter()
+            .map(|a| match a.status {
+                Some(status) => format!("{}:{}", a.provider, status),
+                None => format!("{}:error", a.provider),
+            })
+            .collect::<Vec<_>>()
+            .join(" → ");
+        println!(
+            "{:<24} {:<17} {:<16} {:<28} {:<5} {}",
+            entry.id,
+            format_timestamp(entry.timestamp),
+            entry.client_key.as_deref().unwrap_or("-"),
+            entry.model,
+            if entry.body.is_some() { "yes" } else { "no" },
+            if attempts.is_empty() { "-" } else { &attempts }
+        );
+    }
+    Ok(())
+}
+
+/// 重新提交死信
+///
+/// 以非流式方式发往本地服务器；未保存请求体的死信无法重新提交
+pub async fn deadletter_retry_command(
+    config: Config,
+    output: &Output,
+    id: String,
+    provider: Option<String>,
+) -> Result<()> {
+    let entry = load(&config)?
+        .into_iter()
+        .find(|entry| entry.id == id)
+        .with_context(|| format!("Dead letter {id} not found"))?;
+    let Some(mut body) = entry.body else {
+        anyhow::bail!(
+            "Dead letter {id} has no stored body; send requests with '{DEAD_LETTER_HEADER}: body' to keep it"
+        );
+    };
+    if let Some(obj) = body.as_object_mut() {
+        obj.insert("stream".to_string(), Value::Bool(false));
+    }
+
+    let url = format!(
+        "http://{}:{}/anthropic/v1/messages",
+        config.host, config.port
+    );
+    output.text(format!("Retrying {} ({}) via {}", id, entry.model, url));
+
+    let mut request = reqwest::Client::new()
+        .post(&url)
+        .header("Authorization", format!("Bearer {}", config.secret))
+        .header(DEAD_LETTER_HEADER, "body")
+        .json(&body);
+    if let Some(provider) = &provider {
+        request = request.header("x-pluribus-provider", provider);
+    }
+    let response = request
+        .send()
+        .await
+        .context("Request failed. Make sure the server is running.")?;
+
+    let status = response.status();
+    output.text(format!("Response status: {}", status));
+    let text = response
+        .text()
+        .await
+        .context("Failed to read response body")?;
+    if !status.is_success() {
+        anyhow::bail!("Retry failed: {}", text);
+    }
+    if output.is_json() {
+        return output.emit(&RetryReport {
+            id,
+            status: status.as_u16(),
+            response: serde_json::from_str(&text).unwrap_or(Value::String(text)),
+        });
+    }
+    println!("{}", text);
+    Ok(())
+}
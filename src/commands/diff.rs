@@ -0,0 +1,226 @@
+//! Diff 命令 - 比较磁盘上的配置与运行中的服务器
+//!
+//! 读取磁盘上的 providers、keys 以及环境变量 / `pluribus.toml` 配置，与运行中服务器的
+//! `/admin/info` 和 `/admin/providers` 比较，列出重新加载后会发生的变化。
+//!
+//! 退出码：0 表示没有变化，2 表示只有可热加载的变化（keys 发送 SIGHUP、providers 发送
+//! SIGUSR1 即可生效），3 表示有需要重启才能生效的变化。
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+use super::output::Output;
+use crate::config::Config;
+use crate::keys::{self, KeySummary};
+use crate::providers::{config as provider_config, ProviderSummary};
+
+const EXIT_RELOADABLE: i32 = 2;
+const EXIT_RESTART_REQUIRED: i32 = 3;
+
+/// JSON 输出，各项变化与文本输出的行相同
+#[derive(Serialize)]
+struct DiffReport {
+    providers: Vec<String>,
+    keys: Vec<String>,
+    settings: Vec<String>,
+    warnings: Vec<String>,
+    /// 有需要重启才能生效的变化（退出码 3）
+    restart_required: bool,
+    /// 只有可通过 SIGHUP / SIGUSR1 加载的变化（退出码 2）
+    reloadable: bool,
+}
+
+/// 变化时需要额外提示的配置项
+const RESTART_WARNINGS: &[(&str, &str)] = &[
+    ("host", "bind address"),
+    ("port", "bind address"),
+    ("tls_verify_disabled", "upstream TLS verification"),
+];
+
+/// 执行 diff 命令
+///
+/// `url` 为运行中服务器的地址，默认使用当前配置的监听地址
+pub async fn diff_command(config: Config, output: &Output, url: Option<String>) -> Result<()> {
+    let base = url.unwrap_or_else(|| format!("http://{}:{}", config.host, config.port));
+    let base = base.trim_end_matches('/');
+    let client = reqwest::Client::new();
+
+    let info: Value = fetch(&client, &format!("{base}/admin/info"), &config.secret).await?;
+    let running_providers: Vec<ProviderSummary> =
+        fetch(&client, &format!("{base}/admin/providers"), &config.secret).await?;
+    let running_keys: Vec<KeySummary> =
+        serde_json::from_value(info.get("keys").cloned().unwrap_or_default())
+            .context("Server did not report its keys, is it running an older version?")?;
+    let running_settings = info
+        .get("settings")
+        .and_then(|s| s.as_object())
+        .cloned()
+        .unwrap_or_default();
+
+    let disk_providers: Vec<ProviderSummary> =
+        provider_config::load_all(config.providers_dir(), config.strict_provider_config)
+            .await?
+            .iter()
+            .map(ProviderSummary::from)
+            .collect();
+    let disk_keys: Vec<KeySummary> = keys::read_keys_file(&config.keys_file)?
+        .iter()
+        .map(KeySummary::from)
+        .collect();
+
+    let provider_changes = diff_named(
+        running_providers.iter().map(|p| (p.name.clone(), p)),
+        disk_providers.iter().map(|p| (p.name.clone(), p)),
+    );
+    let key_changes = diff_named(
+        running_keys.iter().map(|k| (k.name.clone(), k)),
+        disk_keys.iter().map(|k| (k.name.clone(), k)),
+    );
+    let (setting_changes, changed_settings) = diff_settings(&running_settings, &config.settings());
+
+    print_section(output, "providers (reload with SIGUSR1)", &provider_changes);
+    print_section(output, "keys (reload with SIGHUP)", &key_changes);
+    print_section(output, "settings (restart required)", &setting_changes);
+
+    let warnings: Vec<String> = RESTART_WARNINGS
+        .iter()
+        .filter(|(key, _)| changed_settings.iter().any(|field| field == key))
+        .map(|(key, label)| format!("! {label} changes ({key}), restart required"))
+        .collect();
+    print_section(output, "warnings", &warnings);
+
+    let restart_required = !setting_changes.is_empty();
+    let reloadable = !restart_required && (!provider_changes.is_empty() || !key_changes.is_empty());
+    let mut signals = Vec::new();
+    if !key_changes.is_empty() {
+        signals.push("SIGHUP for keys");
+    }
+    if !provider_changes.is_empty() {
+        signals.push("SIGUSR1 for providers");
+    }
+    output.emit(&DiffReport {
+        providers: provider_changes,
+        keys: key_changes,
+        settings: setting_changes,
+        warnings,
+        restart_required,
+        reloadable,
+    })?;
+    if restart_required {
+        output.text("Restart required");
+        std::process::exit(EXIT_RESTART_REQUIRED);
+    }
+    if reloadable {
+        output.text(format!(
+            "Reloadable changes, send {} to apply",
+            signals.join(" and ")
+        ));
+        std::process::exit(EXIT_RELOADABLE);
+    }
+    output.text("No changes");
+    Ok(())
+}
+
+async fn fetch<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    url: &str,
+    secret: &str,
+) -> Result<T> {
+    let response = client
+        .get(url)
+        .header("Authorization", format!("Bearer {}", secret))
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach {url}. Make sure the server is running."))?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        anyhow::bail!("{url} returned {status}: {body}");
+    }
+    response
+        .json()
+        .await
+        .with_context(|| format!("Failed to parse response from {url}"))
+}
+
+/// 按名称比较两组配置，修改的条目按字段列出
+fn diff_named<'a, T: Serialize + 'a>(
+    running: impl Iterator<Item = (String, &'a T)>,
+    disk: impl Iterator<Item = (String, &'a T)>,
+) -> Vec<String> {
+    let running = to_value_map(running);
+    let disk = to_value_map(disk);
+
+    let mut changes = Vec::new();
+    for name in disk.keys().filter(|name| !running.contains_key(*name)) {
+        changes.push(format!("+ {name}"));
+    }
+    for name in running.keys().filter(|name| !disk.contains_key(*name)) {
+        changes.push(format!("- {name}"));
+    }
+    for (name, new) in &disk {
+        let Some(old) = running.get(name) else {
+            continue;
+        };
+        let (Some(old), Some(new)) = (old.as_object(), new.as_object()) else {
+            continue;
+        };
+        for field in changed_fields(old, new) {
+            changes.push(format!(
+                "~ {name}.{field}: {} → {}",
+                old.get(&field).unwrap_or(&Value::Null),
+                new.get(&field).unwrap_or(&Value::Null)
+            ));
+        }
+    }
+    changes
+}
+
+fn to_value_map<'a, T: Serialize + 'a>(
+    items: impl Iterator<Item = (String, &'a T)>,
+) -> BTreeMap<String, Value> {
+    items
+        .map(|(name, item)| (name, serde_json::to_value(item).unwrap_or_default()))
+        .collect()
+}
+
+/// 比较运行中与磁盘上的配置项，同时返回发生变化的配置名
+fn diff_settings(
+    running: &Map<String, Value>,
+    disk: &Map<String, Value>,
+) -> (Vec<String>, Vec<String>) {
+    let fields = changed_fields(running, disk);
+    let changes = fields
+        .iter()
+        .map(|field| {
+            format!(
+                "~ {field}: {} → {}",
+                running.get(field).unwrap_or(&Value::Null),
+                disk.get(field).unwrap_or(&Value::Null)
+            )
+        })
+        .collect();
+    (changes, fields)
+}
+
+fn changed_fields(old: &Map<String, Value>, new: &Map<String, Value>) -> Vec<String> {
+    let mut fields: Vec<String> = old.keys().chain(new.keys()).cloned().collect();
+    fields.sort();
+    fields.dedup();
+    fields.retain(|field| old.get(field) != new.get(field));
+    fields
+}
+
+fn print_section(output: &Output, title: &str, lines: &[String]) {
+    if lines.is_empty() {
+        return;
+    }
+    output.text(format!("{title}:"));
+    for line in lines {
+        output.text(format!("  {line}"));
+    }
+    output.text("");
+}
@@ -0,0 +1,190 @@
+//! Keys 命令 - 查看和清理客户端密钥
+//!
+//! 此模块实现 `keys` 命令：
+//! - `keys list`: 列出 keys 文件中的密钥及其使用情况
+//! - `keys prune`: 删除长期未使用的密钥
+
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use super::output::Output;
+use crate::config::Config;
+use crate::keys::{self, ApiKey, KeyUsage, Role};
+#[cfg(feature = "usage-sqlite")]
+use crate::usage::UsageStore;
+use crate::utils::{format_timestamp, unix_timestamp_secs};
+
+const SECS_PER_DAY: u64 = 86_400;
+
+/// `keys list` 的 JSON 输出
+#[derive(Serialize)]
+struct KeyListing {
+    keys: Vec<KeyEntry>,
+}
+
+#[derive(Serialize)]
+struct KeyEntry {
+    name: String,
+    role: Role,
+    created_at: Option<u64>,
+    expires_at: Option<u64>,
+    expired: bool,
+    last_used: Option<u64>,
+    requests: u64,
+}
+
+/// `keys prune` 的 JSON 输出
+#[derive(Serialize)]
+struct PruneReport {
+    /// 删除（`dry_run` 时为将被删除）的密钥
+    removed: Vec<PrunedKey>,
+    dry_run: bool,
+}
+
+#[derive(Serialize)]
+struct PrunedKey {
+    name: String,
+    last_used: Option<u64>,
+}
+
+/// 列出所有密钥
+///
+/// 显示名称、角色、创建 / 过期时间、最后使用时间和累计请求数（来自用量数据库）。
+/// `PLURIBUS_SECRET` 对应的 `default` 密钥不在 keys 文件中，不会列出。
+pub async fn keys_list_command(config: Config, output: &Output) -> Result<()> {
+    let entries = keys::read_keys_file(&config.keys_file)?;
+    let usage = read_key_usage(&config).await?.unwrap_or_default();
+    let now = unix_timestamp_secs();
+
+    if output.is_json() {
+        let keys = entries
+            .iter()
+            .map(|key| {
+                let key_usage = usage.get(&key.name).cloned().unwrap_or_default();
+                KeyEntry {
+                    name: key.name.clone(),
+                    role: key.role,
+                    created_at: key.created_at,
+                    expires_at: key.policy.expires_at,
+                    expired: key.is_expired(now),
+                    last_used: key_usage.last_used,
+                    requests: key_usage.requests,
+                }
+            })
+            .collect();
+        return output.emit(&KeyListing { keys });
+    }
+    if entries.is_empty() {
+        println!("No keys in {}", config.keys_file.display());
+        return Ok(());
+    }
+
+    let date = |ts: Option<u64>| ts.map(format_timestamp).unwrap_or_else(|| "-".into());
+
+    println!(
+        "{:<20} {:<9} {:<17} {:<27} {:<17} {:>8}",
+        "NAME", "ROLE", "CREATED", "EXPIRES", "LAST USED", "REQUESTS"
+    );
+    for key in &entries {
+        let key_usage = usage.get(&key.name).cloned().unwrap_or_default();
+        let mut expires = date(key.policy.expires_at);
+        if key.is_expired(now) {
+            expires.push_str(" (expired)");
+        }
+        println!(
+            "{:<20} {:<9} {:<17} {:<27} {:<17} {:>8}",
+            key.name,
+            key.role.as_str(),
+            date(key.created_at),
+            expires,
+            date(key_usage.last_used),
+            key_usage.requests
+        );
+    }
+    Ok(())
+}
+
+/// 删除超过 `unused_days` 天未使用的密钥
+///
+/// 从未使用过的密钥按 `created_at` 判断；两者都没有时无法判断使用情况，予以保留。
+/// `dry_run` 时只列出将被删除的密钥，不修改文件。没有用量数据库时无法判断，返回错误。
+pub async fn keys_prune_command(
+    config: Config,
+    output: &Output,
+    unused_days: u64,
+    dry_run: bool,
+) -> Result<()> {
+    let Some(usage) = read_key_usage(&config).await? else {
+        anyhow::bail!("Key usage is only recorded with usage history, set PLURIBUS_USAGE_DB");
+    };
+    let entries = keys::read_keys_file(&config.keys_file)?;
+    let cutoff = unix_timestamp_secs().saturating_sub(unused_days * SECS_PER_DAY);
+
+    let (stale, kept): (Vec<ApiKey>, Vec<ApiKey>) = entries
+        .into_iter()
+        .partition(|key| is_unused_since(key, usage.get(&key.name), cutoff));
+
+    let report = PruneReport {
+        removed: stale
+            .iter()
+            .map(|key| PrunedKey {
+                name: key.name.clone(),
+                last_used: usage.get(&key.name).and_then(|u| u.last_used),
+            })
+            .collect(),
+        dry_run,
+    };
+
+    if stale.is_empty() {
+        output.text(format!("No keys unused for {} days", unused_days));
+        return output.emit(&report);
+    }
+
+    for key in &report.removed {
+        let last_used = key
+            .last_used
+            .map(format_timestamp)
+            .unwrap_or_else(|| "never".into());
+        output.text(format!("{} (last used: {})", key.name, last_used));
+    }
+
+    if dry_run {
+        output.text(format!(
+            "\n{} key(s) would be removed (dry run)",
+            stale.len()
+        ));
+        return output.emit(&report);
+    }
+
+    keys::write_keys_file(&config.keys_file, kept)?;
+    output.text(format!(
+        "\nRemoved {} key(s) from {}",
+        stale.len(),
+        config.keys_file.display()
+    ));
+    output.text("Send SIGHUP to a running server to reload keys.");
+    output.emit(&report)
+}
+
+/// 从用量数据库读取密钥使用记录，未启用用量历史时返回 `None`
+#[cfg_attr(not(feature = "usage-sqlite"), allow(unused_variables))]
+async fn read_key_usage(config: &Config) -> Result<Option<HashMap<String, KeyUsage>>> {
+    #[cfg(feature = "usage-sqlite")]
+    if let Some(path) = &config.usage_db {
+        if !path.exists() {
+            return Ok(Some(HashMap::new()));
+        }
+        return Ok(Some(
+            UsageStore::open(path, false).await?.key_usage().await?,
+        ));
+    }
+    Ok(None)
+}
+
+fn is_unused_since(key: &ApiKey, usage: Option<&KeyUsage>, cutoff: u64) -> bool {
+    match usage.and_then(|u| u.last_used).or(key.created_at) {
+        Some(ts) => ts < cutoff,
+        None => false,
+    }
+}
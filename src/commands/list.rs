@@ -0,0 +1,207 @@
+//! List 命令 - 列出已配置的 provider
+//!
+//! 直接读取 provider 配置目录，不需要服务器在运行
+
+use anyhow::Result;
+use serde::Serialize;
+
+use super::output::Output;
+use crate::config::Config;
+use crate::providers::config::{self, AuthConfig, ProviderConfig};
+use crate::utils::{format_relative, unix_timestamp_ms};
+
+/// 在此时间内过期的 token 标记为即将过期
+const EXPIRING_WITHIN_MS: u64 = 3600 * 1000;
+
+/// `list` 的 JSON 输出
+#[derive(Serialize)]
+struct ProviderListing {
+    providers: Vec<ProviderEntry>,
+}
+
+#[derive(Serialize)]
+struct ProviderEntry {
+    name: String,
+    id: String,
+    #[serde(rename = "type")]
+    provider_type: &'static str,
+    /// `oauth` 或 `api_key`
+    auth: &'static str,
+    /// OAuth token 过期时间 (Unix timestamp)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    /// OAuth token 的状态
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token_status: Option<TokenStatus>,
+    /// API key 的末 4 位
+    #[serde(skip_serializing_if = "Option::is_none")]
+    api_key_last4: Option<String>,
+    scopes: Vec<String>,
+    /// 推理所需但授权中缺少的 scopes
+    missing_scopes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum TokenStatus {
+    Valid,
+    /// 一小时内过期
+    Expiring,
+    Expired,
+}
+
+impl TokenStatus {
+    fn of(expires_at_ms: u64, now_ms: u64) -> Self {
+        if expires_at_ms <= now_ms {
+            TokenStatus::Expired
+        } else if expires_at_ms - now_ms <= EXPIRING_WITHIN_MS {
+            TokenStatus::Expiring
+        } else {
+            TokenStatus::Valid
+        }
+    }
+}
+
+/// 末 4 位，过短的 key 不显示
+fn last4(api_key: &str) -> Option<String> {
+    let chars: Vec<char> = api_key.trim().chars().collect();
+    (chars.len() >= 8).then(|| chars[chars.len() - 4..].iter().collect())
+}
+
+fn entry(config: &ProviderConfig, now_ms: u64) -> ProviderEntry {
+    let (auth, expires_at, token_status, api_key_last4, scopes) = match &config.auth {
+        AuthConfig::OAuth(oauth) => (
+            "oauth",
+            Some(oauth.expires_at / 1000),
+            Some(TokenStatus::of(oauth.expires_at, now_ms)),
+            None,
+            oauth.scopes.clone(),
+        ),
+        AuthConfig::Api(api) => ("api_key", None, None, last4(&api.api_key), Vec::new()),
+    };
+    ProviderEntry {
+        name: config.name.clone(),
+        id: config.id.clone(),
+        provider_type: config.provider_type.as_str(),
+        auth,
+        expires_at,
+        token_status,
+        api_key_last4,
+        scopes,
+        missing_scopes: config.missing_scopes(),
+    }
+}
+
+/// 列出 provider 配置目录中的 provider
+///
+/// 显示名称、类型、认证方式、token 过期时间（API key 显示末 4 位）与 scopes，
+/// 标记已过期或一小时内过期的 token。无法加载的配置文件在日志中警告并跳过。
+pub async fn list_command(config: Config, output: &Output) -> Result<()> {
+    let mut configs =
+        config::load_all(config.providers_dir(), config.strict_provider_config).await?;
+    configs.sort_by(|a, b| a.name.cmp(&b.name));
+    let now_ms = unix_timestamp_ms();
+    let entries: Vec<ProviderEntry> = configs.iter().map(|c| entry(c, now_ms)).collect();
+
+    if output.is_json() {
+        return output.emit(&ProviderListing { providers: entries });
+    }
+    if entries.is_empty() {
+        println!("No providers in {}", config.providers_dir().display());
+        return Ok(());
+    }
+
+    println!(
+        "{:<20} {:<12} {:<8} {:<28} SCOPES",
+        "NAME", "TYPE", "AUTH", "TOKEN"
+    );
+    for (entry, provider) in entries.iter().zip(&configs) {
+        let token = match (&provider.auth, entry.token_status) {
+            (AuthConfig::OAuth(oauth), Some(status)) => {
+                let delta = (oauth.expires_at as i64 - now_ms as i64) / 1000;
+                match status {
+                    TokenStatus::Valid => format!("expires {}", format_relative(delta)),
+                    TokenStatus::Expiring => format!("expires {} (soon)", format_relative(delta)),
+                    TokenStatus::Expired => format!("expired {}", format_relative(delta)),
+                }
+            }
+            _ => match &entry.api_key_last4 {
+                Some(last4) => format!("api key ****{last4}"),
+                None => "api key".to_string(),
+            },
+        };
+        let mut scopes = if entry.scopes.is_empty() {
+            "-".to_string()
+        } else {
+            entry.scopes.join(",")
+        };
+        if !entry.missing_scopes.is_empty() {
+            scopes.push_str(&format!(" (missing {})", entry.missing_scopes.join(",")));
+        }
+        println!(
+            "{:<20} {:<12} {:<8} {:<28} {}",
+            entry.name, entry.provider_type, entry.auth, token, scopes
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::OutputFormat;
+    use crate::test_support;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn json_output_lists_providers_by_name() {
+        let (_dir, config) = test_support::config("");
+        let providers = config.providers_dir();
+        std::fs::write(
+            providers.join("b.toml"),
+            "id = \"id-b\"\ntype = \"claude_code\"\n\n[oauth]\naccess_token = \"a\"\n\
+             refresh_token = \"r\"\nexpires_at = 1600000000000\nscopes = [\"user:inference\"]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            providers.join("a.toml"),
+            "id = \"id-a\"\ntype = \"anthropic\"\n\n[api]\nbase_url = \"https://api.example.com\"\n\
+             api_key = \"sk-test-1234abcd\"\n",
+        )
+        .unwrap();
+
+        let (output, stdout, stderr) = Output::captured(OutputFormat::Json);
+        let result = list_command(config, &output).await;
+        output.finish(result).unwrap();
+
+        assert_eq!(
+            stdout.json(),
+            json!({
+                "ok": true,
+                "errors": [],
+                "providers": [
+                    {
+                        "name": "a",
+                        "id": "id-a",
+                        "type": "anthropic",
+                        "auth": "api_key",
+                        "api_key_last4": "abcd",
+                        "scopes": [],
+                        "missing_scopes": [],
+                    },
+                    {
+                        "name": "b",
+                        "id": "id-b",
+                        "type": "claude_code",
+                        "auth": "oauth",
+                        "expires_at": 1600000000,
+                        "token_status": "expired",
+                        "scopes": ["user:inference"],
+                        "missing_scopes": [],
+                    },
+                ],
+            })
+        );
+        assert_eq!(stderr.contents(), "");
+    }
+}
@@ -3,10 +3,12 @@
 //! 此模块实现 `login` 命令，用于通过 OAuth 流程登录到各种 AI Provider。
 //! 当前支持 Claude Code 的 OAuth 认证。
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 
+use super::output::Output;
 use crate::config::Config;
-use crate::providers::claude_code;
+use crate::providers::claude_code::{self, PendingLogin};
+use crate::providers::config::{new_provider_id, DEFAULT_WEIGHT};
 use crate::providers::{AuthConfig, ProviderConfig, ProviderType};
 
 /// 执行登录命令
@@ -16,11 +18,12 @@ use crate::providers::{AuthConfig, ProviderConfig, ProviderType};
 /// * `app_config` - 应用配置
 /// * `provider_type` - Provider 类型（如 ClaudeCode）
 /// * `name` - 可选的 Provider 实例名称（如果未提供，使用默认名称）
+/// * `resume` - 完成上次中断的登录，只需输入授权码
 ///
 /// # 工作流程
 ///
-/// 1. 打开浏览器进行 OAuth 授权
-/// 2. 用户在浏览器中完成授权并获取授权码
+/// 1. 生成授权 URL，并将 PKCE 状态保存到 providers 目录（10 分钟内有效）
+/// 2. 用户在浏览器中完成授权并获取授权码（中断后可用 `--resume` 继续）
 /// 3. 用授权码交换 access token 和 refresh token
 /// 4. 将认证信息保存到配置文件
 ///
@@ -29,33 +32,83 @@ use crate::providers::{AuthConfig, ProviderConfig, ProviderType};
 /// 成功时返回 Ok(())，失败时返回错误信息
 pub async fn login_command(
     app_config: Config,
+    output: &Output,
     provider_type: ProviderType,
     name: Option<String>,
+    resume: bool,
 ) -> Result<()> {
     // 如果用户未提供名称，使用 Provider 类型的默认名称
-    let provider_name = name.unwrap_or_else(|| match provider_type {
+    let default_name = || match provider_type {
         ProviderType::ClaudeCode => "claude-code".to_string(),
         ProviderType::Anthropic => "anthropic".to_string(),
         ProviderType::OpenAI => "openai".to_string(),
         ProviderType::Codex => "codex".to_string(),
-    });
+    };
 
     match provider_type {
         ProviderType::ClaudeCode => {
-            println!("Starting Claude Code OAuth login...\n");
+            let providers_dir = app_config.providers_dir();
 
-            // 执行 OAuth 登录流程
-            let oauth = claude_code::perform_oauth_login()
-                .await
-                .context("OAuth login failed")?;
+            // 中断后继续时回调服务器已不在运行，只能手动输入授权码
+            let mut callback = None;
+            let pending = if resume {
+                let pending = PendingLogin::load(providers_dir)?
+                    .context("No pending login to resume, run `pluribus login` first")?;
+                if let Some(name) = name.filter(|name| *name != pending.provider) {
+                    bail!("The pending login is for {}, not {name}", pending.provider);
+                }
+                output.text(format!(
+                    "Resuming Claude Code OAuth login for {}...\n",
+                    pending.provider
+                ));
+                pending
+            } else {
+                output.text("Starting Claude Code OAuth login...\n");
+                let provider_name = name.unwrap_or_else(default_name);
+                callback = claude_code::CallbackListener::bind().await?;
+                let pending = claude_code::start_oauth_login(
+                    providers_dir,
+                    &provider_name,
+                    callback.as_ref(),
+                )
+                .context("Failed to start OAuth login")?;
+                output.text("Open the following URL in your browser to authorize:");
+                output.text(format!("{}\n", pending.authorize_url));
+                // 设置了 redirect URI 覆盖时授权码不会回到本地
+                callback = callback.filter(|c| c.redirect_uri() == pending.redirect_uri());
+                if let Some(callback) = &callback {
+                    output.text(format!(
+                        "Waiting for the browser to redirect to {}...\n",
+                        callback.redirect_uri()
+                    ));
+                }
+                pending
+            };
+            let provider_name = pending.provider.clone();
 
-            let providers_dir = app_config.providers_dir();
+            // 读取授权码并交换 token
+            let oauth =
+                claude_code::complete_oauth_login(providers_dir, &pending, callback, output)
+                    .await
+                    .context("OAuth login failed")?;
 
-            // 创建 Provider 配置
+            // 创建 Provider 配置，重新登录时保留已有的 ID、告警与时间段配置（凭证会被替换，
+            // 不按严格模式拒绝旧文件）
+            let existing =
+                crate::providers::config::load_by_name(providers_dir, &provider_name, false)
+                    .await
+                    .ok();
             let config = ProviderConfig {
+                id: existing
+                    .as_ref()
+                    .map(|c| c.id.clone())
+                    .unwrap_or_else(new_provider_id),
                 name: provider_name.clone(),
                 provider_type: ProviderType::ClaudeCode,
                 auth: AuthConfig::OAuth(oauth.clone()),
+                alerts: existing.as_ref().and_then(|c| c.alerts.clone()),
+                weight: existing.as_ref().map_or(DEFAULT_WEIGHT, |c| c.weight),
+                schedule: existing.and_then(|c| c.schedule),
             };
 
             // 保存配置到文件
@@ -64,19 +117,73 @@ pub async fn login_command(
                 .context("Failed to save provider config")?;
 
             // 显示成功信息
-            println!("\nLogin successful!");
-            println!("Provider: {}", provider_name);
-            println!(
+            output.text("\nLogin successful!");
+            output.text(format!("Provider: {}", provider_name));
+            output.text(format!(
                 "Config file: {}/{}.toml",
                 providers_dir.display(),
                 provider_name
-            );
+            ));
             if !oauth.scopes.is_empty() {
-                println!("Scopes: {}", oauth.scopes.join(", "));
+                output.text(format!("Scopes: {}", oauth.scopes.join(", ")));
+            }
+            let missing_scopes = oauth.missing_scopes(claude_code::CLAUDE_CODE_INFERENCE_SCOPES);
+            if !missing_scopes.is_empty() {
+                output.text(format!(
+                    "\nWarning: the granted scopes are missing {}, required for inference.\n\
+                     The gateway will not select this provider; run login again and approve all requested scopes.",
+                    missing_scopes.join(", ")
+                ));
             }
-            Ok(())
+            output.emit(&serde_json::json!({
+                "provider": provider_name,
+                "config_file": providers_dir.join(format!("{provider_name}.toml")),
+                "scopes": oauth.scopes,
+                "missing_scopes": missing_scopes,
+            }))
         }
         // 其他 Provider 类型暂不支持
         _ => anyhow::bail!("Provider {:?} not yet supported", provider_type),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[tokio::test]
+    async fn resume_needs_a_matching_pending_login() {
+        let (_dir, config) = test_support::config("");
+        let err = login_command(
+            config.clone(),
+            &test_support::quiet(),
+            ProviderType::ClaudeCode,
+            None,
+            true,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No pending login to resume, run `pluribus login` first"
+        );
+
+        claude_code::start_oauth_login(config.providers_dir(), "work", None).unwrap();
+        let err = login_command(
+            config.clone(),
+            &test_support::quiet(),
+            ProviderType::ClaudeCode,
+            Some("personal".to_string()),
+            true,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The pending login is for work, not personal"
+        );
+        // 名称不符时保留待完成的登录
+        assert!(PendingLogin::path(config.providers_dir()).exists());
+    }
+}
@@ -0,0 +1,297 @@
+//! Logs 命令 - 查看运行中服务器的请求记录
+//!
+//! 连接 `/admin/requests/stream`，打印最近的请求记录；`--follow` 时持续打印新完成的请求。
+//! 输出到终端时按状态码着色。
+
+use anyhow::{Context, Result};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::IsTerminal;
+
+use super::output::Output;
+use crate::config::Config;
+use crate::providers::sse;
+use crate::utils::format_timestamp;
+
+const RESET: &str = "\x1b[0m";
+const DIM: &str = "\x1b[2m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const RED: &str = "\x1b[31m";
+
+/// logs 命令的参数
+pub struct LogsOptions {
+    /// 运行中服务器的地址，默认使用当前配置的监听地址
+    pub url: Option<String>,
+    /// 先打印的最近记录数
+    pub lines: usize,
+    pub follow: bool,
+    pub errors_only: bool,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+}
+
+/// 打印所需的请求记录字段
+#[derive(Deserialize)]
+struct LogRecord {
+    request_id: u64,
+    timestamp: u64,
+    provider: Option<String>,
+    model: String,
+    is_streaming: bool,
+    response_status: u16,
+    usage: Option<Value>,
+    attempts: u32,
+    duration_ms: u64,
+}
+
+/// JSON 输出，记录为服务器返回的原始字段
+#[derive(Serialize)]
+struct LogsReport {
+    requests: Vec<Value>,
+}
+
+/// 执行 logs 命令
+///
+/// JSON 输出时只打印最近的记录，不支持 `--follow`
+pub async fn logs_command(config: Config, output: &Output, options: LogsOptions) -> Result<()> {
+    if options.follow && output.is_json() {
+        anyhow::bail!("--follow cannot be combined with --output json");
+    }
+    let base = options
+        .url
+        .unwrap_or_else(|| format!("http://{}:{}", config.host, config.port));
+    let mut query = vec![("recent", options.lines.to_string())];
+    if options.errors_only {
+        query.push(("status", "error".to_string()));
+    }
+    if let Some(provider) = options.provider {
+        query.push(("provider", provider));
+    }
+    if let Some(model) = options.model {
+        query.push(("model", model));
+    }
+
+    let url = format!("{}/admin/requests/stream", base.trim_end_matches('/'));
+    let response = reqwest::Client::new()
+        .get(&url)
+        .header("Authorization", format!("Bearer {}", config.secret))
+        .query(&query)
+        .send()
+        .await
+        .with_context(|| format!("Failed to reach {url}. Make sure the server is running."))?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        anyhow::bail!("{url} returned {status}: {body}");
+    }
+
+    let color = std::io::stdout().is_terminal();
+    let mut records = Vec::new();
+    let mut body = response.bytes_stream();
+    let mut buffer = String::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.context("Connection to server lost")?;
+        buffer.push_str(&String::from_utf8_lossy(&chunk));
+
+        while let Some(pos) = buffer.find("\n\n") {
+            let event: String = buffer.drain(..pos + 2).collect();
+            let mut name = "";
+            let mut data = None;
+            for line in event.lines() {
+                if let Some(value) = line.strip_prefix("event: ") {
+                    name = value;
+                } else if let Some(value) = sse::parse_data(line) {
+                    data = Some(value);
+                }
+            }
+
+            match (name, data) {
+                ("request", Some(data)) if output.is_json() => records.push(data),
+                ("request", Some(data)) => match serde_json::from_value::<LogRecord>(data) {
+                    Ok(record) => print_record(&record, color),
+                    Err(e) => tracing::warn!("Skipping malformed request record: {}", e),
+                },
+                ("ready", _) if !options.follow => {
+                    return output.emit(&LogsReport { requests: records })
+                }
+                ("lagged", Some(data)) => {
+                    let dropped = data.get("dropped").and_then(Value::as_u64).unwrap_or(0);
+                    eprintln!("... {dropped} records dropped, output could not keep up");
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if options.follow {
+        println!("Server closed the stream");
+    }
+    output.emit(&LogsReport { requests: records })
+}
+
+fn print_record(record: &LogRecord, color: bool) {
+    let status_color = match record.response_status {
+        200..=299 => GREEN,
+        400..=499 => YELLOW,
+        _ => RED,
+    };
+    let usage = record
+        .usage
+        .as_ref()
+        .map(|usage| {
+            let tokens = |field: &str| usage.get(field).and_then(Value::as_u64).unwrap_or(0);
+            format!(
+                "in={} out={}",
+                tokens("input_tokens"),
+                tokens("output_tokens")
+            )
+        })
+        .unwrap_or_else(|| "-".to_string());
+    let (dim, status_color, reset) = if color {
+        (DIM, status_color, RESET)
+    } else {
+        ("", "", "")
+    };
+
+    println!(
+        "{dim}{} #{:<6}{reset} {status_color}{}{reset} {:<16} {:<28} {:<6} {:>7}ms {:<18} attempts={}",
+        format_timestamp(record.timestamp),
+        record.request_id,
+        record.response_status,
+        record.provider.as_deref().unwrap_or("-"),
+        record.model,
+        if record.is_streaming { "stream" } else { "sync" },
+        record.duration_ms,
+        usage,
+        record.attempts,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::OutputFormat;
+    use crate::gateway::{test_router, AppState};
+    use crate::test_support::{self, MockProvider, USER_KEY};
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use std::sync::Arc;
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn options(url: String, errors_only: bool, follow: bool) -> LogsOptions {
+        LogsOptions {
+            url: Some(url),
+            lines: 10,
+            follow,
+            errors_only,
+            provider: None,
+            model: None,
+        }
+    }
+
+    /// 在本地端口上运行网关，返回地址
+    async fn serve(state: AppState) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, test_router(state)).await });
+        format!("http://{addr}")
+    }
+
+    async fn logs_json(config: Config, options: LogsOptions) -> Value {
+        let (output, stdout, _) = Output::captured(OutputFormat::Json);
+        let result = logs_command(config, &output, options).await;
+        output.finish(result).unwrap();
+        stdout.json()
+    }
+
+    #[tokio::test]
+    async fn json_output_lists_completed_requests_from_the_server() {
+        let (_dir, config) = test_support::config("");
+        let provider = Arc::new(MockProvider::new("first"));
+        let state = test_support::state(config.clone(), std::slice::from_ref(&provider));
+        let router = test_router(state.clone());
+        let body = json!({
+            "model": "claude-test",
+            "max_tokens": 16,
+            "messages": [{"role": "user", "content": "hi"}]
+        });
+        test_support::send(&router, test_support::messages_request(USER_KEY, &body)).await;
+        provider.fail_with(StatusCode::BAD_REQUEST, "bad request");
+        let (failed, _, _) =
+            test_support::send(&router, test_support::messages_request(USER_KEY, &body)).await;
+        assert!(failed.is_client_error() || failed.is_server_error());
+        let url = serve(state).await;
+
+        let document = logs_json(config.clone(), options(url.clone(), false, false)).await;
+        assert_eq!(document["ok"], true);
+        let requests = document["requests"].as_array().unwrap();
+        let statuses: Vec<&Value> = requests.iter().map(|r| &r["response_status"]).collect();
+        assert_eq!(statuses, [&json!(200), &json!(failed.as_u16())]);
+        assert_eq!(requests[0]["provider"], "first");
+        assert_eq!(requests[0]["model"], "claude-test");
+
+        let document = logs_json(config, options(url, true, false)).await;
+        let requests = document["requests"].as_array().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["response_status"], failed.as_u16());
+    }
+
+    #[tokio::test]
+    async fn json_output_stops_at_the_ready_event() {
+        let server = MockServer::start().await;
+        let body = "event: request\ndata: {\"request_id\":1}\n\n\
+                    event: lagged\ndata: {\"dropped\":3}\n\n\
+                    : keep-alive\n\n\
+                    event: request\ndata: {\"request_id\":2}\n\n\
+                    event: ready\ndata: {}\n\n\
+                    event: request\ndata: {\"request_id\":3}\n\n";
+        Mock::given(method("GET"))
+            .and(path("/admin/requests/stream"))
+            .and(query_param("recent", "10"))
+            .and(query_param("status", "error"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "text/event-stream"))
+            .mount(&server)
+            .await;
+        let (_dir, config) = test_support::config("");
+
+        let document = logs_json(config, options(server.uri(), true, false)).await;
+        assert_eq!(
+            document,
+            json!({
+                "ok": true,
+                "errors": [],
+                "requests": [{"request_id": 1}, {"request_id": 2}],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_server_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(401).set_body_string("invalid secret"))
+            .mount(&server)
+            .await;
+        let (_dir, config) = test_support::config("");
+
+        let document = logs_json(config.clone(), options(server.uri(), false, false)).await;
+        assert_eq!(document["ok"], false);
+        let error = document["errors"][0].as_str().unwrap();
+        assert!(
+            error.ends_with("returned 401 Unauthorized: invalid secret"),
+            "{error}"
+        );
+
+        let (output, _, _) = Output::captured(OutputFormat::Json);
+        let err = logs_command(config, &output, options(server.uri(), false, true))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--follow cannot be combined with --output json"
+        );
+    }
+}
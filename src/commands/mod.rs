@@ -1,9 +1,31 @@
 //! CLI 命令实现
 
+pub mod bundle;
+pub mod deadletter;
+pub mod diff;
+pub mod keys;
+pub mod list;
 pub mod login;
+pub mod logs;
+pub mod output;
+pub mod repro;
 pub mod serve;
 pub mod test;
+#[cfg(feature = "usage-sqlite")]
+pub mod usage;
+pub mod version;
 
+pub use bundle::{export_bundle_command, import_bundle_command, ExportOptions};
+pub use deadletter::{deadletter_list_command, deadletter_retry_command};
+pub use diff::diff_command;
+pub use keys::{keys_list_command, keys_prune_command};
+pub use list::list_command;
 pub use login::login_command;
+pub use logs::{logs_command, LogsOptions};
+pub use output::OutputFormat;
+pub use repro::{repro_command, ReproOptions};
 pub use serve::serve_command;
 pub use test::test_command;
+#[cfg(feature = "usage-sqlite")]
+pub use usage::{usage_command, usage_migrate_command, UsageOptions};
+pub use version::{version_command, VersionFormat};
@@ -0,0 +1,230 @@
+//! 命令输出格式
+//!
+//! `--output json`（或 `PLURIBUS_OUTPUT=json`）时，每个命令在 stdout 输出且只输出一个 JSON
+//! 文档：`{"ok": true, "errors": [], ...命令数据}`，失败时为 `{"ok": false, "errors": [...]}`，
+//! 面向人的提示改为输出到 stderr。`main` 按输出格式创建一个 [`Output`] 传给命令，命令只需用
+//! [`Output::text`] 输出提示、用 [`Output::emit`] 输出数据，未调用 [`Output::emit`] 的命令由
+//! [`Output::finish`] 补上只含 `ok` 与 `errors` 的文档。
+
+use anyhow::Result;
+use serde::Serialize;
+use std::fmt::Display;
+use std::io::Write;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// 命令输出格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+type Writer = Mutex<Box<dyn Write + Send>>;
+
+/// 一次命令执行的输出上下文
+pub struct Output {
+    format: OutputFormat,
+    /// 是否已输出 JSON 文档
+    emitted: AtomicBool,
+    stdout: Writer,
+    stderr: Writer,
+}
+
+impl Output {
+    /// 输出到进程的 stdout / stderr
+    pub fn new(format: OutputFormat) -> Self {
+        Self::with_writers(
+            format,
+            Box::new(std::io::stdout()),
+            Box::new(std::io::stderr()),
+        )
+    }
+
+    fn with_writers(
+        format: OutputFormat,
+        stdout: Box<dyn Write + Send>,
+        stderr: Box<dyn Write + Send>,
+    ) -> Self {
+        Self {
+            format,
+            emitted: AtomicBool::new(false),
+            stdout: Mutex::new(stdout),
+            stderr: Mutex::new(stderr),
+        }
+    }
+
+    /// 输出到内存，返回的两个缓冲区分别对应 stdout 与 stderr
+    #[cfg(test)]
+    pub(crate) fn captured(format: OutputFormat) -> (Self, Captured, Captured) {
+        let stdout = Captured::default();
+        let stderr = Captured::default();
+        let output = Self::with_writers(format, Box::new(stdout.clone()), Box::new(stderr.clone()));
+        (output, stdout, stderr)
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.format == OutputFormat::Json
+    }
+
+    /// 面向人的输出：text 模式到 stdout，json 模式到 stderr
+    fn human(&self) -> &Writer {
+        if self.is_json() {
+            &self.stderr
+        } else {
+            &self.stdout
+        }
+    }
+
+    fn write(writer: &Writer, content: std::fmt::Arguments<'_>) -> std::io::Result<()> {
+        let mut writer = writer.lock().unwrap_or_else(|e| e.into_inner());
+        writer.write_fmt(content)?;
+        writer.flush()
+    }
+
+    /// 输出一行面向人的提示
+    pub fn text(&self, line: impl Display) {
+        let _ = Self::write(self.human(), format_args!("{line}\n"));
+    }
+
+    /// 输出交互输入的提示（不换行）
+    pub fn prompt(&self, label: &str) -> Result<()> {
+        Self::write(self.human(), format_args!("{label}: "))?;
+        Ok(())
+    }
+
+    /// json 模式下输出命令数据（字段并入顶层），text 模式下不做任何事
+    pub fn emit<T: Serialize>(&self, data: &T) -> Result<()> {
+        if !self.is_json() {
+            return Ok(());
+        }
+        self.document(true, &[], data)?;
+        self.emitted.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn document<T: Serialize>(&self, ok: bool, errors: &[String], data: &T) -> Result<()> {
+        let document = Document { ok, errors, data };
+        let json = serde_json::to_string_pretty(&document)?;
+        Self::write(&self.stdout, format_args!("{json}\n"))?;
+        Ok(())
+    }
+
+    /// 命令结束时调用：json 模式下失败时输出错误文档并返回失败的退出码，成功但未输出数据时
+    /// 补上空文档；text 模式下原样返回错误
+    pub fn finish(&self, result: Result<()>) -> Result<ExitCode> {
+        if !self.is_json() {
+            return result.map(|()| ExitCode::SUCCESS);
+        }
+        match result {
+            Ok(()) => {
+                if !self.emitted.load(Ordering::Relaxed) {
+                    self.emit(&serde_json::Map::new())?;
+                }
+                Ok(ExitCode::SUCCESS)
+            }
+            Err(e) => {
+                self.document(false, &[format!("{e:#}")], &serde_json::Map::new())?;
+                Ok(ExitCode::FAILURE)
+            }
+        }
+    }
+}
+
+/// JSON 模式下输出的文档
+#[derive(Serialize)]
+struct Document<'a, T: Serialize> {
+    ok: bool,
+    errors: &'a [String],
+    #[serde(flatten)]
+    data: &'a T,
+}
+
+/// 测试中捕获的输出
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct Captured(std::sync::Arc<Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl Captured {
+    pub(crate) fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+
+    /// 解析捕获的唯一一个 JSON 文档
+    pub(crate) fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.contents()).expect("exactly one JSON document")
+    }
+}
+
+#[cfg(test)]
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn text_mode_prints_only_human_output() {
+        let (output, stdout, stderr) = Output::captured(OutputFormat::Text);
+        output.text("Loaded 2 provider(s)");
+        output.prompt("Bundle passphrase").unwrap();
+        output.emit(&json!({"providers": 2})).unwrap();
+        assert_eq!(output.finish(Ok(())).unwrap(), ExitCode::SUCCESS);
+
+        assert_eq!(
+            stdout.contents(),
+            "Loaded 2 provider(s)\nBundle passphrase: "
+        );
+        assert_eq!(stderr.contents(), "");
+        let err = output.finish(Err(anyhow::anyhow!("boom"))).unwrap_err();
+        assert_eq!(err.to_string(), "boom");
+    }
+
+    #[test]
+    fn json_mode_prints_a_single_document_with_the_data() {
+        let (output, stdout, stderr) = Output::captured(OutputFormat::Json);
+        output.text("Loaded 2 provider(s)");
+        output.emit(&json!({"providers": ["a", "b"]})).unwrap();
+        assert_eq!(output.finish(Ok(())).unwrap(), ExitCode::SUCCESS);
+
+        assert_eq!(
+            stdout.json(),
+            json!({"ok": true, "errors": [], "providers": ["a", "b"]})
+        );
+        assert_eq!(stderr.contents(), "Loaded 2 provider(s)\n");
+    }
+
+    #[test]
+    fn json_mode_fills_in_an_empty_document() {
+        let (output, stdout, _) = Output::captured(OutputFormat::Json);
+        assert_eq!(output.finish(Ok(())).unwrap(), ExitCode::SUCCESS);
+        assert_eq!(stdout.json(), json!({"ok": true, "errors": []}));
+    }
+
+    #[test]
+    fn json_mode_reports_errors_in_the_document() {
+        let (output, stdout, _) = Output::captured(OutputFormat::Json);
+        let err = anyhow::anyhow!("connection refused").context("Failed to reach the gateway");
+        assert_eq!(output.finish(Err(err)).unwrap(), ExitCode::FAILURE);
+        assert_eq!(
+            stdout.json(),
+            json!({
+                "ok": false,
+                "errors": ["Failed to reach the gateway: connection refused"],
+            })
+        );
+    }
+}
@@ -0,0 +1,342 @@
+//! Repro 命令 - 把上游拒绝的请求缩减为最小复现
+//!
+//! 读取 `PLURIBUS_REPRO_DIR` 中保存的请求，发往 `--target`（Anthropic 兼容的 mock 地址），
+//! 或以 `--live` 经本地服务器发往真实上游，二分去掉 beta flags、tools、可选字段与消息，
+//! 把仍以同样错误失败的最小请求写入文件。
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::Value;
+use std::path::PathBuf;
+
+use super::output::Output;
+use crate::config::Config;
+use crate::providers::claude_code::ANTHROPIC_API_VERSION;
+use crate::repro::{self, Envelope, Oracle};
+use crate::utils::should_disable_tls_verify;
+
+/// repro 命令参数
+pub struct ReproOptions {
+    pub request_id: u64,
+    /// Anthropic 兼容的 messages 地址
+    pub target: Option<String>,
+    /// 经本地服务器发往真实上游
+    pub live: bool,
+    /// `--live` 时跳过确认
+    pub yes: bool,
+    /// `--live` 时使用的 provider，默认使用失败时的 provider
+    pub provider: Option<String>,
+    /// 最多发送的请求数
+    pub max_attempts: usize,
+    /// 最小请求的输出文件，默认 `repro-{request_id}.min.json`
+    pub out: Option<PathBuf>,
+}
+
+/// JSON 输出
+#[derive(Serialize)]
+struct ReproReport<'a> {
+    request_id: u64,
+    error: &'a str,
+    target: &'a str,
+    attempts: usize,
+    exhausted: bool,
+    steps: &'a [String],
+    file: &'a PathBuf,
+}
+
+/// 直接发往 Anthropic 兼容地址
+struct TargetOracle {
+    client: reqwest::Client,
+    url: String,
+    signature: String,
+}
+
+#[async_trait]
+impl Oracle for TargetOracle {
+    async fn still_fails(&mut self, candidate: &Envelope) -> Result<bool> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header("anthropic-version", ANTHROPIC_API_VERSION)
+            .json(&candidate.body);
+        if !candidate.betas.is_empty() {
+            request = request.header("anthropic-beta", candidate.betas.join(","));
+        }
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", self.url))?;
+        if response.status() != reqwest::StatusCode::BAD_REQUEST {
+            return Ok(false);
+        }
+        let text = response.text().await.unwrap_or_default();
+        Ok(repro::error_signature(&text).as_deref() == Some(self.signature.as_str()))
+    }
+}
+
+/// 经本地服务器发往真实上游，标记为合成流量
+struct GatewayOracle {
+    client: reqwest::Client,
+    url: String,
+    secret: String,
+    provider: Option<String>,
+    signature: String,
+}
+
+#[async_trait]
+impl Oracle for GatewayOracle {
+    async fn still_fails(&mut self, candidate: &Envelope) -> Result<bool> {
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header("Authorization", format!("Bearer {}", self.secret))
+            .header("x-pluribus-synthetic", "1")
+            .json(&candidate.body);
+        if !candidate.betas.is_empty() {
+            request = request.header("anthropic-beta", candidate.betas.join(","));
+        }
+        if let Some(provider) = &self.provider {
+            request = request.header("x-pluribus-provider", provider);
+        }
+        let response = request
+            .send()
+            .await
+            .context("Request failed. Make sure the server is running.")?;
+        if response.status().is_success() {
+            return Ok(false);
+        }
+        // gateway 不返回上游原始响应体，用 `upstream` 中解析出的类型与信息重建签名
+        let text = response.text().await.unwrap_or_default();
+        let Some(upstream) = serde_json::from_str::<Value>(&text)
+            .ok()
+            .and_then(|json| json.get("upstream").cloned())
+            .filter(|upstream| upstream["status"] == 400)
+        else {
+            return Ok(false);
+        };
+        let body = serde_json::json!({ "error": upstream }).to_string();
+        Ok(repro::error_signature(&body).as_deref() == Some(self.signature.as_str()))
+    }
+}
+
+fn confirm_live(output: &Output, url: &str, max_attempts: usize) -> Result<()> {
+    output.prompt(&format!(
+        "Send up to {max_attempts} requests to the real upstream via {url}? [y/N]"
+    ))?;
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)?;
+    if !matches!(line.trim(), "y" | "Y" | "yes") {
+        bail!("Aborted");
+    }
+    Ok(())
+}
+
+/// 执行 repro 命令
+pub async fn repro_command(config: Config, output: &Output, options: ReproOptions) -> Result<()> {
+    let Some(dir) = &config.repro_dir else {
+        bail!("PLURIBUS_REPRO_DIR is not set, no requests are captured");
+    };
+    let case = repro::load(dir, options.request_id)?;
+    let mut envelope = case.envelope.clone();
+    if let Some(obj) = envelope.body.as_object_mut() {
+        obj.insert("stream".to_string(), Value::Bool(false));
+    }
+    output.text(format!(
+        "Request {} ({}, provider {}) failed with: {}",
+        case.request_id,
+        case.model,
+        case.provider.as_deref().unwrap_or("-"),
+        case.error
+    ));
+
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(should_disable_tls_verify())
+        .build()?;
+    let signature = case.error.clone();
+    let (mut oracle, target): (Box<dyn Oracle>, String) = match (&options.target, options.live) {
+        (Some(_), true) => bail!("--target and --live cannot be used together"),
+        (Some(url), false) => (
+            Box::new(TargetOracle {
+                client,
+                url: url.clone(),
+                signature,
+            }),
+            url.clone(),
+        ),
+        (None, true) => {
+            let url = format!(
+                "http://{}:{}/anthropic/v1/messages",
+                config.host, config.port
+            );
+            if !options.yes {
+                confirm_live(output, &url, options.max_attempts)?;
+            }
+            (
+                Box::new(GatewayOracle {
+                    client,
+                    url: url.clone(),
+                    secret: config.secret.clone(),
+                    provider: options.provider.clone().or(case.provider.clone()),
+                    signature,
+                }),
+                url,
+            )
+        }
+        (None, false) => bail!(
+            "Pass --target <url> of a mock Anthropic-compatible endpoint, \
+             or --live to send requests to the real upstream"
+        ),
+    };
+
+    output.text(format!(
+        "Checking that the request still fails on {target}..."
+    ));
+    if !oracle.still_fails(&envelope).await? {
+        bail!(
+            "Captured request {} no longer fails with the same error on {target}",
+            case.request_id
+        );
+    }
+
+    let minimized = repro::minimize(envelope, oracle.as_mut(), options.max_attempts).await?;
+    let file = options
+        .out
+        .unwrap_or_else(|| PathBuf::from(format!("repro-{}.min.json", case.request_id)));
+    std::fs::write(&file, serde_json::to_vec_pretty(&minimized.envelope)?)
+        .with_context(|| format!("Failed to write {}", file.display()))?;
+
+    for step in &minimized.steps {
+        output.text(format!("  {step}"));
+    }
+    if minimized.exhausted {
+        output.text(format!(
+            "Stopped after {} requests, the result may not be minimal",
+            minimized.attempts
+        ));
+    }
+    output.text(format!(
+        "Minimal failing request ({} steps, {} requests) written to {}",
+        minimized.steps.len(),
+        minimized.attempts + 1,
+        file.display()
+    ));
+    output.emit(&ReproReport {
+        request_id: case.request_id,
+        error: &case.error,
+        target: &target,
+        attempts: minimized.attempts + 1,
+        exhausted: minimized.exhausted,
+        steps: &minimized.steps,
+        file: &file,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repro::ReproCase;
+    use crate::test_support;
+    use serde_json::json;
+    use wiremock::matchers::{body_string_contains, header_regex, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const ERROR: &str = r#"{"type":"error","error":{"type":"invalid_request_error","message":"tools.3: bad schema"}}"#;
+
+    #[tokio::test]
+    async fn repro_minimizes_a_captured_request_against_a_target() {
+        let (dir, mut config) = test_support::config("");
+        let repro_dir = dir.path().join("repro");
+        config.repro_dir = Some(repro_dir.clone());
+        let message =
+            |text: &str| json!({"role": "user", "content": [{"type": "text", "text": text}]});
+        repro::save(
+            &repro_dir,
+            &ReproCase {
+                request_id: 42,
+                timestamp: 1_700_000_000,
+                provider: Some("first".to_string()),
+                model: "claude-test".to_string(),
+                error: repro::error_signature(ERROR).unwrap(),
+                envelope: Envelope {
+                    betas: vec!["beta-a".to_string(), "beta-b".to_string()],
+                    body: json!({
+                        "model": "claude-test",
+                        "max_tokens": 16,
+                        "stream": true,
+                        "system": "xxxx",
+                        "tools": [{"name": "a"}, {"name": "broken"}, {"name": "b"}],
+                        "messages": [message("one"), message("two")]
+                    }),
+                },
+            },
+        )
+        .unwrap();
+
+        // 只要请求带着 broken tool 与 beta-b 就以同样的错误失败
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_string_contains(r#""name":"broken""#))
+            .and(header_regex("anthropic-beta", "beta-b"))
+            .respond_with(ResponseTemplate::new(400).set_body_string(ERROR))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(test_support::message("ok")))
+            .mount(&server)
+            .await;
+
+        let out = dir.path().join("min.json");
+        repro_command(
+            config.clone(),
+            &test_support::quiet(),
+            ReproOptions {
+                request_id: 42,
+                target: Some(format!("{}/v1/messages", server.uri())),
+                live: false,
+                yes: false,
+                provider: None,
+                max_attempts: 100,
+                out: Some(out.clone()),
+            },
+        )
+        .await
+        .unwrap();
+
+        let minimized: Envelope = serde_json::from_slice(&std::fs::read(&out).unwrap()).unwrap();
+        assert_eq!(minimized.betas, ["beta-b"]);
+        assert_eq!(
+            minimized.body,
+            json!({
+                "model": "claude-test",
+                "max_tokens": 16,
+                "stream": false,
+                "tools": [{"name": "broken"}],
+                "messages": [message("two")]
+            })
+        );
+
+        // 目标不再返回同样的错误时拒绝缩减
+        server.reset().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(test_support::message("ok")))
+            .mount(&server)
+            .await;
+        let err = repro_command(
+            config,
+            &test_support::quiet(),
+            ReproOptions {
+                request_id: 42,
+                target: Some(format!("{}/v1/messages", server.uri())),
+                live: false,
+                yes: false,
+                provider: None,
+                max_attempts: 100,
+                out: Some(out),
+            },
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("no longer fails"), "{err}");
+    }
+}
@@ -12,17 +12,19 @@ use crate::gateway;
 /// # 参数
 ///
 /// * `config` - 应用配置，包含监听地址、端口等信息
+/// * `allow_shared` - 其他实例持有实例锁时以共享模式启动，而不是报错退出
 ///
 /// # 功能
 ///
 /// - 加载所有已配置的 Provider
 /// - 初始化 HTTP 路由和中间件
 /// - 启动服务器并等待关闭信号
+/// - 获取 providers 目录的实例锁，避免多个实例同时刷新 token
 /// - 支持优雅关闭（Ctrl+C 或 SIGTERM）
 ///
 /// # 返回
 ///
 /// 成功时返回 Ok(())，失败时返回错误信息
-pub async fn serve_command(config: Config) -> Result<()> {
-    gateway::serve(config).await
+pub async fn serve_command(config: Config, allow_shared: bool) -> Result<()> {
+    gateway::serve(config, allow_shared).await
 }
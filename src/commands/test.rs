@@ -4,9 +4,20 @@
 //! 验证服务是否正常工作。
 
 use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
 
+use super::output::Output;
 use crate::config::Config;
 
+/// JSON 输出
+#[derive(Serialize)]
+struct TestReport {
+    url: String,
+    status: u16,
+    response: Value,
+}
+
 /// 执行测试命令
 ///
 /// # 参数
@@ -17,6 +28,7 @@ use crate::config::Config;
 ///
 /// - 向本地服务器的 `/anthropic/v1/messages` 端点发送一个简单的测试请求
 /// - 使用配置的 secret 进行认证
+/// - 以 `x-pluribus-synthetic` 标记为合成流量，不计入预算、固定与密钥使用记录
 /// - 显示响应状态和内容
 ///
 /// # 测试请求内容
@@ -26,8 +38,8 @@ use crate::config::Config;
 /// # 返回
 ///
 /// 成功时返回 Ok(())，失败时返回错误信息
-pub async fn test_command(config: Config) -> Result<()> {
-    println!("Sending test request to local server...");
+pub async fn test_command(config: Config, output: &Output) -> Result<()> {
+    output.text("Sending test request to local server...");
 
     // 构造测试请求体
     let test_body = serde_json::json!({
@@ -46,19 +58,20 @@ pub async fn test_command(config: Config) -> Result<()> {
         config.host, config.port
     );
 
-    println!("Request URL: {}", url);
+    output.text(format!("Request URL: {}", url));
 
     // 发送请求
     let response = reqwest::Client::new()
         .post(&url)
         .header("Authorization", format!("Bearer {}", config.secret))
+        .header("x-pluribus-synthetic", "1")
         .json(&test_body)
         .send()
         .await
         .context("Request failed. Make sure the server is running.")?;
 
     let status = response.status();
-    println!("Response status: {}", status);
+    output.text(format!("Response status: {}", status));
 
     // 检查响应状态
     if !status.is_success() {
@@ -72,6 +85,13 @@ pub async fn test_command(config: Config) -> Result<()> {
         .await
         .context("Failed to read response body")?;
 
+    if output.is_json() {
+        return output.emit(&TestReport {
+            url,
+            status: status.as_u16(),
+            response: serde_json::from_str(&body).unwrap_or(Value::String(body)),
+        });
+    }
     println!("Response:");
     println!("{}", body);
 
@@ -0,0 +1,187 @@
+//! Usage 命令 - 查询用量历史
+//!
+//! 直接读取 `PLURIBUS_USAGE_DB`，不需要服务器在运行
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use super::output::Output;
+use crate::config::Config;
+use crate::usage::{ModelStats, UsageFilter, UsageGroup, UsageStore, UsageTotals, SCHEMA_VERSION};
+use crate::utils::{format_timestamp, parse_date_or_timestamp};
+
+/// usage 命令的参数
+pub struct UsageOptions {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+}
+
+fn parse_bound(name: &str, value: Option<String>) -> Result<Option<u64>> {
+    value
+        .map(|value| {
+            parse_date_or_timestamp(&value).with_context(|| {
+                format!("--{name} must be YYYY-MM-DD or a Unix timestamp: {value}")
+            })
+        })
+        .transpose()
+}
+
+/// 已存在的用量数据库路径
+fn existing_db(config: &Config) -> Result<&Path> {
+    let Some(path) = &config.usage_db else {
+        anyhow::bail!("Usage history is disabled (PLURIBUS_USAGE_DB is empty)");
+    };
+    if !path.exists() {
+        anyhow::bail!(
+            "No usage database at {}; it is created when the server starts",
+            path.display()
+        );
+    }
+    Ok(path)
+}
+
+/// 按 provider、密钥与模型汇总用量
+pub async fn usage_command(config: Config, output: &Output, options: UsageOptions) -> Result<()> {
+    let path = existing_db(&config)?;
+    let filter = UsageFilter {
+        from: parse_bound("from", options.from)?,
+        to: parse_bound("to", options.to)?,
+        provider: options.provider,
+        model: options.model,
+    };
+    let store = UsageStore::open(path, false).await?;
+    let report = store.report(filter).await?;
+    if output.is_json() {
+        return output.emit(&report);
+    }
+
+    let range = match (report.from, report.to) {
+        (None, None) => "all time".to_string(),
+        (from, to) => format!(
+            "{} to {}",
+            from.map_or_else(|| "start".to_string(), format_timestamp),
+            to.map_or_else(|| "now".to_string(), format_timestamp)
+        ),
+    };
+    println!("Usage from {range}");
+    println!();
+    print_header("TOTAL");
+    print_row("all", &report.total);
+    for (title, groups) in [
+        ("PROVIDER", &report.by_provider),
+        ("KEY", &report.by_key),
+        ("MODEL", &report.by_model),
+    ] {
+        println!();
+        print_header(title);
+        print_groups(groups);
+    }
+    println!();
+    print_models(&report.models);
+    Ok(())
+}
+
+/// `pluribus usage migrate` 的结果
+#[derive(Serialize)]
+struct MigrateReport {
+    path: PathBuf,
+    from_version: u32,
+    to_version: u32,
+}
+
+/// 把用量数据库升级到本版本的 schema
+///
+/// 服务器启动时同样会执行迁移；此命令用于在升级前离线迁移并确认结果
+pub async fn usage_migrate_command(config: Config, output: &Output) -> Result<()> {
+    let path = existing_db(&config)?;
+    let (_, from_version) = UsageStore::migrate(path, false).await?;
+    if from_version == SCHEMA_VERSION {
+        output.text(format!(
+            "{} is already at schema version {SCHEMA_VERSION}",
+            path.display()
+        ));
+    } else {
+        output.text(format!(
+            "Migrated {} from schema version {from_version} to {SCHEMA_VERSION}",
+            path.display()
+        ));
+    }
+    output.emit(&MigrateReport {
+        path: path.to_path_buf(),
+        from_version,
+        to_version: SCHEMA_VERSION,
+    })
+}
+
+fn print_header(title: &str) {
+    println!(
+        "{:<28} {:>9} {:>13} {:>13} {:>13} {:>13} {:>11}",
+        title, "REQUESTS", "INPUT", "OUTPUT", "CACHE_READ", "CACHE_WRITE", "AVG_MS"
+    );
+}
+
+fn print_groups(groups: &[UsageGroup]) {
+    if groups.is_empty() {
+        println!("(none)");
+    }
+    for group in groups {
+        print_row(group.name.as_deref().unwrap_or("-"), &group.totals);
+    }
+}
+
+/// 按请求模型输出成功 / 失败分布、输出 token 数、TTFT 与估算费用
+fn print_models(models: &[ModelStats]) {
+    println!(
+        "{:<28} {:>9} {:>9} {:<20} {:>9} {:>9} {:>9} {:>10}",
+        "MODEL", "REQUESTS", "SUCCESS", "ERRORS", "AVG_OUT", "P95_OUT", "AVG_TTFT", "COST_USD"
+    );
+    if models.is_empty() {
+        println!("(none)");
+    }
+    for stats in models {
+        let errors = if stats.errors.is_empty() {
+            "-".to_string()
+        } else {
+            stats
+                .errors
+                .iter()
+                .map(|(status, count)| format!("{status}x{count}"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        println!(
+            "{:<28} {:>9} {:>9} {:<20} {:>9} {:>9} {:>9} {:>10}",
+            stats.model,
+            stats.requests,
+            stats.successes,
+            errors,
+            stats.avg_output_tokens,
+            stats.p95_output_tokens,
+            stats
+                .avg_ttft_ms
+                .map_or_else(|| "-".to_string(), |ms| ms.to_string()),
+            stats
+                .estimated_cost_usd
+                .map_or_else(|| "-".to_string(), |cost| format!("{cost:.4}"))
+        );
+        for effective in stats.effective_models.iter().filter(|m| **m != stats.model) {
+            println!("  served as {effective}");
+        }
+    }
+}
+
+fn print_row(name: &str, totals: &UsageTotals) {
+    println!(
+        "{:<28} {:>9} {:>13} {:>13} {:>13} {:>13} {:>11}",
+        name,
+        totals.requests,
+        totals.input_tokens,
+        totals.output_tokens,
+        totals.cache_read_tokens,
+        totals.cache_creation_tokens,
+        totals.avg_latency_ms
+    );
+}
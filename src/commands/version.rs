@@ -0,0 +1,99 @@
+//! Version 命令 - 输出版本与构建信息
+//!
+//! 此模块实现 `version` 命令，输出 Pluribus 版本、目标 Claude Code 版本、
+//! Anthropic API 版本及构建信息，便于提交问题和检查兼容性。
+
+use anyhow::Result;
+use serde::Serialize;
+
+use super::output::Output;
+use crate::providers::claude_code::{self, ANTHROPIC_API_VERSION};
+use crate::utils::format_timestamp;
+
+/// 输出格式
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum VersionFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// 版本与构建信息
+#[derive(Serialize)]
+struct VersionReport {
+    pluribus_version: &'static str,
+    claude_code_version: String,
+    anthropic_api_version: &'static str,
+    git_commit: &'static str,
+    /// 构建时间 (Unix timestamp)
+    build_timestamp: u64,
+    rustc_version: &'static str,
+}
+
+/// 执行版本命令
+///
+/// Claude Code 版本为内置默认值（运行中的服务会从 npm registry 刷新，见 `/admin/info`）
+pub fn version_command(output: &Output, format: VersionFormat) -> Result<()> {
+    let report = VersionReport {
+        pluribus_version: env!("CARGO_PKG_VERSION"),
+        claude_code_version: claude_code::version_info().claude_code_version,
+        anthropic_api_version: ANTHROPIC_API_VERSION,
+        git_commit: env!("PLURIBUS_GIT_HASH"),
+        build_timestamp: env!("PLURIBUS_BUILD_TIMESTAMP").parse().unwrap_or(0),
+        rustc_version: env!("PLURIBUS_RUSTC_VERSION"),
+    };
+
+    if output.is_json() {
+        return output.emit(&report);
+    }
+    match format {
+        VersionFormat::Json => output.text(serde_json::to_string_pretty(&report)?),
+        VersionFormat::Text => {
+            output.text(format!("pluribus {}", report.pluribus_version));
+            output.text(format!(
+                "Claude Code version:   {}",
+                report.claude_code_version
+            ));
+            output.text(format!(
+                "Anthropic API version: {}",
+                report.anthropic_api_version
+            ));
+            output.text(format!("Git commit:            {}", report.git_commit));
+            output.text(format!(
+                "Build time:            {} UTC",
+                format_timestamp(report.build_timestamp)
+            ));
+            output.text(format!("Rust toolchain:        {}", report.rustc_version));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::OutputFormat;
+
+    #[test]
+    fn output_json_wraps_the_report_in_a_document() {
+        let (output, stdout, _) = Output::captured(OutputFormat::Json);
+        let result = version_command(&output, VersionFormat::Text);
+        output.finish(result).unwrap();
+
+        let document = stdout.json();
+        assert_eq!(document["ok"], true);
+        assert_eq!(document["errors"], serde_json::json!([]));
+        assert_eq!(document["pluribus_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(document["anthropic_api_version"], ANTHROPIC_API_VERSION);
+    }
+
+    #[test]
+    fn format_json_prints_the_bare_report() {
+        let (output, stdout, _) = Output::captured(OutputFormat::Text);
+        version_command(&output, VersionFormat::Json).unwrap();
+
+        let report: serde_json::Value = serde_json::from_str(&stdout.contents()).unwrap();
+        assert_eq!(report["pluribus_version"], env!("CARGO_PKG_VERSION"));
+        assert!(report.get("ok").is_none());
+    }
+}
@@ -1,12 +1,44 @@
 //! 应用配置模块
 //!
-//! 负责从环境变量加载应用配置，包括：
+//! 负责从环境变量及可选的 `pluribus.toml` 加载应用配置（两者都设置时环境变量优先），包括：
 //! - 服务器监听地址和端口
 //! - 认证密钥
 //! - Provider 配置文件存储路径
+//! - 按模型指定的上游 API 地址（`[model_endpoints]`）
+//! - 按 provider 类型的请求字段策略（`[field_policy.<type>]`）
+//! - 请求改写的灰度规则（`[canary.<transform>]`）
+//! - 暂停后台上游请求的静默时段（`[quiet_hours]`）
 
 use anyhow::{Context, Result};
-use std::path::PathBuf;
+use regex::Regex;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::egress;
+use crate::gateway::{Canaries, CanaryRule, SelectionMode};
+use crate::providers::anomaly::ValidationMode;
+use crate::providers::field_policy::{FieldAction, FieldPolicies};
+use crate::providers::{
+    OversizedResponse, ProviderType, Transform, DEFAULT_MAX_RESPONSE_BODY_BYTES,
+};
+use crate::quiet_hours::QuietHours;
+
+/// 默认请求体大小上限：32 MiB
+const DEFAULT_MAX_REQUEST_BODY_BYTES: usize = 32 * 1024 * 1024;
+
+/// 默认保存值的响应头：rate limit 相关
+const DEFAULT_CAPTURE_HEADER_VALUES: &str = "anthropic-ratelimit-*,retry-after";
+
+/// 默认检查的弃用响应头：RFC 9745 的 `Deprecation` 与 RFC 8594 的 `Sunset`
+const DEFAULT_DEPRECATION_HEADERS: &str = "deprecation,sunset";
+
+/// 默认检查的弃用响应体字段
+const DEFAULT_DEPRECATION_FIELDS: &str = "deprecation";
+
+/// 默认的延迟探测模型：最便宜的模型
+const DEFAULT_LATENCY_PROBE_MODEL: &str = "claude-haiku-4-5";
 
 /// 应用配置
 ///
@@ -19,49 +51,811 @@ pub struct Config {
     pub port: u16,
     /// API 访问密钥（用于 Bearer token 认证）
     pub secret: String,
-    /// Provider 配置文件存储目录
+    /// Provider 配置文件存储目录（`PLURIBUS_PROVIDERS_DIR`，命令行 `--providers-dir` 优先）
     pub providers_dir: PathBuf,
+    /// 客户端密钥文件路径
+    pub keys_file: PathBuf,
+    /// 认证调试模式：401 响应中附带凭证 header 的诊断信息
+    pub auth_debug: bool,
+    /// 请求体大小上限（字节）
+    pub max_request_body_bytes: usize,
+    /// 管理接口保留的最近请求数，0 表示关闭
+    pub request_history_size: usize,
+    /// 请求历史中单个请求体保存的最大字节数
+    pub request_history_body_bytes: usize,
+    /// GDPR 模式：不在内存中保留请求内容
+    pub gdpr_mode: bool,
+    /// 上游返回可重试错误（429 / 5xx）时的最大重试次数
+    pub max_retries: u32,
+    /// 非流式请求因 429 / 5xx 换到其他 provider 的最大次数，`None` 表示每个 provider 最多尝试一次
+    pub max_failover: Option<u32>,
+    /// provider 连续失败该次数后熔断，0 表示关闭
+    pub circuit_failure_threshold: u32,
+    /// 熔断后经过该时长（秒）放行一个探测请求
+    pub circuit_recovery_secs: u64,
+    /// 429 时按 rate limit 重置时间等待的上限（秒）
+    pub rate_limit_max_wait_secs: u64,
+    /// 非流式上游响应体大小上限（字节）
+    pub max_response_body_bytes: u64,
+    /// 非流式响应体超过上限时的处理方式
+    pub oversized_response: OversizedResponse,
+    /// 上游响应内容检查模式
+    pub response_validation: ValidationMode,
+    /// provider 选择方式
+    pub provider_selection: SelectionMode,
+    /// 按请求 ID 确定性选择 provider 的种子，设置时忽略 `provider_selection`
+    pub selection_seed: Option<u64>,
+    /// 第一个请求的 ID
+    pub request_id_start: u64,
+    /// 慢请求阈值（毫秒），0 表示关闭
+    pub slow_request_ms: u64,
+    /// 按模型前缀覆盖的慢请求阈值（毫秒）
+    pub slow_request_model_ms: Vec<(String, u64)>,
+    /// 成功请求的访问日志每 N 个记录一个（`PLURIBUS_ACCESS_LOG_SAMPLE`），1 表示全部记录
+    pub access_log_sample: u32,
+    /// 未记录的访问日志汇总输出间隔（秒）
+    pub access_log_rollup_secs: u64,
+    /// 配置文件路径（`PLURIBUS_CONFIG`）
+    pub config_file: PathBuf,
+    /// 按模型指定的上游 API 地址
+    pub model_endpoints: ModelEndpoints,
+    /// 返回给客户端的单个响应头值的最大字节数，超出的响应头会被移除
+    pub max_forward_header_value_bytes: usize,
+    /// 关闭时等待进行中请求（含流式响应）结束的最长时间（秒）
+    pub shutdown_drain_secs: u64,
+    /// 对冲请求：第二个 provider 延迟启动的时间（毫秒）
+    pub hedge_delay_ms: u64,
+    /// 对冲请求允许的最大 `max_tokens`
+    pub hedge_max_tokens: u64,
+    /// 对冲请求允许的最大输入字符数
+    pub hedge_max_input_chars: u64,
+    /// tool-use 循环中固定 provider 的时长（秒），0 表示关闭
+    pub tool_pin_ttl_secs: u64,
+    /// 最多记录的 tool_use 固定数
+    pub tool_pin_max_entries: usize,
+    /// 按 provider 类型的请求字段策略
+    pub field_policies: FieldPolicies,
+    /// 请求改写的灰度规则
+    pub canaries: Canaries,
+    /// 暂停后台上游请求的静默时段
+    pub quiet_hours: QuietHours,
+    /// 死信文件路径
+    pub dead_letter_file: PathBuf,
+    /// 死信文件大小上限（字节），0 表示关闭
+    pub dead_letter_max_bytes: u64,
+    /// 死信保留天数
+    pub dead_letter_retention_days: u64,
+    /// 保存上游 400 `invalid_request_error` 请求的目录，未设置时关闭
+    pub repro_dir: Option<PathBuf>,
+    /// 用量历史 SQLite 数据库路径，设为空时关闭
+    pub usage_db: Option<PathBuf>,
+    /// 流式响应超过该时长（秒）记录警告
+    pub stream_warn_age_secs: u64,
+    /// 流式响应超过该时长（秒）未转发数据记录警告
+    pub stream_idle_secs: u64,
+    /// 流式响应超过该时长（秒）强制中止，0 表示不限制
+    pub stream_max_age_secs: u64,
+    /// 是否检测 prompt cache 前缀变化
+    pub cache_prefix_analyzer: bool,
+    /// 前缀变化检测最多记录的会话数
+    pub cache_prefix_max_entries: usize,
+    /// 同时转发的最大请求数，0 表示不限制
+    pub max_concurrent_requests: usize,
+    /// 超出并发上限时最多排队的请求数
+    pub max_queued_requests: usize,
+    /// 排队请求获得许可的调度方式
+    pub admission_scheduler: AdmissionScheduler,
+    /// 公平调度时单个密钥最多同时持有的许可占比（百分比）
+    pub fair_share_max_percent: u8,
+    /// 是否按 provider 采集上游响应头
+    pub capture_response_headers: bool,
+    /// 采集时保存值的响应头名称，以 `*` 结尾时按前缀匹配
+    pub capture_header_values: Vec<String>,
+    /// 视为模型弃用信号的上游响应头名称，小写
+    pub deprecation_headers: Vec<String>,
+    /// 视为模型弃用信号的响应体字段，以 `.` 分隔嵌套字段
+    pub deprecation_fields: Vec<String>,
+    /// 延迟探测间隔（秒），0 表示关闭
+    pub latency_probe_interval_secs: u64,
+    /// 延迟探测使用的模型
+    pub latency_probe_model: String,
+    /// TTFT 指数加权移动平均的平滑系数，取值 (0, 1]
+    pub latency_ewma_alpha: f64,
+    /// 利用率相差不超过该值时优先选择更快的 provider，0 表示关闭
+    pub latency_tiebreak_margin: f64,
+    /// 会话超过该时长（秒）未产生用量时清零累计用量，0 表示不清零
+    pub conversation_budget_ttl_secs: u64,
+    /// 最多记录累计用量的会话数
+    pub conversation_budget_max_entries: usize,
+    /// 同一 provider 两次 token 刷新尝试之间的最短间隔（秒）
+    pub token_refresh_min_interval_secs: u64,
+    /// token 刷新遇到暂时性错误（5xx、连接失败、超时）时的最大重试次数
+    pub token_refresh_max_retries: u32,
+    /// token 刷新重试的基础间隔（毫秒），按全抖动指数退避
+    pub token_refresh_base_ms: u64,
+    /// 拒绝以秒填写 `oauth.expires_at` 的 provider 配置，而不是换算为毫秒
+    pub strict_provider_config: bool,
+    /// 响应中号池 rate limit 汇总头的模式
+    pub pool_headers: PoolHeaders,
+    /// `/metrics` 需要 readonly 及以上角色的密钥
+    pub metrics_auth: bool,
+    /// OpenTelemetry OTLP (gRPC) 导出地址，启动时在初始化日志前读取
+    pub otel_endpoint: Option<String>,
+}
+
+/// 号池 rate limit 汇总响应头的模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PoolHeaders {
+    /// 不添加
+    #[default]
+    Off,
+    /// 添加 `x-pluribus-pool-*`
+    On,
+    /// 添加 `x-pluribus-pool-*`，并以汇总值设置 `anthropic-ratelimit-unified-*`
+    Override,
+}
+
+impl PoolHeaders {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "off" | "" => Some(PoolHeaders::Off),
+            "on" => Some(PoolHeaders::On),
+            "override" => Some(PoolHeaders::Override),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PoolHeaders::Off => "off",
+            PoolHeaders::On => "on",
+            PoolHeaders::Override => "override",
+        }
+    }
+}
+
+/// 排队请求获得许可的调度方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AdmissionScheduler {
+    /// 按优先级，限定插队次数
+    #[default]
+    Priority,
+    /// 在有排队请求的密钥之间轮流发放
+    FairShare,
+}
+
+impl AdmissionScheduler {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "priority" | "" => Some(AdmissionScheduler::Priority),
+            "fair_share" => Some(AdmissionScheduler::FairShare),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdmissionScheduler::Priority => "priority",
+            AdmissionScheduler::FairShare => "fair_share",
+        }
+    }
+}
+
+/// `pluribus.toml` 文件结构
+#[derive(Debug, Default, Deserialize)]
+struct ConfigFile {
+    /// 模型 glob 模式 → API 地址
+    #[serde(default)]
+    model_endpoints: BTreeMap<String, String>,
+    /// provider 类型 → 字段名 → keep / strip / adapt
+    #[serde(default)]
+    field_policy: HashMap<ProviderType, BTreeMap<String, FieldAction>>,
+    /// 改写 → 灰度规则
+    #[serde(default)]
+    canary: BTreeMap<Transform, CanaryRule>,
+    /// 暂停后台上游请求的时间段
+    #[serde(default)]
+    quiet_hours: QuietHours,
+    /// 顶层设置，环境变量未设置时使用
+    #[serde(flatten)]
+    settings: BTreeMap<String, toml::Value>,
+}
+
+/// 按模型匹配的上游 API 地址
+///
+/// 多个模式同时匹配时，使用模式最长（最具体）的一项
+#[derive(Debug, Clone, Default)]
+pub struct ModelEndpoints {
+    entries: Vec<(String, Regex, String)>,
+}
+
+impl ModelEndpoints {
+    /// 编译 glob 模式并校验地址必须为 HTTPS
+    fn compile(patterns: BTreeMap<String, String>) -> Result<Self> {
+        let mut entries = patterns
+            .into_iter()
+            .map(|(pattern, url)| {
+                let parsed = reqwest::Url::parse(&url)
+                    .with_context(|| format!("Invalid endpoint URL for {pattern}: {url}"))?;
+                // 测试中的 mock 上游监听在本机，不使用 HTTPS
+                let loopback = cfg!(test) && parsed.host_str() == Some("127.0.0.1");
+                if parsed.scheme() != "https" && !loopback {
+                    anyhow::bail!("Endpoint URL for {pattern} must use HTTPS: {url}");
+                }
+                egress::check_url(&parsed)
+                    .with_context(|| format!("Endpoint URL for {pattern} is not allowed"))?;
+                let regex = Regex::new(&glob_to_regex(&pattern))
+                    .with_context(|| format!("Invalid model pattern: {pattern}"))?;
+                Ok((pattern, regex, url))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // 模式越长越具体，优先匹配
+        entries.sort_by_key(|(pattern, _, _)| std::cmp::Reverse(pattern.len()));
+
+        Ok(Self { entries })
+    }
+
+    /// 查找模型对应的 API 地址
+    pub fn resolve(&self, model: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(_, regex, _)| regex.is_match(model))
+            .map(|(_, _, url)| url.as_str())
+    }
+
+    /// glob 模式 → API 地址
+    pub fn patterns(&self) -> BTreeMap<&str, &str> {
+        self.entries
+            .iter()
+            .map(|(pattern, _, url)| (pattern.as_str(), url.as_str()))
+            .collect()
+    }
+}
+
+/// 将 glob 模式（`*` 匹配任意字符串，`?` 匹配单个字符）转换为完整匹配的正则
+fn glob_to_regex(pattern: &str) -> String {
+    let mut regex = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// 读取配置文件，文件不存在时使用默认值
+fn load_config_file(path: &Path) -> Result<ConfigFile> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => toml::from_str(&content)
+            .with_context(|| format!("Failed to parse config file {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ConfigFile::default()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read config file {}", path.display())),
+    }
+}
+
+/// 检查配置文件能否被正确加载（文件不存在视为有效）
+pub fn check_config_file(path: &Path) -> Result<()> {
+    let file = load_config_file(path)?;
+    Settings::new(file.settings, |name| std::env::var(name))
+        .with_context(|| format!("Invalid settings in {}", path.display()))?;
+    ModelEndpoints::compile(file.model_endpoints)
+        .with_context(|| format!("Invalid [model_endpoints] in {}", path.display()))?;
+    FieldPolicies::with_overrides(file.field_policy)
+        .with_context(|| format!("Invalid [field_policy] in {}", path.display()))?;
+    Canaries::new(file.canary)
+        .with_context(|| format!("Invalid [canary] in {}", path.display()))?;
+    file.quiet_hours
+        .validate()
+        .with_context(|| format!("Invalid [quiet_hours] in {}", path.display()))?;
+    Ok(())
+}
+
+/// 用户级配置文件，相对于 `$HOME`
+const USER_CONFIG_FILE: &str = ".config/pluribus/config.toml";
+
+/// 配置文件路径
+///
+/// 优先使用 `PLURIBUS_CONFIG`（或旧的 `PLURIBUS_CONFIG_FILE`），否则依次查找 `./pluribus.toml`
+/// 与 `~/.config/pluribus/config.toml`，都不存在时为 `./pluribus.toml`
+fn config_file_path() -> PathBuf {
+    if let Some(path) = ["PLURIBUS_CONFIG", "PLURIBUS_CONFIG_FILE"]
+        .iter()
+        .filter_map(std::env::var_os)
+        .find(|path| !path.is_empty())
+    {
+        return PathBuf::from(path);
+    }
+    let local = PathBuf::from("./pluribus.toml");
+    if local.exists() {
+        return local;
+    }
+    std::env::var_os("HOME")
+        .map(|home| PathBuf::from(home).join(USER_CONFIG_FILE))
+        .filter(|path| path.exists())
+        .unwrap_or(local)
 }
 
 impl Config {
-    /// 从环境变量加载配置
+    /// 查找配置文件并加载配置，见 [`from_file`](Self::from_file)
+    pub fn load() -> Result<Self> {
+        Self::from_file(&config_file_path())
+    }
+
+    /// 从配置文件与环境变量加载配置，文件不存在时只使用环境变量
+    ///
+    /// 文件的顶层设置与去掉 `PLURIBUS_` 前缀的小写环境变量名对应（如 `port = 8080`），
+    /// 同一设置两边都有时使用环境变量
     ///
     /// # 环境变量
     ///
     /// - `PLURIBUS_HOST`: 服务器监听地址（默认: "0.0.0.0"）
     /// - `PLURIBUS_PORT`: 服务器监听端口（默认: 8080）
-    /// - `PLURIBUS_SECRET`: API 访问密钥（**必需**）
+    /// - `PLURIBUS_SECRET`: API 访问密钥（**必需**），同时作为 admin 角色的密钥
+    /// - `PLURIBUS_KEYS_FILE`: 客户端密钥文件（默认: "./keys.toml"）
+    /// - `PLURIBUS_AUTH_DEBUG`: 认证调试模式（默认: false）
+    /// - `PLURIBUS_MAX_REQUEST_BODY_BYTES`: 请求体大小上限（默认: 32 MiB）
+    /// - `PLURIBUS_ADMIN_REQUEST_HISTORY`: 保留的最近请求数（默认: 100）
+    /// - `PLURIBUS_ADMIN_REQUEST_BODY_BYTES`: 请求历史中请求体的最大字节数（默认: 64 KiB）
+    /// - `PLURIBUS_GDPR_MODE`: 不保留请求内容（默认: false）
+    /// - `PLURIBUS_MAX_RETRIES`: 上游可重试错误的最大重试次数（默认: 0）
+    /// - `PLURIBUS_RATE_LIMIT_MAX_WAIT_SECS`: 429 时等待 rate limit 重置的上限（默认: 300）
+    /// - `PLURIBUS_RESPONSE_VALIDATION`: 响应内容检查模式 off / warn / strict（默认: off）
+    /// - `PLURIBUS_SLOW_REQUEST_MS`: 慢请求阈值，0 表示关闭（默认: 0）
+    /// - `PLURIBUS_SLOW_REQUEST_MODEL_MS`: 按模型前缀覆盖慢请求阈值，如 `claude-opus-4=120000,claude-haiku-4=20000`
+    /// - `PLURIBUS_MAX_RESPONSE_HEADER_SIZE_BYTES`: 上游响应头总大小上限（默认: 16 KiB）
+    /// - `PLURIBUS_MAX_FORWARD_HEADER_VALUE_BYTES`: 返回给客户端的单个响应头值上限（默认: 4 KiB）
+    /// - `PLURIBUS_SHUTDOWN_DRAIN_SECS`: 关闭时等待进行中请求结束的最长时间（默认: 30）
+    /// - `PLURIBUS_DEAD_LETTER_FILE`: 死信文件路径（默认: "./deadletter.jsonl"）
+    /// - `PLURIBUS_DEAD_LETTER_MAX_BYTES`: 死信文件大小上限，0 表示关闭（默认: 16 MiB）
+    /// - `PLURIBUS_DEAD_LETTER_RETENTION_DAYS`: 死信保留天数（默认: 7）
     ///
     /// # 错误
     ///
-    /// - 如果 `PLURIBUS_SECRET` 未设置
+    /// - 如果 `PLURIBUS_SECRET` 与配置文件中的 `secret` 都未设置
     /// - 如果 `PLURIBUS_PORT` 不是有效的端口号
-    pub fn from_env() -> Result<Self> {
-        let host = std::env::var("PLURIBUS_HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
+    /// - 如果数值型环境变量或设置无法解析
+    /// - 如果配置文件中有未知的顶层设置
+    /// - 如果配置文件无法解析，或 `[model_endpoints]` 中的地址不是 HTTPS
+    /// - 如果 `[field_policy]` 中对不支持的字段使用了 `adapt`
+    /// - 如果 `[canary]` 中的放量百分比超过 100
+    /// - 如果 `[quiet_hours]` 中的小时或星期超出范围
+    pub fn from_file(path: &Path) -> Result<Self> {
+        Self::from_file_with_env(path, |name| std::env::var(name))
+    }
+
+    /// 同 [`from_file`](Self::from_file)，环境变量由 `env` 读取
+    fn from_file_with_env(path: &Path, env: EnvVar) -> Result<Self> {
+        let config_file = path.to_path_buf();
+        let file = load_config_file(&config_file)?;
+        let settings = Settings::new(file.settings, env)
+            .with_context(|| format!("Invalid settings in {}", config_file.display()))?;
+
+        let host = settings
+            .var("PLURIBUS_HOST")
+            .unwrap_or_else(|_| "0.0.0.0".to_string());
 
-        let port = std::env::var("PLURIBUS_PORT")
+        let port = settings
+            .var("PLURIBUS_PORT")
             .unwrap_or_else(|_| "8080".to_string())
             .parse()
             .context("PLURIBUS_PORT must be a valid port number")?;
 
-        let secret = std::env::var("PLURIBUS_SECRET")
-            .context("PLURIBUS_SECRET environment variable is required")?;
+        let secret = settings.var("PLURIBUS_SECRET").context(
+            "PLURIBUS_SECRET environment variable (or `secret` in the config file) is required",
+        )?;
+
+        let providers_dir = settings
+            .var("PLURIBUS_PROVIDERS_DIR")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("./providers"));
+
+        let keys_file = settings
+            .var("PLURIBUS_KEYS_FILE")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from("./keys.toml"));
+
+        let auth_debug = settings.flag("PLURIBUS_AUTH_DEBUG");
 
-        let providers_dir = PathBuf::from("./providers");
+        let max_request_body_bytes = settings.parse(
+            "PLURIBUS_MAX_REQUEST_BODY_BYTES",
+            DEFAULT_MAX_REQUEST_BODY_BYTES,
+        )?;
+
+        let request_history_size = settings.parse("PLURIBUS_ADMIN_REQUEST_HISTORY", 100)?;
+        let request_history_body_bytes =
+            settings.parse("PLURIBUS_ADMIN_REQUEST_BODY_BYTES", 64 * 1024)?;
+        let gdpr_mode = settings.flag("PLURIBUS_GDPR_MODE");
+
+        let max_retries = settings.parse("PLURIBUS_MAX_RETRIES", 0)?;
+        let max_failover = match settings.var("PLURIBUS_MAX_FAILOVER") {
+            Ok(_) => Some(settings.parse("PLURIBUS_MAX_FAILOVER", 0)?),
+            Err(_) => None,
+        };
+        let circuit_failure_threshold = settings.parse("PLURIBUS_CIRCUIT_FAILURE_THRESHOLD", 5)?;
+        let circuit_recovery_secs = settings.parse("PLURIBUS_CIRCUIT_RECOVERY_SECS", 30)?;
+        let rate_limit_max_wait_secs = settings.parse("PLURIBUS_RATE_LIMIT_MAX_WAIT_SECS", 300)?;
+
+        let max_response_body_bytes = settings.parse(
+            "PLURIBUS_MAX_RESPONSE_BODY_BYTES",
+            DEFAULT_MAX_RESPONSE_BODY_BYTES,
+        )?;
+        let oversized_response = match settings.var("PLURIBUS_OVERSIZED_RESPONSE") {
+            Ok(value) => OversizedResponse::parse(&value).with_context(|| {
+                format!("PLURIBUS_OVERSIZED_RESPONSE must be fail or stream: {value}")
+            })?,
+            Err(_) => OversizedResponse::default(),
+        };
+        let response_validation = match settings.var("PLURIBUS_RESPONSE_VALIDATION") {
+            Ok(value) => ValidationMode::parse(&value).with_context(|| {
+                format!("PLURIBUS_RESPONSE_VALIDATION must be off, warn or strict: {value}")
+            })?,
+            Err(_) => ValidationMode::default(),
+        };
+        let provider_selection = match settings.var("PLURIBUS_PROVIDER_SELECTION") {
+            Ok(value) => SelectionMode::parse(&value).with_context(|| {
+                format!("PLURIBUS_PROVIDER_SELECTION must be priority, weighted or least_connections: {value}")
+            })?,
+            Err(_) => SelectionMode::default(),
+        };
+        let selection_seed = match settings.var("PLURIBUS_SELECTION_SEED") {
+            Ok(value) if !value.trim().is_empty() => {
+                Some(value.trim().parse().with_context(|| {
+                    format!("PLURIBUS_SELECTION_SEED must be an unsigned integer: {value}")
+                })?)
+            }
+            _ => None,
+        };
+        let request_id_start = settings.parse("PLURIBUS_REQUEST_ID_START", 1)?;
+
+        let slow_request_ms = settings.parse("PLURIBUS_SLOW_REQUEST_MS", 0)?;
+        let slow_request_model_ms = match settings.var("PLURIBUS_SLOW_REQUEST_MODEL_MS") {
+            Ok(value) => parse_model_thresholds(&value)
+                .context("PLURIBUS_SLOW_REQUEST_MODEL_MS must look like model=ms,model=ms")?,
+            Err(_) => Vec::new(),
+        };
+        let access_log_sample: u32 = settings.parse("PLURIBUS_ACCESS_LOG_SAMPLE", 1)?;
+        if access_log_sample == 0 {
+            anyhow::bail!("PLURIBUS_ACCESS_LOG_SAMPLE must be at least 1");
+        }
+        let access_log_rollup_secs: u64 = settings.parse("PLURIBUS_ACCESS_LOG_ROLLUP_SECS", 60)?;
+        if access_log_rollup_secs == 0 {
+            anyhow::bail!("PLURIBUS_ACCESS_LOG_ROLLUP_SECS must be at least 1");
+        }
+
+        let max_forward_header_value_bytes =
+            settings.parse("PLURIBUS_MAX_FORWARD_HEADER_VALUE_BYTES", 4 * 1024)?;
+
+        let shutdown_drain_secs = settings.parse("PLURIBUS_SHUTDOWN_DRAIN_SECS", 30)?;
+
+        let hedge_delay_ms = settings.parse("PLURIBUS_HEDGE_DELAY_MS", 300)?;
+        let hedge_max_tokens = settings.parse("PLURIBUS_HEDGE_MAX_TOKENS", 256)?;
+        let hedge_max_input_chars = settings.parse("PLURIBUS_HEDGE_MAX_INPUT_CHARS", 8000)?;
+
+        let tool_pin_ttl_secs = settings.parse("PLURIBUS_TOOL_PIN_TTL_SECS", 300)?;
+        let tool_pin_max_entries = settings.parse("PLURIBUS_TOOL_PIN_MAX_ENTRIES", 10_000)?;
+
+        let dead_letter_file = settings
+            .var("PLURIBUS_DEAD_LETTER_FILE")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from("./deadletter.jsonl"));
+        let dead_letter_max_bytes =
+            settings.parse("PLURIBUS_DEAD_LETTER_MAX_BYTES", 16 * 1024 * 1024)?;
+        let dead_letter_retention_days =
+            settings.parse("PLURIBUS_DEAD_LETTER_RETENTION_DAYS", 7)?;
+        let repro_dir = settings
+            .var("PLURIBUS_REPRO_DIR")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from);
+        let usage_db = match settings.var("PLURIBUS_USAGE_DB") {
+            Ok(path) if path.is_empty() => None,
+            Ok(path) => {
+                require_feature(
+                    cfg!(feature = "usage-sqlite"),
+                    "usage-sqlite",
+                    "PLURIBUS_USAGE_DB",
+                )?;
+                Some(PathBuf::from(path))
+            }
+            // 关闭 `usage-sqlite` feature 时默认不记录
+            Err(_) => cfg!(feature = "usage-sqlite").then(|| PathBuf::from("./usage.db")),
+        };
+
+        let stream_warn_age_secs = settings.parse("PLURIBUS_STREAM_WARN_AGE_SECS", 1800)?;
+        let stream_idle_secs = settings.parse("PLURIBUS_STREAM_IDLE_SECS", 300)?;
+        let stream_max_age_secs = settings.parse("PLURIBUS_STREAM_MAX_AGE_SECS", 7200)?;
+
+        let cache_prefix_analyzer = settings.flag("PLURIBUS_CACHE_PREFIX_ANALYZER");
+        let cache_prefix_max_entries =
+            settings.parse("PLURIBUS_CACHE_PREFIX_MAX_ENTRIES", 10_000)?;
+
+        let max_concurrent_requests = settings.parse("PLURIBUS_MAX_CONCURRENT_REQUESTS", 0)?;
+        let max_queued_requests = settings.parse("PLURIBUS_MAX_QUEUED_REQUESTS", 100)?;
+        let admission_scheduler = match settings.var("PLURIBUS_ADMISSION_SCHEDULER") {
+            Ok(value) => AdmissionScheduler::parse(&value).with_context(|| {
+                format!("PLURIBUS_ADMISSION_SCHEDULER must be priority or fair_share: {value}")
+            })?,
+            Err(_) => AdmissionScheduler::default(),
+        };
+        let fair_share_max_percent: u8 = settings.parse("PLURIBUS_FAIR_SHARE_MAX_PERCENT", 100)?;
+        if !(1..=100).contains(&fair_share_max_percent) {
+            anyhow::bail!(
+                "PLURIBUS_FAIR_SHARE_MAX_PERCENT must be between 1 and 100: {fair_share_max_percent}"
+            );
+        }
+
+        let capture_response_headers = settings.flag("PLURIBUS_CAPTURE_RESPONSE_HEADERS");
+        let capture_header_values = settings
+            .var("PLURIBUS_CAPTURE_HEADER_VALUES")
+            .unwrap_or_else(|_| DEFAULT_CAPTURE_HEADER_VALUES.to_string())
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect();
+        let deprecation_headers = settings
+            .var("PLURIBUS_DEPRECATION_HEADERS")
+            .unwrap_or_else(|_| DEFAULT_DEPRECATION_HEADERS.to_string())
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>();
+        if let Some(name) = deprecation_headers
+            .iter()
+            .find(|name| http::HeaderName::from_bytes(name.as_bytes()).is_err())
+        {
+            anyhow::bail!("PLURIBUS_DEPRECATION_HEADERS contains an invalid header name: {name}");
+        }
+        let deprecation_fields = settings
+            .var("PLURIBUS_DEPRECATION_FIELDS")
+            .unwrap_or_else(|_| DEFAULT_DEPRECATION_FIELDS.to_string())
+            .split(',')
+            .map(|field| field.trim().to_string())
+            .filter(|field| !field.is_empty())
+            .collect::<Vec<_>>();
+        if let Some(field) = deprecation_fields
+            .iter()
+            .find(|field| field.split('.').any(str::is_empty))
+        {
+            anyhow::bail!("PLURIBUS_DEPRECATION_FIELDS contains an invalid field path: {field}");
+        }
+
+        let latency_probe_interval_secs =
+            settings.parse("PLURIBUS_LATENCY_PROBE_INTERVAL_SECS", 0)?;
+        let latency_probe_model = settings
+            .var("PLURIBUS_LATENCY_PROBE_MODEL")
+            .unwrap_or_else(|_| DEFAULT_LATENCY_PROBE_MODEL.to_string());
+        let latency_ewma_alpha: f64 = settings.parse("PLURIBUS_LATENCY_EWMA_ALPHA", 0.3)?;
+        if !(latency_ewma_alpha > 0.0 && latency_ewma_alpha <= 1.0) {
+            anyhow::bail!(
+                "PLURIBUS_LATENCY_EWMA_ALPHA must be in (0, 1], got {latency_ewma_alpha}"
+            );
+        }
+        let latency_tiebreak_margin: f64 =
+            settings.parse("PLURIBUS_LATENCY_TIEBREAK_MARGIN", 0.1)?;
+        if !(0.0..=1.0).contains(&latency_tiebreak_margin) {
+            anyhow::bail!(
+                "PLURIBUS_LATENCY_TIEBREAK_MARGIN must be in [0, 1], got {latency_tiebreak_margin}"
+            );
+        }
+
+        let conversation_budget_ttl_secs =
+            settings.parse("PLURIBUS_CONVERSATION_BUDGET_TTL_SECS", 86_400)?;
+        let conversation_budget_max_entries =
+            settings.parse("PLURIBUS_CONVERSATION_BUDGET_MAX_ENTRIES", 10_000)?;
+
+        let token_refresh_min_interval_secs =
+            settings.parse("PLURIBUS_TOKEN_REFRESH_MIN_INTERVAL_SECS", 60)?;
+        let token_refresh_max_retries = settings.parse("PLURIBUS_TOKEN_REFRESH_MAX_RETRIES", 3)?;
+        let token_refresh_base_ms = settings.parse("PLURIBUS_TOKEN_REFRESH_BASE_MS", 500)?;
+        let strict_provider_config = settings.flag("PLURIBUS_STRICT_PROVIDER_CONFIG");
+        let metrics_auth = settings.flag("PLURIBUS_METRICS_AUTH");
+        if metrics_auth {
+            require_feature(
+                cfg!(feature = "metrics"),
+                "metrics",
+                "PLURIBUS_METRICS_AUTH",
+            )?;
+        }
+        let otel_endpoint = std::env::var(crate::telemetry::ENDPOINT_ENV)
+            .ok()
+            .filter(|endpoint| !endpoint.is_empty());
+        if otel_endpoint.is_some() {
+            require_feature(
+                cfg!(feature = "otel"),
+                "otel",
+                crate::telemetry::ENDPOINT_ENV,
+            )?;
+        }
+        let pool_headers = match settings.var("PLURIBUS_POOL_HEADERS") {
+            Ok(value) => PoolHeaders::parse(&value).with_context(|| {
+                format!("PLURIBUS_POOL_HEADERS must be off, on or override: {value}")
+            })?,
+            Err(_) => PoolHeaders::default(),
+        };
+
+        let model_endpoints = ModelEndpoints::compile(file.model_endpoints)
+            .with_context(|| format!("Invalid [model_endpoints] in {}", config_file.display()))?;
+        let field_policies = FieldPolicies::with_overrides(file.field_policy)
+            .with_context(|| format!("Invalid [field_policy] in {}", config_file.display()))?;
+        let canaries = Canaries::new(file.canary)
+            .with_context(|| format!("Invalid [canary] in {}", config_file.display()))?;
+        let quiet_hours = file.quiet_hours;
+        quiet_hours
+            .validate()
+            .with_context(|| format!("Invalid [quiet_hours] in {}", config_file.display()))?;
+
+        settings
+            .check_unused()
+            .with_context(|| format!("Invalid settings in {}", config_file.display()))?;
 
         Ok(Self {
             host,
             port,
             secret,
             providers_dir,
+            keys_file,
+            auth_debug,
+            max_request_body_bytes,
+            request_history_size,
+            request_history_body_bytes,
+            gdpr_mode,
+            max_retries,
+            max_failover,
+            circuit_failure_threshold,
+            circuit_recovery_secs,
+            rate_limit_max_wait_secs,
+            max_response_body_bytes,
+            oversized_response,
+            response_validation,
+            provider_selection,
+            selection_seed,
+            request_id_start,
+            slow_request_ms,
+            slow_request_model_ms,
+            access_log_sample,
+            access_log_rollup_secs,
+            config_file,
+            model_endpoints,
+            max_forward_header_value_bytes,
+            shutdown_drain_secs,
+            hedge_delay_ms,
+            hedge_max_tokens,
+            hedge_max_input_chars,
+            tool_pin_ttl_secs,
+            tool_pin_max_entries,
+            field_policies,
+            canaries,
+            quiet_hours,
+            dead_letter_file,
+            dead_letter_max_bytes,
+            dead_letter_retention_days,
+            repro_dir,
+            usage_db,
+            stream_warn_age_secs,
+            stream_idle_secs,
+            stream_max_age_secs,
+            cache_prefix_analyzer,
+            cache_prefix_max_entries,
+            max_concurrent_requests,
+            max_queued_requests,
+            admission_scheduler,
+            fair_share_max_percent,
+            capture_response_headers,
+            capture_header_values,
+            deprecation_headers,
+            deprecation_fields,
+            latency_probe_interval_secs,
+            latency_probe_model,
+            latency_ewma_alpha,
+            latency_tiebreak_margin,
+            conversation_budget_ttl_secs,
+            conversation_budget_max_entries,
+            token_refresh_min_interval_secs,
+            token_refresh_max_retries,
+            token_refresh_base_ms,
+            strict_provider_config,
+            pool_headers,
+            metrics_auth,
+            otel_endpoint,
         })
     }
 
+    /// 不含密钥的配置快照，供 `/admin/info` 与 `pluribus diff` 比较
+    pub fn settings(&self) -> serde_json::Map<String, serde_json::Value> {
+        let settings = serde_json::json!({
+            "host": self.host,
+            "port": self.port,
+            "providers_dir": self.providers_dir,
+            "keys_file": self.keys_file,
+            "auth_debug": self.auth_debug,
+            "max_request_body_bytes": self.max_request_body_bytes,
+            "request_history_size": self.request_history_size,
+            "request_history_body_bytes": self.request_history_body_bytes,
+            "gdpr_mode": self.gdpr_mode,
+            "max_retries": self.max_retries,
+            "max_failover": self.max_failover,
+            "circuit_failure_threshold": self.circuit_failure_threshold,
+            "circuit_recovery_secs": self.circuit_recovery_secs,
+            "rate_limit_max_wait_secs": self.rate_limit_max_wait_secs,
+            "max_response_body_bytes": self.max_response_body_bytes,
+            "oversized_response": self.oversized_response.as_str(),
+            "response_validation": self.response_validation.as_str(),
+            "provider_selection": self.provider_selection.as_str(),
+            "selection_seed": self.selection_seed,
+            "request_id_start": self.request_id_start,
+            "slow_request_ms": self.slow_request_ms,
+            "slow_request_model_ms": self.slow_request_model_ms,
+            "access_log_sample": self.access_log_sample,
+            "access_log_rollup_secs": self.access_log_rollup_secs,
+            "config_file": self.config_file,
+            "model_endpoints": self.model_endpoints.patterns(),
+            "canary": self.canaries.settings(),
+            "quiet_hours": self.quiet_hours,
+            "max_forward_header_value_bytes": self.max_forward_header_value_bytes,
+            "shutdown_drain_secs": self.shutdown_drain_secs,
+            "hedge_delay_ms": self.hedge_delay_ms,
+            "hedge_max_tokens": self.hedge_max_tokens,
+            "hedge_max_input_chars": self.hedge_max_input_chars,
+            "tool_pin_ttl_secs": self.tool_pin_ttl_secs,
+            "tool_pin_max_entries": self.tool_pin_max_entries,
+            "dead_letter_file": self.dead_letter_file,
+            "dead_letter_max_bytes": self.dead_letter_max_bytes,
+            "dead_letter_retention_days": self.dead_letter_retention_days,
+            "repro_dir": self.repro_dir,
+            "usage_db": self.usage_db,
+            "stream_warn_age_secs": self.stream_warn_age_secs,
+            "stream_idle_secs": self.stream_idle_secs,
+            "stream_max_age_secs": self.stream_max_age_secs,
+            "cache_prefix_analyzer": self.cache_prefix_analyzer,
+            "cache_prefix_max_entries": self.cache_prefix_max_entries,
+            "max_concurrent_requests": self.max_concurrent_requests,
+            "max_queued_requests": self.max_queued_requests,
+            "admission_scheduler": self.admission_scheduler.as_str(),
+            "fair_share_max_percent": self.fair_share_max_percent,
+            "capture_response_headers": self.capture_response_headers,
+            "capture_header_values": self.capture_header_values,
+            "deprecation_headers": self.deprecation_headers,
+            "deprecation_fields": self.deprecation_fields,
+            "latency_probe_interval_secs": self.latency_probe_interval_secs,
+            "latency_probe_model": self.latency_probe_model,
+            "latency_ewma_alpha": self.latency_ewma_alpha,
+            "latency_tiebreak_margin": self.latency_tiebreak_margin,
+            "conversation_budget_ttl_secs": self.conversation_budget_ttl_secs,
+            "conversation_budget_max_entries": self.conversation_budget_max_entries,
+            "token_refresh_min_interval_secs": self.token_refresh_min_interval_secs,
+            "token_refresh_max_retries": self.token_refresh_max_retries,
+            "token_refresh_base_ms": self.token_refresh_base_ms,
+            "strict_provider_config": self.strict_provider_config,
+            "pool_headers": self.pool_headers.as_str(),
+            "metrics_auth": self.metrics_auth,
+            "otel_endpoint": self.otel_endpoint,
+            "tls_verify_disabled": crate::utils::should_disable_tls_verify(),
+        });
+        match settings {
+            serde_json::Value::Object(map) => map,
+            _ => unreachable!("settings is a JSON object"),
+        }
+    }
+
     /// 获取 provider 配置目录路径
     pub fn providers_dir(&self) -> &std::path::Path {
         &self.providers_dir
     }
 
+    /// 使用命令行指定的 provider 配置目录，优先于 `PLURIBUS_PROVIDERS_DIR`
+    pub fn with_providers_dir(mut self, dir: Option<PathBuf>) -> Self {
+        if let Some(dir) = dir {
+            self.providers_dir = dir;
+        }
+        self
+    }
+
     /// 确保必要的目录存在
     ///
     /// 创建 providers 配置目录（如果不存在）
@@ -71,3 +865,181 @@ impl Config {
         Ok(())
     }
 }
+
+/// 读取环境变量的函数，与 `std::env::var` 的签名相同
+type EnvVar = fn(&str) -> Result<String, std::env::VarError>;
+
+/// 配置项的来源：环境变量优先，未设置时使用配置文件中的顶层设置
+///
+/// 顶层设置的键为环境变量名去掉 `PLURIBUS_` 前缀后的小写形式，如 `port` 对应 `PLURIBUS_PORT`
+struct Settings {
+    file: BTreeMap<String, String>,
+    env: EnvVar,
+    /// 读取过的键，读取完成后仍未读取的键是拼写错误或不支持的设置
+    read: RefCell<HashSet<String>>,
+}
+
+impl Settings {
+    fn new(values: BTreeMap<String, toml::Value>, env: EnvVar) -> Result<Self> {
+        let file = values
+            .into_iter()
+            .map(|(key, value)| Ok((key.clone(), setting_value(&key, value)?)))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            file,
+            env,
+            read: RefCell::default(),
+        })
+    }
+
+    /// 读取设置，与 `std::env::var` 的返回值相同
+    fn var(&self, name: &str) -> Result<String, std::env::VarError> {
+        let key = name.trim_start_matches("PLURIBUS_").to_ascii_lowercase();
+        let value = match (self.env)(name) {
+            Err(std::env::VarError::NotPresent) => self
+                .file
+                .get(&key)
+                .cloned()
+                .ok_or(std::env::VarError::NotPresent),
+            result => result,
+        };
+        self.read.borrow_mut().insert(key);
+        value
+    }
+
+    /// 读取布尔型设置（"1" 或 "true" 视为开启）
+    fn flag(&self, name: &str) -> bool {
+        self.var(name)
+            .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+            .unwrap_or(false)
+    }
+
+    /// 读取可解析的设置，未设置时使用默认值
+    fn parse<T>(&self, name: &str, default: T) -> Result<T>
+    where
+        T: std::str::FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        match self.var(name) {
+            Ok(value) => value
+                .trim()
+                .parse()
+                .with_context(|| format!("{name} has an invalid value: {value}")),
+            Err(_) => Ok(default),
+        }
+    }
+
+    /// 配置文件中是否有未被读取的设置
+    fn check_unused(&self) -> Result<()> {
+        let read = self.read.borrow();
+        match self.file.keys().find(|key| !read.contains(*key)) {
+            Some(key) => anyhow::bail!("Unknown setting `{key}`"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// 把顶层设置转换为与环境变量相同的字符串形式，数组以逗号连接
+fn setting_value(key: &str, value: toml::Value) -> Result<String> {
+    match value {
+        toml::Value::String(value) => Ok(value),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(value.to_string()),
+        toml::Value::Array(values) => Ok(values
+            .into_iter()
+            .map(|value| match value {
+                toml::Value::Array(_) | toml::Value::Table(_) => {
+                    anyhow::bail!("Setting `{key}` must be an array of plain values")
+                }
+                value => setting_value(key, value),
+            })
+            .collect::<Result<Vec<_>>>()?
+            .join(",")),
+        toml::Value::Table(_) => anyhow::bail!("Unknown section [{key}]"),
+        toml::Value::Datetime(_) => {
+            anyhow::bail!("Setting `{key}` must be a string, number or boolean")
+        }
+    }
+}
+
+/// 设置引用了构建时未开启的 cargo feature 时返回错误
+fn require_feature(enabled: bool, feature: &str, setting: &str) -> Result<()> {
+    if !enabled {
+        anyhow::bail!("{setting} is set, but pluribus was built without the `{feature}` feature");
+    }
+    Ok(())
+}
+
+/// 解析 `model=ms,model=ms` 形式的按模型阈值
+fn parse_model_thresholds(value: &str) -> Result<Vec<(String, u64)>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (model, ms) = entry
+                .split_once('=')
+                .with_context(|| format!("missing '=' in {entry}"))?;
+            let ms = ms
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid threshold in {entry}"))?;
+            Ok((model.trim().to_string(), ms))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[test]
+    fn settings_for_disabled_features_fail_to_load() {
+        let settings = [
+            ("metrics_auth = true", "metrics", cfg!(feature = "metrics")),
+            (
+                "usage_db = \"usage.db\"",
+                "usage-sqlite",
+                cfg!(feature = "usage-sqlite"),
+            ),
+        ];
+        for (setting, feature, enabled) in settings {
+            let (_dir, result) = test_support::try_config(setting);
+            match result {
+                Ok(_) => assert!(enabled, "{setting} loaded without {feature}"),
+                Err(e) => {
+                    let message = format!("{e:#}");
+                    assert!(!enabled, "{setting}: {message}");
+                    assert!(message.contains(&format!("`{feature}`")), "{message}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn usage_history_is_on_by_default_only_with_its_feature() {
+        // extra 提到 usage_db 时 test_support 不写入 `usage_db = ""`，使用默认值
+        let (_dir, config) = test_support::config("# usage_db left unset");
+        assert_eq!(config.usage_db.is_some(), cfg!(feature = "usage-sqlite"));
+    }
+
+    #[test]
+    fn providers_dir_flag_takes_precedence_over_the_environment() {
+        let (dir, file_config) = test_support::config("");
+        let env = |name: &str| match name {
+            "PLURIBUS_PROVIDERS_DIR" => Ok("/env/providers".to_string()),
+            _ => Err(std::env::VarError::NotPresent),
+        };
+        let config = Config::from_file_with_env(&dir.path().join("pluribus.toml"), env).unwrap();
+        // 环境变量优先于配置文件中的 providers_dir
+        assert_ne!(file_config.providers_dir(), Path::new("/env/providers"));
+        assert_eq!(config.providers_dir(), Path::new("/env/providers"));
+
+        let unchanged = config.clone().with_providers_dir(None);
+        assert_eq!(unchanged.providers_dir(), Path::new("/env/providers"));
+        let overridden = config.with_providers_dir(Some(PathBuf::from("/cli/providers")));
+        assert_eq!(overridden.providers_dir(), Path::new("/cli/providers"));
+    }
+}
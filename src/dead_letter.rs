@@ -0,0 +1,168 @@
+//! 死信记录
+//!
+//! 在所有 provider 上都失败的请求追加到 JSONL 文件中，记录尝试链与各 provider 的错误，
+//! 客户端通过 `X-Pluribus-Dead-Letter: body` 选择保存请求体时可用
+//! `pluribus deadletter retry` 重新提交。超过保留天数的记录在写入与读取时被忽略，
+//! 文件超过大小上限时丢弃最早的记录。
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::providers::UpstreamError;
+
+/// 客户端选择保存请求体的请求头
+pub const DEAD_LETTER_HEADER: &str = "x-pluribus-dead-letter";
+
+const SECS_PER_DAY: u64 = 86_400;
+
+/// 一次失败的上游尝试
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailedAttempt {
+    pub provider: String,
+    /// 上游返回的状态码，连接失败等情况为空
+    pub status: Option<u16>,
+    pub error: String,
+}
+
+impl FailedAttempt {
+    pub fn from_error(provider: &str, err: &anyhow::Error) -> Self {
+        Self {
+            provider: provider.to_string(),
+            status: err
+                .downcast_ref::<UpstreamError>()
+                .map(|e| e.status.as_u16()),
+            error: format!("{:#}", err),
+        }
+    }
+}
+
+/// 单条死信
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// `{毫秒时间戳}-{请求 ID}`，跨重启唯一
+    pub id: String,
+    /// 失败时间 (Unix timestamp)
+    pub timestamp: u64,
+    pub request_id: u64,
+    pub client_key: Option<String>,
+    pub model: String,
+    /// 按顺序记录的失败尝试
+    pub attempts: Vec<FailedAttempt>,
+    /// 最终返回给客户端的错误
+    pub error: String,
+    /// 客户端选择保存时的请求体
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+}
+
+/// 死信文件，`max_bytes` 为 0 时关闭
+pub struct DeadLetterLog {
+    path: PathBuf,
+    max_bytes: u64,
+    retention_secs: u64,
+    lock: Mutex<()>,
+}
+
+impl DeadLetterLog {
+    pub fn new(path: PathBuf, max_bytes: u64, retention_days: u64) -> Self {
+        Self {
+            path,
+            max_bytes,
+            retention_secs: retention_days * SECS_PER_DAY,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+    /// 追加一条死信，超过大小上限时压缩文件
+    pub fn append(&self, entry: &DeadLetter, now_secs: u64) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let _guard = self
+            .lock
+            .lock()
+            .map_err(|_| anyhow::anyhow!("dead letter lock poisoned"))?;
+
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+        file.write_all(line.as_bytes())
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+
+        let size = file.metadata().map(|m| m.len()).unwrap_or_default();
+        if size > self.max_bytes {
+            self.compact(now_secs)?;
+        }
+        Ok(())
+    }
+
+    /// 去掉过期记录，并丢弃最早的记录直到文件不超过大小上限
+    fn compact(&self, now_secs: u64) -> Result<()> {
+        let content = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read {}", self.path.display()))?;
+        let cutoff = now_secs.saturating_sub(self.retention_secs);
+
+        let lines: Vec<&str> = content
+            .lines()
+            .filter(|line| {
+                serde_json::from_str::<DeadLetter>(line).is_ok_and(|e| e.timestamp >= cutoff)
+            })
+            .collect();
+        let mut size: u64 = lines.iter().map(|line| line.len() as u64 + 1).sum();
+        let mut dropped = 0;
+        while size > self.max_bytes && dropped < lines.len() {
+            size -= lines[dropped].len() as u64 + 1;
+            dropped += 1;
+        }
+        let lines = &lines[dropped..];
+        if dropped > 0 {
+            tracing::warn!(dropped, "dead letter file full, dropped oldest entries");
+        }
+
+        let mut content = lines.join("\n");
+        if !content.is_empty() {
+            content.push('\n');
+        }
+        std::fs::write(&self.path, content)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+/// 读取未过期的死信，文件不存在时返回空列表
+pub fn read_dead_letters(
+    path: &Path,
+    retention_days: u64,
+    now_secs: u64,
+) -> Result<Vec<DeadLetter>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let cutoff = now_secs.saturating_sub(retention_days * SECS_PER_DAY);
+
+    Ok(content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str::<DeadLetter>(line) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                tracing::warn!("Skipping malformed dead letter entry: {}", e);
+                None
+            }
+        })
+        .filter(|entry| entry.timestamp >= cutoff)
+        .collect())
+}
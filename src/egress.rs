@@ -0,0 +1,225 @@
+//! 出站连接白名单
+//!
+//! 默认只允许访问 Anthropic API / OAuth 及 npm registry 的域名，其他域名需要在
+//! `PLURIBUS_EGRESS_ALLOW` 中显式声明（逗号分隔，支持 `*.example.com` 形式的子域名通配）。
+//!
+//! 在加载配置时校验所有可配置的地址，并在发送请求和跟随重定向时再次校验。
+
+use anyhow::Result;
+use reqwest::{redirect, Url};
+use std::sync::LazyLock;
+
+/// 默认允许的域名
+pub const DEFAULT_ALLOWED_HOSTS: &[&str] = &[
+    "api.anthropic.com",
+    "console.anthropic.com",
+    "claude.ai",
+    "registry.npmjs.org",
+];
+
+/// 最多跟随的重定向次数
+const MAX_REDIRECTS: usize = 10;
+
+/// 出站白名单：默认域名加上额外声明的域名模式
+struct Allowlist {
+    extra: Vec<String>,
+}
+
+impl Allowlist {
+    /// 解析逗号分隔的额外域名模式
+    fn parse(value: &str) -> Self {
+        Self {
+            extra: value
+                .split(',')
+                .map(|host| host.trim().to_ascii_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect(),
+        }
+    }
+
+    /// 域名是否在白名单中
+    fn allows(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        // 测试中的 mock 上游监听在本机
+        if cfg!(test) && host == "127.0.0.1" {
+            return true;
+        }
+        DEFAULT_ALLOWED_HOSTS
+            .iter()
+            .copied()
+            .chain(self.extra.iter().map(String::as_str))
+            .any(|pattern| host_matches(pattern, &host))
+    }
+
+    fn check(&self, url: &Url) -> Result<()> {
+        let host = url
+            .host_str()
+            .ok_or_else(|| anyhow::anyhow!("URL has no host: {url}"))?;
+        if !self.allows(host) {
+            anyhow::bail!(
+                "Outbound host '{host}' is not allowed. Add it to PLURIBUS_EGRESS_ALLOW to permit connections to {url}"
+            );
+        }
+        Ok(())
+    }
+}
+
+static ALLOWLIST: LazyLock<Allowlist> =
+    LazyLock::new(|| Allowlist::parse(&std::env::var("PLURIBUS_EGRESS_ALLOW").unwrap_or_default()));
+
+/// 域名是否匹配模式（`*.example.com` 匹配所有子域名，不含 `example.com` 本身）
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => host
+            .strip_suffix(suffix)
+            .is_some_and(|prefix| prefix.ends_with('.')),
+        None => pattern == host,
+    }
+}
+
+/// 校验 URL 的域名在白名单中
+pub fn check_url(url: &Url) -> Result<()> {
+    ALLOWLIST.check(url)
+}
+
+/// 只跟随指向白名单域名的重定向
+pub fn redirect_policy() -> redirect::Policy {
+    redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            return attempt.error("too many redirects");
+        }
+        match check_url(attempt.url()) {
+            Ok(()) => attempt.follow(),
+            Err(e) => {
+                tracing::warn!(url = %attempt.url(), "blocked redirect to disallowed host");
+                attempt.error(e.to_string())
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::path;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn url(value: &str) -> Url {
+        Url::parse(value).unwrap()
+    }
+
+    #[test]
+    fn default_hosts_are_allowed() {
+        let allowlist = Allowlist::parse("");
+        for host in DEFAULT_ALLOWED_HOSTS {
+            assert!(allowlist.allows(host), "{host}");
+        }
+        // 大小写与末尾的点不影响匹配
+        assert!(allowlist.allows("API.Anthropic.com."));
+        assert!(!allowlist.allows("anthropic.com"));
+        assert!(!allowlist.allows("api.anthropic.com.evil.example"));
+    }
+
+    #[test]
+    fn extra_patterns_extend_the_allowlist() {
+        let allowlist = Allowlist::parse(" Proxy.Example.com , *.corp.internal,,");
+        assert!(allowlist.allows("proxy.example.com"));
+        assert!(!allowlist.allows("other.example.com"));
+        assert!(allowlist.allows("llm.corp.internal"));
+        assert!(allowlist.allows("a.b.corp.internal"));
+        // 通配只匹配子域名
+        assert!(!allowlist.allows("corp.internal"));
+        assert!(!allowlist.allows("evilcorp.internal"));
+    }
+
+    #[test]
+    fn check_url_rejects_private_and_unlisted_hosts() {
+        let allowlist = Allowlist::parse("gateway.example.com");
+        assert!(allowlist
+            .check(&url("https://api.anthropic.com/v1/messages"))
+            .is_ok());
+        assert!(allowlist
+            .check(&url("https://gateway.example.com/v1"))
+            .is_ok());
+        for rejected in [
+            "http://10.0.0.1/v1/messages",
+            "http://192.168.1.10/",
+            "http://169.254.169.254/latest/meta-data",
+            "http://localhost:8080/",
+            "http://[::1]/",
+            "https://example.com/",
+        ] {
+            let err = allowlist.check(&url(rejected)).unwrap_err().to_string();
+            assert!(err.contains("PLURIBUS_EGRESS_ALLOW"), "{rejected}: {err}");
+        }
+        assert!(allowlist.check(&url("data:text/plain,hi")).is_err());
+    }
+
+    fn client() -> reqwest::Client {
+        reqwest::Client::builder()
+            .redirect(redirect_policy())
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn redirects_to_disallowed_hosts_are_blocked() {
+        let server = MockServer::start().await;
+        Mock::given(path("/escape"))
+            .respond_with(
+                ResponseTemplate::new(302).insert_header("location", "http://10.0.0.1/metadata"),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(path("/moved"))
+            .respond_with(
+                ResponseTemplate::new(302)
+                    .insert_header("location", format!("{}/target", server.uri())),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(path("/target"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        let err = client()
+            .get(format!("{}/escape", server.uri()))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(err.is_redirect(), "{err:?}");
+        assert!(format!("{err:?}").contains("10.0.0.1"), "{err:?}");
+
+        // 指向白名单内域名的重定向照常跟随
+        let response = client()
+            .get(format!("{}/moved", server.uri()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn redirect_loops_stop() {
+        let server = MockServer::start().await;
+        Mock::given(path("/loop"))
+            .respond_with(
+                ResponseTemplate::new(302)
+                    .insert_header("location", format!("{}/loop", server.uri())),
+            )
+            .mount(&server)
+            .await;
+
+        let err = client()
+            .get(format!("{}/loop", server.uri()))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(err.is_redirect(), "{err:?}");
+        assert_eq!(
+            server.received_requests().await.unwrap().len(),
+            MAX_REDIRECTS
+        );
+    }
+}
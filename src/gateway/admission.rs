@@ -0,0 +1,527 @@
+//! 按优先级的请求准入
+//!
+//! 限制同时转发的请求数，超出时按优先级排队：高优先级的等待者先获得许可，但每个等待者
+//! 最多被后来的高优先级请求插队 [`FAIRNESS_BOUND`] 次，之后按到达顺序优先处理，避免低优先级
+//! 请求饿死。队列已满时先丢弃排队中优先级最低、最晚到达的请求；没有比新请求优先级更低的
+//! 等待者时拒绝新请求。
+//!
+//! [`AdmissionScheduler::FairShare`] 时改为在有排队请求的密钥之间轮流发放许可（同一密钥内
+//! 仍按优先级），单个密钥最多同时持有 `max_share_percent` 的许可，超出时即使有空闲许可也要
+//! 排队，为其他密钥留出余量。队列已满时丢弃排队最多的密钥最晚到达的请求。
+//!
+//! 许可在请求结束时释放（流式响应在流结束时释放）。
+
+use axum::http::HeaderMap;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::oneshot;
+
+use crate::config::AdmissionScheduler;
+use crate::keys::{ClientKey, Priority};
+use crate::metrics::{ADMISSION_WAIT, SHED_REQUESTS};
+
+/// 请求优先级 header：`high` | `normal` | `low`
+pub const PRIORITY_HEADER: &str = "x-pluribus-priority";
+
+/// 等待者最多被插队的次数
+const FAIRNESS_BOUND: u32 = 4;
+
+/// 请求的优先级：header 指定的优先级不超过密钥允许的最高优先级，未指定时为 normal
+pub fn requested_priority(
+    headers: &HeaderMap,
+    client: Option<&ClientKey>,
+) -> Result<Priority, String> {
+    let requested = match headers.get(PRIORITY_HEADER) {
+        None => Priority::Normal,
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(Priority::parse)
+            .ok_or_else(|| {
+                format!("Invalid {PRIORITY_HEADER} value, expected high, normal or low")
+            })?,
+    };
+    let max = client.map_or(Priority::Normal, |c| c.policy.max_priority());
+    if requested > max {
+        tracing::debug!(
+            requested = requested.as_str(),
+            max = max.as_str(),
+            "priority capped by key policy"
+        );
+    }
+    Ok(requested.min(max))
+}
+
+/// 请求被拒绝或被丢弃
+#[derive(Debug)]
+pub struct Overloaded;
+
+struct Waiter {
+    key: String,
+    priority: Priority,
+    /// 被后来的高优先级请求插队的次数
+    skipped: u32,
+    grant: oneshot::Sender<Permit>,
+}
+
+/// 公平调度下单个密钥的状态
+#[derive(Default)]
+struct KeyState {
+    running: usize,
+    /// 最近一次获得许可的序号，越小越先轮到
+    last_grant: u64,
+}
+
+#[derive(Default)]
+struct Queue {
+    running: usize,
+    /// 按到达顺序排列
+    waiters: Vec<Waiter>,
+    /// 公平调度时持有许可或排队中的密钥
+    keys: HashMap<String, KeyState>,
+    /// 已发放的许可数，用作轮转序号
+    grants: u64,
+}
+
+impl Queue {
+    /// 选出下一个获得许可的等待者
+    fn next_waiter(&mut self) -> Option<Waiter> {
+        let index = match self
+            .waiters
+            .iter()
+            .position(|w| w.skipped >= FAIRNESS_BOUND)
+        {
+            Some(index) => index,
+            None => {
+                let best = self.waiters.iter().map(|w| w.priority).max()?;
+                let index = self.waiters.iter().position(|w| w.priority == best)?;
+                for waiter in &mut self.waiters[..index] {
+                    waiter.skipped += 1;
+                }
+                index
+            }
+        };
+        Some(self.waiters.remove(index))
+    }
+
+    /// 公平调度下选出下一个获得许可的等待者：未达份额上限的密钥中最久未获得许可的一个，
+    /// 取其优先级最高、最早到达的请求
+    fn next_fair_waiter(&mut self, key_limit: usize) -> Option<Waiter> {
+        let key = self
+            .waiters
+            .iter()
+            .filter(|w| self.key_running(&w.key) < key_limit)
+            .map(|w| &w.key)
+            .min_by_key(|key| self.keys.get(*key).map_or(0, |k| k.last_grant))?
+            .clone();
+        let best = self
+            .waiters
+            .iter()
+            .filter(|w| w.key == key)
+            .map(|w| w.priority)
+            .max()?;
+        let index = self
+            .waiters
+            .iter()
+            .position(|w| w.key == key && w.priority == best)?;
+        Some(self.waiters.remove(index))
+    }
+
+    fn key_running(&self, key: &str) -> usize {
+        self.keys.get(key).map_or(0, |k| k.running)
+    }
+
+    /// 是否有未达份额上限的密钥在排队
+    fn has_eligible_waiter(&self, key_limit: usize) -> bool {
+        self.waiters
+            .iter()
+            .any(|w| self.key_running(&w.key) < key_limit)
+    }
+
+    /// 记录发放给 `key` 的许可
+    fn grant(&mut self, key: Option<&str>) {
+        self.running += 1;
+        if let Some(key) = key {
+            self.grants += 1;
+            let state = self.keys.entry(key.to_string()).or_default();
+            state.running += 1;
+            state.last_grant = self.grants;
+        }
+    }
+
+    /// 收回 `key` 持有的许可，密钥不再持有许可且没有排队请求时移除其状态
+    fn revoke(&mut self, key: Option<&str>) {
+        self.running = self.running.saturating_sub(1);
+        let Some(key) = key else {
+            return;
+        };
+        let idle = match self.keys.get_mut(key) {
+            Some(state) => {
+                state.running = state.running.saturating_sub(1);
+                state.running == 0
+            }
+            None => false,
+        };
+        if idle && !self.waiters.iter().any(|w| w.key == key) {
+            self.keys.remove(key);
+        }
+    }
+
+    /// 队列已满时选出可丢弃的等待者：优先级低于 `priority` 中最低、最晚到达的
+    fn shed_candidate(&self, priority: Priority) -> Option<usize> {
+        self.waiters
+            .iter()
+            .enumerate()
+            .filter(|(_, w)| w.priority < priority)
+            .min_by_key(|(index, w)| (w.priority, std::cmp::Reverse(*index)))
+            .map(|(index, _)| index)
+    }
+
+    /// 公平调度下队列已满时选出可丢弃的等待者：排队数多于 `key` 的密钥中排队最多的一个
+    /// 最晚到达的请求
+    fn fair_shed_candidate(&self, key: &str) -> Option<usize> {
+        let mut queued: HashMap<&str, usize> = HashMap::new();
+        for waiter in &self.waiters {
+            *queued.entry(waiter.key.as_str()).or_default() += 1;
+        }
+        let own = queued.get(key).copied().unwrap_or(0);
+        let (busiest, count) = queued.into_iter().max_by_key(|(_, count)| *count)?;
+        if count <= own + 1 {
+            return None;
+        }
+        self.waiters.iter().rposition(|w| w.key == busiest)
+    }
+}
+
+/// 并发许可，drop 时释放并唤醒下一个等待者
+pub struct Permit {
+    admission: Option<Arc<Admission>>,
+    /// 公平调度时持有许可的密钥
+    key: Option<String>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(admission) = self.admission.take() {
+            admission.release(self.key.take());
+        }
+    }
+}
+
+/// 请求准入控制，`max_concurrent` 为 0 时不限制
+pub struct Admission {
+    max_concurrent: usize,
+    max_queued: usize,
+    scheduler: AdmissionScheduler,
+    /// 公平调度时单个密钥最多同时持有的许可数
+    key_limit: usize,
+    queue: Mutex<Queue>,
+}
+
+impl Admission {
+    pub fn new(
+        max_concurrent: usize,
+        max_queued: usize,
+        scheduler: AdmissionScheduler,
+        max_share_percent: u8,
+    ) -> Self {
+        let key_limit = (max_concurrent * usize::from(max_share_percent.min(100)) / 100).max(1);
+        Self {
+            max_concurrent,
+            max_queued,
+            scheduler,
+            key_limit,
+            queue: Mutex::new(Queue::default()),
+        }
+    }
+
+    fn is_fair(&self) -> bool {
+        self.scheduler == AdmissionScheduler::FairShare
+    }
+
+    /// 获取许可，需要排队时等待；请求被拒绝或在排队中被丢弃时返回 [`Overloaded`]
+    ///
+    /// `key` 为发起请求的密钥名称，用于公平调度与等待时长统计。
+    pub async fn acquire(
+        self: &Arc<Self>,
+        key: &str,
+        priority: Priority,
+    ) -> Result<Permit, Overloaded> {
+        if self.max_concurrent == 0 {
+            return Ok(Permit {
+                admission: None,
+                key: None,
+            });
+        }
+
+        let start = Instant::now();
+        let permit_key = self.is_fair().then(|| key.to_string());
+        let granted = {
+            let Ok(mut queue) = self.queue.lock() else {
+                return Err(Overloaded);
+            };
+            let available = if self.is_fair() {
+                queue.running < self.max_concurrent
+                    && queue.key_running(key) < self.key_limit
+                    && !queue.has_eligible_waiter(self.key_limit)
+            } else {
+                queue.running < self.max_concurrent && queue.waiters.is_empty()
+            };
+            if available {
+                queue.grant(permit_key.as_deref());
+                ADMISSION_WAIT.with_label_values(&[key]).observe(0.0);
+                return Ok(Permit {
+                    admission: Some(self.clone()),
+                    key: permit_key,
+                });
+            }
+
+            if queue.waiters.len() >= self.max_queued {
+                let candidate = if self.is_fair() {
+                    queue.fair_shed_candidate(key)
+                } else {
+                    queue.shed_candidate(priority)
+                };
+                let Some(index) = candidate else {
+                    SHED_REQUESTS.with_label_values(&[priority.as_str()]).inc();
+                    tracing::warn!(
+                        key,
+                        priority = priority.as_str(),
+                        "queue full, request shed"
+                    );
+                    return Err(Overloaded);
+                };
+                // 被丢弃的等待者在发送端 drop 后收到错误
+                let shed = queue.waiters.remove(index);
+                SHED_REQUESTS
+                    .with_label_values(&[shed.priority.as_str()])
+                    .inc();
+                tracing::warn!(
+                    key = shed.key,
+                    priority = shed.priority.as_str(),
+                    by = priority.as_str(),
+                    "queued request shed for another request"
+                );
+                if self.is_fair() && queue.key_running(&shed.key) == 0 {
+                    let shed_key = shed.key.clone();
+                    if !queue.waiters.iter().any(|w| w.key == shed_key) {
+                        queue.keys.remove(&shed_key);
+                    }
+                }
+            }
+
+            if let Some(key) = &permit_key {
+                queue.keys.entry(key.clone()).or_default();
+            }
+            let (grant, granted) = oneshot::channel();
+            queue.waiters.push(Waiter {
+                key: key.to_string(),
+                priority,
+                skipped: 0,
+                grant,
+            });
+            granted
+        };
+
+        let permit = granted.await.map_err(|_| Overloaded)?;
+        ADMISSION_WAIT
+            .with_label_values(&[key])
+            .observe(start.elapsed().as_secs_f64());
+        Ok(permit)
+    }
+
+    fn release(self: Arc<Self>, key: Option<String>) {
+        let Ok(mut queue) = self.queue.lock() else {
+            return;
+        };
+        queue.revoke(key.as_deref());
+
+        while queue.running < self.max_concurrent {
+            let waiter = if self.is_fair() {
+                queue.next_fair_waiter(self.key_limit)
+            } else {
+                queue.next_waiter()
+            };
+            let Some(waiter) = waiter else {
+                break;
+            };
+            let permit_key = self.is_fair().then(|| waiter.key.clone());
+            queue.grant(permit_key.as_deref());
+            let permit = Permit {
+                admission: Some(self.clone()),
+                key: permit_key,
+            };
+            // 等待者已放弃（客户端断开），收回许可交给下一个；不能让许可在持有锁时 drop
+            if let Err(mut permit) = waiter.grant.send(permit) {
+                permit.admission = None;
+                queue.revoke(permit.key.take().as_deref());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::task::JoinHandle;
+
+    fn queued(admission: &Admission) -> usize {
+        admission.queue.lock().unwrap().waiters.len()
+    }
+
+    /// 等待所有已启动的请求进入队列或获得许可
+    async fn settle(admission: &Admission, waiters: usize) {
+        for _ in 0..100 {
+            if queued(admission) == waiters {
+                return;
+            }
+            tokio::task::yield_now().await;
+        }
+        panic!(
+            "expected {waiters} queued requests, found {}",
+            queued(admission)
+        );
+    }
+
+    /// 排队获取许可，获得后记录 `name` 并立即释放
+    fn request(
+        admission: &Arc<Admission>,
+        order: &Arc<Mutex<Vec<&'static str>>>,
+        name: &'static str,
+        priority: Priority,
+    ) -> JoinHandle<Result<(), Overloaded>> {
+        let admission = admission.clone();
+        let order = order.clone();
+        tokio::spawn(async move {
+            let _permit = admission.acquire(name, priority).await?;
+            order.lock().unwrap().push(name);
+            Ok(())
+        })
+    }
+
+    #[tokio::test]
+    async fn higher_priority_is_admitted_first_and_low_is_shed() {
+        let admission = Arc::new(Admission::new(1, 2, AdmissionScheduler::Priority, 100));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let running = admission
+            .acquire("running", Priority::Normal)
+            .await
+            .unwrap();
+
+        let low = request(&admission, &order, "low", Priority::Low);
+        settle(&admission, 1).await;
+        let normal = request(&admission, &order, "normal", Priority::Normal);
+        settle(&admission, 2).await;
+        // 队列已满：高优先级请求挤掉排队中的低优先级请求
+        let high = request(&admission, &order, "high", Priority::High);
+        settle(&admission, 2).await;
+        assert!(low.await.unwrap().is_err());
+        // 没有更低优先级的等待者时拒绝新请求
+        let rejected = admission.acquire("late", Priority::Low).await;
+        assert!(rejected.is_err());
+
+        drop(running);
+        high.await.unwrap().unwrap();
+        normal.await.unwrap().unwrap();
+        assert_eq!(*order.lock().unwrap(), ["high", "normal"]);
+        assert_eq!(admission.queue.lock().unwrap().running, 0);
+    }
+
+    #[tokio::test]
+    async fn waiters_are_not_starved_by_later_high_priority() {
+        let admission = Arc::new(Admission::new(1, 16, AdmissionScheduler::Priority, 100));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let running = admission
+            .acquire("running", Priority::Normal)
+            .await
+            .unwrap();
+
+        let mut handles = vec![request(&admission, &order, "low", Priority::Low)];
+        settle(&admission, 1).await;
+        for (i, name) in ["h1", "h2", "h3", "h4", "h5", "h6"].into_iter().enumerate() {
+            handles.push(request(&admission, &order, name, Priority::High));
+            settle(&admission, i + 2).await;
+        }
+
+        drop(running);
+        for handle in handles {
+            handle.await.unwrap().unwrap();
+        }
+        // low 被插队 FAIRNESS_BOUND 次后优先处理
+        assert_eq!(
+            *order.lock().unwrap(),
+            ["h1", "h2", "h3", "h4", "low", "h5", "h6"]
+        );
+    }
+
+    fn holding(admission: &Admission, key: &str) -> usize {
+        admission.queue.lock().unwrap().key_running(key)
+    }
+
+    #[tokio::test]
+    async fn one_key_cannot_exceed_its_fair_share() {
+        // 4 个许可，单个密钥最多持有 50%
+        let admission = Arc::new(Admission::new(4, 16, AdmissionScheduler::FairShare, 50));
+        let mut greedy = vec![
+            admission.acquire("greedy", Priority::Normal).await.unwrap(),
+            admission.acquire("greedy", Priority::Normal).await.unwrap(),
+        ];
+
+        // 仍有空闲许可，但 greedy 已达份额上限，只能排队
+        let queued_greedy = {
+            let admission = admission.clone();
+            tokio::spawn(async move { admission.acquire("greedy", Priority::High).await })
+        };
+        settle(&admission, 1).await;
+        assert_eq!(holding(&admission, "greedy"), 2);
+
+        // 其他密钥不必等待
+        let other = admission.acquire("other", Priority::Low).await.unwrap();
+        assert_eq!(holding(&admission, "other"), 1);
+        assert_eq!(queued(&admission), 1);
+
+        // greedy 释放一个许可后排队的请求才获得许可，持有数始终不超过份额
+        drop(greedy.pop());
+        greedy.push(queued_greedy.await.unwrap().unwrap());
+        assert_eq!(holding(&admission, "greedy"), 2);
+        assert_eq!(queued(&admission), 0);
+
+        drop(other);
+        drop(greedy);
+        assert_eq!(admission.queue.lock().unwrap().running, 0);
+        assert!(admission.queue.lock().unwrap().keys.is_empty());
+    }
+
+    #[tokio::test]
+    async fn fair_share_takes_turns_between_waiting_keys() {
+        let admission = Arc::new(Admission::new(2, 16, AdmissionScheduler::FairShare, 100));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let running = [
+            admission
+                .acquire("running", Priority::Normal)
+                .await
+                .unwrap(),
+            admission
+                .acquire("running", Priority::Normal)
+                .await
+                .unwrap(),
+        ];
+
+        let mut handles = Vec::new();
+        for (i, key) in ["busy", "busy", "busy", "quiet"].into_iter().enumerate() {
+            handles.push(request(&admission, &order, key, Priority::Normal));
+            settle(&admission, i + 1).await;
+        }
+        let [first, _second] = running;
+        // 只留一个许可，按获得顺序依次处理
+        drop(first);
+        for handle in handles {
+            handle.await.unwrap().unwrap();
+        }
+        // quiet 晚到，但不会排在 busy 的全部请求之后
+        let order = order.lock().unwrap();
+        let quiet = order.iter().position(|key| *key == "quiet").unwrap();
+        assert!(quiet <= 1, "{order:?}");
+    }
+}
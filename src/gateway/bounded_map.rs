@@ -0,0 +1,282 @@
+//! 有容量上限与过期时间的内存映射
+//!
+//! 按会话、tool_use id 等无界键记录的状态都放在 [`BoundedMap`] 中：超过容量时淘汰最久未
+//! 使用的条目，配置了过期时间时，超过该时长未写入的条目视为不存在并在之后被清理。
+//! 淘汰与过期按映射名称计入 `bounded_map_evictions_total`，用于判断容量设置是否合适。
+//!
+//! 时间由调用方传入 (Unix timestamp)，不加锁，由持有者自行同步。
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+use crate::metrics::BOUNDED_MAP_EVICTIONS;
+
+struct Slot<V> {
+    value: V,
+    /// 在 `order` 中的序号，越小越久未使用
+    tick: u64,
+    /// 最近一次写入的时间
+    written_at: u64,
+}
+
+/// 带 LRU 淘汰与过期时间的映射
+pub struct BoundedMap<K, V> {
+    name: &'static str,
+    capacity: usize,
+    /// 写入后的有效时长（秒），0 表示不过期
+    ttl_secs: u64,
+    entries: HashMap<K, Slot<V>>,
+    order: BTreeMap<u64, K>,
+    next_tick: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> BoundedMap<K, V> {
+    /// `name` 用作指标标签；`capacity` 至少为 1
+    pub fn new(name: &'static str, capacity: usize, ttl_secs: u64) -> Self {
+        Self {
+            name,
+            capacity: capacity.max(1),
+            ttl_secs,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            next_tick: 0,
+        }
+    }
+
+    fn is_expired(&self, slot: &Slot<V>, now_secs: u64) -> bool {
+        self.ttl_secs > 0 && now_secs >= slot.written_at.saturating_add(self.ttl_secs)
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        tick
+    }
+
+    /// 移到最近使用的位置
+    fn touch(&mut self, key: &K) {
+        let tick = self.next_tick();
+        if let Some(slot) = self.entries.get_mut(key) {
+            self.order.remove(&slot.tick);
+            slot.tick = tick;
+            self.order.insert(tick, key.clone());
+        }
+    }
+
+    fn evict(&mut self, key: &K, reason: &str) {
+        if let Some(slot) = self.entries.remove(key) {
+            self.order.remove(&slot.tick);
+            BOUNDED_MAP_EVICTIONS
+                .with_label_values(&[self.name, reason])
+                .inc();
+        }
+    }
+
+    /// 清理最久未使用一端的过期条目，再按容量淘汰，为一个新条目腾出位置
+    fn make_room(&mut self, now_secs: u64) {
+        while let Some((_, key)) = self.order.first_key_value() {
+            let key = key.clone();
+            let expired = self
+                .entries
+                .get(&key)
+                .is_some_and(|slot| self.is_expired(slot, now_secs));
+            if expired {
+                self.evict(&key, "expired");
+            } else if self.entries.len() >= self.capacity {
+                self.evict(&key, "capacity");
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// 未过期的条目，不改变使用顺序
+    pub fn peek(&self, key: &K, now_secs: u64) -> Option<&V> {
+        self.entries
+            .get(key)
+            .filter(|slot| !self.is_expired(slot, now_secs))
+            .map(|slot| &slot.value)
+    }
+
+    /// 写入条目并刷新过期时间，已过期的旧值被替换为 `default()`
+    pub fn upsert_with(&mut self, key: K, now_secs: u64, default: impl FnOnce() -> V) -> &mut V {
+        if self
+            .entries
+            .get(&key)
+            .is_some_and(|slot| self.is_expired(slot, now_secs))
+        {
+            self.evict(&key, "expired");
+        }
+        if self.entries.contains_key(&key) {
+            self.touch(&key);
+        } else {
+            self.make_room(now_secs);
+            let tick = self.next_tick();
+            self.order.insert(tick, key.clone());
+            self.entries.insert(
+                key.clone(),
+                Slot {
+                    value: default(),
+                    tick,
+                    written_at: now_secs,
+                },
+            );
+        }
+        let slot = self.entries.get_mut(&key).expect("entry inserted above");
+        slot.written_at = now_secs;
+        &mut slot.value
+    }
+
+    /// 写入条目并刷新过期时间
+    pub fn insert(&mut self, key: K, value: V, now_secs: u64) {
+        let mut value = Some(value);
+        let slot = self.upsert_with(key, now_secs, || value.take().expect("value taken once"));
+        if let Some(value) = value {
+            *slot = value;
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let slot = self.entries.remove(key)?;
+        self.order.remove(&slot.tick);
+        Some(slot.value)
+    }
+
+    /// 未过期的条目
+    pub fn iter(&self, now_secs: u64) -> impl Iterator<Item = (&K, &V)> {
+        self.entries
+            .iter()
+            .filter(move |(_, slot)| !self.is_expired(slot, now_secs))
+            .map(|(key, slot)| (key, &slot.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 检查淘汰计数；关闭 `metrics` feature 时计数器不记录，不检查
+    fn assert_evictions(name: &str, reason: &str, expected: u64) {
+        #[cfg(feature = "metrics")]
+        assert_eq!(
+            BOUNDED_MAP_EVICTIONS
+                .with_label_values(&[name, reason])
+                .get(),
+            expected,
+            "{name} evictions by {reason}"
+        );
+        #[cfg(not(feature = "metrics"))]
+        let _ = (name, reason, expected);
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_at_capacity() {
+        let mut map = BoundedMap::new("test_capacity", 2, 0);
+        map.insert("a", 1, 0);
+        map.insert("b", 2, 0);
+        // 写入 a 使其成为最近使用的条目，b 被淘汰
+        *map.upsert_with("a", 0, || 0) += 10;
+        map.insert("c", 3, 0);
+
+        assert_eq!(map.peek(&"a", 0), Some(&11));
+        assert_eq!(map.peek(&"b", 0), None);
+        assert_eq!(map.peek(&"c", 0), Some(&3));
+        assert_eq!(map.entries.len(), 2);
+        assert_eq!(map.order.len(), 2);
+        assert_evictions("test_capacity", "capacity", 1);
+
+        // 容量至少为 1
+        let mut single = BoundedMap::new("test_single", 0, 0);
+        single.insert("a", 1, 0);
+        single.insert("b", 2, 0);
+        assert_eq!(single.entries.len(), 1);
+        assert_eq!(single.peek(&"b", 0), Some(&2));
+    }
+
+    #[test]
+    fn entries_expire_after_the_ttl() {
+        let mut map = BoundedMap::new("test_ttl", 2, 10);
+        map.insert("a", 1, 0);
+        map.insert("b", 2, 5);
+
+        assert_eq!(map.peek(&"a", 9), Some(&1));
+        assert_eq!(map.peek(&"a", 10), None);
+        let live: Vec<_> = map.iter(10).map(|(key, _)| *key).collect();
+        assert_eq!(live, ["b"]);
+
+        // 为新条目腾出位置时先清理过期条目，不淘汰未过期的 b
+        map.insert("c", 3, 12);
+        assert_eq!(map.entries.len(), 2);
+        assert_eq!(map.peek(&"b", 12), Some(&2));
+        assert_evictions("test_ttl", "expired", 1);
+        assert_evictions("test_ttl", "capacity", 0);
+
+        // 过期的旧值被替换为默认值，写入刷新过期时间
+        assert_eq!(*map.upsert_with("b", 15, || 0), 0);
+        assert_eq!(map.peek(&"b", 24), Some(&0));
+    }
+
+    #[test]
+    fn zero_ttl_never_expires() {
+        let mut map = BoundedMap::new("test_no_ttl", 4, 0);
+        map.insert("a", 1, 0);
+        assert_eq!(map.peek(&"a", u64::MAX), Some(&1));
+        assert_eq!(map.remove(&"a"), Some(1));
+        assert!(map.order.is_empty());
+    }
+
+    /// 写入 `keys` 个不重复的键，期间大小始终不超过容量，且淘汰数与写入数对得上
+    fn churn(name: &'static str, keys: u64) {
+        const CAPACITY: usize = 10_000;
+        const TTL_SECS: u64 = 60;
+        let mut map = BoundedMap::new(name, CAPACITY, TTL_SECS);
+        let half = keys / 2;
+
+        // 每秒写入 1000 个键：过期前已写满，按容量淘汰
+        for i in 0..half {
+            map.insert(format!("session-{i}"), i, i / 1000);
+            assert!(map.entries.len() <= CAPACITY);
+            assert_eq!(map.entries.len(), map.order.len());
+        }
+        assert_eq!(map.entries.len(), CAPACITY);
+
+        // 每秒写入 100 个键：条目先过期，存活数量回落到 TTL 内的写入量
+        let start = half / 1000 + TTL_SECS;
+        for i in half..keys {
+            map.insert(format!("session-{i}"), i, start + (i - half) / 100);
+            assert!(map.entries.len() <= CAPACITY);
+            assert_eq!(map.entries.len(), map.order.len());
+        }
+        let live = map.entries.len();
+        assert!(live <= 100 * TTL_SECS as usize, "{live}");
+
+        let last = keys - 1;
+        let now = start + (last - half) / 100;
+        assert_eq!(map.peek(&format!("session-{last}"), now), Some(&last));
+        assert_eq!(map.peek(&"session-0".to_string(), now), None);
+        #[cfg(feature = "metrics")]
+        {
+            let count = |reason| {
+                BOUNDED_MAP_EVICTIONS
+                    .with_label_values(&[name, reason])
+                    .get()
+            };
+            assert!(count("capacity") >= half - CAPACITY as u64);
+            assert!(count("expired") > 0);
+            assert_eq!(count("capacity") + count("expired"), keys - live as u64);
+        }
+    }
+
+    #[test]
+    fn churn_stays_bounded() {
+        churn("test_churn", 100_000);
+    }
+
+    /// 数百万个键的淘汰压力测试：
+    /// `cargo test --release -- --ignored stress_`
+    #[test]
+    #[ignore]
+    fn stress_millions_of_keys_stay_bounded() {
+        churn("test_stress", 5_000_000);
+    }
+}
@@ -0,0 +1,362 @@
+//! Provider token 预算
+//!
+//! 按 provider 统计当日 / 当月（UTC）token 用量，跨过 `[alerts]` 中配置的阈值时
+//! 记录 WARN / ERROR 日志；每月配额用尽后在当月剩余时间内不再选择该 provider。
+//! 用量只保存在内存中，重启后从零开始统计。按 provider ID 记录，名称只用于日志。
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::providers::{AlertsConfig, Provider};
+use crate::utils::civil_from_days;
+
+/// 告警级别，按严重程度排序
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum AlertLevel {
+    Normal,
+    Warn,
+    Critical,
+}
+
+impl AlertLevel {
+    fn from_utilization(utilization: f64, alerts: &AlertsConfig) -> Self {
+        if utilization >= alerts.daily_token_critical_threshold {
+            AlertLevel::Critical
+        } else if utilization >= alerts.daily_token_warn_threshold {
+            AlertLevel::Warn
+        } else {
+            AlertLevel::Normal
+        }
+    }
+}
+
+/// 统计周期：当日或当月
+#[derive(Debug, Default)]
+struct Period {
+    /// 周期标识：自 1970-01-01 起的天数，或 `年 * 12 + 月`
+    key: i64,
+    tokens: u64,
+    level: Option<AlertLevel>,
+}
+
+impl Period {
+    /// 进入新周期时清零
+    fn roll_over(&mut self, key: i64) -> bool {
+        if self.key == key {
+            return false;
+        }
+        *self = Period {
+            key,
+            ..Default::default()
+        };
+        true
+    }
+
+    fn utilization(&self, limit: Option<u64>) -> Option<f64> {
+        limit
+            .filter(|limit| *limit > 0)
+            .map(|limit| self.tokens as f64 / limit as f64)
+    }
+}
+
+/// 单个 provider 的预算状态
+struct ProviderBudget {
+    name: String,
+    alerts: AlertsConfig,
+    day: Period,
+    month: Period,
+}
+
+/// 当前时间所在的日 / 月周期标识
+fn period_keys(now_secs: u64) -> (i64, i64) {
+    let days = (now_secs / 86_400) as i64;
+    let (year, month, _) = civil_from_days(days);
+    (days, year * 12 + month)
+}
+
+/// `/health/details` 中展示的预算状态
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetStatus {
+    pub daily_tokens: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_budget: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_remaining: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_utilization: Option<f64>,
+    pub monthly_tokens: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monthly_quota: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monthly_remaining: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monthly_utilization: Option<f64>,
+}
+
+/// 所有配置了 `[alerts]` 的 provider 的预算
+#[derive(Default)]
+pub struct TokenBudgets {
+    entries: Mutex<HashMap<String, ProviderBudget>>,
+}
+
+impl TokenBudgets {
+    pub fn new(providers: &[Arc<dyn Provider>], now_secs: u64) -> Self {
+        let budgets = Self::default();
+        budgets.sync(providers, now_secs);
+        budgets
+    }
+
+    /// 重新加载 provider 后调用：保留仍存在的 provider 的用量并更新其 `[alerts]`，
+    /// 加入新的 provider，移除已删除或不再配置 `[alerts]` 的 provider
+    pub fn sync(&self, providers: &[Arc<dyn Provider>], now_secs: u64) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        let (day, month) = period_keys(now_secs);
+        let mut synced = HashMap::new();
+        for provider in providers {
+            let Some(alerts) = provider.alerts() else {
+                continue;
+            };
+            let budget = match entries.remove(provider.id()) {
+                Some(mut budget) => {
+                    budget.name = provider.name().to_string();
+                    budget.alerts = alerts.clone();
+                    budget
+                }
+                None => ProviderBudget {
+                    name: provider.name().to_string(),
+                    alerts: alerts.clone(),
+                    day: Period {
+                        key: day,
+                        ..Default::default()
+                    },
+                    month: Period {
+                        key: month,
+                        ..Default::default()
+                    },
+                },
+            };
+            synced.insert(provider.id().to_string(), budget);
+        }
+        *entries = synced;
+    }
+
+    /// 记录一次请求的 token 用量，跨过阈值时告警
+    pub fn record(&self, provider_id: &str, tokens: u64, now_secs: u64) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        let Some(budget) = entries.get_mut(provider_id) else {
+            return;
+        };
+
+        let (day, month) = period_keys(now_secs);
+        budget.day.roll_over(day);
+        budget.month.roll_over(month);
+        budget.day.tokens += tokens;
+        budget.month.tokens += tokens;
+
+        let alerts = &budget.alerts;
+        check_threshold(
+            &budget.name,
+            "daily",
+            &mut budget.day,
+            alerts.daily_token_budget,
+            alerts,
+        );
+        check_threshold(
+            &budget.name,
+            "monthly",
+            &mut budget.month,
+            alerts.monthly_token_quota,
+            alerts,
+        );
+    }
+
+    /// 在日 / 月边界清零用量，由后台任务定期调用
+    pub fn roll_over(&self, now_secs: u64) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        let (day, month) = period_keys(now_secs);
+        for budget in entries.values_mut() {
+            if budget.day.roll_over(day) {
+                tracing::debug!(provider = budget.name, "daily token usage reset");
+            }
+            if budget.month.roll_over(month) {
+                tracing::info!(provider = budget.name, "monthly token usage reset");
+            }
+        }
+    }
+
+    /// 当月配额是否已用尽
+    pub fn is_exhausted(&self, provider_id: &str) -> bool {
+        let Ok(entries) = self.entries.lock() else {
+            return false;
+        };
+        entries.get(provider_id).is_some_and(|budget| {
+            budget
+                .alerts
+                .monthly_token_quota
+                .is_some_and(|quota| budget.month.tokens >= quota)
+        })
+    }
+
+    pub fn status(&self, provider_id: &str) -> Option<BudgetStatus> {
+        let entries = self.entries.lock().ok()?;
+        let budget = entries.get(provider_id)?;
+        let daily_budget = budget.alerts.daily_token_budget;
+        let monthly_quota = budget.alerts.monthly_token_quota;
+
+        Some(BudgetStatus {
+            daily_tokens: budget.day.tokens,
+            daily_budget,
+            daily_remaining: daily_budget.map(|b| b.saturating_sub(budget.day.tokens)),
+            daily_utilization: budget.day.utilization(daily_budget),
+            monthly_tokens: budget.month.tokens,
+            monthly_quota,
+            monthly_remaining: monthly_quota.map(|q| q.saturating_sub(budget.month.tokens)),
+            monthly_utilization: budget.month.utilization(monthly_quota),
+        })
+    }
+}
+
+/// 用量首次跨过某一级阈值时记录日志
+fn check_threshold(
+    provider: &str,
+    period_name: &str,
+    period: &mut Period,
+    limit: Option<u64>,
+    alerts: &AlertsConfig,
+) {
+    let Some(utilization) = period.utilization(limit) else {
+        return;
+    };
+    let level = AlertLevel::from_utilization(utilization, alerts);
+    if period.level.is_some_and(|previous| previous >= level) {
+        return;
+    }
+    period.level = Some(level);
+
+    let limit = limit.unwrap_or_default();
+    match level {
+        AlertLevel::Critical => tracing::error!(
+            provider,
+            period = period_name,
+            tokens = period.tokens,
+            limit,
+            "token usage crossed critical threshold"
+        ),
+        AlertLevel::Warn => tracing::warn!(
+            provider,
+            period = period_name,
+            tokens = period.tokens,
+            limit,
+            "token usage crossed warning threshold"
+        ),
+        AlertLevel::Normal => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::AppState;
+    use crate::providers::{self, ProviderSettings};
+    use crate::test_support;
+    use std::path::Path;
+
+    const NOW: u64 = 1_780_000_000;
+
+    /// 写入配置了 `[alerts]` 的 provider，`id` 为 `None` 时不写 ID 行
+    fn write_provider(dir: &Path, name: &str, id: Option<&str>) {
+        let id = id.map(|id| format!("id = \"{id}\"\n")).unwrap_or_default();
+        std::fs::write(
+            dir.join(format!("{name}.toml")),
+            format!(
+                "{id}type = \"claude_code\"\n\n[oauth]\naccess_token = \"access\"\n\
+                 refresh_token = \"refresh\"\nexpires_at = 3786912000000\n\n\
+                 [alerts]\ndaily_token_budget = 1000\nmonthly_token_quota = 500\n"
+            ),
+        )
+        .unwrap();
+    }
+
+    async fn load(config: &crate::config::Config) -> Vec<Arc<dyn Provider>> {
+        let settings = Arc::new(ProviderSettings::from_config(config));
+        providers::load_providers(config.providers_dir(), &settings)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn usage_follows_the_provider_id_across_renames() {
+        let (_dir, config) = test_support::config("");
+        let dir = config.providers_dir();
+        write_provider(dir, "work", Some("id-work"));
+        let budgets = TokenBudgets::new(&load(&config).await, NOW);
+        budgets.record("id-work", 300, NOW);
+
+        std::fs::rename(dir.join("work.toml"), dir.join("team.toml")).unwrap();
+        budgets.sync(&load(&config).await, NOW);
+        let status = budgets.status("id-work").unwrap();
+        assert_eq!(status.daily_tokens, 300);
+        assert_eq!(status.monthly_remaining, Some(200));
+        budgets.record("id-work", 200, NOW);
+        assert!(budgets.is_exhausted("id-work"));
+
+        // 删除后用量随之清除，同一 ID 再次加入时重新计数
+        std::fs::remove_file(dir.join("team.toml")).unwrap();
+        budgets.sync(&load(&config).await, NOW);
+        assert!(budgets.status("id-work").is_none());
+        write_provider(dir, "team", Some("id-work"));
+        budgets.sync(&load(&config).await, NOW);
+        assert_eq!(budgets.status("id-work").unwrap().monthly_tokens, 0);
+    }
+
+    #[tokio::test]
+    async fn reload_keeps_breaker_and_budget_state_of_renamed_providers() {
+        let (_dir, config) = test_support::config("");
+        let dir = config.providers_dir().to_path_buf();
+        write_provider(&dir, "work", Some("id-work"));
+        let threshold = config.circuit_failure_threshold;
+        let keys = test_support::keys(&config);
+        let state = AppState::new(load(&config).await, config, keys);
+
+        let now = crate::utils::unix_timestamp_secs();
+        let breaker = state.circuits().get("id-work").unwrap();
+        for _ in 0..threshold {
+            breaker.on_failure(now);
+        }
+        assert!(!breaker.allows(now));
+        state.budgets().record("id-work", 500, now);
+
+        std::fs::rename(dir.join("work.toml"), dir.join("team.toml")).unwrap();
+        // 复制的配置去掉 ID 行后作为新的 provider 加载
+        write_provider(&dir, "copy", None);
+        let changes = state.reload_providers().await.unwrap();
+        assert_eq!(changes.added, ["copy"]);
+        assert!(changes.removed.is_empty());
+        assert_eq!(changes.unchanged, ["team"]);
+
+        let team = state.provider_by_name("team").unwrap();
+        assert_eq!(team.id(), "id-work");
+        assert!(Arc::ptr_eq(
+            &state.circuits().get("id-work").unwrap(),
+            &breaker
+        ));
+        assert_eq!(
+            serde_json::to_value(state.skip_reason(&team)).unwrap(),
+            serde_json::json!({"reason": "circuit_open"})
+        );
+        assert!(state.budgets().is_exhausted("id-work"));
+
+        let copy = state.provider_by_name("copy").unwrap();
+        assert_ne!(copy.id(), "id-work");
+        assert!(state.circuits().get(copy.id()).unwrap().allows(now));
+        assert_eq!(state.budgets().status(copy.id()).unwrap().monthly_tokens, 0);
+        assert!(state.skip_reason(&copy).is_none());
+    }
+}
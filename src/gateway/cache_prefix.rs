@@ -0,0 +1,341 @@
+//! prompt cache 前缀变化检测
+//!
+//! Anthropic 的 prompt cache 按 tools → system → messages 的顺序匹配前缀，客户端的细微变化
+//! （工具顺序调整、system 中带时间戳）都会让缓存失效并使输入费用翻倍。开启后对每个请求中
+//! 截至第一个 `cache_control` 断点的可缓存前缀逐项计算哈希，与同一会话上一次请求比较，
+//! 变化时记录警告与发生变化的部分。只做观察，不修改请求。
+//!
+//! 会话以 `metadata.user_id` 区分（Claude Code 在其中带有会话 ID），缺失时退回到客户端密钥名。
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+use crate::gateway::bounded_map::BoundedMap;
+use crate::metrics::CACHE_PREFIX_CHANGES;
+use crate::utils::unix_timestamp_secs;
+
+/// 前缀中的部分，按缓存匹配顺序排列
+const SECTIONS: [&str; 3] = ["tools", "system", "messages"];
+
+/// 判断一项是否带有缓存断点
+type BreakpointCheck = fn(&Value) -> bool;
+
+/// 请求体中与缓存前缀相关的字段
+#[derive(Deserialize)]
+struct PrefixFields {
+    #[serde(default)]
+    tools: Vec<Value>,
+    system: Option<Value>,
+    #[serde(default)]
+    messages: Vec<Value>,
+    metadata: Option<Metadata>,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    user_id: Option<String>,
+}
+
+/// 可缓存前缀中各部分逐项的哈希
+#[derive(Debug, Default, PartialEq, Eq)]
+struct CachePrefix {
+    sections: [Vec<u64>; 3],
+}
+
+impl CachePrefix {
+    /// 提取截至第一个 `cache_control` 断点的前缀，没有断点时返回 `None`
+    fn extract(fields: &PrefixFields) -> Option<Self> {
+        let system: Vec<&Value> = match &fields.system {
+            Some(Value::Array(blocks)) => blocks.iter().collect(),
+            // 字符串形式的 system 无法带断点，整体作为一项
+            Some(system @ Value::String(_)) => vec![system],
+            _ => Vec::new(),
+        };
+        let sections: [(Vec<&Value>, BreakpointCheck); 3] = [
+            (fields.tools.iter().collect(), has_cache_control),
+            (system, has_cache_control),
+            (fields.messages.iter().collect(), message_has_cache_control),
+        ];
+
+        let mut prefix = CachePrefix::default();
+        for (index, (items, is_breakpoint)) in sections.into_iter().enumerate() {
+            for item in items {
+                prefix.sections[index].push(hash_item(item));
+                if is_breakpoint(item) {
+                    return Some(prefix);
+                }
+            }
+        }
+        None
+    }
+
+    /// 逐部分比较，返回发生变化的部分及简要说明
+    ///
+    /// 会话正常推进时 messages 只会在末尾追加，不算作变化
+    fn diff(&self, previous: &CachePrefix) -> Vec<(&'static str, String)> {
+        SECTIONS
+            .iter()
+            .zip(previous.sections.iter().zip(&self.sections))
+            .filter_map(|(section, (old, new))| {
+                let detail = match old.iter().zip(new).position(|(a, b)| a != b) {
+                    Some(index) => {
+                        format!("item {index} changed ({} → {} items)", old.len(), new.len())
+                    }
+                    None if old.len() == new.len() => return None,
+                    None if *section == "messages" && new.len() > old.len() => return None,
+                    None => format!("{} → {} items", old.len(), new.len()),
+                };
+                Some((*section, detail))
+            })
+            .collect()
+    }
+}
+
+fn has_cache_control(item: &Value) -> bool {
+    item.get("cache_control").is_some()
+}
+
+fn message_has_cache_control(message: &Value) -> bool {
+    message
+        .get("content")
+        .and_then(Value::as_array)
+        .is_some_and(|blocks| blocks.iter().any(has_cache_control))
+}
+
+/// 忽略 `cache_control` 本身计算哈希，断点后移不算作前缀变化
+fn hash_item(item: &Value) -> u64 {
+    let mut item = item.clone();
+    strip_cache_control(&mut item);
+    let mut hasher = DefaultHasher::new();
+    item.to_string().hash(&mut hasher);
+    hasher.finish()
+}
+
+fn strip_cache_control(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.remove("cache_control");
+            map.values_mut().for_each(strip_cache_control);
+        }
+        Value::Array(items) => items.iter_mut().for_each(strip_cache_control),
+        _ => {}
+    }
+}
+
+fn conversation_key(user_id: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    user_id.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// 各会话上一次请求的缓存前缀，超过 `max_entries` 时丢弃最久未使用的会话
+pub struct CachePrefixAnalyzer {
+    enabled: bool,
+    conversations: Mutex<BoundedMap<u64, CachePrefix>>,
+}
+
+impl CachePrefixAnalyzer {
+    pub fn new(enabled: bool, max_entries: usize) -> Self {
+        Self {
+            enabled,
+            conversations: Mutex::new(BoundedMap::new("cache_prefix", max_entries, 0)),
+        }
+    }
+
+    /// 检查请求的缓存前缀是否与同一会话上一次请求不同
+    pub fn observe(&self, body: &[u8], client_key: Option<&str>, request_id: u64) {
+        if !self.enabled {
+            return;
+        }
+        let Ok(fields) = serde_json::from_slice::<PrefixFields>(body) else {
+            return;
+        };
+        let user_id = fields.metadata.as_ref().and_then(|m| m.user_id.as_deref());
+        let Some(key) = user_id.or(client_key).map(conversation_key) else {
+            return;
+        };
+        let Some(prefix) = CachePrefix::extract(&fields) else {
+            return;
+        };
+
+        let Ok(mut conversations) = self.conversations.lock() else {
+            return;
+        };
+        let now = unix_timestamp_secs();
+        if let Some(previous) = conversations.peek(&key, now) {
+            let changes = prefix.diff(previous);
+            for (section, _) in &changes {
+                CACHE_PREFIX_CHANGES.with_label_values(&[section]).inc();
+            }
+            if !changes.is_empty() {
+                let summary = changes
+                    .iter()
+                    .map(|(section, detail)| format!("{section}: {detail}"))
+                    .collect::<Vec<_>>()
+                    .join("; ");
+                tracing::warn!(
+                    request_id,
+                    client_key,
+                    changes = summary,
+                    "cacheable prompt prefix changed, prompt cache will miss"
+                );
+            }
+        }
+        conversations.insert(key, prefix, now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn tool(name: &str) -> Value {
+        json!({"name": name, "input_schema": {"type": "object"}})
+    }
+
+    fn text(text: &str) -> Value {
+        json!({"type": "text", "text": text})
+    }
+
+    fn cached(mut item: Value) -> Value {
+        item["cache_control"] = json!({"type": "ephemeral"});
+        item
+    }
+
+    fn body(tools: Vec<Value>, system: Vec<Value>, messages: Vec<Value>) -> Value {
+        json!({
+            "model": "claude-sonnet-4-5",
+            "tools": tools,
+            "system": system,
+            "messages": messages,
+            "metadata": {"user_id": "session-a"},
+        })
+    }
+
+    fn prefix(body: &Value) -> Option<CachePrefix> {
+        CachePrefix::extract(&serde_json::from_value(body.clone()).unwrap())
+    }
+
+    fn diff(old: &Value, new: &Value) -> Vec<(&'static str, String)> {
+        prefix(new).unwrap().diff(&prefix(old).unwrap())
+    }
+
+    #[test]
+    fn prefix_ends_at_the_first_breakpoint() {
+        let request = body(
+            vec![tool("a"), tool("b")],
+            vec![cached(text("You are helpful")), text("after breakpoint")],
+            vec![json!({"role": "user", "content": "hi"})],
+        );
+        let prefix = prefix(&request).unwrap();
+        assert_eq!(prefix.sections[0].len(), 2);
+        assert_eq!(prefix.sections[1].len(), 1);
+        assert!(prefix.sections[2].is_empty());
+
+        let uncached = body(vec![tool("a")], vec![text("system")], Vec::new());
+        assert_eq!(
+            CachePrefix::extract(&serde_json::from_value(uncached).unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn reordered_tools_and_changed_system_are_detected() {
+        let system = vec![cached(text("You are helpful"))];
+        let old = body(vec![tool("a"), tool("b")], system.clone(), Vec::new());
+
+        let reordered = body(vec![tool("b"), tool("a")], system, Vec::new());
+        assert_eq!(
+            diff(&old, &reordered),
+            [("tools", "item 0 changed (2 → 2 items)".to_string())]
+        );
+
+        let timestamped = body(
+            vec![tool("a"), tool("b")],
+            vec![cached(text("You are helpful. Now: 12:00"))],
+            Vec::new(),
+        );
+        assert_eq!(
+            diff(&old, &timestamped),
+            [("system", "item 0 changed (1 → 1 items)".to_string())]
+        );
+
+        let fewer = body(
+            vec![tool("a")],
+            vec![cached(text("You are helpful"))],
+            Vec::new(),
+        );
+        assert_eq!(diff(&old, &fewer), [("tools", "2 → 1 items".to_string())]);
+    }
+
+    #[test]
+    fn appended_messages_and_moved_breakpoints_are_not_changes() {
+        let turn =
+            |text: &str| json!({"role": "user", "content": [{"type": "text", "text": text}]});
+        let cached_turn = |text: &str| json!({"role": "user", "content": [cached(json!({"type": "text", "text": text}))]});
+        let old = body(
+            vec![tool("a")],
+            vec![text("system")],
+            vec![cached_turn("one")],
+        );
+        let new = body(
+            vec![tool("a")],
+            vec![text("system")],
+            vec![
+                turn("one"),
+                json!({"role": "assistant", "content": "ok"}),
+                cached_turn("two"),
+            ],
+        );
+        assert!(diff(&old, &new).is_empty());
+
+        // 已缓存的消息被改写仍算作变化
+        let rewritten = body(
+            vec![tool("a")],
+            vec![text("system")],
+            vec![cached_turn("uno")],
+        );
+        assert_eq!(
+            diff(&old, &rewritten),
+            [("messages", "item 0 changed (1 → 1 items)".to_string())]
+        );
+    }
+
+    #[test]
+    fn observe_compares_requests_of_the_same_conversation() {
+        let analyzer = CachePrefixAnalyzer::new(true, 16);
+        let system = vec![cached(text("You are helpful"))];
+        let first = body(vec![tool("a"), tool("b")], system.clone(), Vec::new());
+        let reordered = body(vec![tool("b"), tool("a")], system, Vec::new());
+        #[cfg(feature = "metrics")]
+        let before = CACHE_PREFIX_CHANGES.with_label_values(&["tools"]).get();
+
+        analyzer.observe(first.to_string().as_bytes(), None, 1);
+        analyzer.observe(first.to_string().as_bytes(), None, 2);
+        // 其他会话的请求不参与比较
+        let mut other = reordered.clone();
+        other["metadata"]["user_id"] = json!("session-b");
+        analyzer.observe(other.to_string().as_bytes(), None, 3);
+        #[cfg(feature = "metrics")]
+        assert_eq!(
+            CACHE_PREFIX_CHANGES.with_label_values(&["tools"]).get(),
+            before
+        );
+
+        analyzer.observe(reordered.to_string().as_bytes(), None, 4);
+        #[cfg(feature = "metrics")]
+        assert_eq!(
+            CACHE_PREFIX_CHANGES.with_label_values(&["tools"]).get(),
+            before + 1
+        );
+
+        let disabled = CachePrefixAnalyzer::new(false, 16);
+        disabled.observe(first.to_string().as_bytes(), None, 5);
+        assert_eq!(disabled.conversations.lock().unwrap().iter(0).count(), 0);
+    }
+}
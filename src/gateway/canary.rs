@@ -0,0 +1,198 @@
+//! 请求改写的灰度发布
+//!
+//! `pluribus.toml` 的 `[canary.<transform>]` 为改写设置灰度规则：`percent` 按会话（无会话 ID
+//! 时按请求 ID）的哈希取模 100 放量，`keys` 列出始终启用的密钥，满足其一即启用。未配置规则的
+//! 改写对所有请求生效。同一会话的判定结果固定，多轮对话行为一致；不同改写的哈希相互独立。
+
+use anyhow::Result;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+use crate::providers::Transform;
+
+/// 单个改写的灰度规则
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CanaryRule {
+    /// 放量百分比（0 - 100）
+    #[serde(default)]
+    pub percent: u8,
+    /// 始终启用的密钥名称
+    #[serde(default)]
+    pub keys: Vec<String>,
+}
+
+/// 所有改写的灰度规则
+#[derive(Debug, Clone, Default)]
+pub struct Canaries {
+    rules: BTreeMap<Transform, CanaryRule>,
+}
+
+impl Canaries {
+    /// 校验放量百分比不超过 100
+    pub fn new(rules: BTreeMap<Transform, CanaryRule>) -> Result<Self> {
+        for (transform, rule) in &rules {
+            if rule.percent > 100 {
+                anyhow::bail!(
+                    "percent for {} must be between 0 and 100: {}",
+                    transform.as_str(),
+                    rule.percent
+                );
+            }
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// `key` 的请求是否启用 `transform`，`rollout_id` 为会话 ID 或请求 ID
+    pub fn applies(&self, transform: Transform, key: Option<&str>, rollout_id: &str) -> bool {
+        let Some(rule) = self.rules.get(&transform) else {
+            return true;
+        };
+        key.is_some_and(|key| rule.keys.iter().any(|k| k == key))
+            || bucket(transform, rollout_id) < rule.percent
+    }
+
+    /// 本次请求不启用的改写
+    pub fn skipped(&self, key: Option<&str>, rollout_id: &str) -> Vec<Transform> {
+        self.rules
+            .keys()
+            .copied()
+            .filter(|transform| !self.applies(*transform, key, rollout_id))
+            .collect()
+    }
+
+    /// 改写 → 灰度规则，用于配置快照
+    pub fn settings(&self) -> serde_json::Value {
+        self.rules
+            .iter()
+            .map(|(transform, rule)| {
+                (
+                    transform.as_str().to_string(),
+                    serde_json::json!({ "percent": rule.percent, "keys": rule.keys }),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+}
+
+/// `rollout_id` 在 `transform` 下的分桶（0 - 99），跨进程与重启稳定
+fn bucket(transform: Transform, rollout_id: &str) -> u8 {
+    let digest = Sha256::new()
+        .chain_update(transform.as_str())
+        .chain_update(b":")
+        .chain_update(rollout_id)
+        .finalize();
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(prefix) % 100) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn canaries(rules: &[(Transform, u8, &[&str])]) -> Canaries {
+        Canaries::new(
+            rules
+                .iter()
+                .map(|(transform, percent, keys)| {
+                    (
+                        *transform,
+                        CanaryRule {
+                            percent: *percent,
+                            keys: keys.iter().map(|k| k.to_string()).collect(),
+                        },
+                    )
+                })
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    fn ids() -> impl Iterator<Item = String> {
+        (0..10_000).map(|i| format!("session-{i}"))
+    }
+
+    #[test]
+    fn buckets_are_stable() {
+        // 分桶结果写死，哈希方式变化会让已放量的会话换组
+        assert_eq!(bucket(Transform::IdentityPrompt, "session-a"), 28);
+        assert_eq!(bucket(Transform::ToolSpoof, "session-a"), 1);
+        for id in ids().take(100) {
+            assert_eq!(
+                bucket(Transform::ToolSpoof, &id),
+                bucket(Transform::ToolSpoof, &id)
+            );
+        }
+    }
+
+    #[test]
+    fn zero_and_full_percent_are_exact() {
+        let canaries = canaries(&[
+            (Transform::IdentityPrompt, 0, &[]),
+            (Transform::ToolSpoof, 100, &[]),
+        ]);
+        for id in ids() {
+            assert!(!canaries.applies(Transform::IdentityPrompt, Some("user"), &id));
+            assert!(canaries.applies(Transform::ToolSpoof, Some("user"), &id));
+            assert_eq!(
+                canaries.skipped(Some("user"), &id),
+                [Transform::IdentityPrompt]
+            );
+        }
+    }
+
+    #[test]
+    fn percent_rolls_out_a_stable_share() {
+        let narrow = canaries(&[(Transform::ToolSpoof, 30, &[])]);
+        let enabled: Vec<_> = ids()
+            .filter(|id| narrow.applies(Transform::ToolSpoof, None, id))
+            .collect();
+        assert!(
+            (2_700..=3_300).contains(&enabled.len()),
+            "{}",
+            enabled.len()
+        );
+
+        // 放量扩大时，已启用的会话保持启用
+        let wider = canaries(&[(Transform::ToolSpoof, 60, &[])]);
+        assert!(enabled
+            .iter()
+            .all(|id| wider.applies(Transform::ToolSpoof, None, id)));
+        // 不同改写的分桶相互独立
+        let both = ids()
+            .filter(|id| {
+                bucket(Transform::ToolSpoof, id) < 30 && bucket(Transform::IdentityPrompt, id) < 30
+            })
+            .count();
+        assert!((600..=1_200).contains(&both), "{both}");
+    }
+
+    #[test]
+    fn listed_keys_and_unconfigured_transforms_always_apply() {
+        let canaries = canaries(&[(Transform::ToolSpoof, 0, &["beta"])]);
+        assert!(canaries.applies(Transform::ToolSpoof, Some("beta"), "session-a"));
+        assert!(!canaries.applies(Transform::ToolSpoof, Some("user"), "session-a"));
+        assert!(!canaries.applies(Transform::ToolSpoof, None, "session-a"));
+        assert!(canaries.applies(Transform::IdentityPrompt, None, "session-a"));
+    }
+
+    #[test]
+    fn rejects_percent_above_100() {
+        let rules = [(
+            Transform::ToolSpoof,
+            CanaryRule {
+                percent: 101,
+                keys: Vec::new(),
+            },
+        )];
+        let err = Canaries::new(rules.into_iter().collect()).unwrap_err();
+        assert!(err.to_string().contains("tool_spoof"), "{err}");
+    }
+}
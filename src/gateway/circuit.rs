@@ -0,0 +1,180 @@
+//! provider 熔断
+//!
+//! 每个 provider 一个熔断器。连续失败（5xx 或连接失败）达到阈值后熔断器打开，选择时跳过该
+//! provider；经过恢复时长后进入半开状态，放行一个探测请求：成功则关闭，失败则重新打开。
+//! 429、400 等由请求或配额引起的错误不计入，由 rate limit 与重试策略处理。
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// 熔断器状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// `/health/details` 中展示的熔断状态
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitStatus {
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    /// 打开的时间 (Unix timestamp)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub opened_at: Option<u64>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: CircuitState,
+    consecutive_failures: u32,
+    opened_at: u64,
+    /// 半开状态下探测请求的开始时间
+    probe_started: Option<u64>,
+}
+
+/// 单个 provider 的熔断器
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    /// 打开所需的连续失败次数，0 表示关闭熔断
+    failure_threshold: u32,
+    recovery_secs: u64,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, recovery_secs: u64) -> Self {
+        Self {
+            failure_threshold,
+            recovery_secs,
+            inner: Mutex::new(Inner {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                opened_at: 0,
+                probe_started: None,
+            }),
+        }
+    }
+
+    /// 当前是否可以发送请求，不改变状态
+    pub fn allows(&self, now_secs: u64) -> bool {
+        let Ok(inner) = self.inner.lock() else {
+            return true;
+        };
+        match inner.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => now_secs >= inner.opened_at + self.recovery_secs,
+            // 探测请求未结束（如客户端断开）超过恢复时长时，允许再次探测
+            CircuitState::HalfOpen => inner
+                .probe_started
+                .is_none_or(|started| now_secs >= started + self.recovery_secs),
+        }
+    }
+
+    /// 发送请求前调用；恢复时长已过的打开状态在此转为半开，本次请求作为探测
+    pub fn on_attempt(&self, now_secs: u64) {
+        let Ok(mut inner) = self.inner.lock() else {
+            return;
+        };
+        if inner.state == CircuitState::Open && now_secs >= inner.opened_at + self.recovery_secs {
+            inner.state = CircuitState::HalfOpen;
+        }
+        if inner.state == CircuitState::HalfOpen {
+            inner.probe_started = Some(now_secs);
+        }
+    }
+
+    /// 记录成功，关闭熔断器，返回此前是否处于非关闭状态
+    pub fn on_success(&self) -> bool {
+        let Ok(mut inner) = self.inner.lock() else {
+            return false;
+        };
+        let recovered = inner.state != CircuitState::Closed;
+        inner.state = CircuitState::Closed;
+        inner.consecutive_failures = 0;
+        inner.probe_started = None;
+        recovered
+    }
+
+    /// 记录失败，返回熔断器是否因此打开
+    pub fn on_failure(&self, now_secs: u64) -> bool {
+        if self.failure_threshold == 0 {
+            return false;
+        }
+        let Ok(mut inner) = self.inner.lock() else {
+            return false;
+        };
+        inner.consecutive_failures = inner.consecutive_failures.saturating_add(1);
+        let open = match inner.state {
+            CircuitState::HalfOpen => true,
+            CircuitState::Closed => inner.consecutive_failures >= self.failure_threshold,
+            CircuitState::Open => false,
+        };
+        if open {
+            inner.state = CircuitState::Open;
+            inner.opened_at = now_secs;
+            inner.probe_started = None;
+        }
+        open
+    }
+
+    pub fn status(&self) -> Option<CircuitStatus> {
+        let inner = self.inner.lock().ok()?;
+        Some(CircuitStatus {
+            state: inner.state,
+            consecutive_failures: inner.consecutive_failures,
+            opened_at: (inner.state != CircuitState::Closed).then_some(inner.opened_at),
+        })
+    }
+}
+
+/// 按 provider ID 的熔断器
+pub struct CircuitBreakers {
+    breakers: RwLock<HashMap<String, Arc<CircuitBreaker>>>,
+    failure_threshold: u32,
+    recovery_secs: u64,
+}
+
+impl CircuitBreakers {
+    pub fn new<'a>(
+        provider_ids: impl IntoIterator<Item = &'a str>,
+        failure_threshold: u32,
+        recovery_secs: u64,
+    ) -> Self {
+        let breakers = Self {
+            breakers: RwLock::default(),
+            failure_threshold,
+            recovery_secs,
+        };
+        breakers.sync(provider_ids);
+        breakers
+    }
+
+    /// 重新加载 provider 后调用：保留仍存在的 provider 的熔断状态，为新的 provider 创建熔断器
+    pub fn sync<'a>(&self, provider_ids: impl IntoIterator<Item = &'a str>) {
+        let Ok(mut breakers) = self.breakers.write() else {
+            return;
+        };
+        let synced = provider_ids
+            .into_iter()
+            .map(|id| {
+                let breaker = breakers.remove(id).unwrap_or_else(|| {
+                    Arc::new(CircuitBreaker::new(
+                        self.failure_threshold,
+                        self.recovery_secs,
+                    ))
+                });
+                (id.to_string(), breaker)
+            })
+            .collect();
+        *breakers = synced;
+    }
+
+    /// provider 的熔断器，临时 provider 没有熔断器
+    pub fn get(&self, provider_id: &str) -> Option<Arc<CircuitBreaker>> {
+        self.breakers.read().ok()?.get(provider_id).cloned()
+    }
+}
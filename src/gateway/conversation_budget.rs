@@ -0,0 +1,296 @@
+//! 按会话的累计 token 上限
+//!
+//! 密钥配置了 `conversation_budget` 时，按（密钥, 会话）累计请求的 token 用量（流式请求在流正常
+//! 结束时计入）。累计用量超过软上限后响应带 `x-pluribus-budget-warning`；超过硬上限后该会话的
+//! 请求以 `conversation_budget_exceeded` 拒绝，直到 admin 通过
+//! `DELETE /admin/conversations/{key}/{conversation}` 重置，或会话超过 TTL 未再产生用量。
+//!
+//! 会话以 `x-pluribus-conversation-id` 请求头区分，缺失时使用 `metadata.user_id`
+//! （Claude Code 在其中带有会话 ID）；两者都没有的请求不受限制。用量只保存在内存中。
+
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+use crate::gateway::bounded_map::BoundedMap;
+use crate::keys::ConversationBudget;
+
+/// 客户端指定会话 ID 的请求头
+pub const CONVERSATION_HEADER: &str = "x-pluribus-conversation-id";
+
+/// 会话累计用量超过软上限时的响应头
+pub const WARNING_HEADER: &str = "x-pluribus-budget-warning";
+
+#[derive(Deserialize)]
+struct MetadataFields {
+    metadata: Option<Metadata>,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    user_id: Option<String>,
+}
+
+/// 读取请求所属的会话 ID
+pub fn conversation_id(headers: &HeaderMap, body: &[u8]) -> Option<String> {
+    if let Some(id) = headers
+        .get(CONVERSATION_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+    {
+        return Some(id.to_string());
+    }
+    serde_json::from_slice::<MetadataFields>(body)
+        .ok()?
+        .metadata?
+        .user_id
+        .filter(|id| !id.is_empty())
+}
+
+/// 请求所属的（密钥, 会话）
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ConversationKey {
+    pub key: String,
+    pub conversation: String,
+}
+
+/// 请求前检查的结果
+#[derive(Debug, Clone, Copy)]
+pub enum BudgetCheck {
+    Within {
+        used: u64,
+    },
+    /// 已超过软上限
+    Warning {
+        used: u64,
+    },
+    /// 已超过硬上限，拒绝请求
+    Exceeded {
+        used: u64,
+        hard: u64,
+    },
+}
+
+impl BudgetCheck {
+    /// 请求前的累计用量
+    pub fn used(&self) -> u64 {
+        match *self {
+            BudgetCheck::Within { used }
+            | BudgetCheck::Warning { used, .. }
+            | BudgetCheck::Exceeded { used, .. } => used,
+        }
+    }
+
+    fn of(used: u64, budget: &ConversationBudget) -> Self {
+        match (budget.soft_tokens, budget.hard_tokens) {
+            (_, Some(hard)) if used >= hard => BudgetCheck::Exceeded { used, hard },
+            (Some(soft), _) if used >= soft => BudgetCheck::Warning { used },
+            _ => BudgetCheck::Within { used },
+        }
+    }
+}
+
+/// `x-pluribus-budget-warning` 的值，未超过软上限时为 `None`
+pub fn warning_value(used: u64, budget: &ConversationBudget) -> Option<String> {
+    let soft = budget.soft_tokens.filter(|soft| used >= *soft)?;
+    Some(match budget.hard_tokens {
+        Some(hard) => format!("tokens={used}; soft_limit={soft}; hard_limit={hard}"),
+        None => format!("tokens={used}; soft_limit={soft}"),
+    })
+}
+
+struct Entry {
+    tokens: u64,
+    first_seen: u64,
+    last_seen: u64,
+}
+
+/// `GET /admin/conversations` 中的单个会话
+#[derive(Debug, Serialize)]
+pub struct ConversationUsage {
+    pub key: String,
+    pub conversation: String,
+    pub tokens: u64,
+    /// 首次计入用量的时间 (Unix timestamp)
+    pub first_seen: u64,
+    /// 最近计入用量的时间 (Unix timestamp)
+    pub last_seen: u64,
+}
+
+/// 各会话的累计用量
+///
+/// 超过 `max_entries` 时丢弃最久未使用的会话
+pub struct ConversationBudgets {
+    entries: Mutex<BoundedMap<ConversationKey, Entry>>,
+}
+
+impl ConversationBudgets {
+    pub fn new(ttl_secs: u64, max_entries: usize) -> Self {
+        Self {
+            entries: Mutex::new(BoundedMap::new(
+                "conversation_budgets",
+                max_entries,
+                ttl_secs,
+            )),
+        }
+    }
+
+    /// 请求前检查会话的累计用量
+    pub fn check(
+        &self,
+        key: &ConversationKey,
+        budget: &ConversationBudget,
+        now_secs: u64,
+    ) -> BudgetCheck {
+        let Ok(entries) = self.entries.lock() else {
+            return BudgetCheck::Within { used: 0 };
+        };
+        let used = entries.peek(key, now_secs).map_or(0, |entry| entry.tokens);
+        BudgetCheck::of(used, budget)
+    }
+
+    /// 计入一次请求的 token 用量，返回累计用量
+    pub fn record(&self, key: &ConversationKey, tokens: u64, now_secs: u64) -> u64 {
+        let Ok(mut entries) = self.entries.lock() else {
+            return 0;
+        };
+        let entry = entries.upsert_with(key.clone(), now_secs, || Entry {
+            tokens: 0,
+            first_seen: now_secs,
+            last_seen: now_secs,
+        });
+        entry.tokens += tokens;
+        entry.last_seen = now_secs;
+        entry.tokens
+    }
+
+    /// 清除会话的累计用量，返回是否存在
+    pub fn reset(&self, key: &ConversationKey) -> bool {
+        self.entries
+            .lock()
+            .map(|mut entries| entries.remove(key).is_some())
+            .unwrap_or(false)
+    }
+
+    /// 未过期的会话，按累计用量从高到低排列
+    pub fn list(&self, now_secs: u64) -> Vec<ConversationUsage> {
+        let Ok(entries) = self.entries.lock() else {
+            return Vec::new();
+        };
+        let mut list: Vec<_> = entries
+            .iter(now_secs)
+            .map(|(key, entry)| ConversationUsage {
+                key: key.key.clone(),
+                conversation: key.conversation.clone(),
+                tokens: entry.tokens,
+                first_seen: entry.first_seen,
+                last_seen: entry.last_seen,
+            })
+            .collect();
+        list.sort_by_key(|c| std::cmp::Reverse(c.tokens));
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    const BUDGET: ConversationBudget = ConversationBudget {
+        soft_tokens: Some(100),
+        hard_tokens: Some(200),
+    };
+
+    fn key(conversation: &str) -> ConversationKey {
+        ConversationKey {
+            key: "user".to_string(),
+            conversation: conversation.to_string(),
+        }
+    }
+
+    #[test]
+    fn soft_then_hard_limit() {
+        let budgets = ConversationBudgets::new(3600, 100);
+        let conversation = key("conv");
+
+        assert!(matches!(
+            budgets.check(&conversation, &BUDGET, 0),
+            BudgetCheck::Within { used: 0 }
+        ));
+        budgets.record(&conversation, 99, 0);
+        assert!(matches!(
+            budgets.check(&conversation, &BUDGET, 0),
+            BudgetCheck::Within { used: 99 }
+        ));
+        budgets.record(&conversation, 1, 0);
+        assert!(matches!(
+            budgets.check(&conversation, &BUDGET, 0),
+            BudgetCheck::Warning { used: 100 }
+        ));
+        assert_eq!(budgets.record(&conversation, 100, 0), 200);
+        assert!(matches!(
+            budgets.check(&conversation, &BUDGET, 0),
+            BudgetCheck::Exceeded {
+                used: 200,
+                hard: 200
+            }
+        ));
+        // 其他会话不受影响
+        assert_eq!(budgets.check(&key("other"), &BUDGET, 0).used(), 0);
+    }
+
+    #[test]
+    fn reset_and_idle_ttl_clear_usage() {
+        let budgets = ConversationBudgets::new(60, 100);
+        let conversation = key("conv");
+
+        budgets.record(&conversation, 500, 0);
+        assert!(budgets.reset(&conversation));
+        assert!(!budgets.reset(&conversation));
+        assert_eq!(budgets.check(&conversation, &BUDGET, 0).used(), 0);
+
+        budgets.record(&conversation, 500, 0);
+        // 每次计入用量都会延长 TTL
+        budgets.record(&conversation, 1, 50);
+        assert_eq!(budgets.check(&conversation, &BUDGET, 100).used(), 501);
+        assert_eq!(budgets.check(&conversation, &BUDGET, 111).used(), 0);
+        assert!(budgets.list(111).is_empty());
+    }
+
+    #[test]
+    fn warning_header_value() {
+        assert_eq!(warning_value(99, &BUDGET), None);
+        assert_eq!(
+            warning_value(150, &BUDGET).as_deref(),
+            Some("tokens=150; soft_limit=100; hard_limit=200")
+        );
+        let soft_only = ConversationBudget {
+            soft_tokens: Some(100),
+            hard_tokens: None,
+        };
+        assert_eq!(
+            warning_value(150, &soft_only).as_deref(),
+            Some("tokens=150; soft_limit=100")
+        );
+        // 只有硬上限时不警告
+        assert!(matches!(
+            BudgetCheck::of(150, &ConversationBudget::default()),
+            BudgetCheck::Within { used: 150 }
+        ));
+    }
+
+    #[test]
+    fn conversation_id_prefers_the_header() {
+        let body = br#"{"metadata": {"user_id": "user_abc_session_1"}}"#;
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            conversation_id(&headers, body).as_deref(),
+            Some("user_abc_session_1")
+        );
+        headers.insert(CONVERSATION_HEADER, HeaderValue::from_static(" conv-1 "));
+        assert_eq!(conversation_id(&headers, body).as_deref(), Some("conv-1"));
+        assert_eq!(conversation_id(&HeaderMap::new(), b"{}"), None);
+    }
+}
@@ -0,0 +1,277 @@
+//! 请求/响应黄金用例
+//!
+//! `tests/fixtures/golden/` 下每个目录是一个用例，经完整路由转发给 Claude Code provider，
+//! 上游是记录请求的 mock 服务：
+//!
+//! - `input.json`：客户端请求，`{"key"?: "user" | "admin", "headers"?: {..}, "body": {..}}`
+//! - `config.toml`（可选）：追加到测试配置的设置
+//! - `upstream.json`（可选）：上游响应，`{"status"?, "headers"?, "body"? | "events"?}`，
+//!   缺省时返回 [`test_support::message`]，流式请求返回对应的事件
+//! - `expected_upstream.json`：发往上游的方法、路径、请求头与请求体
+//! - `expected_downstream.json`：返回客户端的状态码、响应头与响应体；
+//!   流式响应体另存为 `expected_downstream.sse`
+//!
+//! 以 `PLURIBUS_BLESS=1` 运行时按实际结果重写期望文件，改写逻辑的变化在评审中表现为期望文件的 diff。
+
+use axum::body::Body;
+use axum::http::{HeaderMap, Request};
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use crate::gateway::{test_router, AppState};
+use crate::providers::claude_code::ClaudeCodeProvider;
+use crate::providers::config::{self, AuthConfig, OAuthConfig, ProviderConfig, ProviderType};
+use crate::providers::Provider;
+use crate::test_support::{self, SECRET, USER_KEY};
+
+/// 设置后重写期望文件而不是比较
+const BLESS_ENV: &str = "PLURIBUS_BLESS";
+
+/// 不记录的上游请求头：端口与版本号每次运行可能不同
+const UNPINNED_UPSTREAM_HEADERS: &[&str] = &["host", "user-agent", "content-length"];
+
+/// 不记录的响应头：每个请求不同
+const UNPINNED_DOWNSTREAM_HEADERS: &[&str] = &["x-pluribus-request-id", "date", "content-length"];
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/golden")
+}
+
+fn read_json(path: &Path) -> Value {
+    let text =
+        std::fs::read_to_string(path).unwrap_or_else(|e| panic!("read {}: {e}", path.display()));
+    serde_json::from_str(&text).unwrap_or_else(|e| panic!("parse {}: {e}", path.display()))
+}
+
+/// 按名称排序的请求头，同名多值以数组保存
+fn headers_json(headers: &HeaderMap, unpinned: &[&str]) -> Value {
+    let mut map = Map::new();
+    for name in headers.keys() {
+        if unpinned.contains(&name.as_str()) {
+            continue;
+        }
+        let values: Vec<Value> = headers
+            .get_all(name)
+            .iter()
+            .map(|v| Value::String(String::from_utf8_lossy(v.as_bytes()).into_owned()))
+            .collect();
+        let value = match <[Value; 1]>::try_from(values) {
+            Ok([single]) => single,
+            Err(values) => Value::Array(values),
+        };
+        map.insert(name.to_string(), value);
+    }
+    Value::Object(map)
+}
+
+/// 请求体为 JSON 时解析，否则保存为字符串
+fn body_json(body: &[u8]) -> Value {
+    serde_json::from_slice(body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(body).into_owned()))
+}
+
+/// mock 上游的响应
+fn upstream_response(case: &Path, streaming: bool) -> ResponseTemplate {
+    let path = case.join("upstream.json");
+    let spec = if path.exists() {
+        read_json(&path)
+    } else {
+        json!({})
+    };
+    let status = spec["status"].as_u64().unwrap_or(200) as u16;
+    let mut template = ResponseTemplate::new(status);
+    if let Some(headers) = spec["headers"].as_object() {
+        for (name, value) in headers {
+            template = template.insert_header(name.as_str(), value.as_str().unwrap_or_default());
+        }
+    }
+    let events = match &spec["events"] {
+        Value::Array(events) => Some(events.clone()),
+        _ if streaming && spec.get("body").is_none() => {
+            Some(test_support::message_events("Hello from upstream"))
+        }
+        _ => None,
+    };
+    match events {
+        Some(events) => template.set_body_raw(test_support::sse(&events), "text/event-stream"),
+        None => template.set_body_json(match &spec["body"] {
+            Value::Null => test_support::message("Hello from upstream"),
+            body => body.clone(),
+        }),
+    }
+}
+
+/// 在 `dir` 中保存 token 未过期的 provider
+async fn claude_code_provider(dir: &Path, upstream: &MockServer) -> Arc<dyn Provider> {
+    let cfg = ProviderConfig {
+        id: "golden".to_string(),
+        name: "golden".to_string(),
+        provider_type: ProviderType::ClaudeCode,
+        auth: AuthConfig::OAuth(OAuthConfig {
+            access_token: "golden-access".to_string(),
+            refresh_token: "golden-refresh".to_string(),
+            expires_at: crate::utils::unix_timestamp_ms() + 3_600_000,
+            scopes: Vec::new(),
+        }),
+        alerts: None,
+        schedule: None,
+        weight: config::DEFAULT_WEIGHT,
+    };
+    config::save(dir, &cfg.name, &cfg).await.unwrap();
+    let provider = ClaudeCodeProvider::new(
+        dir.to_path_buf(),
+        cfg.id,
+        cfg.name,
+        Arc::default(),
+        None,
+        None,
+        Vec::new(),
+    )
+    .unwrap()
+    .with_api_url(format!("{}/v1/messages", upstream.uri()));
+    Arc::new(provider)
+}
+
+/// 写入或比较一个期望文件，不一致时返回说明
+fn check(case: &str, path: &Path, actual: &str, bless: bool) -> Option<String> {
+    if bless {
+        std::fs::write(path, actual).unwrap_or_else(|e| panic!("write {}: {e}", path.display()));
+        return None;
+    }
+    let expected = std::fs::read_to_string(path).unwrap_or_default();
+    (expected != actual).then(|| {
+        let file = path.file_name().unwrap().to_string_lossy();
+        format!("{case}/{file}\n--- expected\n{expected}\n+++ actual\n{actual}")
+    })
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap() + "\n"
+}
+
+/// 运行一个用例，返回与期望不一致之处
+async fn run_case(case: &Path, bless: bool) -> Vec<String> {
+    let name = case.file_name().unwrap().to_string_lossy().into_owned();
+    let input = read_json(&case.join("input.json"));
+    let extra = std::fs::read_to_string(case.join("config.toml")).unwrap_or_default();
+    let streaming = input["body"]["stream"].as_bool().unwrap_or(false);
+
+    let upstream = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(upstream_response(case, streaming))
+        .mount(&upstream)
+        .await;
+
+    let (_dir, config) = test_support::config(&extra);
+    let provider = claude_code_provider(&config.providers_dir, &upstream).await;
+    let keys = test_support::keys(&config);
+    let router = test_router(AppState::new(vec![provider], config, keys));
+
+    let key = match input["key"].as_str() {
+        Some("admin") => SECRET,
+        _ => USER_KEY,
+    };
+    let mut request = Request::post("/anthropic/v1/messages")
+        .header("x-api-key", key)
+        .header("content-type", "application/json");
+    if let Some(headers) = input["headers"].as_object() {
+        for (header, value) in headers {
+            request = request.header(header.as_str(), value.as_str().unwrap_or_default());
+        }
+    }
+    let request = request
+        .body(Body::from(input["body"].to_string()))
+        .expect("build request");
+    let (status, headers, body) = test_support::send(&router, request).await;
+
+    let received = upstream.received_requests().await.unwrap_or_default();
+    let upstream_requests: Vec<Value> = received
+        .iter()
+        .map(|r| {
+            let path = match r.url.query() {
+                Some(query) => format!("{}?{query}", r.url.path()),
+                None => r.url.path().to_string(),
+            };
+            json!({
+                "method": r.method.to_string(),
+                "path": path,
+                "headers": headers_json(&r.headers, UNPINNED_UPSTREAM_HEADERS),
+                "body": body_json(&r.body),
+            })
+        })
+        .collect();
+    let upstream_request = match <[Value; 1]>::try_from(upstream_requests) {
+        Ok([single]) => single,
+        Err(requests) => Value::Array(requests),
+    };
+
+    let event_stream = headers
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"));
+    let mut downstream = json!({
+        "status": status.as_u16(),
+        "headers": headers_json(&headers, UNPINNED_DOWNSTREAM_HEADERS),
+    });
+    if !event_stream {
+        downstream["body"] = body_json(&body);
+    }
+
+    let mut diffs = Vec::new();
+    diffs.extend(check(
+        &name,
+        &case.join("expected_upstream.json"),
+        &pretty(&upstream_request),
+        bless,
+    ));
+    diffs.extend(check(
+        &name,
+        &case.join("expected_downstream.json"),
+        &pretty(&downstream),
+        bless,
+    ));
+    let sse_path = case.join("expected_downstream.sse");
+    if event_stream {
+        diffs.extend(check(
+            &name,
+            &sse_path,
+            &String::from_utf8_lossy(&body),
+            bless,
+        ));
+    } else if sse_path.exists() {
+        if bless {
+            std::fs::remove_file(&sse_path).unwrap();
+        } else {
+            diffs.push(format!(
+                "{name}: response is not an event stream, but expected_downstream.sse exists"
+            ));
+        }
+    }
+    diffs
+}
+
+#[tokio::test]
+async fn golden_cases_match_their_fixtures() {
+    let bless = std::env::var_os(BLESS_ENV).is_some();
+    let mut cases: Vec<PathBuf> = std::fs::read_dir(fixtures_dir())
+        .expect("read golden fixtures dir")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect();
+    cases.sort();
+    assert!(!cases.is_empty(), "no golden cases found");
+
+    let mut diffs = Vec::new();
+    for case in &cases {
+        diffs.extend(run_case(case, bless).await);
+    }
+    assert!(
+        diffs.is_empty(),
+        "{} golden fixture(s) differ; rerun with {BLESS_ENV}=1 to accept the changes\n\n{}",
+        diffs.len(),
+        diffs.join("\n\n")
+    );
+}
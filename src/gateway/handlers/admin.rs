@@ -0,0 +1,487 @@
+//! 管理接口处理器
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    Json,
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::convert::Infallible;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::gateway::conversation_budget::{ConversationKey, ConversationUsage};
+use crate::gateway::handlers::{api_error, invalid_request, messages::replay_request};
+use crate::gateway::history::{RequestFilter, RequestRecord, StatusClass};
+use crate::gateway::state::AppState;
+use crate::gateway::streams::StreamInfo;
+use crate::keys::KeySummary;
+use crate::metrics::REQUEST_FEED_DROPPED;
+use crate::providers::claude_code::{version_info, VersionInfo};
+use crate::providers::deprecation::DeprecationReport;
+use crate::providers::sse::StreamFailure;
+use crate::providers::ProviderSummary;
+use crate::stats::{self, RuntimeStats};
+use crate::utils::unix_timestamp_secs;
+
+/// 服务信息响应
+#[derive(Serialize)]
+struct AdminInfoResponse {
+    pluribus_version: &'static str,
+    #[serde(flatten)]
+    claude_code: VersionInfo,
+    provider_count: usize,
+    runtime: RuntimeStats,
+    /// 不含密钥的运行中配置
+    settings: serde_json::Map<String, serde_json::Value>,
+    keys: Vec<KeySummary>,
+}
+
+/// GET /admin/info
+pub async fn handle_admin_info(State(state): State<AppState>) -> Json<serde_json::Value> {
+    Json(serde_json::json!(AdminInfoResponse {
+        pluribus_version: env!("CARGO_PKG_VERSION"),
+        claude_code: version_info(),
+        provider_count: state.providers().len(),
+        runtime: stats::sample(),
+        settings: state.config().settings(),
+        keys: state.keys().summaries(),
+    }))
+}
+
+/// GET /admin/providers
+///
+/// 运行中 provider 的配置摘要（不含凭证）
+pub async fn handle_list_providers(State(state): State<AppState>) -> Json<Vec<ProviderSummary>> {
+    Json(
+        state
+            .providers()
+            .iter()
+            .map(|p| ProviderSummary::of(p.as_ref()))
+            .collect(),
+    )
+}
+
+/// GET /admin/providers/{name}/headers
+///
+/// provider 采集到的上游响应头
+pub async fn handle_provider_headers(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Response {
+    let Some(provider) = state.provider_by_name(&name) else {
+        return api_error(
+            StatusCode::NOT_FOUND,
+            "not_found_error",
+            format!("Provider {name} not found"),
+        );
+    };
+    match provider.captured_headers() {
+        Some(headers) => Json(headers).into_response(),
+        None => invalid_request(format!(
+            "Provider {name} does not support response header capture"
+        )),
+    }
+}
+
+/// GET /admin/deprecations
+///
+/// 上游响应中出现过的模型弃用信号
+pub async fn handle_deprecations(State(state): State<AppState>) -> Json<DeprecationReport> {
+    Json(state.provider_settings().deprecations.report())
+}
+
+/// DELETE /admin/providers/reliability
+///
+/// 清除所有 provider 的可靠性评分
+pub async fn handle_reset_reliability(State(state): State<AppState>) -> Response {
+    state.reliability().reset_all();
+    tracing::info!("provider reliability scores reset by admin");
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// POST /admin/providers/reload, POST /reload
+///
+/// 重新加载 provider 配置目录，与 `SIGUSR1` 相同，返回新增、删除与保留的 provider。
+/// 没有 `SIGUSR1` 的平台（Windows）使用 `/reload`
+pub async fn handle_reload_providers(State(state): State<AppState>) -> Response {
+    match state.reload_providers().await {
+        Ok(changes) => Json(changes).into_response(),
+        Err(e) => {
+            tracing::error!("Failed to reload providers: {:#}", e);
+            api_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "api_error",
+                format!("Failed to reload providers: {e:#}"),
+            )
+        }
+    }
+}
+
+/// DELETE /admin/providers/{name}/reliability
+///
+/// 清除 provider 的可靠性评分
+pub async fn handle_reset_provider_reliability(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Response {
+    let Some(provider) = state.provider_by_name(&name) else {
+        return api_error(
+            StatusCode::NOT_FOUND,
+            "not_found_error",
+            format!("Provider {name} not found"),
+        );
+    };
+    state.reliability().reset(provider.id());
+    tracing::info!(provider = name, "provider reliability score reset by admin");
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// GET /admin/streams
+///
+/// 转发中的流式响应
+pub async fn handle_list_streams(State(state): State<AppState>) -> Json<Vec<StreamInfo>> {
+    Json(state.streams().list())
+}
+
+/// DELETE /admin/streams/{id}
+///
+/// 中止转发中的流式响应
+pub async fn handle_abort_stream(State(state): State<AppState>, Path(id): Path<u64>) -> Response {
+    if !state.streams().abort(id, StreamFailure::Aborted) {
+        return api_error(
+            StatusCode::NOT_FOUND,
+            "not_found_error",
+            format!("Stream {id} not found"),
+        );
+    }
+    tracing::info!(stream = id, "stream aborted by admin");
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// GET /admin/conversations
+///
+/// 配置了会话上限的密钥下各会话的累计用量
+pub async fn handle_list_conversations(
+    State(state): State<AppState>,
+) -> Json<Vec<ConversationUsage>> {
+    Json(state.conversation_budgets().list(unix_timestamp_secs()))
+}
+
+/// DELETE /admin/conversations/{key}/{conversation}
+///
+/// 清零会话的累计用量，超过硬上限的会话可以继续请求
+pub async fn handle_reset_conversation(
+    State(state): State<AppState>,
+    Path((key, conversation)): Path<(String, String)>,
+) -> Response {
+    let target = ConversationKey { key, conversation };
+    if !state.conversation_budgets().reset(&target) {
+        return api_error(
+            StatusCode::NOT_FOUND,
+            "not_found_error",
+            format!(
+                "Conversation {} of key {} not found",
+                target.conversation, target.key
+            ),
+        );
+    }
+    tracing::info!(
+        key = target.key,
+        conversation = target.conversation,
+        "conversation budget reset by admin"
+    );
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// 默认每页数量
+const DEFAULT_PAGE_SIZE: usize = 20;
+/// 每页数量上限
+const MAX_PAGE_SIZE: usize = 100;
+
+/// 分页与过滤参数
+#[derive(Deserialize)]
+pub struct Pagination {
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+    /// 只列出耗时不小于该值的请求
+    min_latency_ms: Option<u64>,
+}
+
+/// 请求历史分页响应
+#[derive(Serialize)]
+struct RequestListResponse {
+    total: usize,
+    offset: usize,
+    limit: usize,
+    items: Vec<RequestRecord>,
+}
+
+fn request_not_found(id: u64) -> Response {
+    api_error(
+        StatusCode::NOT_FOUND,
+        "not_found_error",
+        format!("Request {id} not found in history"),
+    )
+}
+
+/// GET /admin/requests
+pub async fn handle_list_requests(
+    State(state): State<AppState>,
+    Query(page): Query<Pagination>,
+) -> Json<serde_json::Value> {
+    let limit = page.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+    let (total, items) = state
+        .history()
+        .list(page.offset, limit, page.min_latency_ms);
+
+    Json(serde_json::json!(RequestListResponse {
+        total,
+        offset: page.offset,
+        limit,
+        items,
+    }))
+}
+
+/// 实时请求流的查询参数
+#[derive(Deserialize)]
+pub struct RequestStreamQuery {
+    status: Option<StatusClass>,
+    provider: Option<String>,
+    model: Option<String>,
+    /// 先推送最近的多少条记录
+    #[serde(default)]
+    recent: usize,
+}
+
+fn json_event(name: &'static str, data: &impl Serialize) -> Event {
+    Event::default()
+        .event(name)
+        .json_data(data)
+        .unwrap_or_default()
+}
+
+/// GET /admin/requests/stream
+///
+/// 以 SSE 推送请求完成记录：先推送最近 `recent` 条符合条件的记录，随后发送 `ready` 事件，
+/// 之后实时推送新记录。订阅者过慢时丢弃积压的记录并发送 `lagged` 事件，不影响请求处理。
+pub async fn handle_stream_requests(
+    State(state): State<AppState>,
+    Query(query): Query<RequestStreamQuery>,
+) -> Response {
+    let Some(feed) = state.history().subscribe() else {
+        return api_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "unavailable_error",
+            "Server is shutting down".to_string(),
+        );
+    };
+    let filter = RequestFilter {
+        status: query.status,
+        provider: query.provider,
+        model: query.model,
+    };
+
+    // 先订阅再读取历史，两者重叠的记录只推送一次
+    let recent = state
+        .history()
+        .recent(query.recent.min(MAX_PAGE_SIZE), &filter);
+    let replayed: HashSet<u64> = recent.iter().map(|r| r.request_id).collect();
+    let replay = futures::stream::iter(
+        recent
+            .iter()
+            .map(|record| json_event("request", record))
+            .chain(std::iter::once(json_event("ready", &serde_json::json!({}))))
+            .collect::<Vec<_>>(),
+    );
+
+    let live = futures::stream::unfold(
+        (feed, filter, replayed),
+        |(mut feed, filter, replayed)| async move {
+            loop {
+                let event = match feed.recv().await {
+                    Ok(record) => {
+                        if !filter.matches(&record) || replayed.contains(&record.request_id) {
+                            continue;
+                        }
+                        json_event("request", record.as_ref())
+                    }
+                    Err(RecvError::Lagged(dropped)) => {
+                        REQUEST_FEED_DROPPED.inc_by(dropped);
+                        tracing::warn!(dropped, "request stream subscriber fell behind");
+                        json_event("lagged", &serde_json::json!({ "dropped": dropped }))
+                    }
+                    Err(RecvError::Closed) => return None,
+                };
+                return Some((event, (feed, filter, replayed)));
+            }
+        },
+    );
+
+    Sse::new(replay.chain(live).map(Ok::<_, Infallible>))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// GET /admin/requests/{id}
+pub async fn handle_get_request(State(state): State<AppState>, Path(id): Path<u64>) -> Response {
+    match state.history().get(id) {
+        Some(record) => Json(record).into_response(),
+        None => request_not_found(id),
+    }
+}
+
+/// POST /admin/requests/{id}/replay
+///
+/// 以非流式方式将保存的请求体重新发送给 provider
+pub async fn handle_replay_request(State(state): State<AppState>, Path(id): Path<u64>) -> Response {
+    let Some(record) = state.history().get(id) else {
+        return request_not_found(id);
+    };
+
+    let Some(body) = record.request_body else {
+        return invalid_request(format!("Request {id} has no stored body"));
+    };
+    if body.truncated {
+        return invalid_request(format!(
+            "Request {id} body was truncated and cannot be replayed"
+        ));
+    }
+
+    tracing::info!(request_id = id, "replaying request");
+    replay_request(&state, &body.content, record.anthropic_beta.as_deref()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gateway::test_router;
+    use crate::test_support::{self, MockProvider, READONLY_KEY, SECRET, USER_KEY};
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn admin_info_reports_runtime_counters() {
+        let (_dir, config) = test_support::config("");
+        let providers = [Arc::new(MockProvider::new("first"))];
+        let router = test_router(test_support::state(config, &providers));
+
+        // 流式请求结束后，转发任务与活动流计数都应回落
+        let request = test_support::messages_request(
+            READONLY_KEY,
+            &serde_json::json!({
+                "model": "claude-test",
+                "max_tokens": 16,
+                "stream": true,
+                "messages": [{"role": "user", "content": "hi"}]
+            }),
+        );
+        test_support::send(&router, request).await;
+
+        let request = Request::get("/admin/info")
+            .header("x-api-key", READONLY_KEY)
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = test_support::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["provider_count"], 1);
+        let runtime = &body["runtime"];
+        assert!(runtime["active_streams"].as_i64().unwrap() >= 0);
+        assert!(runtime["stream_channel_high_water"].as_u64().unwrap() >= 1);
+        assert!(runtime["request_body_high_water_bytes"].as_u64().unwrap() > 0);
+        for task in ["server", "stream_relay", "token_refresh", "usage_writer"] {
+            assert!(runtime["tasks"][task].as_i64().unwrap() >= 0, "{task}");
+        }
+        #[cfg(target_os = "linux")]
+        assert!(runtime["resident_memory_bytes"].as_u64().unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn deprecations_lists_findings_from_provider_settings() {
+        let (_dir, config) = test_support::config("");
+        let state = test_support::state(config, &[]);
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert("deprecation", "true".parse().unwrap());
+        state
+            .provider_settings()
+            .deprecations
+            .inspect_headers("first", "claude-old", &headers);
+        let router = test_router(state);
+
+        let request = Request::get("/admin/deprecations")
+            .header("x-api-key", READONLY_KEY)
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = test_support::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["headers"],
+            serde_json::json!(["deprecation", "sunset"])
+        );
+        assert_eq!(body["dropped"], 0);
+        let finding = &body["deprecations"][0];
+        assert_eq!(finding["model"], "claude-old");
+        assert_eq!(finding["provider"], "first");
+        assert_eq!(finding["signal"], "header:deprecation");
+        assert_eq!(finding["count"], 1);
+    }
+
+    #[tokio::test]
+    async fn reload_alias_reloads_providers() {
+        let (_dir, config) = test_support::config("");
+        for name in ["first", "second"] {
+            std::fs::write(
+                config.providers_dir().join(format!("{name}.toml")),
+                format!(
+                    "id = \"id-{name}\"\ntype = \"claude_code\"\n\n[oauth]\n\
+                     access_token = \"access\"\nrefresh_token = \"refresh\"\n\
+                     expires_at = 3786912000000\n"
+                ),
+            )
+            .unwrap();
+        }
+        let providers = [
+            Arc::new(MockProvider::new("first")),
+            Arc::new(MockProvider::new("gone")),
+        ];
+        let state = test_support::state(config, &providers);
+        let router = test_router(state.clone());
+        let reload = |key: &str| {
+            Request::post("/reload")
+                .header("x-api-key", key)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let (status, _, _) = test_support::send(&router, reload(USER_KEY)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(state.providers().len(), 2);
+
+        let (status, _, body) = test_support::send(&router, reload(SECRET)).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "added": ["second"],
+                "removed": ["gone"],
+                "unchanged": ["first"]
+            })
+        );
+        let names: Vec<_> = state
+            .providers()
+            .iter()
+            .map(|p| p.name().to_string())
+            .collect();
+        assert_eq!(names, ["first", "second"]);
+    }
+}
@@ -0,0 +1,42 @@
+//! 网关能力查询处理器
+
+use axum::Json;
+use serde::Serialize;
+
+use crate::providers::claude_code::{
+    BetaFeature, BETA_FLAGS_BASE, BETA_FLAGS_EXCLUDE, PASSTHROUGH_BETAS,
+};
+
+/// 单个 beta 功能的支持情况
+#[derive(Serialize)]
+struct BetaSupport {
+    #[serde(flatten)]
+    feature: BetaFeature,
+    /// 客户端发送的 beta flag 与相关请求体字段是否原样转发到上游
+    passthrough: bool,
+}
+
+/// `GET /v1/capabilities` 响应
+#[derive(Serialize)]
+pub struct CapabilitiesResponse {
+    /// 始终发往上游的 beta flags
+    beta_flags: &'static [&'static str],
+    /// 从客户端请求中移除的 beta flags
+    excluded_beta_flags: &'static [&'static str],
+    betas: Vec<BetaSupport>,
+}
+
+/// GET /v1/capabilities
+pub async fn handle_capabilities() -> Json<CapabilitiesResponse> {
+    Json(CapabilitiesResponse {
+        beta_flags: BETA_FLAGS_BASE,
+        excluded_beta_flags: BETA_FLAGS_EXCLUDE,
+        betas: PASSTHROUGH_BETAS
+            .iter()
+            .map(|feature| BetaSupport {
+                feature: *feature,
+                passthrough: feature.is_passed_through(),
+            })
+            .collect(),
+    })
+}
@@ -0,0 +1,179 @@
+//! count_tokens 处理器
+//!
+//! 按 provider 选择方式选出支持 count_tokens 的 provider，把请求体原样转发到上游的
+//! `/v1/messages/count_tokens`。客户端的 `anthropic-beta` 与 Messages 请求一样合并到上游
+//! 请求头中，使 beta 功能的计数与实际请求一致。不做其他改写、重试与换 provider，上游的
+//! 错误状态码原样返回。
+
+use axum::{
+    body::Bytes,
+    extract::{Extension, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::gateway::handlers::messages::{check_json_body, collect_passthrough_headers, Rejection};
+use crate::gateway::handlers::{api_error, forward_failed, permission_denied};
+use crate::gateway::middleware::RequestId;
+use crate::gateway::state::AppState;
+use crate::keys::{ClientKey, Role};
+use crate::providers::UpstreamError;
+use crate::utils::extract_model;
+
+/// POST /anthropic/v1/messages/count_tokens 处理器
+pub async fn handle_count_tokens(
+    State(state): State<AppState>,
+    Extension(request_id): Extension<RequestId>,
+    client: Option<Extension<ClientKey>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let mut request = match check_json_body(&headers, body).and_then(|body| {
+        serde_json::from_slice::<serde_json::Value>(&body)
+            .map_err(|e| Rejection::InvalidJson(format!("Invalid JSON body: {e}")))
+    }) {
+        Ok(request) => request,
+        Err(rejection) => return rejection.into_response(Some(request_id.0)),
+    };
+    let passthrough = collect_passthrough_headers(&headers);
+    if let Some(obj) = request.as_object_mut().filter(|_| !passthrough.is_empty()) {
+        obj.insert(
+            "_passthrough_headers".to_string(),
+            serde_json::Value::Object(passthrough),
+        );
+    }
+    let client = client.as_ref().map(|Extension(c)| c);
+    let model = extract_model(&request);
+    if client.is_some_and(|c| !c.policy.allows_model(&model)) {
+        return permission_denied(format!("This API key is not allowed to use model {model}"));
+    }
+    let Some(provider) = state.get_next_provider(|p| {
+        p.provider_type().serves_count_tokens()
+            && client.is_none_or(|c| c.policy.allows_provider(p.name()))
+    }) else {
+        return api_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "api_error",
+            "No provider available to count tokens".to_string(),
+        );
+    };
+
+    match provider.count_tokens(request).await {
+        Ok(count) => Json(count).into_response(),
+        Err(err) => {
+            tracing::warn!(
+                provider = provider.name(),
+                error = format!("{err:#}"),
+                "count_tokens failed"
+            );
+            let is_admin = client.is_some_and(|c| c.role == Role::Admin);
+            let privacy = client.is_some_and(|c| c.policy.privacy);
+            let name = is_admin.then(|| provider.name().to_string());
+            let mut response = forward_failed(&err, request_id.0, name, privacy);
+            if let Some(upstream) = err.downcast_ref::<UpstreamError>() {
+                *response.status_mut() = upstream.status;
+            }
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::test_router;
+    use crate::providers::ProviderType;
+    use crate::test_support::{self, MockProvider, USER_KEY};
+    use axum::body::Body;
+    use axum::http::Request;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    fn count_request(key: Option<&str>, beta: Option<&str>) -> Request<Body> {
+        let mut request = Request::post("/anthropic/v1/messages/count_tokens")
+            .header("content-type", "application/json");
+        if let Some(key) = key {
+            request = request.header("x-api-key", key);
+        }
+        if let Some(beta) = beta {
+            request = request.header("anthropic-beta", beta);
+        }
+        let body = json!({"model": "claude-test", "messages": [{"role": "user", "content": "hi"}]});
+        request.body(Body::from(body.to_string())).unwrap()
+    }
+
+    fn claude_code(name: &str) -> Arc<MockProvider> {
+        Arc::new(MockProvider::new(name).with_type(ProviderType::ClaudeCode))
+    }
+
+    #[tokio::test]
+    async fn requires_a_key_and_forwards_the_count() {
+        let (_dir, config) = test_support::config("");
+        let providers = [claude_code("first")];
+        let router = test_router(test_support::state(config, &providers));
+
+        let (status, _, _) = test_support::send(&router, count_request(None, None)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(providers[0].requests().is_empty());
+
+        let (status, _, body) =
+            test_support::send(&router, count_request(Some(USER_KEY), None)).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({"input_tokens": 42}));
+    }
+
+    #[tokio::test]
+    async fn passes_the_client_beta_to_the_provider() {
+        let (_dir, config) = test_support::config("");
+        let providers = [claude_code("first")];
+        let router = test_router(test_support::state(config, &providers));
+
+        let request = count_request(Some(USER_KEY), Some("context-1m-2025-08-07"));
+        let (status, _, _) = test_support::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let forwarded: Value = serde_json::from_slice(&providers[0].requests()[0]).unwrap();
+        assert_eq!(
+            forwarded["_passthrough_headers"]["anthropic-beta"],
+            "context-1m-2025-08-07"
+        );
+
+        // 没有 beta 时不注入
+        test_support::send(&router, count_request(Some(USER_KEY), None)).await;
+        let forwarded: Value = serde_json::from_slice(&providers[0].requests()[1]).unwrap();
+        assert!(forwarded.get("_passthrough_headers").is_none());
+    }
+
+    #[tokio::test]
+    async fn preserves_the_upstream_error_status() {
+        let (_dir, config) = test_support::config("");
+        let providers = [claude_code("first")];
+        let router = test_router(test_support::state(config, &providers));
+
+        for status in [StatusCode::NOT_FOUND, StatusCode::TOO_MANY_REQUESTS] {
+            providers[0].fail_with(
+                status,
+                r#"{"type":"error","error":{"type":"not_found_error","message":"nope"}}"#,
+            );
+            let (got, _, body) =
+                test_support::send(&router, count_request(Some(USER_KEY), None)).await;
+            assert_eq!(got, status);
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["code"], "upstream_error");
+            assert_eq!(body["upstream"]["status"], status.as_u16());
+        }
+    }
+
+    #[tokio::test]
+    async fn needs_a_provider_that_counts_tokens() {
+        let (_dir, config) = test_support::config("");
+        // Anthropic API-key provider 不支持 count_tokens
+        let providers = [Arc::new(MockProvider::new("api"))];
+        let router = test_router(test_support::state(config, &providers));
+
+        let (status, _, _) = test_support::send(&router, count_request(Some(USER_KEY), None)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(providers[0].requests().is_empty());
+    }
+}
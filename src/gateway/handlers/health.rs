@@ -4,42 +4,172 @@ use axum::{extract::State, Json};
 use serde::Serialize;
 use serde_json::json;
 
-use crate::gateway::state::AppState;
-use crate::providers::claude_code::get_claude_code_version;
+use crate::gateway::budget::BudgetStatus;
+use crate::gateway::circuit::{CircuitState, CircuitStatus};
+use crate::gateway::probe::LatencyEstimate;
+use crate::gateway::reliability::ReliabilityScore;
+use crate::gateway::state::{AppState, SkipReason};
+use crate::providers::claude_code::{version_info, VersionInfo};
 use crate::providers::{ProviderType, RateLimitInfo};
 
 /// Provider 状态信息
 #[derive(Serialize)]
 struct ProviderStatus {
+    id: String,
     name: String,
     r#type: ProviderType,
     #[serde(skip_serializing_if = "Option::is_none")]
     rate_limit: Option<RateLimitInfo>,
+    /// 配置了 `[alerts]` 时的 token 预算
+    #[serde(skip_serializing_if = "Option::is_none")]
+    budget: Option<BudgetStatus>,
+    /// 开启延迟探测后的 TTFT 估计
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency: Option<LatencyEstimate>,
+    /// 暂不参与选择的原因
+    #[serde(skip_serializing_if = "Option::is_none")]
+    skipped: Option<SkipReason>,
+    /// 熔断状态
+    #[serde(skip_serializing_if = "Option::is_none")]
+    circuit: Option<CircuitStatus>,
+    /// 近期转发结果的可靠性评分，重试换 provider 时按此排序
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reliability: Option<ReliabilityScore>,
+    /// 推理所需但授权中缺少的 OAuth scopes，非空时不参与选择
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    missing_scopes: Vec<String>,
+    /// refresh token 被作废时的错误，需要执行 `pluribus login` 重新登录
+    #[serde(skip_serializing_if = "Option::is_none")]
+    needs_reauth: Option<String>,
+}
+
+/// 公开的 provider 摘要：只有是否参与选择及原因，不含 ID、类型、上游限流与评分详情
+#[derive(Serialize)]
+struct ProviderSummary {
+    name: String,
+    /// 当前是否参与选择
+    available: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    skipped: Option<SkipReason>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    circuit: Option<CircuitState>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    budget: Option<BudgetStatus>,
+    /// 延迟探测的 TTFT EWMA（毫秒）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttft_ewma_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    missing_scopes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    needs_reauth: Option<String>,
+}
+
+/// 公开的健康检查响应
+#[derive(Serialize)]
+struct HealthSummary {
+    status: &'static str,
+    #[serde(flatten)]
+    claude_code: VersionInfo,
+    providers: Vec<ProviderSummary>,
 }
 
 /// 健康检查响应
 #[derive(Serialize)]
 struct HealthResponse {
     status: &'static str,
-    version: &'static str,
+    version: String,
+    #[serde(flatten)]
+    claude_code: VersionInfo,
     providers: Vec<ProviderStatus>,
 }
 
+/// GET /livez
+///
+/// 开始接受连接后即返回 200，不等待 provider 加载完成
+pub async fn handle_livez() -> Json<serde_json::Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+/// 启动完成前除 `/livez` 外的请求：503，`Retry-After: 1`
+pub async fn handle_starting() -> axum::response::Response {
+    let mut response = super::api_error(
+        axum::http::StatusCode::SERVICE_UNAVAILABLE,
+        "overloaded_error",
+        "Gateway is starting, try again shortly".to_string(),
+    );
+    response.headers_mut().insert(
+        axum::http::header::RETRY_AFTER,
+        axum::http::HeaderValue::from_static("1"),
+    );
+    response
+}
+
 /// GET /health
+///
+/// 无需认证，返回服务状态、Claude Code 版本与各 provider 是否参与选择；
+/// ID、类型、上游限流与可靠性评分等详情见 `/health/details`
 pub async fn handle_health(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let providers: Vec<ProviderSummary> = state
+        .providers()
+        .iter()
+        .map(|p| {
+            let skipped = state.skip_reason(p);
+            ProviderSummary {
+                name: p.name().to_string(),
+                available: skipped.is_none(),
+                skipped,
+                circuit: state
+                    .circuits()
+                    .get(p.id())
+                    .and_then(|breaker| breaker.status())
+                    .map(|status| status.state),
+                budget: state.budgets().status(p.id()),
+                ttft_ewma_ms: state
+                    .latency()
+                    .estimate(p.id())
+                    .and_then(|latency| latency.ttft_ewma_ms),
+                missing_scopes: p.missing_scopes().to_vec(),
+                needs_reauth: p.needs_reauth(),
+            }
+        })
+        .collect();
+
+    Json(json!(HealthSummary {
+        status: "ok",
+        claude_code: version_info(),
+        providers,
+    }))
+}
+
+/// GET /health/details（readonly）
+pub async fn handle_health_details(State(state): State<AppState>) -> Json<serde_json::Value> {
     let providers: Vec<ProviderStatus> = state
         .providers()
         .iter()
         .map(|p| ProviderStatus {
+            id: p.id().to_string(),
             name: p.name().to_string(),
             r#type: p.provider_type(),
             rate_limit: p.rate_limit_info(),
+            budget: state.budgets().status(p.id()),
+            latency: state.latency().estimate(p.id()),
+            skipped: state.skip_reason(p),
+            circuit: state
+                .circuits()
+                .get(p.id())
+                .and_then(|breaker| breaker.status()),
+            reliability: state.reliability().get(p.id()),
+            missing_scopes: p.missing_scopes().to_vec(),
+            needs_reauth: p.needs_reauth(),
         })
         .collect();
 
+    let claude_code = version_info();
+
     Json(json!(HealthResponse {
         status: "ok",
-        version: get_claude_code_version(),
+        version: claude_code.claude_code_version.clone(),
+        claude_code,
         providers,
     }))
 }
@@ -1,32 +1,95 @@
 //! Messages API 处理器
 
 use axum::{
-    body::Body,
+    body::{Body, Bytes},
     extract::State,
-    http::{HeaderMap, Response},
-    Json,
+    http::{header, HeaderMap, HeaderValue, Response, StatusCode},
+    Extension,
 };
+use serde::{de::IgnoredAny, Deserialize};
 use serde_json::Value;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
 
-use crate::gateway::{handlers::error_response, state::AppState};
-use crate::providers::parse_anthropic_usage;
-use crate::utils::extract_model;
+use crate::access_log;
+use crate::config::PoolHeaders;
+use crate::dead_letter::{DeadLetter, FailedAttempt, DEAD_LETTER_HEADER};
+use crate::gateway::admission;
+use crate::gateway::circuit::CircuitBreaker;
+use crate::gateway::conversation_budget::{self, BudgetCheck, ConversationKey};
+use crate::gateway::hedge::{self, AttemptStatus, HedgeAttempt, Leg};
+use crate::gateway::in_flight::{self, InFlight};
+use crate::gateway::latency::{report_if_slow, RequestTiming, SlowRequestContext, TimedStream};
+use crate::gateway::modifications::{self, Modification, ModificationKind};
+use crate::gateway::passthrough::{self, Rejected, UPSTREAM_TOKEN_HEADER};
+use crate::gateway::pinning;
+use crate::gateway::pool_headroom;
+use crate::gateway::privacy;
+use crate::gateway::reliability::{ErrorClass, Event};
+use crate::gateway::retry::RetryPolicy;
+use crate::gateway::streams::RegisteredStream;
+use crate::gateway::synthetic;
+use crate::gateway::trailers;
+use crate::gateway::{
+    handlers::{
+        conversation_budget_exceeded, error_response, forward_failed, invalid_request, not_found,
+        overloaded, permission_denied, rate_limited, request_rejected, request_too_large,
+        upstream_response_too_large,
+    },
+    history::RequestRecord,
+    middleware::RequestId,
+    state::AppState,
+};
+use crate::keys::{ClientKey, ConversationBudget, Priority, Role, DEFAULT_KEY_NAME};
+use crate::metrics::{HEDGED_REQUESTS, MESSAGES_REQUESTS, REQUESTS, TOKENS};
+use crate::providers::anomaly::{self, Anomaly, ResponseShape, ValidationMode};
+use crate::providers::claude_code::ClaudeCodeProvider;
+use crate::providers::sse::{self, StreamFormat};
+use crate::providers::{
+    capture_sent_headers, parse_anthropic_usage, EncodedRequest, Provider, ProviderType,
+    ResponseTooLarge, SentHeaders, StreamSummary, Transform, UpstreamError, Usage,
+    SKIP_TRANSFORMS_FIELD,
+};
+use crate::repro::{self, Envelope, ReproCase};
+use crate::stats::{self, TaskKind};
+#[cfg(feature = "usage-sqlite")]
+use crate::usage::UsageRow;
+use crate::utils::{
+    check_context_limits, extract_model, may_exceed_context_limits, unix_timestamp_ms,
+    unix_timestamp_secs, ContextWarning,
+};
 
 /// 需要透传的 header 名称
 const PASSTHROUGH_HEADERS: &[&str] = &["anthropic-beta"];
 
+/// 调试 header：值为 `headers` 时在响应中回显发往上游的 beta flags（仅 admin）
+const DEBUG_HEADER: &str = "x-pluribus-debug";
+
+/// 回显发往上游的 `anthropic-beta` 的响应头
+const SENT_BETA_HEADER: &str = "x-pluribus-sent-beta";
+
+/// 按名称指定 provider 的请求头（如重新提交死信）；响应中以同名头返回处理请求的 provider
+const PROVIDER_HEADER: &str = "x-pluribus-provider";
+
+/// 响应中的请求 ID，与日志中的 `id` 一致
+const REQUEST_ID_HEADER: &str = "x-pluribus-request-id";
+
+/// 流未正常结束时等待转发任务发送已转发部分概要的最长时间
+const INCOMPLETE_SUMMARY_WAIT: Duration = Duration::from_secs(5);
+
 /// Claude Code 身份标识
 const CLAUDE_CODE_IDENTITY: &str = "You are Claude Code";
 
-/// 注入 Claude Code 身份提示词
-fn inject_claude_code_prompt(body: &mut Value) {
+/// 注入 Claude Code 身份提示词（仅当 system 为数组时），返回是否注入
+fn inject_claude_code_prompt(body: &mut Value) -> bool {
     let system = body
         .as_object_mut()
         .and_then(|obj| obj.get_mut("system"))
         .and_then(|s| s.as_array_mut());
 
     let Some(system_arr) = system else {
-        return;
+        return false;
     };
 
     let needs_injection = system_arr
@@ -44,73 +107,1054 @@ fn inject_claude_code_prompt(body: &mut Value) {
         });
         system_arr.insert(0, prompt);
     }
+    needs_injection
 }
 
-/// POST /anthropic/v1/messages 处理器
-pub async fn handle_anthropic_messages(
-    State(state): State<AppState>,
-    headers: HeaderMap,
-    Json(mut body): Json<Value>,
-) -> axum::response::Response {
-    // 将需要透传的 headers 注入到 body 的 _passthrough_headers 字段
-    if let Some(obj) = body.as_object_mut() {
-        let mut passthrough = serde_json::Map::new();
-        for &name in PASSTHROUGH_HEADERS {
-            if let Some(value) = headers.get(name).and_then(|v| v.to_str().ok()) {
-                passthrough.insert(name.to_string(), Value::String(value.to_string()));
+/// 待转发的请求体
+#[derive(Clone)]
+enum OutboundBody {
+    /// 已解析并完成改写的请求
+    Parsed(Value),
+    /// 无需改写的原始请求，原样转发给上游
+    Raw(Bytes),
+    /// 已按 provider 改写并序列化的请求
+    Encoded(EncodedRequest),
+}
+
+impl OutboundBody {
+    /// 序列化后的字节数，尚未序列化时为 `None`
+    fn serialized_len(&self) -> Option<usize> {
+        match self {
+            OutboundBody::Parsed(_) => None,
+            OutboundBody::Raw(bytes) => Some(bytes.len()),
+            OutboundBody::Encoded(request) => Some(request.body.len()),
+        }
+    }
+}
+
+/// 按 provider 类型编码一次、在各次尝试间复用的请求体
+///
+/// 字段策略与 provider 的改写只取决于 provider 类型，编码结果为引用计数的 `Bytes`，
+/// 发往同类型 provider 的重试与对冲共享同一块缓冲区。换到另一类型的 provider 时才从原始请求
+/// 重新编码，因此只有 provider 池中存在多种类型时才保留原始请求。
+struct RetryBody {
+    /// 尚未编码的原始请求
+    source: Option<OutboundBody>,
+    /// 编码后是否仍保留 `source`
+    keep_source: bool,
+    /// 最近一次编码的 provider 类型、请求体与字段策略的改写
+    encoded: Option<(ProviderType, OutboundBody, Vec<Modification>)>,
+    /// 同时持有的请求体字节数峰值
+    peak_bytes: usize,
+}
+
+impl RetryBody {
+    fn new(state: &AppState, source: OutboundBody) -> Self {
+        let providers = state.providers();
+        let mut types = providers
+            .iter()
+            .map(|p| p.provider_type())
+            .filter(ProviderType::serves_messages);
+        let first = types.next();
+        Self {
+            source: Some(source),
+            keep_source: types.any(|t| Some(t) != first),
+            encoded: None,
+            peak_bytes: 0,
+        }
+    }
+
+    /// 发往 `provider` 的请求体及字段策略所做的改写
+    fn for_provider(
+        &mut self,
+        state: &AppState,
+        provider: &dyn Provider,
+        is_streaming: bool,
+    ) -> anyhow::Result<(OutboundBody, Vec<Modification>)> {
+        let provider_type = provider.provider_type();
+        if let Some((_, body, modifications)) = self
+            .encoded
+            .as_ref()
+            .filter(|(encoded_type, ..)| *encoded_type == provider_type)
+        {
+            return Ok((body.clone(), modifications.clone()));
+        }
+
+        let source = if self.keep_source {
+            self.source.clone()
+        } else {
+            self.source.take()
+        }
+        .ok_or_else(|| {
+            anyhow::anyhow!("Request body was already encoded for another provider type")
+        })?;
+        let (body, mut modifications) = apply_field_policy(state, provider, source)?;
+        let body = match body {
+            OutboundBody::Parsed(body) => {
+                let encoded = provider.encode(body, is_streaming)?;
+                if encoded.tools_spoofed {
+                    modifications.push(Modification::new(
+                        ModificationKind::Adapt,
+                        "tools",
+                        format!("tool names spoofed for provider {}", provider.name()),
+                    ));
+                }
+                OutboundBody::Encoded(encoded)
+            }
+            body => body,
+        };
+        self.encoded = Some((provider_type, body.clone(), modifications.clone()));
+        self.observe();
+        Ok((body, modifications))
+    }
+
+    /// 更新同时持有的请求体字节数峰值，原始请求尚未序列化时按编码结果估算
+    fn observe(&mut self) {
+        let Some((_, encoded, _)) = &self.encoded else {
+            return;
+        };
+        let encoded_len = encoded.serialized_len().unwrap_or(0);
+        let source_len = match (&self.source, encoded) {
+            (Some(OutboundBody::Raw(source)), OutboundBody::Raw(body))
+                if source.as_ptr() == body.as_ptr() =>
+            {
+                0
             }
+            (Some(source), _) => source.serialized_len().unwrap_or(encoded_len),
+            (None, _) => 0,
+        };
+        self.peak_bytes = self.peak_bytes.max(encoded_len + source_len);
+    }
+}
+
+impl Drop for RetryBody {
+    fn drop(&mut self) {
+        stats::observe_request_body(self.peak_bytes);
+    }
+}
+
+/// 快速路径的浅解析结构，只读取判断是否需要改写所需的字段
+#[derive(Deserialize)]
+struct FastPathProbe {
+    model: Option<String>,
+    stream: Option<bool>,
+    system: Option<ProbeSystem>,
+    tools: Option<Vec<IgnoredAny>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ProbeSystem {
+    Blocks(Vec<ProbeSystemBlock>),
+    /// 字符串形式的 system，不会注入身份提示词
+    Other(IgnoredAny),
+}
+
+#[derive(Deserialize)]
+struct ProbeSystemBlock {
+    text: Option<String>,
+}
+
+/// 出现这些片段时请求体可能需要改写（tool 名称伪装、内部字段清理），必须走完整解析
+fn needs_full_parse_marker(body: &[u8]) -> bool {
+    static MARKER: OnceLock<regex::bytes::Regex> = OnceLock::new();
+    MARKER
+        .get_or_init(|| {
+            regex::bytes::Regex::new(r#""tool_use"|"_passthrough_headers""#)
+                .expect("valid marker regex")
+        })
+        .is_match(body)
+}
+
+/// 判断请求能否走快速路径，可以则返回 (model, is_streaming)
+///
+/// 条件：无需注入透传 headers、无 tools / tool_use（无需伪装）、
+/// 无需注入身份提示词、上下文不可能触发告警。
+/// 不满足任一条件时返回 `None`，由完整解析路径处理。
+fn probe_fast_path(body: &[u8], has_passthrough: bool) -> Option<(String, bool)> {
+    // 数组等非对象请求体也能反序列化为 probe，交给完整路径拒绝
+    if has_passthrough || body.first() != Some(&b'{') || needs_full_parse_marker(body) {
+        return None;
+    }
+
+    let probe: FastPathProbe = serde_json::from_slice(body).ok()?;
+
+    if probe.tools.is_some_and(|tools| !tools.is_empty()) {
+        return None;
+    }
+
+    if let Some(ProbeSystem::Blocks(blocks)) = &probe.system {
+        let has_identity = blocks
+            .first()
+            .and_then(|block| block.text.as_deref())
+            .is_some_and(|text| text.contains(CLAUDE_CODE_IDENTITY));
+        if !has_identity {
+            return None;
         }
+    }
+
+    let model = probe.model.unwrap_or_else(|| "unknown".to_string());
+    if may_exceed_context_limits(&model, body.len()) {
+        return None;
+    }
+
+    // 未携带 stream 字段时上游按非流式处理，与完整路径写入 `stream: false` 等价
+    Some((model, probe.stream.unwrap_or(false)))
+}
+
+/// 收集需要透传的 headers
+pub(crate) fn collect_passthrough_headers(headers: &HeaderMap) -> serde_json::Map<String, Value> {
+    let mut passthrough = serde_json::Map::new();
+    for &name in PASSTHROUGH_HEADERS {
+        if let Some(value) = headers.get(name).and_then(|v| v.to_str().ok()) {
+            passthrough.insert(name.to_string(), Value::String(value.to_string()));
+        }
+    }
+    passthrough
+}
+
+/// 请求被拒绝的原因
+pub(crate) enum Rejection {
+    /// 415 `unsupported_media_type`：Content-Type 缺失或不是 JSON
+    UnsupportedMediaType,
+    /// 400 `empty_body`
+    EmptyBody,
+    /// 400 `invalid_json`
+    InvalidJson(String),
+    /// 400 `invalid_request_error`：其他无效请求
+    Invalid(String),
+}
+
+impl Rejection {
+    pub(crate) fn into_response(self, request_id: Option<u64>) -> axum::response::Response {
+        let (status, code, message) = match self {
+            Rejection::UnsupportedMediaType => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_media_type",
+                "Expected request with `Content-Type: application/json`".to_string(),
+            ),
+            Rejection::EmptyBody => (
+                StatusCode::BAD_REQUEST,
+                "empty_body",
+                "Request body is empty".to_string(),
+            ),
+            Rejection::InvalidJson(message) => (StatusCode::BAD_REQUEST, "invalid_json", message),
+            Rejection::Invalid(message) => {
+                (StatusCode::BAD_REQUEST, "invalid_request_error", message)
+            }
+        };
+        request_rejected(status, code, message, request_id)
+    }
+}
+
+/// Content-Type 是否为 `application/json` 或 `application/*+json`
+fn is_json_content_type(headers: &HeaderMap) -> bool {
+    let Some(value) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+    let mime = value
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+}
+
+/// 检查 Content-Type 与请求体是否为空，并去掉开头的 UTF-8 BOM
+///
+/// 部分客户端会在 JSON 前写入 BOM，RFC 8259 允许解析方忽略。是否为合法 JSON 在解析时判断，
+/// 这里不额外解析一次
+pub(crate) fn check_json_body(headers: &HeaderMap, body: Bytes) -> Result<Bytes, Rejection> {
+    if !is_json_content_type(headers) {
+        return Err(Rejection::UnsupportedMediaType);
+    }
+    let body = match body.strip_prefix(b"\xEF\xBB\xBF") {
+        Some(_) => body.slice(3..),
+        None => body,
+    };
+    if body.trim_ascii().is_empty() {
+        return Err(Rejection::EmptyBody);
+    }
+    Ok(body)
+}
+
+/// 读取请求体
+///
+/// 先检查 `Content-Length`，超过上限时直接拒绝而不读取 body；
+/// 未声明长度（如 chunked）时以限长方式读取，超限同样拒绝。
+async fn read_body(
+    headers: &HeaderMap,
+    body: Body,
+    limit: usize,
+) -> Result<Bytes, axum::response::Response> {
+    let declared_len = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+
+    if declared_len.is_some_and(|len| len > limit as u64) {
+        return Err(request_too_large(limit));
+    }
+
+    axum::body::to_bytes(body, limit).await.map_err(|err| {
+        let is_limit = err
+            .into_inner()
+            .downcast_ref::<http_body_util::LengthLimitError>()
+            .is_some();
+        if is_limit {
+            request_too_large(limit)
+        } else {
+            invalid_request("Failed to read request body".to_string())
+        }
+    })
+}
+
+/// 解析完成、待转发的请求
+struct PreparedRequest {
+    outbound: OutboundBody,
+    model: String,
+    is_streaming: bool,
+    stream_format: StreamFormat,
+    /// 是否在流末尾追加校验和事件
+    stream_checksum: bool,
+    /// 是否对冲发送到两个 provider
+    hedge: bool,
+    /// 最后一条 user 消息中 tool_result 引用的 tool_use id
+    tool_result_ids: Vec<String>,
+    /// 只发往指定的 provider
+    provider: Option<String>,
+    /// 密钥允许使用的 provider 名称，为空时不限制
+    allowed_providers: Vec<String>,
+    /// 使用客户端自带 token 的临时 provider，设置时不使用已保存的 provider
+    upstream: Option<Arc<dyn Provider>>,
+    /// 排队与重试退避时的优先级
+    priority: Priority,
+    /// 解析时对请求所做的改写
+    modifications: Vec<Modification>,
+    context_warning: Option<ContextWarning>,
+    /// 合成流量，不计入可靠性评分与熔断器
+    synthetic: bool,
+}
+
+/// 转发过程中产生、需要记录到请求历史的信息
+#[derive(Default)]
+struct DispatchOutcome {
+    provider: Option<String>,
+    /// 处理请求的 provider ID，运行时状态以此为键
+    provider_id: Option<String>,
+    usage: Option<Usage>,
+    /// 非流式响应中实际提供服务的模型
+    effective_model: Option<String>,
+    /// 流式响应结束时的概要
+    stream_summary: Option<oneshot::Receiver<StreamSummary>>,
+    attempts: u32,
+    /// 对冲请求中各路的结果
+    hedge: Vec<HedgeAttempt>,
+    /// 最后一次发往上游的请求头
+    sent_headers: Option<SentHeaders>,
+    /// 非流式响应中 tool_use 块的 id
+    tool_use_ids: Vec<String>,
+    /// 按顺序记录的失败尝试
+    failures: Vec<FailedAttempt>,
+    /// 对请求所做的改写
+    modifications: Vec<Modification>,
+}
+
+/// 解析请求体，能走快速路径时不做完整解析；`skipped` 为灰度规则未选中的改写
+///
+/// 请求体不是合法 JSON 时返回 [`Rejection::InvalidJson`]
+fn prepare_request(
+    headers: &HeaderMap,
+    body: Bytes,
+    skipped: &[Transform],
+) -> Result<PreparedRequest, Rejection> {
+    let passthrough = collect_passthrough_headers(headers);
+    let stream_format = StreamFormat::from_accept(
+        headers
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok()),
+    );
+    let stream_checksum = match headers.get(sse::STREAM_CHECKSUM_HEADER) {
+        None => false,
+        Some(value) if value.as_bytes().eq_ignore_ascii_case(b"sha256") => true,
+        Some(_) => {
+            return Err(Rejection::Invalid(format!(
+                "Unsupported {} value, only 'sha256' is supported",
+                sse::STREAM_CHECKSUM_HEADER
+            )))
+        }
+    };
+
+    if let Some((model, is_streaming)) = probe_fast_path(&body, !passthrough.is_empty()) {
+        return Ok(PreparedRequest {
+            tool_result_ids: pinning::tool_result_ids_raw(&body),
+            outbound: OutboundBody::Raw(body),
+            model,
+            is_streaming,
+            stream_format,
+            stream_checksum,
+            hedge: false,
+            provider: None,
+            allowed_providers: Vec::new(),
+            upstream: None,
+            priority: Priority::Normal,
+            modifications: Vec::new(),
+            context_warning: None,
+            synthetic: false,
+        });
+    }
+
+    let mut body: Value = serde_json::from_slice(&body)
+        .map_err(|e| Rejection::InvalidJson(format!("Invalid JSON body: {e}")))?;
+    if !body.is_object() {
+        return Err(Rejection::Invalid(
+            "Request body must be a JSON object".to_string(),
+        ));
+    }
+
+    // 将需要透传的 headers 注入到 body 的 _passthrough_headers 字段，
+    // 灰度未选中的改写注入到 _skip_transforms，由 provider 编码时读取
+    if let Some(obj) = body.as_object_mut() {
         if !passthrough.is_empty() {
             obj.insert(
                 "_passthrough_headers".to_string(),
                 Value::Object(passthrough),
             );
         }
+        if !skipped.is_empty() {
+            obj.insert(
+                SKIP_TRANSFORMS_FIELD.to_string(),
+                skipped.iter().map(|t| Value::from(t.as_str())).collect(),
+            );
+        }
     }
 
     // 注入 Claude Code 身份提示词
-    inject_claude_code_prompt(&mut body);
+    let mut modifications = Vec::new();
+    if !skipped.contains(&Transform::IdentityPrompt) && inject_claude_code_prompt(&mut body) {
+        modifications.push(Modification::new(
+            ModificationKind::Inject,
+            "system",
+            "Claude Code identity prompt",
+        ));
+    }
 
-    let result: anyhow::Result<Response<Body>> = async {
+    // 估算上下文大小，接近模型窗口上限时告警
+    let context_warning = check_context_limits(&body);
+
+    let model = extract_model(&body);
+    let tool_result_ids = pinning::tool_result_ids(&body);
+    let is_streaming = body
+        .get("stream")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    Ok(PreparedRequest {
+        outbound: OutboundBody::Parsed(body),
+        model,
+        is_streaming,
+        stream_format,
+        stream_checksum,
+        hedge: false,
+        tool_result_ids,
+        provider: None,
+        allowed_providers: Vec::new(),
+        upstream: None,
+        priority: Priority::Normal,
+        modifications,
+        context_warning,
+        synthetic: false,
+    })
+}
+
+/// 选择 provider 并转发请求，上游返回可重试错误时按重试策略退避后重新选择 provider
+async fn dispatch(
+    state: &AppState,
+    prepared: PreparedRequest,
+    outcome: &mut DispatchOutcome,
+) -> anyhow::Result<Response<Body>> {
+    let PreparedRequest {
+        outbound,
+        model,
+        is_streaming,
+        stream_format,
+        stream_checksum,
+        hedge,
+        tool_result_ids,
+        provider: forced,
+        allowed_providers,
+        upstream,
+        priority,
+        modifications,
+        context_warning,
+        synthetic,
+    } = prepared;
+    outcome.modifications = modifications;
+    let parse_modifications = outcome.modifications.len();
+    // 能处理 Messages 请求且密钥允许使用的 provider
+    let eligible = |p: &Arc<dyn Provider>| {
+        p.provider_type().serves_messages()
+            && (allowed_providers.is_empty() || allowed_providers.iter().any(|n| n == p.name()))
+    };
+
+    if hedge && forced.is_none() && upstream.is_none() {
+        // 只为第一路推进一次加权轮询，第二路按推进后的状态预览
+        let primary = state.get_next_provider(|p| eligible(p));
+        let backup = primary.as_ref().and_then(|first| {
+            state.preview_next_provider(|p| eligible(p) && p.name() != first.name())
+        });
+        if let (Some(primary), Some(backup)) = (primary, backup) {
+            return dispatch_hedged(
+                state,
+                [primary, backup],
+                outbound,
+                &model,
+                synthetic,
+                outcome,
+            )
+            .await;
+        }
+        tracing::debug!(model, "hedging skipped, fewer than two providers available");
+    }
+
+    let policy = state.retry_policy();
+    let mut retry_body = RetryBody::new(state, outbound);
+    let mut attempt = 0;
+    // strict 模式下 content 为空的响应只换 provider 重试一次
+    // 自带 token 或指定 provider 的请求没有其他 provider 可换
+    let mut retry_empty = !is_streaming
+        && upstream.is_none()
+        && forced.is_none()
+        && state.config().response_validation == ValidationMode::Strict;
+    let mut excluded: Option<String> = None;
+    // 已尝试过的 provider，重试时优先换到其他 provider
+    let mut tried: Vec<String> = Vec::new();
+    let mut failovers = 0;
+    // 指定的 provider 直接使用，不参与轮询
+    let forced_provider = forced
+        .as_deref()
+        .and_then(|name| state.provider_by_name(name));
+    // 回传 tool_result 的请求优先发往处理对应 tool_use 的 provider
+    let mut pinned = match (&forced, &upstream) {
+        (None, None) => state.pins().lookup(&tool_result_ids),
+        _ => None,
+    };
+
+    loop {
         // 按优先级选择一个可用的 provider
-        let provider = state
-            .get_next_provider(|p| p.provider_type().is_anthropic())
-            .ok_or_else(|| anyhow::anyhow!("No provider available. Run 'pluribus login' first."))?;
+        let excluded_name = excluded.take();
+        let pinned_provider = pinned.take().and_then(|id| {
+            let provider = state.get_next_provider(|p| eligible(p) && p.id() == id);
+            match &provider {
+                Some(p) => {
+                    tracing::debug!(
+                        provider = p.name(),
+                        "using provider pinned by tool-use loop"
+                    )
+                }
+                None => {
+                    tracing::debug!(provider_id = id, "pinned provider unavailable, reselecting")
+                }
+            }
+            provider
+        });
+        let provider = upstream
+            .clone()
+            .or_else(|| forced_provider.clone())
+            .or(pinned_provider)
+            .or_else(|| {
+                let filter = |p: &&Arc<dyn Provider>| {
+                    eligible(p)
+                        && Some(p.name()) != excluded_name.as_deref()
+                        && forced.as_deref().is_none_or(|name| p.name() == name)
+                };
+                // 重试时优先选择未尝试过的 provider，其中最近更可靠的在前
+                if !tried.is_empty() || excluded_name.is_some() {
+                    failover_provider(
+                        state,
+                        |p| filter(p) && !tried.iter().any(|name| name == p.name()),
+                        &model,
+                    )
+                    .or_else(|| failover_provider(state, filter, &model))
+                } else {
+                    state.get_next_provider(filter)
+                }
+            })
+            .ok_or_else(|| match &forced {
+                Some(name) => anyhow::anyhow!("Provider {name} is not available"),
+                None => match state.all_rejected_until(|p| eligible(p)) {
+                    Some(reset) => AllProvidersRateLimited { reset }.into(),
+                    None => anyhow::anyhow!("No provider available. Run 'pluribus login' first."),
+                },
+            })?;
 
         let provider_name = provider.name();
-        let model = extract_model(&body);
+        outcome.provider = Some(provider_name.to_string());
+        // 临时 provider 不计入预算与 tool-use 固定
+        outcome.provider_id = upstream.is_none().then(|| provider.id().to_string());
 
-        // 检查是否为流式请求
-        let is_streaming = body
-            .get("stream")
-            .and_then(|v| v.as_bool())
-            .unwrap_or(false);
+        if tried.is_empty() {
+            if let Some(warning) = &context_warning {
+                tracing::warn!(
+                    provider = provider_name,
+                    model,
+                    estimated_tokens = warning.estimated_tokens,
+                    context_window = warning.context_window,
+                    "request is approaching the model context window"
+                );
+            }
+        }
+
+        let (body, policy_modifications) =
+            retry_body.for_provider(state, provider.as_ref(), is_streaming)?;
+        outcome.modifications.truncate(parse_modifications);
+        outcome.modifications.extend(policy_modifications);
+
+        let request = SendRequest {
+            outbound: body,
+            model: &model,
+            is_streaming,
+            stream_format,
+            stream_checksum,
+            validation: state.config().response_validation,
+            retry_empty,
+            in_flight: state.in_flight(),
+        };
+        outcome.attempts += 1;
+        let result = if upstream.is_none() {
+            send_recorded(state, provider.as_ref(), request, synthetic, outcome).await
+        } else {
+            send(provider.as_ref(), request, outcome).await
+        };
+        let err = match result {
+            Ok(response) => return Ok(response),
+            Err(err) => err,
+        };
+        if !tried.iter().any(|name| name == provider_name) {
+            tried.push(provider_name.to_string());
+        }
+        outcome
+            .failures
+            .push(FailedAttempt::from_error(provider_name, &err));
+
+        let err = match err.downcast::<EmptyContentResponse>() {
+            Ok(empty) => {
+                retry_empty = false;
+                // 只判断是否有其他 provider，不推进加权轮询
+                let has_other = !state
+                    .failover_candidates(|p| eligible(p) && p.name() != provider_name)
+                    .is_empty();
+                if !has_other {
+                    return json_response(&empty.body);
+                }
+                tracing::warn!(
+                    provider = provider_name,
+                    model,
+                    "retrying empty response on another provider"
+                );
+                excluded = Some(provider_name.to_string());
+                continue;
+            }
+            Err(err) => err,
+        };
+
+        // 还有未尝试过的 provider 时立即换过去，否则退避后重试
+        let fail_over = upstream.is_none()
+            && !state
+                .failover_candidates(|p| {
+                    eligible(p)
+                        && !tried.iter().any(|name| name == p.name())
+                        && forced.as_deref().is_none_or(|name| p.name() == name)
+                })
+                .is_empty();
+        let Some(upstream) = err.downcast_ref::<UpstreamError>() else {
+            return Err(err);
+        };
+        // 非流式请求的 429 / 5xx 换 provider 不占用重试次数
+        if fail_over
+            && !is_streaming
+            && RetryPolicy::is_failover_status(upstream.status)
+            && policy.can_fail_over(failovers)
+        {
+            tracing::warn!(
+                provider = provider_name,
+                status = upstream.status.as_u16(),
+                error = %upstream,
+                tried = %tried.join(", "),
+                "failing over to another provider after upstream error"
+            );
+            failovers += 1;
+            continue;
+        }
+        if attempt >= policy.max_retries || !RetryPolicy::is_retryable(upstream.status) {
+            if tried.len() > 1 || attempt > 0 {
+                tracing::warn!(model, tried = %tried.join(", "), "giving up after retries");
+            }
+            return Err(err);
+        }
+        if fail_over {
+            tracing::warn!(
+                provider = provider_name,
+                status = upstream.status.as_u16(),
+                error = %upstream,
+                attempt = attempt + 1,
+                "failing over to another provider after upstream error"
+            );
+            attempt += 1;
+            continue;
+        }
+
+        let reset = provider
+            .rate_limit_info()
+            .map(|info| info.five_hour.reset)
+            .unwrap_or(0);
+        let backoff = policy
+            .backoff(attempt, upstream.status, reset, unix_timestamp_ms())
+            .for_priority(priority);
+
+        tracing::warn!(
+            provider = provider_name,
+            status = upstream.status.as_u16(),
+            error = %upstream,
+            attempt = attempt + 1,
+            priority = priority.as_str(),
+            wait_ms = backoff.wait.as_millis() as u64,
+            source = backoff.source.as_str(),
+            "retrying after upstream error"
+        );
+
+        tokio::time::sleep(backoff.wait).await;
+        attempt += 1;
+    }
+}
+
+/// 发送一次请求，并把结果计入 provider 的可靠性评分与熔断器
+///
+/// 对冲中被取消的一路不会走到记录这一步，不计入结果；合成流量只转发，不计入
+async fn send_recorded(
+    state: &AppState,
+    provider: &dyn Provider,
+    request: SendRequest<'_>,
+    synthetic: bool,
+    outcome: &mut DispatchOutcome,
+) -> anyhow::Result<Response<Body>> {
+    if synthetic {
+        return send(provider, request, outcome).await;
+    }
+    let breaker = state.circuits().get(provider.id());
+    if let Some(breaker) = &breaker {
+        breaker.on_attempt(unix_timestamp_secs());
+    }
+    let result = send(provider, request, outcome).await;
+    let event = match &result {
+        Ok(_) => Event::Success,
+        Err(err) if err.is::<EmptyContentResponse>() => Event::Failure(ErrorClass::EmptyContent),
+        Err(err) => Event::Failure(ErrorClass::of(err)),
+    };
+    state.reliability().record(provider.id(), event);
+    if let Some(breaker) = breaker {
+        record_circuit(&breaker, provider.name(), event);
+    }
+    result
+}
+
+/// 按转发结果更新熔断器，只有 5xx 与连接失败计为失败
+fn record_circuit(breaker: &CircuitBreaker, provider_name: &str, event: Event) {
+    match event {
+        Event::Success => {
+            if breaker.on_success() {
+                tracing::info!(provider = provider_name, "circuit closed");
+            }
+        }
+        Event::Failure(ErrorClass::ServerError | ErrorClass::Transport) => {
+            if breaker.on_failure(unix_timestamp_secs()) {
+                tracing::warn!(
+                    provider = provider_name,
+                    "circuit opened after repeated failures"
+                );
+            }
+        }
+        Event::Failure(_) => {}
+    }
+}
 
+/// 按可靠性评分选择重试的 provider
+fn failover_provider<F>(state: &AppState, filter: F, model: &str) -> Option<Arc<dyn Provider>>
+where
+    F: FnMut(&&Arc<dyn Provider>) -> bool,
+{
+    let ranked = state.failover_candidates(filter);
+    if tracing::enabled!(tracing::Level::DEBUG) {
+        let order: Vec<String> = ranked
+            .iter()
+            .map(|(p, score)| format!("{}={score:.3}", p.name()))
+            .collect();
+        tracing::debug!(model, order = %order.join(" "), "failover order by reliability");
+    }
+    ranked.into_iter().next().map(|(provider, _)| provider)
+}
+
+/// 所有 provider 都被上游 rate limit 拒绝，以 429 返回给客户端
+#[derive(Debug)]
+struct AllProvidersRateLimited {
+    /// 最早的重置时间 (Unix timestamp)
+    reset: u64,
+}
+
+impl std::fmt::Display for AllProvidersRateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "All providers are rate limited until {}", self.reset)
+    }
+}
+
+impl std::error::Error for AllProvidersRateLimited {}
+
+/// strict 模式下 content 为空的非流式响应，由 `dispatch` 决定是否换 provider 重试
+#[derive(Debug)]
+struct EmptyContentResponse {
+    body: Value,
+}
+
+impl std::fmt::Display for EmptyContentResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "upstream returned empty content")
+    }
+}
+
+impl std::error::Error for EmptyContentResponse {}
+
+/// 按 provider 类型的字段策略改写请求，需要改写时放弃快速路径
+fn apply_field_policy(
+    state: &AppState,
+    provider: &dyn Provider,
+    outbound: OutboundBody,
+) -> anyhow::Result<(OutboundBody, Vec<Modification>)> {
+    let Some(policy) = state.config().field_policies.get(provider.provider_type()) else {
+        return Ok((outbound, Vec::new()));
+    };
+
+    let mut body = match outbound {
+        OutboundBody::Parsed(body) => body,
+        OutboundBody::Raw(bytes) => serde_json::from_slice(&bytes)
+            .map_err(|e| anyhow::anyhow!("Failed to parse request body: {e}"))?,
+        OutboundBody::Encoded(_) => anyhow::bail!("Request body was already encoded"),
+    };
+    let modifications = policy
+        .apply(&mut body)
+        .into_iter()
+        .map(|(field, action)| Modification::from_field_policy(field, action, provider.name()))
+        .collect();
+    Ok((OutboundBody::Parsed(body), modifications))
+}
+
+/// 对冲发送：先发往第一个 provider，延迟后仍未返回则同时发往第二个，取先成功的响应
+async fn dispatch_hedged(
+    state: &AppState,
+    providers: [Arc<dyn Provider>; 2],
+    outbound: OutboundBody,
+    model: &str,
+    synthetic: bool,
+    outcome: &mut DispatchOutcome,
+) -> anyhow::Result<Response<Body>> {
+    let [primary, backup] = providers;
+    let request = |outbound| SendRequest {
+        outbound,
+        model,
+        is_streaming: false,
+        stream_format: StreamFormat::Sse,
+        stream_checksum: false,
+        validation: state.config().response_validation,
+        retry_empty: false,
+        in_flight: state.in_flight(),
+    };
+
+    let mut primary_outcome = DispatchOutcome::default();
+    let mut backup_outcome = DispatchOutcome::default();
+    let mut retry_body = RetryBody::new(state, outbound);
+    let (primary_body, primary_modifications) =
+        retry_body.for_provider(state, primary.as_ref(), false)?;
+    let (backup_body, backup_modifications) =
+        retry_body.for_provider(state, backup.as_ref(), false)?;
+    let (result, report) = hedge::race(
+        send_recorded(
+            state,
+            primary.as_ref(),
+            request(primary_body),
+            synthetic,
+            &mut primary_outcome,
+        ),
+        send_recorded(
+            state,
+            backup.as_ref(),
+            request(backup_body),
+            synthetic,
+            &mut backup_outcome,
+        ),
+        Duration::from_millis(state.config().hedge_delay_ms),
+    )
+    .await;
+
+    let (winner, loser, winner_outcome, winner_modifications) = match report.winner {
+        Leg::Primary => (&primary, &backup, primary_outcome, primary_modifications),
+        Leg::Backup => (&backup, &primary, backup_outcome, backup_modifications),
+    };
+    outcome.modifications.extend(winner_modifications);
+    let loser_started = report.winner == Leg::Backup || report.backup_started;
+
+    outcome.provider = Some(winner.name().to_string());
+    outcome.provider_id = Some(winner.id().to_string());
+    outcome.usage = winner_outcome.usage;
+    outcome.effective_model = winner_outcome.effective_model;
+    outcome.sent_headers = winner_outcome.sent_headers;
+    outcome.tool_use_ids = winner_outcome.tool_use_ids;
+    outcome.attempts = 1 + u32::from(loser_started);
+    if let Err(err) = &result {
+        outcome
+            .failures
+            .push(FailedAttempt::from_error(winner.name(), err));
+    }
+    outcome.hedge.push(HedgeAttempt {
+        provider: winner.name().to_string(),
+        status: if result.is_ok() {
+            AttemptStatus::Won
+        } else {
+            AttemptStatus::Failed
+        },
+    });
+    if loser_started {
+        outcome.hedge.push(HedgeAttempt {
+            provider: loser.name().to_string(),
+            status: if report.loser_failed {
+                AttemptStatus::Failed
+            } else {
+                AttemptStatus::Cancelled
+            },
+        });
+    }
+
+    if result.is_ok() {
+        HEDGED_REQUESTS
+            .with_label_values(&[report.winner.as_str()])
+            .inc();
+    }
+    tracing::info!(
+        model,
+        winner = winner.name(),
+        leg = report.winner.as_str(),
+        attempts = ?outcome.hedge,
+        "hedged request finished"
+    );
+
+    result
+}
+
+/// 单次发送的参数
+struct SendRequest<'a> {
+    outbound: OutboundBody,
+    model: &'a str,
+    is_streaming: bool,
+    stream_format: StreamFormat,
+    stream_checksum: bool,
+    /// 非流式响应的内容检查模式
+    validation: ValidationMode,
+    /// 为 true 时，content 为空的响应以 [`EmptyContentResponse`] 错误返回
+    retry_empty: bool,
+    in_flight: &'a InFlight,
+}
+
+/// 向指定 provider 发送一次请求
+async fn send(
+    provider: &dyn Provider,
+    request: SendRequest<'_>,
+    outcome: &mut DispatchOutcome,
+) -> anyhow::Result<Response<Body>> {
+    let SendRequest {
+        outbound,
+        model,
+        is_streaming,
+        stream_format,
+        stream_checksum,
+        validation,
+        retry_empty,
+        in_flight,
+    } = request;
+    let provider_name = provider.name();
+    let in_flight = in_flight.start(provider.id());
+    let fast_path = matches!(outbound, OutboundBody::Raw(_));
+
+    let log_details = access_log::current();
+    if log_details {
         tracing::info!(
             provider = provider_name,
             model,
             streaming = is_streaming,
+            fast_path,
             "request"
         );
+    }
 
-        if is_streaming {
-            // 流式请求
-            let streaming_response = provider.send_streaming(body).await?;
+    if is_streaming {
+        // 流式请求
+        let (streaming_response, sent_headers) = capture_sent_headers(async {
+            match outbound {
+                OutboundBody::Parsed(body) => provider.send_streaming(body).await,
+                OutboundBody::Raw(bytes) => provider.send_streaming_raw(bytes, model).await,
+                OutboundBody::Encoded(request) => provider.send_streaming_encoded(request).await,
+            }
+        })
+        .await;
+        outcome.sent_headers = sent_headers;
+        let streaming_response = streaming_response?;
 
-            let response = Response::builder()
-                .status(streaming_response.status)
-                .header("content-type", "text/event-stream")
-                .header("cache-control", "no-cache")
-                .header("connection", "keep-alive")
-                .body(Body::from_stream(streaming_response.stream))
-                .map_err(|e| anyhow::anyhow!("Failed to build streaming response: {}", e))?;
+        outcome.stream_summary = Some(streaming_response.summary);
 
-            Ok(response)
+        let stream = match stream_format {
+            StreamFormat::Sse => streaming_response.stream,
+            StreamFormat::Ndjson => sse::to_ndjson(streaming_response.stream),
+        };
+        let stream = if stream_checksum {
+            sse::with_checksum(stream, stream_format)
         } else {
-            // 非流式请求
-            let response_body = provider.send_message(body).await?;
-            let usage = parse_anthropic_usage(&response_body).unwrap_or_default();
+            stream
+        };
+        let stream = in_flight::hold(stream, in_flight);
+
+        let response = Response::builder()
+            .status(streaming_response.status)
+            .header("content-type", stream_format.content_type())
+            .header("cache-control", "no-cache")
+            .header("connection", "keep-alive")
+            .body(Body::from_stream(stream))
+            .map_err(|e| anyhow::anyhow!("Failed to build streaming response: {}", e))?;
+
+        Ok(response)
+    } else {
+        // 非流式请求
+        let (response_body, sent_headers) = capture_sent_headers(async {
+            match outbound {
+                OutboundBody::Parsed(body) => provider.send_message(body).await,
+                OutboundBody::Raw(bytes) => provider.send_message_raw(bytes, model).await,
+                OutboundBody::Encoded(request) => provider.send_message_encoded(request).await,
+            }
+        })
+        .await;
+        outcome.sent_headers = sent_headers;
+        let response_body = response_body?;
+        let usage = parse_anthropic_usage(&response_body).unwrap_or_else(|e| {
+            tracing::warn!(
+                provider = provider_name,
+                model,
+                "response usage not recorded: {:#}",
+                e
+            );
+            Default::default()
+        });
 
+        if log_details {
             tracing::info!(
                 provider = provider_name,
                 model,
@@ -120,20 +1164,1494 @@ pub async fn handle_anthropic_messages(
                 cache_write = usage.cache_creation_tokens,
                 "response"
             );
+        }
+        outcome.usage = Some(usage);
+        outcome.tool_use_ids = pinning::tool_use_ids(&response_body);
 
-            let response = Response::builder()
-                .status(200)
-                .header("content-type", "application/json")
-                .body(Body::from(serde_json::to_string(&response_body)?))
-                .map_err(|e| anyhow::anyhow!("Failed to build response: {}", e))?;
+        let shape = ResponseShape::from_message(&response_body);
+        let anomalies = anomaly::inspect(validation, provider_name, model, &shape);
+        outcome.effective_model = shape.model;
+        if retry_empty && anomalies.contains(&Anomaly::EmptyContent) {
+            return Err(EmptyContentResponse {
+                body: response_body,
+            }
+            .into());
+        }
+
+        json_response(&response_body)
+    }
+}
 
-            Ok(response)
+/// 请求是否带有 `x-pluribus-debug: headers`
+fn wants_header_echo(headers: &HeaderMap) -> bool {
+    headers
+        .get(DEBUG_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.split(',')
+                .any(|item| item.trim().eq_ignore_ascii_case("headers"))
+        })
+}
+
+/// 请求体是否满足对冲条件（快速路径的原始请求体需要先解析）
+fn is_hedge_eligible(config: &crate::config::Config, outbound: &OutboundBody) -> bool {
+    match outbound {
+        OutboundBody::Parsed(body) => hedge::is_eligible(config, body),
+        OutboundBody::Raw(bytes) | OutboundBody::Encoded(EncodedRequest { body: bytes, .. }) => {
+            serde_json::from_slice(bytes).is_ok_and(|body: Value| hedge::is_eligible(config, &body))
         }
     }
-    .await;
+}
+
+/// 构建非流式 JSON 响应
+fn json_response(body: &Value) -> anyhow::Result<Response<Body>> {
+    Response::builder()
+        .status(200)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_string(body)?))
+        .map_err(|e| anyhow::anyhow!("Failed to build response: {}", e))
+}
+
+/// 移除值超过 `max_value_bytes` 的响应头，避免异常的上游响应导致客户端解析失败
+fn strip_oversized_headers(
+    headers: &mut HeaderMap,
+    max_value_bytes: usize,
+    modifications: &mut Vec<Modification>,
+) {
+    let oversized: Vec<_> = headers
+        .iter()
+        .filter(|(_, value)| value.len() > max_value_bytes)
+        .map(|(name, value)| (name.clone(), value.len()))
+        .collect();
+
+    for (name, len) in oversized {
+        tracing::warn!(
+            header = %name,
+            bytes = len,
+            limit = max_value_bytes,
+            "stripping oversized response header"
+        );
+        headers.remove(&name);
+        modifications.push(Modification::new(
+            ModificationKind::Strip,
+            format!("header:{name}"),
+            format!("{len} bytes exceeds the {max_value_bytes} byte limit"),
+        ));
+    }
+}
+
+/// 请求完成后需要记录的信息
+struct Completion {
+    state: AppState,
+    /// 请求的 span，流式请求在流结束后才记录 token 数并结束
+    span: tracing::Span,
+    record: RequestRecord,
+    provider_id: Option<String>,
+    /// 非流式响应中 tool_use 块的 id
+    tool_use_ids: Vec<String>,
+    /// 受会话上限约束的请求所属的会话
+    conversation: Option<(ConversationKey, ConversationBudget)>,
+    /// 设置了 `tokens_per_hour` 的密钥名称及其上限
+    token_limit: Option<(String, u64)>,
+    /// 请求使用的密钥名称，记入用量历史
+    #[cfg_attr(not(feature = "usage-sqlite"), allow(dead_code))]
+    key_name: Option<String>,
+    /// 上游实际提供服务的模型，流式请求在流结束后从概要中读取
+    #[cfg_attr(not(feature = "usage-sqlite"), allow(dead_code))]
+    effective_model: Option<String>,
+}
+
+impl Completion {
+    /// 补全耗时与 usage，记录请求历史并检查慢请求
+    fn finish(mut self, timing: RequestTiming, stream_summary: Option<StreamSummary>) {
+        let record = &mut self.record;
+        let mut tool_use_ids = std::mem::take(&mut self.tool_use_ids);
+        if let Some(summary) = stream_summary {
+            // 流式响应在流正常结束后检查
+            if !summary.incomplete {
+                anomaly::inspect(
+                    self.state.config().response_validation,
+                    record.provider.as_deref().unwrap_or_default(),
+                    &record.model,
+                    &summary.shape,
+                );
+            }
+            record.usage = Some(summary.usage);
+            record.incomplete = summary.incomplete;
+            self.effective_model = summary.shape.model;
+            tool_use_ids = summary.tool_use_ids;
+        }
+        record.latency_ms = timing.latency().as_millis() as u64;
+        record.ttft_ms = timing.ttft.map(|t| t.as_millis() as u64);
+        record.duration_ms = timing.duration.as_millis() as u64;
+        MESSAGES_REQUESTS
+            .with_label_values(&[if record.synthetic { "true" } else { "false" }])
+            .inc();
+        let provider_label = record.provider.as_deref().unwrap_or("none");
+        self.span.record("ai.provider", provider_label);
+        self.span.record("ai.model", record.model.as_str());
+        if let Some(usage) = &record.usage {
+            self.span.record("ai.tokens.input", usage.input_tokens);
+            self.span.record("ai.tokens.output", usage.output_tokens);
+            self.span
+                .record("ai.tokens.cache_read", usage.cache_read_tokens);
+            self.span
+                .record("ai.tokens.cache_write", usage.cache_creation_tokens);
+        }
+        // 合成流量只计入 `messages_requests_total`，不计入按 provider 的请求与 token 指标
+        if !record.synthetic {
+            REQUESTS
+                .with_label_values(&[
+                    provider_label,
+                    &record.model,
+                    &record.response_status.to_string(),
+                ])
+                .inc();
+        }
+        if let Some(usage) = record.usage.as_ref().filter(|_| !record.synthetic) {
+            for (kind, tokens) in [
+                ("input", usage.input_tokens),
+                ("output", usage.output_tokens),
+                ("cache_read", usage.cache_read_tokens),
+                ("cache_write", usage.cache_creation_tokens),
+            ] {
+                TOKENS
+                    .with_label_values(&[provider_label, &record.model, kind])
+                    .inc_by(tokens);
+            }
+        }
+
+        let ctx = SlowRequestContext {
+            request_id: record.request_id,
+            provider: record.provider.as_deref(),
+            model: &record.model,
+            is_streaming: record.is_streaming,
+            attempts: record.attempts,
+            usage: record.usage.as_ref(),
+        };
+        report_if_slow(self.state.config(), &ctx, &timing);
+        // 未采样的请求没有转发日志，token 数计入访问日志汇总
+        if let Some(usage) = &record.usage {
+            let access_log = self.state.access_log();
+            if !access_log.is_sampled(record.request_id) {
+                access_log.record_usage(usage);
+            }
+        }
+
+        if let (Some((key, limit)), Some(usage)) = (&self.token_limit, &record.usage) {
+            if !record.synthetic {
+                self.state
+                    .key_limits()
+                    .record_tokens(key, *limit, usage.total());
+            }
+        }
+
+        // 发往过 provider 的请求都记录，失败的请求按状态码计入错误统计
+        #[cfg(feature = "usage-sqlite")]
+        if let (Some(recorder), Some(provider)) = (self.state.usage_recorder(), &record.provider) {
+            if !record.synthetic {
+                recorder.record(UsageRow {
+                    timestamp: record.timestamp,
+                    provider: provider.clone(),
+                    model: record.model.clone(),
+                    effective_model: self.effective_model.take(),
+                    api_key_name: self.key_name.take(),
+                    usage: record.usage.clone().unwrap_or_default(),
+                    status: record.response_status,
+                    latency_ms: record.latency_ms,
+                    ttft_ms: record.ttft_ms,
+                    stream: record.is_streaming,
+                });
+            }
+        }
+
+        // 合成流量不计入 provider 预算，也不记录 tool-use 固定
+        let provider_id = self.provider_id.as_ref().filter(|_| !record.synthetic);
+        if let (Some(provider_id), Some(usage)) = (provider_id, &record.usage) {
+            self.state
+                .budgets()
+                .record(provider_id, usage.total(), unix_timestamp_secs());
+        }
+        if let Some(provider_id) = provider_id {
+            self.state.pins().record(provider_id, &tool_use_ids);
+        }
+        if let (Some((conversation, budget)), Some(usage)) = (&self.conversation, &record.usage) {
+            let total = self.state.conversation_budgets().record(
+                conversation,
+                usage.total(),
+                unix_timestamp_secs(),
+            );
+            let before = total - usage.total();
+            for (limit, name) in [(budget.soft_tokens, "soft"), (budget.hard_tokens, "hard")] {
+                if limit.is_some_and(|limit| before < limit && total >= limit) {
+                    tracing::warn!(
+                        key = conversation.key,
+                        conversation = conversation.conversation,
+                        tokens = total,
+                        limit,
+                        "conversation crossed its {name} token budget"
+                    );
+                }
+            }
+        }
+
+        self.state.history().record(self.record);
+    }
+
+    /// 非流式响应立即记录；流式响应包装响应体，流结束时再记录耗时与 usage 并释放并发许可
+    fn attach(
+        self,
+        response: &mut axum::response::Response,
+        stream_summary: Option<oneshot::Receiver<StreamSummary>>,
+        permit: admission::Permit,
+        start: Instant,
+        stream_format: StreamFormat,
+        wants_trailers: bool,
+    ) {
+        let provider = self.record.provider.clone();
+        match stream_summary {
+            Some(mut summary_rx) if response.status().is_success() => {
+                let registration = self.state.streams().register(
+                    self.record.request_id,
+                    provider.as_deref(),
+                    &self.record.model,
+                );
+                let body = RegisteredStream::new(
+                    std::mem::take(response.body_mut()).into_data_stream(),
+                    registration,
+                    stream_format,
+                );
+                let (trailer_tx, trailer_rx) = oneshot::channel();
+                let on_complete = Box::new(move |timing| {
+                    // 流结束后才释放并发许可
+                    drop(permit);
+                    match summary_rx.try_recv() {
+                        Ok(summary) => {
+                            let _ = trailer_tx.send(Some(summary.usage.clone()));
+                            self.finish(timing, Some(summary));
+                        }
+                        // 流被中止或客户端断开时，转发任务察觉断开后才发送已转发部分的概要
+                        Err(oneshot::error::TryRecvError::Empty) => {
+                            let _ = trailer_tx.send(None);
+                            stats::spawn(TaskKind::StreamCompletion, async move {
+                                let summary =
+                                    tokio::time::timeout(INCOMPLETE_SUMMARY_WAIT, summary_rx)
+                                        .await
+                                        .ok()
+                                        .and_then(Result::ok);
+                                self.finish(timing, summary);
+                            });
+                        }
+                        Err(oneshot::error::TryRecvError::Closed) => {
+                            let _ = trailer_tx.send(None);
+                            self.finish(timing, None);
+                        }
+                    }
+                });
+                let timed = TimedStream::new(body, start, on_complete);
+                *response.body_mut() = if wants_trailers {
+                    response.headers_mut().insert(
+                        header::TRAILER,
+                        HeaderValue::from_static(trailers::DECLARED_TRAILERS),
+                    );
+                    trailers::with_trailers(timed, provider, trailer_rx)
+                } else {
+                    Body::from_stream(timed)
+                };
+            }
+            _ => self.finish(
+                RequestTiming {
+                    ttft: None,
+                    duration: start.elapsed(),
+                },
+                None,
+            ),
+        }
+    }
+}
+
+/// 受会话上限约束的请求所属的会话
+struct Conversation {
+    key: ConversationKey,
+    budget: ConversationBudget,
+    /// 请求前的检查结果
+    check: BudgetCheck,
+}
+
+/// 检查会话 token 上限，会话已用完硬上限时返回错误响应
+///
+/// 没有密钥、密钥未设置会话上限或请求不属于某个会话时返回 `None`
+fn check_conversation_budget(
+    state: &AppState,
+    headers: &HeaderMap,
+    body: &[u8],
+    client: Option<&ClientKey>,
+) -> Result<Option<Conversation>, Box<axum::response::Response>> {
+    let Some((key, budget)) = client.and_then(|c| {
+        let budget = c.policy.conversation_budget?;
+        let conversation = conversation_budget::conversation_id(headers, body)?;
+        Some((
+            ConversationKey {
+                key: c.name.clone(),
+                conversation,
+            },
+            budget,
+        ))
+    }) else {
+        return Ok(None);
+    };
+    let check = state
+        .conversation_budgets()
+        .check(&key, &budget, unix_timestamp_secs());
+    if let BudgetCheck::Exceeded { used, hard } = check {
+        tracing::warn!(
+            key = key.key,
+            conversation = key.conversation,
+            tokens = used,
+            hard_limit = hard,
+            "request rejected, conversation token budget exhausted"
+        );
+        return Err(Box::new(conversation_budget_exceeded(format!(
+            "Conversation has used {used} tokens, over its {hard} token budget; \
+             it resets after being idle or when an admin resets it"
+        ))));
+    }
+    Ok(Some(Conversation { key, budget, check }))
+}
+
+/// 按密钥与请求头确定转发方式：允许的模型与 provider、优先级、对冲、指定的 provider 与客户端自带的上游 token
+///
+/// 密钥不允许的请求返回错误响应
+fn apply_key_policy(
+    state: &AppState,
+    headers: &HeaderMap,
+    client: Option<&ClientKey>,
+    prepared: &mut PreparedRequest,
+) -> Result<(), Box<axum::response::Response>> {
+    if let Some(client) = client {
+        if !client.policy.allows_model(&prepared.model) {
+            return Err(Box::new(permission_denied(format!(
+                "This API key is not allowed to use model {}",
+                prepared.model
+            ))));
+        }
+        prepared.allowed_providers = client.policy.allowed_providers.clone();
+    }
+    prepared.priority = admission::requested_priority(headers, client)
+        .map_err(|message| Box::new(invalid_request(message)))?;
+    // 低优先级请求不占用额外的 provider 做对冲
+    prepared.hedge = !prepared.is_streaming
+        && prepared.priority > Priority::Low
+        && hedge::is_requested(headers, client)
+        && is_hedge_eligible(state.config(), &prepared.outbound);
+    // 不允许使用该 provider 的密钥在下面返回 403
+    prepared.provider = headers
+        .get(PROVIDER_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    match passthrough::upstream_token(headers, client) {
+        Ok(Some(token)) => {
+            if prepared.provider.take().is_some() {
+                tracing::debug!(
+                    "{PROVIDER_HEADER} ignored for a request with its own upstream token"
+                );
+            }
+            prepared.upstream = Some(Arc::new(ClaudeCodeProvider::passthrough(
+                token,
+                state.provider_settings().clone(),
+            )));
+        }
+        Ok(None) => {}
+        Err(Rejected::NotAllowed) => {
+            return Err(Box::new(permission_denied(format!(
+                "This API key is not allowed to use {UPSTREAM_TOKEN_HEADER}"
+            ))))
+        }
+        Err(Rejected::Invalid) => {
+            return Err(Box::new(invalid_request(format!(
+                "Invalid {UPSTREAM_TOKEN_HEADER} value"
+            ))))
+        }
+    }
+    if let Some(name) = &prepared.provider {
+        let found = state
+            .provider_by_name(name)
+            .is_some_and(|p| p.provider_type().serves_messages());
+        if !found {
+            return Err(Box::new(not_found(format!("Provider {name} not found"))));
+        }
+        if client.is_some_and(|c| !c.policy.allows_provider(name)) {
+            return Err(Box::new(permission_denied(format!(
+                "This API key is not allowed to use provider {name}"
+            ))));
+        }
+    }
+    Ok(())
+}
+
+/// 转发结束后加工响应所需的请求信息
+struct ResponseContext {
+    request_id: u64,
+    is_streaming: bool,
+    /// 隐私密钥：清理响应头，错误中不带 provider
+    privacy: bool,
+    is_admin: bool,
+    /// 是否回显发往上游的 beta flags
+    echo_sent_headers: bool,
+    /// 是否在响应体中标注对请求所做的改写
+    annotate_body: bool,
+    context_warning: Option<ContextWarning>,
+    /// 受会话上限约束时的上限与请求前的检查结果
+    budget: Option<(ConversationBudget, BudgetCheck)>,
+}
+
+impl ResponseContext {
+    /// 转发成功的响应，上下文接近上限时附带告警
+    fn success(&self, mut response: axum::response::Response) -> axum::response::Response {
+        if let Some(value) = self
+            .context_warning
+            .as_ref()
+            .and_then(|w| HeaderValue::from_str(&w.header_value()).ok())
+        {
+            response
+                .headers_mut()
+                .insert("x-context-usage-warning", value);
+        }
+        response
+    }
+
+    /// 转发失败时返回给客户端的错误
+    fn failure(
+        &self,
+        err: &anyhow::Error,
+        model: &str,
+        provider: Option<String>,
+    ) -> axum::response::Response {
+        if let Some(limited) = err.downcast_ref::<AllProvidersRateLimited>() {
+            tracing::warn!(model, reset = limited.reset, "all providers rate limited");
+            rate_limited(limited.reset.saturating_sub(unix_timestamp_secs()).max(1))
+        } else if let Some(too_large) = err.downcast_ref::<ResponseTooLarge>() {
+            upstream_response_too_large(too_large)
+        } else {
+            tracing::warn!(error = format!("{err:#}"), "request failed");
+            let provider = provider.filter(|_| self.is_admin);
+            forward_failed(err, self.request_id, provider, self.privacy)
+        }
+    }
+
+    /// 写入 provider、会话上限、号池余量与改写记录等响应头，按需在响应体中标注改写
+    async fn finish(
+        &self,
+        state: &AppState,
+        mut response: axum::response::Response,
+        outcome: &mut DispatchOutcome,
+    ) -> axum::response::Response {
+        if let Some(value) = outcome
+            .provider
+            .as_deref()
+            .and_then(|name| HeaderValue::from_str(name).ok())
+        {
+            response.headers_mut().insert(PROVIDER_HEADER, value);
+        }
+        if let Some(value) = outcome
+            .sent_headers
+            .as_ref()
+            .filter(|_| self.echo_sent_headers)
+            .and_then(|sent| HeaderValue::from_str(&sent.anthropic_beta).ok())
+        {
+            response.headers_mut().insert(SENT_BETA_HEADER, value);
+        }
+        if let Some((budget, check)) = &self.budget {
+            // 非流式响应的用量已知，计入后判断；流式响应只能按请求前的累计用量判断
+            let used = check.used()
+                + outcome
+                    .usage
+                    .as_ref()
+                    .filter(|_| !self.is_streaming)
+                    .map_or(0, Usage::total);
+            if let Some(value) = conversation_budget::warning_value(used, budget)
+                .and_then(|v| HeaderValue::from_str(&v).ok())
+            {
+                response
+                    .headers_mut()
+                    .insert(conversation_budget::WARNING_HEADER, value);
+            }
+        }
+        let pool_headers = state.config().pool_headers;
+        if pool_headers != PoolHeaders::Off {
+            pool_headroom::insert_headers(
+                response.headers_mut(),
+                &state.pool_headroom(),
+                pool_headers,
+            );
+        }
+        strip_oversized_headers(
+            response.headers_mut(),
+            state.config().max_forward_header_value_bytes,
+            &mut outcome.modifications,
+        );
+        modifications::insert_header(response.headers_mut(), &outcome.modifications);
+        if self.privacy {
+            let stripped = privacy::scrub_headers(response.headers_mut());
+            tracing::debug!(stripped, "privacy key, scrubbed response headers");
+        }
+        if self.annotate_body && !self.is_streaming && !outcome.modifications.is_empty() {
+            response = modifications::annotate_body(response, &outcome.modifications).await;
+        }
+        response
+    }
+}
+
+/// POST /anthropic/v1/messages 处理器
+///
+/// 所有响应（包括错误）都带有 `x-pluribus-request-id`，与日志 span 的 `id` 一致
+pub async fn handle_anthropic_messages(
+    state: State<AppState>,
+    Extension(request_id): Extension<RequestId>,
+    client: Option<Extension<ClientKey>>,
+    headers: HeaderMap,
+    body: Body,
+) -> axum::response::Response {
+    let privacy = client.as_ref().is_some_and(|Extension(c)| c.policy.privacy);
+    let mut response = messages(state, Extension(request_id), client, headers, body).await;
+    if !privacy {
+        response
+            .headers_mut()
+            .insert(REQUEST_ID_HEADER, HeaderValue::from(request_id.0));
+    }
+    response
+}
+
+async fn messages(
+    State(state): State<AppState>,
+    Extension(request_id): Extension<RequestId>,
+    client: Option<Extension<ClientKey>>,
+    headers: HeaderMap,
+    body: Body,
+) -> axum::response::Response {
+    let start = Instant::now();
+
+    let body = match read_body(&headers, body, state.config().max_request_body_bytes).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let body = match check_json_body(&headers, body) {
+        Ok(body) => body,
+        Err(rejection) => return rejection.into_response(Some(request_id.0)),
+    };
+    let captured_body = state.history().capture_body(&body);
+    let key = client.as_ref().map(|Extension(c)| c);
+    let synthetic = synthetic::is_synthetic(&headers, key);
+    // 合成流量不受会话上限约束，也不计入会话用量
+    let conversation =
+        match check_conversation_budget(&state, &headers, &body, key.filter(|_| !synthetic)) {
+            Ok(conversation) => conversation,
+            Err(response) => return *response,
+        };
+    if !synthetic {
+        state
+            .cache_prefix()
+            .observe(&body, key.map(|c| c.name.as_str()), request_id.0);
+    }
+    let dead_letter_body = wants_dead_letter_body(&headers).then(|| body.clone());
+    let repro_body = (state.config().repro_dir.is_some() && !synthetic).then(|| body.clone());
+
+    let canaries = &state.config().canaries;
+    let skipped_transforms = if canaries.is_empty() {
+        Vec::new()
+    } else {
+        // 按会话判定，同一会话的多轮请求结果一致
+        let rollout_id = conversation_budget::conversation_id(&headers, &body)
+            .unwrap_or_else(|| request_id.0.to_string());
+        canaries.skipped(key.map(|c| c.name.as_str()), &rollout_id)
+    };
+    if !skipped_transforms.is_empty() {
+        tracing::debug!(
+            skipped = ?skipped_transforms.iter().map(Transform::as_str).collect::<Vec<_>>(),
+            "transforms held back by canary rules"
+        );
+    }
+    let mut prepared = match prepare_request(&headers, body, &skipped_transforms) {
+        Ok(prepared) => prepared,
+        Err(rejection) => return rejection.into_response(Some(request_id.0)),
+    };
+    if let Err(response) = apply_key_policy(&state, &headers, key, &mut prepared) {
+        return *response;
+    }
+    prepared.synthetic = synthetic;
+    let privacy = key.is_some_and(|c| c.policy.privacy);
+    let is_admin = key.is_some_and(|c| c.role == Role::Admin);
+    let respond = ResponseContext {
+        request_id: request_id.0,
+        is_streaming: prepared.is_streaming,
+        privacy,
+        is_admin,
+        echo_sent_headers: is_admin && wants_header_echo(&headers),
+        annotate_body: !privacy && modifications::wants_body_annotation(&headers),
+        context_warning: prepared.context_warning.take(),
+        budget: conversation.as_ref().map(|c| (c.budget, c.check)),
+    };
+    let wants_trailers = !privacy && prepared.is_streaming && trailers::accepts_trailers(&headers);
+    let model = prepared.model.clone();
+    let is_streaming = prepared.is_streaming;
+    let stream_format = prepared.stream_format;
+
+    let admission_key = key.map_or(DEFAULT_KEY_NAME, |c| c.name.as_str());
+    let Ok(permit) = state
+        .admission()
+        .acquire(admission_key, prepared.priority)
+        .await
+    else {
+        return overloaded();
+    };
+
+    let mut outcome = DispatchOutcome::default();
+    let result = dispatch(&state, prepared, &mut outcome).await;
+
+    let response = match result {
+        Ok(response) => respond.success(response),
+        Err(err) => {
+            if let Some(body) = repro_body {
+                record_repro(
+                    &state,
+                    request_id.0,
+                    outcome.provider.clone(),
+                    &model,
+                    &headers,
+                    body,
+                    &err,
+                );
+            }
+            record_dead_letter(
+                &state,
+                DeadLetter {
+                    id: format!("{}-{}", unix_timestamp_ms(), request_id.0),
+                    timestamp: unix_timestamp_secs(),
+                    request_id: request_id.0,
+                    client_key: key.map(|c| c.name.clone()),
+                    model: model.clone(),
+                    attempts: std::mem::take(&mut outcome.failures),
+                    error: format!("{:#}", err),
+                    body: dead_letter_body.and_then(|body| serde_json::from_slice(&body).ok()),
+                },
+            );
+            respond.failure(&err, &model, outcome.provider.clone())
+        }
+    };
+    let mut response = respond.finish(&state, response, &mut outcome).await;
+
+    let completion = Completion {
+        state: state.clone(),
+        span: tracing::Span::current(),
+        record: RequestRecord {
+            request_id: request_id.0,
+            timestamp: unix_timestamp_secs(),
+            provider: outcome.provider,
+            model,
+            is_streaming,
+            request_body: captured_body,
+            anthropic_beta: headers
+                .get("anthropic-beta")
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+            response_status: response.status().as_u16(),
+            usage: outcome.usage,
+            incomplete: false,
+            synthetic,
+            attempts: outcome.attempts,
+            hedge: outcome.hedge,
+            modifications: outcome.modifications,
+            sent_headers: outcome.sent_headers,
+            latency_ms: 0,
+            ttft_ms: None,
+            duration_ms: 0,
+        },
+        provider_id: outcome.provider_id,
+        tool_use_ids: outcome.tool_use_ids,
+        conversation: conversation.map(|c| (c.key, c.budget)),
+        token_limit: key.and_then(|c| Some((c.name.clone(), c.policy.tokens_per_hour?))),
+        key_name: key.map(|c| c.name.clone()),
+        effective_model: outcome.effective_model,
+    };
+
+    completion.attach(
+        &mut response,
+        outcome.stream_summary,
+        permit,
+        start,
+        stream_format,
+        wants_trailers,
+    );
+
+    response
+}
+
+/// 客户端是否选择在失败时保存请求体
+fn wants_dead_letter_body(headers: &HeaderMap) -> bool {
+    headers
+        .get(DEAD_LETTER_HEADER)
+        .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"body"))
+}
+
+/// 在后台追加死信记录
+fn record_dead_letter(state: &AppState, entry: DeadLetter) {
+    if !state.dead_letters().is_enabled() {
+        return;
+    }
+    tracing::warn!(
+        dead_letter = entry.id,
+        model = entry.model,
+        attempts = entry.attempts.len(),
+        body_stored = entry.body.is_some(),
+        "request failed on every provider, recorded dead letter"
+    );
+    let state = state.clone();
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = state.dead_letters().append(&entry, unix_timestamp_secs()) {
+            tracing::warn!("Failed to record dead letter: {:#}", e);
+        }
+    });
+}
+
+/// 上游以 400 `invalid_request_error` 拒绝时脱敏保存请求，供 `pluribus repro` 缩减
+fn record_repro(
+    state: &AppState,
+    request_id: u64,
+    provider: Option<String>,
+    model: &str,
+    headers: &HeaderMap,
+    body: Bytes,
+    err: &anyhow::Error,
+) {
+    let Some(dir) = state.config().repro_dir.clone() else {
+        return;
+    };
+    let Some(signature) = err
+        .downcast_ref::<UpstreamError>()
+        .filter(|upstream| upstream.status == StatusCode::BAD_REQUEST)
+        .and_then(|upstream| repro::error_signature(&upstream.body))
+        .filter(|signature| repro::is_invalid_request(signature))
+    else {
+        return;
+    };
+    let betas = headers
+        .get("anthropic-beta")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| {
+            v.split(',')
+                .map(str::trim)
+                .filter(|flag| !flag.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    let model = model.to_string();
+    tokio::task::spawn_blocking(move || {
+        let Ok(mut body) = serde_json::from_slice::<Value>(&body) else {
+            return;
+        };
+        repro::redact(&mut body);
+        let case = ReproCase {
+            request_id,
+            timestamp: unix_timestamp_secs(),
+            provider,
+            model,
+            error: signature,
+            envelope: Envelope { betas, body },
+        };
+        match repro::save(&dir, &case) {
+            Ok(path) => tracing::info!(
+                path = %path.display(),
+                "captured request rejected with invalid_request_error, run 'pluribus repro {request_id}' to minimize it"
+            ),
+            Err(e) => tracing::warn!("Failed to capture request for repro: {:#}", e),
+        }
+    });
+}
+
+/// 以非流式方式重新发送请求历史中保存的请求体，并带上原请求透传的 `anthropic-beta`
+pub async fn replay_request(
+    state: &AppState,
+    body: &str,
+    anthropic_beta: Option<&str>,
+) -> axum::response::Response {
+    let mut body: Value = match serde_json::from_str(body) {
+        Ok(body) => body,
+        Err(e) => return invalid_request(format!("Stored request body is not valid JSON: {e}")),
+    };
+    if let Some(obj) = body.as_object_mut() {
+        obj.insert("stream".to_string(), Value::Bool(false));
+    }
+
+    let body = match serde_json::to_vec(&body) {
+        Ok(body) => Bytes::from(body),
+        Err(e) => return error_response(e.into()),
+    };
+    let mut headers = HeaderMap::new();
+    if let Some(value) = anthropic_beta.and_then(|v| HeaderValue::from_str(v).ok()) {
+        headers.insert("anthropic-beta", value);
+    }
+    let prepared = match prepare_request(&headers, body, &[]) {
+        Ok(prepared) => prepared,
+        Err(rejection) => return rejection.into_response(None),
+    };
 
-    match result {
+    let mut outcome = DispatchOutcome::default();
+    match dispatch(state, prepared, &mut outcome).await {
         Ok(response) => response,
         Err(err) => error_response(err),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::test_router;
+    use crate::test_support::{self, MockProvider, SECRET, USER_KEY};
+    use serde_json::json;
+
+    fn request_body() -> Value {
+        json!({
+            "model": "claude-test",
+            "max_tokens": 16,
+            "messages": [{"role": "user", "content": "hi"}]
+        })
+    }
+
+    fn pinned(key: &str, provider: &str) -> axum::http::Request<axum::body::Body> {
+        let mut request = test_support::messages_request(key, &request_body());
+        request
+            .headers_mut()
+            .insert(PROVIDER_HEADER, HeaderValue::from_str(provider).unwrap());
+        request
+    }
+
+    #[tokio::test]
+    async fn provider_header_is_honored_for_user_keys() {
+        let (_dir, config) = test_support::config("");
+        let providers = [
+            Arc::new(MockProvider::new("first")),
+            Arc::new(MockProvider::new("second")),
+        ];
+        let router = test_router(test_support::state(config, &providers));
+
+        for key in [USER_KEY, SECRET] {
+            for _ in 0..3 {
+                let (status, headers, _) = test_support::send(&router, pinned(key, "second")).await;
+                assert_eq!(status, StatusCode::OK);
+                assert_eq!(headers[PROVIDER_HEADER], "second");
+            }
+        }
+        assert!(providers[0].requests().is_empty());
+        assert_eq!(providers[1].requests().len(), 6);
+    }
+
+    fn tool_use_message(id: &str) -> Value {
+        json!({
+            "id": "msg_tool",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-test",
+            "content": [{"type": "tool_use", "id": id, "name": "bash", "input": {}}],
+            "stop_reason": "tool_use",
+            "usage": {"input_tokens": 10, "output_tokens": 5}
+        })
+    }
+
+    #[tokio::test]
+    async fn tool_results_are_pinned_to_the_provider_that_issued_the_tool_use() {
+        let (_dir, config) = test_support::config("provider_selection = \"weighted\"");
+        let providers = [
+            Arc::new(MockProvider::new("first")),
+            Arc::new(MockProvider::new("second")),
+        ];
+        providers[0].respond_with(tool_use_message("toolu_first"));
+        providers[1].respond_with(tool_use_message("toolu_second"));
+        let router = test_router(test_support::state(config, &providers));
+
+        let request = test_support::messages_request(USER_KEY, &request_body());
+        let (status, headers, body) = test_support::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let issuer = headers[PROVIDER_HEADER].to_str().unwrap().to_string();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        let tool_use_id = body["content"][0]["id"].as_str().unwrap().to_string();
+
+        // 轮询本会交替选择，回传 tool_result 的请求都发往发出 tool_use 的 provider
+        let follow_up = json!({
+            "model": "claude-test",
+            "max_tokens": 16,
+            "messages": [
+                {"role": "user", "content": "hi"},
+                {"role": "assistant", "content": body["content"]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": tool_use_id, "content": "ok"}
+                ]},
+            ]
+        });
+        for _ in 0..3 {
+            let request = test_support::messages_request(USER_KEY, &follow_up);
+            let (status, headers, _) = test_support::send(&router, request).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(headers[PROVIDER_HEADER], issuer.as_str());
+        }
+
+        // 未知的 tool_result 按轮询选择
+        let unknown = follow_up.to_string().replace(&tool_use_id, "toolu_unknown");
+        let unknown: Value = serde_json::from_str(&unknown).unwrap();
+        let mut served = Vec::new();
+        for _ in 0..2 {
+            let request = test_support::messages_request(USER_KEY, &unknown);
+            let (_, headers, _) = test_support::send(&router, request).await;
+            served.push(headers[PROVIDER_HEADER].to_str().unwrap().to_string());
+        }
+        assert_ne!(served[0], served[1]);
+    }
+
+    #[tokio::test]
+    async fn conversation_budget_warns_then_blocks_until_reset() {
+        let (dir, config) = test_support::config("");
+        std::fs::write(
+            dir.path().join("keys.toml"),
+            format!(
+                "[[keys]]\nname = \"user\"\nkey = \"{USER_KEY}\"\nrole = \"user\"\n\
+                 conversation_budget = {{ soft_tokens = 20, hard_tokens = 40 }}\n"
+            ),
+        )
+        .unwrap();
+        let providers = [Arc::new(MockProvider::new("first"))];
+        let router = test_router(test_support::state(config, &providers));
+        let send = |conversation: &'static str| {
+            let router = router.clone();
+            async move {
+                let mut request = test_support::messages_request(USER_KEY, &request_body());
+                request.headers_mut().insert(
+                    conversation_budget::CONVERSATION_HEADER,
+                    HeaderValue::from_static(conversation),
+                );
+                test_support::send(&router, request).await
+            }
+        };
+
+        // 每个响应计 15 token（输入 10 + 输出 5）
+        let (status, headers, _) = send("conv").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!headers.contains_key(conversation_budget::WARNING_HEADER));
+        for used in [30, 45] {
+            let (status, headers, _) = send("conv").await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(
+                headers[conversation_budget::WARNING_HEADER],
+                format!("tokens={used}; soft_limit=20; hard_limit=40").as_str()
+            );
+        }
+        let (status, _, body) = send("conv").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "conversation_budget_exceeded");
+        assert_eq!(providers[0].requests().len(), 3);
+
+        // 其他会话不受影响
+        let (status, _, _) = send("other").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let reset = axum::http::Request::delete("/admin/conversations/user/conv")
+            .header("x-api-key", SECRET)
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let (status, _, _) = test_support::send(&router, reset).await;
+        assert!(status.is_success(), "{status}");
+        let (status, headers, _) = send("conv").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!headers.contains_key(conversation_budget::WARNING_HEADER));
+    }
+
+    fn empty_message() -> Value {
+        json!({
+            "id": "msg_empty",
+            "type": "message",
+            "role": "assistant",
+            "content": [],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 10, "output_tokens": 0}
+        })
+    }
+
+    #[tokio::test]
+    async fn strict_validation_retries_empty_content_on_another_provider() {
+        let (_dir, config) = test_support::config(
+            "response_validation = \"strict\"\nprovider_selection = \"weighted\"",
+        );
+        let providers = [
+            Arc::new(MockProvider::new("empty")),
+            Arc::new(MockProvider::new("second")),
+            Arc::new(MockProvider::new("third")),
+        ];
+        providers[0].respond_with(empty_message());
+        let router = test_router(test_support::state(config, &providers));
+
+        let mut served = Vec::new();
+        for _ in 0..4 {
+            let request = test_support::messages_request(USER_KEY, &request_body());
+            let (status, headers, body) = test_support::send(&router, request).await;
+            assert_eq!(status, StatusCode::OK);
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(
+                body["content"][0]["text"],
+                headers[PROVIDER_HEADER].to_str().unwrap()
+            );
+            served.push(headers[PROVIDER_HEADER].to_str().unwrap().to_string());
+        }
+        // 轮询仍按 empty → second → third → empty 进行，空响应换到 second 重试；
+        // 判断是否有其他 provider 时推进轮询会跳过其中一个
+        assert_eq!(served, ["second", "second", "third", "second"]);
+        assert_eq!(providers[0].requests().len(), 2);
+        assert_eq!(providers[2].requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn hedged_requests_advance_round_robin_once_and_record_both_legs() {
+        let (_dir, config) =
+            test_support::config("provider_selection = \"weighted\"\nhedge_delay_ms = 10");
+        let providers = [
+            Arc::new(MockProvider::new("first")),
+            Arc::new(MockProvider::new("second")),
+            Arc::new(MockProvider::new("third")),
+        ];
+        providers[0].fail_with(StatusCode::INTERNAL_SERVER_ERROR, "overloaded");
+        let state = test_support::state(config, &providers);
+        let router = test_router(state.clone());
+
+        let mut request = test_support::messages_request(USER_KEY, &request_body());
+        request
+            .headers_mut()
+            .insert(hedge::HEDGE_HEADER, HeaderValue::from_static("1"));
+        let (status, headers, _) = test_support::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[PROVIDER_HEADER], "second");
+
+        // 两路都计入可靠性评分
+        let failed = state.reliability().get("id-first").unwrap();
+        assert_eq!(failed.recent_errors, [ErrorClass::ServerError]);
+        assert_eq!(state.reliability().get("id-second").unwrap().streak, 1);
+
+        // 对冲只为 first 推进一次轮询，second 只是预览，下一个请求仍轮到 second
+        providers[0].recover();
+        let request = test_support::messages_request(USER_KEY, &request_body());
+        let (_, headers, _) = test_support::send(&router, request).await;
+        assert_eq!(headers[PROVIDER_HEADER], "second");
+    }
+
+    #[tokio::test]
+    async fn strict_validation_returns_empty_content_without_another_provider() {
+        let (_dir, config) = test_support::config("response_validation = \"strict\"");
+        let providers = [Arc::new(MockProvider::new("empty"))];
+        providers[0].respond_with(empty_message());
+        let router = test_router(test_support::state(config, &providers));
+
+        let request = test_support::messages_request(USER_KEY, &request_body());
+        let (status, _, body) = test_support::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["id"], "msg_empty");
+        assert_eq!(providers[0].requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn warn_validation_does_not_retry_empty_content() {
+        let (_dir, config) = test_support::config("response_validation = \"warn\"");
+        let providers = [
+            Arc::new(MockProvider::new("empty")),
+            Arc::new(MockProvider::new("healthy")),
+        ];
+        providers[0].respond_with(empty_message());
+        let router = test_router(test_support::state(config, &providers));
+
+        let request = test_support::messages_request(USER_KEY, &request_body());
+        let (status, headers, body) = test_support::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[PROVIDER_HEADER], "empty");
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["id"], "msg_empty");
+        assert!(providers[1].requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn provider_header_rejects_unknown_and_disallowed_providers() {
+        let (dir, config) = test_support::config("");
+        std::fs::write(
+            dir.path().join("keys.toml"),
+            "[[keys]]\nname = \"limited\"\nkey = \"limited-key\"\nallowed_providers = [\"first\"]\n",
+        )
+        .unwrap();
+        let providers = [
+            Arc::new(MockProvider::new("first")),
+            Arc::new(MockProvider::new("second")),
+        ];
+        let router = test_router(test_support::state(config, &providers));
+
+        let (status, _, _) = test_support::send(&router, pinned("limited-key", "missing")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _, _) = test_support::send(&router, pinned("limited-key", "second")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, headers, _) =
+            test_support::send(&router, pinned("limited-key", "first")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[PROVIDER_HEADER], "first");
+        assert!(providers[1].requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn replay_resends_passthrough_beta_header() {
+        let (_dir, config) = test_support::config("");
+        let providers = [Arc::new(MockProvider::new("first"))];
+        let state = test_support::state(config, &providers);
+        let router = test_router(state.clone());
+
+        let mut request = test_support::messages_request(USER_KEY, &request_body());
+        request
+            .headers_mut()
+            .insert("anthropic-beta", HeaderValue::from_static("test-beta-2025"));
+        let (status, _, _) = test_support::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let record = state
+            .history()
+            .recent(1, &Default::default())
+            .pop()
+            .expect("request recorded");
+        assert_eq!(record.anthropic_beta.as_deref(), Some("test-beta-2025"));
+
+        let replay =
+            axum::http::Request::post(format!("/admin/requests/{}/replay", record.request_id))
+                .header("x-api-key", SECRET)
+                .body(axum::body::Body::empty())
+                .unwrap();
+        let (status, _, _) = test_support::send(&router, replay).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let requests = providers[0].requests();
+        assert_eq!(requests.len(), 2);
+        for body in &requests {
+            let body: Value = serde_json::from_slice(body).unwrap();
+            assert_eq!(
+                body["_passthrough_headers"]["anthropic-beta"],
+                "test-beta-2025"
+            );
+        }
+    }
+
+    fn raw_request(
+        content_type: Option<&str>,
+        body: &'static [u8],
+    ) -> axum::http::Request<axum::body::Body> {
+        let mut request =
+            axum::http::Request::post("/anthropic/v1/messages").header("x-api-key", USER_KEY);
+        if let Some(content_type) = content_type {
+            request = request.header("content-type", content_type);
+        }
+        request.body(axum::body::Body::from(body)).unwrap()
+    }
+
+    /// 等待后台任务在 `dir` 中写出的复现文件
+    async fn captured_repros(dir: &std::path::Path, expected: usize) -> Vec<ReproCase> {
+        for _ in 0..100 {
+            let cases: Vec<ReproCase> = std::fs::read_dir(dir)
+                .into_iter()
+                .flatten()
+                .map(|entry| {
+                    serde_json::from_slice(&std::fs::read(entry.unwrap().path()).unwrap()).unwrap()
+                })
+                .collect();
+            if cases.len() >= expected {
+                return cases;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        panic!("expected {expected} captured requests in {}", dir.display());
+    }
+
+    #[tokio::test]
+    async fn invalid_requests_are_captured_redacted() {
+        let (dir, mut config) = test_support::config("");
+        let repro_dir = dir.path().join("repro");
+        config.repro_dir = Some(repro_dir.clone());
+        let providers = [Arc::new(MockProvider::new("first"))];
+        let router = test_router(test_support::state(config, &providers));
+        let body = json!({
+            "model": "claude-test",
+            "max_tokens": 16,
+            "messages": [{"role": "user", "content": "my password is hunter2"}]
+        });
+        let send = |beta: Option<&'static str>| {
+            let mut request = test_support::messages_request(USER_KEY, &body);
+            if let Some(beta) = beta {
+                request
+                    .headers_mut()
+                    .insert("anthropic-beta", HeaderValue::from_static(beta));
+            }
+            test_support::send(&router, request)
+        };
+
+        // 其它错误不保存
+        providers[0].fail_with(
+            StatusCode::BAD_REQUEST,
+            r#"{"type":"error","error":{"type":"overloaded_error","message":"busy"}}"#,
+        );
+        send(None).await;
+        providers[0].fail_with(StatusCode::INTERNAL_SERVER_ERROR, "boom");
+        send(None).await;
+
+        providers[0].fail_with(
+            StatusCode::BAD_REQUEST,
+            r#"{"type":"error","error":{"type":"invalid_request_error","message":"messages.0.content: too long"}}"#,
+        );
+        let (status, _, _) = send(Some("beta-a, beta-b")).await;
+        assert!(status.is_client_error() || status.is_server_error());
+
+        let cases = captured_repros(&repro_dir, 1).await;
+        assert_eq!(cases.len(), 1);
+        let case = &cases[0];
+        assert_eq!(case.provider.as_deref(), Some("first"));
+        assert_eq!(case.model, "claude-test");
+        assert_eq!(
+            case.error,
+            "invalid_request_error: messages.N.content: too long"
+        );
+        assert_eq!(case.envelope.betas, ["beta-a", "beta-b"]);
+        assert_eq!(
+            case.envelope.body["messages"][0]["content"],
+            "xx xxxxxxxx xx xxxxxxx"
+        );
+        assert_eq!(case.envelope.body["max_tokens"], 16);
+        let saved = std::fs::read_to_string(repro::case_path(&repro_dir, case.request_id)).unwrap();
+        assert!(!saved.contains("hunter2"));
+    }
+
+    #[tokio::test]
+    async fn malformed_bodies_get_coded_json_errors() {
+        let (_dir, config) = test_support::config("");
+        let providers = [Arc::new(MockProvider::new("first"))];
+        let router = test_router(test_support::state(config, &providers));
+
+        let cases: [(Option<&str>, &[u8], StatusCode, &str); 7] = [
+            (
+                Some("text/plain"),
+                b"{}",
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_media_type",
+            ),
+            (
+                None,
+                b"{}",
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_media_type",
+            ),
+            (
+                Some("application/json"),
+                b"",
+                StatusCode::BAD_REQUEST,
+                "empty_body",
+            ),
+            (
+                Some("application/json"),
+                b" \n",
+                StatusCode::BAD_REQUEST,
+                "empty_body",
+            ),
+            (
+                Some("application/json"),
+                b"{\"model\":",
+                StatusCode::BAD_REQUEST,
+                "invalid_json",
+            ),
+            (
+                Some("application/json"),
+                b"[]",
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+            ),
+            (
+                Some("application/json"),
+                b"\"hi\"",
+                StatusCode::BAD_REQUEST,
+                "invalid_request_error",
+            ),
+        ];
+        for (content_type, body, expected, code) in cases {
+            let (status, _, response) =
+                test_support::send(&router, raw_request(content_type, body)).await;
+            let response: Value = serde_json::from_slice(&response).unwrap();
+            let case = String::from_utf8_lossy(body);
+            assert_eq!(status, expected, "{case}");
+            assert_eq!(response["type"], "invalid_request_error", "{case}");
+            assert_eq!(response["code"], code, "{case}");
+            assert!(response["request_id"].is_u64(), "{case}");
+        }
+        assert!(providers[0].requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn bom_prefixed_json_is_accepted() {
+        let (_dir, config) = test_support::config("");
+        let providers = [Arc::new(MockProvider::new("first"))];
+        let router = test_router(test_support::state(config, &providers));
+
+        let body = b"\xEF\xBB\xBF{\"model\":\"claude-test\",\"max_tokens\":16,\"messages\":[]}";
+        let (status, _, _) =
+            test_support::send(&router, raw_request(Some("application/json"), body)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(providers[0].requests().len(), 1);
+    }
+
+    /// 可走快速路径的请求体：带身份提示词，保留客户端的空白、字段顺序与转义写法
+    fn fast_path_body(stream: bool) -> String {
+        format!(
+            "{{ \"model\":\"claude-test\",\n  \"stream\": {stream},\"max_tokens\" : 16,\
+             \"system\":[{{\"type\":\"text\",\"text\":\"{CLAUDE_CODE_IDENTITY}\"}}],\n\
+             \"messages\":[{{\"role\":\"user\",\"content\":\"caf\\u00e9 \\/ 1.0e0\"}}],\
+             \"metadata\":{{\"user_id\":\"u\"}}   }}"
+        )
+    }
+
+    #[test]
+    fn fast_path_is_taken_for_plain_requests() {
+        let body = Bytes::from(fast_path_body(false));
+        let prepared = prepare_request(&HeaderMap::new(), body.clone(), &[])
+            .ok()
+            .unwrap();
+        assert!(matches!(&prepared.outbound, OutboundBody::Raw(raw) if *raw == body));
+        assert_eq!(prepared.model, "claude-test");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("anthropic-beta", HeaderValue::from_static("test-beta"));
+        let prepared = prepare_request(&headers, body, &[]).ok().unwrap();
+        assert!(matches!(prepared.outbound, OutboundBody::Parsed(_)));
+    }
+
+    #[tokio::test]
+    async fn fast_path_forwards_body_byte_identical() {
+        let (_dir, config) = test_support::config("");
+        let providers = [Arc::new(MockProvider::new("first"))];
+        let router = test_router(test_support::state(config, &providers));
+
+        for stream in [false, true] {
+            let body = fast_path_body(stream);
+            let request = axum::http::Request::post("/anthropic/v1/messages")
+                .header("x-api-key", USER_KEY)
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(body.clone()))
+                .unwrap();
+            let (status, _, _) = test_support::send(&router, request).await;
+            assert_eq!(status, StatusCode::OK);
+            let forwarded = providers[0].requests().pop().unwrap();
+            assert_eq!(forwarded, body.as_bytes(), "stream: {stream}");
+        }
+    }
+
+    /// 各 provider 在已收到 `seen` 个请求之后收到的请求体是同一块缓冲区，内容与第一次尝试一致
+    fn assert_shared_body(providers: &[Arc<MockProvider>], seen: &[usize]) -> Vec<Bytes> {
+        let bodies: Vec<Bytes> = providers
+            .iter()
+            .zip(seen)
+            .flat_map(|(p, seen)| p.requests().split_off(*seen))
+            .collect();
+        assert!(bodies.len() > 1, "only {} attempt", bodies.len());
+        for body in &bodies[1..] {
+            assert_eq!(body, &bodies[0]);
+            assert_eq!(body.as_ptr(), bodies[0].as_ptr(), "body was re-encoded");
+        }
+        bodies
+    }
+
+    #[tokio::test]
+    async fn retries_reuse_the_encoded_body() {
+        let (_dir, config) = test_support::config("");
+        let providers = [
+            Arc::new(MockProvider::new("first")),
+            Arc::new(MockProvider::new("second")),
+            Arc::new(MockProvider::new("third")),
+        ];
+        providers[0].fail_with(StatusCode::SERVICE_UNAVAILABLE, "overloaded");
+        providers[1].fail_with(StatusCode::SERVICE_UNAVAILABLE, "overloaded");
+        let router = test_router(test_support::state(config, &providers));
+
+        // 快速路径转发原始字节，带 anthropic-beta 时走完整解析并编码一次
+        for beta in [None, Some("test-beta")] {
+            let body = fast_path_body(false);
+            let mut request = axum::http::Request::post("/anthropic/v1/messages")
+                .header("x-api-key", USER_KEY)
+                .header("content-type", "application/json");
+            if let Some(beta) = beta {
+                request = request.header("anthropic-beta", beta);
+            }
+            let request = request.body(axum::body::Body::from(body.clone())).unwrap();
+            let seen: Vec<usize> = providers.iter().map(|p| p.requests().len()).collect();
+            let (status, headers, _) = test_support::send(&router, request).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(headers[PROVIDER_HEADER], "third");
+            let forwarded = assert_shared_body(&providers, &seen);
+            if beta.is_none() {
+                assert_eq!(forwarded[0], body.as_bytes());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn hedged_attempts_share_the_encoded_body() {
+        let (_dir, config) = test_support::config("hedge_delay_ms = 10");
+        let providers = [
+            Arc::new(MockProvider::new("first")),
+            Arc::new(MockProvider::new("second")),
+        ];
+        providers[0].fail_with(StatusCode::SERVICE_UNAVAILABLE, "overloaded");
+        let router = test_router(test_support::state(config, &providers));
+
+        let mut request = test_support::messages_request(USER_KEY, &request_body());
+        request
+            .headers_mut()
+            .insert(hedge::HEDGE_HEADER, HeaderValue::from_static("1"));
+        let (status, headers, _) = test_support::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[PROVIDER_HEADER], "second");
+        assert_eq!(assert_shared_body(&providers, &[0, 0]).len(), 2);
+    }
+
+    /// 快速路径与完整解析路径准备请求（含重新序列化）的耗时对比
+    ///
+    /// `cargo test --release -- --ignored --nocapture bench_`
+    #[test]
+    #[ignore]
+    fn bench_prepare_request() {
+        let text = "lorem ipsum dolor sit amet ".repeat(40);
+        let messages: Vec<Value> = (0..200)
+            .map(|i| {
+                let role = if i % 2 == 0 { "user" } else { "assistant" };
+                json!({"role": role, "content": [{"type": "text", "text": text}]})
+            })
+            .collect();
+        let body = json!({
+            "model": "claude-test",
+            "max_tokens": 1024,
+            "system": [{"type": "text", "text": CLAUDE_CODE_IDENTITY}],
+            "messages": messages,
+        });
+        let body = Bytes::from(serde_json::to_vec(&body).unwrap());
+        let mut passthrough = HeaderMap::new();
+        passthrough.insert("anthropic-beta", HeaderValue::from_static("test-beta"));
+
+        const ITERATIONS: u32 = 200;
+        for (label, headers) in [("fast", HeaderMap::new()), ("full", passthrough)] {
+            let start = Instant::now();
+            for _ in 0..ITERATIONS {
+                let prepared = prepare_request(&headers, body.clone(), &[]).ok().unwrap();
+                // 完整解析路径发送前还需重新序列化
+                if let OutboundBody::Parsed(parsed) = &prepared.outbound {
+                    std::hint::black_box(serde_json::to_vec(parsed).unwrap());
+                }
+                std::hint::black_box(prepared);
+            }
+            let per_request = start.elapsed() / ITERATIONS;
+            println!("{label}: {} bytes, {per_request:?} per request", body.len());
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! 路由未注册方法的处理器
+//!
+//! 部分 HTTP 客户端与可用性检查会向 API 路由发送 OPTIONS / HEAD / GET。每个 API 路由都以
+//! [`allow`] 注册 method fallback：OPTIONS（认证中间件直接放行） 返回 204 与 `Allow`，其他方法返回
+//! 说明支持哪些方法的 JSON 405，而不是 axum 默认的空响应。
+
+use axum::{
+    http::{header, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+};
+
+use super::api_error;
+
+/// POST 路由（如 `/anthropic/v1/messages`）支持的方法
+pub const POST_METHODS: &str = "POST, OPTIONS";
+
+/// 只读路由支持的方法（GET 路由同时响应 HEAD）
+pub const READ_METHODS: &str = "GET, HEAD, OPTIONS";
+
+/// DELETE 路由支持的方法
+pub const DELETE_METHODS: &str = "DELETE, OPTIONS";
+
+/// 以 [`handle_unsupported_method`] 作为路由的 method fallback
+pub fn allow<S>(route: MethodRouter<S>, allowed: &'static str) -> MethodRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    route.fallback(move |method, uri| handle_unsupported_method(allowed, method, uri))
+}
+
+/// 路由未注册的方法，`allowed` 为该路由支持的方法
+pub async fn handle_unsupported_method(
+    allowed: &'static str,
+    method: Method,
+    uri: Uri,
+) -> Response {
+    if method == Method::OPTIONS {
+        return (StatusCode::NO_CONTENT, [(header::ALLOW, allowed)]).into_response();
+    }
+    let mut response = api_error(
+        StatusCode::METHOD_NOT_ALLOWED,
+        "invalid_request_error",
+        format!(
+            "{method} is not supported on {}, allowed methods: {allowed}",
+            uri.path()
+        ),
+    );
+    response
+        .headers_mut()
+        .insert(header::ALLOW, header::HeaderValue::from_static(allowed));
+    response
+}
@@ -0,0 +1,34 @@
+//! Prometheus 指标处理器
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::gateway::state::AppState;
+use crate::metrics::{self, ProviderGauges};
+
+/// Prometheus 文本格式的 Content-Type
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// GET /metrics
+pub async fn handle_metrics(State(state): State<AppState>) -> Response {
+    let providers = state.providers();
+    let gauges: Vec<ProviderGauges> = providers
+        .iter()
+        .map(|p| ProviderGauges {
+            provider: p.name(),
+            utilization: p
+                .rate_limit_info()
+                .filter(|info| info.updated_at > 0)
+                .map(|info| (info.five_hour.utilization, info.seven_day.utilization)),
+            in_flight: state.in_flight().get(p.id()),
+        })
+        .collect();
+
+    match metrics::render(&gauges) {
+        Some(body) => ([(header::CONTENT_TYPE, CONTENT_TYPE)], body).into_response(),
+        None => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
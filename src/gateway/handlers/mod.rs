@@ -1,25 +1,337 @@
 //! HTTP 请求处理器
 
+pub mod admin;
+pub mod capabilities;
+pub mod count_tokens;
 pub mod health;
 pub mod messages;
+pub mod methods;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod route_preview;
+#[cfg(feature = "usage-sqlite")]
+pub mod usage;
 
-pub use health::handle_health;
+pub use admin::{
+    handle_abort_stream, handle_admin_info, handle_deprecations, handle_get_request,
+    handle_list_conversations, handle_list_providers, handle_list_requests, handle_list_streams,
+    handle_provider_headers, handle_reload_providers, handle_replay_request,
+    handle_reset_conversation, handle_reset_provider_reliability, handle_reset_reliability,
+    handle_stream_requests,
+};
+pub use capabilities::handle_capabilities;
+pub use count_tokens::handle_count_tokens;
+pub use health::{handle_health, handle_health_details, handle_livez, handle_starting};
 pub use messages::handle_anthropic_messages;
+pub use methods::{allow, handle_unsupported_method, DELETE_METHODS, POST_METHODS, READ_METHODS};
+#[cfg(feature = "metrics")]
+pub use metrics::handle_metrics;
+pub use route_preview::handle_route_preview;
+#[cfg(feature = "usage-sqlite")]
+pub use usage::handle_usage;
 
 use axum::{http::StatusCode, response::IntoResponse, Json};
 use serde::Serialize;
 
+use crate::providers::{UpstreamError, UpstreamErrorDetail};
+
+/// 返回给客户端的错误响应体
+///
+/// `type` 与 Anthropic 的错误类型对应，`code` 为网关的稳定错误码，`message` 不含上游原始
+/// 响应体。转发失败时带 `request_id` 与 `upstream`。
 #[derive(Serialize)]
 struct ErrorResponse {
     #[serde(rename = "type")]
     error_type: &'static str,
+    code: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstream: Option<UpstreamInfo>,
+}
+
+/// 上游返回的错误
+#[derive(Serialize)]
+struct UpstreamInfo {
+    status: u16,
+    #[serde(flatten)]
+    detail: UpstreamErrorDetail,
+    /// 出错的 provider，只返回给 admin 角色的密钥
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provider: Option<String>,
+}
+
+/// 构建 JSON 错误响应，`code` 与 `type` 相同
+fn api_error(
+    status: StatusCode,
+    error_type: &'static str,
     message: String,
+) -> axum::response::Response {
+    let error = ErrorResponse {
+        error_type,
+        code: error_type,
+        message,
+        request_id: None,
+        upstream: None,
+    };
+    (status, Json(error)).into_response()
 }
 
 fn error_response(err: anyhow::Error) -> axum::response::Response {
+    api_error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "error",
+        format!("{:#}", err),
+    )
+}
+
+/// 转发失败：无法连接上游时 502，等待上游超时时 504，其他情况 500
+///
+/// 错误信息按类别给出，不含上游原始响应体与 provider 名称；上游返回了错误时，`upstream`
+/// 只包含从响应体解析出的类型与信息。`provider` 仅对 admin 角色的密钥传入。
+/// 隐私模式下只返回通用错误信息。
+fn forward_failed(
+    err: &anyhow::Error,
+    request_id: u64,
+    provider: Option<String>,
+    privacy: bool,
+) -> axum::response::Response {
+    let upstream_error = err.downcast_ref::<UpstreamError>();
+    let transport_error = err
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<reqwest::Error>());
+    let (status, code, message) = match (upstream_error, transport_error) {
+        (Some(upstream), _) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "upstream_error",
+            format!(
+                "Upstream request failed with status {}",
+                upstream.status.as_u16()
+            ),
+        ),
+        (None, Some(e)) if e.is_timeout() => (
+            StatusCode::GATEWAY_TIMEOUT,
+            "upstream_timeout",
+            "Timed out waiting for the upstream API".to_string(),
+        ),
+        (None, Some(_)) => (
+            StatusCode::BAD_GATEWAY,
+            "upstream_unavailable",
+            "Failed to reach the upstream API".to_string(),
+        ),
+        (None, None) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            "Request failed".to_string(),
+        ),
+    };
+    let (code, message) = if privacy {
+        (
+            "request_failed",
+            crate::gateway::privacy::GENERIC_ERROR.to_string(),
+        )
+    } else {
+        (code, message)
+    };
+    let upstream = upstream_error
+        .filter(|_| !privacy)
+        .map(|upstream| UpstreamInfo {
+            status: upstream.status.as_u16(),
+            detail: upstream.detail(),
+            provider,
+        });
+    let error = ErrorResponse {
+        error_type: "api_error",
+        code,
+        message,
+        request_id: Some(request_id),
+        upstream,
+    };
+    (status, Json(error)).into_response()
+}
+
+/// 400 错误：请求体无效
+fn invalid_request(message: String) -> axum::response::Response {
+    api_error(StatusCode::BAD_REQUEST, "invalid_request_error", message)
+}
+
+/// 请求被拒绝：`type` 为 `invalid_request_error`，`code` 给出具体原因，带 `request_id`
+fn request_rejected(
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    request_id: Option<u64>,
+) -> axum::response::Response {
     let error = ErrorResponse {
-        error_type: "error",
-        message: format!("{:#}", err),
+        error_type: "invalid_request_error",
+        code,
+        message,
+        request_id,
+        upstream: None,
     };
-    (StatusCode::INTERNAL_SERVER_ERROR, Json(error)).into_response()
+    (status, Json(error)).into_response()
+}
+
+/// 413 错误：请求体超过大小上限
+fn request_too_large(limit: usize) -> axum::response::Response {
+    api_error(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        "request_too_large",
+        format!("Request body exceeds the {limit} byte limit"),
+    )
+}
+
+/// 503 错误：排队已满，请求被拒绝或丢弃
+fn overloaded() -> axum::response::Response {
+    api_error(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "overloaded_error",
+        "Gateway is overloaded, try again later".to_string(),
+    )
+}
+
+/// 429 错误：所有 provider 都被上游 rate limit 拒绝，`Retry-After` 为最早的重置时间
+fn rate_limited(retry_after_secs: u64) -> axum::response::Response {
+    let mut response = api_error(
+        StatusCode::TOO_MANY_REQUESTS,
+        "rate_limit_error",
+        format!("All providers are rate limited, retry in {retry_after_secs} seconds"),
+    );
+    response
+        .headers_mut()
+        .insert(axum::http::header::RETRY_AFTER, retry_after_secs.into());
+    response
+}
+
+/// 502 错误：上游非流式响应体超过大小上限
+fn upstream_response_too_large(
+    err: &crate::providers::ResponseTooLarge,
+) -> axum::response::Response {
+    api_error(
+        StatusCode::BAD_GATEWAY,
+        "upstream_response_too_large",
+        err.to_string(),
+    )
+}
+
+/// 403 错误：会话累计用量超过硬上限，不可重试
+fn conversation_budget_exceeded(message: String) -> axum::response::Response {
+    api_error(
+        StatusCode::FORBIDDEN,
+        "conversation_budget_exceeded",
+        message,
+    )
+}
+
+/// 404 错误：请求指定的资源不存在
+fn not_found(message: String) -> axum::response::Response {
+    api_error(StatusCode::NOT_FOUND, "not_found_error", message)
+}
+
+/// 403 错误：密钥策略不允许该操作
+fn permission_denied(message: String) -> axum::response::Response {
+    api_error(StatusCode::FORBIDDEN, "permission_error", message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::test_router;
+    use crate::test_support::{self, MockProvider, SECRET, USER_KEY};
+    use anyhow::Context;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    async fn error_body(response: axum::response::Response) -> (StatusCode, Value) {
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn unreachable_upstreams_get_502_and_timeouts_504() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed = listener.local_addr().unwrap();
+        drop(listener);
+        let refused = reqwest::get(format!("http://{closed}/"))
+            .await
+            .context("send request")
+            .unwrap_err();
+
+        let server = wiremock::MockServer::start().await;
+        wiremock::Mock::given(wiremock::matchers::any())
+            .respond_with(wiremock::ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+            .mount(&server)
+            .await;
+        let timed_out = reqwest::Client::builder()
+            .timeout(Duration::from_millis(50))
+            .build()
+            .unwrap()
+            .get(server.uri())
+            .send()
+            .await
+            .context("send request")
+            .unwrap_err();
+
+        let (status, body) = error_body(forward_failed(&refused, 1, None, false)).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body["code"], "upstream_unavailable");
+        assert_eq!(body["request_id"], 1);
+        assert!(body.get("upstream").is_none());
+
+        let (status, body) = error_body(forward_failed(&timed_out, 2, None, false)).await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(body["code"], "upstream_timeout");
+
+        // 隐私模式保留状态码，只隐藏错误信息
+        let (status, body) = error_body(forward_failed(&timed_out, 3, None, true)).await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(body["code"], "request_failed");
+
+        let other = anyhow::anyhow!("no providers");
+        let (status, body) = error_body(forward_failed(&other, 4, None, false)).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["code"], "internal_error");
+        assert_eq!(body["message"], "Request failed");
+    }
+
+    /// 错误响应体不含上游原始响应体；provider 名称只出现在 admin 的 `upstream.provider` 中
+    #[tokio::test]
+    async fn upstream_errors_hide_the_raw_body_and_provider_from_non_admins() {
+        let (_dir, config) = test_support::config("");
+        let providers = [Arc::new(MockProvider::new("first"))];
+        providers[0].fail_with(
+            StatusCode::BAD_REQUEST,
+            r#"{"type":"error","error":{"type":"invalid_request_error","message":"max_tokens too large"},"trace":"internal-trace-7f3a"}"#,
+        );
+        let router = test_router(test_support::state(config, &providers));
+        let request = json!({
+            "model": "claude-test",
+            "max_tokens": 16,
+            "messages": [{"role": "user", "content": "hi"}]
+        });
+
+        let (status, _, body) =
+            test_support::send(&router, test_support::messages_request(USER_KEY, &request)).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(!text.contains("internal-trace-7f3a"), "{text}");
+        assert!(!text.contains("first"), "{text}");
+        let body: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(body["code"], "upstream_error");
+        assert_eq!(
+            body["upstream"],
+            json!({"status": 400, "type": "invalid_request_error", "message": "max_tokens too large"})
+        );
+
+        let (_, _, body) =
+            test_support::send(&router, test_support::messages_request(SECRET, &request)).await;
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["upstream"]["provider"], "first");
+        assert!(!body.to_string().contains("internal-trace-7f3a"));
+    }
 }
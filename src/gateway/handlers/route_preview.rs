@@ -0,0 +1,265 @@
+//! 路由预览
+//!
+//! 按当前 provider 状态给出请求会被发往哪个 provider，但不发送请求：不推进加权轮询，也不
+//! 记录 tool-use 固定。选择逻辑与 `dispatch` 首次选择一致，重试与换 provider 不在预览范围内。
+
+use axum::{
+    body::Bytes,
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::gateway::handlers::{invalid_request, not_found};
+use crate::gateway::pinning;
+use crate::gateway::state::{AppState, SkipReason};
+use crate::providers::ProviderType;
+use crate::utils::extract_model;
+
+/// 预览请求可带的字段，对应 `X-Pluribus-Provider`
+const PROVIDER_FIELD: &str = "provider";
+
+/// 选出 provider 的依据
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Decision {
+    /// 请求指定了 provider
+    Forced,
+    /// 回传的 tool_result 固定到处理对应 tool_use 的 provider
+    Pinned,
+    /// 按 `PLURIBUS_PROVIDER_SELECTION` 选择
+    Strategy,
+}
+
+/// tool-use 固定的预览
+#[derive(Serialize)]
+struct PinPreview {
+    provider_id: String,
+    provider: Option<String>,
+    /// 固定的 provider 可用，本次请求会发往该 provider
+    applies: bool,
+}
+
+/// 单个 provider 的候选状态
+#[derive(Serialize)]
+struct CandidatePreview {
+    name: String,
+    #[serde(rename = "type")]
+    provider_type: ProviderType,
+    serves_messages: bool,
+    weight: u32,
+    in_flight: i64,
+    /// 暂不参与选择的原因，可以选择时为 `null`
+    skip: Option<SkipReason>,
+}
+
+#[derive(Serialize)]
+struct RoutePreview {
+    strategy: &'static str,
+    model: String,
+    decision: Option<Decision>,
+    selected: Option<String>,
+    pinned: Option<PinPreview>,
+    /// 没有可选 provider 且全部被上游 rate limit 拒绝时，最早的重置时间
+    rate_limited_until: Option<u64>,
+    candidates: Vec<CandidatePreview>,
+}
+
+/// POST /admin/route-preview
+///
+/// 请求体为 Messages 请求（可只含 `model`），可带 `provider` 字段模拟 `X-Pluribus-Provider`
+pub async fn handle_route_preview(State(state): State<AppState>, body: Bytes) -> Response {
+    let Ok(body) = serde_json::from_slice::<serde_json::Value>(&body) else {
+        return invalid_request("Request body must be a JSON object".to_string());
+    };
+    if !body.is_object() {
+        return invalid_request("Request body must be a JSON object".to_string());
+    }
+    let forced = body.get(PROVIDER_FIELD).and_then(|v| v.as_str());
+
+    let mut preview = RoutePreview {
+        strategy: state.config().provider_selection.as_str(),
+        model: extract_model(&body),
+        decision: None,
+        selected: None,
+        pinned: None,
+        rate_limited_until: None,
+        candidates: state
+            .providers()
+            .iter()
+            .map(|p| CandidatePreview {
+                name: p.name().to_string(),
+                provider_type: p.provider_type(),
+                serves_messages: p.provider_type().serves_messages(),
+                weight: state.weight(p),
+                in_flight: state.in_flight().get(p.id()),
+                skip: state.skip_reason(p),
+            })
+            .collect(),
+    };
+
+    let selected = match forced {
+        Some(name) => {
+            let Some(provider) = state
+                .provider_by_name(name)
+                .filter(|p| p.provider_type().serves_messages())
+            else {
+                return not_found(format!("Provider {name} not found"));
+            };
+            preview.decision = Some(Decision::Forced);
+            Some(provider)
+        }
+        None => {
+            let pinned = state
+                .pins()
+                .lookup(&pinning::tool_result_ids(&body))
+                .map(|id| {
+                    let provider = state.preview_next_provider(|p| {
+                        p.provider_type().serves_messages() && p.id() == id
+                    });
+                    (id, provider)
+                });
+            let pinned_provider = pinned.as_ref().and_then(|(_, p)| p.clone());
+            preview.pinned = pinned.map(|(id, provider)| PinPreview {
+                provider: name_of(&state, &id),
+                applies: provider.is_some(),
+                provider_id: id,
+            });
+            match pinned_provider {
+                Some(provider) => {
+                    preview.decision = Some(Decision::Pinned);
+                    Some(provider)
+                }
+                None => {
+                    let provider =
+                        state.preview_next_provider(|p| p.provider_type().serves_messages());
+                    preview.decision = provider.as_ref().map(|_| Decision::Strategy);
+                    provider
+                }
+            }
+        }
+    };
+
+    match selected {
+        Some(provider) => preview.selected = Some(provider.name().to_string()),
+        None => {
+            preview.rate_limited_until =
+                state.all_rejected_until(|p| p.provider_type().serves_messages())
+        }
+    }
+    Json(preview).into_response()
+}
+
+fn name_of(state: &AppState, id: &str) -> Option<String> {
+    state
+        .providers()
+        .iter()
+        .find(|p| p.id() == id)
+        .map(|p| p.name().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gateway::state::AppState;
+    use crate::gateway::test_router;
+    use crate::providers::Provider;
+    use crate::test_support::{self, MockProvider, READONLY_KEY, USER_KEY};
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use axum::Router;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    async fn preview(router: &Router) -> Value {
+        let request = Request::post("/admin/route-preview")
+            .header("x-api-key", READONLY_KEY)
+            .header("content-type", "application/json")
+            .body(Body::from(json!({"model": "claude-test"}).to_string()))
+            .unwrap();
+        let (status, _, body) = test_support::send(router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn body(stream: bool) -> Value {
+        json!({
+            "model": "claude-test",
+            "max_tokens": 16,
+            "stream": stream,
+            "messages": [{"role": "user", "content": "hi"}]
+        })
+    }
+
+    #[tokio::test]
+    async fn preview_does_not_advance_weighted_round_robin() {
+        let (_dir, config) = test_support::config("provider_selection = \"weighted\"");
+        let keys = test_support::keys(&config);
+        let providers = [
+            Arc::new(MockProvider::new("heavy")),
+            Arc::new(MockProvider::new("light")),
+        ];
+        let state = AppState::with_weights(
+            vec![
+                (providers[0].clone() as Arc<dyn Provider>, 2),
+                (providers[1].clone() as Arc<dyn Provider>, 1),
+            ],
+            config,
+            keys,
+        );
+        let router = test_router(state);
+
+        let mut sent = Vec::new();
+        for _ in 0..6 {
+            let expected = preview(&router).await["selected"].clone();
+            // 重复预览结果不变
+            for _ in 0..3 {
+                let again = preview(&router).await;
+                assert_eq!(again["selected"], expected);
+                assert_eq!(again["decision"], "strategy");
+            }
+            let request = test_support::messages_request(USER_KEY, &body(false));
+            let (status, headers, _) = test_support::send(&router, request).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(headers["x-pluribus-provider"], expected.as_str().unwrap());
+            sent.push(expected.as_str().unwrap().to_string());
+        }
+        // 预览穿插其中，实际请求仍按 2:1 的平滑加权顺序分配
+        assert_eq!(sent, ["heavy", "light", "heavy", "heavy", "light", "heavy"]);
+    }
+
+    #[tokio::test]
+    async fn preview_does_not_change_in_flight_counts() {
+        let (_dir, config) = test_support::config("provider_selection = \"least_connections\"");
+        let providers = [
+            Arc::new(MockProvider::new("first")),
+            Arc::new(MockProvider::new("second")),
+        ];
+        let state = test_support::state(config, &providers);
+        let router = test_router(state.clone());
+
+        // 持有一个流式响应，first 上有一个转发中的请求
+        let held = router
+            .clone()
+            .oneshot(test_support::messages_request(USER_KEY, &body(true)))
+            .await
+            .unwrap();
+        assert_eq!(held.headers()["x-pluribus-provider"], "first");
+
+        for _ in 0..3 {
+            let preview = preview(&router).await;
+            assert_eq!(preview["selected"], "second");
+            assert_eq!(preview["candidates"][0]["in_flight"], 1);
+            assert_eq!(preview["candidates"][1]["in_flight"], 0);
+            assert_eq!(state.in_flight().get(providers[0].id()), 1);
+            assert_eq!(state.in_flight().get(providers[1].id()), 0);
+        }
+
+        let request = test_support::messages_request(USER_KEY, &body(false));
+        let (_, headers, _) = test_support::send(&router, request).await;
+        assert_eq!(headers["x-pluribus-provider"], "second");
+        drop(held);
+        assert_eq!(preview(&router).await["candidates"][0]["in_flight"], 0);
+    }
+}
@@ -0,0 +1,66 @@
+//! 用量历史查询
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Deserialize;
+
+use crate::gateway::handlers::{api_error, invalid_request, not_found};
+use crate::gateway::state::AppState;
+use crate::usage::UsageFilter;
+use crate::utils::parse_date_or_timestamp;
+
+/// `GET /usage` 的查询参数，`from` / `to` 为 `YYYY-MM-DD`（UTC）或 Unix timestamp
+#[derive(Deserialize)]
+pub struct UsageQuery {
+    from: Option<String>,
+    to: Option<String>,
+    provider: Option<String>,
+    model: Option<String>,
+}
+
+/// 解析失败时返回错误信息
+fn parse_bound(name: &str, value: Option<String>) -> Result<Option<u64>, String> {
+    value
+        .map(|value| {
+            parse_date_or_timestamp(&value)
+                .ok_or_else(|| format!("{name} must be YYYY-MM-DD or a Unix timestamp: {value}"))
+        })
+        .transpose()
+}
+
+/// GET /usage
+///
+/// 汇总 `[from, to)` 内的用量，并按 provider、密钥与模型分组
+pub async fn handle_usage(
+    State(state): State<AppState>,
+    Query(query): Query<UsageQuery>,
+) -> Response {
+    let Some(store) = state.usage() else {
+        return not_found("Usage history is disabled (PLURIBUS_USAGE_DB is empty)".to_string());
+    };
+    let filter = match (parse_bound("from", query.from), parse_bound("to", query.to)) {
+        (Ok(from), Ok(to)) => UsageFilter {
+            from,
+            to,
+            provider: query.provider,
+            model: query.model,
+        },
+        (Err(message), _) | (_, Err(message)) => return invalid_request(message),
+    };
+
+    match store.report(filter).await {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => {
+            tracing::warn!("Usage query failed: {:#}", e);
+            api_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "api_error",
+                "Failed to query usage history".to_string(),
+            )
+        }
+    }
+}
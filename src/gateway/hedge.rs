@@ -0,0 +1,208 @@
+//! 请求对冲
+//!
+//! 对延迟敏感的小请求（标题生成、分类等），同时发往两个 provider 并取先成功的响应：
+//! 第二个请求延迟一小段时间启动，任一方成功后取消另一方。
+//! 仅对非流式、`max_tokens` 与输入长度都在上限内且不含 tools 的请求生效。
+
+use axum::http::HeaderMap;
+use serde::Serialize;
+use serde_json::Value;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::keys::ClientKey;
+use crate::utils::count_text_chars;
+
+/// 按请求开启 / 关闭对冲的 header，优先于密钥配置
+pub const HEDGE_HEADER: &str = "x-pluribus-hedge";
+
+/// 客户端是否要求对冲：header 优先，否则看密钥配置
+pub fn is_requested(headers: &HeaderMap, client: Option<&ClientKey>) -> bool {
+    match headers.get(HEDGE_HEADER).and_then(|v| v.to_str().ok()) {
+        Some(value) => value == "1" || value.eq_ignore_ascii_case("true"),
+        None => client.is_some_and(|c| c.policy.hedge),
+    }
+}
+
+/// 请求是否满足对冲条件
+pub fn is_eligible(config: &Config, body: &Value) -> bool {
+    let is_streaming = body
+        .get("stream")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    if is_streaming {
+        return false;
+    }
+
+    let has_tools = body
+        .get("tools")
+        .and_then(|v| v.as_array())
+        .is_some_and(|tools| !tools.is_empty());
+    if has_tools {
+        return false;
+    }
+
+    let within_max_tokens = body
+        .get("max_tokens")
+        .and_then(|v| v.as_u64())
+        .is_some_and(|max_tokens| max_tokens <= config.hedge_max_tokens);
+    if !within_max_tokens {
+        return false;
+    }
+
+    let input_chars: u64 = ["system", "messages"]
+        .iter()
+        .filter_map(|field| body.get(field))
+        .map(count_text_chars)
+        .sum();
+    input_chars <= config.hedge_max_input_chars
+}
+
+/// 对冲中的一路请求
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Leg {
+    Primary,
+    Backup,
+}
+
+impl Leg {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Leg::Primary => "primary",
+            Leg::Backup => "backup",
+        }
+    }
+}
+
+/// 对冲中单路请求的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttemptStatus {
+    /// 返回了响应
+    Won,
+    /// 另一路先成功，本路被取消
+    Cancelled,
+    /// 上游返回错误
+    Failed,
+}
+
+/// 对冲中单路请求的记录
+#[derive(Debug, Clone, Serialize)]
+pub struct HedgeAttempt {
+    pub provider: String,
+    pub status: AttemptStatus,
+}
+
+/// [`race`] 的结果说明
+#[derive(Debug, Clone, Copy)]
+pub struct RaceReport {
+    /// 结果来自哪一路（两路都失败时为 primary）
+    pub winner: Leg,
+    /// 第二路是否已启动
+    pub backup_started: bool,
+    /// 另一路是否失败（而不是被取消）
+    pub loser_failed: bool,
+}
+
+/// 先启动 `primary`，`delay` 后仍未完成则启动 `backup`，返回先成功的结果
+///
+/// 一路失败时等待另一路；两路都失败时返回 `primary` 的错误。
+/// 返回时未完成的一路随 future 一起被丢弃（即取消）。
+pub async fn race<T, E, P, B>(primary: P, backup: B, delay: Duration) -> (Result<T, E>, RaceReport)
+where
+    P: Future<Output = Result<T, E>>,
+    B: Future<Output = Result<T, E>>,
+{
+    let backup_started = AtomicBool::new(false);
+    let delayed = async {
+        tokio::time::sleep(delay).await;
+        backup_started.store(true, Ordering::Relaxed);
+        backup.await
+    };
+    tokio::pin!(primary, delayed);
+
+    let (result, winner, loser_failed) = tokio::select! {
+        result = &mut primary => match result {
+            Ok(value) => (Ok(value), Leg::Primary, false),
+            Err(primary_err) => match delayed.await {
+                Ok(value) => (Ok(value), Leg::Backup, true),
+                Err(_) => (Err(primary_err), Leg::Primary, true),
+            },
+        },
+        result = &mut delayed => match result {
+            Ok(value) => (Ok(value), Leg::Backup, false),
+            Err(_) => match primary.await {
+                Ok(value) => (Ok(value), Leg::Primary, true),
+                Err(primary_err) => (Err(primary_err), Leg::Primary, true),
+            },
+        },
+    };
+
+    let report = RaceReport {
+        winner,
+        backup_started: backup_started.load(Ordering::Relaxed),
+        loser_failed,
+    };
+    (result, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELAY: Duration = Duration::from_millis(20);
+
+    async fn after(
+        ms: u64,
+        result: Result<&'static str, &'static str>,
+    ) -> Result<&'static str, &'static str> {
+        tokio::time::sleep(Duration::from_millis(ms)).await;
+        result
+    }
+
+    #[tokio::test]
+    async fn fast_primary_wins_without_starting_the_backup() {
+        let (result, report) = race(after(0, Ok("primary")), after(0, Ok("backup")), DELAY).await;
+        assert_eq!(result, Ok("primary"));
+        assert_eq!(report.winner, Leg::Primary);
+        assert!(!report.backup_started);
+        assert!(!report.loser_failed);
+    }
+
+    #[tokio::test]
+    async fn slow_primary_is_cancelled_when_the_backup_wins() {
+        let primary_done = AtomicBool::new(false);
+        let primary = async {
+            let result = after(500, Ok("primary")).await;
+            primary_done.store(true, Ordering::Relaxed);
+            result
+        };
+        let (result, report) = race(primary, after(0, Ok("backup")), DELAY).await;
+        assert_eq!(result, Ok("backup"));
+        assert_eq!(report.winner, Leg::Backup);
+        assert!(report.backup_started);
+        assert!(!report.loser_failed);
+        assert!(!primary_done.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn failed_primary_falls_back_to_the_backup() {
+        let (result, report) = race(after(0, Err("primary")), after(0, Ok("backup")), DELAY).await;
+        assert_eq!(result, Ok("backup"));
+        assert_eq!(report.winner, Leg::Backup);
+        assert!(report.backup_started);
+        assert!(report.loser_failed);
+    }
+
+    #[tokio::test]
+    async fn both_failing_returns_the_primary_error() {
+        let (result, report) =
+            race(after(50, Err("primary")), after(0, Err("backup")), DELAY).await;
+        assert_eq!(result, Err("primary"));
+        assert_eq!(report.winner, Leg::Primary);
+        assert!(report.backup_started);
+        assert!(report.loser_failed);
+    }
+}
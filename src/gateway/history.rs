@@ -0,0 +1,477 @@
+//! 请求历史
+//!
+//! 在内存环形缓冲区中保留最近 N 个请求，供管理接口查看和重放；
+//! 同时通过有界广播通道实时推送给 `/admin/requests/stream` 的订阅者，
+//! 订阅者读取过慢时丢弃旧记录而不阻塞请求处理。
+
+use axum::body::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+use crate::gateway::hedge::HedgeAttempt;
+use crate::gateway::modifications::Modification;
+use crate::providers::{SentHeaders, Usage};
+
+/// 单个请求的记录
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestRecord {
+    pub request_id: u64,
+    /// 请求时间 (Unix timestamp)
+    pub timestamp: u64,
+    pub provider: Option<String>,
+    pub model: String,
+    pub is_streaming: bool,
+    /// 原始请求体（超长时截断，GDPR 模式下不保存）
+    pub request_body: Option<CapturedBody>,
+    /// 客户端请求中透传的 `anthropic-beta`，重放时再次发送
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anthropic_beta: Option<String>,
+    pub response_status: u16,
+    /// token 用量（流式请求在流结束后记录）
+    pub usage: Option<Usage>,
+    /// 流未正常结束（上游或客户端中途断开、被中止），usage 只包含已转发的部分
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub incomplete: bool,
+    /// 合成（测试）流量，不计入预算与固定
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub synthetic: bool,
+    /// 向上游发送的次数（含重试）
+    pub attempts: u32,
+    /// 对冲请求中各路的结果（被取消的一路标记为 `cancelled`）
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hedge: Vec<HedgeAttempt>,
+    /// 网关对请求和响应所做的改写，含实际生效的灰度改写
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub modifications: Vec<Modification>,
+    /// 最后一次发往上游的 `anthropic-version` / `anthropic-beta`
+    pub sent_headers: Option<SentHeaders>,
+    /// 请求耗时：流式请求为首 token 耗时 (TTFT)，未收到 token 时为总耗时
+    pub latency_ms: u64,
+    /// 首 token 耗时（仅流式请求）
+    pub ttft_ms: Option<u64>,
+    /// 总耗时（流式请求包含整个流的传输时间）
+    pub duration_ms: u64,
+}
+
+/// 保存的请求体
+#[derive(Debug, Clone, Serialize)]
+pub struct CapturedBody {
+    pub content: String,
+    pub truncated: bool,
+}
+
+/// 实时推送通道的容量，订阅者积压超过该数量时丢弃最旧的记录
+const FEED_CAPACITY: usize = 256;
+
+/// 按状态码类别过滤
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum StatusClass {
+    #[serde(rename = "2xx")]
+    Success,
+    #[serde(rename = "4xx")]
+    ClientError,
+    #[serde(rename = "5xx")]
+    ServerError,
+    /// 4xx 与 5xx
+    #[serde(rename = "error")]
+    Error,
+}
+
+impl StatusClass {
+    fn matches(self, status: u16) -> bool {
+        match self {
+            StatusClass::Success => (200..300).contains(&status),
+            StatusClass::ClientError => (400..500).contains(&status),
+            StatusClass::ServerError => status >= 500,
+            StatusClass::Error => status >= 400,
+        }
+    }
+}
+
+/// 请求记录过滤条件，未设置的条件不过滤
+#[derive(Debug, Default, Deserialize)]
+pub struct RequestFilter {
+    pub status: Option<StatusClass>,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+}
+
+impl RequestFilter {
+    pub fn matches(&self, record: &RequestRecord) -> bool {
+        self.status
+            .is_none_or(|class| class.matches(record.response_status))
+            && self
+                .provider
+                .as_ref()
+                .is_none_or(|p| record.provider.as_ref() == Some(p))
+            && self.model.as_ref().is_none_or(|m| &record.model == m)
+    }
+}
+
+/// 最近请求的环形缓冲区
+pub struct RequestHistory {
+    capacity: usize,
+    max_body_bytes: usize,
+    capture_bodies: bool,
+    entries: Mutex<VecDeque<RequestRecord>>,
+    /// 关闭时取走，订阅者随之结束
+    feed: Mutex<Option<broadcast::Sender<Arc<RequestRecord>>>>,
+}
+
+impl RequestHistory {
+    /// 创建请求历史
+    ///
+    /// * `capacity` - 保留的请求数，0 表示关闭
+    /// * `max_body_bytes` - 单个请求体保存的最大字节数
+    /// * `capture_bodies` - 是否保存请求体（GDPR 模式下关闭）
+    pub fn new(capacity: usize, max_body_bytes: usize, capture_bodies: bool) -> Self {
+        Self {
+            capacity,
+            max_body_bytes,
+            capture_bodies,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+            feed: Mutex::new(Some(broadcast::channel(FEED_CAPACITY).0)),
+        }
+    }
+
+    /// 订阅实时记录，服务关闭后返回 `None`
+    pub fn subscribe(&self) -> Option<broadcast::Receiver<Arc<RequestRecord>>> {
+        self.feed.lock().ok()?.as_ref().map(|feed| feed.subscribe())
+    }
+
+    /// 关闭实时推送，结束所有订阅者的流，使优雅关闭不必等待它们
+    pub fn close_feed(&self) {
+        if let Ok(mut feed) = self.feed.lock() {
+            feed.take();
+        }
+    }
+
+    /// 按配置截取需要保存的请求体
+    pub fn capture_body(&self, body: &Bytes) -> Option<CapturedBody> {
+        if self.capacity == 0 || !self.capture_bodies {
+            return None;
+        }
+
+        let truncated = body.len() > self.max_body_bytes;
+        let bytes = if truncated {
+            &body[..self.max_body_bytes]
+        } else {
+            &body[..]
+        };
+
+        Some(CapturedBody {
+            content: String::from_utf8_lossy(bytes).into_owned(),
+            truncated,
+        })
+    }
+
+    /// 记录一个请求并推送给订阅者，超出容量时淘汰最旧的记录
+    pub fn record(&self, record: RequestRecord) {
+        if let Ok(feed) = self.feed.lock() {
+            if let Some(feed) = feed.as_ref().filter(|feed| feed.receiver_count() > 0) {
+                let _ = feed.send(Arc::new(record.clone()));
+            }
+        }
+        if self.capacity == 0 {
+            return;
+        }
+        if let Ok(mut entries) = self.entries.lock() {
+            if entries.len() >= self.capacity {
+                entries.pop_front();
+            }
+            entries.push_back(record);
+        }
+    }
+
+    /// 分页列出记录（最新的在前），返回 (总数, 当前页)
+    ///
+    /// `min_latency_ms` 只保留 `latency_ms` 不小于该值的记录，总数为过滤后的数量
+    pub fn list(
+        &self,
+        offset: usize,
+        limit: usize,
+        min_latency_ms: Option<u64>,
+    ) -> (usize, Vec<RequestRecord>) {
+        let Ok(entries) = self.entries.lock() else {
+            return (0, vec![]);
+        };
+        let matches =
+            |record: &&RequestRecord| min_latency_ms.is_none_or(|min| record.latency_ms >= min);
+        let total = entries.iter().filter(matches).count();
+        let page = entries
+            .iter()
+            .rev()
+            .filter(matches)
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect();
+        (total, page)
+    }
+
+    /// 最近 `limit` 条符合条件的记录（最旧的在前）
+    pub fn recent(&self, limit: usize, filter: &RequestFilter) -> Vec<RequestRecord> {
+        let Ok(entries) = self.entries.lock() else {
+            return vec![];
+        };
+        let mut records: Vec<RequestRecord> = entries
+            .iter()
+            .rev()
+            .filter(|record| filter.matches(record))
+            .take(limit)
+            .cloned()
+            .collect();
+        records.reverse();
+        records
+    }
+
+    /// 按 request_id 查找记录
+    pub fn get(&self, request_id: u64) -> Option<RequestRecord> {
+        let entries = self.entries.lock().ok()?;
+        entries
+            .iter()
+            .find(|record| record.request_id == request_id)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::gateway::test_router;
+    use crate::test_support::{self, MockProvider, SECRET};
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use futures::StreamExt;
+    use tower::ServiceExt;
+
+    /// 耗时为 `request_id * 100` 毫秒的非流式请求记录
+    pub(in crate::gateway) fn record(
+        request_id: u64,
+        provider: &str,
+        status: u16,
+    ) -> RequestRecord {
+        RequestRecord {
+            request_id,
+            timestamp: 1_700_000_000,
+            provider: Some(provider.to_string()),
+            model: "claude-test".to_string(),
+            is_streaming: false,
+            request_body: None,
+            anthropic_beta: None,
+            response_status: status,
+            usage: None,
+            incomplete: false,
+            synthetic: false,
+            attempts: 1,
+            hedge: vec![],
+            modifications: vec![],
+            sent_headers: None,
+            latency_ms: request_id * 100,
+            ttft_ms: None,
+            duration_ms: request_id * 100,
+        }
+    }
+
+    fn ids(records: &[RequestRecord]) -> Vec<u64> {
+        records.iter().map(|r| r.request_id).collect()
+    }
+
+    #[test]
+    fn keeps_the_most_recent_records() {
+        let history = RequestHistory::new(3, 1024, true);
+        for id in 1..=5 {
+            history.record(record(id, "first", 200));
+        }
+
+        let (total, page) = history.list(0, 10, None);
+        assert_eq!(total, 3);
+        assert_eq!(ids(&page), [5, 4, 3]);
+        assert_eq!(ids(&history.list(1, 1, None).1), [4]);
+        assert!(history.get(2).is_none());
+        assert_eq!(history.get(4).unwrap().request_id, 4);
+
+        let (total, page) = history.list(0, 10, Some(400));
+        assert_eq!(total, 2);
+        assert_eq!(ids(&page), [5, 4]);
+    }
+
+    #[test]
+    fn recent_filters_and_returns_oldest_first() {
+        let history = RequestHistory::new(10, 1024, true);
+        history.record(record(1, "first", 200));
+        history.record(record(2, "second", 429));
+        history.record(record(3, "first", 502));
+        history.record(record(4, "first", 200));
+
+        let all = RequestFilter::default();
+        assert_eq!(ids(&history.recent(2, &all)), [3, 4]);
+
+        let errors = RequestFilter {
+            status: Some(StatusClass::Error),
+            ..Default::default()
+        };
+        assert_eq!(ids(&history.recent(10, &errors)), [2, 3]);
+
+        let first_errors = RequestFilter {
+            status: Some(StatusClass::ServerError),
+            provider: Some("first".to_string()),
+            model: Some("claude-test".to_string()),
+        };
+        assert_eq!(ids(&history.recent(10, &first_errors)), [3]);
+
+        let other_model = RequestFilter {
+            model: Some("claude-other".to_string()),
+            ..Default::default()
+        };
+        assert!(history.recent(10, &other_model).is_empty());
+    }
+
+    #[test]
+    fn capture_body_truncates_and_respects_gdpr_mode() {
+        let history = RequestHistory::new(10, 4, true);
+        let body = history
+            .capture_body(&Bytes::from_static(b"abcdef"))
+            .unwrap();
+        assert_eq!(body.content, "abcd");
+        assert!(body.truncated);
+        let body = history.capture_body(&Bytes::from_static(b"abc")).unwrap();
+        assert_eq!(body.content, "abc");
+        assert!(!body.truncated);
+
+        let gdpr = RequestHistory::new(10, 4, false);
+        assert!(gdpr.capture_body(&Bytes::from_static(b"abc")).is_none());
+        let disabled = RequestHistory::new(0, 4, true);
+        assert!(disabled.capture_body(&Bytes::from_static(b"abc")).is_none());
+    }
+
+    #[tokio::test]
+    async fn subscribers_receive_records_even_when_history_is_disabled() {
+        let history = RequestHistory::new(0, 1024, true);
+        let mut feed = history.subscribe().unwrap();
+        history.record(record(1, "first", 200));
+
+        assert_eq!(feed.recv().await.unwrap().request_id, 1);
+        assert!(history.recent(10, &RequestFilter::default()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn slow_subscribers_lag_instead_of_blocking() {
+        let history = RequestHistory::new(0, 1024, true);
+        let mut feed = history.subscribe().unwrap();
+        let total = FEED_CAPACITY as u64 + 10;
+        for id in 1..=total {
+            history.record(record(id, "first", 200));
+        }
+
+        assert!(matches!(
+            feed.recv().await,
+            Err(broadcast::error::RecvError::Lagged(10))
+        ));
+        assert_eq!(feed.recv().await.unwrap().request_id, 11);
+    }
+
+    #[tokio::test]
+    async fn close_feed_ends_subscribers() {
+        let history = RequestHistory::new(10, 1024, true);
+        let mut feed = history.subscribe().unwrap();
+        history.close_feed();
+
+        assert!(matches!(
+            feed.recv().await,
+            Err(broadcast::error::RecvError::Closed)
+        ));
+        assert!(history.subscribe().is_none());
+        // 关闭后仍然保存记录
+        history.record(record(1, "first", 200));
+        assert!(history.get(1).is_some());
+    }
+
+    /// 读取 SSE 响应体，直到收到 `count` 个事件，返回 (事件名, data)
+    async fn read_events(
+        body: &mut axum::body::BodyDataStream,
+        count: usize,
+    ) -> Vec<(String, String)> {
+        let mut buffer = String::new();
+        let mut events = Vec::new();
+        while events.len() < count {
+            let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), body.next())
+                .await
+                .expect("stream stalled")
+                .expect("stream ended")
+                .unwrap();
+            buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+            while let Some(pos) = buffer.find("\n\n") {
+                let event: String = buffer.drain(..pos + 2).collect();
+                let field = |prefix: &str| {
+                    event
+                        .lines()
+                        .find_map(|line| line.strip_prefix(prefix))
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                events.push((field("event: "), field("data: ")));
+            }
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn stream_replays_recent_records_then_pushes_new_ones() {
+        let (_dir, config) = test_support::config("");
+        let state = test_support::state(config, &[Arc::new(MockProvider::new("first"))]);
+        let router = test_router(state.clone());
+        state.history().record(record(1, "first", 200));
+        state.history().record(record(2, "first", 502));
+        state.history().record(record(3, "second", 500));
+
+        let request = Request::get("/admin/requests/stream?recent=10&status=5xx&provider=first")
+            .header("Authorization", format!("Bearer {SECRET}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        let mut body = response.into_body().into_data_stream();
+
+        let events = read_events(&mut body, 2).await;
+        assert_eq!(events[0].0, "request");
+        let replayed: serde_json::Value = serde_json::from_str(&events[0].1).unwrap();
+        assert_eq!(replayed["request_id"], 2);
+        assert_eq!(events[1], ("ready".to_string(), "{}".to_string()));
+
+        // 不符合过滤条件的新记录不推送
+        state.history().record(record(4, "first", 200));
+        state.history().record(record(5, "first", 503));
+        let events = read_events(&mut body, 1).await;
+        assert_eq!(events[0].0, "request");
+        let pushed: serde_json::Value = serde_json::from_str(&events[0].1).unwrap();
+        assert_eq!(pushed["request_id"], 5);
+        assert_eq!(pushed["response_status"], 503);
+
+        // 关闭推送后流结束
+        state.history().close_feed();
+        let rest = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while body.next().await.is_some() {}
+        })
+        .await;
+        assert!(rest.is_ok(), "stream should end after close_feed");
+    }
+
+    #[tokio::test]
+    async fn stream_is_unavailable_after_shutdown() {
+        let (_dir, config) = test_support::config("");
+        let state = test_support::state(config, &[Arc::new(MockProvider::new("first"))]);
+        let router = test_router(state.clone());
+        state.history().close_feed();
+
+        let request = Request::get("/admin/requests/stream")
+            .header("Authorization", format!("Bearer {SECRET}"))
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, _) = test_support::send(&router, request).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,158 @@
+//! 各 provider 转发中的请求数
+//!
+//! 发送前取得一个 [`InFlightGuard`]，非流式请求在响应返回后释放，流式请求随响应流一起
+//! 释放（正常结束、上游失败或客户端断开时流被丢弃）。`least_connections` 选择方式据此
+//! 选出当前转发中请求最少的 provider。
+
+use bytes::Bytes;
+use futures::Stream;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+/// 按 provider ID 记录的转发中请求数
+#[derive(Default)]
+pub struct InFlight {
+    counts: Mutex<HashMap<String, Arc<AtomicI64>>>,
+}
+
+impl InFlight {
+    fn counter(&self, provider_id: &str) -> Option<Arc<AtomicI64>> {
+        let mut counts = self.counts.lock().ok()?;
+        Some(counts.entry(provider_id.to_string()).or_default().clone())
+    }
+
+    /// 开始一个请求，返回的 guard 被丢弃时计数减一
+    pub fn start(&self, provider_id: &str) -> InFlightGuard {
+        let counter = self.counter(provider_id);
+        if let Some(counter) = &counter {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        InFlightGuard(counter)
+    }
+
+    /// provider 当前转发中的请求数
+    pub fn get(&self, provider_id: &str) -> i64 {
+        self.counts
+            .lock()
+            .ok()
+            .and_then(|counts| counts.get(provider_id).map(|c| c.load(Ordering::Relaxed)))
+            .unwrap_or(0)
+    }
+}
+
+/// 一个转发中的请求，丢弃时计数减一
+pub struct InFlightGuard(Option<Arc<AtomicI64>>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if let Some(counter) = &self.0 {
+            counter.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+type ByteStream = Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send + Unpin>;
+
+/// 让 guard 随响应流一起释放
+pub fn hold(stream: ByteStream, guard: InFlightGuard) -> ByteStream {
+    Box::new(Guarded {
+        inner: stream,
+        _guard: guard,
+    })
+}
+
+struct Guarded {
+    inner: ByteStream,
+    _guard: InFlightGuard,
+}
+
+impl Stream for Guarded {
+    type Item = Result<Bytes, std::io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::test_router;
+    use crate::providers::Provider;
+    use crate::test_support::{self, MockProvider, USER_KEY};
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    #[test]
+    fn guards_from_many_threads_return_to_zero() {
+        let in_flight = Arc::new(InFlight::default());
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let in_flight = in_flight.clone();
+                std::thread::spawn(move || {
+                    let provider = if i % 2 == 0 { "a" } else { "b" };
+                    for _ in 0..1000 {
+                        let _guard = in_flight.start(provider);
+                        assert!(in_flight.get(provider) > 0);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(in_flight.get("a"), 0);
+        assert_eq!(in_flight.get("b"), 0);
+    }
+
+    #[tokio::test]
+    async fn concurrent_streams_stay_balanced_and_drain_to_zero() {
+        let (_dir, config) = test_support::config("provider_selection = \"least_connections\"");
+        let providers = [
+            Arc::new(MockProvider::new("first")),
+            Arc::new(MockProvider::new("second")),
+        ];
+        let state = test_support::state(config, &providers);
+        let router = test_router(state.clone());
+        let body = json!({
+            "model": "claude-test",
+            "max_tokens": 16,
+            "stream": true,
+            "messages": [{"role": "user", "content": "hi"}]
+        });
+
+        // 不读取响应体，流式响应持有的计数保持到响应被丢弃
+        let responses = futures::future::join_all((0..10).map(|_| {
+            router
+                .clone()
+                .oneshot(test_support::messages_request(USER_KEY, &body))
+        }))
+        .await;
+        for response in &responses {
+            assert_eq!(response.as_ref().unwrap().status(), StatusCode::OK);
+        }
+        let counts: Vec<i64> = providers
+            .iter()
+            .map(|p| state.in_flight().get(p.id()))
+            .collect();
+        assert_eq!(counts.iter().sum::<i64>(), 10);
+        assert!((counts[0] - counts[1]).abs() <= 1, "{counts:?}");
+
+        drop(responses);
+        for provider in &providers {
+            assert_eq!(state.in_flight().get(provider.id()), 0);
+        }
+
+        // 读完的响应同样释放
+        let request = test_support::messages_request(USER_KEY, &body);
+        let (status, _, _) = test_support::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        for provider in &providers {
+            assert_eq!(state.in_flight().get(provider.id()), 0);
+        }
+    }
+}
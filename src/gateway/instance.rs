@@ -0,0 +1,229 @@
+//! `serve` 实例锁
+//!
+//! 多个 `serve` 进程共用同一个 providers 目录时会同时刷新 token，互相使对方的 refresh token
+//! 失效。启动时对 providers 目录中的 `serve.lock` 加独占文件锁并写入自身 PID：
+//! - 获取成功的实例正常运行，关闭时释放锁
+//! - 锁被占用时默认启动失败；`--allow-shared` 时以共享模式运行，不刷新 token、不写入密钥使用
+//!   记录，只读取持锁实例保存的配置
+//!
+//! 文件锁在进程退出（包括崩溃）时由系统释放，不会残留。锁文件中的 PID 只用于提示：
+//! 获取锁时发现上一个 PID 说明上次未正常关闭；锁被占用但记录的进程已不存在时提示锁可能被
+//! 其子进程继承。
+
+use anyhow::{Context, Result};
+use fs2::FileExt;
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// providers 目录中的实例锁文件名
+const LOCK_FILE: &str = "serve.lock";
+
+/// 持有的实例锁，drop 时清空 PID 并释放
+pub struct InstanceLock {
+    file: File,
+    path: PathBuf,
+}
+
+/// 获取实例锁的结果
+pub enum Acquired {
+    /// 当前进程独占运行
+    Exclusive(InstanceLock),
+    /// 锁被其他实例持有
+    Contended {
+        path: PathBuf,
+        /// 锁文件中记录的持有者 PID
+        holder: Option<u32>,
+    },
+}
+
+impl InstanceLock {
+    /// 尝试获取 `dir` 中的实例锁，不等待
+    pub fn acquire(dir: &Path) -> Result<Acquired> {
+        let path = dir.join(LOCK_FILE);
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+
+        match FileExt::try_lock_exclusive(&file) {
+            Ok(()) => {}
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+                let holder = read_pid(&mut file);
+                return Ok(Acquired::Contended { path, holder });
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to lock {}", path.display())),
+        }
+
+        if let Some(previous) = read_pid(&mut file) {
+            tracing::warn!(
+                pid = previous,
+                "previous pluribus serve instance did not shut down cleanly"
+            );
+        }
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", std::process::id())?;
+        file.flush()?;
+        Ok(Acquired::Exclusive(Self { file, path }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // 清空 PID 表示正常关闭
+        let _ = self.file.set_len(0);
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+/// 获取实例锁；锁被占用时 `allow_shared` 则以共享模式继续（返回 `None`），否则报错
+pub fn acquire_or_share(dir: &Path, allow_shared: bool) -> Result<Option<InstanceLock>> {
+    match InstanceLock::acquire(dir)? {
+        Acquired::Exclusive(lock) => Ok(Some(lock)),
+        Acquired::Contended { path, holder } => {
+            let message = describe_holder(&path, holder);
+            if !allow_shared {
+                anyhow::bail!(
+                    "{message}. Stop it first, or pass --allow-shared to run without refreshing tokens"
+                );
+            }
+            tracing::warn!("{message}; running in shared mode: tokens are not refreshed");
+            Ok(None)
+        }
+    }
+}
+
+/// 锁被占用时的说明
+pub fn describe_holder(path: &Path, holder: Option<u32>) -> String {
+    match holder {
+        Some(pid) if is_process_alive(pid) => format!(
+            "Another pluribus serve instance (pid {pid}) holds {}",
+            path.display()
+        ),
+        Some(pid) => format!(
+            "{} is locked, but the recorded pid {pid} is no longer running; \
+             the lock may be held by a process it spawned",
+            path.display()
+        ),
+        None => format!("Another pluribus serve instance holds {}", path.display()),
+    }
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut content = String::new();
+    file.rewind().ok()?;
+    file.read_to_string(&mut content).ok()?;
+    content.trim().parse().ok()
+}
+
+/// 进程是否仍在运行，无法判断时视为运行中
+fn is_process_alive(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        Path::new("/proc").join(pid.to_string()).exists()
+    } else {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lock_contents(dir: &Path) -> String {
+        std::fs::read_to_string(dir.join(LOCK_FILE)).unwrap()
+    }
+
+    #[test]
+    fn exclusive_lock_records_the_pid_until_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let Acquired::Exclusive(lock) = InstanceLock::acquire(dir.path()).unwrap() else {
+            panic!("lock should be free");
+        };
+        assert_eq!(lock.path(), dir.path().join(LOCK_FILE));
+        assert_eq!(
+            lock_contents(dir.path()),
+            format!("{}\n", std::process::id())
+        );
+
+        drop(lock);
+        assert_eq!(lock_contents(dir.path()), "");
+        assert!(matches!(
+            InstanceLock::acquire(dir.path()).unwrap(),
+            Acquired::Exclusive(_)
+        ));
+    }
+
+    #[test]
+    fn stale_pid_from_an_unclean_shutdown_is_taken_over() {
+        let dir = tempfile::tempdir().unwrap();
+        // 崩溃的实例留下 PID，但文件锁已随进程退出释放
+        std::fs::write(dir.path().join(LOCK_FILE), "4000000000\n").unwrap();
+
+        let acquired = InstanceLock::acquire(dir.path()).unwrap();
+        assert!(matches!(acquired, Acquired::Exclusive(_)));
+        assert_eq!(
+            lock_contents(dir.path()),
+            format!("{}\n", std::process::id())
+        );
+    }
+
+    #[test]
+    fn live_lock_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let _held = acquire_or_share(dir.path(), false).unwrap().unwrap();
+
+        let Acquired::Contended { path, holder } = InstanceLock::acquire(dir.path()).unwrap()
+        else {
+            panic!("lock is held");
+        };
+        assert_eq!(holder, Some(std::process::id()));
+        assert_eq!(
+            describe_holder(&path, holder),
+            format!(
+                "Another pluribus serve instance (pid {}) holds {}",
+                std::process::id(),
+                path.display()
+            )
+        );
+
+        let err = acquire_or_share(dir.path(), false).err().unwrap();
+        assert!(err.to_string().contains("pass --allow-shared"), "{err}");
+        // 被拒绝的实例不会改写持锁实例的 PID
+        assert_eq!(
+            lock_contents(dir.path()),
+            format!("{}\n", std::process::id())
+        );
+    }
+
+    #[test]
+    fn allow_shared_runs_without_the_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let held = acquire_or_share(dir.path(), true).unwrap();
+        assert!(held.is_some(), "a free lock is still taken");
+
+        assert!(acquire_or_share(dir.path(), true).unwrap().is_none());
+        drop(held);
+        assert!(acquire_or_share(dir.path(), true).unwrap().is_some());
+    }
+
+    #[test]
+    fn holder_description_notes_dead_pids() {
+        let path = Path::new("/tmp/providers/serve.lock");
+        let message = describe_holder(path, Some(4_000_000_000));
+        if cfg!(target_os = "linux") {
+            assert!(message.contains("no longer running"), "{message}");
+        }
+        assert_eq!(
+            describe_holder(path, None),
+            "Another pluribus serve instance holds /tmp/providers/serve.lock"
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! 按密钥的请求速率与 token 用量限制
+//!
+//! keys.toml 中为密钥设置 `requests_per_minute` / `tokens_per_hour` 后，以令牌桶限制该密钥：
+//! 桶容量为每个周期的上限，按周期匀速补充。请求数在请求开始时扣除；token 数在请求完成后按
+//! 实际用量扣除（可扣成负数），余量不为正时拒绝后续请求，直到补充回正数。状态只保存在内存中，
+//! 重启后桶是满的。
+
+use dashmap::DashMap;
+use std::time::{Duration, Instant};
+
+use crate::keys::ClientKey;
+
+/// 单个令牌桶
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    /// 每秒补充的数量
+    refill_per_sec: f64,
+    level: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: u64, period: Duration, now: Instant) -> Self {
+        Self {
+            capacity: capacity as f64,
+            refill_per_sec: capacity as f64 / period.as_secs_f64(),
+            level: capacity as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.level = (self.level + elapsed * self.refill_per_sec).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// 余量至少为 `amount` 时扣除，否则返回补充到所需余量的等待时间
+    fn take(&mut self, amount: f64, now: Instant) -> Result<(), Duration> {
+        self.refill(now);
+        if self.level >= amount {
+            self.level -= amount;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64(
+            (amount - self.level) / self.refill_per_sec,
+        ))
+    }
+
+    /// 无条件扣除，余量可以为负
+    fn debit(&mut self, amount: f64, now: Instant) {
+        self.refill(now);
+        self.level -= amount;
+    }
+}
+
+/// 取出 `key` 的桶（持有该分片的写锁），上限变化（如重新加载 keys 文件）时重建
+fn bucket<'a>(
+    buckets: &'a DashMap<String, TokenBucket>,
+    key: &str,
+    capacity: u64,
+    period: Duration,
+    now: Instant,
+) -> dashmap::mapref::one::RefMut<'a, String, TokenBucket> {
+    let mut bucket = buckets
+        .entry(key.to_string())
+        .or_insert_with(|| TokenBucket::new(capacity, period, now));
+    if bucket.capacity != capacity as f64 {
+        *bucket = TokenBucket::new(capacity, period, now);
+    }
+    bucket
+}
+
+const MINUTE: Duration = Duration::from_secs(60);
+const HOUR: Duration = Duration::from_secs(3600);
+
+/// 所有密钥的令牌桶，按密钥名称记录；不同密钥落在不同分片上，互不阻塞
+#[derive(Default)]
+pub struct KeyLimits {
+    requests: DashMap<String, TokenBucket>,
+    tokens: DashMap<String, TokenBucket>,
+}
+
+impl KeyLimits {
+    /// 为 `client` 的一次请求扣除请求数，超过限制时返回需要等待的时间
+    ///
+    /// token 余量不为正时同样拒绝，此时不扣除请求数
+    pub fn check(&self, client: &ClientKey) -> Result<(), Duration> {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: &ClientKey, now: Instant) -> Result<(), Duration> {
+        if let Some(limit) = client.policy.tokens_per_hour {
+            let mut bucket = bucket(&self.tokens, &client.name, limit, HOUR, now);
+            bucket.refill(now);
+            if bucket.level <= 0.0 {
+                return Err(Duration::from_secs_f64(
+                    -bucket.level / bucket.refill_per_sec,
+                ));
+            }
+        }
+        if let Some(limit) = client.policy.requests_per_minute {
+            bucket(&self.requests, &client.name, u64::from(limit), MINUTE, now).take(1.0, now)?;
+        }
+        Ok(())
+    }
+
+    /// 请求完成后按实际 token 用量扣除
+    pub fn record_tokens(&self, key: &str, tokens_per_hour: u64, tokens: u64) {
+        self.record_tokens_at(key, tokens_per_hour, tokens, Instant::now());
+    }
+
+    fn record_tokens_at(&self, key: &str, tokens_per_hour: u64, tokens: u64, now: Instant) {
+        bucket(&self.tokens, key, tokens_per_hour, HOUR, now).debit(tokens as f64, now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::test_router;
+    use crate::keys::{KeyPolicy, Role};
+    use crate::test_support::{self, MockProvider};
+    use axum::http::{header, StatusCode};
+    use serde_json::json;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    fn client(policy: KeyPolicy) -> ClientKey {
+        ClientKey {
+            name: "limited".to_string(),
+            role: Role::User,
+            policy,
+        }
+    }
+
+    #[test]
+    fn request_bucket_refills_over_the_minute() {
+        let limits = KeyLimits::default();
+        let client = client(KeyPolicy {
+            requests_per_minute: Some(2),
+            ..KeyPolicy::default()
+        });
+        let start = Instant::now();
+        assert!(limits.check_at(&client, start).is_ok());
+        assert!(limits.check_at(&client, start).is_ok());
+        // 每 30 秒补充一个
+        let wait = limits.check_at(&client, start).unwrap_err();
+        assert_eq!(wait.as_secs(), 30);
+        assert!(limits
+            .check_at(&client, start + Duration::from_secs(29))
+            .is_err());
+        assert!(limits
+            .check_at(&client, start + Duration::from_secs(30))
+            .is_ok());
+        // 补充不超过容量
+        let later = start + Duration::from_secs(600);
+        assert!(limits.check_at(&client, later).is_ok());
+        assert!(limits.check_at(&client, later).is_ok());
+        assert!(limits.check_at(&client, later).is_err());
+    }
+
+    #[test]
+    fn token_bucket_rejects_until_usage_is_paid_back() {
+        let limits = KeyLimits::default();
+        let client = client(KeyPolicy {
+            tokens_per_hour: Some(3600),
+            ..KeyPolicy::default()
+        });
+        let start = Instant::now();
+        assert!(limits.check_at(&client, start).is_ok());
+        // 实际用量可以超出余量，扣成负数
+        limits.record_tokens_at("limited", 3600, 3660, start);
+        let wait = limits.check_at(&client, start).unwrap_err();
+        assert_eq!(wait.as_secs(), 60);
+        assert!(limits
+            .check_at(&client, start + Duration::from_secs(60))
+            .is_err());
+        assert!(limits
+            .check_at(&client, start + Duration::from_secs(61))
+            .is_ok());
+    }
+
+    #[test]
+    fn changed_limit_rebuilds_the_bucket() {
+        let limits = KeyLimits::default();
+        let start = Instant::now();
+        let one = client(KeyPolicy {
+            requests_per_minute: Some(1),
+            ..KeyPolicy::default()
+        });
+        assert!(limits.check_at(&one, start).is_ok());
+        assert!(limits.check_at(&one, start).is_err());
+        let two = client(KeyPolicy {
+            requests_per_minute: Some(2),
+            ..KeyPolicy::default()
+        });
+        assert!(limits.check_at(&two, start).is_ok());
+    }
+
+    fn message() -> serde_json::Value {
+        json!({
+            "model": "claude-test",
+            "max_tokens": 16,
+            "messages": [{"role": "user", "content": "hi"}]
+        })
+    }
+
+    #[tokio::test]
+    async fn over_limit_requests_get_429_with_retry_after() {
+        let (_dir, config) = test_support::config("");
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&config.keys_file)
+            .unwrap();
+        write!(
+            file,
+            "\n[[keys]]\nname = \"limited\"\nkey = \"sk-limited\"\nrequests_per_minute = 1\n"
+        )
+        .unwrap();
+        let providers = [Arc::new(MockProvider::new("first"))];
+        let router = test_router(test_support::state(config, &providers));
+        let request = test_support::messages_request("sk-limited", &message());
+        let (status, _, _) = test_support::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let request = test_support::messages_request("sk-limited", &message());
+        let (status, headers, body) = test_support::send(&router, request).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = headers[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((59..=60).contains(&retry_after), "{retry_after}");
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "key_rate_limited");
+        assert_eq!(providers[0].requests().len(), 1);
+
+        // 其他密钥不受影响
+        let request = test_support::messages_request(test_support::USER_KEY, &message());
+        let (status, _, _) = test_support::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
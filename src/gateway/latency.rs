@@ -0,0 +1,276 @@
+//! 请求耗时统计与慢请求日志
+//!
+//! 慢请求阈值按模型前缀匹配（最长前缀优先），未匹配时使用全局阈值。
+//! 流式请求以首 token 耗时 (TTFT) 判断是否为慢请求，总耗时单独记录。
+
+use bytes::Bytes;
+use futures::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::metrics::SLOW_REQUESTS;
+use crate::providers::Usage;
+
+/// 流式响应中标志首个 token 的事件类型
+const FIRST_TOKEN_MARKER: &[u8] = b"content_block_delta";
+
+/// 请求耗时
+#[derive(Debug, Clone, Copy)]
+pub struct RequestTiming {
+    /// 首 token 耗时（仅流式请求）
+    pub ttft: Option<Duration>,
+    /// 总耗时
+    pub duration: Duration,
+}
+
+impl RequestTiming {
+    /// 用于慢请求判断的耗时：流式请求为 TTFT，否则为总耗时
+    pub fn latency(&self) -> Duration {
+        self.ttft.unwrap_or(self.duration)
+    }
+}
+
+/// 查找模型对应的慢请求阈值
+pub fn slow_threshold(config: &Config, model: &str) -> Option<Duration> {
+    let ms = config
+        .slow_request_model_ms
+        .iter()
+        .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, ms)| *ms)
+        .unwrap_or(config.slow_request_ms);
+
+    (ms > 0).then(|| Duration::from_millis(ms))
+}
+
+/// 慢请求的上下文
+pub struct SlowRequestContext<'a> {
+    pub request_id: u64,
+    pub provider: Option<&'a str>,
+    pub model: &'a str,
+    pub is_streaming: bool,
+    pub attempts: u32,
+    pub usage: Option<&'a Usage>,
+}
+
+/// 超过阈值时记录 WARN 日志并计数 `slow_requests_total`
+pub fn report_if_slow(config: &Config, ctx: &SlowRequestContext<'_>, timing: &RequestTiming) {
+    let Some(threshold) = slow_threshold(config, ctx.model) else {
+        return;
+    };
+    if timing.latency() < threshold {
+        return;
+    }
+
+    let usage = ctx.usage.cloned().unwrap_or_default();
+    tracing::warn!(
+        request_id = ctx.request_id,
+        provider = ctx.provider,
+        model = ctx.model,
+        streaming = ctx.is_streaming,
+        attempts = ctx.attempts,
+        threshold_ms = threshold.as_millis() as u64,
+        latency_ms = timing.latency().as_millis() as u64,
+        ttft_ms = timing.ttft.map(|t| t.as_millis() as u64),
+        duration_ms = timing.duration.as_millis() as u64,
+        input_tokens = usage.input_tokens,
+        output_tokens = usage.output_tokens,
+        "slow request"
+    );
+    SLOW_REQUESTS
+        .with_label_values(&[ctx.provider.unwrap_or("none"), ctx.model])
+        .inc();
+}
+
+/// 数据块中是否包含首个 token 事件
+pub(super) fn is_first_token(chunk: &Bytes) -> bool {
+    chunk
+        .windows(FIRST_TOKEN_MARKER.len())
+        .any(|w| w == FIRST_TOKEN_MARKER)
+}
+
+type OnComplete = Box<dyn FnOnce(RequestTiming) + Send>;
+
+/// 记录流式响应 TTFT 与总耗时的包装流
+///
+/// 流结束或被提前丢弃（客户端断开）时调用一次 `on_complete`
+pub struct TimedStream<S> {
+    inner: S,
+    start: Instant,
+    ttft: Option<Duration>,
+    on_complete: Option<OnComplete>,
+}
+
+impl<S> TimedStream<S> {
+    pub fn new(inner: S, start: Instant, on_complete: OnComplete) -> Self {
+        Self {
+            inner,
+            start,
+            ttft: None,
+            on_complete: Some(on_complete),
+        }
+    }
+
+    fn complete(&mut self) {
+        if let Some(on_complete) = self.on_complete.take() {
+            on_complete(RequestTiming {
+                ttft: self.ttft,
+                duration: self.start.elapsed(),
+            });
+        }
+    }
+}
+
+impl<S, E> Stream for TimedStream<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) if self.ttft.is_none() && is_first_token(chunk) => {
+                self.ttft = Some(self.start.elapsed());
+            }
+            Poll::Ready(None) => self.complete(),
+            _ => {}
+        }
+        poll
+    }
+}
+
+impl<S> Drop for TimedStream<S> {
+    fn drop(&mut self) {
+        self.complete();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::history::tests::record;
+    use crate::gateway::test_router;
+    use crate::test_support::{self, MockProvider, SECRET};
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use std::sync::Arc;
+
+    fn ms(threshold: Option<Duration>) -> Option<u64> {
+        threshold.map(|t| t.as_millis() as u64)
+    }
+
+    #[test]
+    fn longest_matching_prefix_wins() {
+        let (_dir, config) = test_support::config(
+            "slow_request_ms = 1000\n\
+             slow_request_model_ms = \"claude=2000, claude-opus=5000, claude-opus-4=0\"",
+        );
+        assert_eq!(ms(slow_threshold(&config, "claude-haiku-4-5")), Some(2000));
+        assert_eq!(ms(slow_threshold(&config, "claude-opus-3")), Some(5000));
+        // 阈值为 0 的前缀关闭该模型的慢请求日志
+        assert_eq!(ms(slow_threshold(&config, "claude-opus-4-1")), None);
+        assert_eq!(ms(slow_threshold(&config, "gpt-4o")), Some(1000));
+    }
+
+    #[test]
+    fn unmatched_models_use_the_global_threshold() {
+        let (_dir, config) = test_support::config("slow_request_model_ms = \"claude-opus=5000\"");
+        assert_eq!(ms(slow_threshold(&config, "claude-opus-4-1")), Some(5000));
+        assert_eq!(ms(slow_threshold(&config, "claude-haiku-4-5")), None);
+    }
+
+    #[test]
+    fn malformed_model_thresholds_fail_to_load() {
+        for value in ["claude-opus", "claude-opus=slow"] {
+            let (_dir, config) =
+                test_support::try_config(&format!("slow_request_model_ms = \"{value}\""));
+            let err = config.unwrap_err();
+            assert!(
+                format!("{err:#}").contains("PLURIBUS_SLOW_REQUEST_MODEL_MS must look like"),
+                "{err:#}"
+            );
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn streaming_requests_are_judged_by_ttft() {
+        let (_dir, config) = test_support::config("slow_request_model_ms = \"latency-test=100\"");
+        let slow = || {
+            SLOW_REQUESTS
+                .with_label_values(&["first", "latency-test-model"])
+                .get()
+        };
+        let ctx = SlowRequestContext {
+            request_id: 1,
+            provider: Some("first"),
+            model: "latency-test-model",
+            is_streaming: true,
+            attempts: 1,
+            usage: None,
+        };
+        let before = slow();
+
+        // 总耗时超过阈值但首 token 很快，不算慢请求
+        let fast_ttft = RequestTiming {
+            ttft: Some(Duration::from_millis(20)),
+            duration: Duration::from_secs(30),
+        };
+        report_if_slow(&config, &ctx, &fast_ttft);
+        assert_eq!(slow(), before);
+
+        let slow_ttft = RequestTiming {
+            ttft: Some(Duration::from_millis(100)),
+            duration: Duration::from_millis(150),
+        };
+        report_if_slow(&config, &ctx, &slow_ttft);
+        assert_eq!(slow(), before + 1);
+
+        let no_tokens = RequestTiming {
+            ttft: None,
+            duration: Duration::from_millis(250),
+        };
+        report_if_slow(&config, &ctx, &no_tokens);
+        assert_eq!(slow(), before + 2);
+    }
+
+    #[tokio::test]
+    async fn history_lists_only_requests_above_the_latency_filter() {
+        let (_dir, config) = test_support::config("");
+        let state = test_support::state(config, &[Arc::new(MockProvider::new("first"))]);
+        let router = test_router(state.clone());
+        // 耗时分别为 100、200、300、400 毫秒
+        for id in 1..=4 {
+            state.history().record(record(id, "first", 200));
+        }
+
+        let request = Request::get("/admin/requests?min_latency_ms=200&limit=1")
+            .header("Authorization", format!("Bearer {SECRET}"))
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = test_support::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["total"], 3);
+        assert_eq!(body["limit"], 1);
+        let ids: Vec<&serde_json::Value> = body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| &item["request_id"])
+            .collect();
+        assert_eq!(ids, [&serde_json::json!(4)]);
+
+        let request = Request::get("/admin/requests?min_latency_ms=1000")
+            .header("Authorization", format!("Bearer {SECRET}"))
+            .body(Body::empty())
+            .unwrap();
+        let (_, _, body) = test_support::send(&router, request).await;
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["total"], 0);
+        assert_eq!(body["items"], serde_json::json!([]));
+    }
+}
@@ -1,80 +1,408 @@
 //! Gateway 中间件
 
 use axum::{
-    extract::Request,
-    http::{header, StatusCode},
+    extract::{MatchedPath, Request, State},
+    http::{header, Method, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
     Json,
 };
 use serde::Serialize;
-use std::sync::atomic::{AtomicU64, Ordering};
-use subtle::ConstantTimeEq;
 use tracing::Instrument;
 
-/// 全局请求计数器，用于生成 request_id
-static REQUEST_COUNTER: AtomicU64 = AtomicU64::new(1);
+use crate::access_log;
+use crate::gateway::synthetic;
+use crate::gateway::AppState;
+use crate::keys::{ClientKey, KeyStore, Role};
+use crate::telemetry;
+use crate::utils::unix_timestamp_secs;
 
-/// 认证错误响应
+/// 请求 ID，由 [`request_logger`] 生成并写入 request extensions
+#[derive(Debug, Clone, Copy)]
+pub struct RequestId(pub u64);
+
+tokio::task_local! {
+    static CURRENT_REQUEST_ID: u64;
+}
+
+/// 当前请求的 ID，不在 [`request_logger`] 处理的请求中调用时为 `None`
+pub fn current_request_id() -> Option<u64> {
+    CURRENT_REQUEST_ID.try_with(|id| *id).ok()
+}
+
+/// 以 `request_id` 作为当前请求 ID 运行 `future`
+#[cfg(test)]
+pub(crate) async fn with_request_id<F: std::future::Future>(
+    request_id: u64,
+    future: F,
+) -> F::Output {
+    CURRENT_REQUEST_ID.scope(request_id, future).await
+}
+
+/// 认证 / 授权错误响应
 #[derive(Serialize)]
 struct AuthError {
     #[serde(rename = "type")]
     error_type: &'static str,
+    code: &'static str,
     message: &'static str,
+    /// 调试模式下附带的凭证诊断信息（不包含凭证值）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<AuthDiagnostics>,
+}
+
+/// 单个凭证 header 的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Credential<'a> {
+    /// 未携带
+    Missing,
+    /// 携带但格式不正确（非 ASCII、缺少 `Bearer ` 前缀或值为空）
+    Malformed,
+    /// 携带且格式正确
+    Present(&'a str),
+}
+
+impl Credential<'_> {
+    fn authenticate(&self, keys: &KeyStore) -> Option<ClientKey> {
+        match self {
+            Credential::Present(value) => keys.authenticate(value),
+            _ => None,
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Credential::Missing => "missing",
+            Credential::Malformed => "malformed",
+            Credential::Present(_) => "present",
+        }
+    }
+}
+
+/// 认证失败时的诊断信息
+#[derive(Serialize)]
+struct AuthDiagnostics {
+    authorization: &'static str,
+    #[serde(rename = "x-api-key")]
+    x_api_key: &'static str,
 }
 
-/// Secret 认证中间件
-pub async fn auth_middleware(secret: String, request: Request, next: Next) -> Response {
-    let provided = request
-        .headers()
-        .get(header::AUTHORIZATION)
-        .and_then(|v| v.to_str().ok())
+/// 提取 `Authorization: Bearer <token>` 凭证
+fn bearer_credential(request: &Request) -> Credential<'_> {
+    let Some(value) = request.headers().get(header::AUTHORIZATION) else {
+        return Credential::Missing;
+    };
+    match value
+        .to_str()
+        .ok()
         .and_then(|v| v.strip_prefix("Bearer "))
-        .or_else(|| {
-            request
-                .headers()
-                .get("x-api-key")
-                .and_then(|v| v.to_str().ok())
-        });
+        .map(str::trim)
+    {
+        Some(token) if !token.is_empty() => Credential::Present(token),
+        _ => Credential::Malformed,
+    }
+}
+
+/// 提取 `x-api-key` 凭证
+fn api_key_credential(request: &Request) -> Credential<'_> {
+    let Some(value) = request.headers().get("x-api-key") else {
+        return Credential::Missing;
+    };
+    match value.to_str().ok().map(str::trim) {
+        Some(key) if !key.is_empty() => Credential::Present(key),
+        _ => Credential::Malformed,
+    }
+}
 
-    let is_valid = provided
-        .map(|p| p.as_bytes().ct_eq(secret.as_bytes()).into())
-        .unwrap_or(false);
+/// 密钥认证中间件
+///
+/// 同时检查 `Authorization: Bearer` 和 `x-api-key`，任意一个匹配即通过，
+/// 匹配到的 [`ClientKey`] 写入 request extensions 供后续授权检查使用。
+/// 认证调试模式开启时，401 响应会说明各 header 是否存在、格式是否正确（不会回显凭证值）。
+/// OPTIONS 预检请求不携带凭证，直接放行，由路由的 method fallback 响应。
+pub async fn auth_middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if request.method() == Method::OPTIONS {
+        return next.run(request).await;
+    }
+
+    let bearer = bearer_credential(&request);
+    let api_key = api_key_credential(&request);
+
+    let keys = state.keys();
+    let client = bearer
+        .authenticate(&keys)
+        .or_else(|| api_key.authenticate(&keys));
 
-    if is_valid {
+    if let Some(client) = client {
+        let now = unix_timestamp_secs();
+        if client.is_expired(now) {
+            tracing::warn!(key = client.name, "rejected expired API key");
+            let error = AuthError {
+                error_type: "authentication_error",
+                code: "key_expired",
+                message: "API key has expired",
+                details: None,
+            };
+            return (StatusCode::UNAUTHORIZED, Json(error)).into_response();
+        }
+
+        // 合成流量不计入密钥使用记录
+        if !synthetic::is_synthetic(request.headers(), Some(&client)) {
+            state.key_usage().record(&client.name, now);
+        }
+        request.extensions_mut().insert(client);
         return next.run(request).await;
     }
 
+    let details = state.config().auth_debug.then(|| AuthDiagnostics {
+        authorization: bearer.describe(),
+        x_api_key: api_key.describe(),
+    });
+
     let error = AuthError {
         error_type: "authentication_error",
+        code: "invalid_api_key",
         message: "Invalid or missing secret",
+        details,
     };
     (StatusCode::UNAUTHORIZED, Json(error)).into_response()
 }
 
+/// 授权检查中间件，要求已认证密钥的角色不低于 `min_role`
+///
+/// 必须位于 [`auth_middleware`] 之后
+pub async fn require_role(min_role: Role, request: Request, next: Next) -> Response {
+    let client = request.extensions().get::<ClientKey>();
+
+    if request.method() == Method::OPTIONS || client.is_some_and(|client| client.role >= min_role) {
+        return next.run(request).await;
+    }
+
+    if let Some(client) = client {
+        tracing::warn!(
+            key = client.name,
+            role = client.role.as_str(),
+            required = min_role.as_str(),
+            "permission denied"
+        );
+    }
+
+    let error = AuthError {
+        error_type: "permission_error",
+        code: "insufficient_role",
+        message: "This API key is not allowed to access this endpoint",
+        details: None,
+    };
+    (StatusCode::FORBIDDEN, Json(error)).into_response()
+}
+
+/// 按密钥限流中间件，密钥超过 `requests_per_minute` / `tokens_per_hour` 时返回 429
+///
+/// 必须位于 [`auth_middleware`] 之后；合成流量不受限制
+pub async fn rate_limit_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limited = request
+        .extensions()
+        .get::<ClientKey>()
+        .filter(|client| !synthetic::is_synthetic(request.headers(), Some(client)))
+        .and_then(|client| {
+            let wait = state.key_limits().check(client).err()?;
+            Some((client.name.clone(), wait))
+        });
+    let Some((key, wait)) = limited else {
+        return next.run(request).await;
+    };
+
+    let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+    tracing::warn!(key, retry_after, "API key rate limit exceeded");
+    let error = AuthError {
+        error_type: "rate_limit_error",
+        code: "key_rate_limited",
+        message: "API key rate limit exceeded",
+        details: None,
+    };
+    let mut response = (StatusCode::TOO_MANY_REQUESTS, Json(error)).into_response();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, retry_after.into());
+    response
+}
+
 /// 请求日志中间件
-pub async fn request_logger(request: Request, next: Next) -> Response {
-    let request_id = REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed);
+pub async fn request_logger(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let request_id = state.next_request_id();
+    request.extensions_mut().insert(RequestId(request_id));
     let method = request.method().clone();
     let path = request.uri().path().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched| matched.as_str().to_string());
 
+    // provider、模型与 token 数在请求完成时由 handler 记录
     let span = tracing::info_span!(
         "req",
         id = request_id,
-        %method,
+        http.method = %method,
+        http.route = route,
         %path,
+        ai.provider = tracing::field::Empty,
+        ai.model = tracing::field::Empty,
+        ai.tokens.input = tracing::field::Empty,
+        ai.tokens.output = tracing::field::Empty,
+        ai.tokens.cache_read = tracing::field::Empty,
+        ai.tokens.cache_write = tracing::field::Empty,
     );
+    telemetry::set_remote_parent(&span, request.headers());
 
     async move {
         let start = std::time::Instant::now();
-        let response = next.run(request).await;
+        let sampled = state.access_log().is_sampled(request_id);
+        let response = CURRENT_REQUEST_ID
+            .scope(request_id, access_log::scope(sampled, next.run(request)))
+            .await;
         let latency_ms = start.elapsed().as_millis() as u64;
         let status = response.status().as_u16();
 
-        tracing::info!(status, latency_ms, "done");
+        let access_log = state.access_log();
+        if access_log.should_log(request_id, status, latency_ms) {
+            tracing::info!(status, latency_ms, "done");
+        } else {
+            access_log.record_request(latency_ms);
+        }
 
         response
     }
     .instrument(span)
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, READONLY_KEY, SECRET, USER_KEY};
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::Router;
+    use serde_json::Value;
+
+    /// 三个分别要求 user / readonly / admin 角色的路由，外层为认证中间件
+    fn router(extra: &str) -> (tempfile::TempDir, Router) {
+        let (dir, config) = test_support::config(extra);
+        let state = test_support::state(config, &[]);
+        let route = |role: Role| {
+            Router::new()
+                .route("/", get(|| async { "ok" }))
+                .route_layer(axum::middleware::from_fn(move |req, next| {
+                    require_role(role, req, next)
+                }))
+        };
+        let router = Router::new()
+            .nest("/user", route(Role::User))
+            .nest("/readonly", route(Role::Readonly))
+            .nest("/admin", route(Role::Admin))
+            .route_layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                auth_middleware,
+            ))
+            .with_state(state);
+        (dir, router)
+    }
+
+    type Header<'a> = (&'a str, &'a str);
+
+    async fn status(router: &Router, path: &str, headers: &[Header<'_>]) -> StatusCode {
+        let mut request = Request::get(path);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        let (status, _, _) = test_support::send(router, request.body(Body::empty()).unwrap()).await;
+        status
+    }
+
+    #[tokio::test]
+    async fn credentials_by_role() {
+        let (_dir, router) = router("");
+        let unauthorized = [StatusCode::UNAUTHORIZED; 3];
+        let (ok, forbidden) = (StatusCode::OK, StatusCode::FORBIDDEN);
+
+        let bearer = |key: &str| format!("Bearer {key}");
+        let (user, readonly, admin) = (bearer(USER_KEY), bearer(READONLY_KEY), bearer(SECRET));
+        // 各列依次为：/user、/readonly、/admin
+        let matrix: Vec<(Vec<Header>, [StatusCode; 3])> = vec![
+            (vec![], unauthorized),
+            (vec![("authorization", "Basic dGVzdA==")], unauthorized),
+            (vec![("authorization", "Bearer ")], unauthorized),
+            (vec![("authorization", USER_KEY)], unauthorized),
+            (vec![("x-api-key", "")], unauthorized),
+            (vec![("x-api-key", "unknown-key")], unauthorized),
+            (vec![("authorization", "Bearer unknown-key")], unauthorized),
+            (vec![("authorization", &user)], [ok, forbidden, forbidden]),
+            (vec![("x-api-key", USER_KEY)], [ok, forbidden, forbidden]),
+            (vec![("authorization", &readonly)], [ok, ok, forbidden]),
+            (vec![("x-api-key", READONLY_KEY)], [ok, ok, forbidden]),
+            (vec![("authorization", &admin)], [ok, ok, ok]),
+            (vec![("x-api-key", SECRET)], [ok, ok, ok]),
+            // 任意一个 header 匹配即通过
+            (
+                vec![("authorization", "Basic dGVzdA=="), ("x-api-key", SECRET)],
+                [ok, ok, ok],
+            ),
+            (
+                vec![("authorization", &readonly), ("x-api-key", "unknown-key")],
+                [ok, ok, forbidden],
+            ),
+        ];
+
+        for (headers, expected) in &matrix {
+            for (path, expected) in ["/user", "/readonly", "/admin"].iter().zip(expected) {
+                assert_eq!(
+                    status(&router, path, headers).await,
+                    *expected,
+                    "GET {path} with {headers:?}"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn options_requests_skip_authentication() {
+        let (_dir, router) = router("");
+        let request = Request::options("/admin").body(Body::empty()).unwrap();
+        let (status, _, _) = test_support::send(&router, request).await;
+        assert_ne!(status, StatusCode::UNAUTHORIZED);
+        assert_ne!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn auth_debug_describes_each_header() {
+        let (_dir, debug) = router("auth_debug = true");
+        let request = Request::get("/user")
+            .header("authorization", "Token abc")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, body) = test_support::send(&debug, request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "invalid_api_key");
+        assert_eq!(body["details"]["authorization"], "malformed");
+        assert_eq!(body["details"]["x-api-key"], "missing");
+
+        let (_dir, quiet) = router("");
+        let request = Request::get("/user").body(Body::empty()).unwrap();
+        let (_, _, body) = test_support::send(&quiet, request).await;
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert!(body.get("details").is_none());
+    }
+}
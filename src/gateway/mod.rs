@@ -2,72 +2,455 @@
 //!
 //! HTTP 服务器和请求处理
 
+mod admission;
+mod bounded_map;
+mod budget;
+mod cache_prefix;
+mod canary;
+mod circuit;
+mod conversation_budget;
+#[cfg(test)]
+mod golden;
 mod handlers;
+mod hedge;
+mod history;
+mod in_flight;
+mod instance;
+mod key_limits;
+mod latency;
 mod middleware;
+mod modifications;
+mod passthrough;
+mod pinning;
+mod pool_headroom;
+mod privacy;
+mod probe;
+mod reliability;
+mod retry;
+mod shutdown;
 mod state;
+mod streams;
+mod synthetic;
+mod trailers;
+mod weighted;
 
+pub use canary::{Canaries, CanaryRule};
 pub use state::AppState;
+pub use weighted::SelectionMode;
 
 use anyhow::Result;
 use axum::{
-    extract::DefaultBodyLimit,
+    extract::{DefaultBodyLimit, Request},
     http::StatusCode,
     middleware as axum_middleware,
-    routing::{get, post},
+    response::IntoResponse,
+    routing::{delete, get, post},
     Router,
 };
+use std::future::IntoFuture;
 use std::net::SocketAddr;
-use std::time::Duration;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 use tokio::signal;
-use tower::ServiceBuilder;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tower::{ServiceBuilder, ServiceExt};
 use tower_http::{timeout::TimeoutLayer, trace::TraceLayer};
 
 use crate::config::Config;
-use crate::providers::{self, claude_code};
+use crate::keys::{KeyStore, Role};
+use crate::metrics::{LATENCY_PROBES, LATENCY_PROBE_TOKENS};
+use crate::providers::sse::StreamFailure;
+use crate::providers::{self, claude_code, ProviderSettings};
+use crate::stats::{self, TaskKind};
+#[cfg(feature = "usage-sqlite")]
+use crate::usage::{UsageRecorder, UsageStore};
+use shutdown::ShutdownCoordinator;
+use streams::WatchdogLimits;
 
 const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 300;
-const MAX_REQUEST_BODY_SIZE: usize = 32 * 1024 * 1024;
 
-pub async fn serve(config: Config) -> Result<()> {
-    claude_code::init_version().await?;
+/// 密钥使用记录的保存间隔
+#[cfg(feature = "usage-sqlite")]
+const KEY_USAGE_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 检查 token 预算日 / 月重置的间隔
+const BUDGET_ROLLOVER_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 检查 OAuth token 是否需要提前刷新的间隔
+const TOKEN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 检查流式响应是否卡住的间隔
+const STREAM_WATCHDOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 单次延迟探测的超时
+const LATENCY_PROBE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 排空超时后中止流式响应，等待错误事件发出的时间
+const SHUTDOWN_STREAM_ABORT_GRACE: Duration = Duration::from_secs(5);
+/// 关闭时写出记录的超时
+const SHUTDOWN_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+/// 关闭时停止后台任务的超时
+const SHUTDOWN_STOP_TASKS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 启动 HTTP 服务器
+///
+/// `allow_shared` 时实例锁被占用也继续启动，但不刷新 token
+pub async fn serve(config: Config, allow_shared: bool) -> Result<()> {
     config.ensure_dirs()?;
+    let instance_lock = instance::acquire_or_share(config.providers_dir(), allow_shared)?;
+    let shared = instance_lock.is_none();
+    if let Some(lock) = &instance_lock {
+        tracing::debug!(lock = %lock.path().display(), "instance lock acquired");
+    }
 
-    let providers = providers::load_providers(config.providers_dir()).await?;
-    let state = AppState::new(providers);
-    let app = build_router(state, &config);
+    let startup = Instant::now();
     let addr: SocketAddr = format!("{}:{}", config.host, config.port).parse()?;
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let bind_ms = startup.elapsed().as_millis() as u64;
     tracing::info!("Starting server on http://{}", addr);
 
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+    // 先开始接受连接，provider 加载完成后再接入完整路由
+    let app = Arc::new(OnceLock::new());
+    let (stop_tx, mut stop_rx) = watch::channel(false);
+    let mut server = stats::spawn(
+        TaskKind::Server,
+        axum::serve(listener, startup_router(app.clone()))
+            .with_graceful_shutdown(async move {
+                let _ = stop_rx.changed().await;
+            })
+            .into_future(),
+    );
+
+    let mut background = vec![claude_code::init_version(config.quiet_hours.clone())];
+
+    let mut provider_settings = ProviderSettings::from_config(&config);
+    provider_settings.refresh.enabled = !shared;
+    let provider_settings = Arc::new(provider_settings);
+    let load = async {
+        let started = Instant::now();
+        let providers =
+            providers::load_providers(config.providers_dir(), &provider_settings).await?;
+        let providers_ms = started.elapsed().as_millis() as u64;
+        let started = Instant::now();
+        let keys = KeyStore::load(&config.keys_file, &config.secret)?;
+        let keys_ms = started.elapsed().as_millis() as u64;
+        #[cfg(feature = "usage-sqlite")]
+        let usage = match &config.usage_db {
+            Some(path) => Some(Arc::new(UsageStore::open(path, true).await?)),
+            None => None,
+        };
+        // 没有用量历史，设置了 PLURIBUS_USAGE_DB 时配置加载已失败
+        #[cfg(not(feature = "usage-sqlite"))]
+        let usage = ();
+        anyhow::Ok((providers, keys, usage, providers_ms, keys_ms))
+    };
+    #[cfg_attr(not(feature = "usage-sqlite"), allow(unused_variables))]
+    let (providers, keys, usage, providers_ms, keys_ms) = tokio::select! {
+        loaded = load => loaded?,
+        _ = shutdown_signal() => {
+            let _ = stop_tx.send(true);
+            let _ = server.await;
+            return Ok(());
+        }
+        result = &mut server => {
+            result??;
+            anyhow::bail!("Server stopped unexpectedly");
+        }
+    };
+    let state = AppState::new(providers, config.clone(), keys)
+        .with_provider_settings(provider_settings)
+        .with_request_counter(config.request_id_start);
+    #[cfg(feature = "usage-sqlite")]
+    let state = match usage {
+        Some(store) => {
+            let (recorder, writer) = UsageRecorder::spawn(store.clone());
+            background.push(writer);
+            state.with_usage(store, recorder)
+        }
+        None => state,
+    };
+    background.extend(spawn_key_tasks(state.clone()));
+    #[cfg(unix)]
+    background.push(spawn_provider_reload(state.clone()));
+    background.push(spawn_budget_rollover(state.clone()));
+    if !shared {
+        background.push(spawn_token_refresh(state.clone()));
+    }
+    background.push(spawn_stream_watchdog(state.clone()));
+    if state.access_log().is_enabled() {
+        background.push(spawn_access_log_rollup(state.clone()));
+    }
+    if config.latency_probe_interval_secs > 0 {
+        background.push(spawn_latency_probes(state.clone()));
+    }
+    let _ = app.set(build_router(state.clone(), &config));
+    tracing::info!(
+        bind_ms,
+        providers_ms,
+        keys_ms,
+        total_ms = startup.elapsed().as_millis() as u64,
+        providers = state.providers().len(),
+        "ready to serve requests"
+    );
+
+    tokio::select! {
+        _ = shutdown_signal() => {}
+        result = &mut server => {
+            // 未收到关闭信号服务就退出了，说明发生了错误
+            result??;
+            anyhow::bail!("Server stopped unexpectedly");
+        }
+    }
+
+    let _ = stop_tx.send(true);
+    // 结束实时请求流的订阅，否则这些长连接会拖住请求排空阶段
+    state.history().close_feed();
+    let flush_state = state.clone();
+    let drain_state = state.clone();
+    let drain = Duration::from_secs(config.shutdown_drain_secs);
+    let abandoned = ShutdownCoordinator::new()
+        .phase(
+            "drain requests",
+            drain + SHUTDOWN_STREAM_ABORT_GRACE,
+            async move {
+                let result = tokio::select! {
+                    result = &mut server => result,
+                    _ = tokio::time::sleep(drain) => {
+                        // 以错误事件结束仍在转发的流，客户端可区分中断原因
+                        let aborted = drain_state.streams().abort_all(StreamFailure::GatewayShutdown);
+                        if aborted > 0 {
+                            tracing::warn!(streams = aborted, "aborting streams still open after the drain timeout");
+                        }
+                        server.await
+                    }
+                };
+                match result {
+                    Ok(Err(e)) => tracing::error!("Server error during shutdown: {}", e),
+                    Err(e) => tracing::error!("Server task failed: {}", e),
+                    Ok(Ok(())) => {}
+                }
+            },
+        )
+        .phase("flush key usage", SHUTDOWN_FLUSH_TIMEOUT, async move {
+            flush_key_usage(&flush_state).await;
+        })
+        .phase(
+            "stop background tasks",
+            SHUTDOWN_STOP_TASKS_TIMEOUT,
+            async move {
+                for task in &background {
+                    task.abort();
+                }
+                for task in background {
+                    let _ = task.await;
+                }
+            },
+        )
+        .run()
+        .await;
+
+    if !abandoned.is_empty() {
+        tracing::error!(
+            "Shutdown did not complete, abandoned: {}. Forcing exit.",
+            abandoned.join(", ")
+        );
+        std::process::exit(1);
+    }
 
+    drop(instance_lock);
     tracing::info!("Server shutdown complete");
     Ok(())
 }
 
 fn build_router(state: AppState, config: &Config) -> Router {
-    let secret = config.secret.clone();
+    // 以下路由只在开启对应 feature 时注册
+    #[cfg(feature = "metrics")]
+    let metrics_route = Router::new().route(
+        "/metrics",
+        handlers::allow(get(handlers::handle_metrics), handlers::READ_METHODS),
+    );
+    #[cfg(not(feature = "metrics"))]
+    let metrics_route = Router::new();
+    #[cfg(feature = "usage-sqlite")]
+    let usage_route = Router::new().route(
+        "/usage",
+        handlers::allow(get(handlers::handle_usage), handlers::READ_METHODS),
+    );
+    #[cfg(not(feature = "usage-sqlite"))]
+    let usage_route = Router::new();
 
-    let public_routes = Router::new().route("/health", get(handlers::handle_health));
-    let api_routes = Router::new()
+    // PLURIBUS_METRICS_AUTH 时需要 readonly 角色，否则与 /health 一样无需认证
+    let (public_metrics, protected_metrics) = if config.metrics_auth {
+        (Router::new(), metrics_route)
+    } else {
+        (metrics_route, Router::new())
+    };
+
+    let public_routes = Router::new()
+        .route(
+            "/health",
+            handlers::allow(get(handlers::handle_health), handlers::READ_METHODS),
+        )
+        .merge(public_metrics);
+
+    let user_routes = Router::new()
         .route(
             "/anthropic/v1/messages",
-            post(handlers::handle_anthropic_messages),
+            handlers::allow(
+                post(handlers::handle_anthropic_messages),
+                handlers::POST_METHODS,
+            ),
+        )
+        .route(
+            "/anthropic/v1/messages/count_tokens",
+            handlers::allow(post(handlers::handle_count_tokens), handlers::POST_METHODS),
+        )
+        .route(
+            "/v1/capabilities",
+            handlers::allow(get(handlers::handle_capabilities), handlers::READ_METHODS),
+        )
+        .route_layer(axum_middleware::from_fn(|req, next| {
+            middleware::require_role(Role::User, req, next)
+        }));
+
+    let readonly_routes = Router::new()
+        .route(
+            "/health/details",
+            handlers::allow(get(handlers::handle_health_details), handlers::READ_METHODS),
         )
-        .route_layer(axum_middleware::from_fn(move |req, next| {
-            let secret = secret.clone();
-            middleware::auth_middleware(secret, req, next)
+        .route(
+            "/admin/info",
+            handlers::allow(get(handlers::handle_admin_info), handlers::READ_METHODS),
+        )
+        .route(
+            "/admin/providers",
+            handlers::allow(get(handlers::handle_list_providers), handlers::READ_METHODS),
+        )
+        .route(
+            "/admin/providers/{name}/headers",
+            handlers::allow(
+                get(handlers::handle_provider_headers),
+                handlers::READ_METHODS,
+            ),
+        )
+        .route(
+            "/admin/deprecations",
+            handlers::allow(get(handlers::handle_deprecations), handlers::READ_METHODS),
+        )
+        .route(
+            "/admin/streams",
+            handlers::allow(get(handlers::handle_list_streams), handlers::READ_METHODS),
+        )
+        .route(
+            "/admin/conversations",
+            handlers::allow(
+                get(handlers::handle_list_conversations),
+                handlers::READ_METHODS,
+            ),
+        )
+        .route(
+            "/admin/route-preview",
+            handlers::allow(post(handlers::handle_route_preview), handlers::POST_METHODS),
+        )
+        .merge(usage_route)
+        .merge(protected_metrics)
+        .route_layer(axum_middleware::from_fn(|req, next| {
+            middleware::require_role(Role::Readonly, req, next)
+        }));
+
+    let admin_routes = Router::new()
+        .route(
+            "/admin/requests",
+            handlers::allow(get(handlers::handle_list_requests), handlers::READ_METHODS),
+        )
+        .route(
+            "/admin/requests/stream",
+            handlers::allow(
+                get(handlers::handle_stream_requests),
+                handlers::READ_METHODS,
+            ),
+        )
+        .route(
+            "/admin/requests/{id}",
+            handlers::allow(get(handlers::handle_get_request), handlers::READ_METHODS),
+        )
+        .route(
+            "/admin/requests/{id}/replay",
+            handlers::allow(
+                post(handlers::handle_replay_request),
+                handlers::POST_METHODS,
+            ),
+        )
+        .route(
+            "/admin/providers/reload",
+            handlers::allow(
+                post(handlers::handle_reload_providers),
+                handlers::POST_METHODS,
+            ),
+        )
+        .route(
+            "/reload",
+            handlers::allow(
+                post(handlers::handle_reload_providers),
+                handlers::POST_METHODS,
+            ),
+        )
+        .route(
+            "/admin/providers/reliability",
+            handlers::allow(
+                delete(handlers::handle_reset_reliability),
+                handlers::DELETE_METHODS,
+            ),
+        )
+        .route(
+            "/admin/providers/{name}/reliability",
+            handlers::allow(
+                delete(handlers::handle_reset_provider_reliability),
+                handlers::DELETE_METHODS,
+            ),
+        )
+        .route(
+            "/admin/streams/{id}",
+            handlers::allow(
+                delete(handlers::handle_abort_stream),
+                handlers::DELETE_METHODS,
+            ),
+        )
+        .route(
+            "/admin/conversations/{key}/{conversation}",
+            handlers::allow(
+                delete(handlers::handle_reset_conversation),
+                handlers::DELETE_METHODS,
+            ),
+        )
+        .route_layer(axum_middleware::from_fn(|req, next| {
+            middleware::require_role(Role::Admin, req, next)
         }));
 
+    let api_routes = Router::new()
+        .merge(user_routes)
+        .merge(readonly_routes)
+        .merge(admin_routes)
+        .route_layer(axum_middleware::from_fn_with_state(
+            state.clone(),
+            middleware::rate_limit_middleware,
+        ))
+        .route_layer(axum_middleware::from_fn_with_state(
+            state.clone(),
+            middleware::auth_middleware,
+        ));
+
     Router::new()
         .merge(api_routes)
         .merge(public_routes)
         .layer(
             ServiceBuilder::new()
-                .layer(DefaultBodyLimit::max(MAX_REQUEST_BODY_SIZE))
-                .layer(axum_middleware::from_fn(middleware::request_logger))
+                .layer(DefaultBodyLimit::max(config.max_request_body_bytes))
+                .layer(axum_middleware::from_fn_with_state(
+                    state.clone(),
+                    middleware::request_logger,
+                ))
                 .layer(TraceLayer::new_for_http())
                 .layer(TimeoutLayer::with_status_code(
                     StatusCode::REQUEST_TIMEOUT,
@@ -77,6 +460,227 @@ fn build_router(state: AppState, config: &Config) -> Router {
         .with_state(state)
 }
 
+/// 测试用的完整路由
+#[cfg(test)]
+pub(crate) fn test_router(state: AppState) -> Router {
+    let config = state.config().clone();
+    build_router(state, &config)
+}
+
+/// 启动阶段的路由：`/livez` 立即可用，其他请求在 `app` 就绪后转交给它，之前返回 503
+fn startup_router(app: Arc<OnceLock<Router>>) -> Router {
+    Router::new()
+        .route(
+            "/livez",
+            get(handlers::handle_livez).fallback(|method, uri| {
+                handlers::handle_unsupported_method(handlers::READ_METHODS, method, uri)
+            }),
+        )
+        .fallback(move |request: Request| {
+            let app = app.get().cloned();
+            async move {
+                match app {
+                    Some(app) => app.oneshot(request).await.into_response(),
+                    None => handlers::handle_starting().await,
+                }
+            }
+        })
+}
+
+/// 把累计的密钥使用记录写入用量数据库，失败时放回等下次保存
+///
+/// 写入只做累加，共享模式下的多个实例也可以各自保存
+async fn flush_key_usage(state: &AppState) {
+    #[cfg(feature = "usage-sqlite")]
+    if let Some(store) = state.usage() {
+        let pending = state.key_usage().take();
+        if pending.is_empty() {
+            return;
+        }
+        if let Err(e) = store.add_key_usage(&pending).await {
+            tracing::warn!("Failed to save key usage: {:#}", e);
+            state.key_usage().restore(pending);
+        }
+    }
+    // 没有用量数据库时不保存
+    #[cfg(not(feature = "usage-sqlite"))]
+    let _ = state;
+}
+
+/// 有用量数据库时定期保存密钥使用记录，并在收到 SIGHUP 时重新加载 keys 文件
+fn spawn_key_tasks(state: AppState) -> Vec<JoinHandle<()>> {
+    let mut tasks = Vec::new();
+
+    #[cfg(feature = "usage-sqlite")]
+    if state.usage().is_some() {
+        let flush_state = state.clone();
+        tasks.push(stats::spawn(TaskKind::KeyUsageFlush, async move {
+            let mut interval = tokio::time::interval(KEY_USAGE_FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                flush_key_usage(&flush_state).await;
+            }
+        }));
+    }
+
+    #[cfg(unix)]
+    tasks.push(stats::spawn(TaskKind::KeyReload, async move {
+        let Ok(mut hangup) = signal::unix::signal(signal::unix::SignalKind::hangup()) else {
+            tracing::warn!("Failed to install SIGHUP handler, key reload disabled");
+            return;
+        };
+        while hangup.recv().await.is_some() {
+            match state.reload_keys() {
+                Ok(()) => tracing::info!("Client keys reloaded"),
+                Err(e) => tracing::error!("Failed to reload client keys: {:#}", e),
+            }
+        }
+    }));
+
+    tasks
+}
+
+/// 收到 SIGUSR1 时重新加载 provider 配置，失败时保留当前的 provider
+#[cfg(unix)]
+fn spawn_provider_reload(state: AppState) -> JoinHandle<()> {
+    stats::spawn(TaskKind::ProviderReload, async move {
+        let Ok(mut usr1) = signal::unix::signal(signal::unix::SignalKind::user_defined1()) else {
+            tracing::warn!("Failed to install SIGUSR1 handler, provider reload disabled");
+            return;
+        };
+        while usr1.recv().await.is_some() {
+            if let Err(e) = state.reload_providers().await {
+                tracing::error!("Failed to reload providers: {:#}", e);
+            }
+        }
+    })
+}
+
+/// 定期在 UTC 日 / 月边界清零 provider token 预算
+fn spawn_budget_rollover(state: AppState) -> JoinHandle<()> {
+    stats::spawn(TaskKind::BudgetRollover, async move {
+        let mut interval = tokio::time::interval(BUDGET_ROLLOVER_INTERVAL);
+        loop {
+            interval.tick().await;
+            state
+                .budgets()
+                .roll_over(crate::utils::unix_timestamp_secs());
+        }
+    })
+}
+
+/// 定期在 OAuth token 过期前提前刷新，避免请求路径上的刷新延迟
+fn spawn_token_refresh(state: AppState) -> JoinHandle<()> {
+    stats::spawn(TaskKind::TokenRefresh, async move {
+        let mut interval = tokio::time::interval(TOKEN_REFRESH_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            for provider in state.providers().iter() {
+                if let Err(e) = provider.refresh_credentials().await {
+                    tracing::warn!(
+                        provider = provider.name(),
+                        "Background token refresh failed: {:#}",
+                        e
+                    );
+                }
+            }
+        }
+    })
+}
+
+/// 定期检查转发中的流式响应，记录卡住的流并中止超过上限的流
+fn spawn_stream_watchdog(state: AppState) -> JoinHandle<()> {
+    let config = state.config();
+    let limits = WatchdogLimits {
+        warn_age: Duration::from_secs(config.stream_warn_age_secs),
+        idle: Duration::from_secs(config.stream_idle_secs),
+        max_age: Duration::from_secs(config.stream_max_age_secs),
+    };
+    stats::spawn(TaskKind::StreamWatchdog, async move {
+        let mut interval = tokio::time::interval(STREAM_WATCHDOG_INTERVAL);
+        loop {
+            interval.tick().await;
+            state.streams().check(limits);
+        }
+    })
+}
+
+/// 定期输出未记录的访问日志汇总
+fn spawn_access_log_rollup(state: AppState) -> JoinHandle<()> {
+    let period = Duration::from_secs(state.config().access_log_rollup_secs);
+    stats::spawn(TaskKind::AccessLogRollup, async move {
+        let mut interval = tokio::time::interval(period);
+        // 第一次 tick 立即返回
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            state.access_log().flush();
+        }
+    })
+}
+
+/// 定期探测每个可用 provider 的 TTFT，逐个发送以免同时占用多个账号，静默时段内跳过
+fn spawn_latency_probes(state: AppState) -> JoinHandle<()> {
+    let period = Duration::from_secs(state.config().latency_probe_interval_secs);
+    stats::spawn(TaskKind::LatencyProbe, async move {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if state
+                .config()
+                .quiet_hours
+                .is_active(crate::utils::unix_timestamp_secs())
+            {
+                continue;
+            }
+            for provider in state.providers().iter() {
+                if !provider.provider_type().is_anthropic() || !state.is_selectable(provider) {
+                    continue;
+                }
+                probe_provider(&state, provider.as_ref()).await;
+            }
+        }
+    })
+}
+
+async fn probe_provider(state: &AppState, provider: &dyn providers::Provider) {
+    let model = &state.config().latency_probe_model;
+    let result = tokio::time::timeout(LATENCY_PROBE_TIMEOUT, probe::probe(provider, model))
+        .await
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("Probe timed out")));
+    let now = crate::utils::unix_timestamp_secs();
+    match result {
+        Ok((ttft, usage)) => {
+            state.latency().record(provider.id(), ttft, now);
+            state.budgets().record(provider.id(), usage.total(), now);
+            LATENCY_PROBES
+                .with_label_values(&[provider.name(), "ok"])
+                .inc();
+            LATENCY_PROBE_TOKENS
+                .with_label_values(&[provider.name()])
+                .inc_by(usage.total());
+            tracing::debug!(
+                provider = provider.name(),
+                ttft_ms = ttft.as_millis() as u64,
+                ewma_ms = state.latency().ttft_ewma_ms(provider.id()),
+                tokens = usage.total(),
+                "latency probe"
+            );
+        }
+        Err(e) => {
+            state
+                .latency()
+                .record_failure(provider.id(), format!("{:#}", e), now);
+            LATENCY_PROBES
+                .with_label_values(&[provider.name(), "error"])
+                .inc();
+            tracing::warn!(provider = provider.name(), "Latency probe failed: {:#}", e);
+        }
+    }
+}
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -105,3 +709,481 @@ async fn shutdown_signal() {
 
     tracing::info!("Shutdown signal received, starting graceful shutdown...");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, MockProvider, READONLY_KEY, SECRET, USER_KEY};
+    use axum::body::Body;
+    use axum::http::{Method, Request};
+    use tower::ServiceExt;
+
+    /// 一个路由的测试请求
+    struct RouteCase {
+        method: Method,
+        path: &'static str,
+        /// 所需的最低角色，`None` 表示无需认证
+        role: Option<Role>,
+        /// JSON 请求体
+        body: Option<&'static str>,
+        /// 有权限时的状态码
+        ok: StatusCode,
+    }
+
+    const MESSAGE: &str =
+        r#"{"model":"claude-test","max_tokens":16,"messages":[{"role":"user","content":"hi"}]}"#;
+
+    const fn case(
+        method: Method,
+        path: &'static str,
+        role: Option<Role>,
+        ok: StatusCode,
+    ) -> RouteCase {
+        RouteCase {
+            method,
+            path,
+            role,
+            body: None,
+            ok,
+        }
+    }
+
+    /// 带 [`MESSAGE`] 请求体的 POST
+    const fn json(path: &'static str, role: Option<Role>) -> RouteCase {
+        RouteCase {
+            method: Method::POST,
+            path,
+            role,
+            body: Some(MESSAGE),
+            ok: StatusCode::OK,
+        }
+    }
+
+    /// `build_router` 注册的每个路由，新增路由须在此登记
+    ///
+    /// 每个请求使用新的状态，其中已有一条 ID 为 1 的请求记录；`/metrics` 按 `metrics_auth = true` 列出
+    const ROUTES: &[RouteCase] = &[
+        case(Method::GET, "/health", None, StatusCode::OK),
+        json("/anthropic/v1/messages", Some(Role::User)),
+        json("/anthropic/v1/messages/count_tokens", Some(Role::User)),
+        case(
+            Method::GET,
+            "/v1/capabilities",
+            Some(Role::User),
+            StatusCode::OK,
+        ),
+        case(
+            Method::GET,
+            "/metrics",
+            Some(Role::Readonly),
+            StatusCode::OK,
+        ),
+        case(
+            Method::GET,
+            "/health/details",
+            Some(Role::Readonly),
+            StatusCode::OK,
+        ),
+        case(
+            Method::GET,
+            "/admin/info",
+            Some(Role::Readonly),
+            StatusCode::OK,
+        ),
+        case(
+            Method::GET,
+            "/admin/providers",
+            Some(Role::Readonly),
+            StatusCode::OK,
+        ),
+        // mock provider 不记录响应头
+        case(
+            Method::GET,
+            "/admin/providers/first/headers",
+            Some(Role::Readonly),
+            StatusCode::BAD_REQUEST,
+        ),
+        case(
+            Method::GET,
+            "/admin/deprecations",
+            Some(Role::Readonly),
+            StatusCode::OK,
+        ),
+        case(
+            Method::GET,
+            "/admin/streams",
+            Some(Role::Readonly),
+            StatusCode::OK,
+        ),
+        case(
+            Method::GET,
+            "/admin/conversations",
+            Some(Role::Readonly),
+            StatusCode::OK,
+        ),
+        json("/admin/route-preview", Some(Role::Readonly)),
+        case(Method::GET, "/usage", Some(Role::Readonly), StatusCode::OK),
+        case(
+            Method::GET,
+            "/admin/requests",
+            Some(Role::Admin),
+            StatusCode::OK,
+        ),
+        case(
+            Method::GET,
+            "/admin/requests/stream",
+            Some(Role::Admin),
+            StatusCode::OK,
+        ),
+        case(
+            Method::GET,
+            "/admin/requests/1",
+            Some(Role::Admin),
+            StatusCode::OK,
+        ),
+        case(
+            Method::POST,
+            "/admin/requests/1/replay",
+            Some(Role::Admin),
+            StatusCode::OK,
+        ),
+        case(
+            Method::POST,
+            "/admin/providers/reload",
+            Some(Role::Admin),
+            StatusCode::OK,
+        ),
+        case(Method::POST, "/reload", Some(Role::Admin), StatusCode::OK),
+        case(
+            Method::DELETE,
+            "/admin/providers/reliability",
+            Some(Role::Admin),
+            StatusCode::NO_CONTENT,
+        ),
+        case(
+            Method::DELETE,
+            "/admin/providers/first/reliability",
+            Some(Role::Admin),
+            StatusCode::NO_CONTENT,
+        ),
+        // 没有转发中的流与会话记录，到达处理器后返回 404
+        case(
+            Method::DELETE,
+            "/admin/streams/1",
+            Some(Role::Admin),
+            StatusCode::NOT_FOUND,
+        ),
+        case(
+            Method::DELETE,
+            "/admin/conversations/user/conversation",
+            Some(Role::Admin),
+            StatusCode::NOT_FOUND,
+        ),
+    ];
+
+    /// 只在开启对应 cargo feature 时注册的路由
+    const FEATURE_ROUTES: &[(&str, bool, Method, &str)] = &[
+        (
+            "metrics",
+            cfg!(feature = "metrics"),
+            Method::GET,
+            "/metrics",
+        ),
+        (
+            "usage-sqlite",
+            cfg!(feature = "usage-sqlite"),
+            Method::GET,
+            "/usage",
+        ),
+    ];
+
+    /// 路由是否因对应 feature 关闭而未注册
+    fn compiled_out(method: &Method, path: &str) -> bool {
+        FEATURE_ROUTES
+            .iter()
+            .any(|(_, enabled, m, p)| !enabled && m == method && *p == path)
+    }
+
+    /// 只读取状态码，不等待响应体（`/admin/requests/stream` 不会结束）
+    async fn status(router: &Router, method: &Method, path: &str, key: Option<&str>) -> StatusCode {
+        send_status(router, method, path, key, None).await
+    }
+
+    async fn send_status(
+        router: &Router,
+        method: &Method,
+        path: &str,
+        key: Option<&str>,
+        body: Option<&str>,
+    ) -> StatusCode {
+        let mut request = Request::builder().method(method).uri(path);
+        if let Some(key) = key {
+            request = request.header("x-api-key", key);
+        }
+        let body = match body {
+            Some(body) => {
+                request = request.header("content-type", "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+        router
+            .clone()
+            .oneshot(request.body(body).unwrap())
+            .await
+            .expect("router is infallible")
+            .status()
+    }
+
+    /// 有一个支持 count_tokens 的 mock provider 且已记录一条请求的路由；
+    /// 开启 `usage-sqlite` 时带用量数据库
+    async fn seeded_router(dir: &tempfile::TempDir, config: Config) -> Router {
+        let providers = [Arc::new(
+            MockProvider::new("first").with_type(crate::providers::ProviderType::ClaudeCode),
+        )];
+        let state = test_support::state(config, &providers);
+        #[cfg(feature = "usage-sqlite")]
+        let state = {
+            let store = Arc::new(
+                UsageStore::open(&dir.path().join("usage.db"), true)
+                    .await
+                    .unwrap(),
+            );
+            let (recorder, _) = UsageRecorder::spawn(store.clone());
+            state.with_usage(store, recorder)
+        };
+        #[cfg(not(feature = "usage-sqlite"))]
+        let _ = dir;
+        let router = test_router(state);
+        let status = send_status(
+            &router,
+            &Method::POST,
+            "/anthropic/v1/messages",
+            Some(USER_KEY),
+            Some(MESSAGE),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        router
+    }
+
+    #[tokio::test]
+    async fn routes_require_their_role() {
+        // 关闭 `metrics` feature 时不能设置 metrics_auth，`/metrics` 也不会注册
+        let extra = if cfg!(feature = "metrics") {
+            "metrics_auth = true"
+        } else {
+            ""
+        };
+        let keys = [
+            (None, None),
+            (Some(USER_KEY), Some(Role::User)),
+            (Some(READONLY_KEY), Some(Role::Readonly)),
+            (Some(SECRET), Some(Role::Admin)),
+        ];
+        for route in ROUTES {
+            if compiled_out(&route.method, route.path) {
+                continue;
+            }
+            for (key, role) in keys {
+                let (dir, config) = test_support::config(extra);
+                let router = seeded_router(&dir, config).await;
+                let status = send_status(&router, &route.method, route.path, key, route.body).await;
+                let expected = match (route.role, role) {
+                    (None, _) => route.ok,
+                    (Some(_), None) => StatusCode::UNAUTHORIZED,
+                    (Some(required), Some(role)) if role < required => StatusCode::FORBIDDEN,
+                    _ => route.ok,
+                };
+                assert_eq!(
+                    status, expected,
+                    "{} {} with {role:?}",
+                    route.method, route.path
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn feature_routes_are_registered_only_with_their_feature() {
+        let (_dir, config) = test_support::config("");
+        let providers = [Arc::new(MockProvider::new("first"))];
+        let router = test_router(test_support::state(config, &providers));
+
+        // 已注册的路径对其他方法返回 405，未注册的返回 404（处理器自身也可能返回 404）
+        for (feature, enabled, _, path) in FEATURE_ROUTES {
+            let status = status(&router, &Method::DELETE, path, Some(SECRET)).await;
+            let expected = if *enabled {
+                StatusCode::METHOD_NOT_ALLOWED
+            } else {
+                StatusCode::NOT_FOUND
+            };
+            assert_eq!(
+                status, expected,
+                "{path} with `{feature}` enabled: {enabled}"
+            );
+        }
+    }
+
+    /// 路由支持的方法与一个不支持的方法
+    fn allowed_methods(route: &RouteCase) -> (&'static str, Method) {
+        match route.method {
+            Method::GET => (handlers::READ_METHODS, Method::DELETE),
+            Method::POST => (handlers::POST_METHODS, Method::GET),
+            Method::DELETE => (handlers::DELETE_METHODS, Method::POST),
+            ref method => panic!("no allowed methods for {method}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn unsupported_methods_get_405_with_allow_on_every_route() {
+        let (dir, config) = test_support::config("");
+        let router = seeded_router(&dir, config).await;
+        for route in ROUTES {
+            if compiled_out(&route.method, route.path) {
+                continue;
+            }
+            let (allowed, method) = allowed_methods(route);
+            let request = Request::builder()
+                .method(&method)
+                .uri(route.path)
+                .header("x-api-key", SECRET)
+                .body(Body::empty())
+                .unwrap();
+            let (status, headers, body) = test_support::send(&router, request).await;
+            assert_eq!(
+                status,
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{method} {}",
+                route.path
+            );
+            assert_eq!(headers["allow"], allowed, "{method} {}", route.path);
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["type"], "invalid_request_error");
+            assert_eq!(
+                body["message"],
+                format!(
+                    "{method} is not supported on {}, allowed methods: {allowed}",
+                    route.path
+                )
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn options_lists_allowed_methods_without_auth() {
+        let (dir, config) = test_support::config("");
+        let router = seeded_router(&dir, config).await;
+        for route in ROUTES {
+            if compiled_out(&route.method, route.path) {
+                continue;
+            }
+            let (allowed, _) = allowed_methods(route);
+            let request = Request::options(route.path).body(Body::empty()).unwrap();
+            let (status, headers, body) = test_support::send(&router, request).await;
+            assert_eq!(status, StatusCode::NO_CONTENT, "OPTIONS {}", route.path);
+            assert_eq!(headers["allow"], allowed, "OPTIONS {}", route.path);
+            assert!(body.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn head_on_health_returns_the_status_without_a_body() {
+        let (_dir, config) = test_support::config("");
+        let providers = [Arc::new(MockProvider::new("first"))];
+        let router = test_router(test_support::state(config, &providers));
+
+        let request = Request::head("/health").body(Body::empty()).unwrap();
+        let (status, _, body) = test_support::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.is_empty());
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn metrics_are_public_without_metrics_auth() {
+        let (_dir, config) = test_support::config("");
+        let providers = [Arc::new(MockProvider::new("first"))];
+        let router = test_router(test_support::state(config, &providers));
+
+        let status = status(&router, &Method::GET, "/metrics", None).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn public_health_omits_provider_details() {
+        let (_dir, config) = test_support::config("");
+        let providers = [Arc::new(MockProvider::new("first"))];
+        let router = test_router(test_support::state(config, &providers));
+
+        let request = Request::get("/health").body(Body::empty()).unwrap();
+        let (_, _, body) = test_support::send(&router, request).await;
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], "ok");
+        assert!(body["claude_code_version"].is_string());
+        assert!(body["version_source"].is_string());
+        // 公开摘要只有选择状态，不含 ID、类型与上游限流
+        assert_eq!(
+            body["providers"],
+            serde_json::json!([{"name": "first", "available": true, "circuit": "closed"}])
+        );
+
+        let request = Request::get("/health/details")
+            .header("x-api-key", READONLY_KEY)
+            .body(Body::empty())
+            .unwrap();
+        let (_, _, body) = test_support::send(&router, request).await;
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["providers"][0]["name"], "first");
+    }
+
+    #[tokio::test]
+    async fn startup_router_serves_livez_until_the_app_is_ready() {
+        let (_dir, config) = test_support::config("");
+        let providers = [Arc::new(MockProvider::new("first"))];
+        let app = Arc::new(OnceLock::new());
+        let router = startup_router(app.clone());
+
+        assert_eq!(
+            status(&router, &Method::GET, "/livez", None).await,
+            StatusCode::OK
+        );
+        for (method, path, key) in [
+            (Method::GET, "/health", None),
+            (Method::GET, "/v1/capabilities", Some(USER_KEY)),
+            (Method::POST, "/anthropic/v1/messages", Some(USER_KEY)),
+            (Method::GET, "/admin/info", Some(SECRET)),
+            (Method::GET, "/no-such-route", None),
+        ] {
+            let mut request = Request::builder().method(&method).uri(path);
+            if let Some(key) = key {
+                request = request.header("x-api-key", key);
+            }
+            let (status, headers, _) =
+                test_support::send(&router, request.body(Body::empty()).unwrap()).await;
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{method} {path}");
+            assert_eq!(headers["retry-after"], "1");
+        }
+
+        // 就绪后其他请求转交给完整路由
+        app.set(test_router(test_support::state(config, &providers)))
+            .unwrap();
+        assert_eq!(
+            status(&router, &Method::GET, "/livez", None).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&router, &Method::GET, "/health", None).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&router, &Method::GET, "/v1/capabilities", Some(USER_KEY)).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&router, &Method::GET, "/v1/capabilities", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! 网关改写标注
+//!
+//! 记录网关对请求和响应所做的改写（注入身份提示词、按字段策略删除 / 转换字段、移除超长响应头），
+//! 通过 `X-Pluribus-Modifications` 响应头以紧凑 JSON 数组返回给客户端。请求带上
+//! `X-Pluribus-Annotate: 1` 时，同时注入到非流式响应 JSON 的 `_pluribus.modifications` 中，
+//! 流式响应不注入。
+
+use axum::body::Body;
+use axum::http::{header, HeaderMap, HeaderValue, Response};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::providers::field_policy::FieldAction;
+
+/// 改写列表响应头
+pub const MODIFICATIONS_HEADER: &str = "x-pluribus-modifications";
+
+/// 选择在响应体中注入改写列表的请求头
+pub const ANNOTATE_HEADER: &str = "x-pluribus-annotate";
+
+/// 改写类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModificationKind {
+    /// 注入了客户端未发送的内容
+    Inject,
+    /// 删除了字段或响应头
+    Strip,
+    /// 转换为等价形式
+    Adapt,
+}
+
+/// 单条改写记录
+#[derive(Debug, Clone, Serialize)]
+pub struct Modification {
+    pub kind: ModificationKind,
+    pub field: String,
+    pub detail: String,
+}
+
+impl Modification {
+    pub fn new(
+        kind: ModificationKind,
+        field: impl Into<String>,
+        detail: impl Into<String>,
+    ) -> Self {
+        Self {
+            kind,
+            field: field.into(),
+            detail: detail.into(),
+        }
+    }
+
+    /// 字段策略产生的改写
+    pub fn from_field_policy(field: &str, action: FieldAction, provider: &str) -> Self {
+        let kind = match action {
+            FieldAction::Adapt => ModificationKind::Adapt,
+            FieldAction::Strip | FieldAction::Keep => ModificationKind::Strip,
+        };
+        Self::new(kind, field, format!("field policy of provider {provider}"))
+    }
+}
+
+/// 客户端是否选择在响应体中注入改写列表
+pub fn wants_body_annotation(headers: &HeaderMap) -> bool {
+    headers
+        .get(ANNOTATE_HEADER)
+        .is_some_and(|v| v.as_bytes() == b"1" || v.as_bytes().eq_ignore_ascii_case(b"true"))
+}
+
+/// 有改写时写入 `X-Pluribus-Modifications` 响应头
+pub fn insert_header(headers: &mut HeaderMap, modifications: &[Modification]) {
+    if modifications.is_empty() {
+        return;
+    }
+    let Ok(json) = serde_json::to_string(modifications) else {
+        return;
+    };
+    match HeaderValue::from_str(&json) {
+        Ok(value) => {
+            headers.insert(MODIFICATIONS_HEADER, value);
+        }
+        Err(_) => tracing::debug!("modifications are not a valid header value, skipping header"),
+    }
+}
+
+/// 在非流式 JSON 响应中注入 `_pluribus.modifications`，响应体不是 JSON 对象时原样返回
+pub async fn annotate_body(
+    response: Response<Body>,
+    modifications: &[Modification],
+) -> Response<Body> {
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::warn!("Failed to read response body for annotation: {}", e);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+
+    let annotated = serde_json::from_slice::<Value>(&bytes)
+        .ok()
+        .and_then(|mut json| {
+            let obj = json.as_object_mut()?;
+            obj.insert(
+                "_pluribus".to_string(),
+                serde_json::json!({ "modifications": modifications }),
+            );
+            serde_json::to_vec(&json).ok()
+        });
+
+    match annotated {
+        Some(annotated) => {
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Response::from_parts(parts, Body::from(annotated))
+        }
+        None => Response::from_parts(parts, Body::from(bytes)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::gateway::test_router;
+    use crate::providers::ProviderType;
+    use crate::test_support::{self, MockProvider, USER_KEY};
+
+    #[test]
+    fn annotation_is_opt_in() {
+        let mut headers = HeaderMap::new();
+        assert!(!wants_body_annotation(&headers));
+        for (value, wanted) in [("1", true), ("true", true), ("TRUE", true), ("0", false)] {
+            headers.insert(ANNOTATE_HEADER, HeaderValue::from_static(value));
+            assert_eq!(wants_body_annotation(&headers), wanted, "{value}");
+        }
+    }
+
+    #[test]
+    fn header_lists_modifications_as_compact_json() {
+        let mut headers = HeaderMap::new();
+        insert_header(&mut headers, &[]);
+        assert!(headers.is_empty());
+
+        insert_header(
+            &mut headers,
+            &[
+                Modification::new(ModificationKind::Inject, "system", "identity prompt"),
+                Modification::from_field_policy("top_k", FieldAction::Strip, "first"),
+            ],
+        );
+        assert_eq!(
+            headers[MODIFICATIONS_HEADER],
+            r#"[{"kind":"inject","field":"system","detail":"identity prompt"},{"kind":"strip","field":"top_k","detail":"field policy of provider first"}]"#
+        );
+    }
+
+    #[tokio::test]
+    async fn body_annotation_only_touches_json_objects() {
+        let modifications = [Modification::new(ModificationKind::Adapt, "thinking", "x")];
+        let response = Response::builder()
+            .header(header::CONTENT_LENGTH, "11")
+            .body(Body::from(r#"{"id":"m1"}"#))
+            .unwrap();
+        let response = annotate_body(response, &modifications).await;
+        assert!(!response.headers().contains_key(header::CONTENT_LENGTH));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "id": "m1",
+                "_pluribus": {"modifications": [{"kind": "adapt", "field": "thinking", "detail": "x"}]},
+            })
+        );
+
+        let response = annotate_body(Response::new(Body::from("[1,2]")), &modifications).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "[1,2]");
+    }
+
+    #[tokio::test]
+    async fn responses_report_the_modifications_made() {
+        let (_dir, config) = test_support::config("");
+        let providers = [Arc::new(
+            MockProvider::new("first").with_type(ProviderType::OpenAI),
+        )];
+        let router = test_router(test_support::state(config, &providers));
+        let request = |annotate: bool, stream: bool| {
+            let mut request = test_support::messages_request(
+                USER_KEY,
+                &json!({
+                    "model": "claude-test",
+                    "max_tokens": 16,
+                    "top_k": 5,
+                    "stream": stream,
+                    "system": [{"type": "text", "text": "be brief"}],
+                    "messages": [{"role": "user", "content": "hi"}]
+                }),
+            );
+            if annotate {
+                request
+                    .headers_mut()
+                    .insert(ANNOTATE_HEADER, HeaderValue::from_static("1"));
+            }
+            request
+        };
+
+        let (status, headers, body) = test_support::send(&router, request(true, false)).await;
+        assert_eq!(status, StatusCode::OK);
+        let listed: Value =
+            serde_json::from_slice(headers[MODIFICATIONS_HEADER].as_bytes()).unwrap();
+        let fields: Vec<(&str, &str)> = listed
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| (m["kind"].as_str().unwrap(), m["field"].as_str().unwrap()))
+            .collect();
+        assert!(fields.contains(&("inject", "system")), "{listed}");
+        assert!(fields.contains(&("strip", "top_k")), "{listed}");
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["_pluribus"]["modifications"], listed);
+
+        // 未选择注入时只有响应头
+        let (_, headers, body) = test_support::send(&router, request(false, false)).await;
+        assert!(headers.contains_key(MODIFICATIONS_HEADER));
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert!(body.get("_pluribus").is_none());
+
+        // 流式响应不注入
+        let (status, headers, body) = test_support::send(&router, request(true, true)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "text/event-stream");
+        assert!(headers.contains_key(MODIFICATIONS_HEADER));
+        assert!(!String::from_utf8_lossy(&body).contains("_pluribus"));
+    }
+}
@@ -0,0 +1,226 @@
+//! 自带上游 token 的请求
+//!
+//! 密钥策略开启 `token_passthrough` 后，请求可通过 `x-pluribus-upstream-token` 提供自己的
+//! Claude OAuth access token。此类请求不使用已保存的 provider，而是由只服务于该请求的临时
+//! provider 转发：不刷新、不保存 token，rate limit 信息不写入任何已保存的 provider。
+//! 请求改写与用量统计照常进行，provider 记为 `passthrough`。token 不出现在日志与请求历史中。
+
+use axum::http::HeaderMap;
+
+use crate::keys::ClientKey;
+
+/// 客户端自带上游 OAuth token 的请求头
+pub const UPSTREAM_TOKEN_HEADER: &str = "x-pluribus-upstream-token";
+
+/// 客户端自带 token 被拒绝的原因
+#[derive(Debug)]
+pub enum Rejected {
+    /// 密钥策略不允许自带 token
+    NotAllowed,
+    /// header 值为空或不是合法字符
+    Invalid,
+}
+
+/// 读取请求自带的上游 token，未携带时返回 `None`
+///
+/// 值可带 `Bearer ` 前缀；header 优先于已保存的 provider 与 `x-pluribus-provider`
+pub fn upstream_token(
+    headers: &HeaderMap,
+    client: Option<&ClientKey>,
+) -> Result<Option<String>, Rejected> {
+    let Some(value) = headers.get(UPSTREAM_TOKEN_HEADER) else {
+        return Ok(None);
+    };
+    if !client.is_some_and(|c| c.policy.token_passthrough) {
+        tracing::warn!(
+            key = client.map(|c| c.name.as_str()),
+            "upstream token passthrough is not allowed for this key"
+        );
+        return Err(Rejected::NotAllowed);
+    }
+    let value = value.to_str().map_err(|_| Rejected::Invalid)?.trim();
+    // 只有 `Bearer` 而没有 token 时同样无效
+    let token = match value.split_once(char::is_whitespace) {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => token.trim(),
+        _ if value.eq_ignore_ascii_case("bearer") => "",
+        _ => value,
+    };
+    if token.is_empty() {
+        return Err(Rejected::Invalid);
+    }
+    Ok(Some(token.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::test_router;
+    use crate::test_support::{self, MockProvider, SECRET, USER_KEY};
+    use axum::body::Body;
+    use axum::http::{HeaderValue, Request, StatusCode};
+    use serde_json::json;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use wiremock::matchers::{header, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    const TOKEN: &str = "sk-ant-REDACTED";
+
+    /// 收集日志输出
+    #[derive(Clone, Default)]
+    struct Logs(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for Logs {
+        type Writer = Logs;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    fn with_token(key: &str, token: &str) -> Request<Body> {
+        let mut request = test_support::messages_request(
+            key,
+            &json!({
+                "model": "claude-test",
+                "max_tokens": 16,
+                "messages": [{"role": "user", "content": "hi"}]
+            }),
+        );
+        request
+            .headers_mut()
+            .insert(UPSTREAM_TOKEN_HEADER, HeaderValue::from_str(token).unwrap());
+        request
+    }
+
+    #[tokio::test]
+    async fn token_is_forwarded_but_never_logged_or_stored() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header("authorization", format!("Bearer {TOKEN}").as_str()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(test_support::message("own")))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let (dir, config) = test_support::config(&format!(
+            "[model_endpoints]\n\"claude-test\" = \"{}/v1/messages\"",
+            server.uri()
+        ));
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&config.keys_file)
+            .unwrap()
+            .write_all(
+                b"\n[[keys]]\nname = \"own-token\"\nkey = \"own-token-key\"\nrole = \"user\"\ntoken_passthrough = true\n",
+            )
+            .unwrap();
+        let providers = [Arc::new(MockProvider::new("first"))];
+        let router = test_router(test_support::state(config, &providers));
+
+        let logs = Logs::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_writer(logs.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let (status, headers, body) = test_support::send(
+            &router,
+            with_token("own-token-key", &format!("Bearer {TOKEN}")),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body:?}");
+        assert_eq!(headers["x-pluribus-provider"], "passthrough");
+        assert!(providers[0].requests().is_empty());
+        server.verify().await;
+
+        let request = Request::get("/admin/requests/1")
+            .header("x-api-key", SECRET)
+            .body(Body::empty())
+            .unwrap();
+        let (status, _, record) = test_support::send(&router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let record = String::from_utf8(record.to_vec()).unwrap();
+        assert!(record.contains("passthrough"), "{record}");
+        assert!(!record.contains(TOKEN), "{record}");
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(!logs.is_empty());
+        assert!(!logs.contains(TOKEN), "{logs}");
+        for entry in walk(dir.path()) {
+            let content = std::fs::read(&entry).unwrap();
+            assert!(
+                !String::from_utf8_lossy(&content).contains(TOKEN),
+                "{}",
+                entry.display()
+            );
+        }
+    }
+
+    fn walk(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .flat_map(|entry| {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    walk(&path)
+                } else {
+                    vec![path]
+                }
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn keys_without_the_policy_are_rejected() {
+        let (_dir, config) = test_support::config("");
+        let providers = [Arc::new(MockProvider::new("first"))];
+        let router = test_router(test_support::state(config, &providers));
+
+        for key in [USER_KEY, SECRET] {
+            let (status, _, body) = test_support::send(&router, with_token(key, TOKEN)).await;
+            assert_eq!(status, StatusCode::FORBIDDEN);
+            assert!(!String::from_utf8_lossy(&body).contains(TOKEN));
+        }
+        assert!(providers[0].requests().is_empty());
+    }
+
+    #[test]
+    fn bearer_prefix_is_optional_and_empty_tokens_are_invalid() {
+        let client = ClientKey {
+            name: "own-token".to_string(),
+            role: crate::keys::Role::User,
+            policy: crate::keys::KeyPolicy {
+                token_passthrough: true,
+                ..Default::default()
+            },
+        };
+        let mut headers = HeaderMap::new();
+        assert!(matches!(upstream_token(&headers, Some(&client)), Ok(None)));
+        headers.insert(UPSTREAM_TOKEN_HEADER, HeaderValue::from_static("Bearer  "));
+        assert!(matches!(
+            upstream_token(&headers, Some(&client)),
+            Err(Rejected::Invalid)
+        ));
+        headers.insert(
+            UPSTREAM_TOKEN_HEADER,
+            HeaderValue::from_static("Bearer abc"),
+        );
+        assert_eq!(
+            upstream_token(&headers, Some(&client)).unwrap().as_deref(),
+            Some("abc")
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! tool-use 循环的 provider 固定
+//!
+//! Claude Code 在一次 tool-use 循环中会连续发出多个请求（assistant 调用工具 → 客户端回传
+//! tool_result → assistant 继续）。响应中包含 tool_use 时记住处理它的 provider，
+//! 紧接着回传对应 tool_result 的请求优先发往同一个 provider，以保留 prompt cache 并避免
+//! 任务中途切换账号。
+//!
+//! 按 provider ID 记录，provider 改名不影响已有的固定。
+//!
+//! tool_use id 由上游生成且全局唯一，已经唯一标识了以该 assistant 轮次结尾的会话前缀，
+//! 因此直接以 id 的哈希作为键，不需要对整个会话前缀做哈希。
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, OnceLock};
+
+use crate::gateway::bounded_map::BoundedMap;
+use crate::utils::unix_timestamp_secs;
+
+fn pin_key(tool_use_id: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    tool_use_id.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// tool_use id → provider ID，带过期时间与数量上限
+pub struct ToolLoopPins {
+    enabled: bool,
+    entries: Mutex<BoundedMap<u64, String>>,
+}
+
+impl ToolLoopPins {
+    /// `ttl_secs` 为 0 时关闭
+    pub fn new(ttl_secs: u64, max_entries: usize) -> Self {
+        Self {
+            enabled: ttl_secs > 0,
+            entries: Mutex::new(BoundedMap::new("tool_pins", max_entries, ttl_secs)),
+        }
+    }
+
+    /// 记录响应中的 tool_use 由 `provider_id` 处理
+    pub fn record(&self, provider_id: &str, tool_use_ids: &[String]) {
+        if !self.enabled || tool_use_ids.is_empty() {
+            return;
+        }
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+
+        let now = unix_timestamp_secs();
+        for id in tool_use_ids {
+            entries.insert(pin_key(id), provider_id.to_string(), now);
+        }
+    }
+
+    /// 查找 tool_result 对应的 provider ID，取第一个未过期的匹配
+    pub fn lookup(&self, tool_result_ids: &[String]) -> Option<String> {
+        if !self.enabled || tool_result_ids.is_empty() {
+            return None;
+        }
+        let entries = self.entries.lock().ok()?;
+        let now = unix_timestamp_secs();
+        tool_result_ids
+            .iter()
+            .find_map(|id| entries.peek(&pin_key(id), now).cloned())
+    }
+}
+
+/// 提取非流式响应中 tool_use 块的 id
+pub fn tool_use_ids(response: &Value) -> Vec<String> {
+    response
+        .get("content")
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+        .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("tool_use"))
+        .filter_map(|block| block.get("id").and_then(|i| i.as_str()))
+        .map(str::to_string)
+        .collect()
+}
+
+/// 提取最后一条 user 消息中 tool_result 块引用的 tool_use id
+pub fn tool_result_ids(body: &Value) -> Vec<String> {
+    let last_user = body
+        .get("messages")
+        .and_then(|m| m.as_array())
+        .and_then(|messages| {
+            messages
+                .iter()
+                .rev()
+                .find(|m| m.get("role").and_then(|r| r.as_str()) == Some("user"))
+        });
+
+    last_user
+        .and_then(|m| m.get("content"))
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+        .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("tool_result"))
+        .filter_map(|block| block.get("tool_use_id").and_then(|i| i.as_str()))
+        .map(str::to_string)
+        .collect()
+}
+
+/// 快速路径的浅解析结构，只读取消息角色与内容块的类型和 tool_use_id
+#[derive(Deserialize)]
+struct ProbeBody {
+    #[serde(default)]
+    messages: Vec<ProbeMessage>,
+}
+
+#[derive(Deserialize)]
+struct ProbeMessage {
+    role: String,
+    content: ProbeContent,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ProbeContent {
+    Blocks(Vec<ProbeBlock>),
+    Text(serde::de::IgnoredAny),
+}
+
+#[derive(Deserialize)]
+struct ProbeBlock {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    tool_use_id: Option<String>,
+}
+
+/// 从未解析的请求体中提取 tool_result id，不含 `tool_result` 时不做解析
+pub fn tool_result_ids_raw(body: &[u8]) -> Vec<String> {
+    static MARKER: OnceLock<regex::bytes::Regex> = OnceLock::new();
+    let marker =
+        MARKER.get_or_init(|| regex::bytes::Regex::new("tool_result").expect("valid marker regex"));
+    if !marker.is_match(body) {
+        return Vec::new();
+    }
+    let Ok(probe) = serde_json::from_slice::<ProbeBody>(body) else {
+        return Vec::new();
+    };
+
+    let Some(ProbeMessage {
+        content: ProbeContent::Blocks(blocks),
+        ..
+    }) = probe.messages.into_iter().rev().find(|m| m.role == "user")
+    else {
+        return Vec::new();
+    };
+
+    blocks
+        .into_iter()
+        .filter(|block| block.kind.as_deref() == Some("tool_result"))
+        .filter_map(|block| block.tool_use_id)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn lookup_returns_the_recorded_provider() {
+        let pins = ToolLoopPins::new(300, 100);
+        pins.record("id-first", &ids(&["toolu_1", "toolu_2"]));
+        pins.record("id-second", &ids(&["toolu_3"]));
+
+        assert_eq!(pins.lookup(&ids(&["toolu_2"])).as_deref(), Some("id-first"));
+        assert_eq!(
+            pins.lookup(&ids(&["toolu_unknown", "toolu_3"])).as_deref(),
+            Some("id-second")
+        );
+        assert_eq!(pins.lookup(&ids(&["toolu_unknown"])), None);
+    }
+
+    #[test]
+    fn zero_ttl_disables_pinning() {
+        let pins = ToolLoopPins::new(0, 100);
+        pins.record("id-first", &ids(&["toolu_1"]));
+        assert_eq!(pins.lookup(&ids(&["toolu_1"])), None);
+    }
+
+    #[test]
+    fn tool_results_come_from_the_last_user_message() {
+        let body = json!({"messages": [
+            {"role": "user", "content": [{"type": "tool_result", "tool_use_id": "toolu_old"}]},
+            {"role": "assistant", "content": [{"type": "tool_use", "id": "toolu_new", "name": "bash"}]},
+            {"role": "user", "content": [
+                {"type": "tool_result", "tool_use_id": "toolu_new"},
+                {"type": "text", "text": "tool_result"},
+            ]},
+        ]});
+        assert_eq!(tool_result_ids(&body), ["toolu_new"]);
+        assert_eq!(
+            tool_result_ids_raw(body.to_string().as_bytes()),
+            ["toolu_new"]
+        );
+
+        let text = json!({"messages": [{"role": "user", "content": "what is a tool_result?"}]});
+        assert!(tool_result_ids(&text).is_empty());
+        assert!(tool_result_ids_raw(text.to_string().as_bytes()).is_empty());
+    }
+
+    #[test]
+    fn tool_use_ids_skip_other_blocks() {
+        let response = json!({"content": [
+            {"type": "text", "text": "running"},
+            {"type": "tool_use", "id": "toolu_1", "name": "bash", "input": {}},
+            {"type": "tool_use", "id": "toolu_2", "name": "read", "input": {}},
+        ]});
+        assert_eq!(tool_use_ids(&response), ["toolu_1", "toolu_2"]);
+    }
+}
@@ -0,0 +1,272 @@
+//! 号池整体的 rate limit 余量
+//!
+//! 开启 `PLURIBUS_POOL_HEADERS` 后，消息请求的响应附带按当前可选 provider 汇总的 rate limit
+//! 状态，使客户端按整个号池而不是本次服务的单个账号调整请求节奏：
+//! - 利用率取可选 provider 中最低的一个，即号池中余量最多的账号
+//! - 重置时间取可选 provider 中最早的未来重置时间
+//!
+//! 尚未收到 rate limit 信息的 provider（刚启动或上游未返回相关响应头）不参与汇总；窗口已过
+//! 重置时间的 provider 利用率按 0 计。`override` 模式下同时以汇总值设置
+//! `anthropic-ratelimit-unified-*` 响应头。
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::config::PoolHeaders;
+use crate::providers::{RateLimitInfo, RateLimitWindow};
+
+/// 单个窗口的汇总
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowHeadroom {
+    /// 可选 provider 中最低的利用率，0.0 - 1.0
+    pub utilization: f64,
+    /// 最早的未来重置时间 (Unix timestamp)，均已过重置时间时为空
+    pub reset: Option<u64>,
+}
+
+/// 号池的 rate limit 余量
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolHeadroom {
+    pub five_hour: Option<WindowHeadroom>,
+    pub seven_day: Option<WindowHeadroom>,
+    /// 当前可选的 provider 数
+    pub usable: usize,
+    /// provider 总数
+    pub total: usize,
+}
+
+/// 汇总可选 provider 的 rate limit 信息，`usable` 中为 `None` 的项视为信息未知
+pub fn aggregate(usable: &[Option<RateLimitInfo>], total: usize, now_secs: u64) -> PoolHeadroom {
+    // updated_at 为 0 表示尚未收到过上游的 rate limit 响应头
+    let known: Vec<&RateLimitInfo> = usable
+        .iter()
+        .flatten()
+        .filter(|info| info.updated_at > 0)
+        .collect();
+    PoolHeadroom {
+        five_hour: aggregate_window(known.iter().map(|info| &info.five_hour), now_secs),
+        seven_day: aggregate_window(known.iter().map(|info| &info.seven_day), now_secs),
+        usable: usable.len(),
+        total,
+    }
+}
+
+fn aggregate_window<'a>(
+    windows: impl Iterator<Item = &'a RateLimitWindow>,
+    now_secs: u64,
+) -> Option<WindowHeadroom> {
+    let mut result: Option<WindowHeadroom> = None;
+    // 上游未返回该窗口时 reset 为 0
+    for window in windows.filter(|window| window.reset > 0) {
+        let (utilization, reset) = if window.reset > now_secs {
+            (window.utilization, Some(window.reset))
+        } else {
+            (0.0, None)
+        };
+        let entry = result.get_or_insert(WindowHeadroom { utilization, reset });
+        entry.utilization = entry.utilization.min(utilization);
+        entry.reset = match (entry.reset, reset) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+    }
+    result
+}
+
+/// 写入汇总的响应头，`mode` 为 `Override` 时同时设置 `anthropic-ratelimit-unified-*`
+pub fn insert_headers(headers: &mut HeaderMap, headroom: &PoolHeadroom, mode: PoolHeaders) {
+    let mut values: Vec<(String, String)> = vec![(
+        "x-pluribus-pool-available".to_string(),
+        format!("{}/{}", headroom.usable, headroom.total),
+    )];
+    for (label, window) in [("5h", &headroom.five_hour), ("7d", &headroom.seven_day)] {
+        let Some(window) = window else {
+            continue;
+        };
+        let mut prefixes = vec!["x-pluribus-pool".to_string()];
+        if mode == PoolHeaders::Override {
+            prefixes.push("anthropic-ratelimit-unified".to_string());
+        }
+        for prefix in prefixes {
+            values.push((
+                format!("{prefix}-{label}-utilization"),
+                format_utilization(window.utilization),
+            ));
+            if let Some(reset) = window.reset {
+                values.push((format!("{prefix}-{label}-reset"), reset.to_string()));
+            }
+        }
+    }
+    for (name, value) in values {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+/// 与上游一致，最多保留两位小数
+fn format_utilization(utilization: f64) -> String {
+    ((utilization * 100.0).round() / 100.0).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::gateway::test_router;
+    use crate::test_support::{self, MockProvider, USER_KEY};
+
+    const NOW: u64 = 1_000_000;
+
+    fn window(utilization: f64, reset: u64) -> RateLimitWindow {
+        RateLimitWindow {
+            status: "allowed".to_string(),
+            reset,
+            utilization,
+        }
+    }
+
+    fn info(five_hour: RateLimitWindow, seven_day: RateLimitWindow) -> Option<RateLimitInfo> {
+        Some(RateLimitInfo {
+            five_hour,
+            seven_day,
+            updated_at: NOW - 10,
+        })
+    }
+
+    fn names(headers: &HeaderMap) -> Vec<&str> {
+        let mut names: Vec<&str> = headers.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn aggregates_the_least_utilized_window_and_earliest_reset() {
+        let usable = [
+            info(window(0.8, NOW + 100), window(0.5, NOW + 9000)),
+            info(window(0.3, NOW + 600), window(0.9, NOW + 7000)),
+            // 尚未收到 rate limit 信息
+            Some(RateLimitInfo::default()),
+            None,
+        ];
+        let headroom = aggregate(&usable, 6, NOW);
+        assert_eq!(
+            headroom,
+            PoolHeadroom {
+                five_hour: Some(WindowHeadroom {
+                    utilization: 0.3,
+                    reset: Some(NOW + 100),
+                }),
+                seven_day: Some(WindowHeadroom {
+                    utilization: 0.5,
+                    reset: Some(NOW + 7000),
+                }),
+                usable: 4,
+                total: 6,
+            }
+        );
+    }
+
+    #[test]
+    fn past_resets_count_as_empty_windows() {
+        let usable = [
+            info(window(0.9, NOW - 1), window(0.4, 0)),
+            info(window(0.7, NOW + 50), window(0.6, 0)),
+        ];
+        let headroom = aggregate(&usable, 2, NOW);
+        assert_eq!(
+            headroom.five_hour,
+            Some(WindowHeadroom {
+                utilization: 0.0,
+                reset: Some(NOW + 50),
+            })
+        );
+        // 上游未返回的窗口不汇总
+        assert_eq!(headroom.seven_day, None);
+
+        let headroom = aggregate(&[info(window(0.9, NOW - 1), window(0.4, 0))], 1, NOW);
+        assert_eq!(
+            headroom.five_hour,
+            Some(WindowHeadroom {
+                utilization: 0.0,
+                reset: None,
+            })
+        );
+        assert_eq!(aggregate(&[None], 3, NOW).five_hour, None);
+    }
+
+    #[test]
+    fn override_also_sets_the_unified_headers() {
+        let headroom = PoolHeadroom {
+            five_hour: Some(WindowHeadroom {
+                utilization: 0.256,
+                reset: Some(NOW),
+            }),
+            seven_day: Some(WindowHeadroom {
+                utilization: 0.5,
+                reset: None,
+            }),
+            usable: 2,
+            total: 3,
+        };
+
+        let mut headers = HeaderMap::new();
+        insert_headers(&mut headers, &headroom, PoolHeaders::On);
+        assert_eq!(
+            names(&headers),
+            [
+                "x-pluribus-pool-5h-reset",
+                "x-pluribus-pool-5h-utilization",
+                "x-pluribus-pool-7d-utilization",
+                "x-pluribus-pool-available",
+            ]
+        );
+        assert_eq!(headers["x-pluribus-pool-available"], "2/3");
+        assert_eq!(headers["x-pluribus-pool-5h-utilization"], "0.26");
+        assert_eq!(headers["x-pluribus-pool-5h-reset"], NOW.to_string());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "anthropic-ratelimit-unified-5h-utilization",
+            HeaderValue::from_static("0.9"),
+        );
+        insert_headers(&mut headers, &headroom, PoolHeaders::Override);
+        assert_eq!(
+            headers["anthropic-ratelimit-unified-5h-utilization"],
+            "0.26"
+        );
+        assert_eq!(
+            headers["anthropic-ratelimit-unified-5h-reset"],
+            NOW.to_string()
+        );
+        assert_eq!(headers["anthropic-ratelimit-unified-7d-utilization"], "0.5");
+        assert_eq!(headers["x-pluribus-pool-5h-utilization"], "0.26");
+    }
+
+    #[tokio::test]
+    async fn pool_headers_follow_the_configured_mode() {
+        for (mode, expected) in [
+            (PoolHeaders::Off, None),
+            (PoolHeaders::On, Some("1/1")),
+            (PoolHeaders::Override, Some("1/1")),
+        ] {
+            let (_dir, mut config) = test_support::config("");
+            config.pool_headers = mode;
+            let providers = [Arc::new(MockProvider::new("first"))];
+            let router = test_router(test_support::state(config, &providers));
+            let request = test_support::messages_request(
+                USER_KEY,
+                &serde_json::json!({
+                    "model": "claude-test",
+                    "max_tokens": 16,
+                    "messages": [{"role": "user", "content": "hi"}]
+                }),
+            );
+            let (_, headers, _) = test_support::send(&router, request).await;
+            let available = headers
+                .get("x-pluribus-pool-available")
+                .map(|v| v.to_str().unwrap());
+            assert_eq!(available, expected, "{mode:?}");
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! 隐私模式的响应后处理
+//!
+//! 密钥策略开启 `privacy` 后，返回给该密钥的响应不携带可识别上游或 provider 的元数据：
+//! - 移除上游 `request-id`、`anthropic-ratelimit-*` 与所有 `x-pluribus-*` 响应头
+//! - 不发送 trailers，不在非流式响应体中注入 `_pluribus` 注解
+//! - 失败响应只返回通用错误信息
+//!
+//! 流式响应只处理响应头，SSE 事件原样转发。日志与请求历史仍记录完整信息。
+
+use axum::http::HeaderMap;
+
+/// 上游 request ID 响应头
+const REQUEST_ID_HEADER: &str = "request-id";
+
+/// 隐私模式下移除的响应头前缀
+const STRIPPED_PREFIXES: &[&str] = &["anthropic-ratelimit-", "x-pluribus-"];
+
+/// 隐私模式下失败响应的错误信息
+pub const GENERIC_ERROR: &str = "Request failed";
+
+/// 移除可识别上游或 provider 的响应头，返回移除的数量
+pub fn scrub_headers(headers: &mut HeaderMap) -> usize {
+    let names: Vec<_> = headers
+        .keys()
+        .filter(|name| {
+            let name = name.as_str();
+            name == REQUEST_ID_HEADER
+                || STRIPPED_PREFIXES
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix))
+        })
+        .cloned()
+        .collect();
+    for name in &names {
+        headers.remove(name);
+    }
+    names.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::test_router;
+    use crate::test_support::{self, MockProvider, USER_KEY};
+    use axum::http::{HeaderValue, StatusCode};
+    use serde_json::{json, Value};
+    use std::io::Write;
+    use std::sync::Arc;
+
+    const PRIVATE_KEY: &str = "test-private-key";
+
+    #[test]
+    fn scrubs_upstream_and_gateway_headers() {
+        let mut headers = HeaderMap::new();
+        for (name, value) in [
+            ("request-id", "req_123"),
+            ("anthropic-ratelimit-requests-remaining", "10"),
+            ("x-pluribus-provider", "first"),
+            ("x-pluribus-request-id", "1"),
+            ("content-type", "application/json"),
+            ("retry-after", "5"),
+        ] {
+            headers.insert(name, HeaderValue::from_static(value));
+        }
+        assert_eq!(scrub_headers(&mut headers), 4);
+        let mut left: Vec<_> = headers.keys().map(|k| k.as_str()).collect();
+        left.sort();
+        assert_eq!(left, ["content-type", "retry-after"]);
+    }
+
+    fn router(providers: &[Arc<MockProvider>]) -> (tempfile::TempDir, axum::Router) {
+        let (dir, config) = test_support::config("");
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&config.keys_file)
+            .unwrap()
+            .write_all(
+                format!(
+                    "\n[[keys]]\nname = \"private\"\nkey = \"{PRIVATE_KEY}\"\nrole = \"user\"\nprivacy = true\n"
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+        (dir, test_router(test_support::state(config, providers)))
+    }
+
+    /// 带 `X-Pluribus-Annotate` 的请求，会注入身份提示词产生改写记录
+    fn annotated_request(key: &str) -> axum::http::Request<axum::body::Body> {
+        let mut request = test_support::messages_request(
+            key,
+            &json!({
+                "model": "claude-test",
+                "max_tokens": 16,
+                "system": [{"type": "text", "text": "be brief"}],
+                "messages": [{"role": "user", "content": "hi"}]
+            }),
+        );
+        request
+            .headers_mut()
+            .insert("x-pluribus-annotate", HeaderValue::from_static("1"));
+        request
+    }
+
+    fn pluribus_headers(headers: &HeaderMap) -> Vec<String> {
+        headers
+            .keys()
+            .map(|k| k.as_str().to_string())
+            .filter(|k| k.starts_with("x-pluribus-"))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn successful_responses_carry_no_gateway_metadata() {
+        let providers = [Arc::new(MockProvider::new("first"))];
+        let (_dir, router) = router(&providers);
+
+        // 普通密钥可以看到 provider、改写与 request ID
+        let (status, headers, body) =
+            test_support::send(&router, annotated_request(USER_KEY)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["x-pluribus-provider"], "first");
+        assert!(headers.contains_key("x-pluribus-request-id"));
+        assert!(headers.contains_key("x-pluribus-modifications"));
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert!(body.get("_pluribus").is_some());
+
+        let (status, headers, body) =
+            test_support::send(&router, annotated_request(PRIVATE_KEY)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(pluribus_headers(&headers).is_empty(), "{headers:?}");
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert!(body.get("_pluribus").is_none());
+        assert_eq!(body["content"][0]["text"], "first");
+    }
+
+    #[tokio::test]
+    async fn failures_return_only_a_generic_error() {
+        let providers = [Arc::new(MockProvider::new("first"))];
+        providers[0].fail_with(
+            StatusCode::BAD_REQUEST,
+            r#"{"type":"error","error":{"type":"invalid_request_error","message":"prompt is too long"}}"#,
+        );
+        let (_dir, router) = router(&providers);
+
+        let (_, _, body) = test_support::send(&router, annotated_request(USER_KEY)).await;
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["upstream"]["message"], "prompt is too long");
+
+        let (status, headers, body) =
+            test_support::send(&router, annotated_request(PRIVATE_KEY)).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(pluribus_headers(&headers).is_empty(), "{headers:?}");
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "request_failed");
+        assert_eq!(body["message"], GENERIC_ERROR);
+        assert!(body.get("upstream").is_none());
+        assert!(!body.to_string().contains("first"));
+        assert!(!body.to_string().contains("too long"));
+    }
+}
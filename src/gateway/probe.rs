@@ -0,0 +1,263 @@
+//! Provider 延迟探测
+//!
+//! 开启后定期向每个 provider 发送一个极小的流式请求，用首 token 耗时 (TTFT) 更新该 provider 的
+//! 指数加权移动平均 (EWMA)。EWMA 显示在 `/health/details` 中，并在选择 provider 时作为平局决胜：
+//! 候选 provider 与按优先级选中的 provider 利用率相差不超过阈值时，选择 EWMA 更低的一个。
+//!
+//! 不在启用时间段内、已达 rate limit 或当月配额用尽的 provider 不探测。探测消耗的 token 计入
+//! provider 预算，并单独计入 `latency_probe_tokens_total`，不计入任何客户端密钥。
+
+use anyhow::{bail, Result};
+use futures::StreamExt;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::latency::is_first_token;
+use crate::providers::{Provider, Usage};
+
+/// 单个 provider 的延迟估计
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LatencyEstimate {
+    /// TTFT 的 EWMA（毫秒），尚无成功探测时为空
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttft_ewma_ms: Option<f64>,
+    /// 最近一次成功探测的 TTFT（毫秒）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_ttft_ms: Option<u64>,
+    /// 成功探测次数
+    pub samples: u64,
+    /// 最近一次探测时间 (Unix timestamp)
+    pub last_probe: u64,
+    /// 最近一次探测失败的原因，成功后清除
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// 按 provider ID 记录的延迟估计
+pub struct LatencyProbes {
+    alpha: f64,
+    entries: Mutex<HashMap<String, LatencyEstimate>>,
+}
+
+/// 用新样本更新 EWMA，没有历史值时直接取样本值
+fn ewma(previous: Option<f64>, sample: f64, alpha: f64) -> f64 {
+    match previous {
+        Some(previous) => alpha * sample + (1.0 - alpha) * previous,
+        None => sample,
+    }
+}
+
+impl LatencyProbes {
+    pub fn new(alpha: f64) -> Self {
+        Self {
+            alpha,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// 记录一次成功探测
+    pub fn record(&self, provider_id: &str, ttft: Duration, now_secs: u64) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        let estimate = entries.entry(provider_id.to_string()).or_default();
+        let sample = ttft.as_secs_f64() * 1000.0;
+        estimate.ttft_ewma_ms = Some(ewma(estimate.ttft_ewma_ms, sample, self.alpha));
+        estimate.last_ttft_ms = Some(ttft.as_millis() as u64);
+        estimate.samples += 1;
+        estimate.last_probe = now_secs;
+        estimate.last_error = None;
+    }
+
+    /// 记录一次失败的探测，保留已有的 EWMA
+    pub fn record_failure(&self, provider_id: &str, error: String, now_secs: u64) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        let estimate = entries.entry(provider_id.to_string()).or_default();
+        estimate.last_probe = now_secs;
+        estimate.last_error = Some(error);
+    }
+
+    pub fn estimate(&self, provider_id: &str) -> Option<LatencyEstimate> {
+        self.entries.lock().ok()?.get(provider_id).cloned()
+    }
+
+    /// TTFT 的 EWMA（毫秒）
+    pub fn ttft_ewma_ms(&self, provider_id: &str) -> Option<f64> {
+        self.estimate(provider_id)?.ttft_ewma_ms
+    }
+}
+
+/// 平局决胜中的候选 provider
+#[derive(Debug, Clone, Copy)]
+pub struct Candidate {
+    /// rate limit 窗口中较高的利用率，0.0 - 1.0
+    pub utilization: f64,
+    pub ttft_ewma_ms: Option<f64>,
+}
+
+/// 从按优先级排列的候选中选出要使用的一个，返回其下标
+///
+/// 默认选第一个；利用率与第一个相差不超过 `margin` 且 EWMA 更低的候选中选最快的。
+/// 第一个候选尚无 EWMA 时无法比较，仍选第一个。
+pub fn tie_break(candidates: &[Candidate], margin: f64) -> usize {
+    let Some((first, rest)) = candidates.split_first() else {
+        return 0;
+    };
+    let Some(mut best_ms) = first.ttft_ewma_ms else {
+        return 0;
+    };
+    let mut best = 0;
+    for (index, candidate) in rest.iter().enumerate() {
+        let Some(ms) = candidate.ttft_ewma_ms else {
+            continue;
+        };
+        if (candidate.utilization - first.utilization).abs() <= margin && ms < best_ms {
+            best = index + 1;
+            best_ms = ms;
+        }
+    }
+    best
+}
+
+/// 探测请求体：带 Claude Code 身份提示词，只生成一个 token
+fn probe_body(model: &str) -> serde_json::Value {
+    serde_json::json!({
+        "model": model,
+        "max_tokens": 1,
+        "stream": true,
+        "system": [{
+            "type": "text",
+            "text": "You are Claude Code, Anthropic's official CLI for Claude."
+        }],
+        "messages": [{ "role": "user", "content": "ping" }]
+    })
+}
+
+/// 向 provider 发送一次探测请求，返回 TTFT 与消耗的 token
+pub async fn probe(provider: &dyn Provider, model: &str) -> Result<(Duration, Usage)> {
+    let start = Instant::now();
+    let response = provider.send_streaming(probe_body(model)).await?;
+    let mut stream = response.stream;
+    let mut ttft = None;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        if ttft.is_none() && is_first_token(&chunk) {
+            ttft = Some(start.elapsed());
+        }
+    }
+    let usage = response.summary.await.unwrap_or_default().usage;
+    match ttft {
+        Some(ttft) => Ok((ttft, usage)),
+        None => bail!("Probe stream ended without a token"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::test_support::{self, MockProvider};
+
+    fn candidate(utilization: f64, ttft_ewma_ms: Option<f64>) -> Candidate {
+        Candidate {
+            utilization,
+            ttft_ewma_ms,
+        }
+    }
+
+    #[test]
+    fn ewma_weights_new_samples_by_alpha() {
+        let probes = LatencyProbes::new(0.25);
+        assert_eq!(probes.ttft_ewma_ms("first"), None);
+
+        probes.record("first", Duration::from_millis(400), 10);
+        assert_eq!(probes.ttft_ewma_ms("first"), Some(400.0));
+        probes.record("first", Duration::from_millis(800), 20);
+        assert_eq!(probes.ttft_ewma_ms("first"), Some(500.0));
+
+        // 失败的探测保留 EWMA，成功后清除错误
+        probes.record_failure("first", "timed out".to_string(), 30);
+        let estimate = probes.estimate("first").unwrap();
+        assert_eq!(estimate.ttft_ewma_ms, Some(500.0));
+        assert_eq!(estimate.last_ttft_ms, Some(800));
+        assert_eq!(estimate.samples, 2);
+        assert_eq!(estimate.last_probe, 30);
+        assert_eq!(estimate.last_error.as_deref(), Some("timed out"));
+
+        probes.record("first", Duration::from_millis(100), 40);
+        let estimate = probes.estimate("first").unwrap();
+        assert_eq!(estimate.ttft_ewma_ms, Some(400.0));
+        assert_eq!(estimate.last_error, None);
+        assert_eq!(probes.ttft_ewma_ms("second"), None);
+    }
+
+    #[test]
+    fn tie_break_prefers_faster_candidates_within_the_margin() {
+        assert_eq!(tie_break(&[], 0.1), 0);
+        let candidates = [
+            candidate(0.50, Some(900.0)),
+            candidate(0.55, Some(600.0)),
+            candidate(0.58, Some(300.0)),
+            // 利用率相差超过阈值
+            candidate(0.70, Some(100.0)),
+            candidate(0.50, None),
+        ];
+        assert_eq!(tie_break(&candidates, 0.1), 2);
+        assert_eq!(tie_break(&candidates, 0.06), 1);
+        assert_eq!(tie_break(&candidates, 0.01), 0);
+        assert_eq!(tie_break(&candidates, 0.5), 3);
+
+        // 第一个候选尚无 EWMA 时无法比较
+        let unknown_first = [candidate(0.5, None), candidate(0.5, Some(1.0))];
+        assert_eq!(tie_break(&unknown_first, 0.1), 0);
+        // 更慢的候选不会被选中
+        let slower = [candidate(0.5, Some(100.0)), candidate(0.5, Some(200.0))];
+        assert_eq!(tie_break(&slower, 0.1), 0);
+    }
+
+    #[test]
+    fn select_uses_the_latency_tie_break_only_when_probing() {
+        let providers = [
+            Arc::new(MockProvider::new("first")),
+            Arc::new(MockProvider::new("second")),
+        ];
+        for (interval, margin, expected) in
+            [(60, 0.1, "second"), (0, 0.1, "first"), (60, 0.0, "first")]
+        {
+            let (_dir, mut config) = test_support::config("");
+            config.latency_probe_interval_secs = interval;
+            config.latency_tiebreak_margin = margin;
+            let state = test_support::state(config, &providers);
+            state
+                .latency()
+                .record("id-first", Duration::from_millis(900), 0);
+            state
+                .latency()
+                .record("id-second", Duration::from_millis(200), 0);
+
+            let selected = state.get_next_provider(|_| true).unwrap();
+            assert_eq!(
+                selected.name(),
+                expected,
+                "interval {interval}, margin {margin}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn probe_measures_time_to_first_token() {
+        let provider = MockProvider::new("first");
+        let (ttft, usage) = probe(&provider, "claude-test").await.unwrap();
+        assert!(ttft < Duration::from_secs(5));
+        assert_eq!(usage.input_tokens, 10);
+
+        let sent: serde_json::Value = serde_json::from_slice(&provider.requests()[0]).unwrap();
+        assert_eq!(sent["max_tokens"], 1);
+        assert_eq!(sent["stream"], true);
+    }
+}
@@ -0,0 +1,307 @@
+//! provider 可靠性评分
+//!
+//! 每次转发结束后按结果更新 provider 的评分：成功率的指数衰减平均（每个事件衰减一次，
+//! 与时间无关，相同事件序列得到相同评分）、连续成功次数与最近的错误类型。
+//! 正常选择不受评分影响；重试换 provider 时按评分从高到低排列候选。
+//! 评分只保存在内存中，重启或管理接口重置后从头开始。
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::providers::UpstreamError;
+
+/// 每个新事件的权重，越大越偏向最近的结果
+const ALPHA: f64 = 0.2;
+/// 保留的最近错误类型数量
+const RECENT_ERRORS: usize = 5;
+
+/// 单次转发的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Success,
+    Failure(ErrorClass),
+}
+
+/// 失败的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    /// 429
+    RateLimited,
+    /// 5xx
+    ServerError,
+    /// 其余上游状态码
+    ClientError,
+    /// strict 模式下 content 为空的响应
+    EmptyContent,
+    /// 没有上游状态码（连接失败、超时等）
+    Transport,
+}
+
+impl ErrorClass {
+    /// 按错误中的上游状态码分类
+    pub fn of(err: &anyhow::Error) -> Self {
+        match err.downcast_ref::<UpstreamError>().map(|e| e.status) {
+            Some(status) if status == http::StatusCode::TOO_MANY_REQUESTS => Self::RateLimited,
+            Some(status) if status.is_server_error() => Self::ServerError,
+            Some(_) => Self::ClientError,
+            None => Self::Transport,
+        }
+    }
+}
+
+/// 单个 provider 的评分
+#[derive(Debug, Clone, Serialize)]
+pub struct ReliabilityScore {
+    /// 成功率的指数衰减平均，0 到 1，尚无记录的 provider 为 1
+    pub score: f64,
+    /// 连续成功次数
+    pub streak: u64,
+    /// 记录的事件数
+    pub samples: u64,
+    /// 最近的错误类型，旧的在前
+    pub recent_errors: Vec<ErrorClass>,
+}
+
+impl Default for ReliabilityScore {
+    fn default() -> Self {
+        Self {
+            score: 1.0,
+            streak: 0,
+            samples: 0,
+            recent_errors: Vec::new(),
+        }
+    }
+}
+
+impl ReliabilityScore {
+    fn record(&mut self, event: Event) {
+        let sample = match event {
+            Event::Success => {
+                self.streak += 1;
+                1.0
+            }
+            Event::Failure(class) => {
+                self.streak = 0;
+                if self.recent_errors.len() == RECENT_ERRORS {
+                    self.recent_errors.remove(0);
+                }
+                self.recent_errors.push(class);
+                0.0
+            }
+        };
+        self.score = ALPHA * sample + (1.0 - ALPHA) * self.score;
+        self.samples += 1;
+    }
+}
+
+/// 按 provider ID 记录的可靠性评分
+#[derive(Default)]
+pub struct Reliability {
+    entries: Mutex<HashMap<String, ReliabilityScore>>,
+}
+
+impl Reliability {
+    /// 记录一次转发结果
+    pub fn record(&self, provider_id: &str, event: Event) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries
+                .entry(provider_id.to_string())
+                .or_default()
+                .record(event);
+        }
+    }
+
+    /// provider 的评分，尚无记录时为空
+    pub fn get(&self, provider_id: &str) -> Option<ReliabilityScore> {
+        self.entries.lock().ok()?.get(provider_id).cloned()
+    }
+
+    /// 按评分从高到低排列，评分相同时连续成功更多的在前，再相同时保持原顺序
+    pub fn rank<T>(&self, candidates: Vec<T>, id: impl Fn(&T) -> &str) -> Vec<(T, f64)> {
+        let entries = self.entries.lock().ok();
+        let mut ranked: Vec<(T, ReliabilityScore)> = candidates
+            .into_iter()
+            .map(|candidate| {
+                let score = entries
+                    .as_ref()
+                    .and_then(|entries| entries.get(id(&candidate)).cloned())
+                    .unwrap_or_default();
+                (candidate, score)
+            })
+            .collect();
+        ranked.sort_by(|(_, a), (_, b)| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| b.streak.cmp(&a.streak))
+        });
+        ranked
+            .into_iter()
+            .map(|(candidate, score)| (candidate, score.score))
+            .collect()
+    }
+
+    /// 清除 provider 的评分，没有记录时返回 false
+    pub fn reset(&self, provider_id: &str) -> bool {
+        self.entries
+            .lock()
+            .is_ok_and(|mut entries| entries.remove(provider_id).is_some())
+    }
+
+    /// 清除所有评分
+    pub fn reset_all(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::test_router;
+    use crate::providers::Provider;
+    use crate::test_support::{self, MockProvider, SECRET, USER_KEY};
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use std::sync::Arc;
+
+    fn scored(events: &[Event]) -> ReliabilityScore {
+        let mut score = ReliabilityScore::default();
+        for event in events {
+            score.record(*event);
+        }
+        score
+    }
+
+    const FAIL: Event = Event::Failure(ErrorClass::ServerError);
+
+    #[test]
+    fn score_decays_per_event() {
+        let failed = scored(&[FAIL]);
+        assert!((failed.score - 0.8).abs() < 1e-12);
+        assert_eq!((failed.streak, failed.samples), (0, 1));
+
+        let twice = scored(&[FAIL, FAIL]);
+        assert!((twice.score - 0.64).abs() < 1e-12);
+
+        // 成功逐步恢复：0.64 → 0.712 → 0.7696
+        let recovering = scored(&[FAIL, FAIL, Event::Success, Event::Success]);
+        assert!((recovering.score - 0.7696).abs() < 1e-12);
+        assert_eq!(recovering.streak, 2);
+
+        // 同样的序列得到同样的评分
+        let again = scored(&[FAIL, FAIL, Event::Success, Event::Success]);
+        assert_eq!(again.score, recovering.score);
+    }
+
+    #[test]
+    fn keeps_only_the_most_recent_errors() {
+        let classes = [
+            ErrorClass::RateLimited,
+            ErrorClass::ServerError,
+            ErrorClass::ClientError,
+            ErrorClass::EmptyContent,
+            ErrorClass::Transport,
+            ErrorClass::RateLimited,
+        ];
+        let events: Vec<Event> = classes.iter().map(|c| Event::Failure(*c)).collect();
+        let score = scored(&events);
+        assert_eq!(score.recent_errors, classes[1..]);
+    }
+
+    #[test]
+    fn rank_orders_by_score_then_streak_then_position() {
+        let reliability = Reliability::default();
+        reliability.record("flaky", FAIL);
+        // 评分都为 1 时连续成功多的在前
+        reliability.record("steady", Event::Success);
+        reliability.record("steady", Event::Success);
+        reliability.record("fresh", Event::Success);
+
+        let ranked = reliability.rank(vec!["flaky", "unknown", "fresh", "steady"], |id| id);
+        let order: Vec<&str> = ranked.iter().map(|(id, _)| *id).collect();
+        assert_eq!(order, ["steady", "fresh", "unknown", "flaky"]);
+        assert!((ranked[3].1 - 0.8).abs() < 1e-12);
+    }
+
+    #[test]
+    fn failover_candidates_follow_injected_events() {
+        let (_dir, config) = test_support::config("");
+        let providers = [
+            Arc::new(MockProvider::new("first")),
+            Arc::new(MockProvider::new("second")),
+            Arc::new(MockProvider::new("third")),
+        ];
+        let state = test_support::state(config, &providers);
+        state.reliability().record(providers[0].id(), FAIL);
+        state
+            .reliability()
+            .record(providers[1].id(), Event::Failure(ErrorClass::RateLimited));
+        state
+            .reliability()
+            .record(providers[1].id(), Event::Success);
+
+        let names = |candidates: Vec<(Arc<dyn Provider>, f64)>| -> Vec<String> {
+            candidates
+                .iter()
+                .map(|(p, _)| p.name().to_string())
+                .collect()
+        };
+        assert_eq!(
+            names(state.failover_candidates(|_| true)),
+            ["third", "second", "first"]
+        );
+        // 过滤后的候选同样按评分排列
+        assert_eq!(
+            names(state.failover_candidates(|p| p.name() != "third")),
+            ["second", "first"]
+        );
+    }
+
+    async fn delete(router: &axum::Router, path: &str, key: &str) -> StatusCode {
+        let request = Request::delete(path)
+            .header("x-api-key", key)
+            .body(Body::empty())
+            .unwrap();
+        test_support::send(router, request).await.0
+    }
+
+    #[tokio::test]
+    async fn admin_endpoints_reset_scores() {
+        let (_dir, config) = test_support::config("");
+        let providers = [
+            Arc::new(MockProvider::new("first")),
+            Arc::new(MockProvider::new("second")),
+        ];
+        let state = test_support::state(config, &providers);
+        let router = test_router(state.clone());
+        for provider in &providers {
+            state.reliability().record(provider.id(), FAIL);
+        }
+
+        assert_eq!(
+            delete(&router, "/admin/providers/first/reliability", USER_KEY).await,
+            StatusCode::FORBIDDEN
+        );
+        assert!(state.reliability().get(providers[0].id()).is_some());
+
+        assert_eq!(
+            delete(&router, "/admin/providers/first/reliability", SECRET).await,
+            StatusCode::NO_CONTENT
+        );
+        assert!(state.reliability().get(providers[0].id()).is_none());
+        assert!(state.reliability().get(providers[1].id()).is_some());
+        assert_eq!(
+            delete(&router, "/admin/providers/missing/reliability", SECRET).await,
+            StatusCode::NOT_FOUND
+        );
+
+        assert_eq!(
+            delete(&router, "/admin/providers/reliability", SECRET).await,
+            StatusCode::NO_CONTENT
+        );
+        assert!(state.reliability().get(providers[1].id()).is_none());
+    }
+}
@@ -0,0 +1,110 @@
+//! 上游错误重试策略
+//!
+//! - 429 且 provider 上报了 rate limit 重置时间：等待到重置时间（至少 `min_wait`，最多 `max_rate_limit_wait`）
+//! - 其余可重试错误，或重置时间已过（时钟偏差）：指数退避
+//! - 指数退避按请求优先级缩放：高优先级减半，低优先级加倍
+
+use http::StatusCode;
+use std::time::Duration;
+
+use crate::keys::Priority;
+
+/// 指数退避的基础延迟
+const BASE_DELAY_MS: u64 = 500;
+/// 指数退避的最大延迟
+const MAX_BACKOFF_MS: u64 = 30_000;
+/// 按重置时间等待时的最小等待
+const RATE_LIMIT_MIN_WAIT_MS: u64 = 1_000;
+
+/// 等待时间的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackoffSource {
+    /// 按 rate limit 重置时间计算
+    ResetTimestamp,
+    /// 指数退避
+    Exponential,
+}
+
+impl BackoffSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackoffSource::ResetTimestamp => "reset_timestamp",
+            BackoffSource::Exponential => "exponential",
+        }
+    }
+}
+
+/// 计算出的等待时间
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub wait: Duration,
+    pub source: BackoffSource,
+}
+
+impl Backoff {
+    /// 按优先级缩放指数退避，按重置时间的等待不变
+    pub fn for_priority(self, priority: Priority) -> Self {
+        if self.source != BackoffSource::Exponential {
+            return self;
+        }
+        let wait = match priority {
+            Priority::High => self.wait / 2,
+            Priority::Normal => self.wait,
+            Priority::Low => (self.wait * 2).min(Duration::from_millis(MAX_BACKOFF_MS * 2)),
+        };
+        Self { wait, ..self }
+    }
+}
+
+/// 重试策略
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// 最大重试次数，0 表示不重试
+    pub max_retries: u32,
+    /// 非流式请求换 provider 的最大次数，`None` 表示不限
+    pub max_failover: Option<u32>,
+    /// 按重置时间等待的上限
+    pub max_rate_limit_wait: Duration,
+}
+
+impl RetryPolicy {
+    /// 上游状态码是否值得重试
+    pub fn is_retryable(status: StatusCode) -> bool {
+        status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+    }
+
+    /// 非流式请求遇到该状态码时是否换到其他 provider（不计入重试次数）
+    pub fn is_failover_status(status: StatusCode) -> bool {
+        matches!(status.as_u16(), 429 | 500 | 502 | 503 | 529)
+    }
+
+    /// 已换过 `failovers` 次 provider 后是否还能再换
+    pub fn can_fail_over(&self, failovers: u32) -> bool {
+        self.max_failover.is_none_or(|max| failovers < max)
+    }
+
+    /// 计算第 `attempt` 次重试（从 0 开始）前的等待时间
+    ///
+    /// * `reset` - provider 上报的 rate limit 重置时间 (Unix timestamp)，0 表示未知
+    /// * `now_ms` - 当前时间 (Unix timestamp, 毫秒)
+    pub fn backoff(&self, attempt: u32, status: StatusCode, reset: u64, now_ms: u64) -> Backoff {
+        if status == StatusCode::TOO_MANY_REQUESTS && reset > 0 {
+            let reset_ms = reset.saturating_mul(1000);
+            if reset_ms > now_ms {
+                let wait_ms = (reset_ms - now_ms).max(RATE_LIMIT_MIN_WAIT_MS);
+                return Backoff {
+                    wait: Duration::from_millis(wait_ms).min(self.max_rate_limit_wait),
+                    source: BackoffSource::ResetTimestamp,
+                };
+            }
+        }
+
+        let wait_ms = BASE_DELAY_MS
+            .saturating_mul(1u64 << attempt.min(16))
+            .min(MAX_BACKOFF_MS);
+        Backoff {
+            wait: Duration::from_millis(wait_ms),
+            source: BackoffSource::Exponential,
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! 有序关闭
+//!
+//! 收到关闭信号后按阶段依次执行：停止接收请求并等待进行中的请求（含流式响应）结束 →
+//! 写出各类记录 → 停止后台任务。每个阶段有独立的超时，超时的阶段会被放弃并继续下一阶段，
+//! 全部阶段结束后若有被放弃的阶段，以非零退出码强制退出。
+
+use futures::future::BoxFuture;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// 单个关闭阶段
+struct Phase {
+    name: &'static str,
+    timeout: Duration,
+    task: BoxFuture<'static, ()>,
+}
+
+/// 按顺序执行关闭阶段
+#[derive(Default)]
+pub struct ShutdownCoordinator {
+    phases: Vec<Phase>,
+}
+
+impl ShutdownCoordinator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 追加一个阶段，阶段按添加顺序执行
+    pub fn phase(
+        mut self,
+        name: &'static str,
+        timeout: Duration,
+        task: impl Future<Output = ()> + Send + 'static,
+    ) -> Self {
+        self.phases.push(Phase {
+            name,
+            timeout,
+            task: Box::pin(task),
+        });
+        self
+    }
+
+    /// 执行所有阶段，返回超时被放弃的阶段名称
+    pub async fn run(self) -> Vec<&'static str> {
+        let mut abandoned = Vec::new();
+
+        for phase in self.phases {
+            let start = Instant::now();
+            match tokio::time::timeout(phase.timeout, phase.task).await {
+                Ok(()) => tracing::info!(
+                    phase = phase.name,
+                    duration_ms = start.elapsed().as_millis() as u64,
+                    "shutdown phase completed"
+                ),
+                Err(_) => {
+                    tracing::error!(
+                        phase = phase.name,
+                        timeout_ms = phase.timeout.as_millis() as u64,
+                        "shutdown phase timed out, abandoning"
+                    );
+                    abandoned.push(phase.name);
+                }
+            }
+        }
+
+        abandoned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    fn record(log: &Arc<Mutex<Vec<&'static str>>>, name: &'static str) -> impl Future<Output = ()> {
+        let log = log.clone();
+        async move { log.lock().unwrap().push(name) }
+    }
+
+    #[tokio::test]
+    async fn phases_run_in_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let timeout = Duration::from_secs(1);
+        let abandoned = ShutdownCoordinator::new()
+            .phase("drain", timeout, record(&log, "drain"))
+            .phase("flush", timeout, record(&log, "flush"))
+            .phase("stop", timeout, record(&log, "stop"))
+            .run()
+            .await;
+
+        assert!(abandoned.is_empty());
+        assert_eq!(*log.lock().unwrap(), ["drain", "flush", "stop"]);
+    }
+
+    #[tokio::test]
+    async fn stuck_phase_times_out_and_next_phase_runs() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let start = Instant::now();
+        let abandoned = ShutdownCoordinator::new()
+            .phase(
+                "drain",
+                Duration::from_millis(50),
+                futures::future::pending(),
+            )
+            .phase("flush", Duration::from_secs(5), record(&log, "flush"))
+            .run()
+            .await;
+
+        assert_eq!(abandoned, ["drain"]);
+        assert_eq!(*log.lock().unwrap(), ["flush"]);
+        // 卡住的阶段在其超时到达时被放弃
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(50), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+    }
+}
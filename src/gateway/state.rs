@@ -1,63 +1,689 @@
 //! Gateway 应用状态
 
-use std::sync::Arc;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
 
-use crate::providers::Provider;
+use crate::access_log::AccessLog;
+use crate::config::Config;
+use crate::dead_letter::DeadLetterLog;
+use crate::gateway::admission::Admission;
+use crate::gateway::budget::TokenBudgets;
+use crate::gateway::cache_prefix::CachePrefixAnalyzer;
+use crate::gateway::circuit::CircuitBreakers;
+use crate::gateway::conversation_budget::ConversationBudgets;
+use crate::gateway::history::RequestHistory;
+use crate::gateway::in_flight::InFlight;
+use crate::gateway::key_limits::KeyLimits;
+use crate::gateway::middleware;
+use crate::gateway::pinning::ToolLoopPins;
+use crate::gateway::pool_headroom::{self, PoolHeadroom};
+use crate::gateway::probe::{self, Candidate, LatencyProbes};
+use crate::gateway::reliability::Reliability;
+use crate::gateway::retry::RetryPolicy;
+use crate::gateway::streams::StreamRegistry;
+use crate::gateway::weighted::{self, SelectionMode, WeightedRoundRobin};
+use crate::keys::{KeyStore, KeyUsageTracker};
+use crate::providers::{Provider, ProviderSettings};
+#[cfg(feature = "usage-sqlite")]
+use crate::usage::{UsageRecorder, UsageStore};
+
+/// provider 列表的快照
+type ProviderList = Arc<Vec<Arc<dyn Provider>>>;
 
 /// Gateway 应用状态
 #[derive(Clone)]
 pub struct AppState {
-    providers: Arc<Vec<Arc<dyn Provider>>>,
+    /// 重新加载时整体替换
+    providers: Arc<RwLock<ProviderList>>,
+    round_robin: Arc<WeightedRoundRobin>,
+    /// 按 provider ID 覆盖配置文件中的 `weight`，见 [`with_weights`](Self::with_weights)
+    weights: Arc<HashMap<String, u32>>,
+    /// 下一个请求的 ID
+    request_counter: Arc<AtomicU64>,
+    config: Arc<Config>,
+    /// 创建 provider 时使用的设置，重新加载时沿用
+    provider_settings: Arc<ProviderSettings>,
+    keys: Arc<RwLock<Arc<KeyStore>>>,
+    key_usage: Arc<KeyUsageTracker>,
+    key_limits: Arc<KeyLimits>,
+    history: Arc<RequestHistory>,
+    budgets: Arc<TokenBudgets>,
+    conversation_budgets: Arc<ConversationBudgets>,
+    pins: Arc<ToolLoopPins>,
+    dead_letters: Arc<DeadLetterLog>,
+    streams: Arc<StreamRegistry>,
+    cache_prefix: Arc<CachePrefixAnalyzer>,
+    admission: Arc<Admission>,
+    latency: Arc<LatencyProbes>,
+    in_flight: Arc<InFlight>,
+    reliability: Arc<Reliability>,
+    circuits: Arc<CircuitBreakers>,
+    access_log: Arc<AccessLog>,
+    /// 用量历史，`PLURIBUS_USAGE_DB` 为空时关闭
+    #[cfg(feature = "usage-sqlite")]
+    usage: Option<Arc<UsageStore>>,
+    #[cfg(feature = "usage-sqlite")]
+    usage_recorder: Option<UsageRecorder>,
 }
 
 const UTILIZATION_THRESHOLD: f64 = 0.995;
 
+/// provider 暂不参与选择的原因
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum SkipReason {
+    /// 授权中缺少推理所需的 OAuth scopes
+    MissingScopes,
+    /// refresh token 已被作废，需要重新登录
+    NeedsReauth,
+    /// 连续失败后熔断
+    CircuitOpen,
+    /// 上游已拒绝该窗口的请求（status 为 `rejected`）
+    RateLimitRejected { window: &'static str, reset: u64 },
+    /// 窗口利用率超过阈值
+    RateLimitExhausted { window: &'static str, reset: u64 },
+    /// 不在启用时间段内
+    OutsideSchedule,
+    /// 当月 token 配额已用尽
+    QuotaExhausted,
+}
+
+/// 重新加载 provider 前后的变化，按 provider ID 比较
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub unchanged: Vec<String>,
+}
+
 /// 检查单个窗口是否可用
-/// 如果利用率超过阈值，但已过重置时间，仍视为可用
-fn is_window_available(window: &crate::providers::RateLimitWindow) -> bool {
-    if window.utilization <= UTILIZATION_THRESHOLD {
-        return true;
+/// 被拒绝或利用率超过阈值时不可用，但已过重置时间仍视为可用
+fn window_skip_reason(
+    name: &'static str,
+    window: &crate::providers::RateLimitWindow,
+    now_secs: u64,
+) -> Option<SkipReason> {
+    if window.status == "rejected" && window.reset > now_secs {
+        return Some(SkipReason::RateLimitRejected {
+            window: name,
+            reset: window.reset,
+        });
     }
-    // 利用率超过阈值，检查是否已过重置时间
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
-    now >= window.reset
+    if window.utilization > UTILIZATION_THRESHOLD && now_secs < window.reset {
+        return Some(SkipReason::RateLimitExhausted {
+            window: name,
+            reset: window.reset,
+        });
+    }
+    None
 }
 
-fn is_provider_available(provider: &Arc<dyn crate::providers::Provider>) -> bool {
-    if let Some(rate_limit) = provider.rate_limit_info() {
-        if !is_window_available(&rate_limit.seven_day) {
-            return false;
-        }
-        if !is_window_available(&rate_limit.five_hour) {
-            return false;
-        }
-    }
-    true
+fn rate_limit_skip_reason(
+    provider: &Arc<dyn crate::providers::Provider>,
+    now_secs: u64,
+) -> Option<SkipReason> {
+    let rate_limit = provider.rate_limit_info()?;
+    window_skip_reason("seven_day", &rate_limit.seven_day, now_secs)
+        .or_else(|| window_skip_reason("five_hour", &rate_limit.five_hour, now_secs))
+}
+
+/// rate limit 窗口中较高的利用率，没有 rate limit 信息时为 0
+fn provider_utilization(provider: &Arc<dyn crate::providers::Provider>) -> f64 {
+    provider
+        .rate_limit_info()
+        .map(|info| info.five_hour.utilization.max(info.seven_day.utilization))
+        .unwrap_or(0.0)
 }
 
 impl AppState {
-    pub fn new(providers: Vec<Arc<dyn crate::providers::Provider>>) -> Self {
+    pub fn new(
+        providers: Vec<Arc<dyn crate::providers::Provider>>,
+        config: Config,
+        keys: KeyStore,
+    ) -> Self {
+        let history = RequestHistory::new(
+            config.request_history_size,
+            config.request_history_body_bytes,
+            !config.gdpr_mode,
+        );
+
+        let pins = ToolLoopPins::new(config.tool_pin_ttl_secs, config.tool_pin_max_entries);
+        let dead_letters = DeadLetterLog::new(
+            config.dead_letter_file.clone(),
+            config.dead_letter_max_bytes,
+            config.dead_letter_retention_days,
+        );
+        let cache_prefix = CachePrefixAnalyzer::new(
+            config.cache_prefix_analyzer,
+            config.cache_prefix_max_entries,
+        );
+        let admission = Admission::new(
+            config.max_concurrent_requests,
+            config.max_queued_requests,
+            config.admission_scheduler,
+            config.fair_share_max_percent,
+        );
+        let budgets = TokenBudgets::new(&providers, crate::utils::unix_timestamp_secs());
+        let latency = LatencyProbes::new(config.latency_ewma_alpha);
+        let circuits = CircuitBreakers::new(
+            providers.iter().map(|p| p.id()),
+            config.circuit_failure_threshold,
+            config.circuit_recovery_secs,
+        );
+        let access_log = AccessLog::new(config.access_log_sample, config.slow_request_ms);
+        let conversation_budgets = ConversationBudgets::new(
+            config.conversation_budget_ttl_secs,
+            config.conversation_budget_max_entries,
+        );
+
+        Self {
+            providers: Arc::new(RwLock::new(Arc::new(providers))),
+            round_robin: Arc::default(),
+            weights: Arc::default(),
+            request_counter: Arc::new(AtomicU64::new(1)),
+            provider_settings: Arc::new(ProviderSettings::from_config(&config)),
+            config: Arc::new(config),
+            keys: Arc::new(RwLock::new(Arc::new(keys))),
+            key_usage: Arc::default(),
+            key_limits: Arc::default(),
+            history: Arc::new(history),
+            budgets: Arc::new(budgets),
+            conversation_budgets: Arc::new(conversation_budgets),
+            pins: Arc::new(pins),
+            dead_letters: Arc::new(dead_letters),
+            streams: Arc::default(),
+            cache_prefix: Arc::new(cache_prefix),
+            admission: Arc::new(admission),
+            latency: Arc::new(latency),
+            in_flight: Arc::default(),
+            reliability: Arc::default(),
+            circuits: Arc::new(circuits),
+            access_log: Arc::new(access_log),
+            #[cfg(feature = "usage-sqlite")]
+            usage: None,
+            #[cfg(feature = "usage-sqlite")]
+            usage_recorder: None,
+        }
+    }
+
+    /// 同 [`new`](Self::new)，加权选择时使用与各 provider 一同传入的权重而不是其配置的 `weight`
+    ///
+    /// 权重按 provider ID 记录，重新加载后同一 ID 的 provider 沿用。正常运行时权重来自配置文件，
+    /// 此构造函数供测试以合成的 provider 验证加权分布
+    #[cfg(test)]
+    pub fn with_weights(
+        providers: Vec<(Arc<dyn crate::providers::Provider>, u32)>,
+        config: Config,
+        keys: KeyStore,
+    ) -> Self {
+        let weights = providers
+            .iter()
+            .map(|(provider, weight)| (provider.id().to_string(), *weight))
+            .collect();
+        let providers = providers
+            .into_iter()
+            .map(|(provider, _)| provider)
+            .collect();
         Self {
-            providers: Arc::new(providers),
+            weights: Arc::new(weights),
+            ..Self::new(providers, config, keys)
+        }
+    }
+
+    /// 加权选择时 provider 的权重
+    pub fn weight(&self, provider: &Arc<dyn crate::providers::Provider>) -> u32 {
+        self.weights
+            .get(provider.id())
+            .copied()
+            .unwrap_or_else(|| provider.weight())
+    }
+
+    /// 启用用量历史
+    #[cfg(feature = "usage-sqlite")]
+    pub fn with_usage(mut self, store: Arc<UsageStore>, recorder: UsageRecorder) -> Self {
+        self.usage = Some(store);
+        self.usage_recorder = Some(recorder);
+        self
+    }
+
+    /// 使用加载 `providers` 时的 provider 设置（默认由配置重新生成）
+    pub fn with_provider_settings(mut self, settings: Arc<ProviderSettings>) -> Self {
+        self.provider_settings = settings;
+        self
+    }
+
+    /// 从 `next` 开始分配请求 ID（默认从 1 开始）
+    ///
+    /// 配合 `PLURIBUS_SELECTION_SEED`，从相同 ID 开始的同一请求序列得到相同的 provider 分配
+    pub fn with_request_counter(self, next: u64) -> Self {
+        self.request_counter.store(next, Ordering::Relaxed);
+        self
+    }
+
+    /// 分配下一个请求 ID
+    pub fn next_request_id(&self) -> u64 {
+        self.request_counter.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn provider_settings(&self) -> &Arc<ProviderSettings> {
+        &self.provider_settings
+    }
+
+    pub fn keys(&self) -> Arc<KeyStore> {
+        self.keys
+            .read()
+            .map(|keys| keys.clone())
+            .unwrap_or_default()
+    }
+
+    /// 重新加载 keys 文件，失败时保留当前密钥
+    pub fn reload_keys(&self) -> Result<()> {
+        let keys = KeyStore::load(&self.config.keys_file, &self.config.secret)?;
+        if let Ok(mut guard) = self.keys.write() {
+            *guard = Arc::new(keys);
+        }
+        Ok(())
+    }
+
+    pub fn key_usage(&self) -> &KeyUsageTracker {
+        &self.key_usage
+    }
+
+    pub fn key_limits(&self) -> &KeyLimits {
+        &self.key_limits
+    }
+
+    pub fn history(&self) -> &RequestHistory {
+        &self.history
+    }
+
+    pub fn dead_letters(&self) -> &DeadLetterLog {
+        &self.dead_letters
+    }
+
+    #[cfg(feature = "usage-sqlite")]
+    pub fn usage(&self) -> Option<&UsageStore> {
+        self.usage.as_deref()
+    }
+
+    #[cfg(feature = "usage-sqlite")]
+    pub fn usage_recorder(&self) -> Option<&UsageRecorder> {
+        self.usage_recorder.as_ref()
+    }
+
+    pub fn streams(&self) -> &Arc<StreamRegistry> {
+        &self.streams
+    }
+
+    pub fn cache_prefix(&self) -> &CachePrefixAnalyzer {
+        &self.cache_prefix
+    }
+
+    pub fn admission(&self) -> &Arc<Admission> {
+        &self.admission
+    }
+
+    pub fn budgets(&self) -> &TokenBudgets {
+        &self.budgets
+    }
+
+    pub fn conversation_budgets(&self) -> &ConversationBudgets {
+        &self.conversation_budgets
+    }
+
+    pub fn pins(&self) -> &ToolLoopPins {
+        &self.pins
+    }
+
+    pub fn latency(&self) -> &LatencyProbes {
+        &self.latency
+    }
+
+    pub fn in_flight(&self) -> &InFlight {
+        &self.in_flight
+    }
+
+    pub fn access_log(&self) -> &AccessLog {
+        &self.access_log
+    }
+
+    pub fn reliability(&self) -> &Reliability {
+        &self.reliability
+    }
+
+    pub fn circuits(&self) -> &CircuitBreakers {
+        &self.circuits
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: self.config.max_retries,
+            max_failover: self.config.max_failover,
+            max_rate_limit_wait: std::time::Duration::from_secs(
+                self.config.rate_limit_max_wait_secs,
+            ),
+        }
+    }
+
+    /// 当前的 provider 列表，重新加载不影响已取得的列表
+    pub fn providers(&self) -> ProviderList {
+        self.providers
+            .read()
+            .map(|providers| providers.clone())
+            .unwrap_or_default()
+    }
+
+    /// 重新加载 provider 配置目录并替换 provider 列表，加载失败时保留当前列表
+    ///
+    /// 按 ID 保留仍存在的 provider 的熔断、预算等运行时状态；新的 provider 实例重新读取配置文件，
+    /// 其他进程写入的 token 随之生效
+    pub async fn reload_providers(&self) -> Result<ProviderChanges> {
+        let providers =
+            crate::providers::load_providers(self.config.providers_dir(), &self.provider_settings)
+                .await?;
+        let previous = self.providers();
+        let changes = ProviderChanges {
+            added: providers
+                .iter()
+                .filter(|p| !previous.iter().any(|old| old.id() == p.id()))
+                .map(|p| p.name().to_string())
+                .collect(),
+            removed: previous
+                .iter()
+                .filter(|old| !providers.iter().any(|p| p.id() == old.id()))
+                .map(|old| old.name().to_string())
+                .collect(),
+            unchanged: providers
+                .iter()
+                .filter(|p| previous.iter().any(|old| old.id() == p.id()))
+                .map(|p| p.name().to_string())
+                .collect(),
+        };
+
+        self.budgets
+            .sync(&providers, crate::utils::unix_timestamp_secs());
+        self.circuits.sync(providers.iter().map(|p| p.id()));
+        if let Ok(mut guard) = self.providers.write() {
+            *guard = Arc::new(providers);
+        }
+        tracing::info!(
+            added = ?changes.added,
+            removed = ?changes.removed,
+            unchanged = ?changes.unchanged,
+            "Providers reloaded"
+        );
+        Ok(changes)
+    }
+
+    /// 按名称精确查找 provider，不考虑是否可选
+    pub fn provider_by_name(&self, name: &str) -> Option<Arc<dyn crate::providers::Provider>> {
+        self.providers().iter().find(|p| p.name() == name).cloned()
+    }
+
+    /// 当前可选 provider 汇总的 rate limit 余量
+    pub fn pool_headroom(&self) -> PoolHeadroom {
+        let providers = self.providers();
+        let usable: Vec<_> = providers
+            .iter()
+            .filter(|provider| self.is_selectable(provider))
+            .map(|provider| provider.rate_limit_info())
+            .collect();
+        pool_headroom::aggregate(
+            &usable,
+            providers.len(),
+            crate::utils::unix_timestamp_secs(),
+        )
+    }
+
+    /// provider 暂不参与选择的原因：缺少 OAuth scopes、已熔断、被 rate limit 拒绝或达到上限、
+    /// 不在启用时间段内或当月配额用尽；可以选择时为 `None`
+    pub fn skip_reason(
+        &self,
+        provider: &Arc<dyn crate::providers::Provider>,
+    ) -> Option<SkipReason> {
+        let now = crate::utils::unix_timestamp_secs();
+        if !provider.missing_scopes().is_empty() {
+            return Some(SkipReason::MissingScopes);
         }
+        if provider.needs_reauth().is_some() {
+            return Some(SkipReason::NeedsReauth);
+        }
+        if self
+            .circuits
+            .get(provider.id())
+            .is_some_and(|breaker| !breaker.allows(now))
+        {
+            return Some(SkipReason::CircuitOpen);
+        }
+        if let Some(reason) = rate_limit_skip_reason(provider, now) {
+            return Some(reason);
+        }
+        if provider
+            .schedule()
+            .is_some_and(|schedule| !schedule.is_active(now))
+        {
+            return Some(SkipReason::OutsideSchedule);
+        }
+        if self.budgets.is_exhausted(provider.id()) {
+            return Some(SkipReason::QuotaExhausted);
+        }
+        None
     }
 
-    pub fn providers(&self) -> &[Arc<dyn crate::providers::Provider>] {
-        &self.providers
+    /// provider 当前是否可被选择
+    pub fn is_selectable(&self, provider: &Arc<dyn crate::providers::Provider>) -> bool {
+        let Some(reason) = self.skip_reason(provider) else {
+            return true;
+        };
+        tracing::debug!(provider = provider.name(), reason = ?reason, "skipping provider");
+        false
+    }
+
+    /// 满足条件的 provider 全部被上游 rate limit 拒绝时，返回其中最早的重置时间
+    pub fn all_rejected_until<F>(&self, mut filter: F) -> Option<u64>
+    where
+        F: FnMut(&&Arc<dyn crate::providers::Provider>) -> bool,
+    {
+        let mut earliest: Option<u64> = None;
+        for provider in self.providers().iter().filter(|p| filter(p)) {
+            let Some(SkipReason::RateLimitRejected { reset, .. }) = self.skip_reason(provider)
+            else {
+                return None;
+            };
+            earliest = Some(earliest.map_or(reset, |e| e.min(reset)));
+        }
+        earliest
     }
 
     /// 按优先级顺序选择第一个可用的 provider
+    ///
+    /// 开启延迟探测时，利用率相近的可用 provider 中优先选择 TTFT 更低的一个；
+    /// 加权模式下改为在可用 provider 之间按权重轮询，最少连接模式下选择转发中请求最少的一个；
+    /// 设置了选择种子时只由种子与当前请求 ID 决定
     pub fn get_next_provider<F>(&self, filter: F) -> Option<Arc<dyn crate::providers::Provider>>
     where
         F: FnMut(&&Arc<dyn crate::providers::Provider>) -> bool,
     {
-        self.providers
+        self.select(filter, true)
+    }
+
+    /// [`get_next_provider`](Self::get_next_provider) 会选出的 provider，不推进加权轮询
+    pub fn preview_next_provider<F>(&self, filter: F) -> Option<Arc<dyn crate::providers::Provider>>
+    where
+        F: FnMut(&&Arc<dyn crate::providers::Provider>) -> bool,
+    {
+        self.select(filter, false)
+    }
+
+    fn select<F>(&self, mut filter: F, advance: bool) -> Option<Arc<dyn crate::providers::Provider>>
+    where
+        F: FnMut(&&Arc<dyn crate::providers::Provider>) -> bool,
+    {
+        let providers = self.providers();
+        if let Some(seed) = self.config.selection_seed {
+            let candidates: Vec<_> = providers
+                .iter()
+                .filter(|p| self.is_selectable(p) && filter(p))
+                .collect();
+            let request_id = middleware::current_request_id().unwrap_or(0);
+            let index = weighted::seeded_index(seed, request_id, candidates.len())?;
+            return Some(candidates[index].clone());
+        }
+        if self.config.provider_selection == SelectionMode::Weighted {
+            let candidates: Vec<_> = providers
+                .iter()
+                .filter(|p| self.is_selectable(p) && filter(p))
+                .collect();
+            let weighted: Vec<(&str, u32)> = candidates
+                .iter()
+                .map(|p| (p.id(), self.weight(p)))
+                .collect();
+            let index = if advance {
+                self.round_robin.pick(&weighted)?
+            } else {
+                self.round_robin.peek(&weighted)?
+            };
+            return Some(candidates[index].clone());
+        }
+        if self.config.provider_selection == SelectionMode::LeastConnections {
+            return providers
+                .iter()
+                .filter(|p| self.is_selectable(p) && filter(p))
+                .min_by_key(|p| self.in_flight.get(p.id()))
+                .cloned();
+        }
+
+        let mut candidates = providers
+            .iter()
+            .filter(|p| self.is_selectable(p))
+            .filter(filter);
+        let margin = self.config.latency_tiebreak_margin;
+        if self.config.latency_probe_interval_secs == 0 || margin <= 0.0 {
+            return candidates.next().cloned();
+        }
+
+        let candidates: Vec<_> = candidates.collect();
+        let scored: Vec<Candidate> = candidates
+            .iter()
+            .map(|p| Candidate {
+                utilization: provider_utilization(p),
+                ttft_ewma_ms: self.latency.ttft_ewma_ms(p.id()),
+            })
+            .collect();
+        let index = probe::tie_break(&scored, margin);
+        if index > 0 {
+            tracing::debug!(
+                provider = candidates[index].name(),
+                instead_of = candidates[0].name(),
+                "preferring faster provider with similar utilization"
+            );
+        }
+        candidates.get(index).map(|p| (*p).clone())
+    }
+
+    /// 换 provider 重试时的候选，按可靠性评分从高到低排列
+    pub fn failover_candidates<F>(
+        &self,
+        mut filter: F,
+    ) -> Vec<(Arc<dyn crate::providers::Provider>, f64)>
+    where
+        F: FnMut(&&Arc<dyn crate::providers::Provider>) -> bool,
+    {
+        let candidates = self
+            .providers()
             .iter()
-            .filter(|p| is_provider_available(p))
-            .find(filter)
+            .filter(|p| self.is_selectable(p) && filter(p))
             .cloned()
+            .collect();
+        self.reliability.rank(candidates, |p| p.id())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, MockProvider};
+    use std::collections::HashMap;
+
+    fn seeded_state(seed: u64) -> (tempfile::TempDir, AppState) {
+        let (dir, config) = test_support::config(&format!("selection_seed = {seed}"));
+        let providers: Vec<_> = ["first", "second", "third", "fourth"]
+            .into_iter()
+            .map(|name| Arc::new(MockProvider::new(name)))
+            .collect();
+        (dir, test_support::state(config, &providers))
+    }
+
+    async fn pick(state: &AppState, request_id: u64) -> String {
+        middleware::with_request_id(request_id, async {
+            state
+                .get_next_provider(|_| true)
+                .unwrap()
+                .name()
+                .to_string()
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn seeded_selection_is_deterministic_per_request_id() {
+        let (_dir, state) = seeded_state(42);
+        let (_other_dir, same_seed) = seeded_state(42);
+        let (_third_dir, other_seed) = seeded_state(7);
+
+        let mut picks = Vec::new();
+        for request_id in 0..200 {
+            let chosen = pick(&state, request_id).await;
+            // 重复选择、预览与另一个同种子的实例都得到同一个 provider
+            assert_eq!(pick(&state, request_id).await, chosen);
+            assert_eq!(pick(&same_seed, request_id).await, chosen);
+            let preview = middleware::with_request_id(request_id, async {
+                state
+                    .preview_next_provider(|_| true)
+                    .unwrap()
+                    .name()
+                    .to_string()
+            })
+            .await;
+            assert_eq!(preview, chosen);
+            picks.push(chosen);
+        }
+
+        let mut other = Vec::new();
+        for request_id in 0..200 {
+            other.push(pick(&other_seed, request_id).await);
+        }
+        assert_ne!(picks, other);
+
+        // 不同请求 ID 分散到所有 provider
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for name in &picks {
+            *counts.entry(name.as_str()).or_default() += 1;
+        }
+        assert_eq!(counts.len(), 4, "{counts:?}");
+        assert!(counts.values().all(|count| *count >= 25), "{counts:?}");
+    }
+
+    #[tokio::test]
+    async fn seeded_selection_only_picks_eligible_providers() {
+        let (_dir, state) = seeded_state(42);
+        for request_id in 0..50 {
+            let chosen = middleware::with_request_id(request_id, async {
+                state
+                    .get_next_provider(|p| p.name() != "first")
+                    .unwrap()
+                    .name()
+                    .to_string()
+            })
+            .await;
+            assert_ne!(chosen, "first");
+        }
     }
 }
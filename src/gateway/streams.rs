@@ -0,0 +1,431 @@
+//! 流式响应登记与看门狗
+//!
+//! 每个转发中的流式响应在登记表中记录开始时间、最后一次转发数据的时间、已转发字节数、
+//! provider 与请求 ID。看门狗定期检查：超过告警时长或空闲时长的流记录警告，超过硬上限的流
+//! 被强制中止。管理接口可列出所有流或手动中止某个流。
+//!
+//! 中止或上游中途失败时，响应体以一个 Anthropic 格式的 `error` 事件（`error.code` 说明原因）
+//! 正常结束；转发任务在通道关闭后退出并清理自身计数。
+
+use axum::body::Bytes;
+use futures::Stream;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+use crate::providers::sse::{self, StreamFailure, StreamFormat};
+use crate::utils::unix_timestamp_secs;
+
+/// 看门狗的检查阈值
+#[derive(Debug, Clone, Copy)]
+pub struct WatchdogLimits {
+    /// 超过该时长记录警告
+    pub warn_age: Duration,
+    /// 超过该时长未转发数据记录警告
+    pub idle: Duration,
+    /// 超过该时长强制中止，0 表示不限制
+    pub max_age: Duration,
+}
+
+/// 单个登记的流
+struct StreamEntry {
+    request_id: u64,
+    provider: Option<String>,
+    model: String,
+    started: Instant,
+    started_at: u64,
+    /// 最后一次转发数据距开始的毫秒数
+    last_event_ms: AtomicU64,
+    bytes: AtomicU64,
+    /// 已记录过超时警告，避免每次检查重复记录
+    warned: AtomicBool,
+    abort: watch::Sender<Option<StreamFailure>>,
+}
+
+impl StreamEntry {
+    fn idle(&self) -> Duration {
+        self.started.elapsed().saturating_sub(Duration::from_millis(
+            self.last_event_ms.load(Ordering::Relaxed),
+        ))
+    }
+}
+
+/// `GET /admin/streams` 中的单个流
+#[derive(Debug, Serialize)]
+pub struct StreamInfo {
+    pub id: u64,
+    pub request_id: u64,
+    pub provider: Option<String>,
+    pub model: String,
+    /// 开始时间 (Unix timestamp)
+    pub started_at: u64,
+    pub age_secs: u64,
+    pub idle_secs: u64,
+    pub bytes: u64,
+}
+
+/// 转发中的流式响应登记表
+#[derive(Default)]
+pub struct StreamRegistry {
+    next_id: AtomicU64,
+    entries: Mutex<HashMap<u64, Arc<StreamEntry>>>,
+}
+
+impl StreamRegistry {
+    /// 登记一个流，返回的 [`Registration`] 在流结束（drop）时自动注销
+    pub fn register(
+        self: &Arc<Self>,
+        request_id: u64,
+        provider: Option<&str>,
+        model: &str,
+    ) -> Registration {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let (abort, aborted) = watch::channel(None);
+        let entry = Arc::new(StreamEntry {
+            request_id,
+            provider: provider.map(str::to_string),
+            model: model.to_string(),
+            started: Instant::now(),
+            started_at: unix_timestamp_secs(),
+            last_event_ms: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            warned: AtomicBool::new(false),
+            abort,
+        });
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.insert(id, entry.clone());
+        }
+        Registration {
+            id,
+            registry: self.clone(),
+            entry,
+            aborted,
+        }
+    }
+
+    pub fn list(&self) -> Vec<StreamInfo> {
+        let Ok(entries) = self.entries.lock() else {
+            return Vec::new();
+        };
+        let mut streams: Vec<StreamInfo> = entries
+            .iter()
+            .map(|(id, entry)| StreamInfo {
+                id: *id,
+                request_id: entry.request_id,
+                provider: entry.provider.clone(),
+                model: entry.model.clone(),
+                started_at: entry.started_at,
+                age_secs: entry.started.elapsed().as_secs(),
+                idle_secs: entry.idle().as_secs(),
+                bytes: entry.bytes.load(Ordering::Relaxed),
+            })
+            .collect();
+        streams.sort_by_key(|s| s.id);
+        streams
+    }
+
+    /// 中止指定的流，流不存在时返回 false
+    pub fn abort(&self, id: u64, reason: StreamFailure) -> bool {
+        let Ok(entries) = self.entries.lock() else {
+            return false;
+        };
+        let Some(entry) = entries.get(&id) else {
+            return false;
+        };
+        entry.abort.send_replace(Some(reason));
+        true
+    }
+
+    /// 中止所有流，返回中止的数量
+    pub fn abort_all(&self, reason: StreamFailure) -> usize {
+        let Ok(entries) = self.entries.lock() else {
+            return 0;
+        };
+        for entry in entries.values() {
+            entry.abort.send_replace(Some(reason));
+        }
+        entries.len()
+    }
+
+    /// 检查所有流：超时或空闲过久的记录警告，超过硬上限的中止
+    pub fn check(&self, limits: WatchdogLimits) {
+        let Ok(entries) = self.entries.lock() else {
+            return;
+        };
+        for (id, entry) in entries.iter() {
+            let age = entry.started.elapsed();
+            let idle = entry.idle();
+
+            if !limits.max_age.is_zero() && age > limits.max_age {
+                tracing::error!(
+                    stream = id,
+                    request_id = entry.request_id,
+                    provider = entry.provider.as_deref(),
+                    age_secs = age.as_secs(),
+                    "aborting stream past its maximum age"
+                );
+                entry.abort.send_replace(Some(StreamFailure::MaxAge));
+                continue;
+            }
+
+            let stuck = age > limits.warn_age || idle > limits.idle;
+            if stuck && !entry.warned.swap(true, Ordering::Relaxed) {
+                tracing::warn!(
+                    stream = id,
+                    request_id = entry.request_id,
+                    provider = entry.provider.as_deref(),
+                    age_secs = age.as_secs(),
+                    idle_secs = idle.as_secs(),
+                    bytes = entry.bytes.load(Ordering::Relaxed),
+                    "stream relay looks stuck"
+                );
+            }
+        }
+    }
+
+    fn remove(&self, id: u64) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.remove(&id);
+        }
+    }
+}
+
+/// 流的登记，drop 时注销
+pub struct Registration {
+    id: u64,
+    registry: Arc<StreamRegistry>,
+    entry: Arc<StreamEntry>,
+    aborted: watch::Receiver<Option<StreamFailure>>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.registry.remove(self.id);
+    }
+}
+
+type AbortSignal = Pin<Box<dyn Future<Output = StreamFailure> + Send>>;
+
+/// 记录转发进度、可被中止的响应体
+pub struct RegisteredStream<S> {
+    inner: S,
+    registration: Registration,
+    abort_signal: AbortSignal,
+    /// 错误事件使用的输出格式
+    format: StreamFormat,
+    done: bool,
+}
+
+impl<S> RegisteredStream<S> {
+    pub fn new(inner: S, registration: Registration, format: StreamFormat) -> Self {
+        let mut aborted = registration.aborted.clone();
+        let abort_signal = Box::pin(async move {
+            let reason = aborted
+                .wait_for(Option::is_some)
+                .await
+                .ok()
+                .and_then(|r| *r);
+            match reason {
+                Some(reason) => reason,
+                // 登记已注销，不会再被中止
+                None => std::future::pending().await,
+            }
+        });
+        Self {
+            inner,
+            registration,
+            abort_signal,
+            format,
+            done: false,
+        }
+    }
+
+    /// 以错误事件结束流
+    fn fail(&mut self, failure: StreamFailure) -> Bytes {
+        self.done = true;
+        sse::error_event(failure, self.registration.entry.request_id, self.format)
+    }
+}
+
+impl<S> Stream for RegisteredStream<S>
+where
+    S: Stream<Item = Result<Bytes, axum::Error>> + Unpin,
+{
+    type Item = Result<Bytes, axum::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        if let Poll::Ready(reason) = self.abort_signal.as_mut().poll(cx) {
+            tracing::warn!(
+                stream = self.registration.id,
+                request_id = self.registration.entry.request_id,
+                code = reason.code(),
+                "stream aborted"
+            );
+            return Poll::Ready(Some(Ok(self.fail(reason))));
+        }
+
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => {
+                let entry = &self.registration.entry;
+                entry.bytes.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                entry.last_event_ms.store(
+                    entry.started.elapsed().as_millis() as u64,
+                    Ordering::Relaxed,
+                );
+            }
+            Poll::Ready(Some(Err(err))) => {
+                if let Some(failure) = StreamFailure::of(err) {
+                    return Poll::Ready(Some(Ok(self.fail(failure))));
+                }
+            }
+            _ => {}
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+
+    fn chunk(text: &'static str) -> Result<Bytes, axum::Error> {
+        Ok(Bytes::from_static(text.as_bytes()))
+    }
+
+    fn failure(failure: StreamFailure) -> Result<Bytes, axum::Error> {
+        Err(axum::Error::new(std::io::Error::from(failure)))
+    }
+
+    async fn collect<S>(stream: RegisteredStream<S>) -> Vec<Bytes>
+    where
+        S: Stream<Item = Result<Bytes, axum::Error>> + Unpin,
+    {
+        stream
+            .map(|item| item.expect("failures become error events"))
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn failed_stream_ends_with_an_error_event() {
+        let registry = Arc::new(StreamRegistry::default());
+        let inner = futures::stream::iter([
+            chunk("event: ping\ndata: {\"type\":\"ping\"}\n\n"),
+            failure(StreamFailure::UpstreamDisconnect),
+            chunk("never relayed"),
+        ]);
+        let registration = registry.register(7, Some("first"), "claude-sonnet-4-5");
+        let stream = RegisteredStream::new(inner, registration, StreamFormat::Sse);
+
+        let chunks = collect(stream).await;
+        assert_eq!(chunks.len(), 2, "{chunks:?}");
+        assert_eq!(
+            chunks[1],
+            sse::error_event(StreamFailure::UpstreamDisconnect, 7, StreamFormat::Sse)
+        );
+    }
+
+    #[tokio::test]
+    async fn aborted_stream_ends_with_an_error_event() {
+        let registry = Arc::new(StreamRegistry::default());
+        let registration = registry.register(9, None, "claude-sonnet-4-5");
+        let inner = futures::stream::pending::<Result<Bytes, axum::Error>>();
+        let stream = RegisteredStream::new(inner, registration, StreamFormat::Ndjson);
+
+        assert_eq!(registry.abort_all(StreamFailure::GatewayShutdown), 1);
+        let chunks = collect(stream).await;
+        assert_eq!(
+            chunks,
+            [sse::error_event(
+                StreamFailure::GatewayShutdown,
+                9,
+                StreamFormat::Ndjson
+            )]
+        );
+    }
+
+    fn limits(max_age: Duration) -> WatchdogLimits {
+        WatchdogLimits {
+            warn_age: Duration::ZERO,
+            idle: Duration::ZERO,
+            max_age,
+        }
+    }
+
+    #[tokio::test]
+    async fn registered_streams_are_listed_until_dropped() {
+        let registry = Arc::new(StreamRegistry::default());
+        let first = registry.register(1, Some("first"), "claude-sonnet-4-5");
+        let second = registry.register(2, None, "claude-haiku-4-5");
+
+        let listed: Vec<(u64, u64, Option<String>)> = registry
+            .list()
+            .into_iter()
+            .map(|s| (s.id, s.request_id, s.provider))
+            .collect();
+        assert_eq!(listed, [(1, 1, Some("first".to_string())), (2, 2, None)]);
+
+        let inner = futures::stream::iter([chunk("12345"), chunk("678")]);
+        let stream = RegisteredStream::new(inner, first, StreamFormat::Sse);
+        let mut stream = Box::pin(stream);
+        while stream.next().await.is_some() {}
+        assert_eq!(registry.list()[0].bytes, 8);
+
+        drop(stream);
+        let ids: Vec<u64> = registry.list().iter().map(|s| s.id).collect();
+        assert_eq!(ids, [2]);
+        assert!(!registry.abort(1, StreamFailure::Aborted));
+        assert!(registry.abort(2, StreamFailure::Aborted));
+        drop(second);
+        assert!(registry.list().is_empty());
+        assert_eq!(registry.abort_all(StreamFailure::GatewayShutdown), 0);
+    }
+
+    #[tokio::test]
+    async fn watchdog_reaps_streams_past_their_maximum_age() {
+        let registry = Arc::new(StreamRegistry::default());
+        let old = registry.register(1, Some("first"), "claude-sonnet-4-5");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let young = registry.register(2, Some("first"), "claude-sonnet-4-5");
+
+        registry.check(limits(Duration::from_millis(10)));
+        assert_eq!(*old.aborted.borrow(), Some(StreamFailure::MaxAge));
+        assert_eq!(*young.aborted.borrow(), None);
+
+        let inner = futures::stream::pending::<Result<Bytes, axum::Error>>();
+        let chunks = collect(RegisteredStream::new(inner, old, StreamFormat::Sse)).await;
+        assert_eq!(
+            chunks,
+            [sse::error_event(
+                StreamFailure::MaxAge,
+                1,
+                StreamFormat::Sse
+            )]
+        );
+        assert_eq!(registry.list().len(), 1, "reaped stream is removed");
+    }
+
+    #[tokio::test]
+    async fn stuck_streams_are_only_warned_without_a_maximum_age() {
+        let registry = Arc::new(StreamRegistry::default());
+        let stream = registry.register(1, None, "claude-sonnet-4-5");
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        registry.check(limits(Duration::ZERO));
+        registry.check(limits(Duration::ZERO));
+        assert!(stream.entry.warned.load(Ordering::Relaxed));
+        assert_eq!(*stream.aborted.borrow(), None);
+    }
+}
@@ -0,0 +1,167 @@
+//! 合成（测试）流量
+//!
+//! `pluribus test` 等 CLI 命令发出的请求带 `x-pluribus-synthetic: 1`。admin 密钥的此类请求照常
+//! 转发，但不影响生产状态：不记录 tool-use 固定与可缓存前缀，不计入 provider 预算、可靠性评分、
+//! 熔断器、会话上限、密钥使用记录与用量数据库。请求历史中标记 `synthetic`，指标中只计入
+//! `messages_requests_total{synthetic="true"}`。其他角色的密钥带上该头时忽略。
+
+use axum::http::HeaderMap;
+
+use crate::keys::{ClientKey, Role};
+
+/// 标记合成请求的请求头
+pub const SYNTHETIC_HEADER: &str = "x-pluribus-synthetic";
+
+/// 请求是否为合成流量（仅 admin 密钥生效）
+pub fn is_synthetic(headers: &HeaderMap, client: Option<&ClientKey>) -> bool {
+    let marked = headers
+        .get(SYNTHETIC_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| matches!(v.trim(), "1" | "true"));
+    if !marked {
+        return false;
+    }
+    if client.is_none_or(|c| c.role != Role::Admin) {
+        tracing::debug!(
+            key = client.map(|c| c.name.as_str()),
+            "{SYNTHETIC_HEADER} ignored for a non-admin key"
+        );
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    #[cfg(feature = "usage-sqlite")]
+    use std::time::Duration;
+
+    use axum::http::{HeaderValue, StatusCode};
+    use serde_json::json;
+
+    use super::*;
+    use crate::gateway::test_router;
+    #[cfg(feature = "metrics")]
+    use crate::metrics::{MESSAGES_REQUESTS, REQUESTS, TOKENS};
+    use crate::test_support::{self, messages_request, send, MockProvider, SECRET, USER_KEY};
+    #[cfg(feature = "usage-sqlite")]
+    use crate::usage::{UsageFilter, UsageRecorder, UsageStore};
+
+    fn request(key: &str) -> axum::http::Request<axum::body::Body> {
+        let mut request = messages_request(
+            key,
+            &json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 16,
+                "messages": [{"role": "user", "content": "hi"}]
+            }),
+        );
+        request
+            .headers_mut()
+            .insert(SYNTHETIC_HEADER, HeaderValue::from_static("1"));
+        request
+    }
+
+    #[test]
+    fn only_admin_keys_mark_synthetic_traffic() {
+        let (_dir, config) = test_support::config("");
+        let keys = test_support::keys(&config);
+        let admin = keys.authenticate(SECRET);
+        let user = keys.authenticate(USER_KEY);
+
+        let mut headers = HeaderMap::new();
+        assert!(!is_synthetic(&headers, admin.as_ref()));
+        for value in ["1", "true", " true "] {
+            headers.insert(SYNTHETIC_HEADER, HeaderValue::from_str(value).unwrap());
+            assert!(is_synthetic(&headers, admin.as_ref()), "{value:?}");
+            assert!(!is_synthetic(&headers, user.as_ref()), "{value:?}");
+            assert!(!is_synthetic(&headers, None), "{value:?}");
+        }
+        headers.insert(SYNTHETIC_HEADER, HeaderValue::from_static("0"));
+        assert!(!is_synthetic(&headers, admin.as_ref()));
+    }
+
+    #[tokio::test]
+    async fn synthetic_traffic_stays_out_of_production_state() {
+        let (dir, config) = test_support::config("");
+        let provider = Arc::new(MockProvider::new("synthetic-probe"));
+        let state = test_support::state(config, std::slice::from_ref(&provider));
+        #[cfg(feature = "usage-sqlite")]
+        let (state, store) = {
+            let store = Arc::new(
+                UsageStore::open(&dir.path().join("usage.db"), true)
+                    .await
+                    .unwrap(),
+            );
+            let (recorder, _) = UsageRecorder::spawn(store.clone());
+            (state.with_usage(store.clone(), recorder), store)
+        };
+        #[cfg(not(feature = "usage-sqlite"))]
+        let _ = &dir;
+        let router = test_router(state.clone());
+        let id = "id-synthetic-probe";
+        #[cfg(feature = "metrics")]
+        let synthetic_before = MESSAGES_REQUESTS.with_label_values(&["true"]).get();
+
+        let (status, _, _) = send(&router, request(SECRET)).await;
+        assert_eq!(status, StatusCode::OK);
+        provider.fail_with(StatusCode::INTERNAL_SERVER_ERROR, "boom");
+        let (status, _, _) = send(&router, request(SECRET)).await;
+        assert!(!status.is_success());
+        provider.recover();
+        assert_eq!(
+            provider.requests().len(),
+            2,
+            "synthetic requests are relayed"
+        );
+
+        assert!(state.reliability().get(id).is_none());
+        let breaker = state.circuits().get(id).and_then(|b| b.status());
+        assert!(breaker.is_none_or(|b| b.consecutive_failures == 0));
+        assert_eq!(state.budgets().status(id).map_or(0, |b| b.daily_tokens), 0);
+        assert!(state.key_usage().take().is_empty());
+        #[cfg(feature = "metrics")]
+        {
+            // 其他测试也可能发出合成请求，只检查下限
+            assert!(MESSAGES_REQUESTS.with_label_values(&["true"]).get() >= synthetic_before + 2);
+            for status in ["200", "500"] {
+                let requests =
+                    REQUESTS.with_label_values(&["synthetic-probe", "claude-sonnet-4-5", status]);
+                assert_eq!(requests.get(), 0, "{status}");
+            }
+            let tokens =
+                TOKENS.with_label_values(&["synthetic-probe", "claude-sonnet-4-5", "input"]);
+            assert_eq!(tokens.get(), 0);
+        }
+
+        // 同一 provider 的普通请求照常计入
+        let (status, _, _) = send(&router, request(USER_KEY)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(state.reliability().get(id).is_some());
+        assert!(!state.key_usage().take().is_empty());
+        #[cfg(feature = "metrics")]
+        assert_eq!(
+            REQUESTS
+                .with_label_values(&["synthetic-probe", "claude-sonnet-4-5", "200"])
+                .get(),
+            1
+        );
+        #[cfg(feature = "usage-sqlite")]
+        {
+            let filter = || UsageFilter {
+                provider: Some("synthetic-probe".to_string()),
+                ..Default::default()
+            };
+            let mut requests = 0;
+            for _ in 0..100 {
+                requests = store.report(filter()).await.unwrap().total.requests;
+                if requests > 0 {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            assert_eq!(requests, 1, "only the non-synthetic request is recorded");
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! 流式响应的 HTTP trailers
+//!
+//! 客户端请求带 `TE: trailers` 时，流结束后以 trailers 返回 provider 与 token 用量，
+//! 无需解析 SSE 事件即可读取 usage。HTTP/1.1 下 hyper 仅在客户端声明 `TE: trailers` 时发送 trailers。
+
+use axum::body::{Body, Bytes};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+use futures::{stream, Stream, StreamExt};
+use http_body::Frame;
+use http_body_util::StreamBody;
+use tokio::sync::oneshot;
+
+use crate::providers::Usage;
+
+const PROVIDER: HeaderName = HeaderName::from_static("x-pluribus-provider");
+const TOTAL_TOKENS: HeaderName = HeaderName::from_static("x-pluribus-total-tokens");
+const INPUT_TOKENS: HeaderName = HeaderName::from_static("x-pluribus-input-tokens");
+const OUTPUT_TOKENS: HeaderName = HeaderName::from_static("x-pluribus-output-tokens");
+
+/// `Trailer` 响应头中预先声明的 trailer 名称
+///
+/// hyper 按小写名称匹配，未在此声明的 trailer 会被丢弃
+pub const DECLARED_TRAILERS: &str =
+    "x-pluribus-provider, x-pluribus-total-tokens, x-pluribus-input-tokens, x-pluribus-output-tokens";
+
+/// 客户端是否通过 `TE` 请求头声明接受 trailers
+pub fn accepts_trailers(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::TE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|te| {
+            te.split(';')
+                .next()
+                .is_some_and(|t| t.trim().eq_ignore_ascii_case("trailers"))
+        })
+}
+
+/// 构建 trailers；usage 缺失（如流中途失败）时只包含 provider
+fn build_trailers(provider: Option<&str>, usage: Option<&Usage>) -> HeaderMap {
+    let mut trailers = HeaderMap::new();
+    if let Some(value) = provider.and_then(|p| HeaderValue::from_str(p).ok()) {
+        trailers.insert(PROVIDER, value);
+    }
+    if let Some(usage) = usage {
+        trailers.insert(TOTAL_TOKENS, HeaderValue::from(usage.total()));
+        trailers.insert(INPUT_TOKENS, HeaderValue::from(usage.input_tokens));
+        trailers.insert(OUTPUT_TOKENS, HeaderValue::from(usage.output_tokens));
+    }
+    trailers
+}
+
+/// 在数据流结束后追加 trailers
+///
+/// `usage_rx` 应在数据流结束时（或之前）收到 usage
+pub fn with_trailers<S, E>(
+    data: S,
+    provider: Option<String>,
+    usage_rx: oneshot::Receiver<Option<Usage>>,
+) -> Body
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<axum::BoxError> + 'static,
+{
+    let trailers = stream::once(async move {
+        let usage = usage_rx.await.ok().flatten();
+        Ok(Frame::trailers(build_trailers(
+            provider.as_deref(),
+            usage.as_ref(),
+        )))
+    });
+    let frames = data.map(|chunk| chunk.map(Frame::data)).chain(trailers);
+    Body::new(StreamBody::new(frames))
+}
@@ -0,0 +1,190 @@
+//! 按权重的 provider 选择
+//!
+//! `PLURIBUS_PROVIDER_SELECTION=weighted` 时，在当前可选的 provider 之间做平滑加权轮询
+//! （smooth weighted round-robin）：每次选择时各候选的当前值加上自身权重，选出当前值最大的一个
+//! 并减去本轮权重之和。权重 3 的 provider 获得权重 1 的三倍请求，且请求交错分布。
+//! 暂不可选的 provider 不参与本轮计算，恢复后从保留的当前值继续。
+//!
+//! 设置 `PLURIBUS_SELECTION_SEED` 时改为按种子与请求 ID 的哈希在可选的 provider 中选择，
+//! 不依赖之前的流量，同样的请求序列每次运行都分配到同样的 provider，用于可复现的集成测试。
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::utils::splitmix64;
+
+/// provider 选择方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SelectionMode {
+    /// 按顺序选择第一个可用的 provider
+    #[default]
+    Priority,
+    /// 按 provider 的 `weight` 加权轮询
+    Weighted,
+    /// 选择转发中请求最少的 provider，相同时按顺序
+    LeastConnections,
+}
+
+impl SelectionMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "priority" | "" => Some(SelectionMode::Priority),
+            "weighted" => Some(SelectionMode::Weighted),
+            "least_connections" | "least-connections" => Some(SelectionMode::LeastConnections),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SelectionMode::Priority => "priority",
+            SelectionMode::Weighted => "weighted",
+            SelectionMode::LeastConnections => "least_connections",
+        }
+    }
+}
+
+/// 平滑加权轮询的状态，按 provider ID 记录当前值
+#[derive(Default)]
+pub struct WeightedRoundRobin {
+    current: Mutex<HashMap<String, i64>>,
+}
+
+impl WeightedRoundRobin {
+    /// 从 `(provider ID, 权重)` 候选中选出一个，返回其下标
+    pub fn pick(&self, candidates: &[(&str, u32)]) -> Option<usize> {
+        if candidates.is_empty() {
+            return None;
+        }
+        let Ok(mut current) = self.current.lock() else {
+            return Some(0);
+        };
+        let index = choose(&current, candidates)?;
+        let total: i64 = candidates.iter().map(|(_, w)| i64::from(*w)).sum();
+        for (id, weight) in candidates {
+            *current.entry(id.to_string()).or_insert(0) += i64::from(*weight);
+        }
+        if let Some(value) = current.get_mut(candidates[index].0) {
+            *value -= total;
+        }
+        Some(index)
+    }
+
+    /// 下一次 [`pick`](Self::pick) 会选出的下标，不更新当前值
+    pub fn peek(&self, candidates: &[(&str, u32)]) -> Option<usize> {
+        if candidates.is_empty() {
+            return None;
+        }
+        let Ok(current) = self.current.lock() else {
+            return Some(0);
+        };
+        choose(&current, candidates)
+    }
+}
+
+/// 当前值加上权重后最大的候选，相同时取靠前的一个
+fn choose(current: &HashMap<String, i64>, candidates: &[(&str, u32)]) -> Option<usize> {
+    let mut best: Option<(usize, i64)> = None;
+    for (index, (id, weight)) in candidates.iter().enumerate() {
+        let value = current.get(*id).copied().unwrap_or(0) + i64::from(*weight);
+        if best.is_none_or(|(_, max)| value > max) {
+            best = Some((index, value));
+        }
+    }
+    best.map(|(index, _)| index)
+}
+
+/// 固定种子下为请求选出的候选下标，只取决于种子与请求 ID
+pub fn seeded_index(seed: u64, request_id: u64, len: usize) -> Option<usize> {
+    if len == 0 {
+        return None;
+    }
+    Some((splitmix64(seed ^ splitmix64(request_id)) % len as u64) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::state::AppState;
+    use crate::providers::Provider;
+    use crate::test_support::{self, MockProvider};
+    use std::sync::Arc;
+
+    /// `requests` 次选择中各候选被选中的次数
+    fn distribution(weights: &[u32], requests: usize) -> Vec<usize> {
+        let ids: Vec<String> = (0..weights.len()).map(|i| format!("p{i}")).collect();
+        let candidates: Vec<(&str, u32)> = ids
+            .iter()
+            .map(String::as_str)
+            .zip(weights.iter().copied())
+            .collect();
+        let round_robin = WeightedRoundRobin::default();
+        let mut counts = vec![0; weights.len()];
+        for _ in 0..requests {
+            counts[round_robin.pick(&candidates).unwrap()] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn distribution_over_1000_requests_matches_weights() {
+        assert_eq!(distribution(&[3, 1], 1000), [750, 250]);
+        assert_eq!(distribution(&[5, 3, 2], 1000), [500, 300, 200]);
+        assert_eq!(distribution(&[1, 1, 1, 1], 1000), [250; 4]);
+    }
+
+    #[test]
+    fn picks_are_interleaved() {
+        let round_robin = WeightedRoundRobin::default();
+        let candidates = [("a", 3), ("b", 1)];
+        let order: Vec<usize> = (0..8)
+            .map(|_| round_robin.pick(&candidates).unwrap())
+            .collect();
+        assert_eq!(order, [0, 0, 1, 0, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn peek_does_not_advance() {
+        let round_robin = WeightedRoundRobin::default();
+        let candidates = [("a", 1), ("b", 1)];
+        assert_eq!(round_robin.peek(&candidates), Some(0));
+        assert_eq!(round_robin.peek(&candidates), Some(0));
+        assert_eq!(round_robin.pick(&candidates), Some(0));
+        assert_eq!(round_robin.peek(&candidates), Some(1));
+        assert_eq!(round_robin.pick(&[]), None);
+    }
+
+    #[test]
+    fn app_state_uses_the_weights_it_was_built_with() {
+        let (_dir, config) = test_support::config("provider_selection = \"weighted\"");
+        let keys = test_support::keys(&config);
+        let heavy = Arc::new(MockProvider::new("heavy"));
+        let light = Arc::new(MockProvider::new("light"));
+        let state = AppState::with_weights(
+            vec![
+                (heavy.clone() as Arc<dyn Provider>, 3),
+                (light.clone() as Arc<dyn Provider>, 1),
+            ],
+            config,
+            keys,
+        );
+
+        let mut heavy_count = 0;
+        for _ in 0..1000 {
+            let provider = state.get_next_provider(|_| true).unwrap();
+            if provider.name() == "heavy" {
+                heavy_count += 1;
+            }
+        }
+        assert_eq!(heavy_count, 750);
+    }
+
+    #[test]
+    fn seeded_selection_only_depends_on_seed_and_request() {
+        let first: Vec<_> = (0..100).map(|id| seeded_index(7, id, 3)).collect();
+        let second: Vec<_> = (0..100).map(|id| seeded_index(7, id, 3)).collect();
+        assert_eq!(first, second);
+        assert!((0..3).all(|i| first.contains(&Some(i))));
+        assert_eq!(seeded_index(7, 1, 0), None);
+    }
+}
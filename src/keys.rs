@@ -0,0 +1,511 @@
+//! 客户端 API 密钥
+//!
+//! 从 keys.toml 加载客户端密钥及其角色，`PLURIBUS_SECRET` 始终作为名为 `default` 的 admin 密钥。
+//!
+//! ```toml
+//! [[keys]]
+//! name = "alice"
+//! key = "sk-..."
+//! role = "user"   # admin | readonly | user，默认 user
+//! max_priority = "normal"   # 可选，允许请求的最高优先级：high | normal | low，默认 normal
+//! token_passthrough = true   # 可选，允许通过 x-pluribus-upstream-token 自带上游 OAuth token，默认 false
+//! privacy = true   # 可选，响应中去除上游 request-id、rate limit 与 pluribus 附加的头和注解，默认 false
+//! conversation_budget = { soft_tokens = 500000, hard_tokens = 2000000 }   # 可选，单个会话的累计 token 上限
+//! requests_per_minute = 60   # 可选，每分钟请求数上限
+//! tokens_per_hour = 1000000   # 可选，每小时 token 用量上限
+//! allowed_models = ["claude-sonnet-4-5"]   # 可选，允许请求的模型，默认不限制
+//! allowed_providers = ["work"]   # 可选，允许使用的 provider 名称，默认不限制
+//! created_at = 1760000000   # 可选，创建时间 (Unix timestamp)
+//! expires_at = 1767225600   # 可选，过期时间 (Unix timestamp)
+//! ```
+//!
+//! 每个密钥的最后使用时间与请求数保存在用量数据库（`PLURIBUS_USAGE_DB`）中，未启用用量历史时不保存。
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+use subtle::ConstantTimeEq;
+
+/// `PLURIBUS_SECRET` 对应的密钥名称
+pub const DEFAULT_KEY_NAME: &str = "default";
+
+/// 密钥角色，权限依次递增：user < readonly < admin
+///
+/// - `user`: 调用 Messages API
+/// - `readonly`: 在 user 基础上可读取管理信息（如 `/admin/info`）
+/// - `admin`: 全部权限，包括 `/admin/*` 下的变更操作
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    User,
+    Readonly,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Readonly => "readonly",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+/// 请求优先级，排队与限流时高优先级先被处理
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "low" => Some(Priority::Low),
+            "normal" => Some(Priority::Normal),
+            "high" => Some(Priority::High),
+            _ => None,
+        }
+    }
+}
+
+/// 单个会话的累计 token 上限
+///
+/// 超过 `soft_tokens` 时响应带 `x-pluribus-budget-warning`，超过 `hard_tokens` 后拒绝该会话的请求
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConversationBudget {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub soft_tokens: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hard_tokens: Option<u64>,
+}
+
+/// 密钥的访问策略，keys 文件、摘要与认证后的身份共用
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyPolicy {
+    /// 过期时间 (Unix timestamp)，到期后拒绝认证
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// 是否对符合条件的小请求启用对冲（同时发往两个 provider，取先成功者）
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hedge: bool,
+    /// 允许请求的最高优先级，未设置时为 normal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_priority: Option<Priority>,
+    /// 是否允许请求自带上游 OAuth token
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub token_passthrough: bool,
+    /// 是否去除响应中可识别上游与 provider 的元数据
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub privacy: bool,
+    /// 单个会话的累计 token 上限
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversation_budget: Option<ConversationBudget>,
+    /// 每分钟请求数上限
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_minute: Option<u32>,
+    /// 每小时 token 用量上限
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens_per_hour: Option<u64>,
+    /// 允许请求的模型，为空时不限制
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_models: Vec<String>,
+    /// 允许使用的 provider 名称，为空时不限制
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_providers: Vec<String>,
+}
+
+impl KeyPolicy {
+    /// `now` 时刻是否已过期
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+
+    /// 允许请求的最高优先级
+    pub fn max_priority(&self) -> Priority {
+        self.max_priority.unwrap_or_default()
+    }
+
+    /// 是否允许请求 `model`
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.allowed_models.is_empty() || self.allowed_models.iter().any(|m| m == model)
+    }
+
+    /// 是否允许使用名为 `provider` 的 provider
+    pub fn allows_provider(&self, provider: &str) -> bool {
+        self.allowed_providers.is_empty() || self.allowed_providers.iter().any(|p| p == provider)
+    }
+
+    fn validate(&self, name: &str) -> Result<()> {
+        if let Some(ConversationBudget {
+            soft_tokens: Some(soft),
+            hard_tokens: Some(hard),
+        }) = self.conversation_budget
+        {
+            anyhow::ensure!(
+                soft <= hard,
+                "key '{}' has a conversation soft limit above its hard limit",
+                name
+            );
+        }
+        anyhow::ensure!(
+            self.requests_per_minute != Some(0) && self.tokens_per_hour != Some(0),
+            "key '{}' has a zero rate limit",
+            name
+        );
+        Ok(())
+    }
+}
+
+/// 单个客户端密钥
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub name: String,
+    pub key: String,
+    #[serde(default)]
+    pub role: Role,
+    /// 创建时间 (Unix timestamp)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    #[serde(flatten)]
+    pub policy: KeyPolicy,
+}
+
+impl ApiKey {
+    /// `now` 时刻是否已过期
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.policy.is_expired(now)
+    }
+}
+
+/// 不含密钥值的摘要，供 `/admin/info` 与 `pluribus diff` 比较
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeySummary {
+    pub name: String,
+    pub role: Role,
+    #[serde(flatten)]
+    pub policy: KeyPolicy,
+}
+
+impl From<&ApiKey> for KeySummary {
+    fn from(key: &ApiKey) -> Self {
+        Self {
+            name: key.name.clone(),
+            role: key.role,
+            policy: key.policy.clone(),
+        }
+    }
+}
+
+/// 认证通过的客户端身份，作为 request extension 传递给后续中间件和处理器
+#[derive(Debug, Clone)]
+pub struct ClientKey {
+    pub name: String,
+    pub role: Role,
+    pub policy: KeyPolicy,
+}
+
+impl ClientKey {
+    /// `now` 时刻是否已过期
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.policy.is_expired(now)
+    }
+}
+
+/// keys.toml 文件结构
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct KeysFile {
+    #[serde(default)]
+    keys: Vec<ApiKey>,
+}
+
+/// 读取 keys 文件中的密钥（不含 `PLURIBUS_SECRET`），文件不存在时返回空列表
+pub fn read_keys_file(path: &Path) -> Result<Vec<ApiKey>> {
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let file: KeysFile =
+        toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(file.keys)
+}
+
+/// 覆盖写入 keys 文件
+pub fn write_keys_file(path: &Path, keys: Vec<ApiKey>) -> Result<()> {
+    let content = toml::to_string_pretty(&KeysFile { keys })?;
+    std::fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// 所有可用的客户端密钥
+#[derive(Debug, Clone, Default)]
+pub struct KeyStore {
+    keys: Vec<ApiKey>,
+}
+
+impl KeyStore {
+    /// 加载密钥：`secret` 作为 admin 密钥，再合并 keys 文件（不存在时忽略）
+    pub fn load(path: impl AsRef<Path>, secret: &str) -> Result<Self> {
+        let path = path.as_ref();
+        let mut keys = vec![ApiKey {
+            name: DEFAULT_KEY_NAME.to_string(),
+            key: secret.to_string(),
+            role: Role::Admin,
+            created_at: None,
+            policy: KeyPolicy {
+                max_priority: Some(Priority::High),
+                ..KeyPolicy::default()
+            },
+        }];
+
+        if path.exists() {
+            keys.extend(read_keys_file(path)?);
+            tracing::info!(
+                "Loaded {} client key(s) from {}",
+                keys.len() - 1,
+                path.display()
+            );
+        }
+
+        Self::validate(&keys).with_context(|| format!("Invalid keys in {}", path.display()))?;
+        Ok(Self { keys })
+    }
+
+    fn validate(keys: &[ApiKey]) -> Result<()> {
+        let mut names = HashSet::new();
+        let mut secrets = HashSet::new();
+        for key in keys {
+            anyhow::ensure!(!key.name.is_empty(), "key name cannot be empty");
+            anyhow::ensure!(!key.key.is_empty(), "key '{}' has an empty value", key.name);
+            anyhow::ensure!(names.insert(&key.name), "duplicate key name '{}'", key.name);
+            anyhow::ensure!(
+                secrets.insert(&key.key),
+                "key '{}' reuses another key's value",
+                key.name
+            );
+            key.policy.validate(&key.name)?;
+        }
+        Ok(())
+    }
+
+    /// keys 文件中密钥的摘要（不含 `PLURIBUS_SECRET`）
+    pub fn summaries(&self) -> Vec<KeySummary> {
+        self.keys
+            .iter()
+            .filter(|k| k.name != DEFAULT_KEY_NAME)
+            .map(KeySummary::from)
+            .collect()
+    }
+
+    /// 用凭证查找密钥（常量时间比较）
+    pub fn authenticate(&self, token: &str) -> Option<ClientKey> {
+        self.keys
+            .iter()
+            .find(|k| bool::from(k.key.as_bytes().ct_eq(token.as_bytes())))
+            .map(|k| ClientKey {
+                name: k.name.clone(),
+                role: k.role,
+                policy: k.policy.clone(),
+            })
+    }
+}
+
+/// 单个密钥的使用记录
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyUsage {
+    /// 最后使用时间 (Unix timestamp)
+    pub last_used: Option<u64>,
+    /// 累计请求数
+    pub requests: u64,
+}
+
+/// 按密钥名称累计上次保存以来的使用情况，由 gateway 定期及退出时写入用量数据库
+#[derive(Default)]
+pub struct KeyUsageTracker {
+    pending: Mutex<HashMap<String, KeyUsage>>,
+}
+
+impl KeyUsageTracker {
+    /// 记录一次使用
+    pub fn record(&self, name: &str, now: u64) {
+        if let Ok(mut pending) = self.pending.lock() {
+            let entry = pending.entry(name.to_string()).or_default();
+            entry.last_used = Some(now);
+            entry.requests += 1;
+        }
+    }
+
+    /// 取出尚未保存的记录
+    #[cfg_attr(not(feature = "usage-sqlite"), allow(dead_code))]
+    pub fn take(&self) -> HashMap<String, KeyUsage> {
+        self.pending
+            .lock()
+            .map(|mut pending| std::mem::take(&mut *pending))
+            .unwrap_or_default()
+    }
+
+    /// 保存失败时放回取出的记录，与之后的使用合并
+    #[cfg_attr(not(feature = "usage-sqlite"), allow(dead_code))]
+    pub fn restore(&self, entries: HashMap<String, KeyUsage>) {
+        let Ok(mut pending) = self.pending.lock() else {
+            return;
+        };
+        for (name, usage) in entries {
+            let entry = pending.entry(name).or_default();
+            entry.requests += usage.requests;
+            entry.last_used = entry.last_used.max(usage.last_used);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use std::io::Write;
+
+    const EXPIRES_AT: u64 = 1_767_225_600;
+
+    fn expiring() -> KeyPolicy {
+        KeyPolicy {
+            expires_at: Some(EXPIRES_AT),
+            ..KeyPolicy::default()
+        }
+    }
+
+    #[test]
+    fn expiry_boundaries() {
+        let policy = expiring();
+        assert!(!policy.is_expired(EXPIRES_AT - 1));
+        // 到期的那一秒起拒绝
+        assert!(policy.is_expired(EXPIRES_AT));
+        assert!(policy.is_expired(EXPIRES_AT + 1));
+        assert!(!KeyPolicy::default().is_expired(u64::MAX));
+    }
+
+    #[test]
+    fn policy_is_flattened_into_the_keys_file() {
+        let keys: KeysFile = toml::from_str(
+            r#"
+            [[keys]]
+            name = "alice"
+            key = "sk-alice"
+            expires_at = 1767225600
+            max_priority = "high"
+            privacy = true
+            requests_per_minute = 60
+            allowed_models = ["claude-sonnet-4-5"]
+            "#,
+        )
+        .unwrap();
+        let key = &keys.keys[0];
+        assert_eq!(key.role, Role::User);
+        assert_eq!(key.policy.expires_at, Some(EXPIRES_AT));
+        assert_eq!(key.policy.max_priority(), Priority::High);
+        assert!(key.policy.privacy && !key.policy.hedge);
+        assert_eq!(key.policy.requests_per_minute, Some(60));
+        assert!(key.policy.allows_model("claude-sonnet-4-5"));
+        assert!(!key.policy.allows_model("claude-opus-4-1"));
+        assert!(key.policy.allows_provider("anything"));
+
+        // 摘要与认证后的身份带有同样的策略
+        let summary = serde_json::to_value(KeySummary::from(key)).unwrap();
+        assert_eq!(summary["expires_at"], EXPIRES_AT);
+        assert_eq!(summary["max_priority"], "high");
+        let roundtrip: KeySummary = serde_json::from_value(summary).unwrap();
+        assert_eq!(roundtrip.policy, key.policy);
+
+        let store = KeyStore { keys: keys.keys };
+        let client = store.authenticate("sk-alice").unwrap();
+        assert_eq!(client.policy, store.keys[0].policy);
+        assert!(client.is_expired(EXPIRES_AT));
+    }
+
+    #[test]
+    fn default_key_allows_high_priority() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = KeyStore::load(dir.path().join("keys.toml"), "secret").unwrap();
+        let client = store.authenticate("secret").unwrap();
+        assert_eq!(client.role, Role::Admin);
+        assert_eq!(client.policy.max_priority(), Priority::High);
+    }
+
+    #[test]
+    fn tracker_hands_out_pending_usage_once() {
+        let tracker = KeyUsageTracker::default();
+        tracker.record("alice", 100);
+        tracker.record("alice", 120);
+        tracker.record("bob", 110);
+
+        let pending = tracker.take();
+        assert_eq!(
+            pending["alice"],
+            KeyUsage {
+                last_used: Some(120),
+                requests: 2
+            }
+        );
+        assert!(tracker.take().is_empty());
+
+        // 保存失败放回后与新的使用合并
+        tracker.record("alice", 90);
+        tracker.restore(pending);
+        let merged = tracker.take();
+        assert_eq!(merged["alice"].requests, 3);
+        assert_eq!(merged["alice"].last_used, Some(120));
+        assert_eq!(merged["bob"].requests, 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_key_expiring_now() {
+        let (_dir, config) = test_support::config("");
+        let now = crate::utils::unix_timestamp_secs();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&config.keys_file)
+            .unwrap();
+        write!(
+            file,
+            "\n[[keys]]\nname = \"expiring\"\nkey = \"sk-expiring\"\nexpires_at = {now}\n\n\
+             [[keys]]\nname = \"valid\"\nkey = \"sk-valid\"\nexpires_at = {}\n",
+            now + 3600
+        )
+        .unwrap();
+        let router = crate::gateway::test_router(test_support::state(config, &[]));
+
+        let request = |key: &str| {
+            Request::get("/v1/capabilities")
+                .header("x-api-key", key)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let (status, _, body) = test_support::send(&router, request("sk-expiring")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "key_expired");
+
+        let (status, _, _) = test_support::send(&router, request("sk-valid")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
@@ -14,18 +14,44 @@
 //!
 //! - `serve`: 启动 API 服务器
 //! - `login`: 通过 OAuth 登录添加 Provider
+//! - `keys`: 查看和清理客户端密钥
+//! - `deadletter`: 查看和重新提交失败的请求
+//! - `diff`: 比较磁盘配置与运行中的服务器
+//! - `logs`: 查看运行中服务器的请求记录
+//! - `repro`: 把上游拒绝的请求缩减为最小复现
+//! - `export-bundle` / `import-bundle`: 导出 / 导入 gateway 状态，用于迁移到新主机
+//! - `version`: 输出版本与构建信息
 //! - `test`: 向本地服务器发送测试请求
+//! - `usage`: 按 provider、密钥与模型汇总用量历史；`usage migrate` 把数据库升级到当前 schema
 
+// `Config::settings` 中的 `json!` 字段较多
+#![recursion_limit = "256"]
+
+mod access_log;
 mod commands;
 mod config;
+mod dead_letter;
+mod egress;
 mod gateway;
+mod keys;
+mod metrics;
 mod providers;
+mod quiet_hours;
+mod repro;
+mod stats;
+mod telemetry;
+#[cfg(test)]
+mod test_support;
+#[cfg(feature = "usage-sqlite")]
+mod usage;
 mod utils;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use config::Config;
 use providers::ProviderType;
+use std::path::PathBuf;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 /// Pluribus CLI
@@ -34,6 +60,9 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 #[command(about = "Claude Code API Relay Service", long_about = None)]
 #[command(version)]
 struct Cli {
+    /// 输出格式：json 时在 stdout 输出单个 JSON 文档，提示输出到 stderr（默认取 PLURIBUS_OUTPUT）
+    #[arg(long, global = true, value_enum)]
+    output: Option<commands::OutputFormat>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -42,7 +71,14 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// 启动 API 中继服务器
-    Serve,
+    Serve {
+        /// 其他实例已在运行时以共享模式启动：不刷新 token，只读取其保存的配置
+        #[arg(long)]
+        allow_shared: bool,
+        /// Provider 配置目录（优先于 PLURIBUS_PROVIDERS_DIR，默认 ./providers）
+        #[arg(long)]
+        providers_dir: Option<PathBuf>,
+    },
     /// 通过 OAuth 登录到 Provider
     Login {
         /// Provider 类型
@@ -51,13 +87,171 @@ enum Commands {
         /// 为此 Provider 实例指定自定义名称
         #[arg(short, long)]
         name: Option<String>,
+        /// 完成上次中断的登录（10 分钟内有效），只需输入授权码
+        #[arg(long)]
+        resume: bool,
+        /// Provider 配置目录（优先于 PLURIBUS_PROVIDERS_DIR，默认 ./providers）
+        #[arg(long)]
+        providers_dir: Option<PathBuf>,
+    },
+    /// 列出已配置的 provider 及其 token 状态
+    List {
+        /// Provider 配置目录（优先于 PLURIBUS_PROVIDERS_DIR，默认 ./providers）
+        #[arg(long)]
+        providers_dir: Option<PathBuf>,
+    },
+    /// 管理客户端密钥
+    Keys {
+        #[command(subcommand)]
+        action: KeysAction,
+    },
+    /// 查看和重新提交在所有 provider 上都失败的请求
+    Deadletter {
+        #[command(subcommand)]
+        action: DeadletterAction,
+    },
+    /// 比较磁盘上的配置与运行中的服务器
+    ///
+    /// 退出码：0 无变化，2 仅有可通过 SIGHUP 加载的变化，3 需要重启
+    Diff {
+        /// 运行中服务器的地址（默认使用配置的监听地址）
+        #[arg(long)]
+        url: Option<String>,
+    },
+    /// 查看运行中服务器最近完成的请求
+    Logs {
+        /// 运行中服务器的地址（默认使用配置的监听地址）
+        #[arg(long)]
+        url: Option<String>,
+        /// 先打印的最近记录数
+        #[arg(short = 'n', long, default_value_t = 20)]
+        lines: usize,
+        /// 持续打印新完成的请求
+        #[arg(short, long)]
+        follow: bool,
+        /// 只显示失败（4xx / 5xx）的请求
+        #[arg(long)]
+        errors_only: bool,
+        /// 只显示指定 provider 处理的请求
+        #[arg(long)]
+        provider: Option<String>,
+        /// 只显示指定模型的请求
+        #[arg(long)]
+        model: Option<String>,
+    },
+    /// 按 provider、密钥与模型汇总用量历史
+    #[cfg(feature = "usage-sqlite")]
+    #[command(args_conflicts_with_subcommands = true)]
+    Usage {
+        #[command(subcommand)]
+        action: Option<UsageAction>,
+        /// 起始日期（含），YYYY-MM-DD（UTC）或 Unix timestamp
+        #[arg(long)]
+        from: Option<String>,
+        /// 结束日期（不含），YYYY-MM-DD（UTC）或 Unix timestamp
+        #[arg(long)]
+        to: Option<String>,
+        /// 只统计指定 provider 处理的请求
+        #[arg(long)]
+        provider: Option<String>,
+        /// 只统计指定模型的请求
+        #[arg(long)]
+        model: Option<String>,
+    },
+    /// 把上游以 400 拒绝并保存在 PLURIBUS_REPRO_DIR 中的请求缩减为最小复现
+    Repro {
+        /// 失败请求的 request ID
+        request_id: u64,
+        /// 发往的 Anthropic 兼容 messages 地址（如本地 mock）
+        #[arg(long)]
+        target: Option<String>,
+        /// 经本地服务器发往真实上游，每一步都消耗一次请求
+        #[arg(long)]
+        live: bool,
+        /// --live 时不再确认
+        #[arg(long)]
+        yes: bool,
+        /// --live 时使用的 provider（默认使用失败时的 provider）
+        #[arg(long)]
+        provider: Option<String>,
+        /// 最多发送的缩减请求数
+        #[arg(long, default_value_t = 100)]
+        max_attempts: usize,
+        /// 最小请求的输出文件（默认 repro-<request-id>.min.json）
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// 打包 provider 配置、客户端密钥与配置文件，用于迁移到新主机
+    ExportBundle {
+        /// 输出文件
+        #[arg(short, long, default_value = "pluribus-bundle.tar.zst")]
+        output: PathBuf,
+        /// 同时导出用量数据库（含密钥使用记录）
+        #[arg(long)]
+        include_usage: bool,
+        /// 用口令加密含凭证的文件（口令取自 PLURIBUS_BUNDLE_PASSPHRASE 或标准输入）
+        #[arg(long)]
+        encrypt: bool,
+    },
+    /// 将 export-bundle 生成的文件导入到当前配置的路径
+    ImportBundle {
+        /// bundle 文件
+        bundle: PathBuf,
+        /// 覆盖已存在的文件
+        #[arg(long)]
+        force: bool,
     },
     /// 向本地服务器发送测试请求
     Test,
+    /// 输出版本与构建信息
+    Version {
+        /// 输出格式
+        #[arg(long, value_enum, default_value_t = commands::VersionFormat::Text)]
+        format: commands::VersionFormat,
+    },
+}
+
+/// usage 子命令
+#[cfg(feature = "usage-sqlite")]
+#[derive(Subcommand)]
+enum UsageAction {
+    /// 把用量数据库升级到当前版本的 schema
+    Migrate,
+}
+
+/// keys 子命令
+#[derive(Subcommand)]
+enum KeysAction {
+    /// 列出密钥及其使用情况
+    List,
+    /// 删除长期未使用的密钥
+    Prune {
+        /// 超过多少天未使用视为可删除
+        #[arg(long, default_value_t = 30)]
+        unused_days: u64,
+        /// 只列出将被删除的密钥，不修改文件
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// deadletter 子命令
+#[derive(Subcommand)]
+enum DeadletterAction {
+    /// 列出未过期的死信
+    List,
+    /// 通过本地服务器重新提交保存了请求体的死信
+    Retry {
+        /// 死信 ID
+        id: String,
+        /// 只发往指定的 provider
+        #[arg(long)]
+        provider: Option<String>,
+    },
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> Result<std::process::ExitCode> {
     // 加载 .env 文件（如果存在）
     if let Ok(dotenv_path) = std::env::var("PLURIBUS_ENV_FILE") {
         dotenvy::from_path(&dotenv_path).ok();
@@ -65,7 +259,33 @@ async fn main() -> Result<()> {
         dotenvy::dotenv().ok();
     }
 
-    // 初始化日志系统
+    // 解析命令行参数
+    let cli = Cli::parse();
+    let format = match cli.output {
+        Some(format) => format,
+        None => match std::env::var("PLURIBUS_OUTPUT") {
+            Ok(value) => clap::ValueEnum::from_str(&value, true)
+                .map_err(|_| anyhow::anyhow!("Invalid PLURIBUS_OUTPUT: {value}"))?,
+            Err(_) => commands::OutputFormat::Text,
+        },
+    };
+    let output = commands::output::Output::new(format);
+
+    // 初始化日志系统，json 输出时日志写到 stderr
+    let log_writer = if output.is_json() {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
+    // 设置 PLURIBUS_OTEL_ENDPOINT 时同时导出到 OpenTelemetry
+    let otel_endpoint = std::env::var(telemetry::ENDPOINT_ENV)
+        .ok()
+        .filter(|endpoint| !endpoint.is_empty());
+    let (otel_layer, otel_error) = match otel_endpoint.as_deref().map(telemetry::layer) {
+        Some(Ok(layer)) => (Some(layer), None),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
@@ -75,18 +295,173 @@ async fn main() -> Result<()> {
             tracing_subscriber::fmt::layer()
                 .with_target(false)
                 .with_thread_ids(false)
-                .with_thread_names(false),
+                .with_thread_names(false)
+                .with_writer(log_writer),
         )
+        .with(otel_layer)
         .init();
+    if let Some(e) = otel_error {
+        tracing::warn!("OpenTelemetry export disabled: {:#}", e);
+    }
 
-    // 解析命令行参数和配置
-    let cli = Cli::parse();
-    let config = Config::from_env()?;
+    // version 命令不依赖配置
+    if let Commands::Version { format } = cli.command {
+        return output.finish(commands::version_command(&output, format));
+    }
+
+    let result = match Config::load() {
+        Ok(config) => run(cli.command, config, &output).await,
+        Err(e) => Err(e),
+    };
+    telemetry::shutdown();
+    output.finish(result)
+}
+
+/// 执行相应的命令
+async fn run(command: Commands, config: Config, output: &commands::output::Output) -> Result<()> {
+    match command {
+        Commands::Serve {
+            allow_shared,
+            providers_dir,
+        } => commands::serve_command(config.with_providers_dir(providers_dir), allow_shared).await,
+        Commands::Login {
+            provider,
+            name,
+            resume,
+            providers_dir,
+        } => {
+            let config = config.with_providers_dir(providers_dir);
+            commands::login_command(config, output, provider, name, resume).await
+        }
+        Commands::List { providers_dir } => {
+            commands::list_command(config.with_providers_dir(providers_dir), output).await
+        }
+        Commands::Keys { action } => match action {
+            KeysAction::List => commands::keys_list_command(config, output).await,
+            KeysAction::Prune {
+                unused_days,
+                dry_run,
+            } => commands::keys_prune_command(config, output, unused_days, dry_run).await,
+        },
+        Commands::Deadletter { action } => match action {
+            DeadletterAction::List => commands::deadletter_list_command(config, output),
+            DeadletterAction::Retry { id, provider } => {
+                commands::deadletter_retry_command(config, output, id, provider).await
+            }
+        },
+        Commands::Diff { url } => commands::diff_command(config, output, url).await,
+        Commands::Logs {
+            url,
+            lines,
+            follow,
+            errors_only,
+            provider,
+            model,
+        } => {
+            let options = commands::LogsOptions {
+                url,
+                lines,
+                follow,
+                errors_only,
+                provider,
+                model,
+            };
+            commands::logs_command(config, output, options).await
+        }
+        #[cfg(feature = "usage-sqlite")]
+        Commands::Usage {
+            action: Some(UsageAction::Migrate),
+            ..
+        } => commands::usage_migrate_command(config, output).await,
+        #[cfg(feature = "usage-sqlite")]
+        Commands::Usage {
+            action: None,
+            from,
+            to,
+            provider,
+            model,
+        } => {
+            let options = commands::UsageOptions {
+                from,
+                to,
+                provider,
+                model,
+            };
+            commands::usage_command(config, output, options).await
+        }
+        Commands::Repro {
+            request_id,
+            target,
+            live,
+            yes,
+            provider,
+            max_attempts,
+            out,
+        } => {
+            let options = commands::ReproOptions {
+                request_id,
+                target,
+                live,
+                yes,
+                provider,
+                max_attempts,
+                out,
+            };
+            commands::repro_command(config, output, options).await
+        }
+        Commands::ExportBundle {
+            output: path,
+            include_usage,
+            encrypt,
+        } => {
+            let options = commands::ExportOptions {
+                output: path,
+                include_usage,
+                encrypt,
+            };
+            commands::export_bundle_command(config, output, options).await
+        }
+        Commands::ImportBundle { bundle, force } => {
+            commands::import_bundle_command(config, output, bundle, force).await
+        }
+        Commands::Test => commands::test_command(config, output).await,
+        Commands::Version { .. } => unreachable!("handled before loading config"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn providers_dir(args: &[&str]) -> Option<PathBuf> {
+        match Cli::try_parse_from(args).unwrap().command {
+            Commands::Serve { providers_dir, .. }
+            | Commands::Login { providers_dir, .. }
+            | Commands::List { providers_dir } => providers_dir,
+            _ => panic!("{args:?} has no --providers-dir"),
+        }
+    }
 
-    // 执行相应的命令
-    match cli.command {
-        Commands::Serve => commands::serve_command(config).await,
-        Commands::Login { provider, name } => commands::login_command(config, provider, name).await,
-        Commands::Test => commands::test_command(config).await,
+    #[test]
+    fn providers_dir_flag_is_accepted_by_commands_that_load_providers() {
+        let cases: [&[&str]; 3] = [
+            &["pluribus", "serve", "--providers-dir", "/cli/providers"],
+            &[
+                "pluribus",
+                "login",
+                "claude-code",
+                "--providers-dir",
+                "/cli/providers",
+            ],
+            &["pluribus", "list", "--providers-dir", "/cli/providers"],
+        ];
+        for args in cases {
+            assert_eq!(
+                providers_dir(args),
+                Some(PathBuf::from("/cli/providers")),
+                "{args:?}"
+            );
+        }
+        assert_eq!(providers_dir(&["pluribus", "list"]), None);
     }
 }
@@ -0,0 +1,461 @@
+//! Prometheus 指标
+//!
+//! 所有指标注册到同一个全局 `Registry`。关闭 `metrics` feature 时不依赖 prometheus：
+//! 计数器不做任何事，gauge 仍然记录数值以供 `/admin/info` 读取。
+
+#[cfg(not(feature = "metrics"))]
+use fallback::{
+    HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+};
+#[cfg(feature = "metrics")]
+use prometheus::{
+    GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry,
+};
+use std::sync::LazyLock;
+
+#[cfg(feature = "metrics")]
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+/// provider 的瞬时状态，在 `/metrics` 被抓取时写入 gauge
+#[cfg(feature = "metrics")]
+pub struct ProviderGauges<'a> {
+    pub provider: &'a str,
+    /// 5 小时与 7 天窗口的使用率，尚无 rate limit 信息时为 `None`
+    pub utilization: Option<(f64, f64)>,
+    pub in_flight: i64,
+}
+
+/// 以 Prometheus 文本格式导出全部指标，关闭 `metrics` feature 时不注册 `/metrics`
+#[cfg(feature = "metrics")]
+pub fn render(providers: &[ProviderGauges]) -> Option<String> {
+    // 先清空，已移除的 provider 不再出现
+    RATE_LIMIT_UTILIZATION.reset();
+    IN_FLIGHT_REQUESTS.reset();
+    for p in providers {
+        if let Some((five_hour, seven_day)) = p.utilization {
+            RATE_LIMIT_UTILIZATION
+                .with_label_values(&[p.provider, "5h"])
+                .set(five_hour);
+            RATE_LIMIT_UTILIZATION
+                .with_label_values(&[p.provider, "7d"])
+                .set(seven_day);
+        }
+        IN_FLIGHT_REQUESTS
+            .with_label_values(&[p.provider])
+            .set(p.in_flight);
+    }
+    prometheus::TextEncoder::new()
+        .encode_to_string(&REGISTRY.gather())
+        .ok()
+}
+
+/// 注册指标到全局 registry
+#[cfg(feature = "metrics")]
+fn register<T: prometheus::core::Collector + Clone + 'static>(collector: T) -> T {
+    REGISTRY
+        .register(Box::new(collector.clone()))
+        .expect("metric registered twice");
+    collector
+}
+
+#[cfg(not(feature = "metrics"))]
+fn register<T>(collector: T) -> T {
+    collector
+}
+
+/// 与 prometheus 同名、接口子集相同的替代实现
+#[cfg(not(feature = "metrics"))]
+mod fallback {
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicI64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    pub struct Opts;
+
+    impl Opts {
+        pub fn new(_name: &str, _help: &str) -> Self {
+            Self
+        }
+    }
+
+    /// 不记录数值的计数器
+    #[derive(Clone)]
+    pub struct IntCounter;
+
+    impl IntCounter {
+        pub fn new(_name: &str, _help: &str) -> Result<Self, Infallible> {
+            Ok(Self)
+        }
+
+        pub fn inc(&self) {}
+
+        pub fn inc_by(&self, _v: u64) {}
+    }
+
+    pub struct HistogramOpts;
+
+    impl HistogramOpts {
+        pub fn new(_name: &str, _help: &str) -> Self {
+            Self
+        }
+
+        pub fn buckets(self, _buckets: Vec<f64>) -> Self {
+            self
+        }
+    }
+
+    /// 不记录数值的直方图
+    pub struct Histogram;
+
+    impl Histogram {
+        pub fn observe(&self, _v: f64) {}
+    }
+
+    pub struct HistogramVec;
+
+    impl HistogramVec {
+        pub fn new(_opts: HistogramOpts, _labels: &[&str]) -> Result<Self, Infallible> {
+            Ok(Self)
+        }
+
+        pub fn with_label_values(&self, _values: &[&str]) -> Histogram {
+            Histogram
+        }
+    }
+
+    pub struct IntCounterVec;
+
+    impl IntCounterVec {
+        pub fn new(_opts: Opts, _labels: &[&str]) -> Result<Self, Infallible> {
+            Ok(Self)
+        }
+
+        pub fn with_label_values(&self, _values: &[&str]) -> IntCounter {
+            IntCounter
+        }
+    }
+
+    #[derive(Clone, Default)]
+    pub struct IntGauge(Arc<AtomicI64>);
+
+    impl IntGauge {
+        pub fn new(_name: &str, _help: &str) -> Result<Self, Infallible> {
+            Ok(Self::default())
+        }
+
+        pub fn inc(&self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+
+        pub fn dec(&self) {
+            self.0.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        pub fn set(&self, v: i64) {
+            self.0.store(v, Ordering::Relaxed);
+        }
+
+        pub fn get(&self) -> i64 {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    pub struct IntGaugeVec(Mutex<HashMap<Vec<String>, IntGauge>>);
+
+    impl IntGaugeVec {
+        pub fn new(_opts: Opts, _labels: &[&str]) -> Result<Self, Infallible> {
+            Ok(Self(Mutex::new(HashMap::new())))
+        }
+
+        pub fn with_label_values(&self, values: &[&str]) -> IntGauge {
+            let key = values.iter().map(|v| v.to_string()).collect();
+            match self.0.lock() {
+                Ok(mut gauges) => gauges.entry(key).or_default().clone(),
+                Err(_) => IntGauge::default(),
+            }
+        }
+    }
+}
+
+/// 未知 SSE 事件类型计数
+pub static UNKNOWN_SSE_EVENTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "unknown_sse_event_total",
+                "SSE events with an unrecognized type",
+            ),
+            &["type"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 上游响应内容异常计数
+pub static RESPONSE_ANOMALIES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "response_anomaly_total",
+                "Upstream responses that failed content sanity checks",
+            ),
+            &["provider", "kind"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// Messages API 请求计数，合成流量以 `synthetic` 标签区分
+pub static MESSAGES_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "messages_requests_total",
+                "Messages API requests by whether they were synthetic test traffic",
+            ),
+            &["synthetic"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 按 provider、模型与响应状态码的请求计数，没有选中 provider 时 provider 为 `none`
+pub static REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "pluribus_requests_total",
+                "Messages API requests by provider, model and response status",
+            ),
+            &["provider", "model", "status"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 按 provider、模型与类型（input、output、cache_read、cache_write）的 token 计数
+pub static TOKENS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "pluribus_tokens_total",
+                "Tokens reported by upstream usage by provider, model and type",
+            ),
+            &["provider", "model", "type"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 慢请求计数
+pub static SLOW_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "slow_requests_total",
+                "Requests whose latency exceeded the slow request threshold",
+            ),
+            &["provider", "model"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 对冲请求计数
+pub static HEDGED_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "hedged_requests_total",
+                "Hedged requests by which leg returned the response",
+            ),
+            &["winner"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 延迟探测次数
+pub static LATENCY_PROBES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("latency_probes_total", "Provider latency probes by result"),
+            &["provider", "result"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 延迟探测消耗的 token，与客户端请求分开统计
+pub static LATENCY_PROBE_TOKENS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "latency_probe_tokens_total",
+                "Tokens consumed by provider latency probes",
+            ),
+            &["provider"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 可缓存前缀变化计数
+pub static CACHE_PREFIX_CHANGES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "cache_prefix_changed_total",
+                "Requests whose cacheable prompt prefix differs from the previous request of the same conversation",
+            ),
+            &["section"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 有界映射因容量或过期淘汰的条目数
+pub static BOUNDED_MAP_EVICTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "bounded_map_evictions_total",
+                "Entries removed from in-memory state maps because they were full or expired",
+            ),
+            &["map", "reason"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 因排队已满被拒绝或丢弃的请求数
+pub static SHED_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "requests_shed_total",
+                "Requests rejected or dropped from the admission queue because it was full",
+            ),
+            &["priority"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 按密钥的准入等待时长（秒），无需排队的请求记为 0，用于计算各密钥等待时长的分位数
+pub static ADMISSION_WAIT: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "admission_wait_seconds",
+                "Time requests waited for a concurrency permit by client key",
+            )
+            .buckets(vec![
+                0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0,
+            ]),
+            &["key"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 实时请求流中因订阅者过慢而丢弃的记录数
+pub static REQUEST_FEED_DROPPED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(
+        IntCounter::new(
+            "request_feed_dropped_total",
+            "Request records dropped because a /admin/requests/stream subscriber fell behind",
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 各后台任务的存活数量
+pub static LIVE_TASKS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new(
+                "gateway_live_tasks",
+                "Live tokio tasks spawned by the gateway",
+            ),
+            &["task"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 进程常驻内存
+pub static RESIDENT_MEMORY: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(
+        IntGauge::new(
+            "gateway_resident_memory_bytes",
+            "Resident memory of the gateway process",
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 正在转发的流式响应数
+pub static ACTIVE_STREAMS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(
+        IntGauge::new(
+            "gateway_active_streams",
+            "Streaming responses being relayed",
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 流转发通道积压的历史最大值
+pub static STREAM_CHANNEL_HIGH_WATER: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(
+        IntGauge::new(
+            "gateway_stream_channel_high_water",
+            "Highest number of chunks queued in a single stream relay channel",
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 单个请求同时持有的请求体字节数的历史最大值
+pub static REQUEST_BODY_HIGH_WATER: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(
+        IntGauge::new(
+            "gateway_request_body_high_water_bytes",
+            "Most request body bytes held at once by a single request across its attempts",
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// provider 各 rate limit 窗口的使用率（0.0 - 1.0），抓取时更新
+#[cfg(feature = "metrics")]
+static RATE_LIMIT_UTILIZATION: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register(
+        GaugeVec::new(
+            Opts::new(
+                "pluribus_provider_rate_limit_utilization",
+                "Last reported rate limit utilization of each provider window",
+            ),
+            &["provider", "window"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// 各 provider 转发中的请求数，抓取时更新
+#[cfg(feature = "metrics")]
+static IN_FLIGHT_REQUESTS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new(
+                "pluribus_in_flight_requests",
+                "Requests currently being forwarded to each provider",
+            ),
+            &["provider"],
+        )
+        .expect("valid metric"),
+    )
+});
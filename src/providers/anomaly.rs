@@ -0,0 +1,262 @@
+//! 响应内容检查
+//!
+//! 检测上游返回的异常响应，记录结构化警告并计数 `response_anomaly_total{provider, kind}`：
+//! - 正常结束（`end_turn` / `stop_sequence` / `tool_use`）但 content 为空
+//! - content 非空但 `usage.output_tokens` 为 0
+//! - 未知类型的 content block
+//!
+//! 非流式响应在返回前检查，流式响应在流结束后检查
+
+use serde_json::Value;
+
+use crate::metrics::RESPONSE_ANOMALIES;
+
+/// 视为正常结束的 stop_reason
+const NON_ERROR_STOP_REASONS: &[&str] = &["end_turn", "stop_sequence", "tool_use"];
+
+/// 已知的 content block 类型
+const KNOWN_CONTENT_BLOCK_TYPES: &[&str] = &[
+    "text",
+    "thinking",
+    "redacted_thinking",
+    "tool_use",
+    "server_tool_use",
+    "web_search_tool_result",
+    "web_fetch_tool_result",
+    "code_execution_tool_result",
+    "bash_code_execution_tool_result",
+    "text_editor_code_execution_tool_result",
+    "mcp_tool_use",
+    "mcp_tool_result",
+    "container_upload",
+];
+
+/// 响应检查模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValidationMode {
+    /// 不检查
+    #[default]
+    Off,
+    /// 检查并记录警告
+    Warn,
+    /// 检查并记录警告，非流式响应 content 为空时换一个 provider 重试一次
+    Strict,
+}
+
+impl ValidationMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "off" | "" => Some(ValidationMode::Off),
+            "warn" => Some(ValidationMode::Warn),
+            "strict" => Some(ValidationMode::Strict),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValidationMode::Off => "off",
+            ValidationMode::Warn => "warn",
+            ValidationMode::Strict => "strict",
+        }
+    }
+}
+
+/// 异常类型
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Anomaly {
+    /// 正常结束但 content 为空
+    EmptyContent,
+    /// content 非空但 output_tokens 为 0
+    ZeroOutputTokens,
+    /// 未知类型的 content block
+    UnknownContentBlock(String),
+}
+
+impl Anomaly {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Anomaly::EmptyContent => "empty_content",
+            Anomaly::ZeroOutputTokens => "zero_output_tokens",
+            Anomaly::UnknownContentBlock(_) => "unknown_content_block",
+        }
+    }
+}
+
+/// 用于检查的响应概要，可从完整响应读取，也可在流式响应中逐事件累积
+#[derive(Debug, Clone, Default)]
+pub struct ResponseShape {
+    /// 上游响应中实际提供服务的模型
+    pub model: Option<String>,
+    pub stop_reason: Option<String>,
+    pub block_types: Vec<String>,
+    pub output_tokens: u64,
+}
+
+impl ResponseShape {
+    /// 从非流式响应读取
+    pub fn from_message(message: &Value) -> Self {
+        let block_types = message
+            .get("content")
+            .and_then(|c| c.as_array())
+            .map(|blocks| {
+                blocks
+                    .iter()
+                    .map(|b| {
+                        b.get("type")
+                            .and_then(|t| t.as_str())
+                            .unwrap_or_default()
+                            .to_string()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            model: message
+                .get("model")
+                .and_then(|m| m.as_str())
+                .map(str::to_string),
+            stop_reason: message
+                .get("stop_reason")
+                .and_then(|s| s.as_str())
+                .map(str::to_string),
+            block_types,
+            output_tokens: message
+                .pointer("/usage/output_tokens")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(0),
+        }
+    }
+
+    /// 检查概要中的异常
+    pub fn anomalies(&self) -> Vec<Anomaly> {
+        let mut anomalies = Vec::new();
+
+        let normal_stop = self
+            .stop_reason
+            .as_deref()
+            .is_some_and(|r| NON_ERROR_STOP_REASONS.contains(&r));
+        if self.block_types.is_empty() && normal_stop {
+            anomalies.push(Anomaly::EmptyContent);
+        }
+        if !self.block_types.is_empty() && self.output_tokens == 0 {
+            anomalies.push(Anomaly::ZeroOutputTokens);
+        }
+        for block_type in &self.block_types {
+            if !KNOWN_CONTENT_BLOCK_TYPES.contains(&block_type.as_str()) {
+                anomalies.push(Anomaly::UnknownContentBlock(block_type.clone()));
+            }
+        }
+
+        anomalies
+    }
+}
+
+/// 按 `mode` 检查响应，记录警告并计数，返回检测到的异常
+pub fn inspect(
+    mode: ValidationMode,
+    provider: &str,
+    model: &str,
+    shape: &ResponseShape,
+) -> Vec<Anomaly> {
+    if mode == ValidationMode::Off {
+        return Vec::new();
+    }
+
+    let anomalies = shape.anomalies();
+    for anomaly in &anomalies {
+        let block_type = match anomaly {
+            Anomaly::UnknownContentBlock(t) => Some(t.as_str()),
+            _ => None,
+        };
+        tracing::warn!(
+            provider,
+            model,
+            kind = anomaly.kind(),
+            stop_reason = shape.stop_reason.as_deref(),
+            content_blocks = shape.block_types.len(),
+            output_tokens = shape.output_tokens,
+            block_type,
+            "response anomaly"
+        );
+        RESPONSE_ANOMALIES
+            .with_label_values(&[provider, anomaly.kind()])
+            .inc();
+    }
+
+    anomalies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn shape(message: Value) -> ResponseShape {
+        ResponseShape::from_message(&message)
+    }
+
+    #[test]
+    fn empty_content_with_normal_stop_is_an_anomaly() {
+        let shape = shape(json!({
+            "content": [],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 10, "output_tokens": 0}
+        }));
+        assert_eq!(shape.anomalies(), vec![Anomaly::EmptyContent]);
+    }
+
+    #[test]
+    fn empty_content_with_max_tokens_is_not_an_anomaly() {
+        let shape = shape(json!({
+            "content": [],
+            "stop_reason": "max_tokens",
+            "usage": {"input_tokens": 10, "output_tokens": 0}
+        }));
+        assert!(shape.anomalies().is_empty());
+    }
+
+    #[test]
+    fn content_without_output_tokens_is_an_anomaly() {
+        let shape = shape(json!({
+            "content": [{"type": "text", "text": "hi"}],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 10, "output_tokens": 0}
+        }));
+        assert_eq!(shape.anomalies(), vec![Anomaly::ZeroOutputTokens]);
+    }
+
+    #[test]
+    fn unknown_content_block_is_an_anomaly() {
+        let shape = shape(json!({
+            "content": [{"type": "text", "text": "hi"}, {"type": "hologram"}],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 10, "output_tokens": 3}
+        }));
+        assert_eq!(
+            shape.anomalies(),
+            vec![Anomaly::UnknownContentBlock("hologram".to_string())]
+        );
+    }
+
+    #[test]
+    fn well_formed_response_has_no_anomalies() {
+        let shape = shape(json!({
+            "content": [{"type": "thinking", "thinking": "..."}, {"type": "text", "text": "hi"}],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 10, "output_tokens": 3}
+        }));
+        assert!(shape.anomalies().is_empty());
+    }
+
+    #[test]
+    fn inspect_is_a_no_op_when_off() {
+        let shape = shape(json!({"content": [], "stop_reason": "end_turn"}));
+        assert!(inspect(ValidationMode::Off, "p", "m", &shape).is_empty());
+        assert_eq!(
+            inspect(ValidationMode::Warn, "p", "m", &shape),
+            vec![Anomaly::EmptyContent]
+        );
+    }
+}
@@ -0,0 +1,461 @@
+//! Anthropic API Provider
+//!
+//! 基于 API key 认证，请求发往 `{base_url}/v1/messages`。请求体只去掉内部字段、不做 tool
+//! 名称伪装；客户端的 `anthropic-beta` 原样透传。响应头中的 rate limit 信息与 Claude Code
+//! 使用相同的解析方式，上游未返回的窗口保持默认值，不影响选择。
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{header, HeaderMap, HeaderValue};
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::egress;
+use crate::providers::claude_code::{
+    self, check_response_headers, get_api_client, parse_rate_limit, read_message_capped,
+    relay_unmodified, ANTHROPIC_API_VERSION,
+};
+use crate::providers::header_capture::{CapturedHeaders, HeaderCapture};
+use crate::providers::{
+    record_sent_headers, AlertsConfig, ApiConfig, EncodedRequest, Provider, ProviderSettings,
+    ProviderType, RateLimitInfo, Schedule, SentHeaders, StreamingResponse, UpstreamError,
+    SKIP_TRANSFORMS_FIELD,
+};
+use crate::utils::extract_model;
+
+pub struct AnthropicApiProvider {
+    id: String,
+    name: String,
+    /// `{base_url}/v1/messages`
+    url: reqwest::Url,
+    api_key: String,
+    alerts: Option<AlertsConfig>,
+    schedule: Option<Schedule>,
+    weight: u32,
+    rate_limit: std::sync::RwLock<RateLimitInfo>,
+    response_headers: HeaderCapture,
+    settings: Arc<ProviderSettings>,
+}
+
+impl AnthropicApiProvider {
+    pub fn new(
+        id: String,
+        name: String,
+        api: ApiConfig,
+        settings: Arc<ProviderSettings>,
+        alerts: Option<AlertsConfig>,
+        schedule: Option<Schedule>,
+    ) -> Result<Self> {
+        let url = format!("{}/v1/messages", api.base_url.trim_end_matches('/'));
+        let url = reqwest::Url::parse(&url)
+            .with_context(|| format!("Invalid base_url for provider {name}"))?;
+        Ok(Self {
+            id,
+            name,
+            url,
+            api_key: api.api_key,
+            alerts,
+            schedule,
+            weight: crate::providers::config::DEFAULT_WEIGHT,
+            rate_limit: std::sync::RwLock::new(RateLimitInfo::default()),
+            response_headers: HeaderCapture::new(settings.capture.clone()),
+            settings,
+        })
+    }
+
+    /// 设置加权选择时的权重
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// 将序列化好的请求体发送到上游，非 2xx 响应返回 [`UpstreamError`]
+    async fn post(&self, body: Bytes, beta: &str) -> Result<reqwest::Response> {
+        let headers = build_headers(&self.api_key, beta)?;
+
+        tracing::debug!(
+            provider = self.name,
+            anthropic_version = ANTHROPIC_API_VERSION,
+            anthropic_beta = beta,
+            "outbound headers"
+        );
+        record_sent_headers(SentHeaders {
+            anthropic_version: ANTHROPIC_API_VERSION.to_string(),
+            anthropic_beta: beta.to_string(),
+        });
+
+        egress::check_url(&self.url)?;
+        let response = get_api_client()
+            .post(self.url.clone())
+            .headers(headers)
+            .body(body)
+            .send()
+            .await
+            .context("Failed to send request to Anthropic API")?;
+
+        check_response_headers(response.headers())?;
+
+        // 提取 rate limit 信息并采集响应头（无论成功与否）
+        if let Ok(mut guard) = self.rate_limit.write() {
+            *guard = parse_rate_limit(response.headers());
+        }
+        self.response_headers.record(&self.name, response.headers());
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_body = response.text().await.unwrap_or_default();
+            return Err(UpstreamError {
+                status,
+                body: error_body,
+            }
+            .into());
+        }
+
+        Ok(response)
+    }
+
+    /// 发送非流式请求，响应体超过上限时按配置失败或以流式重发
+    async fn send_and_read(&self, body: Bytes, beta: &str, model: &str) -> Result<Value> {
+        let response = self.post(body.clone(), beta).await?;
+        let resend = || async { self.post(claude_code::with_stream(&body)?, beta).await };
+        read_message_capped(&self.settings, &self.name, model, response, resend).await
+    }
+}
+
+#[async_trait]
+impl Provider for AnthropicApiProvider {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn provider_type(&self) -> ProviderType {
+        ProviderType::Anthropic
+    }
+
+    async fn send_message(&self, request: Value) -> Result<Value> {
+        self.send_message_encoded(self.encode(request, false)?)
+            .await
+    }
+
+    async fn send_streaming(&self, request: Value) -> Result<StreamingResponse> {
+        self.send_streaming_encoded(self.encode(request, true)?)
+            .await
+    }
+
+    async fn send_message_raw(&self, body: Bytes, model: &str) -> Result<Value> {
+        self.send_and_read(body, "", model).await
+    }
+
+    async fn send_streaming_raw(&self, body: Bytes, model: &str) -> Result<StreamingResponse> {
+        let response = self.post(body, "").await?;
+        Ok(relay_unmodified(
+            response,
+            self.name.clone(),
+            model.to_string(),
+            self.settings.deprecations.clone(),
+        ))
+    }
+
+    fn encode(&self, request: Value, stream: bool) -> Result<EncodedRequest> {
+        let model = extract_model(&request);
+        let beta = request
+            .pointer("/_passthrough_headers/anthropic-beta")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string();
+        let mut request = request;
+        if let Some(obj) = request.as_object_mut() {
+            obj.insert("stream".to_string(), Value::Bool(stream));
+            obj.remove("_passthrough_headers");
+            obj.remove(SKIP_TRANSFORMS_FIELD);
+        }
+        let body = serde_json::to_vec(&request).context("Failed to serialize request body")?;
+        Ok(EncodedRequest {
+            body: Bytes::from(body),
+            beta,
+            model,
+            tools_spoofed: false,
+        })
+    }
+
+    async fn send_message_encoded(&self, request: EncodedRequest) -> Result<Value> {
+        self.send_and_read(request.body, &request.beta, &request.model)
+            .await
+    }
+
+    async fn send_streaming_encoded(&self, request: EncodedRequest) -> Result<StreamingResponse> {
+        let response = self.post(request.body, &request.beta).await?;
+        Ok(relay_unmodified(
+            response,
+            self.name.clone(),
+            request.model,
+            self.settings.deprecations.clone(),
+        ))
+    }
+
+    fn rate_limit_info(&self) -> Option<RateLimitInfo> {
+        self.rate_limit.read().ok().map(|guard| guard.clone())
+    }
+
+    fn captured_headers(&self) -> Option<CapturedHeaders> {
+        Some(self.response_headers.snapshot())
+    }
+
+    fn alerts(&self) -> Option<&AlertsConfig> {
+        self.alerts.as_ref()
+    }
+
+    fn schedule(&self) -> Option<&Schedule> {
+        self.schedule.as_ref()
+    }
+
+    fn weight(&self) -> u32 {
+        self.weight
+    }
+}
+
+fn build_headers(api_key: &str, beta: &str) -> Result<HeaderMap> {
+    let mut map = HeaderMap::new();
+
+    map.insert(
+        "x-api-key",
+        HeaderValue::from_str(api_key).context("Invalid api_key for header")?,
+    );
+    map.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    map.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
+    map.insert(
+        header::USER_AGENT,
+        HeaderValue::from_static(concat!("pluribus/", env!("CARGO_PKG_VERSION"))),
+    );
+    map.insert(
+        "anthropic-version",
+        HeaderValue::from_static(ANTHROPIC_API_VERSION),
+    );
+    if !beta.is_empty() {
+        map.insert(
+            "anthropic-beta",
+            HeaderValue::from_str(beta).context("Invalid beta flags")?,
+        );
+    }
+
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::deprecation::{DeprecationWatch, Deprecations};
+    use crate::providers::{OversizedResponse, ResponseBodyLimit, ResponseTooLarge};
+    use crate::test_support;
+    use futures::StreamExt;
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn provider(server: &MockServer, settings: &Arc<ProviderSettings>) -> AnthropicApiProvider {
+        let api = ApiConfig {
+            base_url: server.uri(),
+            api_key: "sk-test".to_string(),
+        };
+        AnthropicApiProvider::new(
+            "id".to_string(),
+            "api".to_string(),
+            api,
+            settings.clone(),
+            None,
+            None,
+        )
+        .unwrap()
+    }
+
+    fn request(stream: bool) -> Value {
+        json!({
+            "model": "claude-old",
+            "max_tokens": 16,
+            "stream": stream,
+            "messages": [{"role": "user", "content": "hi"}]
+        })
+    }
+
+    #[tokio::test]
+    async fn deprecation_signals_are_recorded_in_shared_settings() {
+        let server = MockServer::start().await;
+        let mut message = test_support::message("hi");
+        message["deprecation"] = json!("2026-12-01");
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("sunset", "Tue, 01 Dec 2026 00:00:00 GMT")
+                    .set_body_json(message),
+            )
+            .mount(&server)
+            .await;
+        let settings = Arc::new(ProviderSettings {
+            deprecations: Arc::new(Deprecations::new(DeprecationWatch {
+                headers: vec!["sunset".to_string()],
+                fields: vec!["deprecation".to_string()],
+            })),
+            ..Default::default()
+        });
+
+        provider(&server, &settings)
+            .send_message(request(false))
+            .await
+            .unwrap();
+
+        let report = settings.deprecations.report();
+        let signals: Vec<_> = report
+            .deprecations
+            .iter()
+            .map(|f| (f.provider.as_str(), f.signal.as_str(), f.value.as_str()))
+            .collect();
+        assert_eq!(
+            signals,
+            [
+                ("api", "field:deprecation", "2026-12-01"),
+                ("api", "header:sunset", "Tue, 01 Dec 2026 00:00:00 GMT"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn streamed_message_start_is_inspected() {
+        let server = MockServer::start().await;
+        let mut events = test_support::message_events("hi");
+        events[0]["message"]["deprecation"] = json!(true);
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "text/event-stream")
+                    .set_body_string(test_support::sse(&events)),
+            )
+            .mount(&server)
+            .await;
+        let settings = Arc::new(ProviderSettings {
+            deprecations: Arc::new(Deprecations::new(DeprecationWatch {
+                headers: Vec::new(),
+                fields: vec!["deprecation".to_string()],
+            })),
+            ..Default::default()
+        });
+
+        let response = provider(&server, &settings)
+            .send_streaming(request(true))
+            .await
+            .unwrap();
+        response.stream.for_each(|_| async {}).await;
+
+        let report = settings.deprecations.report();
+        assert_eq!(report.deprecations.len(), 1);
+        assert_eq!(report.deprecations[0].model, "claude-old");
+        assert_eq!(report.deprecations[0].signal, "field:deprecation");
+        assert_eq!(report.deprecations[0].value, "true");
+    }
+
+    /// 非流式请求返回 `message`，流式请求返回对应的 SSE
+    async fn upstream(message: &Value) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(body_partial_json(json!({"stream": true})))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "text/event-stream")
+                    .set_body_string(test_support::sse(&test_support::message_events("hi"))),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(message))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn limited(max_bytes: u64, oversized: OversizedResponse) -> Arc<ProviderSettings> {
+        Arc::new(ProviderSettings {
+            response_limit: ResponseBodyLimit {
+                max_bytes,
+                oversized,
+            },
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn body_at_the_limit_is_read_and_one_byte_over_fails() {
+        let message = test_support::message("hi");
+        let size = serde_json::to_vec(&message).unwrap().len() as u64;
+        let server = upstream(&message).await;
+
+        let settings = limited(size, OversizedResponse::Fail);
+        let response = provider(&server, &settings)
+            .send_message(request(false))
+            .await
+            .unwrap();
+        assert_eq!(response, message);
+
+        let settings = limited(size - 1, OversizedResponse::Fail);
+        let err = provider(&server, &settings)
+            .send_message(request(false))
+            .await
+            .unwrap_err();
+        let too_large = err.downcast_ref::<ResponseTooLarge>().unwrap();
+        assert_eq!((too_large.limit, too_large.observed), (size - 1, size));
+        // 失败模式不以流式重发
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn oversized_body_is_refetched_as_stream() {
+        let mut message = test_support::message("hi");
+        message["padding"] = json!("x".repeat(8192));
+        let server = upstream(&message).await;
+
+        let settings = limited(4096, OversizedResponse::Stream);
+        let response = provider(&server, &settings)
+            .send_message(request(false))
+            .await
+            .unwrap();
+        assert_eq!(response["content"][0]["text"], "hi");
+        assert_eq!(response["stop_reason"], "end_turn");
+        assert!(response.get("padding").is_none());
+
+        let requests = server.received_requests().await.unwrap();
+        let streams: Vec<bool> = requests
+            .iter()
+            .map(|r| r.body_json::<Value>().unwrap()["stream"] == true)
+            .collect();
+        assert_eq!(streams, [false, true]);
+    }
+
+    #[tokio::test]
+    async fn oversized_body_fails_with_bad_gateway() {
+        let mut message = test_support::message("hi");
+        message["padding"] = json!("x".repeat(8192));
+        let server = upstream(&message).await;
+        let settings = limited(4096, OversizedResponse::Fail);
+        let (_dir, config) = test_support::config("");
+        let keys = test_support::keys(&config);
+        let providers: Vec<Arc<dyn Provider>> = vec![Arc::new(provider(&server, &settings))];
+        let router =
+            crate::gateway::test_router(crate::gateway::AppState::new(providers, config, keys));
+
+        let request = test_support::messages_request(test_support::USER_KEY, &request(false));
+        let (status, _, body) = test_support::send(&router, request).await;
+        assert_eq!(status, http::StatusCode::BAD_GATEWAY);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "upstream_response_too_large");
+    }
+}
@@ -3,8 +3,14 @@
 use anyhow::{Context, Result};
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use rand::Rng;
+use serde::Serialize;
 use sha2::{Digest, Sha256};
-use std::sync::OnceLock;
+use std::sync::{LazyLock, RwLock};
+use std::time::Duration;
+
+use crate::quiet_hours::QuietHours;
+use crate::stats::{self, TaskKind};
+use crate::utils::unix_timestamp_secs;
 
 pub const ANTHROPIC_API_URL: &str = "https://api.anthropic.com/v1/messages";
 pub const ANTHROPIC_API_VERSION: &str = "2023-06-01";
@@ -15,12 +21,21 @@ pub const CLAUDE_CODE_OAUTH_TOKEN_URL: &str = "https://console.anthropic.com/v1/
 pub const CLAUDE_CODE_OAUTH_REDIRECT_URI: &str =
     "https://console.anthropic.com/oauth/code/callback";
 
+/// 自定义 scheme 回调的默认路径（如 `pluribus://oauth/callback`）
+const OAUTH_CUSTOM_SCHEME_PATH: &str = "oauth/callback";
+
+/// 本地回调服务器接收授权码的路径
+pub const OAUTH_LOCAL_CALLBACK_PATH: &str = "/callback";
+
 pub const CLAUDE_CODE_OAUTH_SCOPES: &[&str] = &[
     "user:profile",
     "user:inference",
     "user:sessions:claude_code",
 ];
 
+/// 推理请求必需的 OAuth scopes，缺少时上游返回权限错误
+pub const CLAUDE_CODE_INFERENCE_SCOPES: &[&str] = &["user:inference"];
+
 /// Claude Code OAuth 需要的基础 beta flags
 pub const BETA_FLAGS_BASE: &[&str] = &[
     "claude-code-20250219",
@@ -32,37 +47,220 @@ pub const BETA_FLAGS_BASE: &[&str] = &[
 /// 需要从用户请求中排除的 beta flags
 pub const BETA_FLAGS_EXCLUDE: &[&str] = &[];
 
-static CLAUDE_CODE_VERSION: OnceLock<String> = OnceLock::new();
+/// 明确支持透传的 beta 功能
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BetaFeature {
+    pub name: &'static str,
+    /// beta flag 前缀，flag 带日期后缀（如 `skills-2025-10-02`）
+    pub prefix: &'static str,
+    /// 该功能引入的请求体字段，原样转发
+    pub fields: &'static [&'static str],
+}
+
+pub const SKILLS_BETA: BetaFeature = BetaFeature {
+    name: "skills",
+    prefix: "skills-",
+    fields: &["container"],
+};
+
+pub const PASSTHROUGH_BETAS: &[BetaFeature] = &[
+    SKILLS_BETA,
+    BetaFeature {
+        name: "context_management",
+        prefix: "context-management-",
+        fields: &["context_management"],
+    },
+    BetaFeature {
+        name: "code_execution",
+        prefix: "code-execution-",
+        fields: &["container"],
+    },
+];
+
+impl BetaFeature {
+    /// 逗号分隔的 beta flags 中是否启用了该功能
+    pub fn is_enabled(&self, beta: &str) -> bool {
+        beta.split(',')
+            .any(|flag| flag.trim().starts_with(self.prefix))
+    }
+
+    /// 该功能的 beta flag 是否会透传到上游（未被 [`BETA_FLAGS_EXCLUDE`] 排除）
+    pub fn is_passed_through(&self) -> bool {
+        !BETA_FLAGS_EXCLUDE
+            .iter()
+            .any(|flag| flag.starts_with(self.prefix))
+    }
+}
+
+/// 解析 OAuth redirect URI，`local_port` 为本地回调服务器监听的端口
+///
+/// 优先级：
+/// 1. `PLURIBUS_OAUTH_REDIRECT_URI`：完整 URI，原样使用
+/// 2. `PLURIBUS_OAUTH_CUSTOM_SCHEME`：自定义 scheme（如 `pluribus`），拼接为 `pluribus://oauth/callback`；
+///    若已是完整 URI（包含 `://`）则原样使用
+/// 3. 启用本地回调服务器时为 `http://localhost:{port}/callback`
+/// 4. 默认的 Anthropic Console 回调地址
+pub fn oauth_redirect_uri(local_port: Option<u16>) -> String {
+    redirect_uri_from(|name| std::env::var(name).ok(), local_port)
+}
+
+/// 本地回调服务器的 redirect URI
+pub fn local_callback_uri(port: u16) -> String {
+    format!("http://localhost:{port}{OAUTH_LOCAL_CALLBACK_PATH}")
+}
+
+/// 按 [`oauth_redirect_uri`] 的优先级解析，`var` 读取环境变量
+fn redirect_uri_from(var: impl Fn(&str) -> Option<String>, local_port: Option<u16>) -> String {
+    let from_env = |name: &str| {
+        var(name)
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+
+    if let Some(uri) = from_env("PLURIBUS_OAUTH_REDIRECT_URI") {
+        return uri;
+    }
+
+    if let Some(scheme) = from_env("PLURIBUS_OAUTH_CUSTOM_SCHEME") {
+        if scheme.contains("://") {
+            return scheme;
+        }
+        let scheme = scheme.trim_end_matches(':');
+        return format!("{scheme}://{OAUTH_CUSTOM_SCHEME_PATH}");
+    }
+
+    if let Some(port) = local_port {
+        return local_callback_uri(port);
+    }
+
+    CLAUDE_CODE_OAUTH_REDIRECT_URI.to_string()
+}
+
+/// 本地回调服务器的端口（`PLURIBUS_OAUTH_CALLBACK_PORT`），未设置时不启用，0 表示任意空闲端口
+pub fn oauth_callback_port() -> Result<Option<u16>> {
+    callback_port_from(|name| std::env::var(name).ok())
+}
+
+fn callback_port_from(var: impl Fn(&str) -> Option<String>) -> Result<Option<u16>> {
+    var("PLURIBUS_OAUTH_CALLBACK_PORT")
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            v.parse()
+                .with_context(|| format!("PLURIBUS_OAUTH_CALLBACK_PORT '{v}' is not a valid port"))
+        })
+        .transpose()
+}
+
 const CLAUDE_CODE_NPM_REGISTRY_URL: &str = "https://registry.npmjs.org/@anthropic-ai/claude-code";
 const CLAUDE_CODE_DEFAULT_VERSION: &str = "2.0.75";
 
-pub async fn init_version() -> Result<()> {
-    let version = fetch_latest_version().await.unwrap_or_else(|e| {
-        tracing::warn!("Failed to fetch Claude Code version: {}", e);
-        CLAUDE_CODE_DEFAULT_VERSION.to_string()
-    });
+/// 版本刷新间隔：1 天
+const VERSION_REFRESH_INTERVAL_SECS: u64 = 24 * 3600;
+/// 版本刷新的随机抖动上限：1 小时
+const VERSION_REFRESH_JITTER_SECS: u64 = 3600;
 
-    CLAUDE_CODE_VERSION
-        .set(version.clone())
-        .map_err(|_| anyhow::anyhow!("Version already initialized"))?;
+/// 当前版本号的来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionSource {
+    /// 最近一次从 npm registry 获取成功
+    Fetched,
+    /// 最近一次获取失败，沿用之前获取到的版本
+    Cached,
+    /// 从未获取成功，使用内置默认版本
+    Default,
+}
+
+/// Claude Code 版本信息
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionInfo {
+    pub claude_code_version: String,
+    pub version_source: VersionSource,
+    /// 最近一次获取成功的时间 (Unix timestamp)
+    pub fetched_at: Option<u64>,
+}
+
+static CLAUDE_CODE_VERSION: LazyLock<RwLock<VersionInfo>> = LazyLock::new(|| {
+    RwLock::new(VersionInfo {
+        claude_code_version: CLAUDE_CODE_DEFAULT_VERSION.to_string(),
+        version_source: VersionSource::Default,
+        fetched_at: None,
+    })
+});
 
-    tracing::info!("Claude Code version: {}", version);
-    Ok(())
+/// 启动后台任务：立即获取一次版本号，之后定时刷新，返回任务句柄
+///
+/// 不等待首次获取完成，获取成功前使用内置默认版本；定时刷新在静默时段内推迟到时段结束
+pub fn init_version(quiet_hours: QuietHours) -> tokio::task::JoinHandle<()> {
+    stats::spawn(TaskKind::VersionRefresh, async move {
+        refresh_version().await;
+        tracing::info!("Claude Code version: {}", get_claude_code_version());
+        loop {
+            let jitter = rand::rng().random_range(0..VERSION_REFRESH_JITTER_SECS);
+            let delay = Duration::from_secs(VERSION_REFRESH_INTERVAL_SECS + jitter);
+            tokio::time::sleep(delay).await;
+            quiet_hours.wait_until_over().await;
+            refresh_version().await;
+        }
+    })
 }
 
-pub fn get_claude_code_version() -> &'static str {
+/// 从 npm registry 刷新版本号，失败时保留当前值并更新来源
+async fn refresh_version() {
+    let result = fetch_latest_version(CLAUDE_CODE_NPM_REGISTRY_URL).await;
+    if let Ok(mut info) = CLAUDE_CODE_VERSION.write() {
+        apply_fetch_result(&mut info, result, unix_timestamp_secs());
+    }
+}
+
+/// 按一次获取的结果更新版本信息：成功时替换版本号，失败时沿用当前版本号
+fn apply_fetch_result(info: &mut VersionInfo, result: Result<String>, now_secs: u64) {
+    match result {
+        Ok(version) => {
+            if version != info.claude_code_version {
+                tracing::info!(
+                    "Claude Code version updated: {} -> {}",
+                    info.claude_code_version,
+                    version
+                );
+            }
+            info.claude_code_version = version;
+            info.version_source = VersionSource::Fetched;
+            info.fetched_at = Some(now_secs);
+        }
+        Err(e) => {
+            tracing::warn!("Failed to fetch Claude Code version: {:#}", e);
+            if info.version_source == VersionSource::Fetched {
+                info.version_source = VersionSource::Cached;
+            }
+        }
+    }
+}
+
+pub fn get_claude_code_version() -> String {
+    version_info().claude_code_version
+}
+
+/// 获取当前版本号及其来源
+pub fn version_info() -> VersionInfo {
     CLAUDE_CODE_VERSION
-        .get()
-        .map(|s| s.as_str())
-        .unwrap_or(CLAUDE_CODE_DEFAULT_VERSION)
+        .read()
+        .map(|info| info.clone())
+        .unwrap_or_else(|_| VersionInfo {
+            claude_code_version: CLAUDE_CODE_DEFAULT_VERSION.to_string(),
+            version_source: VersionSource::Default,
+            fetched_at: None,
+        })
 }
 
-async fn fetch_latest_version() -> Result<String> {
+async fn fetch_latest_version(registry_url: &str) -> Result<String> {
     let response: serde_json::Value = crate::utils::get_shared_client()
-        .get(CLAUDE_CODE_NPM_REGISTRY_URL)
+        .get(registry_url)
         .header("Accept", "application/json")
         .send()
         .await
+        .and_then(reqwest::Response::error_for_status)
         .context("Failed to fetch npm registry")?
         .json()
         .await
@@ -105,3 +303,179 @@ impl PkceChallenge {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var<'a>(vars: &'a [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |name| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    fn redirect_uri(vars: &[(&str, &str)]) -> String {
+        redirect_uri_from(var(vars), None)
+    }
+
+    #[test]
+    fn redirect_uri_defaults_to_the_console_callback() {
+        assert_eq!(redirect_uri(&[]), CLAUDE_CODE_OAUTH_REDIRECT_URI);
+        // 空值视为未设置
+        assert_eq!(
+            redirect_uri(&[("PLURIBUS_OAUTH_REDIRECT_URI", "  ")]),
+            CLAUDE_CODE_OAUTH_REDIRECT_URI
+        );
+    }
+
+    #[test]
+    fn redirect_uri_override_wins() {
+        let vars = [
+            (
+                "PLURIBUS_OAUTH_REDIRECT_URI",
+                " http://localhost:8080/callback ",
+            ),
+            ("PLURIBUS_OAUTH_CUSTOM_SCHEME", "pluribus"),
+        ];
+        assert_eq!(redirect_uri(&vars), "http://localhost:8080/callback");
+    }
+
+    #[test]
+    fn custom_scheme_builds_the_callback() {
+        for scheme in ["pluribus", "pluribus:"] {
+            assert_eq!(
+                redirect_uri(&[("PLURIBUS_OAUTH_CUSTOM_SCHEME", scheme)]),
+                "pluribus://oauth/callback"
+            );
+        }
+        assert_eq!(
+            redirect_uri(&[("PLURIBUS_OAUTH_CUSTOM_SCHEME", "myapp://done")]),
+            "myapp://done"
+        );
+    }
+
+    #[test]
+    fn local_callback_is_used_unless_overridden() {
+        assert_eq!(
+            redirect_uri_from(var(&[]), Some(54545)),
+            "http://localhost:54545/callback"
+        );
+        let overrides = [
+            (
+                ("PLURIBUS_OAUTH_REDIRECT_URI", "https://example.com/cb"),
+                "https://example.com/cb",
+            ),
+            (
+                ("PLURIBUS_OAUTH_CUSTOM_SCHEME", "pluribus"),
+                "pluribus://oauth/callback",
+            ),
+        ];
+        for (setting, expected) in overrides {
+            assert_eq!(redirect_uri_from(var(&[setting]), Some(54545)), expected);
+        }
+    }
+
+    #[test]
+    fn callback_port_is_optional() {
+        assert_eq!(callback_port_from(var(&[])).unwrap(), None);
+        assert_eq!(
+            callback_port_from(var(&[("PLURIBUS_OAUTH_CALLBACK_PORT", " ")])).unwrap(),
+            None
+        );
+        assert_eq!(
+            callback_port_from(var(&[("PLURIBUS_OAUTH_CALLBACK_PORT", "0")])).unwrap(),
+            Some(0)
+        );
+        let err =
+            callback_port_from(var(&[("PLURIBUS_OAUTH_CALLBACK_PORT", "70000")])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "PLURIBUS_OAUTH_CALLBACK_PORT '70000' is not a valid port"
+        );
+    }
+
+    fn info(version: &str, source: VersionSource, fetched_at: Option<u64>) -> VersionInfo {
+        VersionInfo {
+            claude_code_version: version.to_string(),
+            version_source: source,
+            fetched_at,
+        }
+    }
+
+    #[test]
+    fn failed_refreshes_fall_back_to_the_last_known_version() {
+        let mut current = info(CLAUDE_CODE_DEFAULT_VERSION, VersionSource::Default, None);
+        apply_fetch_result(&mut current, Err(anyhow::anyhow!("offline")), 100);
+        assert_eq!(current.claude_code_version, CLAUDE_CODE_DEFAULT_VERSION);
+        assert_eq!(current.version_source, VersionSource::Default);
+        assert_eq!(current.fetched_at, None);
+
+        apply_fetch_result(&mut current, Ok("2.1.0".to_string()), 200);
+        assert_eq!(current.claude_code_version, "2.1.0");
+        assert_eq!(current.version_source, VersionSource::Fetched);
+        assert_eq!(current.fetched_at, Some(200));
+
+        // 获取失败时沿用上次获取到的版本与时间
+        apply_fetch_result(&mut current, Err(anyhow::anyhow!("offline")), 300);
+        assert_eq!(current.claude_code_version, "2.1.0");
+        assert_eq!(current.version_source, VersionSource::Cached);
+        assert_eq!(current.fetched_at, Some(200));
+
+        apply_fetch_result(&mut current, Ok("2.1.1".to_string()), 400);
+        assert_eq!(current.claude_code_version, "2.1.1");
+        assert_eq!(current.version_source, VersionSource::Fetched);
+        assert_eq!(current.fetched_at, Some(400));
+    }
+
+    #[tokio::test]
+    async fn fetch_reads_the_latest_dist_tag() {
+        use wiremock::matchers::{header, method};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(header("Accept", "application/json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "name": "@anthropic-ai/claude-code",
+                "dist-tags": {"latest": "2.1.0", "next": "2.2.0-beta.1"}
+            })))
+            .mount(&server)
+            .await;
+        let version = fetch_latest_version(&server.uri()).await.unwrap();
+        assert_eq!(version, "2.1.0");
+    }
+
+    #[tokio::test]
+    async fn fetch_fails_on_unexpected_responses() {
+        use wiremock::matchers::path;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/no-tags"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .mount(&server)
+            .await;
+        Mock::given(path("/html"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<html></html>"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/down"))
+            .respond_with(ResponseTemplate::new(503).set_body_json(serde_json::json!({})))
+            .mount(&server)
+            .await;
+
+        let cases = [
+            ("/no-tags", "Latest version not found"),
+            ("/html", "Failed to parse npm registry response"),
+            ("/down", "Failed to fetch npm registry"),
+        ];
+        for (path, expected) in cases {
+            let err = fetch_latest_version(&format!("{}{path}", server.uri()))
+                .await
+                .unwrap_err();
+            assert_eq!(err.to_string(), expected, "{path}");
+        }
+    }
+}
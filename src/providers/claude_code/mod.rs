@@ -6,27 +6,42 @@ mod constants;
 pub mod oauth;
 mod tool_spoof;
 
-use crate::providers::claude_code::constants::{
-    ANTHROPIC_API_VERSION, BETA_FLAGS_BASE, BETA_FLAGS_EXCLUDE,
-};
+use crate::access_log;
+use crate::egress;
+use crate::providers::anomaly::ResponseShape;
+use crate::providers::claude_code::constants::{CLAUDE_CODE_OAUTH_TOKEN_URL, SKILLS_BETA};
+use crate::providers::claude_code::tool_spoof::SpoofOptions;
 use crate::providers::config;
+use crate::providers::deprecation::Deprecations;
+use crate::providers::header_capture::{CapturedHeaders, HeaderCapture};
+use crate::providers::sse::{self, EventKind, StreamFailure};
 use crate::providers::{
-    parse_anthropic_usage, AuthConfig, OAuthConfig, Provider, ProviderType, StreamingResponse,
-    Usage,
+    parse_anthropic_usage, parse_delta_usage, record_sent_headers, skips_transform, AlertsConfig,
+    AuthConfig, EncodedRequest, OAuthConfig, OversizedResponse, Provider, ProviderSettings,
+    ProviderType, ResponseTooLarge, Schedule, SentHeaders, StreamSummary, StreamingResponse,
+    Transform, UpstreamError, SKIP_TRANSFORMS_FIELD,
+};
+use crate::stats::{self, StreamStats, TaskKind};
+use crate::utils::{
+    extract_model, header_list_size, max_response_header_size, should_disable_tls_verify,
 };
-use crate::utils::{extract_model, should_disable_tls_verify};
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::{Stream, StreamExt};
 use http::{header, HeaderMap, HeaderValue};
+use rand::Rng;
 use reqwest::Client;
 use serde::Serialize;
 use serde_json::Value;
+use std::borrow::Cow;
 use std::collections::BTreeSet;
+use std::future::Future;
 use std::path::PathBuf;
-use std::sync::OnceLock;
-use tokio::sync::{mpsc, Mutex};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tracing::Instrument;
 
 /// Rate limit 窗口信息
 #[derive(Debug, Clone, Default, Serialize)]
@@ -52,8 +67,14 @@ pub struct RateLimitInfo {
 
 use constants::ANTHROPIC_API_URL;
 
-pub use constants::{get_claude_code_version, init_version};
-pub use oauth::perform_oauth_login;
+pub use constants::{
+    init_version, version_info, BetaFeature, VersionInfo, ANTHROPIC_API_VERSION, BETA_FLAGS_BASE,
+    BETA_FLAGS_EXCLUDE, CLAUDE_CODE_INFERENCE_SCOPES, PASSTHROUGH_BETAS,
+};
+pub use oauth::{complete_oauth_login, start_oauth_login, CallbackListener, PendingLogin};
+
+/// 客户端自带 token 的临时 provider 的名称，用于日志与用量统计
+pub const PASSTHROUGH_PROVIDER: &str = "passthrough";
 
 /// 流式响应通道缓冲大小
 const STREAM_CHANNEL_BUFFER: usize = 100;
@@ -61,15 +82,51 @@ const STREAM_CHANNEL_BUFFER: usize = 100;
 /// API 请求超时（秒）
 const API_TIMEOUT_SECS: u64 = 300;
 
-/// 共享的 API 客户端（带 user-agent）
+/// 后台刷新的提前量：token 在此时间内过期时由后台任务刷新，早于请求路径的刷新阈值
+const BACKGROUND_REFRESH_LEAD_MS: u64 = 15 * 60 * 1000;
+/// 静默时段内后台刷新的提前量：只刷新即将过期的 token，与请求路径的刷新阈值相同
+const QUIET_HOURS_REFRESH_LEAD_MS: u64 = 5 * 60 * 1000;
+
+/// token 刷新设置
+#[derive(Debug, Clone)]
+pub struct RefreshPolicy {
+    /// 是否由本进程刷新 token；共享模式下由持有实例锁的进程刷新并写回配置文件
+    pub enabled: bool,
+    /// 同一 provider 两次刷新尝试之间的最短间隔，无论上次是否成功
+    pub min_interval: Duration,
+    /// 遇到暂时性错误时的最大重试次数
+    pub max_retries: u32,
+    /// 重试的基础间隔（毫秒），第 n 次重试前随机等待 `0..base * 2^n`
+    pub base_delay_ms: u64,
+}
+
+impl Default for RefreshPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_interval: Duration::from_secs(60),
+            max_retries: 3,
+            base_delay_ms: 500,
+        }
+    }
+}
+
+impl RefreshPolicy {
+    /// 第 `retries` 次重试前随机等待的上限（毫秒，不含）
+    fn delay_ceiling_ms(&self, retries: u32) -> u64 {
+        self.base_delay_ms.saturating_mul(1 << retries.min(16))
+    }
+}
+
+/// 共享的 API 客户端（user-agent 按请求设置，以便版本更新后立即生效）
 static API_CLIENT: OnceLock<Client> = OnceLock::new();
 
-fn get_api_client() -> &'static Client {
+pub(crate) fn get_api_client() -> &'static Client {
     API_CLIENT.get_or_init(|| {
         let mut builder = Client::builder()
             .timeout(std::time::Duration::from_secs(API_TIMEOUT_SECS))
-            .user_agent(user_agent())
-            .pool_max_idle_per_host(10);
+            .pool_max_idle_per_host(10)
+            .redirect(egress::redirect_policy());
 
         if should_disable_tls_verify() {
             tracing::warn!("TLS certificate verification is DISABLED - for debugging only!");
@@ -80,117 +137,446 @@ fn get_api_client() -> &'static Client {
     })
 }
 
+/// 请求上游使用的 access token 来源
+enum TokenSource {
+    /// 保存在 provider 配置文件中的 OAuth 凭证，过期前自动刷新
+    Stored {
+        providers_dir: PathBuf,
+        cached_oauth: Mutex<Option<OAuthConfig>>,
+        /// 上次尝试刷新的时间；持有期间串行刷新
+        last_refresh: Mutex<Option<Instant>>,
+    },
+    /// 客户端随请求提供的 token，不刷新、不保存
+    Passthrough(String),
+}
+
+/// 被上游作废的 refresh token 及刷新时的错误
+struct Reauth {
+    refresh_token: String,
+    error: String,
+}
+
 pub struct ClaudeCodeProvider {
-    providers_dir: PathBuf,
+    id: String,
     name: String,
-    cached_oauth: Mutex<Option<OAuthConfig>>,
+    settings: Arc<ProviderSettings>,
+    alerts: Option<AlertsConfig>,
+    schedule: Option<Schedule>,
+    weight: u32,
+    /// 推理所需但授权中缺少的 OAuth scopes，非空时不参与选择
+    missing_scopes: Vec<String>,
+    token: TokenSource,
+    /// 配置文件名（不含扩展名）；文件改名后按 provider ID 重新查找
+    file_name: std::sync::RwLock<String>,
+    /// 刷新 token 使用的 OAuth 接口
+    token_url: String,
+    /// `[model_endpoints]` 未匹配时使用的 Messages 地址
+    api_url: String,
+    /// refresh token 被上游作废（`invalid_grant`），需要重新登录
+    reauth: std::sync::RwLock<Option<Reauth>>,
     rate_limit: std::sync::RwLock<RateLimitInfo>,
+    response_headers: HeaderCapture,
 }
 
 impl ClaudeCodeProvider {
-    pub fn new(providers_dir: PathBuf, name: String) -> Result<Self> {
+    pub fn new(
+        providers_dir: PathBuf,
+        id: String,
+        name: String,
+        settings: Arc<ProviderSettings>,
+        alerts: Option<AlertsConfig>,
+        schedule: Option<Schedule>,
+        missing_scopes: Vec<String>,
+    ) -> Result<Self> {
+        let response_headers = HeaderCapture::new(settings.capture.clone());
         Ok(Self {
-            providers_dir,
+            id,
+            file_name: std::sync::RwLock::new(name.clone()),
+            token: TokenSource::Stored {
+                providers_dir,
+                cached_oauth: Mutex::new(None),
+                last_refresh: Mutex::new(None),
+            },
             name,
-            cached_oauth: Mutex::new(None),
+            settings,
+            alerts,
+            schedule,
+            weight: crate::providers::config::DEFAULT_WEIGHT,
+            missing_scopes,
+            token_url: CLAUDE_CODE_OAUTH_TOKEN_URL.to_string(),
+            api_url: ANTHROPIC_API_URL.to_string(),
+            reauth: std::sync::RwLock::new(None),
             rate_limit: std::sync::RwLock::new(RateLimitInfo::default()),
+            response_headers,
         })
     }
 
+    /// 设置加权选择时的权重
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// 使用指定的 OAuth token 接口刷新，测试中指向 mock 服务
+    #[cfg(test)]
+    pub(crate) fn with_token_url(mut self, token_url: String) -> Self {
+        self.token_url = token_url;
+        self
+    }
+
+    /// 使用指定的默认 Messages 地址，测试中指向 mock 上游
+    #[cfg(test)]
+    pub(crate) fn with_api_url(mut self, api_url: String) -> Self {
+        self.api_url = api_url;
+        self
+    }
+
+    /// 使用客户端提供的 access token 的临时 provider，只用于单个请求
+    ///
+    /// 不刷新也不保存 token，rate limit 信息只记录在自身，不影响已保存的 provider
+    pub fn passthrough(access_token: String, settings: Arc<ProviderSettings>) -> Self {
+        Self {
+            id: PASSTHROUGH_PROVIDER.to_string(),
+            name: PASSTHROUGH_PROVIDER.to_string(),
+            response_headers: HeaderCapture::new(settings.capture.clone()),
+            settings,
+            alerts: None,
+            schedule: None,
+            weight: crate::providers::config::DEFAULT_WEIGHT,
+            missing_scopes: Vec::new(),
+            token: TokenSource::Passthrough(access_token),
+            file_name: std::sync::RwLock::new(PASSTHROUGH_PROVIDER.to_string()),
+            token_url: CLAUDE_CODE_OAUTH_TOKEN_URL.to_string(),
+            api_url: ANTHROPIC_API_URL.to_string(),
+            reauth: std::sync::RwLock::new(None),
+            rate_limit: std::sync::RwLock::new(RateLimitInfo::default()),
+        }
+    }
+
     /// 从响应头提取并更新 rate limit 信息
     fn update_rate_limit(&self, headers: &HeaderMap) {
-        let get_str =
-            |name: &str| -> Option<&str> { headers.get(name).and_then(|v| v.to_str().ok()) };
-
-        let get_u64 =
-            |name: &str| -> u64 { get_str(name).and_then(|s| s.parse().ok()).unwrap_or(0) };
-
-        let get_f64 =
-            |name: &str| -> f64 { get_str(name).and_then(|s| s.parse().ok()).unwrap_or(0.0) };
-
-        let info = RateLimitInfo {
-            five_hour: RateLimitWindow {
-                status: get_str("anthropic-ratelimit-unified-5h-status")
-                    .unwrap_or_default()
-                    .to_string(),
-                reset: get_u64("anthropic-ratelimit-unified-5h-reset"),
-                utilization: get_f64("anthropic-ratelimit-unified-5h-utilization"),
-            },
-            seven_day: RateLimitWindow {
-                status: get_str("anthropic-ratelimit-unified-7d-status")
-                    .unwrap_or_default()
-                    .to_string(),
-                reset: get_u64("anthropic-ratelimit-unified-7d-reset"),
-                utilization: get_f64("anthropic-ratelimit-unified-7d-utilization"),
-            },
-            updated_at: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .map(|d| d.as_secs())
-                .unwrap_or(0),
-        };
-
         if let Ok(mut guard) = self.rate_limit.write() {
-            *guard = info;
+            *guard = parse_rate_limit(headers);
         }
     }
 
     /// 获取有效的 access token，必要时自动刷新
     async fn get_valid_token(&self) -> Result<String> {
+        let (cached_oauth, last_refresh) = match &self.token {
+            TokenSource::Stored {
+                cached_oauth,
+                last_refresh,
+                ..
+            } => (cached_oauth, last_refresh),
+            TokenSource::Passthrough(token) => return Ok(token.clone()),
+        };
+
         // 检查缓存
-        {
-            let cached = self.cached_oauth.lock().await;
-            if let Some(oauth) = &*cached {
-                if !oauth.should_refresh() {
-                    return Ok(oauth.access_token.clone());
-                }
-            }
+        let cached_token = || async {
+            let cached = cached_oauth.lock().await;
+            cached
+                .as_ref()
+                .filter(|oauth| !oauth.should_refresh())
+                .map(|oauth| oauth.access_token.clone())
+        };
+        if let Some(token) = cached_token().await {
+            return Ok(token);
         }
 
-        // 从文件加载
-        let cfg = config::load_by_name(&self.providers_dir, &self.name).await?;
-        let mut oauth = match cfg.auth {
-            AuthConfig::OAuth(o) => o,
-            _ => anyhow::bail!("Provider {} is not OAuth type", self.name),
-        };
+        // 同一时间只有一个请求加载 / 刷新（锁一直持有到缓存更新后），
+        // 等待者拿到锁后先复用刚刷新的 token，不会各自用同一个 refresh token 刷新
+        let mut last_refresh = last_refresh.lock().await;
+        if let Some(token) = cached_token().await {
+            return Ok(token);
+        }
+
+        let mut oauth = self.load_oauth().await?;
 
         // 刷新
         if oauth.should_refresh() {
-            tracing::info!("Refreshing token for provider {}", self.name);
-            oauth = oauth::refresh_token(&oauth.refresh_token).await?;
-            config::update_oauth(&self.providers_dir, &self.name, &oauth).await?;
+            self.ensure_not_revoked()?;
+            let min_interval = self.settings.refresh.min_interval;
+            let throttled = last_refresh.is_some_and(|at| at.elapsed() < min_interval);
+            let expired = oauth.expires_at <= crate::utils::unix_timestamp_ms();
+            if self.settings.refresh.enabled && !throttled {
+                *last_refresh = Some(Instant::now());
+                oauth = self.refresh_stored(&oauth).await?;
+            } else if throttled && expired {
+                let wait = min_interval
+                    .saturating_sub(last_refresh.map_or_else(Duration::default, |at| at.elapsed()));
+                anyhow::bail!(
+                    "Token for provider {} has expired and the last refresh attempt was less than {}s ago; \
+                     next attempt in {}s. Check oauth.expires_at in its config if this repeats",
+                    self.name,
+                    min_interval.as_secs(),
+                    wait.as_secs() + 1
+                );
+            } else if throttled {
+                tracing::debug!(
+                    provider = self.name,
+                    "token due for refresh, but the last attempt was too recent"
+                );
+            } else if expired {
+                anyhow::bail!(
+                    "Token for provider {} has expired; this instance runs with --allow-shared \
+                     and leaves refreshing to the instance holding the lock",
+                    self.name
+                );
+            } else {
+                tracing::debug!(
+                    provider = self.name,
+                    "token due for refresh, leaving it to the instance holding the lock"
+                );
+            }
         }
 
         // 更新缓存
         let token = oauth.access_token.clone();
         {
-            let mut cached = self.cached_oauth.lock().await;
+            let mut cached = cached_oauth.lock().await;
             *cached = Some(oauth);
         }
 
         Ok(token)
     }
 
+    /// 后台刷新：token 将在 [`BACKGROUND_REFRESH_LEAD_MS`] 内过期时提前刷新，静默时段内
+    /// 改为 [`QUIET_HOURS_REFRESH_LEAD_MS`]
+    ///
+    /// 与请求路径共用刷新锁与最短间隔，刷新期间缓存中的 token 仍然有效，请求不必等待
+    async fn refresh_ahead(&self) -> Result<bool> {
+        let TokenSource::Stored {
+            cached_oauth,
+            last_refresh,
+            ..
+        } = &self.token
+        else {
+            return Ok(false);
+        };
+        if !self.settings.refresh.enabled {
+            return Ok(false);
+        }
+        let lead_ms = if self
+            .settings
+            .quiet_hours
+            .is_active(crate::utils::unix_timestamp_secs())
+        {
+            QUIET_HOURS_REFRESH_LEAD_MS
+        } else {
+            BACKGROUND_REFRESH_LEAD_MS
+        };
+        let due = |oauth: &OAuthConfig| oauth.expires_within(lead_ms);
+        if cached_oauth.lock().await.as_ref().is_some_and(|o| !due(o)) {
+            return Ok(false);
+        }
+
+        let mut last_refresh = last_refresh.lock().await;
+        // 等锁期间可能已由请求刷新
+        let mut oauth = self.load_oauth().await?;
+        let min_interval = self.settings.refresh.min_interval;
+        let throttled = last_refresh.is_some_and(|at| at.elapsed() < min_interval);
+        let refresh = due(&oauth) && !throttled && self.needs_reauth().is_none();
+        if refresh {
+            *last_refresh = Some(Instant::now());
+            oauth = self.refresh_stored(&oauth).await?;
+        }
+        *cached_oauth.lock().await = Some(oauth);
+        Ok(refresh)
+    }
+
+    /// 从配置文件加载 OAuth 凭证，缓存中的凭证更新（过期时间更晚）时使用缓存
+    ///
+    /// 其他实例或 `pluribus login` 写入的新凭证以文件为准；刷新后未能写回文件时，
+    /// 文件中是已被上游作废的 refresh token，只能使用缓存。配置文件按 provider ID 查找，
+    /// 改名后仍能找到
+    async fn load_oauth(&self) -> Result<OAuthConfig> {
+        let TokenSource::Stored {
+            providers_dir,
+            cached_oauth,
+            ..
+        } = &self.token
+        else {
+            anyhow::bail!("Provider {} has no stored credentials", self.name);
+        };
+        let hint = self.file_name.read().map(|n| n.clone()).unwrap_or_default();
+        let cfg =
+            config::load_by_id(providers_dir, &self.id, &hint, self.settings.strict_units).await?;
+        if cfg.name != hint {
+            tracing::info!(
+                provider = self.name,
+                file = cfg.name,
+                "provider config was renamed"
+            );
+            if let Ok(mut file_name) = self.file_name.write() {
+                *file_name = cfg.name.clone();
+            }
+        }
+        let AuthConfig::OAuth(oauth) = cfg.auth else {
+            anyhow::bail!("Provider {} is not OAuth type", self.name);
+        };
+        let oauth = {
+            let cached = cached_oauth.lock().await;
+            match cached.as_ref() {
+                Some(cached) if cached.expires_at > oauth.expires_at => cached.clone(),
+                _ => oauth,
+            }
+        };
+        if let Ok(mut reauth) = self.reauth.write() {
+            if let Some(revoked) = reauth.as_ref() {
+                if revoked.refresh_token != oauth.refresh_token {
+                    tracing::info!(
+                        provider = self.name,
+                        "new credentials found, provider re-enabled"
+                    );
+                    *reauth = None;
+                }
+            }
+        }
+        Ok(oauth)
+    }
+
+    /// refresh token 已被作废时返回错误，不再用它请求刷新
+    fn ensure_not_revoked(&self) -> Result<()> {
+        match self.needs_reauth() {
+            Some(error) => anyhow::bail!(
+                "Provider {} needs to log in again (run 'pluribus login'): {}",
+                self.name,
+                error
+            ),
+            None => Ok(()),
+        }
+    }
+
+    /// 刷新 token 并写回配置文件
+    ///
+    /// 上游会轮换 refresh token，写回失败时仍返回新凭证，由调用方保存在缓存中
+    async fn refresh_stored(&self, oauth: &OAuthConfig) -> Result<OAuthConfig> {
+        let TokenSource::Stored { providers_dir, .. } = &self.token else {
+            anyhow::bail!("Provider {} has no stored credentials", self.name);
+        };
+        tracing::info!("Refreshing token for provider {}", self.name);
+        let oauth = match self.refresh_with_retry(&oauth.refresh_token).await {
+            Ok(refreshed) => refreshed,
+            Err(err) => {
+                if oauth::is_invalid_grant(&err) {
+                    tracing::error!(
+                        provider = self.name,
+                        "refresh token was revoked, skipping this provider until it logs in again: {:#}",
+                        err
+                    );
+                    if let Ok(mut reauth) = self.reauth.write() {
+                        *reauth = Some(Reauth {
+                            refresh_token: oauth.refresh_token.clone(),
+                            error: format!("{err:#}"),
+                        });
+                    }
+                }
+                return Err(err);
+            }
+        };
+        let file_name = self.file_name.read().map(|n| n.clone()).unwrap_or_default();
+        if let Err(e) = config::update_oauth(providers_dir, &file_name, &self.id, &oauth).await {
+            tracing::error!(
+                provider = self.name,
+                "Refreshed token could not be saved and is kept in memory only; \
+                 it will be lost on restart: {:#}",
+                e
+            );
+        }
+        Ok(oauth)
+    }
+
+    /// 刷新 token，OAuth 接口暂时不可用（5xx、连接失败、超时）时按全抖动指数退避重试
+    async fn refresh_with_retry(&self, refresh_token: &str) -> Result<OAuthConfig> {
+        let policy = &self.settings.refresh;
+        let max_retries = policy.max_retries;
+        let mut retries = 0;
+        loop {
+            let err = match oauth::refresh_token(&self.token_url, refresh_token).await {
+                Ok(oauth) => return Ok(oauth),
+                Err(err) => err,
+            };
+            if retries >= max_retries || !oauth::is_transient(&err) {
+                return Err(err);
+            }
+            let ceiling = policy.delay_ceiling_ms(retries);
+            let delay = if ceiling > 0 {
+                rand::rng().random_range(0..ceiling)
+            } else {
+                0
+            };
+            retries += 1;
+            tracing::warn!(
+                provider = self.name,
+                attempt = retries,
+                max_retries,
+                delay_ms = delay,
+                "token refresh failed, retrying: {err:#}"
+            );
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+        }
+    }
+
     fn ensure_stream_field(mut request: Value, stream: bool) -> Value {
         if let Some(obj) = request.as_object_mut() {
             obj.insert("stream".to_string(), Value::Bool(stream));
             obj.remove("_passthrough_headers");
+            obj.remove(SKIP_TRANSFORMS_FIELD);
         }
         request
     }
 
-    /// 发送请求的公共逻辑
-    async fn send_request(&self, request: Value, stream: bool) -> Result<reqwest::Response> {
+    /// 发送 `encode` 生成的请求体
+    ///
+    /// 返回的伪装选项用于还原响应中的 tool 名称，请求未伪装时为 `None`
+    async fn send_request(
+        &self,
+        request: &EncodedRequest,
+    ) -> Result<(reqwest::Response, Option<SpoofOptions>)> {
+        let response = self
+            .post(request.body.clone(), &request.beta, &request.model)
+            .await?;
+        let options = request.tools_spoofed.then(|| spoof_options(&request.beta));
+        Ok((response, options))
+    }
+
+    /// 原样转发请求体（调用方保证 body 无需改写）
+    async fn send_raw(&self, body: Bytes, model: &str) -> Result<reqwest::Response> {
+        self.post(body, &build_beta_value(&Value::Null), model)
+            .await
+    }
+
+    /// 将序列化好的请求体发送到上游
+    ///
+    /// 上游地址按 `[model_endpoints]` 匹配模型，未匹配时使用默认地址
+    async fn post(&self, body: Bytes, beta: &str, model: &str) -> Result<reqwest::Response> {
+        let endpoint = self
+            .settings
+            .endpoints
+            .resolve(model)
+            .unwrap_or(&self.api_url);
+        self.post_to(endpoint, body, beta).await
+    }
+
+    /// 将序列化好的请求体发送到 `endpoint`
+    async fn post_to(&self, endpoint: &str, body: Bytes, beta: &str) -> Result<reqwest::Response> {
         let access_token = self.get_valid_token().await?;
+        let headers = build_headers(&access_token, beta)?;
 
-        // 伪装 tool 名称，绕过 Anthropic 检测
-        let request = tool_spoof::spoof(request);
-        // 先从原始 request 构建 headers（包含透传的 headers）
-        let headers = build_headers(&access_token, &request)?;
-        // 再处理 body（会移除内部字段）
-        let body = Self::ensure_stream_field(request, stream);
+        tracing::debug!(
+            provider = self.name,
+            anthropic_version = ANTHROPIC_API_VERSION,
+            anthropic_beta = beta,
+            "outbound headers"
+        );
+        record_sent_headers(SentHeaders {
+            anthropic_version: ANTHROPIC_API_VERSION.to_string(),
+            anthropic_beta: beta.to_string(),
+        });
 
         // 构建带有 beta=true 参数的 URL
-        let mut url = reqwest::Url::parse(ANTHROPIC_API_URL).context("Invalid API URL")?;
+        let mut url = reqwest::Url::parse(endpoint).context("Invalid API URL")?;
+        egress::check_url(&url)?;
         if !url.query_pairs().any(|(k, _)| k == "beta") {
             url.query_pairs_mut().append_pair("beta", "true");
         }
@@ -198,32 +584,73 @@ impl ClaudeCodeProvider {
         let response = get_api_client()
             .post(url)
             .headers(headers)
-            .json(&body)
+            .body(body)
             .send()
             .await
             .context("Failed to send request to Claude API")?;
 
-        // 提取 rate limit 信息（无论成功与否）
+        check_response_headers(response.headers())?;
+
+        // 提取 rate limit 信息并采集响应头（无论成功与否）
         self.update_rate_limit(response.headers());
+        self.response_headers.record(&self.name, response.headers());
 
         let status = response.status();
         if !status.is_success() {
             let error_body = response.text().await.unwrap_or_default();
-            anyhow::bail!("Claude API error {}: {}", status, error_body);
+            return Err(UpstreamError {
+                status,
+                body: error_body,
+            }
+            .into());
         }
 
         Ok(response)
     }
-}
 
-/// 从 SSE data 行解析 JSON
-fn parse_sse_data(line: &str) -> Option<Value> {
-    line.strip_prefix("data: ")
-        .and_then(|json_str| serde_json::from_str(json_str).ok())
+    /// 解析非流式响应并还原 tool name
+    ///
+    /// 响应体超过上限且配置为流式重发时，以流式重发并按上限收集
+    async fn read_message_or_stream(
+        &self,
+        response: reqwest::Response,
+        body: &[u8],
+        beta: &str,
+        model: &str,
+        options: Option<SpoofOptions>,
+    ) -> Result<Value> {
+        let resend = || async { self.post(with_stream(body)?, beta, model).await };
+        let mut message =
+            read_message_capped(&self.settings, &self.name, model, response, resend).await?;
+        if let Some(options) = options {
+            tool_spoof::restore(&mut message, options);
+        }
+        Ok(message)
+    }
+
+    /// 启动流式响应的转发任务
+    fn relay(
+        &self,
+        response: reqwest::Response,
+        model: String,
+        options: Option<SpoofOptions>,
+    ) -> StreamingResponse {
+        relay_response(
+            response,
+            self.name.clone(),
+            model,
+            self.settings.deprecations.clone(),
+            options,
+        )
+    }
 }
 
 #[async_trait]
 impl Provider for ClaudeCodeProvider {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
     fn name(&self) -> &str {
         &self.name
     }
@@ -233,36 +660,113 @@ impl Provider for ClaudeCodeProvider {
     }
 
     async fn send_message(&self, request: Value) -> Result<Value> {
-        let response = self.send_request(request, false).await?;
-        let mut response_json: Value = response
-            .json()
+        self.send_message_encoded(self.encode(request, false)?)
             .await
-            .context("Failed to parse Claude API response")?;
-
-        tool_spoof::restore(&mut response_json);
-        Ok(response_json)
     }
 
     async fn send_streaming(&self, request: Value) -> Result<StreamingResponse> {
+        self.send_streaming_encoded(self.encode(request, true)?)
+            .await
+    }
+
+    async fn send_message_raw(&self, body: Bytes, model: &str) -> Result<Value> {
+        let beta = build_beta_value(&Value::Null);
+        let response = self.post(body.clone(), &beta, model).await?;
+        self.read_message_or_stream(response, &body, &beta, model, Some(SpoofOptions::default()))
+            .await
+    }
+
+    async fn send_streaming_raw(&self, body: Bytes, model: &str) -> Result<StreamingResponse> {
+        let response = self.send_raw(body, model).await?;
+        Ok(self.relay(response, model.to_string(), Some(SpoofOptions::default())))
+    }
+
+    fn encode(&self, request: Value, stream: bool) -> Result<EncodedRequest> {
         let model = extract_model(&request);
-        let response = self.send_request(request, true).await?;
-        let status = response.status();
+        // 先从原始 request 计算 beta flags（包含透传的 headers）
+        let beta = build_beta_value(&request);
+        // 伪装 tool 名称，绕过 Anthropic 检测（灰度规则未选中时跳过）
+        let mut request = request;
+        let tools_spoofed = !skips_transform(&request, Transform::ToolSpoof)
+            && tool_spoof::spoof(&mut request, spoof_options(&beta));
+        // 再处理 body（会移除内部字段）
+        let body = Self::ensure_stream_field(request, stream);
+        let body = serde_json::to_vec(&body).context("Failed to serialize request body")?;
+        Ok(EncodedRequest {
+            body: Bytes::from(body),
+            beta,
+            model,
+            tools_spoofed,
+        })
+    }
 
-        let (tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(STREAM_CHANNEL_BUFFER);
-        let byte_stream = response.bytes_stream();
-        let provider_name = self.name.clone();
+    async fn send_message_encoded(&self, request: EncodedRequest) -> Result<Value> {
+        let (response, options) = self.send_request(&request).await?;
+        self.read_message_or_stream(
+            response,
+            &request.body,
+            &request.beta,
+            &request.model,
+            options,
+        )
+        .await
+    }
 
-        tokio::spawn(async move {
-            relay_stream(byte_stream, tx, &provider_name, &model).await;
-        });
+    async fn send_streaming_encoded(&self, request: EncodedRequest) -> Result<StreamingResponse> {
+        let (response, options) = self.send_request(&request).await?;
+        Ok(self.relay(response, request.model, options))
+    }
 
-        let stream = Box::new(tokio_stream::wrappers::ReceiverStream::new(rx));
-        Ok(StreamingResponse { stream, status })
+    /// 由后台刷新任务调用，token 即将过期时提前刷新
+    async fn refresh_credentials(&self) -> Result<bool> {
+        self.refresh_ahead().await
+    }
+
+    /// 发往 Messages 地址下的 `/count_tokens`，`[model_endpoints]` 同样按模型生效
+    async fn count_tokens(&self, request: Value) -> Result<Value> {
+        let model = extract_model(&request);
+        let endpoint = self
+            .settings
+            .endpoints
+            .resolve(&model)
+            .unwrap_or(&self.api_url);
+        let endpoint = format!("{}/count_tokens", endpoint.trim_end_matches('/'));
+        let (body, beta) = encode_count_tokens(request)?;
+        let response = self.post_to(&endpoint, body, &beta).await?;
+        let body = read_body_capped(response, self.settings.response_limit.max_bytes).await?;
+        serde_json::from_slice(&body).context("Failed to parse count_tokens response")
     }
 
     fn rate_limit_info(&self) -> Option<RateLimitInfo> {
         self.rate_limit.read().ok().map(|guard| guard.clone())
     }
+
+    fn captured_headers(&self) -> Option<CapturedHeaders> {
+        Some(self.response_headers.snapshot())
+    }
+
+    fn alerts(&self) -> Option<&AlertsConfig> {
+        self.alerts.as_ref()
+    }
+
+    fn schedule(&self) -> Option<&Schedule> {
+        self.schedule.as_ref()
+    }
+
+    fn weight(&self) -> u32 {
+        self.weight
+    }
+
+    fn missing_scopes(&self) -> &[String] {
+        &self.missing_scopes
+    }
+
+    fn needs_reauth(&self) -> Option<String> {
+        self.reauth
+            .read()
+            .ok()
+            .and_then(|reauth| reauth.as_ref().map(|r| r.error.clone()))
+    }
 }
 
 fn user_agent() -> String {
@@ -287,7 +791,24 @@ fn build_beta_value(data: &Value) -> String {
     flags.into_iter().collect::<Vec<_>>().join(",")
 }
 
-fn build_headers(access_token: &str, data: &Value) -> Result<HeaderMap> {
+/// 序列化 count_tokens 请求体，客户端透传的 `anthropic-beta` 与 Messages 请求一样合并
+fn encode_count_tokens(mut request: Value) -> Result<(Bytes, String)> {
+    let beta = build_beta_value(&request);
+    if let Some(obj) = request.as_object_mut() {
+        obj.remove("_passthrough_headers");
+    }
+    let body = serde_json::to_vec(&request).context("Failed to serialize request body")?;
+    Ok((Bytes::from(body), beta))
+}
+
+/// 按请求的 beta flags 决定 tool 名称伪装选项
+fn spoof_options(beta: &str) -> SpoofOptions {
+    SpoofOptions {
+        preserve_skill_tools: SKILLS_BETA.is_enabled(beta),
+    }
+}
+
+fn build_headers(access_token: &str, beta: &str) -> Result<HeaderMap> {
     let mut map = HeaderMap::new();
 
     // 使用 OAuth Bearer token 进行认证（不使用 x-api-key）
@@ -301,6 +822,10 @@ fn build_headers(access_token: &str, data: &Value) -> Result<HeaderMap> {
         HeaderValue::from_static("application/json"),
     );
     map.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
+    map.insert(
+        header::USER_AGENT,
+        HeaderValue::from_str(&user_agent()).context("Invalid user agent")?,
+    );
 
     map.insert(
         "anthropic-version",
@@ -309,51 +834,394 @@ fn build_headers(access_token: &str, data: &Value) -> Result<HeaderMap> {
 
     map.insert(
         "anthropic-beta",
-        HeaderValue::from_str(&build_beta_value(data)).context("Invalid beta flags")?,
+        HeaderValue::from_str(beta).context("Invalid beta flags")?,
     );
 
     Ok(map)
 }
 
+/// 从单个 SSE 事件中提取 usage、tool 调用与响应概要
+fn inspect_event(
+    data: &Value,
+    summary: &mut StreamSummary,
+    tool_calls: &mut Vec<String>,
+    shape: &mut ResponseShape,
+) {
+    let Some(event_type) = sse::event_type(data) else {
+        return;
+    };
+
+    // 未知事件按策略原样透传，这里只做分类计数
+    if sse::classify(event_type) == EventKind::Unknown {
+        return;
+    }
+
+    match event_type {
+        "message_start" => {
+            if let Some(msg) = data.get("message") {
+                shape.model = msg
+                    .get("model")
+                    .and_then(|m| m.as_str())
+                    .map(str::to_string);
+                match parse_anthropic_usage(msg) {
+                    Ok(parsed_usage) => summary.usage.merge_from(&parsed_usage),
+                    Err(e) => tracing::debug!("ignoring message_start usage: {:#}", e),
+                }
+            }
+        }
+        "message_delta" => {
+            match parse_delta_usage(data) {
+                Ok(parsed_usage) => summary.usage.merge_from(&parsed_usage),
+                Err(e) => tracing::debug!("ignoring message_delta usage: {:#}", e),
+            }
+            if let Some(reason) = data.pointer("/delta/stop_reason").and_then(|r| r.as_str()) {
+                shape.stop_reason = Some(reason.to_string());
+            }
+            if let Some(tokens) = data
+                .pointer("/usage/output_tokens")
+                .and_then(|t| t.as_u64())
+            {
+                shape.output_tokens = tokens;
+            }
+        }
+        "content_block_start" => {
+            let block = data.get("content_block");
+            let block_type = block.and_then(|b| b.get("type")).and_then(|t| t.as_str());
+            shape
+                .block_types
+                .push(block_type.unwrap_or_default().to_string());
+            if block_type == Some("tool_use") {
+                if let Some(name) = block.and_then(|b| b.get("name")).and_then(|n| n.as_str()) {
+                    tool_calls.push(name.to_string());
+                }
+                if let Some(id) = block.and_then(|b| b.get("id")).and_then(|i| i.as_str()) {
+                    summary.tool_use_ids.push(id.to_string());
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// 从响应头解析 rate limit 信息，缺少的字段为默认值
+pub(crate) fn parse_rate_limit(headers: &HeaderMap) -> RateLimitInfo {
+    let get_str = |name: &str| -> Option<&str> { headers.get(name).and_then(|v| v.to_str().ok()) };
+
+    let get_u64 = |name: &str| -> u64 { get_str(name).and_then(|s| s.parse().ok()).unwrap_or(0) };
+
+    let get_f64 = |name: &str| -> f64 { get_str(name).and_then(|s| s.parse().ok()).unwrap_or(0.0) };
+
+    RateLimitInfo {
+        five_hour: RateLimitWindow {
+            status: get_str("anthropic-ratelimit-unified-5h-status")
+                .unwrap_or_default()
+                .to_string(),
+            reset: get_u64("anthropic-ratelimit-unified-5h-reset"),
+            utilization: get_f64("anthropic-ratelimit-unified-5h-utilization"),
+        },
+        seven_day: RateLimitWindow {
+            status: get_str("anthropic-ratelimit-unified-7d-status")
+                .unwrap_or_default()
+                .to_string(),
+            reset: get_u64("anthropic-ratelimit-unified-7d-reset"),
+            utilization: get_f64("anthropic-ratelimit-unified-7d-utilization"),
+        },
+        updated_at: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+    }
+}
+
+/// 检查上游响应头大小，reqwest 未提供 HTTP/1 响应头大小限制，收到后检查
+pub(crate) fn check_response_headers(headers: &HeaderMap) -> Result<()> {
+    let header_size = header_list_size(headers);
+    let max_header_size = max_response_header_size();
+    if header_size > max_header_size {
+        anyhow::bail!(
+            "Upstream response headers too large: {header_size} bytes (limit {max_header_size})"
+        );
+    }
+    Ok(())
+}
+
+/// 把序列化好的请求体改为流式请求
+pub(crate) fn with_stream(body: &[u8]) -> Result<Bytes> {
+    let mut request: Value =
+        serde_json::from_slice(body).context("Failed to parse request body")?;
+    if let Some(obj) = request.as_object_mut() {
+        obj.insert("stream".to_string(), Value::Bool(true));
+    }
+    Ok(Bytes::from(serde_json::to_vec(&request)?))
+}
+
+/// 读取非流式响应，响应体超过上限时返回 [`ResponseTooLarge`]
+///
+/// 配置为流式重发时改由 `resend` 发送流式请求，收集不超过上限的事件并还原为 message
+pub(crate) async fn read_message_capped<F, Fut>(
+    settings: &ProviderSettings,
+    provider: &str,
+    model: &str,
+    response: reqwest::Response,
+    resend: F,
+) -> Result<Value>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<reqwest::Response>>,
+{
+    settings
+        .deprecations
+        .inspect_headers(provider, model, response.headers());
+    let limit = settings.response_limit.max_bytes;
+    let err = match read_body_capped(response, limit).await {
+        Ok(body) => {
+            let message: Value =
+                serde_json::from_slice(&body).context("Failed to parse Claude API response")?;
+            settings
+                .deprecations
+                .inspect_message(provider, model, &message);
+            return Ok(message);
+        }
+        Err(err) => err,
+    };
+    let Some(too_large) = err.downcast_ref::<ResponseTooLarge>() else {
+        return Err(err);
+    };
+    let as_stream = settings.response_limit.oversized == OversizedResponse::Stream;
+    tracing::warn!(
+        provider,
+        model,
+        limit = too_large.limit,
+        observed = too_large.observed,
+        behavior = if as_stream { "stream" } else { "fail" },
+        "upstream response body exceeds the limit"
+    );
+    if !as_stream {
+        return Err(err);
+    }
+
+    let response = resend().await?;
+    settings
+        .deprecations
+        .inspect_headers(provider, model, response.headers());
+    let (message, truncated) = collect_message(response, limit).await?;
+    settings
+        .deprecations
+        .inspect_message(provider, model, &message);
+    if truncated {
+        tracing::warn!(
+            provider,
+            model,
+            limit,
+            "streamed fallback reached the limit, returning a truncated message"
+        );
+    }
+    Ok(message)
+}
+
+/// 收集不超过 `limit` 字节的流式事件并还原为 message，返回 message 与是否被截断
+///
+/// 超过上限时停止读取，只保留已完成的内容块，`stop_reason` 为 `max_tokens`
+async fn collect_message(response: reqwest::Response, limit: u64) -> Result<(Value, bool)> {
+    let mut upstream = response.bytes_stream();
+    let mut assembler = sse::MessageAssembler::default();
+    let mut buffer = String::new();
+    let mut received: u64 = 0;
+    let mut truncated = false;
+    while let Some(chunk) = upstream.next().await {
+        let chunk = chunk.context("Failed to read Claude API stream")?;
+        received += chunk.len() as u64;
+        if received > limit {
+            truncated = true;
+            break;
+        }
+        buffer.push_str(&String::from_utf8_lossy(&chunk));
+        while let Some(pos) = buffer.find("\n\n") {
+            for data in buffer[..pos].lines().filter_map(sse::parse_data) {
+                if sse::event_type(&data) == Some("error") {
+                    anyhow::bail!("Claude API stream error: {data}");
+                }
+                assembler.push(&data);
+            }
+            buffer.drain(..pos + 2);
+        }
+    }
+
+    let message = assembler
+        .finish(truncated)
+        .context("Claude API stream ended before message_start")?;
+    Ok((message, truncated))
+}
+
+/// 原样转发上游的流式响应，不还原 tool 名称
+pub(crate) fn relay_unmodified(
+    response: reqwest::Response,
+    provider: String,
+    model: String,
+    deprecations: Arc<Deprecations>,
+) -> StreamingResponse {
+    relay_response(response, provider, model, deprecations, None)
+}
+
+/// 启动流式响应的转发任务，`options` 为 `None` 时不还原 tool 名称
+fn relay_response(
+    response: reqwest::Response,
+    provider: String,
+    model: String,
+    deprecations: Arc<Deprecations>,
+    options: Option<SpoofOptions>,
+) -> StreamingResponse {
+    let status = response.status();
+    deprecations.inspect_headers(&provider, &model, response.headers());
+    relay_byte_stream(
+        response.bytes_stream(),
+        status,
+        provider,
+        model,
+        deprecations,
+        options,
+    )
+}
+
+/// 转发已转换为 Anthropic SSE 事件的流式响应
+#[cfg_attr(not(feature = "openai-compat"), allow(dead_code))]
+pub(crate) fn relay_events(
+    events: impl Stream<Item = std::result::Result<Bytes, reqwest::Error>> + Send + 'static,
+    status: http::StatusCode,
+    provider: String,
+    model: String,
+    deprecations: Arc<Deprecations>,
+) -> StreamingResponse {
+    relay_byte_stream(events, status, provider, model, deprecations, None)
+}
+
+fn relay_byte_stream(
+    byte_stream: impl Stream<Item = std::result::Result<Bytes, reqwest::Error>> + Send + 'static,
+    status: http::StatusCode,
+    provider: String,
+    model: String,
+    deprecations: Arc<Deprecations>,
+    options: Option<SpoofOptions>,
+) -> StreamingResponse {
+    let (tx, rx) = mpsc::channel::<Result<Bytes, std::io::Error>>(STREAM_CHANNEL_BUFFER);
+    let (summary_tx, summary_rx) = oneshot::channel();
+
+    // 上游流式会话作为请求 span 的子 span
+    let span = tracing::info_span!("upstream_stream", ai.provider = %provider, ai.model = %model);
+    // 转发任务沿用请求的访问日志采样结果
+    let sampled = access_log::current();
+    stats::spawn(
+        TaskKind::StreamRelay,
+        access_log::scope(sampled, async move {
+            relay_stream(
+                byte_stream,
+                tx,
+                summary_tx,
+                &provider,
+                &model,
+                &deprecations,
+                options,
+            )
+            .await;
+        })
+        .instrument(span),
+    );
+
+    let stream = Box::new(tokio_stream::wrappers::ReceiverStream::new(rx));
+    StreamingResponse {
+        stream,
+        status,
+        summary: summary_rx,
+    }
+}
+
+fn restore_sse_event(event: &str, options: Option<SpoofOptions>) -> Cow<'_, str> {
+    match options {
+        Some(options) => tool_spoof::restore_sse_event(event, options),
+        None => Cow::Borrowed(event),
+    }
+}
+
+/// 读取响应体，超过 `limit` 时继续读完（不保存）以记录实际大小，并返回 [`ResponseTooLarge`]
+pub(crate) async fn read_body_capped(mut response: reqwest::Response, limit: u64) -> Result<Bytes> {
+    if let Some(length) = response.content_length().filter(|length| *length > limit) {
+        return Err(ResponseTooLarge {
+            limit,
+            observed: length,
+        }
+        .into());
+    }
+    let mut body = Vec::new();
+    let mut observed: u64 = 0;
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .context("Failed to read Claude API response")?
+    {
+        observed += chunk.len() as u64;
+        if observed <= limit {
+            body.extend_from_slice(&chunk);
+        } else if !body.is_empty() {
+            body = Vec::new();
+        }
+    }
+    if observed > limit {
+        return Err(ResponseTooLarge { limit, observed }.into());
+    }
+    Ok(Bytes::from(body))
+}
+
+/// 流未正常结束时发送概要，usage 只包含已转发的部分
+fn send_incomplete(summary_tx: oneshot::Sender<StreamSummary>, mut summary: StreamSummary) {
+    summary.incomplete = true;
+    let _ = summary_tx.send(summary);
+}
+
 async fn relay_stream(
     upstream: impl Stream<Item = std::result::Result<Bytes, reqwest::Error>>,
     tx: mpsc::Sender<std::result::Result<Bytes, std::io::Error>>,
+    summary_tx: oneshot::Sender<StreamSummary>,
     provider: &str,
     model: &str,
+    deprecations: &Deprecations,
+    options: Option<SpoofOptions>,
 ) {
     let mut buffer = String::new();
     let mut pinned = Box::pin(upstream);
-    let mut usage = Usage::default();
+    let mut summary = StreamSummary::default();
+    let mut tool_calls: Vec<String> = Vec::new();
+    let mut shape = ResponseShape::default();
+    let mut stream_stats = StreamStats::start();
+    let mut failure = None;
 
-    while let Some(chunk_result) = pinned.next().await {
+    loop {
+        // 上游空闲时也要察觉客户端断开，避免转发任务滞留
+        let chunk_result = tokio::select! {
+            chunk = pinned.next() => match chunk {
+                Some(chunk) => chunk,
+                None => break,
+            },
+            _ = tx.closed() => {
+                tracing::debug!("client disconnected");
+                return send_incomplete(summary_tx, summary);
+            }
+        };
         match chunk_result {
             Ok(chunk) => {
                 buffer.push_str(&String::from_utf8_lossy(&chunk));
 
                 while let Some(pos) = buffer.find("\n\n") {
-                    let event = &buffer[..pos];
                     // 还原 SSE 事件中的 tool 名称
-                    let event = tool_spoof::restore_text(event);
+                    let event = restore_sse_event(&buffer[..pos], options);
                     let event_with_newlines = format!("{}\n\n", event);
 
-                    // 解析 SSE 事件提取 usage
+                    // 解析 SSE 事件提取 usage 和 tool 调用
                     for line in event.lines() {
-                        if let Some(data) = parse_sse_data(line) {
-                            if let Some(event_type) = data.get("type").and_then(|t| t.as_str()) {
-                                match event_type {
-                                    "message_start" => {
-                                        if let Some(msg) = data.get("message") {
-                                            if let Ok(parsed_usage) = parse_anthropic_usage(msg) {
-                                                usage.merge_from(&parsed_usage);
-                                            }
-                                        }
-                                    }
-                                    "message_delta" => {
-                                        if let Ok(parsed_usage) = parse_anthropic_usage(&data) {
-                                            usage.merge_from(&parsed_usage);
-                                        }
-                                    }
-                                    _ => {}
+                        if let Some(data) = sse::parse_data(line) {
+                            inspect_event(&data, &mut summary, &mut tool_calls, &mut shape);
+                            if sse::event_type(&data) == Some("message_start") {
+                                if let Some(message) = data.get("message") {
+                                    deprecations.inspect_message(provider, model, message);
                                 }
                             }
                         }
@@ -361,34 +1229,504 @@ async fn relay_stream(
 
                     if tx.send(Ok(Bytes::from(event_with_newlines))).await.is_err() {
                         tracing::debug!("client disconnected");
-                        return;
+                        return send_incomplete(summary_tx, summary);
                     }
+                    stream_stats.observe(&tx);
 
                     buffer = buffer[pos + 2..].to_string();
                 }
             }
             Err(e) => {
-                tracing::error!("stream error: {e}");
-                let error_bytes = Bytes::from(format!("data: {{\"error\": \"{}\"}}\n\n", e));
-                let _ = tx.send(Ok(error_bytes)).await;
+                tracing::error!(provider, model, "stream error: {e}");
+                failure = Some(if e.is_timeout() {
+                    StreamFailure::IdleTimeout
+                } else {
+                    StreamFailure::UpstreamDisconnect
+                });
                 break;
             }
         }
     }
 
+    if let Some(failure) = failure {
+        // 先发送概要，流结束时即可计入已转发部分的 usage
+        send_incomplete(summary_tx, summary);
+        let _ = tx.send(Err(failure.into())).await;
+        return;
+    }
+
     if !buffer.is_empty() {
-        let buffer = tool_spoof::restore_text(&buffer);
+        let buffer = restore_sse_event(&buffer, options).into_owned();
         let _ = tx.send(Ok(Bytes::from(buffer))).await;
     }
 
     // 流结束时记录 usage
-    tracing::info!(
-        provider,
-        model,
-        input_tokens = usage.input_tokens,
-        output_tokens = usage.output_tokens,
-        cache_read = usage.cache_read_tokens,
-        cache_write = usage.cache_creation_tokens,
-        "stream completed"
-    );
+    let usage = &summary.usage;
+    if access_log::current() {
+        tracing::info!(
+            provider,
+            model,
+            input_tokens = usage.input_tokens,
+            output_tokens = usage.output_tokens,
+            cache_read = usage.cache_read_tokens,
+            cache_write = usage.cache_creation_tokens,
+            tool_calls = ?tool_calls,
+            channel_high_water = stream_stats.high_water(),
+            "stream completed"
+        );
+    }
+
+    summary.shape = shape;
+    let _ = summary_tx.send(summary);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::config::{ProviderConfig, DEFAULT_WEIGHT};
+    use serde_json::json;
+    use std::path::Path;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// 在 `dir` 中保存一个 token 已过期的 OAuth provider
+    async fn save_expired(dir: &Path, name: &str) -> ProviderConfig {
+        save_expiring(dir, name, 0).await
+    }
+
+    /// 在 `dir` 中保存一个 token 将在 `in_ms` 后过期（为 0 时已过期）的 OAuth provider
+    async fn save_expiring(dir: &Path, name: &str, in_ms: u64) -> ProviderConfig {
+        let expires_at = match in_ms {
+            0 => crate::utils::unix_timestamp_ms() - 60_000,
+            ms => crate::utils::unix_timestamp_ms() + ms,
+        };
+        let cfg = ProviderConfig {
+            id: config::new_provider_id(),
+            name: name.to_string(),
+            provider_type: ProviderType::ClaudeCode,
+            auth: AuthConfig::OAuth(OAuthConfig {
+                access_token: "old-access".to_string(),
+                refresh_token: "old-refresh".to_string(),
+                expires_at,
+                scopes: Vec::new(),
+            }),
+            alerts: None,
+            schedule: None,
+            weight: DEFAULT_WEIGHT,
+        };
+        config::save(dir, name, &cfg).await.unwrap();
+        cfg
+    }
+
+    /// 只接受 `old-refresh` 的 token 接口，返回新凭证
+    async fn token_endpoint(delay: Duration) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/oauth/token"))
+            .and(body_partial_json(json!({
+                "grant_type": "refresh_token",
+                "refresh_token": "old-refresh",
+            })))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({
+                        "access_token": "new-access",
+                        "refresh_token": "new-refresh",
+                        "expires_in": 3600,
+                    }))
+                    .set_delay(delay),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn provider(dir: &Path, cfg: &ProviderConfig, server: &MockServer) -> ClaudeCodeProvider {
+        provider_with(dir, cfg, server, ProviderSettings::default())
+    }
+
+    fn provider_with(
+        dir: &Path,
+        cfg: &ProviderConfig,
+        server: &MockServer,
+        settings: ProviderSettings,
+    ) -> ClaudeCodeProvider {
+        ClaudeCodeProvider::new(
+            dir.to_path_buf(),
+            cfg.id.clone(),
+            cfg.name.clone(),
+            Arc::new(settings),
+            None,
+            None,
+            Vec::new(),
+        )
+        .unwrap()
+        .with_token_url(format!("{}/v1/oauth/token", server.uri()))
+    }
+
+    /// 以 `status` 拒绝所有刷新请求的 token 接口
+    async fn failing_token_endpoint(status: u16) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/oauth/token"))
+            .respond_with(
+                ResponseTemplate::new(status).set_body_json(json!({"error": "invalid_request"})),
+            )
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn refresh(enabled: bool, min_interval: Duration) -> ProviderSettings {
+        ProviderSettings {
+            refresh: RefreshPolicy {
+                enabled,
+                min_interval,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// 最多重试 `max_retries` 次、基础间隔 1ms 的刷新设置
+    fn retrying(max_retries: u32) -> ProviderSettings {
+        ProviderSettings {
+            refresh: RefreshPolicy {
+                max_retries,
+                base_delay_ms: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn quiet_hours_only_refresh_tokens_about_to_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        // 10 分钟后过期：早于后台提前量，晚于静默时段的提前量
+        let cfg = save_expiring(dir.path(), "first", 10 * 60 * 1000).await;
+        let server = token_endpoint(Duration::ZERO).await;
+        let all_day = crate::quiet_hours::QuietHours {
+            hours: vec![(0, 24)],
+            days: Vec::new(),
+        };
+        let settings = ProviderSettings {
+            quiet_hours: all_day,
+            ..Default::default()
+        };
+        let quiet = provider_with(dir.path(), &cfg, &server, settings);
+        assert!(!quiet.refresh_ahead().await.unwrap());
+        assert!(server.received_requests().await.unwrap().is_empty());
+
+        let provider = provider(dir.path(), &cfg, &server);
+        assert!(provider.refresh_ahead().await.unwrap());
+        assert_eq!(provider.get_valid_token().await.unwrap(), "new-access");
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = save_expired(dir.path(), "first").await;
+        let server = token_endpoint(Duration::ZERO).await;
+        Mock::given(method("POST"))
+            .and(path("/v1/oauth/token"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        let provider = provider_with(dir.path(), &cfg, &server, retrying(3));
+
+        let oauth = provider.refresh_with_retry("old-refresh").await.unwrap();
+        assert_eq!(oauth.access_token, "new-access");
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn retries_stop_at_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = save_expired(dir.path(), "first").await;
+        let server = failing_token_endpoint(502).await;
+        let provider = provider_with(dir.path(), &cfg, &server, retrying(2));
+
+        let err = provider
+            .refresh_with_retry("old-refresh")
+            .await
+            .unwrap_err();
+        assert!(oauth::is_transient(&err));
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = save_expired(dir.path(), "first").await;
+        let server = failing_token_endpoint(400).await;
+        let provider = provider_with(dir.path(), &cfg, &server, retrying(3));
+
+        let err = provider
+            .refresh_with_retry("old-refresh")
+            .await
+            .unwrap_err();
+        assert!(!oauth::is_transient(&err));
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn connection_failures_are_retried() {
+        // 先占用再释放一个端口，token 接口稍后才在该端口上启动
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = save_expired(dir.path(), "first").await;
+        let settings = ProviderSettings {
+            refresh: RefreshPolicy {
+                max_retries: 8,
+                base_delay_ms: 50,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let provider = ClaudeCodeProvider::new(
+            dir.path().to_path_buf(),
+            cfg.id.clone(),
+            cfg.name.clone(),
+            Arc::new(settings),
+            None,
+            None,
+            Vec::new(),
+        )
+        .unwrap()
+        .with_token_url(format!("http://{addr}/v1/oauth/token"));
+
+        let start_server = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let listener = std::net::TcpListener::bind(addr).unwrap();
+            let server = MockServer::builder().listener(listener).start().await;
+            Mock::given(method("POST"))
+                .and(path("/v1/oauth/token"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "access_token": "new-access",
+                    "refresh_token": "new-refresh",
+                    "expires_in": 3600,
+                })))
+                .mount(&server)
+                .await;
+            server
+        };
+        let (result, _server) =
+            tokio::join!(provider.refresh_with_retry("old-refresh"), start_server);
+        assert_eq!(result.unwrap().access_token, "new-access");
+    }
+
+    #[test]
+    fn backoff_ceiling_doubles_and_saturates() {
+        let policy = RefreshPolicy::default();
+        let ceilings: Vec<u64> = (0..4).map(|n| policy.delay_ceiling_ms(n)).collect();
+        assert_eq!(ceilings, [500, 1000, 2000, 4000]);
+        assert_eq!(policy.delay_ceiling_ms(16), policy.delay_ceiling_ms(40));
+        let huge = RefreshPolicy {
+            base_delay_ms: u64::MAX / 2,
+            ..Default::default()
+        };
+        assert_eq!(huge.delay_ceiling_ms(3), u64::MAX);
+        let zero = RefreshPolicy {
+            base_delay_ms: 0,
+            ..Default::default()
+        };
+        assert_eq!(zero.delay_ceiling_ms(5), 0);
+    }
+
+    #[tokio::test]
+    async fn failed_refresh_is_throttled() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = save_expired(dir.path(), "first").await;
+        let server = failing_token_endpoint(400).await;
+        let settings = refresh(true, Duration::from_secs(60));
+        let provider = provider_with(dir.path(), &cfg, &server, settings);
+
+        assert!(provider.get_valid_token().await.is_err());
+        // 最短间隔内不再请求 token 接口，直接报告下次尝试的时间
+        let err = provider.get_valid_token().await.unwrap_err();
+        assert!(format!("{err:#}").contains("less than 60s ago"), "{err:#}");
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn refresh_without_min_interval_retries_immediately() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = save_expired(dir.path(), "first").await;
+        let server = failing_token_endpoint(400).await;
+        let provider = provider_with(dir.path(), &cfg, &server, refresh(true, Duration::ZERO));
+
+        assert!(provider.get_valid_token().await.is_err());
+        assert!(provider.get_valid_token().await.is_err());
+        assert_eq!(server.received_requests().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn shared_mode_leaves_refreshing_to_the_lock_holder() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = save_expired(dir.path(), "first").await;
+        let server = failing_token_endpoint(400).await;
+        let settings = refresh(false, Duration::from_secs(60));
+        let provider = provider_with(dir.path(), &cfg, &server, settings);
+
+        let err = provider.get_valid_token().await.unwrap_err();
+        assert!(format!("{err:#}").contains("--allow-shared"), "{err:#}");
+        assert!(!provider.refresh_ahead().await.unwrap());
+        assert!(server.received_requests().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_refresh_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = save_expired(dir.path(), "first").await;
+        // 刷新较慢，保证所有请求都在刷新完成前到达
+        let server = token_endpoint(Duration::from_millis(200)).await;
+        let provider = provider(dir.path(), &cfg, &server);
+
+        let tokens = futures::future::join_all((0..16).map(|_| provider.get_valid_token())).await;
+        for token in tokens {
+            assert_eq!(token.unwrap(), "new-access");
+        }
+        // 轮换后的 refresh token 已写回配置文件
+        let saved = config::load_by_name(dir.path(), "first", false)
+            .await
+            .unwrap();
+        let AuthConfig::OAuth(oauth) = saved.auth else {
+            panic!("not an OAuth provider");
+        };
+        assert_eq!(oauth.refresh_token, "new-refresh");
+        // 退出时校验 token 接口恰好被调用一次
+        server.verify().await;
+    }
+
+    #[test]
+    fn count_tokens_merges_the_client_beta() {
+        let request = json!({
+            "model": "claude-test",
+            "messages": [],
+            "_passthrough_headers": {"anthropic-beta": "context-1m-2025-08-07, oauth-2025-04-20"},
+        });
+        let (body, beta) = encode_count_tokens(request).unwrap();
+        let flags: Vec<&str> = beta.split(',').collect();
+        assert!(flags.contains(&"context-1m-2025-08-07"));
+        for base in BETA_FLAGS_BASE {
+            assert_eq!(flags.iter().filter(|f| *f == base).count(), 1, "{base}");
+        }
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!({"model": "claude-test", "messages": []}));
+
+        let (_, beta) = encode_count_tokens(json!({"model": "claude-test"})).unwrap();
+        assert_eq!(beta, BETA_FLAGS_BASE.join(","));
+    }
+
+    #[tokio::test]
+    async fn refresh_follows_a_renamed_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let cfg = save_expired(dir.path(), "first").await;
+        let server = token_endpoint(Duration::ZERO).await;
+        let provider = provider(dir.path(), &cfg, &server);
+
+        std::fs::rename(
+            dir.path().join("first.toml"),
+            dir.path().join("renamed.toml"),
+        )
+        .unwrap();
+        assert_eq!(provider.get_valid_token().await.unwrap(), "new-access");
+
+        // 新凭证写入改名后的文件，不会按旧名称新建文件
+        assert!(!dir.path().join("first.toml").exists());
+        let saved = config::load_by_name(dir.path(), "renamed", false)
+            .await
+            .unwrap();
+        assert_eq!(saved.id, cfg.id);
+        let AuthConfig::OAuth(oauth) = saved.auth else {
+            panic!("not an OAuth provider");
+        };
+        assert_eq!(oauth.refresh_token, "new-refresh");
+        server.verify().await;
+    }
+
+    #[tokio::test]
+    async fn relay_passes_ping_and_unknown_events_through_untouched() {
+        let mut events = crate::test_support::message_events("hi");
+        events.insert(1, json!({"type": "ping"}));
+        events.insert(3, json!({"type": "content_block_meta", "index": 0}));
+        let body = crate::test_support::sse(&events);
+        let chunks: Vec<std::result::Result<Bytes, reqwest::Error>> = body
+            .as_bytes()
+            .chunks(23)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let response = relay_byte_stream(
+            futures::stream::iter(chunks),
+            http::StatusCode::OK,
+            "first".to_string(),
+            "claude-test".to_string(),
+            Arc::default(),
+            None,
+        );
+
+        let relayed: Vec<Bytes> = response.stream.map(|chunk| chunk.unwrap()).collect().await;
+        assert_eq!(String::from_utf8(relayed.concat()).unwrap(), body);
+        let summary = response.summary.await.unwrap();
+        assert!(!summary.incomplete);
+        assert_eq!(summary.shape.stop_reason.as_deref(), Some("end_turn"));
+    }
+
+    #[tokio::test]
+    async fn streamed_tool_use_names_are_restored() {
+        let events = [
+            json!({"type": "message_start", "message": {
+                "id": "msg_test", "type": "message", "role": "assistant", "model": "claude-test",
+                "content": [], "usage": {"input_tokens": 10, "output_tokens": 1}
+            }}),
+            json!({"type": "content_block_start", "index": 0, "content_block": {
+                "type": "tool_use", "id": "toolu_1", "name": "mcp_weather", "input": {}
+            }}),
+            json!({"type": "content_block_stop", "index": 0}),
+            json!({"type": "content_block_start", "index": 1, "content_block": {
+                "type": "tool_use", "id": "toolu_2", "name": "Bash", "input": {}
+            }}),
+            json!({"type": "content_block_stop", "index": 1}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "tool_use"}, "usage": {"output_tokens": 7}}),
+            json!({"type": "message_stop"}),
+        ];
+        let body = crate::test_support::sse(&events);
+        // 事件跨 chunk 切分
+        let chunks: Vec<std::result::Result<Bytes, reqwest::Error>> = body
+            .as_bytes()
+            .chunks(37)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let response = relay_byte_stream(
+            futures::stream::iter(chunks),
+            http::StatusCode::OK,
+            "first".to_string(),
+            "claude-test".to_string(),
+            Arc::default(),
+            Some(SpoofOptions::default()),
+        );
+
+        let relayed: Vec<Bytes> = response.stream.map(|chunk| chunk.unwrap()).collect().await;
+        let relayed = String::from_utf8(relayed.concat()).unwrap();
+        let names: Vec<String> = relayed
+            .lines()
+            .filter_map(sse::parse_data)
+            .filter(|data| data["type"] == "content_block_start")
+            .map(|data| data["content_block"]["name"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(names, ["weather", "bash"]);
+        // 其余事件原样转发
+        assert!(relayed.contains(&format!("data: {}\n\n", events[5])));
+
+        let summary = response.summary.await.unwrap();
+        assert!(!summary.incomplete);
+        assert_eq!(summary.tool_use_ids, ["toolu_1", "toolu_2"]);
+        assert_eq!(summary.shape.stop_reason.as_deref(), Some("tool_use"));
+    }
 }
@@ -4,74 +4,108 @@ use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::io::{self, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use crate::commands::output::Output;
 use crate::providers::OAuthConfig;
 use crate::utils::unix_timestamp_ms;
 
 use super::constants::{
-    generate_random_base64url, PkceChallenge, CLAUDE_CODE_OAUTH_AUTHORIZE_URL,
-    CLAUDE_CODE_OAUTH_CLIENT_ID, CLAUDE_CODE_OAUTH_REDIRECT_URI, CLAUDE_CODE_OAUTH_SCOPES,
-    CLAUDE_CODE_OAUTH_TOKEN_URL,
+    generate_random_base64url, local_callback_uri, oauth_callback_port, oauth_redirect_uri,
+    PkceChallenge, CLAUDE_CODE_OAUTH_AUTHORIZE_URL, CLAUDE_CODE_OAUTH_CLIENT_ID,
+    CLAUDE_CODE_OAUTH_SCOPES, CLAUDE_CODE_OAUTH_TOKEN_URL, OAUTH_LOCAL_CALLBACK_PATH,
 };
 
-/// 登录会话缓存，用于调试时复用同一个授权 URL
+/// 登录状态文件名，位于 providers 目录
+const LOGIN_STATE_FILE: &str = ".login-state.json";
+
+/// 待完成的登录，在打开授权 URL 与输入授权码之间保存到磁盘
+///
+/// 进程在浏览器授权后中断时，可通过 `pluribus login --resume` 只输入授权码完成登录
 #[derive(Debug, Clone, Serialize, Deserialize)]
-struct OAuthLoginCache {
+pub struct PendingLogin {
+    /// 登录完成后保存的 provider 名称
+    pub provider: String,
     /// PKCE verifier
     verifier: String,
-    /// PKCE challenge
-    challenge: String,
     /// OAuth state (独立于 verifier)
     state: String,
+    /// 生成授权 URL 时使用的 redirect URI
+    redirect_uri: String,
     /// 完整的授权 URL
-    authorize_url: String,
-    /// 缓存创建时间
+    pub authorize_url: String,
+    /// 创建时间 (Unix timestamp 毫秒)
     created_at: u64,
 }
 
-impl OAuthLoginCache {
-    /// 缓存有效期：1 小时
-    const CACHE_TTL_MS: u64 = 3600 * 1000;
-
-    fn cache_path() -> PathBuf {
-        let cache_dir = dirs::cache_dir()
-            .unwrap_or_else(|| PathBuf::from("."))
-            .join("pluribus");
-        std::fs::create_dir_all(&cache_dir).ok();
-        cache_dir.join("oauth_login_cache.json")
-    }
-
-    fn load() -> Option<Self> {
-        let path = Self::cache_path();
-        let content = std::fs::read_to_string(&path).ok()?;
-        let cache: Self = serde_json::from_str(&content).ok()?;
-
-        // 检查缓存是否过期
-        let now = unix_timestamp_ms();
-        if now > cache.created_at + Self::CACHE_TTL_MS {
-            tracing::info!("OAuth login cache expired, will generate new session");
-            Self::clear();
-            return None;
-        }
+impl PendingLogin {
+    /// 有效期：10 分钟
+    const TTL_MS: u64 = 10 * 60 * 1000;
+
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(LOGIN_STATE_FILE)
+    }
+
+    pub fn redirect_uri(&self) -> &str {
+        &self.redirect_uri
+    }
 
-        tracing::info!("Loaded OAuth login cache from {:?}", path);
-        Some(cache)
+    fn is_expired(&self, now_ms: u64) -> bool {
+        now_ms > self.created_at + Self::TTL_MS
     }
 
-    fn save(&self) -> Result<()> {
-        let path = Self::cache_path();
-        let content = serde_json::to_string_pretty(self)?;
-        std::fs::write(&path, content)?;
-        tracing::info!("Saved OAuth login cache to {:?}", path);
+    /// 距离过期的剩余时间
+    fn remaining(&self, now_ms: u64) -> std::time::Duration {
+        std::time::Duration::from_millis((self.created_at + Self::TTL_MS).saturating_sub(now_ms))
+    }
+
+    /// 读取待完成的登录，没有时返回 `None`；已过期的状态文件会被删除并返回错误
+    pub fn load(dir: &Path) -> Result<Option<Self>> {
+        let path = Self::path(dir);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let pending: Self = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        if pending.is_expired(unix_timestamp_ms()) {
+            Self::clear(dir);
+            bail!(
+                "Pending login for {} expired after 10 minutes, start a new login",
+                pending.provider
+            );
+        }
+        Ok(Some(pending))
+    }
+
+    /// 以仅所有者可读写的权限保存，文件中含有 PKCE verifier
+    fn save(&self, dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = Self::path(dir);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options
+            .open(&path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        tracing::debug!("Saved pending login to {}", path.display());
         Ok(())
     }
 
-    fn clear() {
-        let path = Self::cache_path();
-        if path.exists() {
-            std::fs::remove_file(&path).ok();
-            tracing::info!("Cleared OAuth login cache");
+    /// 删除状态文件
+    pub fn clear(dir: &Path) {
+        let path = Self::path(dir);
+        match std::fs::remove_file(&path) {
+            Ok(()) => tracing::debug!("Removed {}", path.display()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Failed to remove {}: {}", path.display(), e),
         }
     }
 }
@@ -82,11 +116,13 @@ impl OAuthLoginCache {
 ///
 /// # 参数
 ///
+/// * `token_url` - OAuth token 接口，通常为 [`CLAUDE_CODE_OAUTH_TOKEN_URL`]
 /// * `code` - 授权码
 /// * `verifier` - PKCE verifier
 /// * `state` - OAuth state
 /// * `redirect_uri` - 重定向 URI
 pub async fn exchange_code(
+    token_url: &str,
     code: &str,
     verifier: &str,
     state: &str,
@@ -101,7 +137,7 @@ pub async fn exchange_code(
         "state": state,
     });
 
-    let response = token_request(&body).await?;
+    let response = token_request(token_url, &body).await?;
     parse_token_response(&response)
 }
 
@@ -109,8 +145,9 @@ pub async fn exchange_code(
 ///
 /// # 参数
 ///
+/// * `token_url` - OAuth token 接口，通常为 [`CLAUDE_CODE_OAUTH_TOKEN_URL`]
 /// * `refresh_token` - Refresh token
-pub async fn refresh_token(refresh_token: &str) -> Result<OAuthConfig> {
+pub async fn refresh_token(token_url: &str, refresh_token: &str) -> Result<OAuthConfig> {
     tracing::info!("Refreshing OAuth access token");
 
     let body = json!({
@@ -120,14 +157,58 @@ pub async fn refresh_token(refresh_token: &str) -> Result<OAuthConfig> {
         "scope": CLAUDE_CODE_OAUTH_SCOPES.join(" "),
     });
 
-    let response = token_request(&body).await?;
+    let response = token_request(token_url, &body).await?;
     parse_token_response(&response)
 }
 
+/// OAuth 接口返回的非 2xx 错误
+#[derive(Debug)]
+pub struct OAuthApiError {
+    pub status: reqwest::StatusCode,
+    pub body: String,
+}
+
+impl std::fmt::Display for OAuthApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "OAuth API error (HTTP {}): {}",
+            self.status.as_u16(),
+            self.body
+        )
+    }
+}
+
+impl std::error::Error for OAuthApiError {}
+
+/// 刷新失败是否可能是暂时的：OAuth 接口返回 5xx，或连接失败、超时
+///
+/// 4xx（如 refresh token 失效）重试也不会成功
+pub fn is_transient(err: &anyhow::Error) -> bool {
+    if let Some(api_error) = err.downcast_ref::<OAuthApiError>() {
+        return api_error.status.is_server_error();
+    }
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        .any(|e| e.is_timeout() || e.is_connect())
+}
+
+/// 刷新失败是否因为 refresh token 已失效（`invalid_grant`），需要重新登录
+///
+/// 在其他客户端登录同一账号后，旧的 refresh token 会被作废
+pub fn is_invalid_grant(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<OAuthApiError>()
+        .is_some_and(|api_error| {
+            api_error.status.is_client_error()
+                && serde_json::from_str::<serde_json::Value>(&api_error.body)
+                    .is_ok_and(|body| body["error"] == "invalid_grant")
+        })
+}
+
 /// 发送 token 请求（使用 JSON 格式）
-async fn token_request(body: &serde_json::Value) -> Result<serde_json::Value> {
+async fn token_request(url: &str, body: &serde_json::Value) -> Result<serde_json::Value> {
     let response = crate::utils::get_shared_client()
-        .post(CLAUDE_CODE_OAUTH_TOKEN_URL)
+        .post(url)
         .header("Content-Type", "application/json")
         .json(body)
         .send()
@@ -137,7 +218,7 @@ async fn token_request(body: &serde_json::Value) -> Result<serde_json::Value> {
     if !response.status().is_success() {
         let status = response.status();
         let body = response.text().await.unwrap_or_default();
-        bail!("OAuth API error (HTTP {}): {}", status.as_u16(), body);
+        return Err(OAuthApiError { status, body }.into());
     }
 
     response
@@ -176,96 +257,310 @@ fn parse_token_response(json: &serde_json::Value) -> Result<OAuthConfig> {
 }
 
 /// 从标准输入读取授权码
-fn read_authorization_code() -> Result<String> {
-    print!("Enter authorization code: ");
-    io::stdout().flush()?;
-
-    let mut code = String::new();
-    io::stdin().read_line(&mut code)?;
-
-    // 处理可能包含 state 的授权码格式（如 `code#state`）
-    let code = code
-        .trim()
-        .split('#')
-        .next()
-        .unwrap_or("")
-        .trim()
-        .to_string();
+///
+/// 授权码可带 `#state` 后缀，此时 state 须与待完成的登录一致；标准输入已关闭时返回 `None`
+fn read_authorization_code(output: &Output, expected_state: &str) -> Result<Option<String>> {
+    output.prompt("Enter authorization code")?;
 
+    let mut input = String::new();
+    if io::stdin().read_line(&mut input)? == 0 {
+        return Ok(None);
+    }
+    parse_authorization_code(&input, expected_state).map(Some)
+}
+
+/// 解析输入的授权码，带 `#state` 后缀时校验 state
+///
+/// 也可以粘贴浏览器跳转到的完整回调地址（如本地回调服务器未运行时的 `http://localhost:{port}/callback?code=...&state=...`）
+fn parse_authorization_code(input: &str, expected_state: &str) -> Result<String> {
+    let input = input.trim();
+    if let Some(query) = reqwest::Url::parse(input)
+        .ok()
+        .filter(|url| url.query().is_some())
+    {
+        let param = |name: &str| {
+            query
+                .query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+        let code = param("code").unwrap_or_default();
+        let state = param("state").unwrap_or_default();
+        return parse_authorization_code(&format!("{code}#{state}"), expected_state);
+    }
+    let (code, state) = match input.split_once('#') {
+        Some((code, state)) => (code.trim(), Some(state.trim())),
+        None => (input, None),
+    };
     if code.is_empty() {
         bail!("Authorization code cannot be empty");
     }
+    if state.is_some_and(|state| state != expected_state) {
+        bail!("Authorization code belongs to a different login attempt");
+    }
+
+    Ok(code.to_string())
+}
+
+/// 本地回调服务器：接收浏览器跳转到 `http://localhost:{port}/callback` 时带回的授权码
+pub struct CallbackListener {
+    listener: tokio::net::TcpListener,
+    port: u16,
+}
+
+/// 一次回调请求的结果
+enum Callback {
+    Code(String),
+    /// 用户拒绝授权，或授权服务器返回错误
+    Denied(String),
+    /// 授权码缺失或属于另一次登录
+    Invalid(String),
+    NotFound,
+}
+
+impl CallbackListener {
+    /// 按 `PLURIBUS_OAUTH_CALLBACK_PORT` 在 localhost 上监听，未设置时返回 `None`
+    pub async fn bind() -> Result<Option<Self>> {
+        match oauth_callback_port()? {
+            Some(port) => Self::bind_port(port).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    async fn bind_port(port: u16) -> Result<Self> {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", port))
+            .await
+            .with_context(|| {
+                format!("Failed to listen on localhost:{port} for the OAuth callback")
+            })?;
+        let port = listener.local_addr()?.port();
+        Ok(Self { listener, port })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn redirect_uri(&self) -> String {
+        local_callback_uri(self.port)
+    }
+
+    /// 等待带回授权码的回调
+    ///
+    /// 其他路径返回 404，授权码无效的回调返回 400 并继续等待；用户拒绝授权时返回错误
+    async fn wait(&self, expected_state: &str) -> Result<String> {
+        use tokio::io::AsyncWriteExt;
+
+        loop {
+            let (mut stream, _) = self.listener.accept().await?;
+            let target = match read_request_target(&mut stream).await {
+                Ok(target) => target,
+                Err(e) => {
+                    tracing::debug!("Ignoring malformed OAuth callback request: {:#}", e);
+                    continue;
+                }
+            };
+            let callback = parse_callback(&target, expected_state);
+            let (status, message) = match &callback {
+                Callback::Code(_) => (
+                    "200 OK",
+                    "Login complete, you can close this window and return to the terminal.",
+                ),
+                Callback::Denied(_) => ("400 Bad Request", "Authorization was denied."),
+                Callback::Invalid(_) => ("400 Bad Request", "Invalid authorization callback."),
+                Callback::NotFound => ("404 Not Found", "Not found."),
+            };
+            let response = format!(
+                "HTTP/1.1 {status}\r\ncontent-type: text/plain; charset=utf-8\r\n\
+                 content-length: {}\r\nconnection: close\r\n\r\n{message}",
+                message.len()
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+            let _ = stream.shutdown().await;
+
+            match callback {
+                Callback::Code(code) => return Ok(code),
+                Callback::Denied(error) => bail!("Authorization denied: {error}"),
+                Callback::Invalid(error) => {
+                    tracing::warn!("Ignoring OAuth callback: {}", error);
+                }
+                Callback::NotFound => {}
+            }
+        }
+    }
+}
+
+/// 读取 HTTP 请求头，返回请求行中的路径与查询参数
+async fn read_request_target(stream: &mut tokio::net::TcpStream) -> Result<String> {
+    use tokio::io::AsyncReadExt;
+
+    /// 请求头的大小上限
+    const MAX_HEAD_BYTES: usize = 8 * 1024;
+
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+        if head.len() > MAX_HEAD_BYTES {
+            bail!("Request head exceeds {MAX_HEAD_BYTES} bytes");
+        }
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(target)) => Ok(target.to_string()),
+        _ => bail!("Unexpected request line"),
+    }
+}
 
-    Ok(code)
+/// 解析回调请求的路径与查询参数
+fn parse_callback(target: &str, expected_state: &str) -> Callback {
+    let Ok(url) = reqwest::Url::parse(&format!("http://localhost{target}")) else {
+        return Callback::NotFound;
+    };
+    if url.path() != OAUTH_LOCAL_CALLBACK_PATH {
+        return Callback::NotFound;
+    }
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    if let Some(error) = param("error") {
+        return match param("error_description") {
+            Some(description) => Callback::Denied(format!("{error} ({description})")),
+            None => Callback::Denied(error),
+        };
+    }
+    let code = param("code").unwrap_or_default();
+    let state = param("state").unwrap_or_default();
+    match parse_authorization_code(&format!("{code}#{state}"), expected_state) {
+        Ok(code) => Callback::Code(code),
+        Err(e) => Callback::Invalid(e.to_string()),
+    }
 }
 
 /// 构建授权 URL
-fn build_authorize_url(challenge: &str, state: &str) -> String {
+fn build_authorize_url(challenge: &str, state: &str, redirect_uri: &str) -> String {
     let scopes = CLAUDE_CODE_OAUTH_SCOPES.join(" ");
     format!(
         "{}?client_id={}&redirect_uri={}&response_type=code&scope={}&state={}&code_challenge={}&code_challenge_method=S256",
         CLAUDE_CODE_OAUTH_AUTHORIZE_URL,
         CLAUDE_CODE_OAUTH_CLIENT_ID,
-        urlencoding::encode(CLAUDE_CODE_OAUTH_REDIRECT_URI),
+        urlencoding::encode(redirect_uri),
         urlencoding::encode(&scopes),
         urlencoding::encode(state),
         urlencoding::encode(challenge),
     )
 }
 
-/// 执行完整的 OAuth 登录流程
-///
-/// 打开浏览器进行授权，获取授权码，然后交换 token
-/// 支持无限次重试，直到成功或用户中断
+/// 开始 OAuth 登录：生成 PKCE 与 state，保存为待完成的登录
 ///
-/// 注意：登录会话会被缓存，下次登录时如果缓存有效会复用相同的 URL
-pub async fn perform_oauth_login() -> Result<OAuthConfig> {
+/// 有本地回调服务器时 redirect URI 默认指向它；已有待完成的登录时会被覆盖
+pub fn start_oauth_login(
+    dir: &Path,
+    provider: &str,
+    callback: Option<&CallbackListener>,
+) -> Result<PendingLogin> {
     tracing::info!("Starting OAuth login flow");
 
-    // 尝试加载缓存的登录会话
-    let (verifier, state, authorize_url) = if let Some(cache) = OAuthLoginCache::load() {
-        println!("Using cached OAuth session (delete cache to start fresh)");
-        println!("Cache file: {:?}\n", OAuthLoginCache::cache_path());
-        (cache.verifier, cache.state, cache.authorize_url)
-    } else {
-        // 生成新的 PKCE 和 state
-        let pkce = PkceChallenge::generate();
-        let state = generate_random_base64url();
-        let authorize_url = build_authorize_url(&pkce.challenge, &state);
-
-        // 缓存登录会话
-        let cache = OAuthLoginCache {
-            verifier: pkce.verifier.clone(),
-            challenge: pkce.challenge,
-            state: state.clone(),
-            authorize_url: authorize_url.clone(),
-            created_at: unix_timestamp_ms(),
-        };
-        if let Err(e) = cache.save() {
-            tracing::warn!("Failed to save OAuth login cache: {}", e);
-        }
-
-        (pkce.verifier, state, authorize_url)
+    let redirect_uri = oauth_redirect_uri(callback.map(CallbackListener::port));
+    tracing::info!("Using OAuth redirect URI: {}", redirect_uri);
+
+    let pkce = PkceChallenge::generate();
+    let state = generate_random_base64url();
+    let pending = PendingLogin {
+        provider: provider.to_string(),
+        authorize_url: build_authorize_url(&pkce.challenge, &state, &redirect_uri),
+        verifier: pkce.verifier,
+        state,
+        redirect_uri,
+        created_at: unix_timestamp_ms(),
     };
+    pending.save(dir)?;
+    Ok(pending)
+}
 
-    println!("Open the following URL in your browser to authorize:");
-    println!("{}\n", authorize_url);
+/// 完成 OAuth 登录：读取授权码并交换 token，成功后删除状态文件
+///
+/// redirect URI 指向 `callback` 时等待浏览器跳转带回授权码，否则从标准输入读取；
+/// 授权码无效时可重新输入，直到成功、状态过期或用户中断
+pub async fn complete_oauth_login(
+    dir: &Path,
+    pending: &PendingLogin,
+    callback: Option<CallbackListener>,
+    output: &Output,
+) -> Result<OAuthConfig> {
+    complete_login_with(
+        dir,
+        pending,
+        CLAUDE_CODE_OAUTH_TOKEN_URL,
+        callback,
+        |state| read_authorization_code(output, state),
+    )
+    .await
+}
 
-    loop {
-        let code = match read_authorization_code() {
-            Ok(code) => code,
-            Err(e) => {
-                eprintln!("Error: {}. Please try again.\n", e);
-                continue;
+/// [`complete_oauth_login`] 的实现，回调服务器之外的授权码由 `read_code` 读取
+async fn complete_login_with(
+    dir: &Path,
+    pending: &PendingLogin,
+    token_url: &str,
+    callback: Option<CallbackListener>,
+    mut read_code: impl FnMut(&str) -> Result<Option<String>>,
+) -> Result<OAuthConfig> {
+    let mut received = match callback.filter(|c| c.redirect_uri() == pending.redirect_uri) {
+        Some(callback) => {
+            let remaining = pending.remaining(unix_timestamp_ms());
+            match tokio::time::timeout(remaining, callback.wait(&pending.state)).await {
+                Ok(code) => Some(code?),
+                Err(_) => {
+                    PendingLogin::clear(dir);
+                    bail!("Login expired after 10 minutes, start a new login");
+                }
             }
+        }
+        None => None,
+    };
+
+    loop {
+        if pending.is_expired(unix_timestamp_ms()) {
+            PendingLogin::clear(dir);
+            bail!("Login expired after 10 minutes, start a new login");
+        }
+
+        // 回调服务器带回的授权码只使用一次，交换失败后改为手动输入
+        let code = match received.take() {
+            Some(code) => code,
+            None => match read_code(&pending.state) {
+                Ok(Some(code)) => code,
+                Ok(None) => bail!(
+                    "Login interrupted, run `pluribus login --resume` within 10 minutes to finish"
+                ),
+                Err(e) => {
+                    eprintln!("Error: {}. Please try again.\n", e);
+                    continue;
+                }
+            },
         };
 
         tracing::info!("Received authorization code");
 
-        match exchange_code(&code, &verifier, &state, CLAUDE_CODE_OAUTH_REDIRECT_URI).await {
+        match exchange_code(
+            token_url,
+            &code,
+            &pending.verifier,
+            &pending.state,
+            &pending.redirect_uri,
+        )
+        .await
+        {
             Ok(config) => {
-                // 登录成功，清除缓存
-                OAuthLoginCache::clear();
+                PendingLogin::clear(dir);
                 return Ok(config);
             }
             Err(e) => {
@@ -274,3 +569,239 @@ pub async fn perform_oauth_login() -> Result<OAuthConfig> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// 只接受 `pending` 对应授权码的 token 接口
+    async fn token_endpoint(pending: &PendingLogin) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/oauth/token"))
+            .and(body_partial_json(json!({
+                "grant_type": "authorization_code",
+                "code": "good-code",
+                "code_verifier": pending.verifier,
+                "state": pending.state,
+                "redirect_uri": pending.redirect_uri,
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "access",
+                "refresh_token": "refresh",
+                "expires_in": 3600,
+                "scope": "user:profile user:inference",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(400).set_body_string("invalid code"))
+            .mount(&server)
+            .await;
+        server
+    }
+
+    /// 依次返回 `inputs` 的授权码读取器，读完后视为标准输入已关闭
+    fn scripted(inputs: &[&str]) -> impl FnMut(&str) -> Result<Option<String>> {
+        let mut inputs: Vec<String> = inputs.iter().rev().map(|s| s.to_string()).collect();
+        move |state| {
+            inputs
+                .pop()
+                .map(|input| parse_authorization_code(&input, state))
+                .transpose()
+        }
+    }
+
+    #[tokio::test]
+    async fn interrupted_login_resumes_from_the_saved_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let pending = start_oauth_login(dir.path(), "work", None).unwrap();
+        assert!(pending.authorize_url.contains(&pending.state));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(PendingLogin::path(dir.path()))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let server = token_endpoint(&pending).await;
+        let token_url = format!("{}/v1/oauth/token", server.uri());
+
+        // 输入授权码前进程被中断
+        let err = complete_login_with(dir.path(), &pending, &token_url, None, scripted(&[]))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("pluribus login --resume"), "{err}");
+
+        // `--resume` 读取保存的状态，只需输入授权码
+        let resumed = PendingLogin::load(dir.path()).unwrap().unwrap();
+        assert_eq!(resumed.provider, "work");
+        assert_eq!(resumed.verifier, pending.verifier);
+        assert_eq!(resumed.authorize_url, pending.authorize_url);
+        let wrong_state = "good-code#another-login".to_string();
+        let with_state = format!("good-code#{}", resumed.state);
+        let inputs = ["bad-code", wrong_state.as_str(), with_state.as_str()];
+        let oauth = complete_login_with(dir.path(), &resumed, &token_url, None, scripted(&inputs))
+            .await
+            .unwrap();
+        assert_eq!(oauth.access_token, "access");
+        assert_eq!(oauth.scopes, ["user:profile", "user:inference"]);
+        server.verify().await;
+
+        // 完成后删除状态，不能再次继续
+        assert!(!PendingLogin::path(dir.path()).exists());
+        assert!(PendingLogin::load(dir.path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn expired_pending_logins_are_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut pending = start_oauth_login(dir.path(), "work", None).unwrap();
+        pending.created_at = unix_timestamp_ms() - PendingLogin::TTL_MS - 1;
+        pending.save(dir.path()).unwrap();
+
+        let err = PendingLogin::load(dir.path()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Pending login for work expired after 10 minutes, start a new login"
+        );
+        assert!(!PendingLogin::path(dir.path()).exists());
+    }
+
+    #[test]
+    fn authorization_codes_are_checked_against_the_login_state() {
+        assert_eq!(parse_authorization_code(" abc \n", "s1").unwrap(), "abc");
+        assert_eq!(parse_authorization_code("abc#s1\n", "s1").unwrap(), "abc");
+        let err = parse_authorization_code("abc#s2", "s1").unwrap_err();
+        assert!(err.to_string().contains("different login attempt"), "{err}");
+        assert!(parse_authorization_code("\n", "s1").is_err());
+    }
+
+    /// 模拟浏览器访问本地回调服务器，返回 (状态码, 响应体)
+    async fn visit(callback_port: u16, target: &str) -> (u16, String) {
+        let response = reqwest::get(format!("http://127.0.0.1:{callback_port}{target}"))
+            .await
+            .unwrap();
+        (response.status().as_u16(), response.text().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn local_callback_completes_the_login() {
+        let dir = tempfile::tempdir().unwrap();
+        let callback = CallbackListener::bind_port(0).await.unwrap();
+        let port = callback.port();
+        let pending = start_oauth_login(dir.path(), "work", Some(&callback)).unwrap();
+        assert_eq!(
+            pending.redirect_uri,
+            format!("http://localhost:{port}/callback")
+        );
+        assert!(pending
+            .authorize_url
+            .contains(&*urlencoding::encode(&pending.redirect_uri)));
+        let server = token_endpoint(&pending).await;
+        let token_url = format!("{}/v1/oauth/token", server.uri());
+
+        let state = pending.state.clone();
+        let browser = tokio::spawn(async move {
+            let not_found = visit(port, "/favicon.ico").await;
+            let other_login = visit(port, "/callback?code=good-code&state=other").await;
+            let redirect = visit(port, &format!("/callback?code=good-code&state={state}")).await;
+            (not_found, other_login, redirect)
+        });
+        let oauth = complete_login_with(
+            dir.path(),
+            &pending,
+            &token_url,
+            Some(callback),
+            scripted(&[]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(oauth.access_token, "access");
+        server.verify().await;
+        assert!(!PendingLogin::path(dir.path()).exists());
+
+        let (not_found, other_login, redirect) = browser.await.unwrap();
+        assert_eq!(not_found.0, 404);
+        assert_eq!(other_login.0, 400);
+        assert_eq!(redirect.0, 200);
+        assert!(redirect.1.starts_with("Login complete"), "{}", redirect.1);
+    }
+
+    #[tokio::test]
+    async fn rejected_callback_codes_fall_back_to_manual_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let callback = CallbackListener::bind_port(0).await.unwrap();
+        let port = callback.port();
+        let pending = start_oauth_login(dir.path(), "work", Some(&callback)).unwrap();
+        let server = token_endpoint(&pending).await;
+        let token_url = format!("{}/v1/oauth/token", server.uri());
+
+        let state = pending.state.clone();
+        let browser = tokio::spawn(async move {
+            visit(port, &format!("/callback?code=bad-code&state={state}")).await
+        });
+        // 回调带回的授权码交换失败后，粘贴浏览器地址栏中的回调地址
+        let pasted = format!(
+            "http://localhost:{port}/callback?code=good-code&state={}",
+            pending.state
+        );
+        let oauth = complete_login_with(
+            dir.path(),
+            &pending,
+            &token_url,
+            Some(callback),
+            scripted(&[pasted.as_str()]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(oauth.access_token, "access");
+        assert_eq!(browser.await.unwrap().0, 200);
+    }
+
+    #[tokio::test]
+    async fn denied_callback_fails_the_login() {
+        let dir = tempfile::tempdir().unwrap();
+        let callback = CallbackListener::bind_port(0).await.unwrap();
+        let port = callback.port();
+        let pending = start_oauth_login(dir.path(), "work", Some(&callback)).unwrap();
+
+        let browser = tokio::spawn(async move {
+            visit(
+                port,
+                "/callback?error=access_denied&error_description=User%20denied",
+            )
+            .await
+        });
+        let err = complete_login_with(
+            dir.path(),
+            &pending,
+            "http://127.0.0.1:9/unused",
+            Some(callback),
+            scripted(&[]),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Authorization denied: access_denied (User denied)"
+        );
+        assert_eq!(browser.await.unwrap().0, 400);
+    }
+
+    #[test]
+    fn pasted_callback_urls_are_parsed() {
+        let url = "http://localhost:54545/callback?code=abc&state=s1";
+        assert_eq!(parse_authorization_code(url, "s1").unwrap(), "abc");
+        let err = parse_authorization_code(url, "s2").unwrap_err();
+        assert!(err.to_string().contains("different login attempt"), "{err}");
+        let err =
+            parse_authorization_code("http://localhost:54545/callback?state=s1", "s1").unwrap_err();
+        assert_eq!(err.to_string(), "Authorization code cannot be empty");
+    }
+}
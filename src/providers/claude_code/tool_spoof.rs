@@ -1,8 +1,10 @@
 //! Tool 名称伪装模块
 //!
-//! 通过映射 tool 名称绕过 Claude Code 检测，响应时还原
+//! 通过映射 tool 名称绕过 Claude Code 检测，响应时还原。启用 skills beta 时，skill 相关的
+//! tool 名称由上游识别，不做伪装。
 
 use serde_json::Value;
+use std::borrow::Cow;
 
 /// 默认前缀
 const DEFAULT_PREFIX: &str = "mcp_";
@@ -23,21 +25,37 @@ const MAPPINGS: &[(&str, &str)] = &[
     ("skill", "Skill"),
 ];
 
-/// 伪装请求中的 tool 名称
+/// 启用 skills beta 时不伪装的 tool 名称
+const SKILL_TOOL_NAMES: &[&str] = &["skill", "Skill", "code_execution"];
+
+/// 伪装选项，请求与其响应使用同一份
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpoofOptions {
+    /// 保留 skill 相关的 tool 名称
+    pub preserve_skill_tools: bool,
+}
+
+impl SpoofOptions {
+    fn preserves(&self, name: &str) -> bool {
+        self.preserve_skill_tools && SKILL_TOOL_NAMES.contains(&name)
+    }
+}
+
+/// 伪装请求中的 tool 名称，返回是否有名称被改写
 ///
 /// 处理：
 /// 1. tools 数组中的 tool 定义
 /// 2. messages 中的 tool_use 块
-pub fn spoof(mut request: Value) -> Value {
-    let obj = match request.as_object_mut() {
-        Some(obj) => obj,
-        None => return request,
+pub fn spoof(request: &mut Value, options: SpoofOptions) -> bool {
+    let Some(obj) = request.as_object_mut() else {
+        return false;
     };
+    let mut changed = false;
 
     // 处理 tools 数组
     if let Some(tools) = obj.get_mut("tools").and_then(|t| t.as_array_mut()) {
         for tool in tools {
-            transform_name(tool, to_spoofed);
+            changed |= transform_name(tool, |name| to_spoofed(name, options));
         }
     }
 
@@ -47,20 +65,20 @@ pub fn spoof(mut request: Value) -> Value {
             if let Some(content) = msg.get_mut("content").and_then(|c| c.as_array_mut()) {
                 for block in content {
                     if is_tool_use_block(block) {
-                        transform_name(block, to_spoofed);
+                        changed |= transform_name(block, |name| to_spoofed(name, options));
                     }
                 }
             }
         }
     }
 
-    request
+    changed
 }
 
 /// 还原响应中的 tool 名称
 ///
 /// 处理 content 数组中的 tool_use 块
-pub fn restore(response: &mut Value) {
+pub fn restore(response: &mut Value, options: SpoofOptions) {
     let content = response
         .as_object_mut()
         .and_then(|obj| obj.get_mut("content"))
@@ -68,33 +86,59 @@ pub fn restore(response: &mut Value) {
 
     if let Some(content) = content {
         for item in content {
-            transform_name(item, to_original);
+            transform_name(item, |name| to_original(name, options));
         }
     }
 }
 
-/// 还原 SSE 文本中的 tool 名称
+/// 还原单个 SSE 事件中的 tool 名称
 ///
-/// 使用正则替换，适用于流式响应
-pub fn restore_text(text: &str) -> String {
-    let mut result = text.to_string();
+/// 流式响应中 tool 名称只出现在 `content_block_start` 事件的 `content_block.name`，
+/// 与非流式响应的 content 数组结构不同。仅改写这类事件，其余事件原样返回。
+pub fn restore_sse_event(event: &str, options: SpoofOptions) -> Cow<'_, str> {
+    if !event.contains("content_block_start") {
+        return Cow::Borrowed(event);
+    }
 
-    // 还原特殊映射
-    for (original, spoofed) in MAPPINGS {
-        let pattern = format!(r#""name"\s*:\s*"{}""#, regex::escape(spoofed));
-        let replacement = format!(r#""name": "{}""#, original);
-        if let Ok(re) = regex::Regex::new(&pattern) {
-            result = re.replace_all(&result, replacement.as_str()).to_string();
-        }
+    let mut changed = false;
+    let lines: Vec<Cow<'_, str>> = event
+        .split('\n')
+        .map(|line| match restore_data_line(line, options) {
+            Some(restored) => {
+                changed = true;
+                Cow::Owned(restored)
+            }
+            None => Cow::Borrowed(line),
+        })
+        .collect();
+
+    if changed {
+        Cow::Owned(lines.join("\n"))
+    } else {
+        Cow::Borrowed(event)
     }
+}
 
-    // 还原默认前缀
-    let prefix_pattern = format!(r#""name"\s*:\s*"{}([^"]+)""#, DEFAULT_PREFIX);
-    if let Ok(re) = regex::Regex::new(&prefix_pattern) {
-        result = re.replace_all(&result, r#""name": "$1""#).to_string();
+/// 还原 `content_block_start` 事件 data 行中的 tool_use 名称，无需改写时返回 `None`
+fn restore_data_line(line: &str, options: SpoofOptions) -> Option<String> {
+    let mut data: Value = serde_json::from_str(line.strip_prefix("data: ")?).ok()?;
+
+    if data.get("type").and_then(|t| t.as_str()) != Some("content_block_start") {
+        return None;
     }
 
-    result
+    let block = data.get_mut("content_block")?;
+    if !is_tool_use_block(block) {
+        return None;
+    }
+
+    let name = block.get("name").and_then(|n| n.as_str())?;
+    if to_original(name, options) == name {
+        return None;
+    }
+    transform_name(block, |name| to_original(name, options));
+
+    Some(format!("data: {}", serde_json::to_string(&data).ok()?))
 }
 
 /// 检查是否为 tool_use 块
@@ -102,23 +146,29 @@ fn is_tool_use_block(block: &Value) -> bool {
     block.get("type").and_then(|t| t.as_str()) == Some("tool_use")
 }
 
-/// 转换 name 字段
-fn transform_name(item: &mut Value, transformer: fn(&str) -> String) {
+/// 转换 name 字段，返回是否改写
+fn transform_name(item: &mut Value, transformer: impl Fn(&str) -> String) -> bool {
     let obj = match item.as_object_mut() {
         Some(obj) => obj,
-        None => return,
+        None => return false,
     };
 
     if let Some(name) = obj.get("name").and_then(|n| n.as_str()) {
         let new_name = transformer(name);
         if new_name != name {
             obj.insert("name".to_string(), Value::String(new_name));
+            return true;
         }
     }
+    false
 }
 
 /// 将原始名称转换为伪装名称
-fn to_spoofed(name: &str) -> String {
+fn to_spoofed(name: &str, options: SpoofOptions) -> String {
+    if options.preserves(name) {
+        return name.to_string();
+    }
+
     // 检查特殊映射
     for (original, spoofed) in MAPPINGS {
         if name == *original {
@@ -135,7 +185,11 @@ fn to_spoofed(name: &str) -> String {
 }
 
 /// 将伪装名称还原为原始名称
-fn to_original(name: &str) -> String {
+fn to_original(name: &str, options: SpoofOptions) -> String {
+    if options.preserves(name) {
+        return name.to_string();
+    }
+
     // 检查特殊映射
     for (original, spoofed) in MAPPINGS {
         if name == *spoofed {
@@ -148,3 +202,48 @@ fn to_original(name: &str) -> String {
         .unwrap_or(name)
         .to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn start_event(name: &str) -> String {
+        let data = json!({"type": "content_block_start", "index": 0, "content_block": {
+            "type": "tool_use", "id": "toolu_1", "name": name, "input": {}
+        }});
+        format!("event: content_block_start\ndata: {data}")
+    }
+
+    #[test]
+    fn sse_event_restores_only_tool_use_starts() {
+        let event = start_event("mcp_weather");
+        let restored = restore_sse_event(&event, SpoofOptions::default());
+        assert!(restored.contains(r#""name":"weather""#));
+
+        let delta = r#"data: {"type":"content_block_delta","index":0,"delta":{"type":"input_json_delta","partial_json":"mcp_x"}}"#;
+        assert!(matches!(
+            restore_sse_event(delta, SpoofOptions::default()),
+            Cow::Borrowed(_)
+        ));
+        let text = r#"data: {"type":"content_block_start","index":0,"content_block":{"type":"text","text":"mcp_"}}"#;
+        assert!(matches!(
+            restore_sse_event(text, SpoofOptions::default()),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn sse_event_keeps_skill_tools_with_the_skills_beta() {
+        let options = SpoofOptions {
+            preserve_skill_tools: true,
+        };
+        let event = start_event("Skill");
+        assert!(matches!(
+            restore_sse_event(&event, options),
+            Cow::Borrowed(_)
+        ));
+        let restored = restore_sse_event(&event, SpoofOptions::default());
+        assert!(restored.contains(r#""name":"skill""#));
+    }
+}
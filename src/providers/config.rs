@@ -2,12 +2,22 @@
 //!
 //! 包含所有 Provider 相关的类型定义和配置持久化逻辑
 //! TOML 格式: type + [oauth] 或 [api]
+//!
+//! 读写时对 `{name}.toml.lock` 加文件锁（读共享、写独占），避免 `serve` 与 `login`
+//! 等多个进程同时修改同一配置文件
+//!
+//! 每个 provider 有一个首次保存时生成、写入 TOML 的实例 ID，运行时状态按 ID 而不是名称
+//! 记录：改名（重命名文件）保留状态，删除后以同名重新创建则从头开始。缺少 ID 的旧配置
+//! 在首次加载时分配 ID 并写回。
 
 use anyhow::{Context, Result};
+use fs2::FileExt;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use tokio::fs;
 
+use crate::providers::claude_code::CLAUDE_CODE_INFERENCE_SCOPES;
 use crate::utils::unix_timestamp_ms;
 
 /// Provider 类型枚举
@@ -15,6 +25,7 @@ use crate::utils::unix_timestamp_ms;
 #[serde(rename_all = "snake_case")]
 pub enum ProviderType {
     Anthropic,
+    #[serde(rename = "openai")]
     OpenAI,
     #[clap(name = "claude-code")]
     ClaudeCode,
@@ -22,17 +33,154 @@ pub enum ProviderType {
 }
 
 impl ProviderType {
+    /// 配置文件中的 `type` 值
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProviderType::Anthropic => "anthropic",
+            ProviderType::OpenAI => "openai",
+            ProviderType::ClaudeCode => "claude_code",
+            ProviderType::Codex => "codex",
+        }
+    }
+
     pub fn is_anthropic(&self) -> bool {
         matches!(self, ProviderType::Anthropic | ProviderType::ClaudeCode)
     }
+
+    /// 能处理 Anthropic Messages 格式的请求（OpenAI 兼容 provider 转换后发送）
+    pub fn serves_messages(&self) -> bool {
+        self.is_anthropic() || *self == ProviderType::OpenAI
+    }
+
+    /// 能处理 count_tokens 请求
+    pub fn serves_count_tokens(&self) -> bool {
+        *self == ProviderType::ClaudeCode
+    }
+
+    /// 实现该类型所需、但构建时未开启的 cargo feature
+    pub fn missing_feature(&self) -> Option<&'static str> {
+        match self {
+            ProviderType::OpenAI if !cfg!(feature = "openai-compat") => Some("openai-compat"),
+            _ => None,
+        }
+    }
 }
 
 /// Provider 配置
 #[derive(Debug, Clone)]
 pub struct ProviderConfig {
+    /// 稳定的实例 ID，不随名称变化
+    pub id: String,
+    /// 文件名，用于显示与按名称选择 provider
     pub name: String,
     pub provider_type: ProviderType,
     pub auth: AuthConfig,
+    pub alerts: Option<AlertsConfig>,
+    pub schedule: Option<Schedule>,
+    /// 加权选择时的权重，至少为 1
+    pub weight: u32,
+}
+
+/// 未配置时的 provider 权重
+pub const DEFAULT_WEIGHT: u32 = 1;
+
+pub(crate) fn default_weight() -> u32 {
+    DEFAULT_WEIGHT
+}
+
+fn is_default_weight(weight: &u32) -> bool {
+    *weight == DEFAULT_WEIGHT
+}
+
+/// 生成新的 provider 实例 ID（UUID v4 格式）
+pub fn new_provider_id() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// `expires_at`（毫秒）的合理范围：2001-09-09 至 2100-01-01
+const EXPIRES_AT_RANGE_MS: std::ops::Range<u64> = 1_000_000_000_000..4_102_444_800_000;
+
+/// 误以秒填写的 `expires_at` 所在范围，与 [`EXPIRES_AT_RANGE_MS`] 对应
+const EXPIRES_AT_RANGE_SECS: std::ops::Range<u64> = 1_000_000_000..4_102_444_800;
+
+impl ProviderConfig {
+    /// 推理所需但授权中缺少的 OAuth scopes，非 OAuth 配置为空
+    pub fn missing_scopes(&self) -> Vec<String> {
+        match (&self.auth, self.provider_type) {
+            (AuthConfig::OAuth(oauth), ProviderType::ClaudeCode) => {
+                oauth.missing_scopes(CLAUDE_CODE_INFERENCE_SCOPES)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// 检查配置是否可用，错误信息中包含出错的字段
+    pub fn validate(&self) -> Result<()> {
+        match &self.auth {
+            AuthConfig::OAuth(oauth) => {
+                anyhow::ensure!(
+                    !oauth.access_token.trim().is_empty(),
+                    "oauth.access_token must not be empty"
+                );
+                anyhow::ensure!(
+                    !oauth.refresh_token.trim().is_empty(),
+                    "oauth.refresh_token must not be empty"
+                );
+                anyhow::ensure!(
+                    EXPIRES_AT_RANGE_MS.contains(&oauth.expires_at),
+                    "oauth.expires_at {} is not a plausible Unix timestamp in milliseconds",
+                    oauth.expires_at
+                );
+            }
+            AuthConfig::Api(api) => {
+                let url = reqwest::Url::parse(&api.base_url).with_context(|| {
+                    format!("api.base_url '{}' is not an absolute URL", api.base_url)
+                })?;
+                anyhow::ensure!(
+                    matches!(url.scheme(), "http" | "https"),
+                    "api.base_url '{}' must use http or https",
+                    api.base_url
+                );
+                anyhow::ensure!(
+                    !api.api_key.trim().is_empty(),
+                    "api.api_key must not be empty"
+                );
+            }
+        }
+
+        if self.provider_type == ProviderType::ClaudeCode {
+            anyhow::ensure!(
+                matches!(self.auth, AuthConfig::OAuth(_)),
+                "type = \"claude_code\" requires an [oauth] section"
+            );
+        }
+        if matches!(
+            self.provider_type,
+            ProviderType::Anthropic | ProviderType::OpenAI
+        ) {
+            anyhow::ensure!(
+                matches!(self.auth, AuthConfig::Api(_)),
+                "type = \"anthropic\" and type = \"openai\" require an [api] section"
+            );
+        }
+
+        if let Some(schedule) = &self.schedule {
+            schedule.validate()?;
+        }
+        anyhow::ensure!(self.weight >= 1, "weight must be at least 1");
+        Ok(())
+    }
 }
 
 /// 认证配置
@@ -59,21 +207,209 @@ pub struct ApiConfig {
     pub api_key: String,
 }
 
+/// token 预算告警配置（`[alerts]`）
+///
+/// 阈值为预算的比例，每日预算与每月配额分别计算；用量按 UTC 自然日 / 自然月重置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertsConfig {
+    /// 每日 token 预算
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_token_budget: Option<u64>,
+    #[serde(default = "default_warn_threshold")]
+    pub daily_token_warn_threshold: f64,
+    #[serde(default = "default_critical_threshold")]
+    pub daily_token_critical_threshold: f64,
+    /// 每月 token 配额，用尽后当月不再选择该 provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_token_quota: Option<u64>,
+}
+
+fn default_warn_threshold() -> f64 {
+    0.8
+}
+
+fn default_critical_threshold() -> f64 {
+    0.95
+}
+
+/// 按时间段启用 provider（`[schedule]`，UTC）
+///
+/// 列表为空表示不限制；`start_hour > end_hour` 的时间段跨越午夜，如 `[22, 6]`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Schedule {
+    /// `[start_hour, end_hour]`，包含开始、不包含结束，取值 0-24
+    #[serde(default)]
+    pub active_hours: Vec<(u8, u8)>,
+    /// 0 = 周日 … 6 = 周六
+    #[serde(default)]
+    pub active_days: Vec<u8>,
+}
+
+impl Schedule {
+    fn validate(&self) -> Result<()> {
+        for &(start, end) in &self.active_hours {
+            anyhow::ensure!(
+                start <= 24 && end <= 24,
+                "schedule.active_hours entry [{start}, {end}] is out of range 0-24"
+            );
+        }
+        for &day in &self.active_days {
+            anyhow::ensure!(
+                day <= 6,
+                "schedule.active_days entry {day} is out of range 0-6"
+            );
+        }
+        Ok(())
+    }
+
+    /// `now_secs` 时刻是否在启用时间内
+    pub fn is_active(&self, now_secs: u64) -> bool {
+        let days = now_secs / 86_400;
+        // 1970-01-01 是周四
+        let weekday = ((days + 4) % 7) as u8;
+        let hour = (now_secs % 86_400 / 3600) as u8;
+
+        let day_ok = self.active_days.is_empty() || self.active_days.contains(&weekday);
+        let hour_ok = self.active_hours.is_empty()
+            || self.active_hours.iter().any(|&(start, end)| {
+                if start <= end {
+                    (start..end).contains(&hour)
+                } else {
+                    hour >= start || hour < end
+                }
+            });
+        day_ok && hour_ok
+    }
+}
+
 const TOKEN_REFRESH_THRESHOLD_MS: u64 = 5 * 60 * 1000;
 
 impl OAuthConfig {
+    /// `required` 中授权未包含的 scopes
+    ///
+    /// 未记录 scopes 的旧配置无法判断，视为齐全
+    pub fn missing_scopes(&self, required: &[&str]) -> Vec<String> {
+        if self.scopes.is_empty() {
+            return Vec::new();
+        }
+        required
+            .iter()
+            .filter(|scope| !self.scopes.iter().any(|s| s == *scope))
+            .map(|scope| scope.to_string())
+            .collect()
+    }
+
     pub fn should_refresh(&self) -> bool {
-        unix_timestamp_ms() + TOKEN_REFRESH_THRESHOLD_MS >= self.expires_at
+        self.expires_within(TOKEN_REFRESH_THRESHOLD_MS)
+    }
+
+    /// token 是否将在 `ms` 毫秒内过期
+    pub fn expires_within(&self, ms: u64) -> bool {
+        unix_timestamp_ms() + ms >= self.expires_at
+    }
+
+    /// 把以秒填写的 `expires_at` 换算为毫秒，`strict_units` 时报错
+    ///
+    /// 否则 token 会一直被视为已过期，每个请求都触发一次刷新
+    fn normalize_expires_at(&mut self, path: &Path, strict_units: bool) -> Result<()> {
+        if !EXPIRES_AT_RANGE_SECS.contains(&self.expires_at) {
+            return Ok(());
+        }
+        anyhow::ensure!(
+            !strict_units,
+            "oauth.expires_at {} is in seconds, expected milliseconds",
+            self.expires_at
+        );
+        tracing::warn!(
+            file = %path.display(),
+            expires_at = self.expires_at,
+            "oauth.expires_at looks like seconds, treating it as {}; fix the config file",
+            self.expires_at * 1000
+        );
+        self.expires_at *= 1000;
+        Ok(())
     }
 }
 
 /// TOML 文件结构
 #[derive(Debug, Deserialize, Serialize)]
 struct TomlFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
     #[serde(rename = "type")]
     provider_type: ProviderType,
+    #[serde(default = "default_weight", skip_serializing_if = "is_default_weight")]
+    weight: u32,
     oauth: Option<OAuthConfig>,
     api: Option<ApiConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    alerts: Option<AlertsConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    schedule: Option<Schedule>,
+}
+
+/// 等待配置文件锁的最长时间
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+/// 锁被占用时的重试间隔
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy)]
+enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+/// 配置文件锁，drop 时释放
+struct ConfigLock {
+    file: std::fs::File,
+}
+
+impl Drop for ConfigLock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+/// 获取 `{name}.toml.lock` 上的文件锁，最多等待 [`LOCK_TIMEOUT`]
+async fn acquire_lock(dir: &Path, name: &str, mode: LockMode) -> Result<ConfigLock> {
+    let path = lock_path(dir, name);
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+
+    let start = Instant::now();
+    let mut waiting = false;
+    loop {
+        let result = match mode {
+            LockMode::Shared => FileExt::try_lock_shared(&file),
+            LockMode::Exclusive => FileExt::try_lock_exclusive(&file),
+        };
+        match result {
+            Ok(()) => return Ok(ConfigLock { file }),
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to lock {}", path.display())),
+        }
+
+        if start.elapsed() >= LOCK_TIMEOUT {
+            anyhow::bail!(
+                "Timed out after {}s waiting for lock on {}",
+                LOCK_TIMEOUT.as_secs(),
+                path.display()
+            );
+        }
+        if !waiting {
+            tracing::debug!(lock = %path.display(), ?mode, "waiting for provider config lock");
+            waiting = true;
+        }
+        tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+    }
+}
+
+fn lock_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.toml.lock", name))
 }
 
 /// 保存配置到文件
@@ -81,18 +417,32 @@ pub async fn save(dir: impl AsRef<Path>, name: &str, config: &ProviderConfig) ->
     let dir = dir.as_ref();
     fs::create_dir_all(dir).await?;
 
+    let _lock = acquire_lock(dir, name, LockMode::Exclusive).await?;
+    write_unlocked(dir, name, config).await
+}
+
+/// 写入配置文件，调用方需持有独占锁
+async fn write_unlocked(dir: &Path, name: &str, config: &ProviderConfig) -> Result<()> {
     let (oauth, api) = match &config.auth {
         AuthConfig::OAuth(o) => (Some(o.clone()), None),
         AuthConfig::Api(a) => (None, Some(a.clone())),
     };
 
+    anyhow::ensure!(!config.id.is_empty(), "Provider {name} has no id");
     let file = TomlFile {
+        id: Some(config.id.clone()),
         provider_type: config.provider_type,
         oauth,
         api,
+        alerts: config.alerts.clone(),
+        schedule: config.schedule.clone(),
+        weight: config.weight,
     };
 
     let path = dir.join(format!("{}.toml", name));
+    config
+        .validate()
+        .with_context(|| format!("Invalid provider config {}", path.display()))?;
     let content = toml::to_string_pretty(&file)?;
     fs::write(&path, content).await?;
 
@@ -100,9 +450,36 @@ pub async fn save(dir: impl AsRef<Path>, name: &str, config: &ProviderConfig) ->
     Ok(())
 }
 
-/// 加载单个配置
-async fn load(path: impl AsRef<Path>) -> Result<ProviderConfig> {
+/// 加载单个配置，`strict_units` 时拒绝以秒填写的 `expires_at`
+async fn load(path: impl AsRef<Path>, strict_units: bool) -> Result<ProviderConfig> {
     let path = path.as_ref();
+    let name = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .context("Invalid file name")?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    let config = {
+        let _lock = acquire_lock(dir, name, LockMode::Shared).await?;
+        read_unlocked(path, strict_units).await?
+    };
+    if !config.id.is_empty() {
+        return Ok(config);
+    }
+
+    // 旧配置没有 ID，分配后写回；重新读取以免覆盖其他进程在此期间的修改
+    let _lock = acquire_lock(dir, name, LockMode::Exclusive).await?;
+    let mut config = read_unlocked(path, strict_units).await?;
+    if config.id.is_empty() {
+        config.id = new_provider_id();
+        write_unlocked(dir, name, &config).await?;
+        tracing::info!(provider = name, id = config.id, "assigned id to provider");
+    }
+    Ok(config)
+}
+
+/// 读取配置文件，调用方需持有锁
+async fn read_unlocked(path: &Path, strict_units: bool) -> Result<ProviderConfig> {
     let name = path
         .file_stem()
         .and_then(|s| s.to_str())
@@ -110,9 +487,13 @@ async fn load(path: impl AsRef<Path>) -> Result<ProviderConfig> {
         .to_string();
 
     let content = fs::read_to_string(path).await?;
-    let file: TomlFile = toml::from_str(&content)?;
+    let file: TomlFile =
+        toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))?;
 
-    let auth = if let Some(oauth) = file.oauth {
+    let auth = if let Some(mut oauth) = file.oauth {
+        oauth
+            .normalize_expires_at(path, strict_units)
+            .with_context(|| format!("Invalid provider config {}", path.display()))?;
         AuthConfig::OAuth(oauth)
     } else if let Some(api) = file.api {
         AuthConfig::Api(api)
@@ -120,15 +501,23 @@ async fn load(path: impl AsRef<Path>) -> Result<ProviderConfig> {
         anyhow::bail!("No [oauth] or [api] section in {}", path.display());
     };
 
-    Ok(ProviderConfig {
+    let config = ProviderConfig {
+        id: file.id.unwrap_or_default(),
         name,
         provider_type: file.provider_type,
         auth,
-    })
+        alerts: file.alerts,
+        schedule: file.schedule,
+        weight: file.weight,
+    };
+    config
+        .validate()
+        .with_context(|| format!("Invalid provider config {}", path.display()))?;
+    Ok(config)
 }
 
-/// 加载目录下所有配置
-pub async fn load_all(dir: impl AsRef<Path>) -> Result<Vec<ProviderConfig>> {
+/// 加载目录下所有配置，`strict_units` 时跳过以秒填写 `expires_at` 的配置
+pub async fn load_all(dir: impl AsRef<Path>, strict_units: bool) -> Result<Vec<ProviderConfig>> {
     let dir = dir.as_ref();
     if !dir.exists() {
         return Ok(vec![]);
@@ -140,9 +529,18 @@ pub async fn load_all(dir: impl AsRef<Path>) -> Result<Vec<ProviderConfig>> {
     while let Some(entry) = entries.next_entry().await? {
         let path = entry.path();
         if path.extension().is_some_and(|e| e == "toml") {
-            match load(&path).await {
-                Ok(cfg) => configs.push(cfg),
-                Err(e) => tracing::warn!("Failed to load {}: {}", path.display(), e),
+            match load(&path, strict_units).await {
+                // 复制配置文件得到的 provider 与原文件 ID 相同，会共用运行时状态
+                Ok(cfg) => match configs.iter().find(|c: &&ProviderConfig| c.id == cfg.id) {
+                    Some(other) => tracing::warn!(
+                        "Skipping {}: id {} is already used by provider {}; remove the id line to assign a new one",
+                        path.display(),
+                        cfg.id,
+                        other.name
+                    ),
+                    None => configs.push(cfg),
+                },
+                Err(e) => tracing::warn!("Failed to load {}: {:#}", path.display(), e),
             }
         }
     }
@@ -151,14 +549,296 @@ pub async fn load_all(dir: impl AsRef<Path>) -> Result<Vec<ProviderConfig>> {
 }
 
 /// 根据名称加载配置
-pub async fn load_by_name(dir: impl AsRef<Path>, name: &str) -> Result<ProviderConfig> {
+pub async fn load_by_name(
+    dir: impl AsRef<Path>,
+    name: &str,
+    strict_units: bool,
+) -> Result<ProviderConfig> {
     let path = dir.as_ref().join(format!("{}.toml", name));
-    load(&path).await
+    load(&path, strict_units).await
 }
 
-/// 更新 OAuth 配置
-pub async fn update_oauth(dir: impl AsRef<Path>, name: &str, oauth: &OAuthConfig) -> Result<()> {
-    let mut config = load_by_name(&dir, name).await?;
+/// 根据 ID 加载配置
+///
+/// `hint` 为上次所在的文件名，文件不存在或已属于其他 provider（改名）时扫描目录查找
+pub async fn load_by_id(
+    dir: impl AsRef<Path>,
+    id: &str,
+    hint: &str,
+    strict_units: bool,
+) -> Result<ProviderConfig> {
+    let dir = dir.as_ref();
+    let path = dir.join(format!("{}.toml", hint));
+    if path.exists() {
+        let config = load(&path, strict_units).await?;
+        if config.id == id {
+            return Ok(config);
+        }
+    }
+    load_all(dir, strict_units)
+        .await?
+        .into_iter()
+        .find(|c| c.id == id)
+        .with_context(|| format!("No provider with id {} in {}", id, dir.display()))
+}
+
+/// 更新 OAuth 配置，读改写期间持有独占锁
+///
+/// 文件中的 ID 与 `id` 不同时（期间被改名或替换）不写入
+pub async fn update_oauth(
+    dir: impl AsRef<Path>,
+    name: &str,
+    id: &str,
+    oauth: &OAuthConfig,
+) -> Result<()> {
+    let dir = dir.as_ref();
+    let _lock = acquire_lock(dir, name, LockMode::Exclusive).await?;
+
+    let path = dir.join(format!("{}.toml", name));
+    // 只替换其中的 OAuth 凭证，原有的 `expires_at` 以秒填写也不影响写入
+    let mut config = read_unlocked(&path, false).await?;
+    anyhow::ensure!(
+        config.id == id,
+        "{} no longer belongs to provider {}",
+        path.display(),
+        id
+    );
     config.auth = AuthConfig::OAuth(oauth.clone());
-    save(dir, name, &config).await
+    write_unlocked(dir, name, &config).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 写入 `expires_at` 为 `expires_at` 的 claude-code 配置
+    fn write_oauth(dir: &Path, name: &str, expires_at: u64) {
+        std::fs::write(
+            dir.join(format!("{name}.toml")),
+            format!(
+                "id = \"id-{name}\"\ntype = \"claude_code\"\n\n[oauth]\n\
+                 access_token = \"access\"\nrefresh_token = \"refresh\"\nexpires_at = {expires_at}\n"
+            ),
+        )
+        .unwrap();
+    }
+
+    fn expires_at(config: &ProviderConfig) -> u64 {
+        match &config.auth {
+            AuthConfig::OAuth(oauth) => oauth.expires_at,
+            AuthConfig::Api(_) => panic!("not an OAuth provider"),
+        }
+    }
+
+    #[tokio::test]
+    async fn seconds_are_converted_to_milliseconds() {
+        let dir = tempfile::tempdir().unwrap();
+        write_oauth(dir.path(), "secs", 1_900_000_000);
+        write_oauth(dir.path(), "millis", 1_900_000_000_123);
+
+        let secs = load_by_name(dir.path(), "secs", false).await.unwrap();
+        assert_eq!(expires_at(&secs), 1_900_000_000_000);
+        let millis = load_by_name(dir.path(), "millis", false).await.unwrap();
+        assert_eq!(expires_at(&millis), 1_900_000_000_123);
+    }
+
+    #[tokio::test]
+    async fn missing_inference_scopes_are_detected_on_load() {
+        let dir = tempfile::tempdir().unwrap();
+        write_oauth(dir.path(), "profile", 1_900_000_000_123);
+        write_oauth(dir.path(), "legacy", 1_900_000_000_123);
+        let path = dir.path().join("profile.toml");
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, format!("{content}scopes = [\"user:profile\"]\n")).unwrap();
+
+        let profile = load_by_name(dir.path(), "profile", false).await.unwrap();
+        assert_eq!(profile.missing_scopes(), ["user:inference"]);
+        let legacy = load_by_name(dir.path(), "legacy", false).await.unwrap();
+        assert!(legacy.missing_scopes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn strict_units_reject_seconds() {
+        let dir = tempfile::tempdir().unwrap();
+        write_oauth(dir.path(), "secs", 1_900_000_000);
+        write_oauth(dir.path(), "millis", 1_900_000_000_123);
+
+        let err = load_by_name(dir.path(), "secs", true).await.unwrap_err();
+        assert!(format!("{err:#}").contains("is in seconds"), "{err:#}");
+        // 目录加载时跳过该配置，其他配置不受影响
+        let names: Vec<String> = load_all(dir.path(), true)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(names, ["millis"]);
+    }
+
+    #[tokio::test]
+    async fn implausible_timestamps_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        write_oauth(dir.path(), "tiny", 12_345);
+        let err = load_by_name(dir.path(), "tiny", false).await.unwrap_err();
+        assert!(format!("{err:#}").contains("not a plausible"), "{err:#}");
+    }
+
+    #[tokio::test]
+    async fn ids_are_assigned_once_and_survive_a_rename() {
+        let dir = tempfile::tempdir().unwrap();
+        write_oauth(dir.path(), "work", 1_900_000_000_123);
+        let path = dir.path().join("work.toml");
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, content.replace("id = \"id-work\"\n", "")).unwrap();
+
+        let assigned = load_by_name(dir.path(), "work", false).await.unwrap();
+        assert!(!assigned.id.is_empty());
+        assert!(std::fs::read_to_string(&path)
+            .unwrap()
+            .contains(&assigned.id));
+        let again = load_by_name(dir.path(), "work", false).await.unwrap();
+        assert_eq!(again.id, assigned.id);
+
+        std::fs::rename(&path, dir.path().join("team.toml")).unwrap();
+        let renamed = load_by_name(dir.path(), "team", false).await.unwrap();
+        assert_eq!(renamed.name, "team");
+        assert_eq!(renamed.id, assigned.id);
+    }
+
+    #[tokio::test]
+    async fn copies_sharing_an_id_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        write_oauth(dir.path(), "work", 1_900_000_000_123);
+        std::fs::copy(dir.path().join("work.toml"), dir.path().join("copy.toml")).unwrap();
+
+        let configs = load_all(dir.path(), false).await.unwrap();
+        assert_eq!(configs.len(), 1);
+        assert_eq!(configs[0].id, "id-work");
+    }
+
+    const OAUTH: &str = "[oauth]\naccess_token = \"access\"\nrefresh_token = \"refresh\"\n\
+                         expires_at = 1900000000123\n";
+    const API: &str = "[api]\nbase_url = \"https://api.example.com\"\napi_key = \"sk-key\"\n";
+
+    #[tokio::test]
+    async fn invalid_configs_are_rejected_on_load() {
+        let cases = [
+            (
+                format!(
+                    "type = \"claude_code\"\n{}",
+                    OAUTH.replace("\"access\"", "\" \"")
+                ),
+                "oauth.access_token must not be empty",
+            ),
+            (
+                format!(
+                    "type = \"claude_code\"\n{}",
+                    OAUTH.replace("\"refresh\"", "\"\"")
+                ),
+                "oauth.refresh_token must not be empty",
+            ),
+            (
+                format!(
+                    "type = \"anthropic\"\n{}",
+                    API.replace("https://api.example.com", "api.example.com")
+                ),
+                "api.base_url 'api.example.com' is not an absolute URL",
+            ),
+            (
+                format!(
+                    "type = \"anthropic\"\n{}",
+                    API.replace("https://", "ftp://")
+                ),
+                "api.base_url 'ftp://api.example.com' must use http or https",
+            ),
+            (
+                format!("type = \"anthropic\"\n{}", API.replace("sk-key", "")),
+                "api.api_key must not be empty",
+            ),
+            (
+                format!("type = \"claude_code\"\n{API}"),
+                "type = \"claude_code\" requires an [oauth] section",
+            ),
+            (
+                format!("type = \"anthropic\"\n{OAUTH}"),
+                "type = \"anthropic\" and type = \"openai\" require an [api] section",
+            ),
+            (
+                format!("type = \"claude_code\"\nweight = 0\n{OAUTH}"),
+                "weight must be at least 1",
+            ),
+            (
+                format!("type = \"claude_code\"\n{OAUTH}\n[schedule]\nactive_hours = [[22, 25]]\n"),
+                "schedule.active_hours entry [22, 25] is out of range 0-24",
+            ),
+            (
+                format!("type = \"claude_code\"\n{OAUTH}\n[schedule]\nactive_days = [7]\n"),
+                "schedule.active_days entry 7 is out of range 0-6",
+            ),
+            (
+                "type = \"claude_code\"\n".to_string(),
+                "No [oauth] or [api] section",
+            ),
+        ];
+
+        let dir = tempfile::tempdir().unwrap();
+        for (content, expected) in cases {
+            std::fs::write(dir.path().join("bad.toml"), &content).unwrap();
+            let err = load_by_name(dir.path(), "bad", false).await.unwrap_err();
+            let message = format!("{err:#}");
+            assert!(message.contains(expected), "{content}: {message}");
+            assert!(message.contains("bad.toml"), "{message}");
+        }
+        // 目录加载时跳过无效配置
+        write_oauth(dir.path(), "good", 1_900_000_000_123);
+        let configs = load_all(dir.path(), false).await.unwrap();
+        assert_eq!(configs.len(), 1);
+        assert_eq!(configs[0].name, "good");
+    }
+
+    #[tokio::test]
+    async fn invalid_configs_are_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        write_oauth(dir.path(), "work", 1_900_000_000_123);
+        let path = dir.path().join("work.toml");
+        let original = std::fs::read_to_string(&path).unwrap();
+        let config = load_by_name(dir.path(), "work", false).await.unwrap();
+
+        let mut zero_weight = config.clone();
+        zero_weight.weight = 0;
+        let mut empty_token = config.clone();
+        if let AuthConfig::OAuth(oauth) = &mut empty_token.auth {
+            oauth.access_token.clear();
+        }
+        let mut bad_schedule = config.clone();
+        bad_schedule.schedule = Some(Schedule {
+            active_hours: vec![],
+            active_days: vec![9],
+        });
+        let mut no_id = config.clone();
+        no_id.id.clear();
+        let cases = [
+            (zero_weight, "weight must be at least 1"),
+            (empty_token, "oauth.access_token must not be empty"),
+            (
+                bad_schedule,
+                "schedule.active_days entry 9 is out of range 0-6",
+            ),
+            (no_id, "Provider work has no id"),
+        ];
+        for (invalid, expected) in cases {
+            let err = save(dir.path(), "work", &invalid).await.unwrap_err();
+            let message = format!("{err:#}");
+            assert!(message.contains(expected), "{message}");
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), original);
+        }
+
+        // 有效的修改写入后可以重新加载
+        let mut heavier = config;
+        heavier.weight = 3;
+        save(dir.path(), "work", &heavier).await.unwrap();
+        let reloaded = load_by_name(dir.path(), "work", false).await.unwrap();
+        assert_eq!(reloaded.weight, 3);
+        assert_eq!(reloaded.id, "id-work");
+    }
 }
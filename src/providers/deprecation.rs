@@ -0,0 +1,290 @@
+//! 模型弃用信号
+//!
+//! 上游通过响应头或响应体字段提示模型即将弃用 / 下线。按配置的响应头名称与 JSON 字段检查
+//! 上游响应（非流式响应的响应头与响应体，流式响应的响应头与 `message_start`），按模型、
+//! provider 与信号记录首次 / 最近出现时间，每个模型每天（UTC）记录一条 WARN 日志。
+
+use http::HeaderMap;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use crate::utils::unix_timestamp_secs;
+
+/// 最多记录的模型、provider 与信号组合数
+const MAX_FINDINGS: usize = 1024;
+
+/// 保存的信号值最大字节数
+const MAX_VALUE_BYTES: usize = 256;
+
+/// 检查的信号
+#[derive(Debug, Clone, Default)]
+pub struct DeprecationWatch {
+    /// 响应头名称，小写
+    pub headers: Vec<String>,
+    /// 响应体（message 对象）中的字段，以 `.` 分隔嵌套字段
+    pub fields: Vec<String>,
+}
+
+impl DeprecationWatch {
+    fn is_empty(&self) -> bool {
+        self.headers.is_empty() && self.fields.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Finding {
+    value: String,
+    count: u64,
+    first_seen: u64,
+    last_seen: u64,
+}
+
+#[derive(Debug, Default)]
+struct Findings {
+    /// (model, provider, signal) -> 记录
+    entries: BTreeMap<(String, String, String), Finding>,
+    /// model -> 最近一次记录 WARN 日志的日期（自 Unix epoch 起的天数）
+    warned: HashMap<String, u64>,
+    /// 达到上限后未记录的新组合出现次数
+    dropped: u64,
+}
+
+/// `GET /admin/deprecations` 中的单条记录
+#[derive(Debug, Serialize)]
+pub struct DeprecationFinding {
+    pub model: String,
+    pub provider: String,
+    /// 命中的响应头名称（`header:` 前缀）或字段（`field:` 前缀）
+    pub signal: String,
+    /// 最近一次的值
+    pub value: String,
+    pub count: u64,
+    /// 首次出现时间 (Unix timestamp)
+    pub first_seen: u64,
+    /// 最近出现时间 (Unix timestamp)
+    pub last_seen: u64,
+}
+
+/// 全部记录
+#[derive(Debug, Serialize)]
+pub struct DeprecationReport {
+    pub headers: Vec<String>,
+    pub fields: Vec<String>,
+    /// 达到上限后未记录的新组合出现次数
+    pub dropped: u64,
+    pub deprecations: Vec<DeprecationFinding>,
+}
+
+/// 检查的信号与已记录的弃用信号，由所有 provider 共享
+#[derive(Debug, Default)]
+pub struct Deprecations {
+    watch: DeprecationWatch,
+    findings: Mutex<Findings>,
+}
+
+impl Deprecations {
+    pub fn new(watch: DeprecationWatch) -> Self {
+        Self {
+            watch,
+            findings: Mutex::default(),
+        }
+    }
+
+    /// 检查上游响应头
+    pub fn inspect_headers(&self, provider: &str, model: &str, headers: &HeaderMap) {
+        if self.watch.is_empty() {
+            return;
+        }
+        for name in &self.watch.headers {
+            if let Some(value) = headers.get(name.as_str()).and_then(|v| v.to_str().ok()) {
+                self.record(provider, model, format!("header:{name}"), value);
+            }
+        }
+    }
+
+    /// 检查上游返回的 message（非流式响应或 `message_start` 中的 `message`）
+    pub fn inspect_message(&self, provider: &str, model: &str, message: &Value) {
+        if self.watch.is_empty() {
+            return;
+        }
+        for field in &self.watch.fields {
+            let value = field
+                .split('.')
+                .try_fold(message, |value, key| value.get(key));
+            let value = match value {
+                None | Some(Value::Null) | Some(Value::Bool(false)) => continue,
+                Some(Value::String(s)) => s.clone(),
+                Some(other) => other.to_string(),
+            };
+            self.record(provider, model, format!("field:{field}"), &value);
+        }
+    }
+
+    fn record(&self, provider: &str, model: &str, signal: String, value: &str) {
+        let Ok(mut findings) = self.findings.lock() else {
+            return;
+        };
+        let now = unix_timestamp_secs();
+        let value = truncate(value, MAX_VALUE_BYTES);
+
+        let today = now / 86400;
+        if findings.warned.get(model) != Some(&today) {
+            findings.warned.insert(model.to_string(), today);
+            tracing::warn!(
+                provider,
+                model,
+                signal,
+                value,
+                "upstream signals model deprecation"
+            );
+        }
+
+        let key = (model.to_string(), provider.to_string(), signal);
+        if let Some(finding) = findings.entries.get_mut(&key) {
+            finding.count += 1;
+            finding.last_seen = now;
+            finding.value = value;
+            return;
+        }
+        if findings.entries.len() >= MAX_FINDINGS {
+            findings.dropped += 1;
+            return;
+        }
+        findings.entries.insert(
+            key,
+            Finding {
+                value,
+                count: 1,
+                first_seen: now,
+                last_seen: now,
+            },
+        );
+    }
+
+    /// 当前的检查配置与记录，按模型、provider、信号排序
+    pub fn report(&self) -> DeprecationReport {
+        let (dropped, deprecations) = match self.findings.lock() {
+            Ok(findings) => (
+                findings.dropped,
+                findings
+                    .entries
+                    .iter()
+                    .map(|((model, provider, signal), finding)| DeprecationFinding {
+                        model: model.clone(),
+                        provider: provider.clone(),
+                        signal: signal.clone(),
+                        value: finding.value.clone(),
+                        count: finding.count,
+                        first_seen: finding.first_seen,
+                        last_seen: finding.last_seen,
+                    })
+                    .collect(),
+            ),
+            Err(_) => (0, Vec::new()),
+        };
+        DeprecationReport {
+            headers: self.watch.headers.clone(),
+            fields: self.watch.fields.clone(),
+            dropped,
+            deprecations,
+        }
+    }
+}
+
+fn truncate(value: &str, max: usize) -> String {
+    let mut end = value.len().min(max);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    value[..end].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+    use serde_json::json;
+
+    fn deprecations() -> Deprecations {
+        Deprecations::new(DeprecationWatch {
+            headers: vec!["deprecation".to_string(), "sunset".to_string()],
+            fields: vec!["deprecation".to_string(), "notice.sunset".to_string()],
+        })
+    }
+
+    fn signals(report: &DeprecationReport) -> Vec<(&str, &str, &str, u64)> {
+        report
+            .deprecations
+            .iter()
+            .map(|f| {
+                (
+                    f.model.as_str(),
+                    f.signal.as_str(),
+                    f.value.as_str(),
+                    f.count,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn watched_headers_are_recorded() {
+        let deprecations = deprecations();
+        let mut headers = HeaderMap::new();
+        headers.insert("Sunset", HeaderValue::from_static("2026-12-01"));
+        headers.insert("x-other", HeaderValue::from_static("1"));
+        deprecations.inspect_headers("first", "claude-old", &headers);
+        deprecations.inspect_headers("first", "claude-old", &headers);
+
+        let report = deprecations.report();
+        assert_eq!(
+            signals(&report),
+            [("claude-old", "header:sunset", "2026-12-01", 2)]
+        );
+        assert_eq!(report.headers, ["deprecation", "sunset"]);
+    }
+
+    #[test]
+    fn watched_fields_are_recorded_and_empty_values_skipped() {
+        let deprecations = deprecations();
+        deprecations.inspect_message(
+            "first",
+            "claude-old",
+            &json!({"deprecation": false, "notice": {"sunset": "soon"}}),
+        );
+        deprecations.inspect_message("first", "claude-new", &json!({"deprecation": null}));
+        deprecations.inspect_message("second", "claude-b", &json!({"deprecation": {"at": 1}}));
+
+        // 按模型、provider、信号排序
+        assert_eq!(
+            signals(&deprecations.report()),
+            [
+                ("claude-b", "field:deprecation", r#"{"at":1}"#, 1),
+                ("claude-old", "field:notice.sunset", "soon", 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_watch_records_nothing() {
+        let deprecations = Deprecations::default();
+        let mut headers = HeaderMap::new();
+        headers.insert("deprecation", HeaderValue::from_static("true"));
+        deprecations.inspect_headers("first", "claude-old", &headers);
+        deprecations.inspect_message("first", "claude-old", &json!({"deprecation": true}));
+        assert!(deprecations.report().deprecations.is_empty());
+    }
+
+    #[test]
+    fn long_values_are_truncated_on_char_boundary() {
+        let deprecations = deprecations();
+        let value = "弃".repeat(MAX_VALUE_BYTES);
+        deprecations.inspect_message("first", "claude-old", &json!({ "deprecation": value }));
+        let report = deprecations.report();
+        let stored = &report.deprecations[0].value;
+        assert!(stored.len() <= MAX_VALUE_BYTES);
+        assert!(stored.chars().all(|c| c == '弃'));
+    }
+}
@@ -0,0 +1,333 @@
+//! 按 provider 类型改写请求字段
+//!
+//! Anthropic 格式请求中的部分字段（`cache_control`、`thinking` 等）对其他后端没有意义甚至会
+//! 导致请求失败。每种 provider 类型有一份字段策略：`keep` 原样保留，`strip` 删除请求中所有
+//! 层级的同名字段，`adapt` 转换为目标后端的等价形式。claude_code / anthropic 默认不做改写，
+//! 可在 `pluribus.toml` 的 `[field_policy.<type>]` 中覆盖。
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::providers::ProviderType;
+
+/// 单个字段的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldAction {
+    Keep,
+    Strip,
+    Adapt,
+}
+
+/// 支持 `adapt` 的字段
+const ADAPTABLE_FIELDS: &[&str] = &["cache_control", "thinking"];
+
+/// thinking 预算与 reasoning effort 的对应：不超过该值时使用对应档位
+const REASONING_EFFORT_LEVELS: &[(u64, &str)] = &[(4_096, "low"), (16_384, "medium")];
+
+/// 单个 provider 类型的字段策略
+#[derive(Debug, Clone, Default)]
+pub struct FieldPolicy {
+    fields: BTreeMap<String, FieldAction>,
+}
+
+impl FieldPolicy {
+    /// 是否不会改动请求
+    pub fn is_noop(&self) -> bool {
+        self.fields
+            .values()
+            .all(|action| *action == FieldAction::Keep)
+    }
+
+    /// 按策略改写请求体，返回实际被改写的字段
+    pub fn apply(&self, body: &mut Value) -> Vec<(&str, FieldAction)> {
+        self.fields
+            .iter()
+            .filter(|(field, action)| match action {
+                FieldAction::Keep => false,
+                FieldAction::Strip => strip_field(body, field),
+                FieldAction::Adapt => adapt_field(body, field),
+            })
+            .map(|(field, action)| (field.as_str(), *action))
+            .collect()
+    }
+}
+
+/// 所有 provider 类型的字段策略
+#[derive(Debug, Clone)]
+pub struct FieldPolicies {
+    by_type: HashMap<ProviderType, FieldPolicy>,
+}
+
+impl Default for FieldPolicies {
+    /// 内置策略：Anthropic 系后端保留所有字段，OpenAI 系后端去掉缓存标记与 top_k，
+    /// thinking 转换为 reasoning_effort
+    fn default() -> Self {
+        let translated = FieldPolicy {
+            fields: BTreeMap::from([
+                ("cache_control".to_string(), FieldAction::Strip),
+                ("thinking".to_string(), FieldAction::Adapt),
+                ("top_k".to_string(), FieldAction::Strip),
+            ]),
+        };
+        Self {
+            by_type: HashMap::from([
+                (ProviderType::OpenAI, translated.clone()),
+                (ProviderType::Codex, translated),
+            ]),
+        }
+    }
+}
+
+impl FieldPolicies {
+    /// 在内置策略上合并配置文件中的覆盖项
+    pub fn with_overrides(
+        overrides: HashMap<ProviderType, BTreeMap<String, FieldAction>>,
+    ) -> Result<Self> {
+        let mut policies = Self::default();
+        for (provider_type, fields) in overrides {
+            for (field, action) in fields {
+                if action == FieldAction::Adapt && !ADAPTABLE_FIELDS.contains(&field.as_str()) {
+                    anyhow::bail!(
+                        "Field {field} cannot be adapted, supported: {}",
+                        ADAPTABLE_FIELDS.join(", ")
+                    );
+                }
+                policies
+                    .by_type
+                    .entry(provider_type)
+                    .or_default()
+                    .fields
+                    .insert(field, action);
+            }
+        }
+        Ok(policies)
+    }
+
+    pub fn get(&self, provider_type: ProviderType) -> Option<&FieldPolicy> {
+        self.by_type
+            .get(&provider_type)
+            .filter(|policy| !policy.is_noop())
+    }
+}
+
+/// 递归删除所有层级的同名字段，返回是否删除了字段
+fn strip_field(value: &mut Value, field: &str) -> bool {
+    match value {
+        Value::Object(map) => {
+            let mut removed = map.remove(field).is_some();
+            for child in map.values_mut() {
+                removed |= strip_field(child, field);
+            }
+            removed
+        }
+        Value::Array(items) => items
+            .iter_mut()
+            .fold(false, |removed, item| strip_field(item, field) | removed),
+        _ => false,
+    }
+}
+
+fn adapt_field(body: &mut Value, field: &str) -> bool {
+    match field {
+        // 目标后端自动缓存，没有等价字段
+        "cache_control" => strip_field(body, field),
+        "thinking" => adapt_thinking(body),
+        _ => false,
+    }
+}
+
+/// 将 `thinking.budget_tokens` 转换为 `reasoning_effort`，并去掉历史消息中的 thinking 块
+fn adapt_thinking(body: &mut Value) -> bool {
+    let Some(map) = body.as_object_mut() else {
+        return false;
+    };
+    let mut changed = false;
+
+    if let Some(messages) = map.get_mut("messages").and_then(|m| m.as_array_mut()) {
+        for content in messages
+            .iter_mut()
+            .filter_map(|m| m.get_mut("content").and_then(|c| c.as_array_mut()))
+        {
+            let before = content.len();
+            content.retain(|block| {
+                !matches!(
+                    block.get("type").and_then(|t| t.as_str()),
+                    Some("thinking" | "redacted_thinking")
+                )
+            });
+            changed |= content.len() != before;
+        }
+    }
+
+    let Some(thinking) = map.remove("thinking") else {
+        return changed;
+    };
+    if thinking.get("type").and_then(|t| t.as_str()) != Some("enabled") {
+        return true;
+    }
+
+    let budget = thinking
+        .get("budget_tokens")
+        .and_then(|b| b.as_u64())
+        .unwrap_or_default();
+    let effort = REASONING_EFFORT_LEVELS
+        .iter()
+        .find(|(limit, _)| budget <= *limit)
+        .map_or("high", |(_, effort)| *effort);
+    map.insert("reasoning_effort".to_string(), Value::from(effort));
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn request() -> Value {
+        json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 32000,
+            "top_k": 5,
+            "thinking": {"type": "enabled", "budget_tokens": 10000},
+            "system": [{"type": "text", "text": "sys", "cache_control": {"type": "ephemeral"}}],
+            "messages": [
+                {"role": "user", "content": "hi"},
+                {"role": "assistant", "content": [
+                    {"type": "thinking", "thinking": "hmm", "signature": "sig"},
+                    {"type": "text", "text": "hello", "cache_control": {"type": "ephemeral"}},
+                ]},
+            ],
+        })
+    }
+
+    fn overrides(
+        provider_type: ProviderType,
+        fields: &[(&str, FieldAction)],
+    ) -> Result<FieldPolicies> {
+        FieldPolicies::with_overrides(HashMap::from([(
+            provider_type,
+            fields
+                .iter()
+                .map(|(field, action)| (field.to_string(), *action))
+                .collect(),
+        )]))
+    }
+
+    #[test]
+    fn anthropic_backends_keep_every_field_by_default() {
+        let policies = FieldPolicies::default();
+        assert!(policies.get(ProviderType::ClaudeCode).is_none());
+        assert!(policies.get(ProviderType::Anthropic).is_none());
+    }
+
+    #[test]
+    fn translated_backends_strip_and_adapt_by_default() {
+        let policies = FieldPolicies::default();
+        for provider_type in [ProviderType::OpenAI, ProviderType::Codex] {
+            let mut body = request();
+            let applied = policies.get(provider_type).unwrap().apply(&mut body);
+            assert_eq!(
+                applied,
+                [
+                    ("cache_control", FieldAction::Strip),
+                    ("thinking", FieldAction::Adapt),
+                    ("top_k", FieldAction::Strip),
+                ]
+            );
+            assert_eq!(
+                body,
+                json!({
+                    "model": "claude-sonnet-4-5",
+                    "max_tokens": 32000,
+                    "reasoning_effort": "medium",
+                    "system": [{"type": "text", "text": "sys"}],
+                    "messages": [
+                        {"role": "user", "content": "hi"},
+                        {"role": "assistant", "content": [{"type": "text", "text": "hello"}]},
+                    ],
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn thinking_budget_maps_to_reasoning_effort() {
+        let policy = FieldPolicies::default();
+        let policy = policy.get(ProviderType::OpenAI).unwrap();
+        for (budget, effort) in [
+            (1024, "low"),
+            (4096, "low"),
+            (16384, "medium"),
+            (32000, "high"),
+        ] {
+            let mut body = json!({"thinking": {"type": "enabled", "budget_tokens": budget}});
+            policy.apply(&mut body);
+            assert_eq!(body, json!({"reasoning_effort": effort}), "{budget}");
+        }
+
+        let mut body = json!({"thinking": {"type": "disabled"}});
+        assert_eq!(policy.apply(&mut body), [("thinking", FieldAction::Adapt)]);
+        assert_eq!(body, json!({}));
+
+        // 请求中没有的字段不算作改写
+        let mut body = json!({"model": "claude-sonnet-4-5"});
+        assert!(policy.apply(&mut body).is_empty());
+    }
+
+    #[test]
+    fn overrides_merge_into_the_builtin_policies() {
+        let policies = overrides(
+            ProviderType::OpenAI,
+            &[
+                ("cache_control", FieldAction::Keep),
+                ("metadata", FieldAction::Strip),
+            ],
+        )
+        .unwrap();
+        let mut body = request();
+        body["metadata"] = json!({"user_id": "session-a"});
+        let applied = policies.get(ProviderType::OpenAI).unwrap().apply(&mut body);
+        assert_eq!(
+            applied,
+            [
+                ("metadata", FieldAction::Strip),
+                ("thinking", FieldAction::Adapt),
+                ("top_k", FieldAction::Strip),
+            ]
+        );
+        assert_eq!(
+            body["system"][0]["cache_control"],
+            json!({"type": "ephemeral"})
+        );
+
+        let policies =
+            overrides(ProviderType::ClaudeCode, &[("top_k", FieldAction::Strip)]).unwrap();
+        let mut body = request();
+        let applied = policies
+            .get(ProviderType::ClaudeCode)
+            .unwrap()
+            .apply(&mut body);
+        assert_eq!(applied, [("top_k", FieldAction::Strip)]);
+        assert!(body.get("top_k").is_none());
+        assert!(body.get("thinking").is_some());
+
+        let noop = overrides(ProviderType::Anthropic, &[("top_k", FieldAction::Keep)]).unwrap();
+        assert!(noop.get(ProviderType::Anthropic).is_none());
+    }
+
+    #[test]
+    fn adapt_is_rejected_for_unsupported_fields() {
+        let err = overrides(ProviderType::OpenAI, &[("top_k", FieldAction::Adapt)]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Field top_k cannot be adapted, supported: cache_control, thinking"
+        );
+        for field in ADAPTABLE_FIELDS {
+            assert!(overrides(ProviderType::Anthropic, &[(field, FieldAction::Adapt)]).is_ok());
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! 上游响应头采集
+//!
+//! 上游会在响应头中返回未公开的限额与诊断信息。开启后按 provider 记录见过的响应头名称、
+//! 出现次数与首次 / 最近出现时间，包括错误响应的响应头。只有白名单中的响应头保存最近一次的值，
+//! 其余只记录名称，避免保存可能带有标识信息的值。首次出现的响应头名称记录一条 INFO 日志。
+
+use http::HeaderMap;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::utils::unix_timestamp_secs;
+
+/// 每个 provider 最多记录的不同响应头名称数
+const MAX_DISTINCT_HEADERS: usize = 256;
+
+/// 保存的响应头值最大字节数
+const MAX_VALUE_BYTES: usize = 256;
+
+/// 采集配置
+#[derive(Debug, Clone, Default)]
+pub struct CaptureSettings {
+    pub enabled: bool,
+    /// 保存值的响应头名称，小写，以 `*` 结尾时按前缀匹配
+    pub value_allowlist: Vec<String>,
+}
+
+impl CaptureSettings {
+    fn keeps_value(&self, name: &str) -> bool {
+        self.value_allowlist
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => pattern == name,
+            })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct HeaderStat {
+    count: u64,
+    first_seen: u64,
+    last_seen: u64,
+    value: Option<String>,
+}
+
+#[derive(Default)]
+struct Captured {
+    headers: BTreeMap<String, HeaderStat>,
+    /// 达到名称上限后未记录的新名称出现次数
+    dropped: u64,
+}
+
+/// `GET /admin/providers/{name}/headers` 中的单个响应头
+#[derive(Debug, Serialize)]
+pub struct CapturedHeader {
+    pub name: String,
+    pub count: u64,
+    /// 首次出现时间 (Unix timestamp)
+    pub first_seen: u64,
+    /// 最近出现时间 (Unix timestamp)
+    pub last_seen: u64,
+    /// 最近一次的值，仅白名单中的响应头保存
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+/// 单个 provider 的采集结果
+#[derive(Debug, Serialize)]
+pub struct CapturedHeaders {
+    pub enabled: bool,
+    /// 达到名称上限后未记录的新名称出现次数
+    pub dropped: u64,
+    pub headers: Vec<CapturedHeader>,
+}
+
+/// 单个 provider 见过的响应头
+#[derive(Default)]
+pub struct HeaderCapture {
+    settings: CaptureSettings,
+    captured: Mutex<Captured>,
+}
+
+impl HeaderCapture {
+    pub fn new(settings: CaptureSettings) -> Self {
+        Self {
+            settings,
+            captured: Mutex::default(),
+        }
+    }
+
+    /// 记录一次上游响应的响应头，未开启采集时忽略
+    pub fn record(&self, provider: &str, headers: &HeaderMap) {
+        let settings = &self.settings;
+        if !settings.enabled {
+            return;
+        }
+        let Ok(mut captured) = self.captured.lock() else {
+            return;
+        };
+        let now = unix_timestamp_secs();
+
+        for name in headers.keys() {
+            let name = name.as_str();
+            let value = settings
+                .keeps_value(name)
+                .then(|| headers.get(name).and_then(|v| v.to_str().ok()))
+                .flatten()
+                .map(|v| truncate(v, MAX_VALUE_BYTES));
+
+            if let Some(stat) = captured.headers.get_mut(name) {
+                stat.count += 1;
+                stat.last_seen = now;
+                if value.is_some() {
+                    stat.value = value;
+                }
+                continue;
+            }
+
+            if captured.headers.len() >= MAX_DISTINCT_HEADERS {
+                captured.dropped += 1;
+                continue;
+            }
+            tracing::info!(provider, header = name, "new upstream response header seen");
+            captured.headers.insert(
+                name.to_string(),
+                HeaderStat {
+                    count: 1,
+                    first_seen: now,
+                    last_seen: now,
+                    value,
+                },
+            );
+        }
+    }
+
+    pub fn snapshot(&self) -> CapturedHeaders {
+        let enabled = self.settings.enabled;
+        let Ok(captured) = self.captured.lock() else {
+            return CapturedHeaders {
+                enabled,
+                dropped: 0,
+                headers: Vec::new(),
+            };
+        };
+        CapturedHeaders {
+            enabled,
+            dropped: captured.dropped,
+            headers: captured
+                .headers
+                .iter()
+                .map(|(name, stat)| CapturedHeader {
+                    name: name.clone(),
+                    count: stat.count,
+                    first_seen: stat.first_seen,
+                    last_seen: stat.last_seen,
+                    value: stat.value.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn truncate(value: &str, max: usize) -> String {
+    let mut end = value.len().min(max);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    value[..end].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "anthropic-ratelimit-unified-status",
+            HeaderValue::from_static("allowed"),
+        );
+        headers.insert("request-id", HeaderValue::from_static("req_123"));
+        headers
+    }
+
+    #[test]
+    fn disabled_capture_records_nothing() {
+        let capture = HeaderCapture::new(CaptureSettings::default());
+        capture.record("first", &headers());
+        let snapshot = capture.snapshot();
+        assert!(!snapshot.enabled);
+        assert!(snapshot.headers.is_empty());
+    }
+
+    #[test]
+    fn only_allowlisted_values_are_kept() {
+        let capture = HeaderCapture::new(CaptureSettings {
+            enabled: true,
+            value_allowlist: vec!["anthropic-ratelimit-*".to_string()],
+        });
+        capture.record("first", &headers());
+        capture.record("first", &headers());
+
+        let snapshot = capture.snapshot();
+        assert!(snapshot.enabled);
+        let by_name: BTreeMap<_, _> = snapshot
+            .headers
+            .iter()
+            .map(|h| (h.name.as_str(), h))
+            .collect();
+        let status = by_name["anthropic-ratelimit-unified-status"];
+        assert_eq!(status.count, 2);
+        assert_eq!(status.value.as_deref(), Some("allowed"));
+        let request_id = by_name["request-id"];
+        assert_eq!(request_id.count, 2);
+        assert_eq!(request_id.value, None);
+    }
+
+    #[test]
+    fn each_capture_uses_its_own_settings() {
+        let enabled = HeaderCapture::new(CaptureSettings {
+            enabled: true,
+            value_allowlist: Vec::new(),
+        });
+        let disabled = HeaderCapture::new(CaptureSettings::default());
+        enabled.record("first", &headers());
+        disabled.record("second", &headers());
+        assert_eq!(enabled.snapshot().headers.len(), 2);
+        assert!(disabled.snapshot().headers.is_empty());
+    }
+}
@@ -2,23 +2,44 @@
 //!
 //! 定义所有 AI Provider 的统一接口，从 providers/*.toml 加载配置
 
+pub mod anomaly;
+pub mod anthropic;
 pub mod claude_code;
 pub mod config;
+pub mod deprecation;
+pub mod field_policy;
+pub mod header_capture;
+#[cfg(feature = "openai-compat")]
+pub mod openai;
+pub mod sse;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::Stream;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::cell::RefCell;
+use std::future::Future;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 
-use claude_code::ClaudeCodeProvider;
+use crate::config::{Config, ModelEndpoints};
+use crate::quiet_hours::QuietHours;
+use anthropic::AnthropicApiProvider;
+use claude_code::{ClaudeCodeProvider, RefreshPolicy};
 pub use claude_code::{RateLimitInfo, RateLimitWindow};
-pub use config::{save, AuthConfig, OAuthConfig, ProviderConfig, ProviderType};
+pub use config::{
+    save, AlertsConfig, ApiConfig, AuthConfig, OAuthConfig, ProviderConfig, ProviderType, Schedule,
+};
+use deprecation::{DeprecationWatch, Deprecations};
+use header_capture::{CaptureSettings, CapturedHeaders};
+#[cfg(feature = "openai-compat")]
+use openai::OpenAiProvider;
 
 /// Token 使用统计
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct Usage {
     pub input_tokens: u64,
     pub output_tokens: u64,
@@ -27,6 +48,11 @@ pub struct Usage {
 }
 
 impl Usage {
+    /// 总 token 数（含缓存读写）
+    pub fn total(&self) -> u64 {
+        self.input_tokens + self.output_tokens + self.cache_read_tokens + self.cache_creation_tokens
+    }
+
     /// 合并另一个 Usage，非零值会覆盖当前值
     pub fn merge_from(&mut self, other: &Usage) {
         if other.input_tokens > 0 {
@@ -53,15 +79,17 @@ impl Usage {
 /// # 返回值
 ///
 /// 返回解析后的 `Usage` 结构，包含各类 token 用量统计
-/// 如果任意值为0，返回 `Err`
+/// 只在缺少 usage 字段，或缺少 `input_tokens` / `output_tokens` 键时返回 `Err`
 ///
 /// # 说明
 ///
 /// 这个函数解析 Anthropic API 响应中的 usage 字段，提取：
 /// - input_tokens: 输入 token 数
 /// - output_tokens: 输出 token 数
-/// - cache_read_input_tokens: 缓存读取的 token 数
-/// - cache_creation_input_tokens: 缓存创建的 token 数
+/// - cache_read_input_tokens: 缓存读取的 token 数，缺失时为 0
+/// - cache_creation_input_tokens: 缓存创建的 token 数，缺失时为 0
+///
+/// 会话的第一个请求或未使用缓存时缓存字段为 0，属于正常值；值为 `null` 的字段同样按 0 计算
 pub fn parse_anthropic_usage(response: &Value) -> Result<Usage> {
     let usage_obj = response
         .get("usage")
@@ -69,32 +97,21 @@ pub fn parse_anthropic_usage(response: &Value) -> Result<Usage> {
 
     let input_tokens = usage_obj
         .get("input_tokens")
-        .and_then(|v| v.as_u64())
-        .ok_or_else(|| anyhow::anyhow!("Missing or invalid input_tokens"))?;
+        .map(token_count)
+        .ok_or_else(|| anyhow::anyhow!("Missing input_tokens"))?;
 
     let output_tokens = usage_obj
         .get("output_tokens")
-        .and_then(|v| v.as_u64())
-        .ok_or_else(|| anyhow::anyhow!("Missing or invalid output_tokens"))?;
+        .map(token_count)
+        .ok_or_else(|| anyhow::anyhow!("Missing output_tokens"))?;
 
     let cache_read_tokens = usage_obj
         .get("cache_read_input_tokens")
-        .and_then(|v| v.as_u64())
-        .unwrap_or(0);
+        .map_or(0, token_count);
 
     let cache_creation_tokens = usage_obj
         .get("cache_creation_input_tokens")
-        .and_then(|v| v.as_u64())
-        .unwrap_or(0);
-
-    // 如果任意值为0，返回错误
-    if input_tokens == 0
-        || output_tokens == 0
-        || cache_read_tokens == 0
-        || cache_creation_tokens == 0
-    {
-        return Err(anyhow::anyhow!("Usage contains zero values"));
-    }
+        .map_or(0, token_count);
 
     Ok(Usage {
         input_tokens,
@@ -104,31 +121,459 @@ pub fn parse_anthropic_usage(response: &Value) -> Result<Usage> {
     })
 }
 
+/// usage 中的 token 数，`null` 或无法识别的值按 0 计算
+fn token_count(value: &Value) -> u64 {
+    value
+        .as_u64()
+        .or_else(|| value.as_f64().map(|n| n.max(0.0) as u64))
+        .unwrap_or(0)
+}
+
+/// 解析流式 `message_delta` 事件的 usage
+///
+/// 事件中的 usage 可能只有 `output_tokens`，此时其余字段为 0，合并时由 [`Usage::merge_from`] 保留已有值
+pub fn parse_delta_usage(event: &Value) -> Result<Usage> {
+    parse_anthropic_usage(event).or_else(|err| {
+        let output_tokens = event
+            .pointer("/usage/output_tokens")
+            .and_then(|v| v.as_u64())
+            .ok_or(err)?;
+        Ok(Usage {
+            output_tokens,
+            ..Usage::default()
+        })
+    })
+}
+
+/// 实际发往上游的版本与 beta 请求头，用于排查 beta flag 相关的错误
+#[derive(Debug, Clone, Serialize)]
+pub struct SentHeaders {
+    pub anthropic_version: String,
+    pub anthropic_beta: String,
+}
+
+tokio::task_local! {
+    static SENT_HEADERS: RefCell<Option<SentHeaders>>;
+}
+
+/// 执行 `fut`，并捕获其中最后一次发往上游的请求头
+pub async fn capture_sent_headers<F: Future>(fut: F) -> (F::Output, Option<SentHeaders>) {
+    SENT_HEADERS
+        .scope(RefCell::new(None), async {
+            let output = fut.await;
+            (output, SENT_HEADERS.with(|sent| sent.take()))
+        })
+        .await
+}
+
+/// 记录发往上游的请求头，不在 [`capture_sent_headers`] 中调用时忽略
+pub fn record_sent_headers(headers: SentHeaders) {
+    let _ = SENT_HEADERS.try_with(|sent| *sent.borrow_mut() = Some(headers));
+}
+
+/// 上游 API 返回的非 2xx 错误
+///
+/// 通过 `anyhow::Error` 传递，调用方可 downcast 后按状态码决定是否重试
+#[derive(Debug)]
+pub struct UpstreamError {
+    pub status: http::StatusCode,
+    pub body: String,
+}
+
+impl std::fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Claude API error {}: {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for UpstreamError {}
+
+/// 上游错误的类型与信息，不含原始响应体，可以返回给客户端
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UpstreamErrorDetail {
+    #[serde(rename = "type")]
+    pub error_type: String,
+    pub message: String,
+}
+
+impl UpstreamError {
+    /// 按 Anthropic 的错误格式 `{"type": "error", "error": {"type", "message"}}` 解析响应体
+    ///
+    /// 响应体不是该格式（如代理返回的 HTML 或纯文本）时，按状态码给出类型与通用信息
+    pub fn detail(&self) -> UpstreamErrorDetail {
+        let parsed = serde_json::from_str::<Value>(&self.body)
+            .ok()
+            .and_then(|body| {
+                let error = body.get("error")?;
+                Some(UpstreamErrorDetail {
+                    error_type: error.get("type")?.as_str()?.to_string(),
+                    message: error
+                        .get("message")
+                        .and_then(|m| m.as_str())
+                        .unwrap_or_default()
+                        .to_string(),
+                })
+            });
+        parsed.unwrap_or_else(|| UpstreamErrorDetail {
+            error_type: error_type_for_status(self.status).to_string(),
+            message: self
+                .status
+                .canonical_reason()
+                .unwrap_or("Upstream error")
+                .to_string(),
+        })
+    }
+}
+
+/// 与状态码对应的 Anthropic 错误类型
+fn error_type_for_status(status: http::StatusCode) -> &'static str {
+    match status.as_u16() {
+        400 => "invalid_request_error",
+        401 => "authentication_error",
+        403 => "permission_error",
+        404 => "not_found_error",
+        413 => "request_too_large",
+        429 => "rate_limit_error",
+        503 | 529 => "overloaded_error",
+        _ => "api_error",
+    }
+}
+
+/// 非流式响应体超过大小上限
+#[derive(Debug)]
+pub struct ResponseTooLarge {
+    pub limit: u64,
+    /// 实际大小（字节）
+    pub observed: u64,
+}
+
+impl std::fmt::Display for ResponseTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Upstream response body too large: {} bytes (limit {})",
+            self.observed, self.limit
+        )
+    }
+}
+
+impl std::error::Error for ResponseTooLarge {}
+
+/// 非流式响应体超过大小上限时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OversizedResponse {
+    /// 以 `upstream_response_too_large` 失败
+    #[default]
+    Fail,
+    /// 改为流式请求重发，按上限收集后还原为非流式响应
+    Stream,
+}
+
+impl OversizedResponse {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "fail" | "" => Some(OversizedResponse::Fail),
+            "stream" => Some(OversizedResponse::Stream),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OversizedResponse::Fail => "fail",
+            OversizedResponse::Stream => "stream",
+        }
+    }
+}
+
+/// 默认的非流式响应体大小上限（字节）
+pub const DEFAULT_MAX_RESPONSE_BODY_BYTES: u64 = 32 * 1024 * 1024;
+
+/// 非流式响应体大小上限与超限时的处理方式
+#[derive(Debug, Clone, Copy)]
+pub struct ResponseBodyLimit {
+    pub max_bytes: u64,
+    pub oversized: OversizedResponse,
+}
+
+impl Default for ResponseBodyLimit {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_RESPONSE_BODY_BYTES,
+            oversized: OversizedResponse::default(),
+        }
+    }
+}
+
+/// 可按灰度规则开关的请求改写
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transform {
+    /// 注入 Claude Code 身份提示词
+    IdentityPrompt,
+    /// 伪装 tool 名称（claude_code provider）
+    ToolSpoof,
+}
+
+impl Transform {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Transform::IdentityPrompt => "identity_prompt",
+            Transform::ToolSpoof => "tool_spoof",
+        }
+    }
+}
+
+/// 请求体内部字段：本次请求不做的改写，provider 编码时读取并移除
+pub const SKIP_TRANSFORMS_FIELD: &str = "_skip_transforms";
+
+/// 请求体是否标记为不做 `transform`
+pub fn skips_transform(request: &Value, transform: Transform) -> bool {
+    request
+        .get(SKIP_TRANSFORMS_FIELD)
+        .and_then(|v| v.as_array())
+        .is_some_and(|skipped| {
+            skipped
+                .iter()
+                .any(|v| v.as_str() == Some(transform.as_str()))
+        })
+}
+
+/// 按 provider 改写并序列化好的请求体
+///
+/// `body` 为引用计数的 `Bytes`，重试与换 provider 时 clone 不复制请求体
+#[derive(Debug, Clone)]
+pub struct EncodedRequest {
+    pub body: Bytes,
+    /// 发往上游的 `anthropic-beta`，为空时不使用
+    pub beta: String,
+    pub model: String,
+    /// 请求中的 tool 名称已被伪装，响应需要还原
+    pub tools_spoofed: bool,
+}
+
 /// 流式响应
 pub struct StreamingResponse {
     pub stream: Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send + Unpin>,
     pub status: http::StatusCode,
+    /// 流的概要，在 `stream` 结束之前发送；中途失败时带 `incomplete`，客户端断开时在断开后发送
+    pub summary: tokio::sync::oneshot::Receiver<StreamSummary>,
+}
+
+/// 流式响应结束时的概要
+#[derive(Debug, Clone, Default)]
+pub struct StreamSummary {
+    /// 累计的 usage
+    pub usage: Usage,
+    /// 响应中 tool_use 块的 id
+    pub tool_use_ids: Vec<String>,
+    /// 流未正常结束（上游断开或客户端断开），usage 只包含已转发的部分
+    pub incomplete: bool,
+    /// 响应概要，流正常结束后由调用方按配置检查
+    pub shape: anomaly::ResponseShape,
+}
+
+/// 不含凭证的 provider 配置摘要，供 `/admin/providers` 与 `pluribus diff` 比较
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderSummary {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub provider_type: ProviderType,
+    pub alerts: Option<AlertsConfig>,
+    pub schedule: Option<Schedule>,
+    #[serde(default = "config::default_weight")]
+    pub weight: u32,
+    /// 推理所需但授权中缺少的 OAuth scopes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub missing_scopes: Vec<String>,
+}
+
+impl ProviderSummary {
+    pub fn of(provider: &dyn Provider) -> Self {
+        Self {
+            id: provider.id().to_string(),
+            name: provider.name().to_string(),
+            provider_type: provider.provider_type(),
+            alerts: provider.alerts().cloned(),
+            schedule: provider.schedule().cloned(),
+            weight: provider.weight(),
+            missing_scopes: provider.missing_scopes().to_vec(),
+        }
+    }
+}
+
+impl From<&ProviderConfig> for ProviderSummary {
+    fn from(config: &ProviderConfig) -> Self {
+        Self {
+            id: config.id.clone(),
+            name: config.name.clone(),
+            provider_type: config.provider_type,
+            alerts: config.alerts.clone(),
+            schedule: config.schedule.clone(),
+            weight: config.weight,
+            missing_scopes: config.missing_scopes(),
+        }
+    }
 }
 
 /// Provider Trait - 所有 AI 服务提供商的统一接口
 #[async_trait]
 pub trait Provider: Send + Sync {
-    /// Provider 名称（用于日志和标识）
+    /// 稳定的实例 ID，运行时状态以此为键
+    fn id(&self) -> &str;
+    /// Provider 名称（用于日志、显示与按名称选择）
     fn name(&self) -> &str;
     fn provider_type(&self) -> ProviderType;
     async fn send_message(&self, request: Value) -> Result<Value>;
     async fn send_streaming(&self, request: Value) -> Result<StreamingResponse>;
 
+    /// 原样转发无需改写的请求体，默认解析后走 `send_message`
+    ///
+    /// `model` 由调用方浅解析得到，用于选择上游地址和日志
+    async fn send_message_raw(&self, body: Bytes, _model: &str) -> Result<Value> {
+        self.send_message(serde_json::from_slice(&body)?).await
+    }
+
+    /// 原样转发无需改写的流式请求体，默认解析后走 `send_streaming`
+    ///
+    /// `model` 由调用方浅解析得到，用于选择上游地址和日志
+    async fn send_streaming_raw(&self, body: Bytes, _model: &str) -> Result<StreamingResponse> {
+        self.send_streaming(serde_json::from_slice(&body)?).await
+    }
+
+    /// 把请求改写并序列化为可在多次尝试间复用的请求体，默认原样序列化
+    ///
+    /// 同一类型的 provider 改写结果相同，调用方可在它们之间复用
+    fn encode(&self, request: Value, stream: bool) -> Result<EncodedRequest> {
+        let model = crate::utils::extract_model(&request);
+        let mut request = request;
+        if let Some(obj) = request.as_object_mut() {
+            obj.insert("stream".to_string(), Value::Bool(stream));
+            obj.remove(SKIP_TRANSFORMS_FIELD);
+        }
+        Ok(EncodedRequest {
+            body: Bytes::from(serde_json::to_vec(&request)?),
+            beta: String::new(),
+            model,
+            tools_spoofed: false,
+        })
+    }
+
+    /// 发送 `encode` 生成的请求体，默认解析后走 `send_message`
+    async fn send_message_encoded(&self, request: EncodedRequest) -> Result<Value> {
+        self.send_message(serde_json::from_slice(&request.body)?)
+            .await
+    }
+
+    /// 发送 `encode` 生成的流式请求体，默认解析后走 `send_streaming`
+    async fn send_streaming_encoded(&self, request: EncodedRequest) -> Result<StreamingResponse> {
+        self.send_streaming(serde_json::from_slice(&request.body)?)
+            .await
+    }
+
+    /// 计算请求的输入 token 数（仅部分 provider 支持）
+    async fn count_tokens(&self, _request: Value) -> Result<Value> {
+        anyhow::bail!("Provider {} does not support count_tokens", self.name())
+    }
+
+    /// 凭证即将过期时提前刷新（仅部分 provider 支持），返回是否刷新了
+    async fn refresh_credentials(&self) -> Result<bool> {
+        Ok(false)
+    }
+
     /// 获取 rate limit 信息（仅部分 provider 支持）
     fn rate_limit_info(&self) -> Option<RateLimitInfo> {
         None
     }
+
+    /// 采集到的上游响应头（仅部分 provider 支持）
+    fn captured_headers(&self) -> Option<CapturedHeaders> {
+        None
+    }
+
+    /// token 预算告警配置
+    fn alerts(&self) -> Option<&AlertsConfig> {
+        None
+    }
+
+    /// 启用时间段，`None` 表示始终启用
+    fn schedule(&self) -> Option<&Schedule> {
+        None
+    }
+
+    /// 加权选择时的权重
+    fn weight(&self) -> u32 {
+        config::DEFAULT_WEIGHT
+    }
+
+    /// 推理所需但授权中缺少的 OAuth scopes，非空时不参与选择
+    fn missing_scopes(&self) -> &[String] {
+        &[]
+    }
+
+    /// 凭证已失效、需要重新登录时的错误信息，此时不参与选择
+    fn needs_reauth(&self) -> Option<String> {
+        None
+    }
+}
+
+/// 创建 provider 时使用的 gateway 设置，由配置生成，所有 provider 共享同一份
+///
+/// 保存在应用状态中，重新加载 provider 时沿用
+#[derive(Debug, Default)]
+pub struct ProviderSettings {
+    /// 按模型指定的 Messages 地址
+    pub endpoints: ModelEndpoints,
+    /// 上游响应头采集
+    pub capture: CaptureSettings,
+    /// 模型弃用信号
+    pub deprecations: Arc<Deprecations>,
+    /// 非流式响应体大小上限
+    pub response_limit: ResponseBodyLimit,
+    /// Claude Code token 刷新
+    pub refresh: RefreshPolicy,
+    /// 拒绝以秒填写 `expires_at` 的配置，而不是换算为毫秒
+    pub strict_units: bool,
+    /// 静默时段内后台刷新只处理即将过期的 token
+    pub quiet_hours: QuietHours,
+}
+
+impl ProviderSettings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            endpoints: config.model_endpoints.clone(),
+            capture: CaptureSettings {
+                enabled: config.capture_response_headers,
+                value_allowlist: config.capture_header_values.clone(),
+            },
+            deprecations: Arc::new(Deprecations::new(DeprecationWatch {
+                headers: config.deprecation_headers.clone(),
+                fields: config.deprecation_fields.clone(),
+            })),
+            response_limit: ResponseBodyLimit {
+                max_bytes: config.max_response_body_bytes,
+                oversized: config.oversized_response,
+            },
+            refresh: RefreshPolicy {
+                enabled: true,
+                min_interval: Duration::from_secs(config.token_refresh_min_interval_secs),
+                max_retries: config.token_refresh_max_retries,
+                base_delay_ms: config.token_refresh_base_ms,
+            },
+            strict_units: config.strict_provider_config,
+            quiet_hours: config.quiet_hours.clone(),
+        }
+    }
 }
 
 /// 从 providers 目录加载所有 Provider
-pub async fn load_providers(providers_dir: impl AsRef<Path>) -> Result<Vec<Arc<dyn Provider>>> {
+pub async fn load_providers(
+    providers_dir: impl AsRef<Path>,
+    settings: &Arc<ProviderSettings>,
+) -> Result<Vec<Arc<dyn Provider>>> {
     let providers_dir = providers_dir.as_ref();
-    let configs = config::load_all(providers_dir).await?;
+    let configs = config::load_all(providers_dir, settings.strict_units).await?;
 
     if configs.is_empty() {
         tracing::warn!("No providers found. Run 'pluribus login claude-code' to add one.");
@@ -139,10 +584,22 @@ pub async fn load_providers(providers_dir: impl AsRef<Path>) -> Result<Vec<Arc<d
 
     let mut providers: Vec<Arc<dyn Provider>> = Vec::new();
 
+    // 引用未编译进来的 provider 类型时整体失败，而不是静默跳过
+    if let Some((cfg, feature)) = configs
+        .iter()
+        .find_map(|cfg| Some((cfg, cfg.provider_type.missing_feature()?)))
+    {
+        anyhow::bail!(
+            "Provider {} has type {}, but pluribus was built without the `{feature}` feature",
+            cfg.name,
+            cfg.provider_type.as_str()
+        );
+    }
+
     for cfg in configs {
-        match create_provider(providers_dir, cfg) {
+        match create_provider(providers_dir, cfg, settings) {
             Ok(provider) => providers.push(provider),
-            Err(e) => tracing::warn!("Failed to create provider: {}", e),
+            Err(e) => tracing::warn!("Failed to create provider: {:#}", e),
         }
     }
 
@@ -150,12 +607,227 @@ pub async fn load_providers(providers_dir: impl AsRef<Path>) -> Result<Vec<Arc<d
 }
 
 /// 根据配置创建 Provider
-fn create_provider(providers_dir: &Path, config: ProviderConfig) -> Result<Arc<dyn Provider>> {
+fn create_provider(
+    providers_dir: &Path,
+    config: ProviderConfig,
+    settings: &Arc<ProviderSettings>,
+) -> Result<Arc<dyn Provider>> {
+    if let AuthConfig::Api(api) = &config.auth {
+        let url = reqwest::Url::parse(&api.base_url)
+            .with_context(|| format!("Invalid base_url for provider {}", config.name))?;
+        crate::egress::check_url(&url)
+            .with_context(|| format!("Provider {} has a disallowed base_url", config.name))?;
+    }
+
+    let missing_scopes = config.missing_scopes();
+    if !missing_scopes.is_empty() {
+        tracing::warn!(
+            provider = %config.name,
+            missing = %missing_scopes.join(" "),
+            "provider's OAuth grant lacks scopes required for inference, it will not be selected; \
+             run 'pluribus login claude-code' again to re-authorize"
+        );
+    }
+
     match config.provider_type {
         ProviderType::ClaudeCode => {
-            let provider = ClaudeCodeProvider::new(providers_dir.to_path_buf(), config.name)?;
+            let provider = ClaudeCodeProvider::new(
+                providers_dir.to_path_buf(),
+                config.id,
+                config.name,
+                settings.clone(),
+                config.alerts,
+                config.schedule,
+                missing_scopes,
+            )?
+            .with_weight(config.weight);
+            Ok(Arc::new(provider))
+        }
+        ProviderType::Anthropic => {
+            let AuthConfig::Api(api) = config.auth else {
+                anyhow::bail!(
+                    "Provider {} of type anthropic has no [api] section",
+                    config.name
+                );
+            };
+            let provider = AnthropicApiProvider::new(
+                config.id,
+                config.name,
+                api,
+                settings.clone(),
+                config.alerts,
+                config.schedule,
+            )?
+            .with_weight(config.weight);
+            Ok(Arc::new(provider))
+        }
+        #[cfg(feature = "openai-compat")]
+        ProviderType::OpenAI => {
+            let AuthConfig::Api(api) = config.auth else {
+                anyhow::bail!(
+                    "Provider {} of type openai has no [api] section",
+                    config.name
+                );
+            };
+            let provider = OpenAiProvider::new(
+                config.id,
+                config.name,
+                api,
+                settings.clone(),
+                config.alerts,
+                config.schedule,
+            )?
+            .with_weight(config.weight);
             Ok(Arc::new(provider))
         }
         other => anyhow::bail!("Unknown provider type: {other:?}"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn zero_token_counts_are_ok() {
+        let response = json!({"usage": {
+            "input_tokens": 0,
+            "output_tokens": 0,
+            "cache_read_input_tokens": 0,
+            "cache_creation_input_tokens": 0,
+        }});
+        let usage = parse_anthropic_usage(&response).unwrap();
+        assert_eq!(usage.input_tokens, 0);
+        assert_eq!(usage.output_tokens, 0);
+        assert_eq!(usage.cache_read_tokens, 0);
+        assert_eq!(usage.cache_creation_tokens, 0);
+    }
+
+    #[test]
+    fn missing_and_null_cache_counts_are_zero() {
+        let response = json!({"usage": {
+            "input_tokens": 12,
+            "output_tokens": 34,
+            "cache_creation_input_tokens": null,
+        }});
+        let usage = parse_anthropic_usage(&response).unwrap();
+        assert_eq!(usage.input_tokens, 12);
+        assert_eq!(usage.output_tokens, 34);
+        assert_eq!(usage.cache_read_tokens, 0);
+        assert_eq!(usage.cache_creation_tokens, 0);
+    }
+
+    #[test]
+    fn usage_with_zero_cache_fields() {
+        let response = json!({"usage": {
+            "input_tokens": 12,
+            "output_tokens": 34,
+            "cache_read_input_tokens": 0,
+            "cache_creation_input_tokens": 0,
+        }});
+        let usage = parse_anthropic_usage(&response).unwrap();
+        assert_eq!(usage.input_tokens, 12);
+        assert_eq!(usage.output_tokens, 34);
+        assert_eq!(usage.cache_read_tokens, 0);
+        assert_eq!(usage.cache_creation_tokens, 0);
+    }
+
+    #[test]
+    fn usage_with_all_fields() {
+        let response = json!({"usage": {
+            "input_tokens": 12,
+            "output_tokens": 34,
+            "cache_read_input_tokens": 5600,
+            "cache_creation_input_tokens": 780,
+        }});
+        let usage = parse_anthropic_usage(&response).unwrap();
+        assert_eq!(usage.input_tokens, 12);
+        assert_eq!(usage.output_tokens, 34);
+        assert_eq!(usage.cache_read_tokens, 5600);
+        assert_eq!(usage.cache_creation_tokens, 780);
+    }
+
+    #[test]
+    fn missing_required_counts_are_errors() {
+        let missing_input = json!({"usage": {"output_tokens": 34}});
+        assert!(parse_anthropic_usage(&missing_input).is_err());
+        let missing_output = json!({"usage": {"input_tokens": 12}});
+        assert!(parse_anthropic_usage(&missing_output).is_err());
+        assert!(parse_anthropic_usage(&json!({})).is_err());
+    }
+
+    #[tokio::test]
+    async fn openai_providers_need_their_feature() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("vllm.toml"),
+            "type = \"openai\"\n\n[api]\nbase_url = \"https://api.anthropic.com/v1\"\napi_key = \"k\"\n",
+        )
+        .unwrap();
+
+        let result = load_providers(dir.path(), &Arc::default()).await;
+        if cfg!(feature = "openai-compat") {
+            assert_eq!(result.unwrap()[0].provider_type(), ProviderType::OpenAI);
+        } else {
+            let message = format!("{:#}", result.err().unwrap());
+            assert!(message.contains("`openai-compat`"), "{message}");
+        }
+    }
+
+    #[tokio::test]
+    async fn providers_without_inference_scopes_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, scopes) in [
+            ("a-profile-only", "scopes = [\"user:profile\"]\n"),
+            (
+                "b-inference",
+                "scopes = [\"user:profile\", \"user:inference\"]\n",
+            ),
+            // 未记录 scopes 的旧配置视为齐全
+            ("c-legacy", ""),
+        ] {
+            std::fs::write(
+                dir.path().join(format!("{name}.toml")),
+                format!(
+                    "id = \"id-{name}\"\ntype = \"claude_code\"\n\n[oauth]\n\
+                     access_token = \"access\"\nrefresh_token = \"refresh\"\n\
+                     expires_at = 3786912000000\n{scopes}"
+                ),
+            )
+            .unwrap();
+        }
+
+        let providers = load_providers(dir.path(), &Arc::default()).await.unwrap();
+        let mut missing: Vec<(&str, &[String])> = providers
+            .iter()
+            .map(|p| (p.name(), p.missing_scopes()))
+            .collect();
+        missing.sort();
+        assert_eq!(
+            missing,
+            [
+                ("a-profile-only", &["user:inference".to_string()][..]),
+                ("b-inference", &[][..]),
+                ("c-legacy", &[][..]),
+            ]
+        );
+
+        let (_config_dir, config) = crate::test_support::config("");
+        let keys = crate::test_support::keys(&config);
+        let state = crate::gateway::AppState::new(providers.clone(), config, keys);
+        for provider in &providers {
+            let reason = serde_json::to_value(state.skip_reason(provider)).unwrap();
+            let expected = match provider.name() {
+                "a-profile-only" => json!({"reason": "missing_scopes"}),
+                _ => Value::Null,
+            };
+            assert_eq!(reason, expected, "{}", provider.name());
+        }
+        // 无论加载顺序如何都不会选中缺少 scopes 的 provider
+        let selected = state.get_next_provider(|p| p.name() != "c-legacy").unwrap();
+        assert_eq!(selected.name(), "b-inference");
+        let only_deficient = state.get_next_provider(|p| p.name() == "a-profile-only");
+        assert!(only_deficient.is_none());
+    }
+}
@@ -0,0 +1,253 @@
+//! OpenAI 兼容 Provider
+//!
+//! 基于 API key 认证，把 Anthropic Messages 格式的请求转换为 Chat Completions 请求发往
+//! `{base_url}/v1/chat/completions`（`base_url` 已以 `/v1` 结尾时不再追加），响应与流式
+//! chunk 转换回 Anthropic 格式，可与 Claude 账号混用（如 vLLM 部署）。上游没有 rate limit
+//! 信息；非流式响应体超过上限时直接失败，不以流式重发。
+
+mod translate;
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use http::{header, HeaderMap, HeaderValue};
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::egress;
+use crate::providers::claude_code::{
+    check_response_headers, get_api_client, read_body_capped, relay_events,
+};
+use crate::providers::header_capture::{CapturedHeaders, HeaderCapture};
+use crate::providers::{
+    AlertsConfig, ApiConfig, EncodedRequest, Provider, ProviderSettings, ProviderType, Schedule,
+    StreamingResponse, UpstreamError,
+};
+use crate::utils::extract_model;
+use translate::StreamTranslator;
+
+pub struct OpenAiProvider {
+    id: String,
+    name: String,
+    /// Chat Completions 地址
+    url: reqwest::Url,
+    api_key: String,
+    alerts: Option<AlertsConfig>,
+    schedule: Option<Schedule>,
+    weight: u32,
+    response_headers: HeaderCapture,
+    settings: Arc<ProviderSettings>,
+}
+
+impl OpenAiProvider {
+    pub fn new(
+        id: String,
+        name: String,
+        api: ApiConfig,
+        settings: Arc<ProviderSettings>,
+        alerts: Option<AlertsConfig>,
+        schedule: Option<Schedule>,
+    ) -> Result<Self> {
+        let base = api.base_url.trim_end_matches('/');
+        let url = if base.ends_with("/v1") {
+            format!("{base}/chat/completions")
+        } else {
+            format!("{base}/v1/chat/completions")
+        };
+        let url = reqwest::Url::parse(&url)
+            .with_context(|| format!("Invalid base_url for provider {name}"))?;
+        Ok(Self {
+            id,
+            name,
+            url,
+            api_key: api.api_key,
+            alerts,
+            schedule,
+            weight: crate::providers::config::DEFAULT_WEIGHT,
+            response_headers: HeaderCapture::new(settings.capture.clone()),
+            settings,
+        })
+    }
+
+    /// 设置加权选择时的权重
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// 将转换后的请求体发送到上游，非 2xx 响应返回 [`UpstreamError`]
+    async fn post(&self, body: Bytes) -> Result<reqwest::Response> {
+        egress::check_url(&self.url)?;
+        let response = get_api_client()
+            .post(self.url.clone())
+            .headers(build_headers(&self.api_key)?)
+            .body(body)
+            .send()
+            .await
+            .context("Failed to send request to OpenAI-compatible API")?;
+
+        check_response_headers(response.headers())?;
+        self.response_headers.record(&self.name, response.headers());
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_body = response.text().await.unwrap_or_default();
+            return Err(UpstreamError {
+                status,
+                body: error_body,
+            }
+            .into());
+        }
+
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl Provider for OpenAiProvider {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn provider_type(&self) -> ProviderType {
+        ProviderType::OpenAI
+    }
+
+    async fn send_message(&self, request: Value) -> Result<Value> {
+        self.send_message_encoded(self.encode(request, false)?)
+            .await
+    }
+
+    async fn send_streaming(&self, request: Value) -> Result<StreamingResponse> {
+        self.send_streaming_encoded(self.encode(request, true)?)
+            .await
+    }
+
+    fn encode(&self, request: Value, stream: bool) -> Result<EncodedRequest> {
+        let model = extract_model(&request);
+        let body = translate::to_openai_request(&request, stream);
+        let body = serde_json::to_vec(&body).context("Failed to serialize request body")?;
+        Ok(EncodedRequest {
+            body: Bytes::from(body),
+            beta: String::new(),
+            model,
+            tools_spoofed: false,
+        })
+    }
+
+    async fn send_message_encoded(&self, request: EncodedRequest) -> Result<Value> {
+        let response = self.post(request.body).await?;
+        let body = read_body_capped(response, self.settings.response_limit.max_bytes).await?;
+        let response: Value = serde_json::from_slice(&body)
+            .context("Failed to parse OpenAI-compatible API response")?;
+        Ok(translate::from_openai_response(&response))
+    }
+
+    async fn send_streaming_encoded(&self, request: EncodedRequest) -> Result<StreamingResponse> {
+        let response = self.post(request.body).await?;
+        let status = response.status();
+        let events = translate_stream(response.bytes_stream(), request.model.clone());
+        Ok(relay_events(
+            events,
+            status,
+            self.name.clone(),
+            request.model,
+            self.settings.deprecations.clone(),
+        ))
+    }
+
+    fn captured_headers(&self) -> Option<CapturedHeaders> {
+        Some(self.response_headers.snapshot())
+    }
+
+    fn alerts(&self) -> Option<&AlertsConfig> {
+        self.alerts.as_ref()
+    }
+
+    fn schedule(&self) -> Option<&Schedule> {
+        self.schedule.as_ref()
+    }
+
+    fn weight(&self) -> u32 {
+        self.weight
+    }
+}
+
+fn build_headers(api_key: &str) -> Result<HeaderMap> {
+    let mut map = HeaderMap::new();
+    map.insert(
+        header::AUTHORIZATION,
+        HeaderValue::from_str(&format!("Bearer {api_key}"))
+            .context("Invalid api_key for header")?,
+    );
+    map.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    map.insert(
+        header::USER_AGENT,
+        HeaderValue::from_static(concat!("pluribus/", env!("CARGO_PKG_VERSION"))),
+    );
+    Ok(map)
+}
+
+type UpstreamChunk = std::result::Result<Bytes, reqwest::Error>;
+
+/// 把上游的 OpenAI chunk 流转换为 Anthropic SSE 事件流
+fn translate_stream(
+    upstream: impl Stream<Item = UpstreamChunk> + Send + 'static,
+    model: String,
+) -> impl Stream<Item = UpstreamChunk> + Send + 'static {
+    struct State<S> {
+        upstream: std::pin::Pin<Box<S>>,
+        translator: StreamTranslator,
+        buffer: String,
+        done: bool,
+    }
+
+    let state = State {
+        upstream: Box::pin(upstream),
+        translator: StreamTranslator::new(model),
+        buffer: String::new(),
+        done: false,
+    };
+    futures::stream::unfold(state, |mut state| async move {
+        while !state.done {
+            match state.upstream.next().await {
+                Some(Ok(chunk)) => {
+                    state.buffer.push_str(&String::from_utf8_lossy(&chunk));
+                    let mut out = String::new();
+                    while let Some(pos) = state.buffer.find("\n\n") {
+                        for line in state.buffer[..pos].lines() {
+                            if let Some(data) = line.strip_prefix("data:").map(str::trim) {
+                                out.push_str(&state.translator.push(data));
+                            }
+                        }
+                        state.buffer.drain(..pos + 2);
+                    }
+                    if !out.is_empty() {
+                        return Some((Ok(Bytes::from(out)), state));
+                    }
+                }
+                Some(Err(e)) => {
+                    // 不补发结束事件，由转发任务按上游失败处理
+                    state.done = true;
+                    return Some((Err(e), state));
+                }
+                None => {
+                    state.done = true;
+                    let out = state.translator.finish();
+                    if !out.is_empty() {
+                        return Some((Ok(Bytes::from(out)), state));
+                    }
+                }
+            }
+        }
+        None
+    })
+}
@@ -0,0 +1,511 @@
+//! Anthropic Messages 与 OpenAI Chat Completions 格式互转
+//!
+//! 请求：`system` 转为 system 消息，内容块按类型拆分为 user / assistant / tool 消息，
+//! `tool_use` 与 `tool_result` 对应 `tool_calls` 与 tool 消息。响应与流式 chunk 转换回
+//! Anthropic 的 message 与 SSE 事件，usage 中 `prompt_tokens` 扣除缓存命中部分后作为
+//! `input_tokens`。thinking 块与服务端工具没有对应形式，转换时忽略。
+
+use serde_json::{json, Map, Value};
+
+/// 转换为 OpenAI 请求
+pub fn to_openai_request(request: &Value, stream: bool) -> Value {
+    let mut messages = Vec::new();
+    if let Some(system) = request.get("system").and_then(text_of) {
+        messages.push(json!({ "role": "system", "content": system }));
+    }
+    for message in request
+        .get("messages")
+        .and_then(|m| m.as_array())
+        .into_iter()
+        .flatten()
+    {
+        match message.get("role").and_then(|r| r.as_str()) {
+            Some("assistant") => messages.push(assistant_message(message.get("content"))),
+            _ => messages.extend(user_messages(message.get("content"))),
+        }
+    }
+
+    let mut out = Map::new();
+    out.insert(
+        "model".to_string(),
+        request.get("model").cloned().unwrap_or(Value::Null),
+    );
+    out.insert("messages".to_string(), Value::Array(messages));
+    for (from, to) in [
+        ("max_tokens", "max_tokens"),
+        ("temperature", "temperature"),
+        ("top_p", "top_p"),
+        ("stop_sequences", "stop"),
+        // 字段策略由 thinking 转换而来
+        ("reasoning_effort", "reasoning_effort"),
+    ] {
+        if let Some(value) = request.get(from) {
+            out.insert(to.to_string(), value.clone());
+        }
+    }
+
+    let tools: Vec<Value> = request
+        .get("tools")
+        .and_then(|t| t.as_array())
+        .into_iter()
+        .flatten()
+        // 带其他 type 的是服务端工具（如 web_search），没有对应形式
+        .filter(|tool| tool.get("type").is_none_or(|t| t == "custom"))
+        .map(|tool| {
+            let mut function = Map::new();
+            function.insert(
+                "name".to_string(),
+                tool.get("name").cloned().unwrap_or(Value::Null),
+            );
+            if let Some(description) = tool.get("description") {
+                function.insert("description".to_string(), description.clone());
+            }
+            function.insert(
+                "parameters".to_string(),
+                tool.get("input_schema")
+                    .cloned()
+                    .unwrap_or_else(|| json!({ "type": "object" })),
+            );
+            json!({ "type": "function", "function": function })
+        })
+        .collect();
+    if !tools.is_empty() {
+        out.insert("tools".to_string(), Value::Array(tools));
+        if let Some(choice) = request.get("tool_choice") {
+            let mapped = match choice.get("type").and_then(|t| t.as_str()) {
+                Some("any") => json!("required"),
+                Some("none") => json!("none"),
+                Some("tool") => json!({
+                    "type": "function",
+                    "function": { "name": choice.get("name").cloned().unwrap_or(Value::Null) },
+                }),
+                _ => json!("auto"),
+            };
+            out.insert("tool_choice".to_string(), mapped);
+            if choice.get("disable_parallel_tool_use") == Some(&Value::Bool(true)) {
+                out.insert("parallel_tool_calls".to_string(), Value::Bool(false));
+            }
+        }
+    }
+
+    out.insert("stream".to_string(), Value::Bool(stream));
+    if stream {
+        out.insert(
+            "stream_options".to_string(),
+            json!({ "include_usage": true }),
+        );
+    }
+    Value::Object(out)
+}
+
+/// 字符串或文本块数组中的文本，多个块以空行连接
+fn text_of(content: &Value) -> Option<String> {
+    match content {
+        Value::String(text) => Some(text.clone()),
+        Value::Array(blocks) => {
+            let texts: Vec<&str> = blocks
+                .iter()
+                .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("text"))
+                .filter_map(|block| block.get("text").and_then(|t| t.as_str()))
+                .collect();
+            (!texts.is_empty()).then(|| texts.join("\n\n"))
+        }
+        _ => None,
+    }
+}
+
+/// user 消息：`tool_result` 块转为 tool 消息并放在前面，紧跟上一条 assistant 的 tool_calls
+fn user_messages(content: Option<&Value>) -> Vec<Value> {
+    let blocks = match content {
+        Some(Value::Array(blocks)) => blocks,
+        Some(content) => return vec![json!({ "role": "user", "content": content })],
+        None => return Vec::new(),
+    };
+
+    let mut messages = Vec::new();
+    let mut parts = Vec::new();
+    for block in blocks {
+        match block.get("type").and_then(|t| t.as_str()) {
+            Some("text") => parts.push(json!({
+                "type": "text",
+                "text": block.get("text").cloned().unwrap_or_default(),
+            })),
+            Some("image") => {
+                if let Some(url) = image_url(block.get("source")) {
+                    parts.push(json!({ "type": "image_url", "image_url": { "url": url } }));
+                }
+            }
+            Some("tool_result") => {
+                let content = block.get("content").and_then(text_of).unwrap_or_default();
+                messages.push(json!({
+                    "role": "tool",
+                    "tool_call_id": block.get("tool_use_id").cloned().unwrap_or_default(),
+                    "content": content,
+                }));
+            }
+            _ => {}
+        }
+    }
+    if !parts.is_empty() {
+        messages.push(json!({ "role": "user", "content": parts }));
+    }
+    messages
+}
+
+/// 图片来源转为 URL，base64 数据使用 data URL
+fn image_url(source: Option<&Value>) -> Option<String> {
+    let source = source?;
+    match source.get("type").and_then(|t| t.as_str())? {
+        "base64" => Some(format!(
+            "data:{};base64,{}",
+            source.get("media_type")?.as_str()?,
+            source.get("data")?.as_str()?
+        )),
+        "url" => Some(source.get("url")?.as_str()?.to_string()),
+        _ => None,
+    }
+}
+
+/// assistant 消息：文本块合并为 content，`tool_use` 块转为 tool_calls
+fn assistant_message(content: Option<&Value>) -> Value {
+    let mut message = Map::new();
+    message.insert("role".to_string(), json!("assistant"));
+    let blocks = match content {
+        Some(Value::Array(blocks)) => blocks,
+        Some(content) => {
+            message.insert("content".to_string(), content.clone());
+            return Value::Object(message);
+        }
+        None => return Value::Object(message),
+    };
+
+    let tool_calls: Vec<Value> = blocks
+        .iter()
+        .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("tool_use"))
+        .map(|block| {
+            let input = block.get("input").cloned().unwrap_or_else(|| json!({}));
+            json!({
+                "id": block.get("id").cloned().unwrap_or_default(),
+                "type": "function",
+                "function": {
+                    "name": block.get("name").cloned().unwrap_or_default(),
+                    "arguments": input.to_string(),
+                },
+            })
+        })
+        .collect();
+    message.insert(
+        "content".to_string(),
+        content.and_then(text_of).map_or(Value::Null, Value::String),
+    );
+    if !tool_calls.is_empty() {
+        message.insert("tool_calls".to_string(), Value::Array(tool_calls));
+    }
+    Value::Object(message)
+}
+
+/// 结束原因转换为 `stop_reason`
+fn stop_reason(finish_reason: &str) -> &'static str {
+    match finish_reason {
+        "length" => "max_tokens",
+        "tool_calls" | "function_call" => "tool_use",
+        "content_filter" => "refusal",
+        _ => "end_turn",
+    }
+}
+
+/// OpenAI usage 转换为 Anthropic usage
+fn usage_of(usage: Option<&Value>) -> Value {
+    let get = |pointer: &str| {
+        usage
+            .and_then(|u| u.pointer(pointer))
+            .and_then(|v| v.as_u64())
+            .unwrap_or(0)
+    };
+    let cached = get("/prompt_tokens_details/cached_tokens");
+    json!({
+        "input_tokens": get("/prompt_tokens").saturating_sub(cached),
+        "output_tokens": get("/completion_tokens"),
+        "cache_read_input_tokens": cached,
+        "cache_creation_input_tokens": 0,
+    })
+}
+
+/// tool call 的参数字符串解析为 input，不是合法 JSON 时为空对象
+fn tool_input(arguments: Option<&Value>) -> Value {
+    arguments
+        .and_then(|a| a.as_str())
+        .filter(|a| !a.trim().is_empty())
+        .and_then(|a| serde_json::from_str(a).ok())
+        .unwrap_or_else(|| json!({}))
+}
+
+/// 转换 OpenAI 非流式响应
+pub fn from_openai_response(response: &Value) -> Value {
+    let choice = response.pointer("/choices/0");
+    let message = choice.and_then(|c| c.get("message"));
+
+    let mut content = Vec::new();
+    if let Some(text) = message
+        .and_then(|m| m.get("content"))
+        .and_then(|c| c.as_str())
+        .filter(|text| !text.is_empty())
+    {
+        content.push(json!({ "type": "text", "text": text }));
+    }
+    for call in message
+        .and_then(|m| m.get("tool_calls"))
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+    {
+        content.push(json!({
+            "type": "tool_use",
+            "id": call.get("id").cloned().unwrap_or_default(),
+            "name": call.pointer("/function/name").cloned().unwrap_or_default(),
+            "input": tool_input(call.pointer("/function/arguments")),
+        }));
+    }
+
+    let finish_reason = choice
+        .and_then(|c| c.get("finish_reason"))
+        .and_then(|r| r.as_str())
+        .unwrap_or("stop");
+    json!({
+        "id": response.get("id").cloned().unwrap_or_default(),
+        "type": "message",
+        "role": "assistant",
+        "model": response.get("model").cloned().unwrap_or_default(),
+        "content": content,
+        "stop_reason": stop_reason(finish_reason),
+        "stop_sequence": null,
+        "usage": usage_of(response.get("usage")),
+    })
+}
+
+/// 当前打开的内容块
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OpenBlock {
+    Text,
+    /// OpenAI tool call 的 index
+    ToolUse(u64),
+}
+
+/// 把 OpenAI 流式 chunk 转换为 Anthropic SSE 事件
+///
+/// 第一个 chunk 发送 `message_start`；文本与各个 tool call 依次作为内容块，切换时关闭上一个；
+/// `[DONE]` 或流结束时发送 `message_delta`（含 stop_reason 与 usage）与 `message_stop`
+pub struct StreamTranslator {
+    model: String,
+    started: bool,
+    finished: bool,
+    /// 下一个内容块的 index
+    next_index: u64,
+    open: Option<(OpenBlock, u64)>,
+    stop_reason: &'static str,
+    usage: Option<Value>,
+}
+
+impl StreamTranslator {
+    pub fn new(model: String) -> Self {
+        Self {
+            model,
+            started: false,
+            finished: false,
+            next_index: 0,
+            open: None,
+            stop_reason: "end_turn",
+            usage: None,
+        }
+    }
+
+    /// 处理一个 `data:` 的内容，返回转换后的 SSE 文本
+    pub fn push(&mut self, data: &str) -> String {
+        let mut out = String::new();
+        if self.finished {
+            return out;
+        }
+        if data == "[DONE]" {
+            self.finish_into(&mut out);
+            return out;
+        }
+        let Ok(chunk) = serde_json::from_str::<Value>(data) else {
+            return out;
+        };
+        if let Some(error) = chunk.get("error") {
+            let message = error
+                .get("message")
+                .and_then(|m| m.as_str())
+                .unwrap_or("Upstream stream error");
+            emit(
+                &mut out,
+                "error",
+                json!({ "type": "error", "error": { "type": "api_error", "message": message } }),
+            );
+            self.finished = true;
+            return out;
+        }
+
+        if !self.started {
+            self.started = true;
+            let id = chunk.get("id").and_then(|i| i.as_str()).unwrap_or_default();
+            let model = chunk
+                .get("model")
+                .and_then(|m| m.as_str())
+                .unwrap_or(&self.model);
+            emit(
+                &mut out,
+                "message_start",
+                json!({
+                    "type": "message_start",
+                    "message": {
+                        "id": id,
+                        "type": "message",
+                        "role": "assistant",
+                        "model": model,
+                        "content": [],
+                        "stop_reason": null,
+                        "stop_sequence": null,
+                        "usage": { "input_tokens": 0, "output_tokens": 0 },
+                    },
+                }),
+            );
+        }
+
+        if let Some(usage) = chunk.get("usage").filter(|u| !u.is_null()) {
+            self.usage = Some(usage.clone());
+        }
+        let Some(choice) = chunk.pointer("/choices/0") else {
+            return out;
+        };
+        let delta = choice.get("delta");
+
+        if let Some(text) = delta
+            .and_then(|d| d.get("content"))
+            .and_then(|c| c.as_str())
+            .filter(|text| !text.is_empty())
+        {
+            let index = self.open_block(
+                &mut out,
+                OpenBlock::Text,
+                || json!({ "type": "text", "text": "" }),
+            );
+            emit(
+                &mut out,
+                "content_block_delta",
+                json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": { "type": "text_delta", "text": text },
+                }),
+            );
+        }
+
+        for call in delta
+            .and_then(|d| d.get("tool_calls"))
+            .and_then(|c| c.as_array())
+            .into_iter()
+            .flatten()
+        {
+            let call_index = call.get("index").and_then(|i| i.as_u64()).unwrap_or(0);
+            let index = self.open_block(&mut out, OpenBlock::ToolUse(call_index), || {
+                json!({
+                    "type": "tool_use",
+                    "id": call.get("id").cloned().unwrap_or_default(),
+                    "name": call.pointer("/function/name").cloned().unwrap_or_default(),
+                    "input": {},
+                })
+            });
+            if let Some(arguments) = call
+                .pointer("/function/arguments")
+                .and_then(|a| a.as_str())
+                .filter(|a| !a.is_empty())
+            {
+                emit(
+                    &mut out,
+                    "content_block_delta",
+                    json!({
+                        "type": "content_block_delta",
+                        "index": index,
+                        "delta": { "type": "input_json_delta", "partial_json": arguments },
+                    }),
+                );
+            }
+        }
+
+        if let Some(reason) = choice.get("finish_reason").and_then(|r| r.as_str()) {
+            self.stop_reason = stop_reason(reason);
+        }
+        out
+    }
+
+    /// 上游流正常结束但没有 `[DONE]` 时补发结束事件
+    pub fn finish(&mut self) -> String {
+        let mut out = String::new();
+        if self.started && !self.finished {
+            self.finish_into(&mut out);
+        }
+        out
+    }
+
+    /// 返回 `block` 的 index，与当前打开的块不同时关闭当前块并打开新块
+    fn open_block(
+        &mut self,
+        out: &mut String,
+        block: OpenBlock,
+        content_block: impl FnOnce() -> Value,
+    ) -> u64 {
+        if let Some((open, index)) = self.open {
+            if open == block {
+                return index;
+            }
+            close_block(out, index);
+        }
+        let index = self.next_index;
+        self.next_index += 1;
+        self.open = Some((block, index));
+        emit(
+            out,
+            "content_block_start",
+            json!({
+                "type": "content_block_start",
+                "index": index,
+                "content_block": content_block(),
+            }),
+        );
+        index
+    }
+
+    fn finish_into(&mut self, out: &mut String) {
+        self.finished = true;
+        if let Some((_, index)) = self.open.take() {
+            close_block(out, index);
+        }
+        emit(
+            out,
+            "message_delta",
+            json!({
+                "type": "message_delta",
+                "delta": { "stop_reason": self.stop_reason, "stop_sequence": null },
+                "usage": usage_of(self.usage.as_ref()),
+            }),
+        );
+        emit(out, "message_stop", json!({ "type": "message_stop" }));
+    }
+}
+
+fn close_block(out: &mut String, index: u64) {
+    emit(
+        out,
+        "content_block_stop",
+        json!({ "type": "content_block_stop", "index": index }),
+    );
+}
+
+fn emit(out: &mut String, event: &str, data: Value) {
+    out.push_str("event: ");
+    out.push_str(event);
+    out.push_str("\ndata: ");
+    out.push_str(&data.to_string());
+    out.push_str("\n\n");
+}
@@ -0,0 +1,630 @@
+//! SSE 事件处理
+//!
+//! 流式响应各层（relay 及后续的格式转换层）共享的事件解析与事件类型策略：
+//! - 已知事件（包括 `ping` 等控制事件）原样透传
+//! - 未知事件同样原样透传，不丢弃也不重排，记录 debug 日志并计数 `unknown_sse_event_total{type}`
+//! - 格式转换层遇到 [`EventKind::Unknown`] 时应视为 no-op，而不是报错
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+
+use crate::metrics::UNKNOWN_SSE_EVENTS;
+use crate::providers::{parse_anthropic_usage, parse_delta_usage, Usage};
+use crate::stats::{self, TaskKind};
+
+/// NDJSON 输出的 Content-Type
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+const NDJSON_CHANNEL_BUFFER: usize = 32;
+
+/// 请求流式响应校验和的 header，目前仅支持 `sha256`
+pub const STREAM_CHECKSUM_HEADER: &str = "x-pluribus-stream-checksum";
+
+/// Anthropic Messages API 已知的流式事件类型
+pub const KNOWN_EVENT_TYPES: &[&str] = &[
+    "message_start",
+    "content_block_start",
+    "content_block_delta",
+    "content_block_stop",
+    "message_delta",
+    "message_stop",
+    "ping",
+    "error",
+];
+
+/// 事件类型分类
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// 已知事件
+    Known,
+    /// 未知事件（上游新增的类型等）
+    Unknown,
+}
+
+/// 从 SSE data 行解析 JSON
+pub fn parse_data(line: &str) -> Option<Value> {
+    line.strip_prefix("data: ")
+        .and_then(|json_str| serde_json::from_str(json_str).ok())
+}
+
+/// 读取事件 JSON 中的 `type` 字段
+pub fn event_type(data: &Value) -> Option<&str> {
+    data.get("type").and_then(|t| t.as_str())
+}
+
+/// 按策略分类事件类型，未知类型会记录 debug 日志并计数
+pub fn classify(event_type: &str) -> EventKind {
+    if KNOWN_EVENT_TYPES.contains(&event_type) {
+        return EventKind::Known;
+    }
+
+    tracing::debug!(event_type, "passing through unknown SSE event");
+    UNKNOWN_SSE_EVENTS.with_label_values(&[event_type]).inc();
+    EventKind::Unknown
+}
+
+/// 流式响应的输出格式，由请求的 `Accept` header 协商
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StreamFormat {
+    /// 原样输出 SSE
+    #[default]
+    Sse,
+    /// 每行一个 JSON 对象：`{"event_type": ..., "data": ...}`，最后一行为累计的 usage
+    Ndjson,
+}
+
+impl StreamFormat {
+    pub fn from_accept(accept: Option<&str>) -> Self {
+        let wants_ndjson = accept.is_some_and(|accept| {
+            accept.split(',').any(|media| {
+                media
+                    .split(';')
+                    .next()
+                    .is_some_and(|m| m.trim().eq_ignore_ascii_case(NDJSON_CONTENT_TYPE))
+            })
+        });
+
+        if wants_ndjson {
+            StreamFormat::Ndjson
+        } else {
+            StreamFormat::Sse
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            StreamFormat::Sse => "text/event-stream",
+            StreamFormat::Ndjson => NDJSON_CONTENT_TYPE,
+        }
+    }
+}
+
+/// 流式响应中途失败的原因
+///
+/// 以 `io::Error` 的内部错误沿流的各层传递，到达客户端前由 [`error_event`] 转换为错误事件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFailure {
+    /// 上游连接中途断开或读取出错
+    UpstreamDisconnect,
+    /// 上游长时间未发送数据，读取超时
+    IdleTimeout,
+    /// 流超过最长转发时长，被看门狗中止
+    MaxAge,
+    /// 被 admin 中止
+    Aborted,
+    /// 关闭时排空超时
+    GatewayShutdown,
+}
+
+impl StreamFailure {
+    /// 错误事件中的 `code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            StreamFailure::UpstreamDisconnect => "upstream_disconnect",
+            StreamFailure::IdleTimeout => "idle_timeout",
+            StreamFailure::MaxAge => "stream_max_age",
+            StreamFailure::Aborted => "aborted_by_admin",
+            StreamFailure::GatewayShutdown => "gateway_shutdown",
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            StreamFailure::UpstreamDisconnect => "Upstream connection closed mid-stream",
+            StreamFailure::IdleTimeout => "Upstream stopped sending data",
+            StreamFailure::MaxAge => "Stream exceeded its maximum age",
+            StreamFailure::Aborted => "Stream aborted by admin",
+            StreamFailure::GatewayShutdown => "Gateway is shutting down",
+        }
+    }
+
+    /// 从流中的错误取出失败原因，其他错误返回 `None`
+    pub fn of(err: &(dyn std::error::Error + 'static)) -> Option<Self> {
+        let mut current = Some(err);
+        while let Some(err) = current {
+            if let Some(failure) = err.downcast_ref::<StreamFailure>() {
+                return Some(*failure);
+            }
+            // 自定义 io::Error 的 source() 跳过了内部错误本身
+            if let Some(failure) = err
+                .downcast_ref::<std::io::Error>()
+                .and_then(|e| e.get_ref())
+                .and_then(|e| e.downcast_ref::<StreamFailure>())
+            {
+                return Some(*failure);
+            }
+            current = err.source();
+        }
+        None
+    }
+}
+
+impl std::fmt::Display for StreamFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for StreamFailure {}
+
+impl From<StreamFailure> for std::io::Error {
+    fn from(failure: StreamFailure) -> Self {
+        std::io::Error::other(failure)
+    }
+}
+
+/// 流中途失败时发给客户端的最后一个事件
+///
+/// 与 Anthropic 的错误事件结构一致，`error.code` 说明失败原因，`request_id` 为网关的请求 ID
+pub fn error_event(failure: StreamFailure, request_id: u64, format: StreamFormat) -> Bytes {
+    let data = serde_json::json!({
+        "type": "error",
+        "error": {
+            "type": "api_error",
+            "code": failure.code(),
+            "message": failure.message(),
+        },
+        "request_id": request_id.to_string(),
+    });
+    Bytes::from(match format {
+        StreamFormat::Sse => format!("event: error\ndata: {data}\n\n"),
+        StreamFormat::Ndjson => {
+            format!(
+                "{}\n",
+                serde_json::json!({ "event_type": "error", "data": data })
+            )
+        }
+    })
+}
+
+/// SSE → NDJSON 转换器，按事件边界切分并累计 usage
+#[derive(Default)]
+struct NdjsonEncoder {
+    buffer: String,
+    usage: Usage,
+}
+
+impl NdjsonEncoder {
+    /// 写入一段 SSE 字节，返回已完整的事件对应的 NDJSON 行
+    fn push(&mut self, chunk: &[u8]) -> String {
+        self.buffer.push_str(&String::from_utf8_lossy(chunk));
+
+        let mut out = String::new();
+        while let Some(pos) = self.buffer.find("\n\n") {
+            let event: String = self.buffer.drain(..pos + 2).collect();
+            self.encode_event(&event, &mut out);
+        }
+        out
+    }
+
+    /// 流结束：输出剩余事件及累计的 usage
+    fn finish(mut self) -> String {
+        let mut out = String::new();
+        let rest = std::mem::take(&mut self.buffer);
+        self.encode_event(&rest, &mut out);
+
+        let line = serde_json::json!({ "event_type": "usage", "data": self.usage });
+        out.push_str(&line.to_string());
+        out.push('\n');
+        out
+    }
+
+    fn encode_event(&mut self, event: &str, out: &mut String) {
+        let mut name = None;
+        let mut data_lines = Vec::new();
+        for line in event.lines() {
+            if let Some(value) = line.strip_prefix("event:") {
+                name = Some(value.trim());
+            } else if let Some(value) = line.strip_prefix("data:") {
+                data_lines.push(value.strip_prefix(' ').unwrap_or(value));
+            }
+        }
+        if data_lines.is_empty() {
+            return;
+        }
+
+        let raw = data_lines.join("\n");
+        let data = serde_json::from_str(&raw).unwrap_or(Value::String(raw));
+        let event_type = event_type(&data).or(name).unwrap_or("unknown").to_string();
+
+        match event_type.as_str() {
+            "message_start" => match data.get("message").map(parse_anthropic_usage) {
+                Some(Ok(usage)) => self.usage.merge_from(&usage),
+                Some(Err(e)) => tracing::debug!("ignoring message_start usage: {:#}", e),
+                None => {}
+            },
+            "message_delta" => match parse_delta_usage(&data) {
+                Ok(usage) => self.usage.merge_from(&usage),
+                Err(e) => tracing::debug!("ignoring message_delta usage: {:#}", e),
+            },
+            _ => {}
+        }
+
+        let line = serde_json::json!({ "event_type": event_type, "data": data });
+        out.push_str(&line.to_string());
+        out.push('\n');
+    }
+}
+
+/// 读取上游的下一块数据，客户端先断开时返回 `None`，上游空闲时也能及时退出
+async fn next_or_closed<S, T>(upstream: &mut S, tx: &mpsc::Sender<T>) -> Option<S::Item>
+where
+    S: Stream + Unpin,
+{
+    tokio::select! {
+        chunk = upstream.next() => chunk,
+        _ = tx.closed() => {
+            tracing::debug!("client disconnected");
+            None
+        }
+    }
+}
+
+/// 将 SSE 字节流转换为 NDJSON 字节流
+pub fn to_ndjson<S>(
+    upstream: S,
+) -> Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send + Unpin>
+where
+    S: Stream<Item = Result<Bytes, std::io::Error>> + Send + Unpin + 'static,
+{
+    let (tx, rx) = mpsc::channel(NDJSON_CHANNEL_BUFFER);
+
+    stats::spawn(TaskKind::NdjsonEncoder, async move {
+        let mut upstream = upstream;
+        let mut encoder = NdjsonEncoder::default();
+
+        while let Some(chunk) = next_or_closed(&mut upstream, &tx).await {
+            let item = chunk.map(|chunk| Bytes::from(encoder.push(&chunk)));
+            let failed = item.is_err();
+            if tx.send(item).await.is_err() {
+                tracing::debug!("client disconnected");
+                return;
+            }
+            if failed {
+                return;
+            }
+        }
+
+        let _ = tx.send(Ok(Bytes::from(encoder.finish()))).await;
+    });
+
+    Box::new(tokio_stream::wrappers::ReceiverStream::new(rx))
+}
+
+/// 在流末尾追加 `stream_checksum` 事件，内容为此前所有输出字节的 SHA-256
+///
+/// 上游出错时不追加校验和事件，客户端可据此判断流不完整
+pub fn with_checksum<S>(
+    upstream: S,
+    format: StreamFormat,
+) -> Box<dyn Stream<Item = Result<Bytes, std::io::Error>> + Send + Unpin>
+where
+    S: Stream<Item = Result<Bytes, std::io::Error>> + Send + Unpin + 'static,
+{
+    let (tx, rx) = mpsc::channel(NDJSON_CHANNEL_BUFFER);
+
+    stats::spawn(TaskKind::StreamChecksum, async move {
+        let mut upstream = upstream;
+        let mut hasher = Sha256::new();
+
+        while let Some(chunk) = next_or_closed(&mut upstream, &tx).await {
+            if let Ok(chunk) = &chunk {
+                hasher.update(chunk);
+            }
+            let failed = chunk.is_err();
+            if tx.send(chunk).await.is_err() {
+                tracing::debug!("client disconnected");
+                return;
+            }
+            if failed {
+                return;
+            }
+        }
+
+        let data = serde_json::json!({
+            "type": "stream_checksum",
+            "sha256": format!("{:x}", hasher.finalize()),
+        });
+        let event = match format {
+            StreamFormat::Sse => format!("event: stream_checksum\ndata: {data}\n\n"),
+            StreamFormat::Ndjson => {
+                let line = serde_json::json!({ "event_type": "stream_checksum", "data": data });
+                format!("{line}\n")
+            }
+        };
+        let _ = tx.send(Ok(Bytes::from(event))).await;
+    });
+
+    Box::new(tokio_stream::wrappers::ReceiverStream::new(rx))
+}
+
+/// 把流式事件还原为非流式响应的 message
+#[derive(Default)]
+pub struct MessageAssembler {
+    message: Option<Value>,
+    blocks: Vec<Value>,
+    /// 各块 `input_json_delta` 拼接出的 JSON 文本
+    partial_json: Vec<String>,
+}
+
+impl MessageAssembler {
+    fn block(&mut self, data: &Value) -> Option<(usize, &mut Value)> {
+        let index = data.get("index")?.as_u64()? as usize;
+        if index >= self.blocks.len() {
+            self.blocks.resize(index + 1, Value::Null);
+            self.partial_json.resize(index + 1, String::new());
+        }
+        Some((index, &mut self.blocks[index]))
+    }
+
+    /// 处理一个流式事件
+    pub fn push(&mut self, data: &Value) {
+        match event_type(data) {
+            Some("message_start") => self.message = data.get("message").cloned(),
+            Some("content_block_start") => {
+                let start = data.get("content_block").cloned().unwrap_or(Value::Null);
+                if let Some((_, block)) = self.block(data) {
+                    *block = start;
+                }
+            }
+            Some("content_block_delta") => {
+                let Some(delta) = data.get("delta") else {
+                    return;
+                };
+                let Some((index, block)) = self.block(data) else {
+                    return;
+                };
+                let append = |block: &mut Value, field: &str, key: &str| {
+                    let piece = delta.get(key).and_then(|v| v.as_str()).unwrap_or_default();
+                    if let Some(obj) = block.as_object_mut() {
+                        let current = obj
+                            .entry(field)
+                            .or_insert_with(|| Value::String(String::new()));
+                        if let Value::String(text) = current {
+                            text.push_str(piece);
+                        }
+                    }
+                };
+                match event_type(delta) {
+                    Some("text_delta") => append(block, "text", "text"),
+                    Some("thinking_delta") => append(block, "thinking", "thinking"),
+                    Some("signature_delta") => append(block, "signature", "signature"),
+                    Some("input_json_delta") => {
+                        let piece = delta.get("partial_json").and_then(|v| v.as_str());
+                        self.partial_json[index].push_str(piece.unwrap_or_default());
+                    }
+                    Some("citations_delta") => {
+                        if let (Some(obj), Some(citation)) =
+                            (block.as_object_mut(), delta.get("citation"))
+                        {
+                            let citations = obj
+                                .entry("citations")
+                                .or_insert_with(|| Value::Array(Vec::new()));
+                            if let Value::Array(list) = citations {
+                                list.push(citation.clone());
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Some("content_block_stop") => {
+                if let Some((index, _)) = self.block(data) {
+                    self.finish_input(index);
+                }
+            }
+            Some("message_delta") => {
+                let Some(message) = self.message.as_mut().and_then(|m| m.as_object_mut()) else {
+                    return;
+                };
+                if let Some(delta) = data.get("delta").and_then(|d| d.as_object()) {
+                    for (key, value) in delta {
+                        message.insert(key.clone(), value.clone());
+                    }
+                }
+                if let Some(usage) = data.get("usage").and_then(|u| u.as_object()) {
+                    let target = message
+                        .entry("usage")
+                        .or_insert_with(|| Value::Object(Default::default()));
+                    if let Some(target) = target.as_object_mut() {
+                        for (key, value) in usage {
+                            target.insert(key.clone(), value.clone());
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// 把拼接好的 JSON 写入块的 `input`，不完整时保留原值
+    fn finish_input(&mut self, index: usize) {
+        let json = std::mem::take(&mut self.partial_json[index]);
+        if json.is_empty() {
+            return;
+        }
+        if let (Some(obj), Ok(input)) = (
+            self.blocks[index].as_object_mut(),
+            serde_json::from_str::<Value>(&json),
+        ) {
+            obj.insert("input".to_string(), input);
+        }
+    }
+
+    /// 还原出的 message；`truncated` 时丢弃未结束的块并把 `stop_reason` 设为 `max_tokens`
+    pub fn finish(mut self, truncated: bool) -> Option<Value> {
+        let mut message = self.message.take()?;
+        let mut blocks = std::mem::take(&mut self.blocks);
+        if truncated {
+            // 未收到 stop 的块仍有未拼接的 JSON，不是完整的块
+            let complete = self
+                .partial_json
+                .iter()
+                .take_while(|json| json.is_empty())
+                .count();
+            blocks.truncate(complete);
+        }
+        blocks.retain(|block| !block.is_null());
+        let obj = message.as_object_mut()?;
+        obj.insert("content".to_string(), Value::Array(blocks));
+        if truncated {
+            obj.insert(
+                "stop_reason".to_string(),
+                Value::String("max_tokens".to_string()),
+            );
+        }
+        Some(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{message_events, sse};
+
+    /// 夹带 `ping` 与虚构的 `content_block_meta` 事件的流
+    fn events_with_extras() -> Vec<Value> {
+        let mut events = message_events("hi");
+        events.insert(1, serde_json::json!({"type": "ping"}));
+        events.insert(
+            3,
+            serde_json::json!({"type": "content_block_meta", "index": 0}),
+        );
+        events
+    }
+
+    #[test]
+    fn known_events_are_classified_without_counting() {
+        for event_type in KNOWN_EVENT_TYPES {
+            assert_eq!(classify(event_type), EventKind::Known, "{event_type}");
+        }
+        #[cfg(feature = "metrics")]
+        for event_type in KNOWN_EVENT_TYPES {
+            let count = UNKNOWN_SSE_EVENTS.with_label_values(&[event_type]).get();
+            assert_eq!(count, 0, "{event_type}");
+        }
+    }
+
+    #[test]
+    fn unknown_events_are_counted_by_type() {
+        #[cfg(feature = "metrics")]
+        let before = UNKNOWN_SSE_EVENTS
+            .with_label_values(&["sse_test_unknown"])
+            .get();
+        assert_eq!(classify("sse_test_unknown"), EventKind::Unknown);
+        assert_eq!(classify("sse_test_unknown"), EventKind::Unknown);
+        #[cfg(feature = "metrics")]
+        assert_eq!(
+            UNKNOWN_SSE_EVENTS
+                .with_label_values(&["sse_test_unknown"])
+                .get(),
+            before + 2
+        );
+    }
+
+    #[tokio::test]
+    async fn ndjson_keeps_ping_and_unknown_events_in_order() {
+        let events = events_with_extras();
+        let body = sse(&events);
+        // 事件跨 chunk 切分
+        let chunks: Vec<Result<Bytes, std::io::Error>> = body
+            .as_bytes()
+            .chunks(29)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let output: Vec<Bytes> = to_ndjson(futures::stream::iter(chunks))
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        let lines: Vec<Value> = std::str::from_utf8(&output.concat())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        let (usage, relayed) = lines.split_last().unwrap();
+        let expected: Vec<Value> = events
+            .iter()
+            .map(|event| serde_json::json!({ "event_type": event["type"], "data": event }))
+            .collect();
+        assert_eq!(relayed, expected);
+        assert_eq!(usage["event_type"], "usage");
+    }
+
+    const FAILURES: [StreamFailure; 5] = [
+        StreamFailure::UpstreamDisconnect,
+        StreamFailure::IdleTimeout,
+        StreamFailure::MaxAge,
+        StreamFailure::Aborted,
+        StreamFailure::GatewayShutdown,
+    ];
+
+    fn expected_error(failure: StreamFailure) -> Value {
+        serde_json::json!({
+            "type": "error",
+            "error": {
+                "type": "api_error",
+                "code": failure.code(),
+                "message": failure.to_string(),
+            },
+            "request_id": "42",
+        })
+    }
+
+    #[test]
+    fn error_event_is_an_anthropic_error_event() {
+        for failure in FAILURES {
+            let event = error_event(failure, 42, StreamFormat::Sse);
+            let event = std::str::from_utf8(&event).unwrap();
+            let data = event
+                .strip_prefix("event: error\n")
+                .and_then(|rest| rest.strip_suffix("\n\n"))
+                .unwrap_or_else(|| panic!("not a single SSE event: {event:?}"));
+            assert_eq!(parse_data(data), Some(expected_error(failure)));
+
+            let line = error_event(failure, 42, StreamFormat::Ndjson);
+            let line = std::str::from_utf8(&line).unwrap();
+            assert_eq!(line.matches('\n').count(), 1, "{line:?}");
+            let parsed: Value = serde_json::from_str(line).unwrap();
+            assert_eq!(
+                parsed,
+                serde_json::json!({ "event_type": "error", "data": expected_error(failure) })
+            );
+        }
+    }
+
+    #[test]
+    fn stream_failure_survives_io_and_axum_errors() {
+        for failure in FAILURES {
+            let io = std::io::Error::from(failure);
+            assert_eq!(StreamFailure::of(&io), Some(failure));
+            let axum = axum::Error::new(std::io::Error::from(failure));
+            assert_eq!(StreamFailure::of(&axum), Some(failure));
+        }
+        let other = std::io::Error::other("connection reset");
+        assert_eq!(StreamFailure::of(&other), None);
+    }
+}
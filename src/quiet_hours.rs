@@ -0,0 +1,129 @@
+//! 静默时段
+//!
+//! `pluribus.toml` 的 `[quiet_hours]`（UTC）内暂停可选的后台上游请求：延迟探测与 Claude Code
+//! 版本号的定时刷新推迟到静默时段结束，OAuth token 的后台刷新只处理即将过期的 token，
+//! 不再提前刷新。用户请求不受影响。
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::providers::Schedule;
+use crate::utils::unix_timestamp_secs;
+
+/// 等待静默时段结束时的检查间隔
+const RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// `[quiet_hours]` 配置，两个列表都为空时不启用
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QuietHours {
+    /// `[start_hour, end_hour]`，包含开始、不包含结束，取值 0-24；为空表示全天
+    #[serde(default)]
+    pub hours: Vec<(u8, u8)>,
+    /// 0 = 周日 … 6 = 周六；为空表示每天
+    #[serde(default)]
+    pub days: Vec<u8>,
+}
+
+impl QuietHours {
+    pub fn validate(&self) -> Result<()> {
+        for &(start, end) in &self.hours {
+            anyhow::ensure!(
+                start <= 24 && end <= 24,
+                "quiet_hours.hours entry [{start}, {end}] is out of range 0-24"
+            );
+        }
+        for &day in &self.days {
+            anyhow::ensure!(day <= 6, "quiet_hours.days entry {day} is out of range 0-6");
+        }
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.hours.is_empty() || !self.days.is_empty()
+    }
+
+    /// `now_secs`（Unix timestamp）是否处于静默时段，未启用时总是 `false`
+    pub fn is_active(&self, now_secs: u64) -> bool {
+        self.is_enabled()
+            && Schedule {
+                active_hours: self.hours.clone(),
+                active_days: self.days.clone(),
+            }
+            .is_active(now_secs)
+    }
+
+    /// 等待静默时段结束，不在静默时段时立即返回
+    pub async fn wait_until_over(&self) {
+        while self.is_active(unix_timestamp_secs()) {
+            tokio::time::sleep(RECHECK_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1970-01-04（周日）之后第 `day` 天的 `hour` 点
+    fn at(day: u64, hour: u64) -> u64 {
+        (3 + day) * 86_400 + hour * 3600
+    }
+
+    fn quiet(hours: &[(u8, u8)], days: &[u8]) -> QuietHours {
+        QuietHours {
+            hours: hours.to_vec(),
+            days: days.to_vec(),
+        }
+    }
+
+    #[test]
+    fn disabled_is_never_active() {
+        let quiet = QuietHours::default();
+        assert!(!quiet.is_enabled());
+        assert!((0..24).all(|hour| !quiet.is_active(at(0, hour))));
+    }
+
+    #[test]
+    fn window_includes_start_and_excludes_end() {
+        let quiet = quiet(&[(2, 5)], &[]);
+        let active: Vec<u64> = (0..24).filter(|&h| quiet.is_active(at(1, h))).collect();
+        assert_eq!(active, [2, 3, 4]);
+    }
+
+    #[test]
+    fn window_wraps_past_midnight() {
+        let quiet = quiet(&[(22, 6)], &[]);
+        let active: Vec<u64> = (0..24).filter(|&h| quiet.is_active(at(1, h))).collect();
+        assert_eq!(active, [0, 1, 2, 3, 4, 5, 22, 23]);
+    }
+
+    #[test]
+    fn days_filter_weekdays() {
+        // 只在周六、周日
+        let weekend = quiet(&[], &[0, 6]);
+        let active: Vec<u64> = (0..7).filter(|&d| weekend.is_active(at(d, 12))).collect();
+        assert_eq!(active, [0, 6]);
+
+        // 周一 1-3 点
+        let monday = quiet(&[(1, 3)], &[1]);
+        assert!(monday.is_active(at(1, 2)));
+        assert!(!monday.is_active(at(1, 3)));
+        assert!(!monday.is_active(at(2, 2)));
+    }
+
+    #[test]
+    fn full_day_bounds() {
+        let quiet = quiet(&[(0, 24)], &[]);
+        assert!((0..24).all(|hour| quiet.is_active(at(2, hour))));
+        assert!(quiet.validate().is_ok());
+    }
+
+    #[test]
+    fn out_of_range_entries_are_rejected() {
+        assert!(quiet(&[(0, 25)], &[]).validate().is_err());
+        assert!(quiet(&[(25, 3)], &[]).validate().is_err());
+        assert!(quiet(&[], &[7]).validate().is_err());
+        assert!(quiet(&[(23, 1)], &[0, 6]).validate().is_ok());
+    }
+}
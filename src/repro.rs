@@ -0,0 +1,532 @@
+//! 上游 400 请求的最小复现
+//!
+//! 设置 `PLURIBUS_REPRO_DIR` 后，上游以 400 `invalid_request_error` 拒绝的请求脱敏后保存为
+//! `{dir}/{request_id}.json`；request ID 重启后从头计数，同 ID 的旧记录会被覆盖。
+//! `pluribus repro <request-id>` 用 [`minimize`] 逐步去掉 beta flags、tools、可选字段、
+//! 消息与内容块，每一步由 [`Oracle`] 判断候选请求是否仍以同样的错误失败，得到最小的失败请求体。
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// 缩减时尝试去掉的可选顶层字段
+const OPTIONAL_FIELDS: &[&str] = &[
+    "system",
+    "metadata",
+    "thinking",
+    "tool_choice",
+    "temperature",
+    "top_p",
+    "top_k",
+    "stop_sequences",
+    "container",
+    "service_tier",
+];
+
+/// 发往上游的请求：beta flags 与请求体
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    #[serde(default)]
+    pub betas: Vec<String>,
+    pub body: Value,
+}
+
+/// 保存的失败请求
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReproCase {
+    pub request_id: u64,
+    /// 失败时间 (Unix timestamp)
+    pub timestamp: u64,
+    /// 返回 400 的 provider
+    pub provider: Option<String>,
+    pub model: String,
+    /// 上游错误的签名，见 [`error_signature`]
+    pub error: String,
+    pub envelope: Envelope,
+}
+
+/// 上游错误响应体的签名：`{type}: {message}`，消息中的数字替换为 `N`
+///
+/// 去掉消息后下标会变化（如 `messages.5.content`），只比较签名即可判断是否为同一个错误
+pub fn error_signature(body: &str) -> Option<String> {
+    let json: Value = serde_json::from_str(body).ok()?;
+    let error = json.get("error")?;
+    let error_type = error.get("type")?.as_str()?;
+    let message = error.get("message").and_then(|m| m.as_str()).unwrap_or("");
+
+    let mut normalized = String::with_capacity(message.len());
+    let mut in_number = false;
+    for c in message.chars() {
+        if c.is_ascii_digit() {
+            if !in_number {
+                normalized.push('N');
+            }
+            in_number = true;
+        } else {
+            normalized.push(c);
+            in_number = false;
+        }
+    }
+    Some(format!("{error_type}: {normalized}"))
+}
+
+/// 签名是否为值得保存的 `invalid_request_error`
+pub fn is_invalid_request(signature: &str) -> bool {
+    signature.starts_with("invalid_request_error:")
+}
+
+/// 保持空白不变、其余字符替换为 `x`，保留长度与是否为空白
+fn mask(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_whitespace() { c } else { 'x' })
+        .collect()
+}
+
+/// 把所有字符串叶子替换为 [`mask`] 的结果
+fn mask_all(value: &mut Value) {
+    match value {
+        Value::String(s) => *s = mask(s),
+        Value::Array(items) => items.iter_mut().for_each(mask_all),
+        Value::Object(obj) => obj.values_mut().for_each(mask_all),
+        _ => {}
+    }
+}
+
+/// 脱敏请求体：文本、思考内容、工具输入与 `metadata` 中的字符串被遮盖，结构、长度与空白保持不变
+///
+/// 图片等 `source.data` 保留原样，遮盖后不再是合法的 base64
+pub fn redact(body: &mut Value) {
+    match body {
+        Value::Object(obj) => {
+            for (key, value) in obj.iter_mut() {
+                match (key.as_str(), &mut *value) {
+                    ("text" | "thinking" | "system" | "content", Value::String(s)) => *s = mask(s),
+                    ("input" | "metadata", value) => mask_all(value),
+                    (_, value) => redact(value),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// 请求对应的保存路径
+pub fn case_path(dir: &Path, request_id: u64) -> PathBuf {
+    dir.join(format!("{request_id}.json"))
+}
+
+/// 保存失败请求，返回文件路径
+pub fn save(dir: &Path, case: &ReproCase) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let path = case_path(dir, case.request_id);
+    std::fs::write(&path, serde_json::to_vec_pretty(case)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// 读取保存的失败请求
+pub fn load(dir: &Path, request_id: u64) -> Result<ReproCase> {
+    let path = case_path(dir, request_id);
+    let content = std::fs::read(&path).with_context(|| {
+        format!(
+            "No captured request {request_id} in {}; was PLURIBUS_REPRO_DIR set when it failed?",
+            dir.display()
+        )
+    })?;
+    serde_json::from_slice(&content).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// 判断候选请求是否仍以原错误失败
+#[async_trait]
+pub trait Oracle: Send {
+    /// 返回错误时中止缩减
+    async fn still_fails(&mut self, candidate: &Envelope) -> Result<bool>;
+}
+
+/// 可缩减的列表
+#[derive(Debug, Clone, Copy)]
+enum ListPath {
+    Betas,
+    Tools,
+    Messages,
+    /// 第 n 条消息的 content 数组
+    Content(usize),
+}
+
+impl ListPath {
+    /// 列表是否至少保留一项
+    fn keeps_one(self) -> bool {
+        matches!(self, ListPath::Messages | ListPath::Content(_))
+    }
+
+    fn label(self) -> String {
+        match self {
+            ListPath::Betas => "betas".to_string(),
+            ListPath::Tools => "tools".to_string(),
+            ListPath::Messages => "messages".to_string(),
+            ListPath::Content(n) => format!("messages[{n}].content"),
+        }
+    }
+
+    fn get(self, envelope: &Envelope) -> Vec<Value> {
+        match self {
+            ListPath::Betas => envelope.betas.iter().cloned().map(Value::String).collect(),
+            ListPath::Tools => array(envelope.body.get("tools")),
+            ListPath::Messages => array(envelope.body.get("messages")),
+            ListPath::Content(n) => array(
+                envelope
+                    .body
+                    .get("messages")
+                    .and_then(|m| m.get(n))
+                    .and_then(|m| m.get("content")),
+            ),
+        }
+    }
+
+    fn set(self, envelope: &Envelope, items: Vec<Value>) -> Envelope {
+        let mut envelope = envelope.clone();
+        match self {
+            ListPath::Betas => {
+                envelope.betas = items
+                    .into_iter()
+                    .filter_map(|v| v.as_str().map(str::to_string))
+                    .collect();
+            }
+            ListPath::Tools => {
+                if let Some(obj) = envelope.body.as_object_mut() {
+                    if items.is_empty() {
+                        obj.remove("tools");
+                        obj.remove("tool_choice");
+                    } else {
+                        obj.insert("tools".to_string(), Value::Array(items));
+                    }
+                }
+            }
+            ListPath::Messages => {
+                envelope.body["messages"] = Value::Array(items);
+            }
+            ListPath::Content(n) => {
+                envelope.body["messages"][n]["content"] = Value::Array(items);
+            }
+        }
+        envelope
+    }
+}
+
+fn array(value: Option<&Value>) -> Vec<Value> {
+    value
+        .and_then(|v| v.as_array())
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// [`minimize`] 的结果
+#[derive(Debug, Clone)]
+pub struct Minimized {
+    pub envelope: Envelope,
+    /// 按顺序生效的缩减步骤
+    pub steps: Vec<String>,
+    /// 调用 oracle 的次数
+    pub attempts: usize,
+    /// 达到尝试上限时结果可能不是最小的
+    pub exhausted: bool,
+}
+
+struct Minimizer<'a, O: Oracle + ?Sized> {
+    oracle: &'a mut O,
+    attempts: usize,
+    max_attempts: usize,
+    steps: Vec<String>,
+}
+
+impl<O: Oracle + ?Sized> Minimizer<'_, O> {
+    fn exhausted(&self) -> bool {
+        self.attempts >= self.max_attempts
+    }
+
+    /// 候选仍然失败时替换当前请求并记录步骤
+    async fn attempt(
+        &mut self,
+        current: &mut Envelope,
+        candidate: Envelope,
+        step: String,
+    ) -> Result<bool> {
+        if self.exhausted() || candidate == *current {
+            return Ok(false);
+        }
+        self.attempts += 1;
+        if !self.oracle.still_fails(&candidate).await? {
+            return Ok(false);
+        }
+        tracing::debug!(step, "reduction kept the failure");
+        *current = candidate;
+        self.steps.push(step);
+        Ok(true)
+    }
+
+    /// 先尝试清空，再按块从大到小删除列表项
+    async fn shrink_list(&mut self, current: &mut Envelope, path: ListPath) -> Result<bool> {
+        let mut changed = false;
+        let mut items = path.get(current);
+        if items.is_empty() {
+            return Ok(false);
+        }
+        if !path.keeps_one() {
+            let candidate = path.set(current, Vec::new());
+            if self
+                .attempt(current, candidate, format!("removed all {}", path.label()))
+                .await?
+            {
+                return Ok(true);
+            }
+        }
+
+        let mut chunk = items.len().div_ceil(2);
+        while chunk >= 1 && !self.exhausted() {
+            let mut start = 0;
+            while start < items.len() {
+                let end = (start + chunk).min(items.len());
+                if path.keeps_one() && end - start == items.len() {
+                    start = end;
+                    continue;
+                }
+                let mut rest = items[..start].to_vec();
+                rest.extend_from_slice(&items[end..]);
+                let candidate = path.set(current, rest);
+                let step = format!(
+                    "removed {}[{start}..{end}] of {}",
+                    path.label(),
+                    items.len()
+                );
+                if self.attempt(current, candidate, step).await? {
+                    items = path.get(current);
+                    changed = true;
+                } else {
+                    start = end;
+                }
+            }
+            if chunk == 1 {
+                break;
+            }
+            chunk = chunk.div_ceil(2);
+        }
+        Ok(changed)
+    }
+
+    /// 逐个去掉可选顶层字段
+    async fn drop_fields(&mut self, current: &mut Envelope) -> Result<bool> {
+        let mut changed = false;
+        for field in OPTIONAL_FIELDS {
+            if current.body.get(field).is_none() {
+                continue;
+            }
+            let mut candidate = current.clone();
+            if let Some(obj) = candidate.body.as_object_mut() {
+                obj.remove(*field);
+            }
+            changed |= self
+                .attempt(current, candidate, format!("removed {field}"))
+                .await?;
+        }
+        Ok(changed)
+    }
+
+    /// 一轮缩减，返回是否有变化
+    async fn pass(&mut self, current: &mut Envelope) -> Result<bool> {
+        let mut changed = self.shrink_list(current, ListPath::Betas).await?;
+        changed |= self.shrink_list(current, ListPath::Tools).await?;
+        changed |= self.drop_fields(current).await?;
+        changed |= self.shrink_list(current, ListPath::Messages).await?;
+        let messages = ListPath::Messages.get(current).len();
+        for n in 0..messages {
+            changed |= self.shrink_list(current, ListPath::Content(n)).await?;
+        }
+        Ok(changed)
+    }
+}
+
+/// 缩减请求直到任何一步都不再保持失败，或达到 `max_attempts` 次 oracle 调用
+///
+/// 调用方应先确认原始请求确实失败
+pub async fn minimize<O: Oracle + ?Sized>(
+    envelope: Envelope,
+    oracle: &mut O,
+    max_attempts: usize,
+) -> Result<Minimized> {
+    let mut minimizer = Minimizer {
+        oracle,
+        attempts: 0,
+        max_attempts,
+        steps: Vec::new(),
+    };
+    let mut current = envelope;
+    while minimizer.pass(&mut current).await? && !minimizer.exhausted() {}
+    Ok(Minimized {
+        envelope: current,
+        exhausted: minimizer.exhausted(),
+        steps: minimizer.steps,
+        attempts: minimizer.attempts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn error_signature_normalizes_numbers() {
+        let body = json!({"type": "error", "error": {
+            "type": "invalid_request_error",
+            "message": "messages.12.content.3: unexpected tool_use_id"
+        }});
+        let signature = error_signature(&body.to_string()).unwrap();
+        assert_eq!(
+            signature,
+            "invalid_request_error: messages.N.content.N: unexpected tool_use_id"
+        );
+        assert!(is_invalid_request(&signature));
+        assert!(!is_invalid_request(
+            &error_signature(r#"{"error": {"type": "overloaded_error"}}"#).unwrap()
+        ));
+        assert_eq!(error_signature("<html>bad gateway</html>"), None);
+    }
+
+    #[test]
+    fn redact_masks_text_and_keeps_structure() {
+        let mut body = json!({
+            "model": "claude-test",
+            "system": "secret system",
+            "metadata": {"user_id": "alice"},
+            "tools": [{"name": "bash", "input_schema": {"type": "object"}}],
+            "messages": [
+                {"role": "user", "content": "my password"},
+                {"role": "assistant", "content": [
+                    {"type": "thinking", "thinking": "hmm ok", "signature": "sig"},
+                    {"type": "tool_use", "id": "toolu_1", "name": "bash", "input": {"cmd": "cat key"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": "top secret"},
+                    {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBOR"}}
+                ]}
+            ]
+        });
+        redact(&mut body);
+
+        assert_eq!(
+            body,
+            json!({
+                "model": "claude-test",
+                "system": "xxxxxx xxxxxx",
+                "metadata": {"user_id": "xxxxx"},
+                "tools": [{"name": "bash", "input_schema": {"type": "object"}}],
+                "messages": [
+                    {"role": "user", "content": "xx xxxxxxxx"},
+                    {"role": "assistant", "content": [
+                        {"type": "thinking", "thinking": "xxx xx", "signature": "sig"},
+                        {"type": "tool_use", "id": "toolu_1", "name": "bash", "input": {"cmd": "xxx xxx"}}
+                    ]},
+                    {"role": "user", "content": [
+                        {"type": "tool_result", "tool_use_id": "toolu_1", "content": "xxx xxxxxx"},
+                        {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": "iVBOR"}}
+                    ]}
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let case = ReproCase {
+            request_id: 7,
+            timestamp: 1_700_000_000,
+            provider: Some("first".to_string()),
+            model: "claude-test".to_string(),
+            error: "invalid_request_error: bad".to_string(),
+            envelope: Envelope {
+                betas: vec!["beta-1".to_string()],
+                body: json!({"model": "claude-test", "messages": []}),
+            },
+        };
+        let path = save(&dir.path().join("repro"), &case).unwrap();
+        assert_eq!(path, case_path(&dir.path().join("repro"), 7));
+
+        let loaded = load(&dir.path().join("repro"), 7).unwrap();
+        assert_eq!(loaded.provider, case.provider);
+        assert_eq!(loaded.error, case.error);
+        assert_eq!(loaded.envelope, case.envelope);
+        let err = load(&dir.path().join("repro"), 8).unwrap_err();
+        assert!(err.to_string().contains("PLURIBUS_REPRO_DIR"), "{err}");
+    }
+
+    /// 请求仍带有名为 `bad` 的 tool 且带 `beta-bad` 时失败
+    struct BadTool {
+        calls: usize,
+    }
+
+    #[async_trait]
+    impl Oracle for BadTool {
+        async fn still_fails(&mut self, candidate: &Envelope) -> Result<bool> {
+            self.calls += 1;
+            let has_tool = array(candidate.body.get("tools"))
+                .iter()
+                .any(|tool| tool["name"] == "bad");
+            Ok(has_tool && candidate.betas.iter().any(|beta| beta == "beta-bad"))
+        }
+    }
+
+    fn failing_envelope() -> Envelope {
+        let message =
+            |text: &str| json!({"role": "user", "content": [{"type": "text", "text": text}]});
+        Envelope {
+            betas: vec![
+                "beta-a".to_string(),
+                "beta-bad".to_string(),
+                "beta-b".to_string(),
+            ],
+            body: json!({
+                "model": "claude-test",
+                "max_tokens": 16,
+                "system": "xxxx",
+                "temperature": 0.5,
+                "tools": [{"name": "a"}, {"name": "bad"}, {"name": "b"}, {"name": "c"}],
+                "tool_choice": {"type": "auto"},
+                "messages": [message("one"), message("two"), message("three")]
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn minimize_keeps_only_what_the_failure_needs() {
+        let mut oracle = BadTool { calls: 0 };
+        let minimized = minimize(failing_envelope(), &mut oracle, 200)
+            .await
+            .unwrap();
+
+        assert!(!minimized.exhausted);
+        assert_eq!(minimized.attempts, oracle.calls);
+        assert_eq!(minimized.envelope.betas, ["beta-bad"]);
+        assert_eq!(
+            minimized.envelope.body,
+            json!({
+                "model": "claude-test",
+                "max_tokens": 16,
+                "tools": [{"name": "bad"}],
+                "messages": [{"role": "user", "content": [{"type": "text", "text": "three"}]}]
+            })
+        );
+        assert!(minimized.steps.iter().any(|step| step == "removed system"));
+    }
+
+    #[tokio::test]
+    async fn minimize_stops_at_max_attempts() {
+        let mut oracle = BadTool { calls: 0 };
+        let minimized = minimize(failing_envelope(), &mut oracle, 3).await.unwrap();
+        assert!(minimized.exhausted);
+        assert_eq!(oracle.calls, 3);
+    }
+}
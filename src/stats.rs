@@ -0,0 +1,260 @@
+//! Gateway 自身运行指标
+//!
+//! 统计 gateway 启动的 tokio 任务数（按启动位置命名）、正在转发的流数量、流转发通道积压的
+//! 最大值、单个请求同时持有的请求体字节数最大值以及进程常驻内存，用于判断变慢是源于自身还是上游。任务与流的计数在启动 / 结束时
+//! 更新，内存在查询时按需读取。
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::metrics::{
+    ACTIVE_STREAMS, LIVE_TASKS, REQUEST_BODY_HIGH_WATER, RESIDENT_MEMORY, STREAM_CHANNEL_HIGH_WATER,
+};
+
+/// 任务的启动位置
+#[derive(Debug, Clone, Copy)]
+pub enum TaskKind {
+    Server,
+    VersionRefresh,
+    KeyUsageFlush,
+    KeyReload,
+    BudgetRollover,
+    StreamWatchdog,
+    LatencyProbe,
+    StreamRelay,
+    NdjsonEncoder,
+    StreamChecksum,
+    StreamCompletion,
+    AccessLogRollup,
+    TokenRefresh,
+    UsageWriter,
+    ProviderReload,
+}
+
+impl TaskKind {
+    const ALL: [TaskKind; 15] = [
+        TaskKind::Server,
+        TaskKind::VersionRefresh,
+        TaskKind::KeyUsageFlush,
+        TaskKind::KeyReload,
+        TaskKind::BudgetRollover,
+        TaskKind::StreamWatchdog,
+        TaskKind::LatencyProbe,
+        TaskKind::StreamRelay,
+        TaskKind::NdjsonEncoder,
+        TaskKind::StreamChecksum,
+        TaskKind::StreamCompletion,
+        TaskKind::AccessLogRollup,
+        TaskKind::TokenRefresh,
+        TaskKind::UsageWriter,
+        TaskKind::ProviderReload,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TaskKind::Server => "server",
+            TaskKind::VersionRefresh => "version_refresh",
+            TaskKind::KeyUsageFlush => "key_usage_flush",
+            TaskKind::KeyReload => "key_reload",
+            TaskKind::BudgetRollover => "budget_rollover",
+            TaskKind::StreamWatchdog => "stream_watchdog",
+            TaskKind::LatencyProbe => "latency_probe",
+            TaskKind::StreamRelay => "stream_relay",
+            TaskKind::NdjsonEncoder => "ndjson_encoder",
+            TaskKind::StreamChecksum => "stream_checksum",
+            TaskKind::StreamCompletion => "stream_completion",
+            TaskKind::AccessLogRollup => "access_log_rollup",
+            TaskKind::TokenRefresh => "token_refresh",
+            TaskKind::UsageWriter => "usage_writer",
+            TaskKind::ProviderReload => "provider_reload",
+        }
+    }
+}
+
+/// 任务结束（包括被取消）时减少计数
+struct TaskGuard(TaskKind);
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        LIVE_TASKS.with_label_values(&[self.0.name()]).dec();
+    }
+}
+
+/// 启动任务并按 `kind` 计数
+pub fn spawn<F>(kind: TaskKind, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    LIVE_TASKS.with_label_values(&[kind.name()]).inc();
+    let guard = TaskGuard(kind);
+    tokio::spawn(async move {
+        let _guard = guard;
+        future.await
+    })
+}
+
+/// 所有流转发通道积压的历史最大值
+static CHANNEL_HIGH_WATER: AtomicUsize = AtomicUsize::new(0);
+
+/// 单个请求同时持有的请求体字节数的历史最大值
+static BODY_HIGH_WATER: AtomicUsize = AtomicUsize::new(0);
+
+/// 记录一个请求在各次尝试中同时持有的请求体字节数峰值
+pub fn observe_request_body(peak_bytes: usize) {
+    BODY_HIGH_WATER.fetch_max(peak_bytes, Ordering::Relaxed);
+}
+
+/// 单个流转发的统计，drop 时计为结束
+pub struct StreamStats {
+    high_water: usize,
+}
+
+impl StreamStats {
+    pub fn start() -> Self {
+        ACTIVE_STREAMS.inc();
+        Self { high_water: 0 }
+    }
+
+    /// 记录发送后通道中积压的块数
+    pub fn observe<T>(&mut self, tx: &mpsc::Sender<T>) {
+        let depth = tx.max_capacity() - tx.capacity();
+        if depth > self.high_water {
+            self.high_water = depth;
+            CHANNEL_HIGH_WATER.fetch_max(depth, Ordering::Relaxed);
+        }
+    }
+
+    pub fn high_water(&self) -> usize {
+        self.high_water
+    }
+}
+
+impl Drop for StreamStats {
+    fn drop(&mut self) {
+        ACTIVE_STREAMS.dec();
+    }
+}
+
+/// `/admin/info` 中展示的运行指标
+#[derive(Debug, Serialize)]
+pub struct RuntimeStats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resident_memory_bytes: Option<u64>,
+    pub active_streams: i64,
+    pub stream_channel_high_water: usize,
+    pub request_body_high_water_bytes: usize,
+    pub tasks: BTreeMap<&'static str, i64>,
+}
+
+/// 采样当前运行指标，同时更新对应的 Prometheus gauge
+pub fn sample() -> RuntimeStats {
+    let resident_memory_bytes = resident_memory_bytes();
+    if let Some(bytes) = resident_memory_bytes {
+        RESIDENT_MEMORY.set(bytes as i64);
+    }
+    let stream_channel_high_water = CHANNEL_HIGH_WATER.load(Ordering::Relaxed);
+    STREAM_CHANNEL_HIGH_WATER.set(stream_channel_high_water as i64);
+    let request_body_high_water_bytes = BODY_HIGH_WATER.load(Ordering::Relaxed);
+    REQUEST_BODY_HIGH_WATER.set(request_body_high_water_bytes as i64);
+
+    let tasks = TaskKind::ALL
+        .iter()
+        .map(|kind| {
+            (
+                kind.name(),
+                LIVE_TASKS.with_label_values(&[kind.name()]).get(),
+            )
+        })
+        .collect();
+
+    RuntimeStats {
+        resident_memory_bytes,
+        active_streams: ACTIVE_STREAMS.get(),
+        stream_channel_high_water,
+        request_body_high_water_bytes,
+        tasks,
+    }
+}
+
+/// 读取 `/proc/self/status` 中的 VmRSS
+#[cfg(target_os = "linux")]
+fn resident_memory_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: u64 = line
+        .trim_start_matches("VmRSS:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn resident_memory_bytes() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试中不会由其他代码启动的任务类型，计数可精确比较
+    const KIND: TaskKind = TaskKind::KeyReload;
+
+    fn live(kind: TaskKind) -> i64 {
+        sample().tasks[kind.name()]
+    }
+
+    #[test]
+    fn every_task_kind_is_reported() {
+        let tasks = sample().tasks;
+        assert_eq!(tasks.len(), TaskKind::ALL.len());
+        for kind in TaskKind::ALL {
+            assert!(tasks.contains_key(kind.name()), "{}", kind.name());
+        }
+    }
+
+    #[tokio::test]
+    async fn task_counts_follow_spawn_finish_and_abort() {
+        let before = live(KIND);
+
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let finished = spawn(KIND, async move {
+            let _ = rx.await;
+        });
+        let aborted = spawn(KIND, std::future::pending::<()>());
+        assert_eq!(live(KIND), before + 2);
+
+        tx.send(()).unwrap();
+        finished.await.unwrap();
+        assert_eq!(live(KIND), before + 1);
+
+        // 被取消的任务同样减少计数
+        aborted.abort();
+        assert!(aborted.await.unwrap_err().is_cancelled());
+        assert_eq!(live(KIND), before);
+    }
+
+    #[test]
+    fn high_water_marks_only_grow() {
+        let (tx, _rx) = mpsc::channel::<u8>(8);
+        let mut stream = StreamStats::start();
+        for _ in 0..3 {
+            tx.try_send(0).unwrap();
+            stream.observe(&tx);
+        }
+        assert_eq!(stream.high_water(), 3);
+        assert!(sample().stream_channel_high_water >= 3);
+
+        observe_request_body(1 << 30);
+        observe_request_body(1);
+        assert!(sample().request_body_high_water_bytes >= 1 << 30);
+    }
+}
@@ -0,0 +1,106 @@
+//! OpenTelemetry 链路追踪
+//!
+//! 设置 `PLURIBUS_OTEL_ENDPOINT` 时，在日志输出之外安装 OTLP (gRPC) 导出层，`tracing` 的
+//! span 作为 trace 上报；客户端请求带 `traceparent` 时作为远端父 span。关闭 `otel` feature
+//! 时不依赖 opentelemetry，设置该变量会导致启动失败。
+
+use axum::http::HeaderMap;
+use tracing::Span;
+
+/// OTLP 导出地址的环境变量
+pub const ENDPOINT_ENV: &str = "PLURIBUS_OTEL_ENDPOINT";
+
+#[cfg(feature = "otel")]
+mod otel {
+    use anyhow::{Context, Result};
+    use axum::http::HeaderMap;
+    use opentelemetry::propagation::Extractor;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::{span_processor_with_async_runtime, SdkTracerProvider};
+    use opentelemetry_sdk::{runtime, Resource};
+    use std::sync::OnceLock;
+    use tracing::Span;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::registry::LookupSpan;
+    use tracing_subscriber::Layer;
+
+    static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+    /// 创建导出到 `endpoint` 的 tracing 层（需在 tokio 运行时中调用）
+    pub fn layer<S>(endpoint: &str) -> Result<impl Layer<S>>
+    where
+        S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build()
+            .with_context(|| format!("Failed to create OTLP exporter for {endpoint}"))?;
+        // gRPC 导出需要 tokio 运行时，不使用默认的独立线程批处理
+        let processor = span_processor_with_async_runtime::BatchSpanProcessor::builder(
+            exporter,
+            runtime::Tokio,
+        )
+        .build();
+        let provider = SdkTracerProvider::builder()
+            .with_span_processor(processor)
+            .with_resource(Resource::builder().with_service_name("pluribus").build())
+            .build();
+        let tracer = provider.tracer("pluribus");
+        let _ = PROVIDER.set(provider);
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+    }
+
+    /// 导出尚未发送的 span
+    pub fn shutdown() {
+        if let Some(provider) = PROVIDER.get() {
+            if let Err(e) = provider.shutdown() {
+                tracing::warn!("Failed to flush OpenTelemetry spans: {}", e);
+            }
+        }
+    }
+
+    struct HeaderExtractor<'a>(&'a HeaderMap);
+
+    impl Extractor for HeaderExtractor<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).and_then(|v| v.to_str().ok())
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().map(|k| k.as_str()).collect()
+        }
+    }
+
+    pub fn set_remote_parent(span: &Span, headers: &HeaderMap) {
+        if PROVIDER.get().is_none() {
+            return;
+        }
+        let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(headers))
+        });
+        let _ = span.set_parent(parent);
+    }
+}
+
+#[cfg(feature = "otel")]
+pub use otel::{layer, shutdown};
+
+#[cfg(not(feature = "otel"))]
+pub fn layer(_endpoint: &str) -> anyhow::Result<tracing_subscriber::layer::Identity> {
+    anyhow::bail!("built without the otel feature")
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn shutdown() {}
+
+/// 以客户端 `traceparent` 指定的 span 作为 `span` 的父 span，未启用导出时不做任何事
+pub fn set_remote_parent(span: &Span, headers: &HeaderMap) {
+    #[cfg(feature = "otel")]
+    otel::set_remote_parent(span, headers);
+    #[cfg(not(feature = "otel"))]
+    let _ = (span, headers);
+}
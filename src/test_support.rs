@@ -0,0 +1,284 @@
+//! 测试辅助
+//!
+//! 临时目录中的配置文件，以及记录收到的请求、返回固定响应的 mock provider
+
+use anyhow::Result;
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::http::{HeaderMap, Request, StatusCode};
+use axum::Router;
+use bytes::Bytes;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+use crate::commands::output::{Output, OutputFormat};
+use crate::config::Config;
+use crate::gateway::AppState;
+use crate::keys::KeyStore;
+use crate::providers::claude_code::relay_events;
+use crate::providers::{EncodedRequest, Provider, ProviderType, StreamingResponse, UpstreamError};
+
+/// 测试配置中的 `PLURIBUS_SECRET`（admin 密钥）
+pub const SECRET: &str = "test-secret";
+
+/// 测试配置中 user 角色的密钥
+pub const USER_KEY: &str = "test-user-key";
+
+/// 测试配置中 readonly 角色的密钥
+pub const READONLY_KEY: &str = "test-readonly-key";
+
+/// 在临时目录中写入 `pluribus.toml` 并加载配置
+///
+/// provider 目录、keys 文件与死信文件都位于临时目录中，用量历史关闭；`extra` 追加到文件末尾。
+/// keys 文件中有名为 `user` 与 `readonly` 的密钥。
+pub fn config(extra: &str) -> (TempDir, Config) {
+    let (dir, config) = try_config(extra);
+    (dir, config.expect("load test config"))
+}
+
+/// 同 [`config`]，返回加载结果；`extra` 设置了 `usage_db` 时不再关闭用量历史
+pub fn try_config(extra: &str) -> (TempDir, anyhow::Result<Config>) {
+    let dir = tempfile::tempdir().expect("create temp dir");
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("providers")).expect("create providers dir");
+    std::fs::write(
+        root.join("keys.toml"),
+        format!(
+            "[[keys]]\nname = \"user\"\nkey = \"{USER_KEY}\"\nrole = \"user\"\n\n\
+             [[keys]]\nname = \"readonly\"\nkey = \"{READONLY_KEY}\"\nrole = \"readonly\"\n"
+        ),
+    )
+    .expect("write keys file");
+    let path = root.join("pluribus.toml");
+    std::fs::write(
+        &path,
+        format!(
+            "secret = \"{SECRET}\"\n\
+             providers_dir = {providers:?}\n\
+             keys_file = {keys:?}\n\
+             dead_letter_file = {dead_letters:?}\n\
+             {usage_db}\
+             {extra}\n",
+            usage_db = if extra.contains("usage_db") {
+                ""
+            } else {
+                "usage_db = \"\"\n"
+            },
+            providers = root.join("providers"),
+            keys = root.join("keys.toml"),
+            dead_letters = root.join("deadletter.jsonl"),
+        ),
+    )
+    .expect("write config file");
+    let config = Config::from_file(&path);
+    (dir, config)
+}
+
+/// 捕获并丢弃输出的 text 模式输出上下文
+pub fn quiet() -> Output {
+    Output::captured(OutputFormat::Text).0
+}
+
+/// 加载 `config` 中的密钥
+pub fn keys(config: &Config) -> KeyStore {
+    KeyStore::load(&config.keys_file, &config.secret).expect("load test keys")
+}
+
+/// 以 mock provider 创建应用状态
+pub fn state(config: Config, providers: &[Arc<MockProvider>]) -> AppState {
+    let keys = keys(&config);
+    let providers = providers
+        .iter()
+        .map(|p| p.clone() as Arc<dyn Provider>)
+        .collect();
+    AppState::new(providers, config, keys)
+}
+
+/// 以 `key` 认证的 Messages 请求
+pub fn messages_request(key: &str, body: &Value) -> Request<Body> {
+    Request::post("/anthropic/v1/messages")
+        .header("x-api-key", key)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .expect("build request")
+}
+
+/// 发送请求并读取完整响应
+pub async fn send(router: &Router, request: Request<Body>) -> (StatusCode, HeaderMap, Bytes) {
+    let response = router
+        .clone()
+        .oneshot(request)
+        .await
+        .expect("router is infallible");
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("read response body");
+    (status, headers, body)
+}
+
+/// 非流式 Messages 响应
+pub fn message(text: &str) -> Value {
+    json!({
+        "id": "msg_test",
+        "type": "message",
+        "role": "assistant",
+        "model": "claude-test",
+        "content": [{"type": "text", "text": text}],
+        "stop_reason": "end_turn",
+        "usage": {"input_tokens": 10, "output_tokens": 5}
+    })
+}
+
+/// 与 [`message`] 对应的流式事件
+pub fn message_events(text: &str) -> Vec<Value> {
+    vec![
+        json!({"type": "message_start", "message": {
+            "id": "msg_test", "type": "message", "role": "assistant", "model": "claude-test",
+            "content": [], "usage": {"input_tokens": 10, "output_tokens": 1}
+        }}),
+        json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}),
+        json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": text}}),
+        json!({"type": "content_block_stop", "index": 0}),
+        json!({"type": "message_delta", "delta": {"stop_reason": "end_turn"}, "usage": {"output_tokens": 5}}),
+        json!({"type": "message_stop"}),
+    ]
+}
+
+/// 把事件编码为 SSE
+pub fn sse(events: &[Value]) -> String {
+    events
+        .iter()
+        .map(|event| {
+            format!(
+                "event: {}\ndata: {event}\n\n",
+                event["type"].as_str().unwrap_or_default()
+            )
+        })
+        .collect()
+}
+
+/// 记录收到的请求体并返回固定响应的 provider
+pub struct MockProvider {
+    id: String,
+    name: String,
+    provider_type: ProviderType,
+    response: Mutex<Value>,
+    /// 设置后所有请求都以该状态码与响应体失败
+    failure: Mutex<Option<(StatusCode, String)>>,
+    /// 收到的请求体（原样字节）
+    requests: Mutex<Vec<Bytes>>,
+}
+
+impl MockProvider {
+    pub fn new(name: &str) -> Self {
+        Self {
+            id: format!("id-{name}"),
+            name: name.to_string(),
+            provider_type: ProviderType::Anthropic,
+            response: Mutex::new(message(name)),
+            failure: Mutex::default(),
+            requests: Mutex::default(),
+        }
+    }
+
+    /// 以 `provider_type` 类型出现（如支持 count_tokens 的 claude-code）
+    pub fn with_type(mut self, provider_type: ProviderType) -> Self {
+        self.provider_type = provider_type;
+        self
+    }
+
+    /// 之后的请求都返回上游错误
+    pub fn fail_with(&self, status: StatusCode, body: &str) {
+        *self.failure.lock().unwrap() = Some((status, body.to_string()));
+    }
+
+    /// 取消 [`fail_with`](Self::fail_with)，之后的请求恢复正常响应
+    pub fn recover(&self) {
+        *self.failure.lock().unwrap() = None;
+    }
+
+    /// 设置非流式响应
+    pub fn respond_with(&self, response: Value) {
+        *self.response.lock().unwrap() = response;
+    }
+
+    /// 收到的请求体
+    pub fn requests(&self) -> Vec<Bytes> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// 记录请求体，设置了失败时返回上游错误
+    fn record(&self, body: Bytes) -> Result<()> {
+        self.requests.lock().unwrap().push(body);
+        match self.failure.lock().unwrap().clone() {
+            Some((status, body)) => Err(UpstreamError { status, body }.into()),
+            None => Ok(()),
+        }
+    }
+
+    fn stream(&self) -> StreamingResponse {
+        let text = self.name.clone();
+        let body = Bytes::from(sse(&message_events(&text)));
+        relay_events(
+            futures::stream::iter([Ok(body)]),
+            http::StatusCode::OK,
+            self.name.clone(),
+            "claude-test".to_string(),
+            Arc::default(),
+        )
+    }
+}
+
+#[async_trait]
+impl Provider for MockProvider {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn provider_type(&self) -> ProviderType {
+        self.provider_type
+    }
+
+    async fn send_message(&self, request: Value) -> Result<Value> {
+        self.record(Bytes::from(serde_json::to_vec(&request)?))?;
+        Ok(self.response.lock().unwrap().clone())
+    }
+
+    async fn send_streaming(&self, request: Value) -> Result<StreamingResponse> {
+        self.record(Bytes::from(serde_json::to_vec(&request)?))?;
+        Ok(self.stream())
+    }
+
+    async fn send_message_raw(&self, body: Bytes, _model: &str) -> Result<Value> {
+        self.record(body)?;
+        Ok(self.response.lock().unwrap().clone())
+    }
+
+    async fn send_streaming_raw(&self, body: Bytes, _model: &str) -> Result<StreamingResponse> {
+        self.record(body)?;
+        Ok(self.stream())
+    }
+
+    async fn send_message_encoded(&self, request: EncodedRequest) -> Result<Value> {
+        self.record(request.body)?;
+        Ok(self.response.lock().unwrap().clone())
+    }
+
+    async fn send_streaming_encoded(&self, request: EncodedRequest) -> Result<StreamingResponse> {
+        self.record(request.body)?;
+        Ok(self.stream())
+    }
+
+    async fn count_tokens(&self, request: Value) -> Result<Value> {
+        self.record(Bytes::from(serde_json::to_vec(&request)?))?;
+        Ok(json!({"input_tokens": 42}))
+    }
+}
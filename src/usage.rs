@@ -0,0 +1,863 @@
+//! 用量历史
+//!
+//! 每个发往过 provider 的请求（流式请求在流结束后）写入 SQLite 数据库 `PLURIBUS_USAGE_DB`
+//! 的一行，供 `GET /usage` 与 `pluribus usage` 按时间、provider、模型筛选后汇总。写入由
+//! 后台任务批量完成，不阻塞请求；队列满时丢弃并记录警告。合成流量不记录。
+//!
+//! token 汇总只统计成功（状态码低于 400）的请求；按模型的统计同时包含按状态码分类的错误。
+//!
+//! 表结构的版本记录在 `PRAGMA user_version` 中，打开时按顺序执行尚未应用的迁移。
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions};
+use sqlx::{QueryBuilder, Sqlite};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::keys::KeyUsage;
+use crate::providers::Usage;
+use crate::stats::{self, TaskKind};
+
+/// 等待写入的记录上限
+const QUEUE_CAPACITY: usize = 4096;
+/// 单个事务写入的记录上限
+const MAX_BATCH: usize = 256;
+/// 数据库被其他连接（或其他实例）锁定时的等待时间
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 版本 1：最初的表结构
+///
+/// 记录版本之前创建的数据库（`user_version` 为 0）已有这张表，执行后只补上版本号
+const SCHEMA_V1: &str = "
+CREATE TABLE IF NOT EXISTS usage (
+    id INTEGER PRIMARY KEY,
+    timestamp INTEGER NOT NULL,
+    provider TEXT NOT NULL,
+    model TEXT NOT NULL,
+    api_key_name TEXT,
+    input_tokens INTEGER NOT NULL,
+    output_tokens INTEGER NOT NULL,
+    cache_read_tokens INTEGER NOT NULL,
+    cache_creation_tokens INTEGER NOT NULL,
+    latency_ms INTEGER NOT NULL,
+    stream INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS usage_timestamp ON usage (timestamp);
+";
+
+/// 版本 2：上游实际提供服务的模型、响应状态码与首 token 耗时
+///
+/// 之前只记录成功的请求，因此旧记录的状态码为 200
+const SCHEMA_V2: &str = "
+ALTER TABLE usage ADD COLUMN effective_model TEXT;
+ALTER TABLE usage ADD COLUMN status INTEGER NOT NULL DEFAULT 200;
+ALTER TABLE usage ADD COLUMN ttft_ms INTEGER;
+";
+
+/// 版本 3：客户端密钥的最后使用时间与累计请求数，取代 keys 文件旁的 `*.usage.json`
+const SCHEMA_V3: &str = "
+CREATE TABLE key_usage (
+    name TEXT PRIMARY KEY,
+    last_used INTEGER,
+    requests INTEGER NOT NULL
+);
+";
+
+/// 按顺序编号的 schema 迁移，第 N 个脚本把数据库从版本 N - 1 升级到 N
+///
+/// 当前版本记录在 `PRAGMA user_version` 中，已发布的脚本不能再修改
+const MIGRATIONS: &[&str] = &[SCHEMA_V1, SCHEMA_V2, SCHEMA_V3];
+
+/// 本版本写入的 schema 版本
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// 一个完成的请求
+#[derive(Debug, Clone)]
+pub struct UsageRow {
+    /// 请求时间 (Unix timestamp)
+    pub timestamp: u64,
+    pub provider: String,
+    /// 请求的模型
+    pub model: String,
+    /// 上游响应中实际提供服务的模型
+    pub effective_model: Option<String>,
+    pub api_key_name: Option<String>,
+    pub usage: Usage,
+    /// 返回给客户端的状态码
+    pub status: u16,
+    pub latency_ms: u64,
+    /// 首 token 耗时（仅流式请求）
+    pub ttft_ms: Option<u64>,
+    pub stream: bool,
+}
+
+/// 查询条件，未设置的条件不筛选
+#[derive(Debug, Default)]
+pub struct UsageFilter {
+    /// 起始时间（含），Unix timestamp
+    pub from: Option<u64>,
+    /// 结束时间（不含），Unix timestamp
+    pub to: Option<u64>,
+    pub provider: Option<String>,
+    /// 请求的模型或实际提供服务的模型
+    pub model: Option<String>,
+}
+
+impl UsageFilter {
+    /// 追加 `WHERE` 条件
+    fn push_conditions(&self, query: &mut QueryBuilder<'_, Sqlite>) {
+        query.push(" WHERE 1 = 1");
+        if let Some(from) = self.from {
+            query.push(" AND timestamp >= ").push_bind(from as i64);
+        }
+        if let Some(to) = self.to {
+            query.push(" AND timestamp < ").push_bind(to as i64);
+        }
+        if let Some(provider) = &self.provider {
+            query.push(" AND provider = ").push_bind(provider.clone());
+        }
+        if let Some(model) = &self.model {
+            query
+                .push(" AND (model = ")
+                .push_bind(model.clone())
+                .push(" OR effective_model = ")
+                .push_bind(model.clone())
+                .push(")");
+        }
+    }
+}
+
+/// 一组请求的汇总
+#[derive(Debug, Default, Serialize)]
+pub struct UsageTotals {
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_read_tokens: u64,
+    pub cache_creation_tokens: u64,
+    pub avg_latency_ms: u64,
+}
+
+/// 按 provider / 密钥 / 模型分组的汇总，没有密钥的请求 `name` 为 `null`
+#[derive(Debug, Serialize)]
+pub struct UsageGroup {
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub totals: UsageTotals,
+}
+
+/// `GET /usage` 与 `pluribus usage` 的结果，各分组按总 token 数降序
+#[derive(Debug, Serialize)]
+pub struct UsageReport {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub total: UsageTotals,
+    pub by_provider: Vec<UsageGroup>,
+    pub by_key: Vec<UsageGroup>,
+    pub by_model: Vec<UsageGroup>,
+    /// 按请求模型分组的统计，按请求数降序
+    pub models: Vec<ModelStats>,
+}
+
+/// 一个请求模型的统计
+#[derive(Debug, Serialize)]
+pub struct ModelStats {
+    /// 请求的模型
+    pub model: String,
+    /// 上游响应中实际提供服务的模型（失败的请求没有）
+    pub effective_models: Vec<String>,
+    /// 全部请求数，含失败的请求
+    pub requests: u64,
+    pub successes: u64,
+    /// 按状态码统计的失败请求数
+    pub errors: BTreeMap<u16, u64>,
+    /// 成功请求的平均输出 token 数
+    pub avg_output_tokens: u64,
+    /// 成功请求输出 token 数的 95 分位
+    pub p95_output_tokens: u64,
+    /// 成功的流式请求的平均首 token 耗时，没有记录时为 `null`
+    pub avg_ttft_ms: Option<u64>,
+    /// 按公开价格估算的费用（美元），价格未知的模型为 `null`
+    pub estimated_cost_usd: Option<f64>,
+}
+
+/// 每百万 token 的价格（美元）：输入、输出，按模型名前缀匹配，越具体的前缀越靠前
+///
+/// 缓存读取按输入价格的 0.1 倍、缓存写入按 1.25 倍计算
+const MODEL_PRICES: &[(&str, f64, f64)] = &[
+    ("claude-opus-4-5", 5.0, 25.0),
+    ("claude-opus-4", 15.0, 75.0),
+    ("claude-sonnet-4", 3.0, 15.0),
+    ("claude-haiku-4", 1.0, 5.0),
+    ("claude-3-7-sonnet", 3.0, 15.0),
+    ("claude-3-5-sonnet", 3.0, 15.0),
+    ("claude-3-5-haiku", 0.8, 4.0),
+    ("claude-3-opus", 15.0, 75.0),
+    ("claude-3-haiku", 0.25, 1.25),
+];
+
+/// 按 [`MODEL_PRICES`] 估算费用，未知模型返回 `None`
+fn estimate_cost(model: &str, totals: &UsageTotals) -> Option<f64> {
+    let (_, input, output) = MODEL_PRICES
+        .iter()
+        .find(|(prefix, _, _)| model.starts_with(prefix))?;
+    let cost = totals.input_tokens as f64 * input
+        + totals.output_tokens as f64 * output
+        + totals.cache_read_tokens as f64 * input * 0.1
+        + totals.cache_creation_tokens as f64 * input * 1.25;
+    Some(cost / 1_000_000.0)
+}
+
+/// 按模型分组的查询结果：请求模型、逗号分隔的实际模型、请求数、成功数、四类 token 数、平均 TTFT
+type ModelRow = (
+    String,
+    Option<String>,
+    i64,
+    i64,
+    i64,
+    i64,
+    i64,
+    i64,
+    Option<f64>,
+);
+
+type TotalsRow = (Option<String>, i64, i64, i64, i64, i64, i64);
+
+fn totals_from_row(row: TotalsRow) -> UsageGroup {
+    let (name, requests, input, output, cache_read, cache_creation, latency) = row;
+    UsageGroup {
+        name,
+        totals: UsageTotals {
+            requests: requests as u64,
+            input_tokens: input as u64,
+            output_tokens: output as u64,
+            cache_read_tokens: cache_read as u64,
+            cache_creation_tokens: cache_creation as u64,
+            avg_latency_ms: latency as u64,
+        },
+    }
+}
+
+/// 用量数据库
+pub struct UsageStore {
+    pool: SqlitePool,
+}
+
+impl UsageStore {
+    /// 打开数据库，`create` 时不存在则创建；执行尚未应用的迁移
+    ///
+    /// 数据库的 schema 版本比本版本新时返回错误，不会写入无法理解的数据库
+    pub async fn open(path: &Path, create: bool) -> Result<Self> {
+        Ok(Self::migrate(path, create).await?.0)
+    }
+
+    /// 打开数据库并升级到 [`SCHEMA_VERSION`]，同时返回升级前的版本
+    pub async fn migrate(path: &Path, create: bool) -> Result<(Self, u32)> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(create)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(BUSY_TIMEOUT);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(4)
+            .connect_with(options)
+            .await
+            .with_context(|| format!("Failed to open usage database {}", path.display()))?;
+        let from = migrate(&pool)
+            .await
+            .with_context(|| format!("Failed to initialize {}", path.display()))?;
+        Ok((Self { pool }, from))
+    }
+
+    /// 在一个事务中写入多条记录
+    pub async fn insert(&self, rows: &[UsageRow]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for row in rows {
+            sqlx::query(
+                "INSERT INTO usage (timestamp, provider, model, effective_model, api_key_name, \
+                 input_tokens, output_tokens, cache_read_tokens, cache_creation_tokens, status, \
+                 latency_ms, ttft_ms, stream) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(row.timestamp as i64)
+            .bind(&row.provider)
+            .bind(&row.model)
+            .bind(&row.effective_model)
+            .bind(&row.api_key_name)
+            .bind(row.usage.input_tokens as i64)
+            .bind(row.usage.output_tokens as i64)
+            .bind(row.usage.cache_read_tokens as i64)
+            .bind(row.usage.cache_creation_tokens as i64)
+            .bind(row.status as i64)
+            .bind(row.latency_ms as i64)
+            .bind(row.ttft_ms.map(|ms| ms as i64))
+            .bind(row.stream)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// 在一个事务中累加密钥使用记录：请求数相加，最后使用时间取较晚者
+    ///
+    /// 只做累加，多个实例共用一个数据库时互不覆盖
+    pub async fn add_key_usage(&self, entries: &HashMap<String, KeyUsage>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for (name, entry) in entries {
+            sqlx::query(
+                "INSERT INTO key_usage (name, last_used, requests) VALUES (?, ?, ?) \
+                 ON CONFLICT (name) DO UPDATE SET \
+                 last_used = MAX(COALESCE(last_used, 0), COALESCE(excluded.last_used, 0)), \
+                 requests = requests + excluded.requests",
+            )
+            .bind(name)
+            .bind(entry.last_used.map(|ts| ts as i64))
+            .bind(entry.requests as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// 所有密钥的使用记录
+    pub async fn key_usage(&self) -> Result<HashMap<String, KeyUsage>> {
+        let rows: Vec<(String, Option<i64>, i64)> =
+            sqlx::query_as("SELECT name, last_used, requests FROM key_usage")
+                .fetch_all(&self.pool)
+                .await
+                .context("Failed to query key usage")?;
+        Ok(rows
+            .into_iter()
+            .map(|(name, last_used, requests)| {
+                let usage = KeyUsage {
+                    last_used: last_used.map(|ts| ts as u64),
+                    requests: requests as u64,
+                };
+                (name, usage)
+            })
+            .collect())
+    }
+
+    /// 把数据库的一致快照写入 `path`（不能已存在），用于导出
+    pub async fn snapshot(&self, path: &Path) -> Result<()> {
+        sqlx::query("VACUUM INTO ?")
+            .bind(path.to_string_lossy().into_owned())
+            .execute(&self.pool)
+            .await
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+
+    /// 按条件汇总
+    pub async fn report(&self, filter: UsageFilter) -> Result<UsageReport> {
+        let mut total = self.aggregate(&filter, None).await?;
+        let total = total.pop().map(|group| group.totals).unwrap_or_default();
+        Ok(UsageReport {
+            total,
+            by_provider: self.aggregate(&filter, Some("provider")).await?,
+            by_key: self.aggregate(&filter, Some("api_key_name")).await?,
+            by_model: self.aggregate(&filter, Some("model")).await?,
+            models: self.model_stats(&filter).await?,
+            from: filter.from,
+            to: filter.to,
+            provider: filter.provider,
+            model: filter.model,
+        })
+    }
+
+    /// `group` 为 `None` 时返回一行总计
+    async fn aggregate(
+        &self,
+        filter: &UsageFilter,
+        group: Option<&'static str>,
+    ) -> Result<Vec<UsageGroup>> {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT ");
+        query.push(group.unwrap_or("NULL"));
+        query.push(
+            ", COUNT(*), COALESCE(SUM(input_tokens), 0), COALESCE(SUM(output_tokens), 0), \
+             COALESCE(SUM(cache_read_tokens), 0), COALESCE(SUM(cache_creation_tokens), 0), \
+             CAST(COALESCE(AVG(latency_ms), 0) AS INTEGER) FROM usage",
+        );
+        filter.push_conditions(&mut query);
+        query.push(" AND status < 400");
+        if let Some(group) = group {
+            query.push(" GROUP BY ").push(group).push(
+                " ORDER BY SUM(input_tokens + output_tokens + cache_read_tokens \
+                 + cache_creation_tokens) DESC",
+            );
+        }
+        let rows = query
+            .build_query_as::<TotalsRow>()
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to query usage")?;
+        Ok(rows.into_iter().map(totals_from_row).collect())
+    }
+
+    /// 按请求模型分组统计，含失败请求的状态码分布与输出 token 数的 95 分位
+    async fn model_stats(&self, filter: &UsageFilter) -> Result<Vec<ModelStats>> {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT model, GROUP_CONCAT(DISTINCT effective_model), COUNT(*), \
+             COALESCE(SUM(status < 400), 0), \
+             COALESCE(SUM(CASE WHEN status < 400 THEN input_tokens END), 0), \
+             COALESCE(SUM(CASE WHEN status < 400 THEN output_tokens END), 0), \
+             COALESCE(SUM(CASE WHEN status < 400 THEN cache_read_tokens END), 0), \
+             COALESCE(SUM(CASE WHEN status < 400 THEN cache_creation_tokens END), 0), \
+             AVG(CASE WHEN status < 400 AND stream THEN ttft_ms END) FROM usage",
+        );
+        filter.push_conditions(&mut query);
+        query.push(" GROUP BY model ORDER BY COUNT(*) DESC, model");
+        let rows = query
+            .build_query_as::<ModelRow>()
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to query usage by model")?;
+
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT model, status, COUNT(*) FROM usage");
+        filter.push_conditions(&mut query);
+        query.push(" AND status >= 400 GROUP BY model, status");
+        let errors = query
+            .build_query_as::<(String, i64, i64)>()
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to query usage errors")?;
+
+        // 最近秩法：第 ceil(0.95 * n) 小的值
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT model, output_tokens FROM (SELECT model, output_tokens, \
+             ROW_NUMBER() OVER (PARTITION BY model ORDER BY output_tokens) AS position, \
+             COUNT(*) OVER (PARTITION BY model) AS n FROM usage",
+        );
+        filter.push_conditions(&mut query);
+        query.push(" AND status < 400) WHERE position = (n * 95 + 99) / 100");
+        let p95 = query
+            .build_query_as::<(String, i64)>()
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to query output token percentiles")?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let (model, effective, requests, successes, input, output, read, write, ttft) = row;
+                let mut effective_models: Vec<String> = effective
+                    .iter()
+                    .flat_map(|names| names.split(','))
+                    .map(str::to_string)
+                    .collect();
+                effective_models.sort();
+                let totals = UsageTotals {
+                    requests: successes as u64,
+                    input_tokens: input as u64,
+                    output_tokens: output as u64,
+                    cache_read_tokens: read as u64,
+                    cache_creation_tokens: write as u64,
+                    avg_latency_ms: 0,
+                };
+                // 请求的是别名时按实际模型定价
+                let estimated_cost_usd = std::iter::once(&model)
+                    .chain(&effective_models)
+                    .find_map(|name| estimate_cost(name, &totals));
+                ModelStats {
+                    errors: errors
+                        .iter()
+                        .filter(|(m, _, _)| *m == model)
+                        .map(|(_, status, count)| (*status as u16, *count as u64))
+                        .collect(),
+                    p95_output_tokens: p95
+                        .iter()
+                        .find(|(m, _)| *m == model)
+                        .map_or(0, |(_, tokens)| *tokens as u64),
+                    avg_output_tokens: totals
+                        .output_tokens
+                        .checked_div(totals.requests)
+                        .unwrap_or(0),
+                    avg_ttft_ms: ttft.map(|ms| ms.round() as u64),
+                    estimated_cost_usd,
+                    requests: requests as u64,
+                    successes: successes as u64,
+                    model,
+                    effective_models,
+                }
+            })
+            .collect())
+    }
+}
+
+/// 把数据库升级到 [`SCHEMA_VERSION`]，返回升级前的版本
+///
+/// 在同一个写事务中读取版本并执行迁移，多个实例同时打开时只有一个会执行
+async fn migrate(pool: &SqlitePool) -> Result<u32> {
+    let mut conn = pool.acquire().await?;
+    sqlx::raw_sql("BEGIN IMMEDIATE").execute(&mut *conn).await?;
+    let result = async {
+        let (version,): (i64,) = sqlx::query_as("PRAGMA user_version")
+            .fetch_one(&mut *conn)
+            .await?;
+        let version = version as u32;
+        if version > SCHEMA_VERSION {
+            anyhow::bail!(
+                "usage database schema version {version} is newer than this build supports \
+                 ({SCHEMA_VERSION}); upgrade pluribus or point PLURIBUS_USAGE_DB elsewhere"
+            );
+        }
+        for (index, script) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            let target = index + 1;
+            sqlx::raw_sql(script)
+                .execute(&mut *conn)
+                .await
+                .with_context(|| format!("Migration to schema version {target} failed"))?;
+            sqlx::raw_sql(&format!("PRAGMA user_version = {target}"))
+                .execute(&mut *conn)
+                .await?;
+        }
+        Ok(version)
+    }
+    .await;
+    let end = if result.is_ok() { "COMMIT" } else { "ROLLBACK" };
+    sqlx::raw_sql(end).execute(&mut *conn).await?;
+    result
+}
+
+/// 向后台写入任务提交记录
+#[derive(Clone)]
+pub struct UsageRecorder {
+    tx: mpsc::Sender<UsageRow>,
+}
+
+impl UsageRecorder {
+    /// 启动写入任务
+    pub fn spawn(store: Arc<UsageStore>) -> (Self, JoinHandle<()>) {
+        let (tx, mut rx) = mpsc::channel(QUEUE_CAPACITY);
+        let task = stats::spawn(TaskKind::UsageWriter, async move {
+            let mut batch = Vec::with_capacity(MAX_BATCH);
+            while rx.recv_many(&mut batch, MAX_BATCH).await > 0 {
+                if let Err(e) = store.insert(&batch).await {
+                    tracing::warn!(rows = batch.len(), "Failed to save usage: {:#}", e);
+                }
+                batch.clear();
+            }
+        });
+        (Self { tx }, task)
+    }
+
+    pub fn record(&self, row: UsageRow) {
+        if self.tx.try_send(row).is_err() {
+            tracing::warn!("usage queue full, dropped a usage record");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(
+        provider: &str,
+        key: Option<&str>,
+        input: u64,
+        output: u64,
+        latency_ms: u64,
+    ) -> UsageRow {
+        UsageRow {
+            timestamp: 1_700_000_000,
+            provider: provider.to_string(),
+            model: "claude-test".to_string(),
+            effective_model: None,
+            api_key_name: key.map(str::to_string),
+            usage: Usage {
+                input_tokens: input,
+                output_tokens: output,
+                cache_read_tokens: 0,
+                cache_creation_tokens: 0,
+            },
+            status: 200,
+            latency_ms,
+            ttft_ms: None,
+            stream: false,
+        }
+    }
+
+    fn group<'a>(groups: &'a [UsageGroup], name: Option<&str>) -> &'a UsageTotals {
+        &groups
+            .iter()
+            .find(|group| group.name.as_deref() == name)
+            .unwrap_or_else(|| panic!("no group {name:?}"))
+            .totals
+    }
+
+    #[tokio::test]
+    async fn reports_aggregate_per_provider_and_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = UsageStore::open(&dir.path().join("usage.db"), true)
+            .await
+            .unwrap();
+        store
+            .insert(&[
+                row("first", Some("alice"), 100, 10, 100),
+                row("first", Some("bob"), 200, 20, 300),
+                row("second", Some("alice"), 1000, 100, 200),
+                row("second", None, 1, 1, 0),
+            ])
+            .await
+            .unwrap();
+
+        let report = store.report(UsageFilter::default()).await.unwrap();
+        assert_eq!(report.total.requests, 4);
+        assert_eq!(report.total.input_tokens, 1301);
+        assert_eq!(report.total.output_tokens, 131);
+        assert_eq!(report.total.avg_latency_ms, 150);
+
+        // 按总 token 数降序
+        let providers: Vec<_> = report.by_provider.iter().map(|g| g.name.clone()).collect();
+        assert_eq!(providers, [Some("second".into()), Some("first".into())]);
+        let first = group(&report.by_provider, Some("first"));
+        assert_eq!(
+            (first.requests, first.input_tokens, first.output_tokens),
+            (2, 300, 30)
+        );
+        assert_eq!(first.avg_latency_ms, 200);
+
+        let alice = group(&report.by_key, Some("alice"));
+        assert_eq!(
+            (alice.requests, alice.input_tokens, alice.output_tokens),
+            (2, 1100, 110)
+        );
+        let bob = group(&report.by_key, Some("bob"));
+        assert_eq!((bob.requests, bob.input_tokens), (1, 200));
+        assert_eq!(group(&report.by_key, None).requests, 1);
+
+        let filtered = store
+            .report(UsageFilter {
+                provider: Some("first".to_string()),
+                ..UsageFilter::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(filtered.total.requests, 2);
+        assert_eq!(filtered.by_key.len(), 2);
+    }
+
+    /// 各历史版本的数据库，按版本号排列
+    const FIXTURES: &[(u32, &str)] = &[
+        (0, include_str!("../tests/fixtures/usage/v0.sql")),
+        (1, include_str!("../tests/fixtures/usage/v1.sql")),
+        (2, include_str!("../tests/fixtures/usage/v2.sql")),
+    ];
+
+    async fn user_version(store: &UsageStore) -> u32 {
+        let (version,): (i64,) = sqlx::query_as("PRAGMA user_version")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap();
+        version as u32
+    }
+
+    /// 不经过迁移直接连接数据库
+    async fn raw_pool(path: &Path) -> SqlitePool {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true);
+        SqlitePool::connect_with(options).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn migrations_preserve_totals_from_every_version() {
+        for (version, script) in FIXTURES {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("usage.db");
+            let pool = raw_pool(&path).await;
+            sqlx::raw_sql(script).execute(&pool).await.unwrap();
+            let (expected,): (i64,) = sqlx::query_as(
+                "SELECT SUM(input_tokens + output_tokens + cache_read_tokens \
+                 + cache_creation_tokens) FROM usage",
+            )
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            pool.close().await;
+
+            let (store, from) = UsageStore::migrate(&path, false).await.unwrap();
+            assert_eq!(from, *version);
+            assert_eq!(user_version(&store).await, SCHEMA_VERSION);
+
+            let report = store.report(UsageFilter::default()).await.unwrap();
+            let total = &report.total;
+            assert_eq!(total.requests, 6, "v{version}");
+            assert_eq!(
+                total.input_tokens
+                    + total.output_tokens
+                    + total.cache_read_tokens
+                    + total.cache_creation_tokens,
+                expected as u64,
+                "v{version}"
+            );
+            assert_eq!(report.by_provider.len(), 2, "v{version}");
+            assert_eq!(group(&report.by_key, Some("alice")).requests, 3);
+
+            // 迁移后的数据库可以继续写入
+            store.insert(&[row("work", None, 1, 1, 1)]).await.unwrap();
+        }
+    }
+
+    fn model<'a>(report: &'a UsageReport, name: &str) -> &'a ModelStats {
+        report
+            .models
+            .iter()
+            .find(|stats| stats.model == name)
+            .unwrap_or_else(|| panic!("no model {name}"))
+    }
+
+    #[tokio::test]
+    async fn model_stats_split_outcomes_per_model() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = UsageStore::open(&dir.path().join("usage.db"), true)
+            .await
+            .unwrap();
+        sqlx::raw_sql(include_str!("../tests/fixtures/usage/models.sql"))
+            .execute(&store.pool)
+            .await
+            .unwrap();
+
+        let report = store.report(UsageFilter::default()).await.unwrap();
+        // token 汇总只包含成功的请求
+        assert_eq!(report.total.requests, 14);
+        let names: Vec<_> = report.models.iter().map(|m| m.model.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "claude-sonnet-4-5",
+                "claude-opus-4-1",
+                "mystery-model",
+                "sonnet"
+            ]
+        );
+
+        let sonnet = model(&report, "claude-sonnet-4-5");
+        assert_eq!((sonnet.requests, sonnet.successes), (14, 10));
+        assert_eq!(sonnet.errors, BTreeMap::from([(429, 3), (529, 1)]));
+        assert_eq!(sonnet.effective_models, ["claude-sonnet-4-5-20250929"]);
+        assert_eq!(sonnet.avg_output_tokens, 550);
+        assert_eq!(sonnet.p95_output_tokens, 1000);
+        assert_eq!(sonnet.avg_ttft_ms, Some(300));
+        let cost = sonnet.estimated_cost_usd.unwrap();
+        assert!((cost - 0.1125).abs() < 1e-9, "{cost}");
+
+        let opus = model(&report, "claude-opus-4-1");
+        assert_eq!((opus.requests, opus.successes), (3, 2));
+        assert_eq!(opus.errors, BTreeMap::from([(500, 1)]));
+        assert_eq!((opus.avg_output_tokens, opus.p95_output_tokens), (100, 150));
+        assert_eq!(opus.avg_ttft_ms, None);
+        let cost = opus.estimated_cost_usd.unwrap();
+        assert!((cost - 0.105).abs() < 1e-9, "{cost}");
+
+        // 别名按实际模型定价，未知模型不估算
+        let alias = model(&report, "sonnet");
+        assert_eq!(alias.effective_models, ["claude-sonnet-4-20250514"]);
+        assert!((alias.estimated_cost_usd.unwrap() - 0.0009).abs() < 1e-9);
+        assert_eq!(model(&report, "mystery-model").estimated_cost_usd, None);
+
+        // 按实际模型筛选时，同一请求模型的失败请求（没有实际模型）不计入
+        let filtered = store
+            .report(UsageFilter {
+                model: Some("claude-opus-4-1-20250805".to_string()),
+                ..UsageFilter::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(filtered.models.len(), 1);
+        assert_eq!(filtered.models[0].requests, 2);
+        assert!(filtered.models[0].errors.is_empty());
+
+        let filtered = store
+            .report(UsageFilter {
+                model: Some("claude-opus-4-1".to_string()),
+                ..UsageFilter::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(filtered.models[0].requests, 3);
+        assert_eq!(filtered.total.requests, 2);
+    }
+
+    #[tokio::test]
+    async fn key_usage_accumulates_across_flushes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = UsageStore::open(&dir.path().join("usage.db"), true)
+            .await
+            .unwrap();
+        let entry = |last_used, requests| KeyUsage {
+            last_used: Some(last_used),
+            requests,
+        };
+        store
+            .add_key_usage(&HashMap::from([
+                ("alice".to_string(), entry(1_700_000_100, 3)),
+                ("bob".to_string(), entry(1_700_000_000, 1)),
+            ]))
+            .await
+            .unwrap();
+        // 另一个实例较早的记录不会把最后使用时间往回改
+        store
+            .add_key_usage(&HashMap::from([(
+                "alice".to_string(),
+                entry(1_700_000_050, 2),
+            )]))
+            .await
+            .unwrap();
+
+        let usage = store.key_usage().await.unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage["alice"].requests, 5);
+        assert_eq!(usage["alice"].last_used, Some(1_700_000_100));
+        assert_eq!(usage["bob"].requests, 1);
+    }
+
+    #[tokio::test]
+    async fn refuses_a_newer_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("usage.db");
+        let next = SCHEMA_VERSION + 1;
+        let pool = raw_pool(&path).await;
+        sqlx::raw_sql(&format!("PRAGMA user_version = {next}"))
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let err = UsageStore::open(&path, false).await.err().unwrap();
+        assert!(format!("{err:#}").contains("newer than this build supports"));
+
+        // 拒绝时不修改数据库
+        let (version,): (i64,) = sqlx::query_as("PRAGMA user_version")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(version as u32, next);
+        let (tables,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM sqlite_master")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(tables, 0);
+    }
+
+    #[tokio::test]
+    async fn open_records_the_schema_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("usage.db");
+        let store = UsageStore::open(&path, true).await.unwrap();
+        assert_eq!(user_version(&store).await, SCHEMA_VERSION);
+        drop(store);
+
+        // 再次打开不重复执行迁移
+        let (_, from) = UsageStore::migrate(&path, false).await.unwrap();
+        assert_eq!(from, SCHEMA_VERSION);
+    }
+}
@@ -9,12 +9,33 @@ pub fn should_disable_tls_verify() -> bool {
         .unwrap_or(false)
 }
 
+/// 默认上游响应头总大小上限：16 KiB
+const DEFAULT_MAX_RESPONSE_HEADER_SIZE_BYTES: usize = 16 * 1024;
+
+/// 上游响应头总大小上限（`PLURIBUS_MAX_RESPONSE_HEADER_SIZE_BYTES`，默认 16 KiB）
+pub fn max_response_header_size() -> usize {
+    std::env::var("PLURIBUS_MAX_RESPONSE_HEADER_SIZE_BYTES")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(DEFAULT_MAX_RESPONSE_HEADER_SIZE_BYTES)
+}
+
+/// 计算响应头总大小（按 HTTP/1 线路格式 `name: value\r\n` 估算）
+pub fn header_list_size(headers: &http::HeaderMap) -> usize {
+    headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len() + 4)
+        .sum()
+}
+
 /// 获取共享的 HTTP 客户端（用于一般请求，如 OAuth、版本查询等）
 static SHARED_CLIENT: OnceLock<Client> = OnceLock::new();
 
 pub fn get_shared_client() -> &'static Client {
     SHARED_CLIENT.get_or_init(|| {
-        let mut builder = Client::builder().timeout(Duration::from_secs(30));
+        let mut builder = Client::builder()
+            .timeout(Duration::from_secs(30))
+            .redirect(crate::egress::redirect_policy());
 
         if should_disable_tls_verify() {
             tracing::warn!("TLS certificate verification is DISABLED - for debugging only!");
@@ -42,6 +63,105 @@ pub fn unix_timestamp_ms() -> u64 {
         .unwrap_or(0)
 }
 
+/// 获取当前 Unix 时间戳（秒）
+#[inline]
+pub fn unix_timestamp_secs() -> u64 {
+    unix_timestamp_ms() / 1000
+}
+
+/// splitmix64，使连续的整数（如请求 ID）均匀分布
+pub fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// 将自 1970-01-01 起的天数转换为 UTC 日期 `(年, 月, 日)`
+///
+/// civil-from-days（Howard Hinnant 算法）
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// 将 UTC 日期转换为自 1970-01-01 起的天数，[`civil_from_days`] 的逆运算
+#[cfg_attr(not(feature = "usage-sqlite"), allow(dead_code))]
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// 解析 `YYYY-MM-DD`（UTC 当天零点）或 Unix 时间戳（秒）
+#[cfg_attr(not(feature = "usage-sqlite"), allow(dead_code))]
+pub fn parse_date_or_timestamp(value: &str) -> Option<u64> {
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(secs);
+    }
+    let mut parts = value.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (Some(Some(year)), Some(Some(month)), Some(Some(day))) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    // 拒绝 2024-02-30 这类不存在的日期
+    if civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+    Some(days as u64 * 86_400)
+}
+
+/// 将 Unix 时间戳（秒）格式化为 UTC 时间 `YYYY-MM-DD HH:MM`
+pub fn format_timestamp(secs: u64) -> String {
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let rem = secs % 86_400;
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}",
+        rem / 3600,
+        rem % 3600 / 60
+    )
+}
+
+/// 把相对现在的秒数格式化为 `in 2h 5m` / `3d 4h ago`，只保留最大的两个单位
+pub fn format_relative(delta_secs: i64) -> String {
+    let mut rest = delta_secs.unsigned_abs();
+    let mut parts = Vec::new();
+    for (unit, size) in [("d", 86_400), ("h", 3600), ("m", 60), ("s", 1)] {
+        let count = rest / size;
+        rest %= size;
+        if count > 0 || (unit == "s" && parts.is_empty()) {
+            parts.push(format!("{count}{unit}"));
+        }
+        if parts.len() == 2 || (!parts.is_empty() && count == 0) {
+            break;
+        }
+    }
+    let span = parts.join(" ");
+    if delta_secs < 0 {
+        format!("{span} ago")
+    } else {
+        format!("in {span}")
+    }
+}
+
 /// 从请求体中提取 model 字段
 ///
 /// # 参数
@@ -58,3 +178,92 @@ pub fn extract_model(body: &serde_json::Value) -> String {
         .unwrap_or("unknown")
         .to_string()
 }
+
+/// 已知模型的上下文窗口（token 数），按模型名前缀匹配，越具体的前缀越靠前
+const MODEL_CONTEXT_WINDOWS: &[(&str, u64)] = &[
+    ("claude-opus-4", 200_000),
+    ("claude-sonnet-4", 200_000),
+    ("claude-haiku-4", 200_000),
+    ("claude-3-7-sonnet", 200_000),
+    ("claude-3-5-sonnet", 200_000),
+    ("claude-3-5-haiku", 200_000),
+    ("claude-3-opus", 200_000),
+    ("claude-3-haiku", 200_000),
+];
+
+/// 粗略估算：1 token ≈ 4 字符
+const CHARS_PER_TOKEN: u64 = 4;
+
+/// 触发上下文告警的使用率阈值
+const CONTEXT_WARNING_RATIO: f64 = 0.9;
+
+/// 上下文接近模型窗口上限的告警
+#[derive(Debug, Clone)]
+pub struct ContextWarning {
+    /// 估算的上下文 token 数
+    pub estimated_tokens: u64,
+    /// 模型的上下文窗口大小
+    pub context_window: u64,
+}
+
+impl ContextWarning {
+    /// 用作 `X-Context-Usage-Warning` 响应头的值，如 `estimated=185000; window=200000`
+    pub fn header_value(&self) -> String {
+        format!(
+            "estimated={}; window={}",
+            self.estimated_tokens, self.context_window
+        )
+    }
+}
+
+/// 查询模型的上下文窗口大小，未知模型返回 `None`
+pub fn model_context_window(model: &str) -> Option<u64> {
+    MODEL_CONTEXT_WINDOWS
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, window)| *window)
+}
+
+/// 累计 JSON 中所有字符串值的字符数
+pub fn count_text_chars(value: &serde_json::Value) -> u64 {
+    match value {
+        serde_json::Value::String(s) => s.chars().count() as u64,
+        serde_json::Value::Array(items) => items.iter().map(count_text_chars).sum(),
+        serde_json::Value::Object(obj) => obj.values().map(count_text_chars).sum(),
+        _ => 0,
+    }
+}
+
+/// 仅凭请求体字节数判断上下文是否可能触发告警
+///
+/// 字符数不超过字节数，因此返回 `false` 时 [`check_context_limits`] 一定不会告警
+pub fn may_exceed_context_limits(model: &str, body_len: usize) -> bool {
+    model_context_window(model).is_some_and(|window| {
+        (body_len as u64 / CHARS_PER_TOKEN) as f64 >= window as f64 * CONTEXT_WARNING_RATIO
+    })
+}
+
+/// 估算请求的上下文 token 数，超过模型窗口 90% 时返回告警
+///
+/// 统计 system、messages 和 tools 中的文本字符数，按 1 token ≈ 4 字符估算。
+/// 模型不在已知列表中时不做检查。
+pub fn check_context_limits(body: &serde_json::Value) -> Option<ContextWarning> {
+    let model = body.get("model").and_then(|v| v.as_str())?;
+    let context_window = model_context_window(model)?;
+
+    let chars: u64 = ["system", "messages", "tools"]
+        .iter()
+        .filter_map(|field| body.get(field))
+        .map(count_text_chars)
+        .sum();
+    let estimated_tokens = chars / CHARS_PER_TOKEN;
+
+    if (estimated_tokens as f64) < context_window as f64 * CONTEXT_WARNING_RATIO {
+        return None;
+    }
+
+    Some(ContextWarning {
+        estimated_tokens,
+        context_window,
+    })
+}
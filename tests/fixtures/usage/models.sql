@@ -0,0 +1,23 @@
+-- 当前 schema 下成功与失败请求的混合，用于按模型统计的测试
+INSERT INTO usage (timestamp, provider, model, effective_model, api_key_name, input_tokens,
+                   output_tokens, cache_read_tokens, cache_creation_tokens, status, latency_ms,
+                   ttft_ms, stream) VALUES
+    (1735689660, 'work', 'claude-sonnet-4-5', 'claude-sonnet-4-5-20250929', 'alice', 1000, 100, 0, 0, 200, 1500, 100, 1),
+    (1735689720, 'work', 'claude-sonnet-4-5', 'claude-sonnet-4-5-20250929', 'alice', 1000, 200, 0, 0, 200, 1600, NULL, 0),
+    (1735689780, 'work', 'claude-sonnet-4-5', 'claude-sonnet-4-5-20250929', 'alice', 1000, 300, 0, 0, 200, 1700, 200, 1),
+    (1735689840, 'work', 'claude-sonnet-4-5', 'claude-sonnet-4-5-20250929', 'alice', 1000, 400, 0, 0, 200, 1800, NULL, 0),
+    (1735689900, 'work', 'claude-sonnet-4-5', 'claude-sonnet-4-5-20250929', 'alice', 1000, 500, 0, 0, 200, 1900, 300, 1),
+    (1735689960, 'work', 'claude-sonnet-4-5', 'claude-sonnet-4-5-20250929', 'alice', 1000, 600, 0, 0, 200, 2000, NULL, 0),
+    (1735690020, 'work', 'claude-sonnet-4-5', 'claude-sonnet-4-5-20250929', 'alice', 1000, 700, 0, 0, 200, 2100, 400, 1),
+    (1735690080, 'work', 'claude-sonnet-4-5', 'claude-sonnet-4-5-20250929', 'alice', 1000, 800, 0, 0, 200, 2200, NULL, 0),
+    (1735690140, 'work', 'claude-sonnet-4-5', 'claude-sonnet-4-5-20250929', 'alice', 1000, 900, 0, 0, 200, 2300, 500, 1),
+    (1735690200, 'work', 'claude-sonnet-4-5', 'claude-sonnet-4-5-20250929', 'alice', 1000, 1000, 0, 0, 200, 2400, NULL, 0),
+    (1735690260, 'personal', 'claude-sonnet-4-5', NULL, 'bob', 0, 0, 0, 0, 429, 300, NULL, 1),
+    (1735690320, 'work', 'claude-sonnet-4-5', NULL, 'bob', 0, 0, 0, 0, 429, 300, NULL, 1),
+    (1735690380, 'personal', 'claude-sonnet-4-5', NULL, 'bob', 0, 0, 0, 0, 429, 300, NULL, 1),
+    (1735690440, 'work', 'claude-sonnet-4-5', NULL, 'bob', 0, 0, 0, 0, 529, 300, NULL, 1),
+    (1735690500, 'work', 'claude-opus-4-1', 'claude-opus-4-1-20250805', 'alice', 2000, 50, 10000, 0, 200, 8000, NULL, 0),
+    (1735690560, 'work', 'claude-opus-4-1', NULL, 'alice', 0, 0, 0, 0, 500, 200, NULL, 0),
+    (1735690620, 'personal', 'claude-opus-4-1', 'claude-opus-4-1-20250805', 'bob', 2000, 150, 10000, 0, 200, 9000, NULL, 0),
+    (1735690680, 'personal', 'sonnet', 'claude-sonnet-4-20250514', NULL, 100, 40, 0, 0, 200, 900, 250, 1),
+    (1735690740, 'personal', 'mystery-model', NULL, 'bob', 10, 10, 0, 0, 200, 100, NULL, 0);
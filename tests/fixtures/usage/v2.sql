@@ -0,0 +1,30 @@
+-- schema 版本 2：增加实际模型、状态码与首 token 耗时
+CREATE TABLE usage (
+    id INTEGER PRIMARY KEY,
+    timestamp INTEGER NOT NULL,
+    provider TEXT NOT NULL,
+    model TEXT NOT NULL,
+    api_key_name TEXT,
+    input_tokens INTEGER NOT NULL,
+    output_tokens INTEGER NOT NULL,
+    cache_read_tokens INTEGER NOT NULL,
+    cache_creation_tokens INTEGER NOT NULL,
+    latency_ms INTEGER NOT NULL,
+    stream INTEGER NOT NULL,
+    effective_model TEXT,
+    status INTEGER NOT NULL DEFAULT 200,
+    ttft_ms INTEGER
+);
+CREATE INDEX usage_timestamp ON usage (timestamp);
+
+PRAGMA user_version = 2;
+
+INSERT INTO usage (timestamp, provider, model, api_key_name, input_tokens, output_tokens,
+                   cache_read_tokens, cache_creation_tokens, latency_ms, stream,
+                   effective_model, status, ttft_ms) VALUES
+    (1704067200, 'work', 'claude-sonnet-4', 'alice', 1200, 340, 8000, 0, 2100, 1, 'claude-sonnet-4-20250514', 200, 600),
+    (1704070800, 'work', 'claude-sonnet-4', 'alice', 900, 120, 9200, 400, 1800, 1, 'claude-sonnet-4-20250514', 200, 450),
+    (1704074400, 'work', 'claude-opus-4', 'bob', 5000, 2100, 0, 5000, 9400, 0, 'claude-opus-4-20250514', 200, NULL),
+    (1704153600, 'personal', 'claude-sonnet-4', NULL, 300, 80, 0, 0, 700, 0, 'claude-sonnet-4-20250514', 200, NULL),
+    (1704157200, 'personal', 'claude-haiku-4', 'bob', 150, 30, 1200, 0, 400, 1, 'claude-haiku-4-20251001', 200, 200),
+    (1704240000, 'personal', 'claude-opus-4', 'alice', 7000, 900, 20000, 1500, 12000, 1, 'claude-opus-4-20250514', 200, 1900);